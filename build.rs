@@ -2,5 +2,6 @@ use anyhow::{Context, Result};
 use tonic_build::compile_protos;
 
 fn main() -> Result<()> {
-    compile_protos("proto/criapi.proto").context("compile CRI protocol buffers")
+    compile_protos("proto/criapi.proto").context("compile CRI protocol buffers")?;
+    compile_protos("proto/admin.proto").context("compile admin protocol buffers")
 }
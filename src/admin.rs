@@ -0,0 +1,843 @@
+//! Read-only administrative access to the runtime
+//!
+//! The admin service exposes the runtime and image service on a dedicated socket, whereas all
+//! methods which would mutate workloads or give access to them, like `Exec`, are rejected. This
+//! allows monitoring agents to observe the runtime via a socket with dedicated permissions. Next
+//! to both services, the admin socket streams the events of the runtime together with their
+//! structured fields, which allows node agents to display the progress of image pulls. It also
+//! lists the files which containers changed compared to their image, but not their content, and
+//! resolves the log files of containers by their ID prefix.
+//!
+//! Committing the changes of a container as a new image, pushing images to their registry and
+//! checkpointing pods for node maintenance are part of the admin service as well, but they are
+//! only allowed by the writable instance which is served on the listen socket of the runtime.
+
+use crate::{
+    adminapi::{self, admin_client::AdminClient, admin_server::Admin},
+    container::Container,
+    cri_service::CRIService,
+    criapi::{self, image_service_server::ImageService, runtime_service_server::RuntimeService},
+    image::{
+        reference::Reference,
+        rootfs::{self, ChangeKind},
+        store::{ImageRecord, ImageStore},
+    },
+    oci::spec::ROOTFS_DIR,
+    storage::{default_key_value_storage::DefaultKeyValueStorage, KeyValueStorage},
+};
+use anyhow::Context;
+use futures_util::{
+    future,
+    stream::{Stream, StreamExt},
+};
+use log::{debug, warn};
+use std::{
+    convert::TryFrom,
+    path::{Path, PathBuf},
+    pin::Pin,
+};
+use tokio::{net::UnixStream, sync::broadcast::RecvError, task};
+use tonic::{
+    transport::{Channel, Endpoint, Uri},
+    Request, Response, Status,
+};
+use tower::service_fn;
+
+#[derive(Clone)]
+/// AdminService wraps the CRI service and only allows read-only access to it, unless it is
+/// writable.
+pub struct AdminService<S = DefaultKeyValueStorage> {
+    /// The wrapped CRI service.
+    cri_service: CRIService<S>,
+
+    /// Whether the admin methods creating content, like `CommitContainer`, are allowed.
+    writable: bool,
+}
+
+impl<S: KeyValueStorage> AdminService<S> {
+    /// Create a new read-only admin service for the provided `cri_service`.
+    pub fn new(cri_service: CRIService<S>) -> Self {
+        Self {
+            cri_service,
+            writable: false,
+        }
+    }
+
+    /// Create a new admin service for the provided `cri_service`, which allows the admin methods
+    /// creating content. The wrapped runtime and image services stay read-only.
+    pub fn writable(cri_service: CRIService<S>) -> Self {
+        Self {
+            cri_service,
+            writable: true,
+        }
+    }
+
+    /// Parse the `image` reference, which has to be tagged because it names new content.
+    fn tagged_reference(image: &str) -> Result<Reference, Status> {
+        let reference = image
+            .parse::<Reference>()
+            .map_err(|e| Status::invalid_argument(format!("parse image reference: {:#}", e)))?;
+        if reference.tag().is_none() {
+            return Err(Status::invalid_argument(format!(
+                "image reference {} has no tag",
+                reference
+            )));
+        }
+        Ok(reference)
+    }
+
+    /// Resolve the container `id` together with the record of its image.
+    fn container_image(&self, id: &str) -> Result<(Container, ImageRecord), Status> {
+        let container = self.cri_service.resolve_container(id)?;
+        let image = container.image();
+        let record = ImageStore::find(&mut self.cri_service.storage().clone(), image)
+            .map_err(|e| Status::internal(format!("find image {}: {:#}", image, e)))?
+            .ok_or_else(|| {
+                Status::failed_precondition(format!(
+                    "image {} of container {} not found",
+                    image,
+                    container.id()
+                ))
+            })?;
+        Ok((container, record))
+    }
+
+    /// Reject the mutating `method`.
+    fn deny<R, T>(method: &str, _: Request<R>) -> Result<Response<T>, Status> {
+        debug!("Rejecting {} on read-only admin socket", method);
+        Err(Status::permission_denied(format!(
+            "{} is not allowed on the read-only admin socket",
+            method
+        )))
+    }
+}
+
+#[tonic::async_trait]
+impl<S: KeyValueStorage> RuntimeService for AdminService<S> {
+    async fn version(
+        &self,
+        request: Request<criapi::VersionRequest>,
+    ) -> Result<Response<criapi::VersionResponse>, Status> {
+        RuntimeService::version(&self.cri_service, request).await
+    }
+
+    async fn create_container(
+        &self,
+        request: Request<criapi::CreateContainerRequest>,
+    ) -> Result<Response<criapi::CreateContainerResponse>, Status> {
+        Self::deny("CreateContainer", request)
+    }
+
+    async fn start_container(
+        &self,
+        request: Request<criapi::StartContainerRequest>,
+    ) -> Result<Response<criapi::StartContainerResponse>, Status> {
+        Self::deny("StartContainer", request)
+    }
+
+    async fn stop_container(
+        &self,
+        request: Request<criapi::StopContainerRequest>,
+    ) -> Result<Response<criapi::StopContainerResponse>, Status> {
+        Self::deny("StopContainer", request)
+    }
+
+    async fn remove_container(
+        &self,
+        request: Request<criapi::RemoveContainerRequest>,
+    ) -> Result<Response<criapi::RemoveContainerResponse>, Status> {
+        Self::deny("RemoveContainer", request)
+    }
+
+    async fn list_containers(
+        &self,
+        request: Request<criapi::ListContainersRequest>,
+    ) -> Result<Response<criapi::ListContainersResponse>, Status> {
+        RuntimeService::list_containers(&self.cri_service, request).await
+    }
+
+    async fn container_status(
+        &self,
+        request: Request<criapi::ContainerStatusRequest>,
+    ) -> Result<Response<criapi::ContainerStatusResponse>, Status> {
+        RuntimeService::container_status(&self.cri_service, request).await
+    }
+
+    async fn container_stats(
+        &self,
+        request: Request<criapi::ContainerStatsRequest>,
+    ) -> Result<Response<criapi::ContainerStatsResponse>, Status> {
+        RuntimeService::container_stats(&self.cri_service, request).await
+    }
+
+    async fn list_container_stats(
+        &self,
+        request: Request<criapi::ListContainerStatsRequest>,
+    ) -> Result<Response<criapi::ListContainerStatsResponse>, Status> {
+        RuntimeService::list_container_stats(&self.cri_service, request).await
+    }
+
+    async fn pod_sandbox_stats(
+        &self,
+        request: Request<criapi::PodSandboxStatsRequest>,
+    ) -> Result<Response<criapi::PodSandboxStatsResponse>, Status> {
+        RuntimeService::pod_sandbox_stats(&self.cri_service, request).await
+    }
+
+    async fn list_pod_sandbox_stats(
+        &self,
+        request: Request<criapi::ListPodSandboxStatsRequest>,
+    ) -> Result<Response<criapi::ListPodSandboxStatsResponse>, Status> {
+        RuntimeService::list_pod_sandbox_stats(&self.cri_service, request).await
+    }
+
+    async fn update_container_resources(
+        &self,
+        request: Request<criapi::UpdateContainerResourcesRequest>,
+    ) -> Result<Response<criapi::UpdateContainerResourcesResponse>, Status> {
+        Self::deny("UpdateContainerResources", request)
+    }
+
+    async fn reopen_container_log(
+        &self,
+        request: Request<criapi::ReopenContainerLogRequest>,
+    ) -> Result<Response<criapi::ReopenContainerLogResponse>, Status> {
+        Self::deny("ReopenContainerLog", request)
+    }
+
+    async fn exec_sync(
+        &self,
+        request: Request<criapi::ExecSyncRequest>,
+    ) -> Result<Response<criapi::ExecSyncResponse>, Status> {
+        Self::deny("ExecSync", request)
+    }
+
+    async fn exec(
+        &self,
+        request: Request<criapi::ExecRequest>,
+    ) -> Result<Response<criapi::ExecResponse>, Status> {
+        Self::deny("Exec", request)
+    }
+
+    async fn attach(
+        &self,
+        request: Request<criapi::AttachRequest>,
+    ) -> Result<Response<criapi::AttachResponse>, Status> {
+        Self::deny("Attach", request)
+    }
+
+    async fn port_forward(
+        &self,
+        request: Request<criapi::PortForwardRequest>,
+    ) -> Result<Response<criapi::PortForwardResponse>, Status> {
+        Self::deny("PortForward", request)
+    }
+
+    async fn run_pod_sandbox(
+        &self,
+        request: Request<criapi::RunPodSandboxRequest>,
+    ) -> Result<Response<criapi::RunPodSandboxResponse>, Status> {
+        Self::deny("RunPodSandbox", request)
+    }
+
+    async fn stop_pod_sandbox(
+        &self,
+        request: Request<criapi::StopPodSandboxRequest>,
+    ) -> Result<Response<criapi::StopPodSandboxResponse>, Status> {
+        Self::deny("StopPodSandbox", request)
+    }
+
+    async fn remove_pod_sandbox(
+        &self,
+        request: Request<criapi::RemovePodSandboxRequest>,
+    ) -> Result<Response<criapi::RemovePodSandboxResponse>, Status> {
+        Self::deny("RemovePodSandbox", request)
+    }
+
+    async fn list_pod_sandbox(
+        &self,
+        request: Request<criapi::ListPodSandboxRequest>,
+    ) -> Result<Response<criapi::ListPodSandboxResponse>, Status> {
+        RuntimeService::list_pod_sandbox(&self.cri_service, request).await
+    }
+
+    async fn pod_sandbox_status(
+        &self,
+        request: Request<criapi::PodSandboxStatusRequest>,
+    ) -> Result<Response<criapi::PodSandboxStatusResponse>, Status> {
+        RuntimeService::pod_sandbox_status(&self.cri_service, request).await
+    }
+
+    async fn status(
+        &self,
+        request: Request<criapi::StatusRequest>,
+    ) -> Result<Response<criapi::StatusResponse>, Status> {
+        let verbose = request.get_ref().verbose;
+        let mut response = RuntimeService::status(&self.cri_service, request).await?;
+
+        // The effective configuration and the connected clients are only exposed to observers of
+        // the admin socket
+        if verbose {
+            let config = serde_json::to_string(self.cri_service.config().as_ref())
+                .map_err(|e| Status::internal(format!("serialize config: {}", e)))?;
+            response.get_mut().info.insert("config".into(), config);
+            let peers = serde_json::to_string(&self.cri_service.peers().list())
+                .map_err(|e| Status::internal(format!("serialize peers: {}", e)))?;
+            response.get_mut().info.insert("peers".into(), peers);
+        }
+        Ok(response)
+    }
+
+    async fn update_runtime_config(
+        &self,
+        request: Request<criapi::UpdateRuntimeConfigRequest>,
+    ) -> Result<Response<criapi::UpdateRuntimeConfigResponse>, Status> {
+        Self::deny("UpdateRuntimeConfig", request)
+    }
+}
+
+#[tonic::async_trait]
+impl<S: KeyValueStorage> ImageService for AdminService<S> {
+    async fn list_images(
+        &self,
+        request: Request<criapi::ListImagesRequest>,
+    ) -> Result<Response<criapi::ListImagesResponse>, Status> {
+        ImageService::list_images(&self.cri_service, request).await
+    }
+
+    async fn pull_image(
+        &self,
+        request: Request<criapi::PullImageRequest>,
+    ) -> Result<Response<criapi::PullImageResponse>, Status> {
+        Self::deny("PullImage", request)
+    }
+
+    async fn image_status(
+        &self,
+        request: Request<criapi::ImageStatusRequest>,
+    ) -> Result<Response<criapi::ImageStatusResponse>, Status> {
+        ImageService::image_status(&self.cri_service, request).await
+    }
+
+    async fn remove_image(
+        &self,
+        request: Request<criapi::RemoveImageRequest>,
+    ) -> Result<Response<criapi::RemoveImageResponse>, Status> {
+        Self::deny("RemoveImage", request)
+    }
+
+    async fn image_fs_info(
+        &self,
+        request: Request<criapi::ImageFsInfoRequest>,
+    ) -> Result<Response<criapi::ImageFsInfoResponse>, Status> {
+        ImageService::image_fs_info(&self.cri_service, request).await
+    }
+}
+
+#[tonic::async_trait]
+impl<S: KeyValueStorage> Admin for AdminService<S> {
+    type EventsStream =
+        Pin<Box<dyn Stream<Item = Result<adminapi::Event, Status>> + Send + Sync + 'static>>;
+
+    async fn events(
+        &self,
+        _: Request<adminapi::EventsRequest>,
+    ) -> Result<Response<Self::EventsStream>, Status> {
+        let clock = self.cri_service.clock().clone();
+        let events = self.cri_service.events().subscribe().filter_map(move |x| {
+            let event = match x {
+                Ok(event) => {
+                    let kind: &str = event.as_ref();
+                    Some(Ok(adminapi::Event {
+                        kind: kind.into(),
+                        message: event.to_string(),
+                        timestamp: clock.unix_nanos().unwrap_or_default(),
+                        fields: event.fields(),
+                    }))
+                }
+                // Slow observers miss events instead of slowing down the runtime
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Admin event stream missed {} events", missed);
+                    None
+                }
+                Err(RecvError::Closed) => None,
+            };
+            future::ready(event)
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+
+    async fn container_diff(
+        &self,
+        request: Request<adminapi::ContainerDiffRequest>,
+    ) -> Result<Response<adminapi::ContainerDiffResponse>, Status> {
+        let id = request.into_inner().container_id;
+        let (container, record) = self.container_image(&id)?;
+        let store = self.cri_service.image_store()?;
+        let rootfs = container.bundle().join(ROOTFS_DIR);
+        let changes = task::spawn_blocking(move || rootfs::diff(&store, &record, &rootfs))
+            .await
+            .map_err(|e| Status::internal(format!("diff container: {}", e)))?
+            .map_err(|e| Status::internal(format!("diff container {}: {:#}", container.id(), e)))?;
+
+        let changes = changes
+            .iter()
+            .map(|x| adminapi::FileChange {
+                path: x.path().display().to_string(),
+                kind: match x.kind() {
+                    ChangeKind::Added => adminapi::ChangeKind::Added,
+                    ChangeKind::Changed => adminapi::ChangeKind::Changed,
+                    ChangeKind::Deleted => adminapi::ChangeKind::Deleted,
+                } as i32,
+            })
+            .collect();
+        Ok(Response::new(adminapi::ContainerDiffResponse { changes }))
+    }
+
+    async fn container_log_path(
+        &self,
+        request: Request<adminapi::ContainerLogPathRequest>,
+    ) -> Result<Response<adminapi::ContainerLogPathResponse>, Status> {
+        let path = self
+            .cri_service
+            .container_log_path(&request.into_inner().container_id)?;
+        Ok(Response::new(adminapi::ContainerLogPathResponse {
+            log_path: path.display().to_string(),
+        }))
+    }
+
+    async fn commit_container(
+        &self,
+        request: Request<adminapi::CommitContainerRequest>,
+    ) -> Result<Response<adminapi::CommitContainerResponse>, Status> {
+        if !self.writable {
+            return Self::deny("CommitContainer", request);
+        }
+        let request = request.into_inner();
+        let reference = Self::tagged_reference(&request.image)?;
+        let (container, base) = self.container_image(&request.container_id)?;
+
+        let store = self.cri_service.image_store()?;
+        let mut storage = self.cri_service.storage().clone();
+        let rootfs = container.bundle().join(ROOTFS_DIR);
+        let comment = request.comment;
+        let record = task::spawn_blocking(move || {
+            store.commit_container(&mut storage, &base, &rootfs, &reference, &comment)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("commit container: {}", e)))?
+        .map_err(|e| Status::internal(format!("commit container {}: {:#}", container.id(), e)))?;
+        Ok(Response::new(adminapi::CommitContainerResponse {
+            image_id: record.id().clone(),
+        }))
+    }
+
+    async fn push_image(
+        &self,
+        request: Request<adminapi::PushImageRequest>,
+    ) -> Result<Response<adminapi::PushImageResponse>, Status> {
+        if !self.writable {
+            return Self::deny("PushImage", request);
+        }
+        let request = request.into_inner();
+        let reference = Self::tagged_reference(if request.reference.is_empty() {
+            &request.image
+        } else {
+            &request.reference
+        })?;
+        let mut storage = self.cri_service.storage().clone();
+        let record = ImageStore::find(&mut storage, &request.image)
+            .map_err(|e| Status::internal(format!("find image {}: {:#}", request.image, e)))?
+            .ok_or_else(|| Status::not_found(format!("image {} not found", request.image)))?;
+
+        let registry = self.cri_service.registry(&reference, None)?;
+        let repo_digest = self
+            .cri_service
+            .image_store()?
+            .push(&mut storage, &registry, &record, &reference)
+            .await
+            .map_err(|e| Status::internal(format!("push image {}: {:#}", reference, e)))?;
+        Ok(Response::new(adminapi::PushImageResponse { repo_digest }))
+    }
+
+    async fn checkpoint_pods(
+        &self,
+        request: Request<adminapi::CheckpointPodsRequest>,
+    ) -> Result<Response<adminapi::CheckpointPodsResponse>, Status> {
+        if !self.writable {
+            return Self::deny("CheckpointPods", request);
+        }
+        let mut container_ids = vec![];
+        for id in &request.into_inner().pod_sandbox_ids {
+            container_ids.extend(self.cri_service.checkpoint_pod_sandbox(id).await?);
+        }
+        Ok(Response::new(adminapi::CheckpointPodsResponse {
+            container_ids,
+        }))
+    }
+
+    async fn restore_pods(
+        &self,
+        request: Request<adminapi::RestorePodsRequest>,
+    ) -> Result<Response<adminapi::RestorePodsResponse>, Status> {
+        if !self.writable {
+            return Self::deny("RestorePods", request);
+        }
+        let mut container_ids = vec![];
+        for id in &request.into_inner().pod_sandbox_ids {
+            container_ids.extend(self.cri_service.restore_pod_sandbox(id).await?);
+        }
+        Ok(Response::new(adminapi::RestorePodsResponse {
+            container_ids,
+        }))
+    }
+}
+
+/// Connect to the admin service served on the unix socket at `sock_path`.
+async fn connect(sock_path: &Path) -> anyhow::Result<AdminClient<Channel>> {
+    let sock_path = sock_path.to_path_buf();
+
+    // The URI is ignored by the connector but has to be valid
+    let channel = Endpoint::try_from("http://[::]:50051")?
+        .connect_with_connector(service_fn(move |_: Uri| {
+            UnixStream::connect(sock_path.clone())
+        }))
+        .await
+        .with_context(|| format!("connect to socket {}", sock_path.display()))?;
+    Ok(AdminClient::new(channel))
+}
+
+/// Retrieve the changes of the container `container_id` compared to its image from the admin
+/// socket at `sock_path`, formatted like `docker diff` does.
+pub async fn container_diff(sock_path: &Path, container_id: &str) -> anyhow::Result<Vec<String>> {
+    let request = adminapi::ContainerDiffRequest {
+        container_id: container_id.into(),
+    };
+    let response = connect(sock_path)
+        .await
+        .context("connect to admin socket")?
+        .container_diff(Request::new(request))
+        .await
+        .map_err(|e| anyhow::format_err!("{}", e.message()))?;
+    Ok(response
+        .into_inner()
+        .changes
+        .iter()
+        .map(|x| {
+            let kind = match adminapi::ChangeKind::from_i32(x.kind) {
+                Some(adminapi::ChangeKind::Added) => 'A',
+                Some(adminapi::ChangeKind::Changed) => 'C',
+                _ => 'D',
+            };
+            format!("{} {}", kind, x.path)
+        })
+        .collect())
+}
+
+/// Resolve the path of the log file of the container `container_id`, which may be a unique prefix
+/// of its ID, via the admin socket at `sock_path`.
+pub async fn container_log_path(sock_path: &Path, container_id: &str) -> anyhow::Result<PathBuf> {
+    let request = adminapi::ContainerLogPathRequest {
+        container_id: container_id.into(),
+    };
+    let response = connect(sock_path)
+        .await
+        .context("connect to admin socket")?
+        .container_log_path(Request::new(request))
+        .await
+        .map_err(|e| anyhow::format_err!("{}", e.message()))?;
+    Ok(response.into_inner().log_path.into())
+}
+
+/// Commit the changes of the container `container_id` as the new image `image` via the listen
+/// socket of the runtime at `sock_path`, whereas the `comment` describes the new layer. Returns
+/// the ID of the new image.
+pub async fn commit_container(
+    sock_path: &Path,
+    container_id: &str,
+    image: &str,
+    comment: &str,
+) -> anyhow::Result<String> {
+    let request = adminapi::CommitContainerRequest {
+        container_id: container_id.into(),
+        image: image.into(),
+        comment: comment.into(),
+    };
+    let response = connect(sock_path)
+        .await
+        .context("connect to runtime socket")?
+        .commit_container(Request::new(request))
+        .await
+        .map_err(|e| anyhow::format_err!("{}", e.message()))?;
+    Ok(response.into_inner().image_id)
+}
+
+/// Push the image `image` as the `reference`, or as itself if there is none, via the listen
+/// socket of the runtime at `sock_path`. Returns the digested reference of the pushed manifest.
+pub async fn push_image(
+    sock_path: &Path,
+    image: &str,
+    reference: Option<&str>,
+) -> anyhow::Result<String> {
+    let request = adminapi::PushImageRequest {
+        image: image.into(),
+        reference: reference.unwrap_or_default().into(),
+    };
+    let response = connect(sock_path)
+        .await
+        .context("connect to runtime socket")?
+        .push_image(Request::new(request))
+        .await
+        .map_err(|e| anyhow::format_err!("{}", e.message()))?;
+    Ok(response.into_inner().repo_digest)
+}
+
+/// Checkpoint the running containers of the pod sandboxes `pod_sandbox_ids` via the listen socket
+/// of the runtime at `sock_path`. Returns the IDs of the checkpointed containers.
+pub async fn checkpoint_pods(
+    sock_path: &Path,
+    pod_sandbox_ids: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let request = adminapi::CheckpointPodsRequest {
+        pod_sandbox_ids: pod_sandbox_ids.to_vec(),
+    };
+    let response = connect(sock_path)
+        .await
+        .context("connect to runtime socket")?
+        .checkpoint_pods(Request::new(request))
+        .await
+        .map_err(|e| anyhow::format_err!("{}", e.message()))?;
+    Ok(response.into_inner().container_ids)
+}
+
+/// Restore the checkpointed containers of the pod sandboxes `pod_sandbox_ids` via the listen
+/// socket of the runtime at `sock_path`. Returns the IDs of the restored containers.
+pub async fn restore_pods(
+    sock_path: &Path,
+    pod_sandbox_ids: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let request = adminapi::RestorePodsRequest {
+        pod_sandbox_ids: pod_sandbox_ids.to_vec(),
+    };
+    let response = connect(sock_path)
+        .await
+        .context("connect to runtime socket")?
+        .restore_pods(Request::new(request))
+        .await
+        .map_err(|e| anyhow::format_err!("{}", e.message()))?;
+    Ok(response.into_inner().container_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ConfigBuilder, cri_service::tests::new_cri_service_with_config, event::Event,
+    };
+    use anyhow::{Context, Result};
+    use tonic::Code;
+
+    fn new_admin_service() -> Result<AdminService> {
+        let config = ConfigBuilder::default().request_timeout(42u64).build()?;
+        Ok(AdminService::new(new_cri_service_with_config(config)?))
+    }
+
+    #[tokio::test]
+    async fn read_success() -> Result<()> {
+        let sut = new_admin_service()?;
+        sut.version(Request::new(criapi::VersionRequest::default()))
+            .await?;
+        sut.list_pod_sandbox(Request::new(criapi::ListPodSandboxRequest::default()))
+            .await?;
+        sut.list_images(Request::new(criapi::ListImagesRequest::default()))
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn status_success_verbose_config() -> Result<()> {
+        let sut = new_admin_service()?;
+        let request = criapi::StatusRequest { verbose: true };
+        let response = sut.status(Request::new(request)).await?;
+
+        let config = response
+            .get_ref()
+            .info
+            .get("config")
+            .context("config info is none")?;
+        assert!(config.contains("\"request-timeout\":42"));
+        assert!(response.get_ref().info.contains_key("features"));
+        assert_eq!(
+            response.get_ref().info.get("peers").map(String::as_str),
+            Some("[]")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn events_success() -> Result<()> {
+        let sut = new_admin_service()?;
+        let mut events = sut
+            .events(Request::new(adminapi::EventsRequest {}))
+            .await?
+            .into_inner();
+        sut.cri_service.events().publish(Event::PullStarted {
+            image: "app".into(),
+        });
+
+        let event = events.next().await.context("no event streamed")??;
+        assert_eq!(event.kind, "PullStarted");
+        assert_eq!(event.message, "Pulling image app");
+        assert!(event.timestamp > 0);
+        assert_eq!(event.fields.get("image").map(String::as_str), Some("app"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_fail_denied() -> Result<()> {
+        let sut = new_admin_service()?;
+        let response = sut
+            .run_pod_sandbox(Request::new(criapi::RunPodSandboxRequest::default()))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+
+        let response = sut
+            .exec_sync(Request::new(criapi::ExecSyncRequest::default()))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+
+        let response = sut
+            .pull_image(Request::new(criapi::PullImageRequest::default()))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn container_diff_fail_not_found() -> Result<()> {
+        let sut = new_admin_service()?;
+        let request = adminapi::ContainerDiffRequest {
+            container_id: "unknown".into(),
+        };
+        let response = sut.container_diff(Request::new(request)).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn container_log_path_fail_not_found() -> Result<()> {
+        let sut = new_admin_service()?;
+        let request = adminapi::ContainerLogPathRequest {
+            container_id: "unknown".into(),
+        };
+        let response = sut.container_log_path(Request::new(request)).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn commit_container_fail() -> Result<()> {
+        let request = || {
+            Request::new(adminapi::CommitContainerRequest {
+                container_id: "unknown".into(),
+                image: "localhost/app:debug".into(),
+                comment: "".into(),
+            })
+        };
+        let sut = new_admin_service()?;
+        let response = sut.commit_container(request()).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+
+        let sut = AdminService::writable(sut.cri_service);
+        let response = sut.commit_container(request()).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+
+        let mut invalid = request();
+        invalid.get_mut().image = "localhost/app@sha256:abc".into();
+        let response = sut.commit_container(invalid).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn push_image_fail() -> Result<()> {
+        let request = || {
+            Request::new(adminapi::PushImageRequest {
+                image: "localhost/app:debug".into(),
+                reference: "".into(),
+            })
+        };
+        let sut = new_admin_service()?;
+        let response = sut.push_image(request()).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+
+        let sut = AdminService::writable(sut.cri_service);
+        let response = sut.push_image(request()).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+    #[tokio::test]
+    async fn checkpoint_pods_fail() -> Result<()> {
+        let request = || {
+            Request::new(adminapi::CheckpointPodsRequest {
+                pod_sandbox_ids: vec!["unknown".into()],
+            })
+        };
+        let sut = new_admin_service()?;
+        let response = sut.checkpoint_pods(request()).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+
+        // The checkpoint feature is disabled by default
+        let sut = AdminService::writable(sut.cri_service);
+        let response = sut.checkpoint_pods(request()).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restore_pods_fail() -> Result<()> {
+        let request = || {
+            Request::new(adminapi::RestorePodsRequest {
+                pod_sandbox_ids: vec!["unknown".into()],
+            })
+        };
+        let sut = new_admin_service()?;
+        let response = sut.restore_pods(request()).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+
+        let sut = AdminService::writable(sut.cri_service);
+        let response = sut.restore_pods(request()).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,4 @@
+//! The generated API types and services of the read-only admin socket.
+#![allow(missing_docs)]
+
+tonic::include_proto!("admin.v1");
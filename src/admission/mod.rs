@@ -0,0 +1,146 @@
+//! Admission of pod sandboxes and containers
+//!
+//! Admissions are invoked before a pod sandbox or container gets created. Every admission can
+//! either reject the request by returning an error or mutate it in place.
+
+use crate::criapi::{CreateContainerRequest, RunPodSandboxRequest};
+use anyhow::{Context, Result};
+use std::sync::Arc;
+
+pub mod pod_security;
+pub mod policy;
+
+#[tonic::async_trait]
+/// The admission trait which defines the methods a policy implementation can fulfill. All
+/// methods admit the request by default.
+pub trait Admission: Send + Sync {
+    /// The name of the admission, used for error reporting.
+    fn name(&self) -> &str;
+
+    /// Admit or reject the provided pod sandbox `request`, which may be mutated.
+    async fn admit_pod_sandbox(&self, _request: &mut RunPodSandboxRequest) -> Result<()> {
+        Ok(())
+    }
+
+    /// Admit or reject the provided container `request`, which may be mutated.
+    async fn admit_container(&self, _request: &mut CreateContainerRequest) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+/// AdmissionChain invokes multiple admissions in the order they got added. The first rejecting
+/// admission stops the chain.
+pub struct AdmissionChain {
+    /// All admissions of the chain.
+    admissions: Vec<Arc<dyn Admission>>,
+}
+
+impl AdmissionChain {
+    /// Append a new admission to the end of the chain.
+    pub fn push(&mut self, admission: Arc<dyn Admission>) {
+        self.admissions.push(admission)
+    }
+
+    /// Run all admissions for the provided pod sandbox `request`.
+    pub async fn admit_pod_sandbox(&self, request: &mut RunPodSandboxRequest) -> Result<()> {
+        for admission in &self.admissions {
+            admission
+                .admit_pod_sandbox(request)
+                .await
+                .with_context(|| format!("rejected by admission {}", admission.name()))?;
+        }
+        Ok(())
+    }
+
+    /// Run all admissions for the provided container `request`.
+    pub async fn admit_container(&self, request: &mut CreateContainerRequest) -> Result<()> {
+        for admission in &self.admissions {
+            admission
+                .admit_container(request)
+                .await
+                .with_context(|| format!("rejected by admission {}", admission.name()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    /// An admission which rejects every request.
+    pub struct RejectAll;
+
+    #[tonic::async_trait]
+    impl Admission for RejectAll {
+        fn name(&self) -> &str {
+            "reject-all"
+        }
+
+        async fn admit_pod_sandbox(&self, _: &mut RunPodSandboxRequest) -> Result<()> {
+            bail!("pod sandboxes are not allowed")
+        }
+
+        async fn admit_container(&self, _: &mut CreateContainerRequest) -> Result<()> {
+            bail!("containers are not allowed")
+        }
+    }
+
+    /// An admission which sets the runtime handler of every pod sandbox.
+    struct SetHandler;
+
+    #[tonic::async_trait]
+    impl Admission for SetHandler {
+        fn name(&self) -> &str {
+            "set-handler"
+        }
+
+        async fn admit_pod_sandbox(&self, request: &mut RunPodSandboxRequest) -> Result<()> {
+            request.runtime_handler = "mutated".into();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn admit_success_empty() -> Result<()> {
+        let sut = AdmissionChain::default();
+        sut.admit_pod_sandbox(&mut RunPodSandboxRequest::default())
+            .await?;
+        sut.admit_container(&mut CreateContainerRequest::default())
+            .await
+    }
+
+    #[tokio::test]
+    async fn admit_success_mutate() -> Result<()> {
+        let mut sut = AdmissionChain::default();
+        sut.push(Arc::new(SetHandler));
+
+        let mut request = RunPodSandboxRequest::default();
+        sut.admit_pod_sandbox(&mut request).await?;
+        assert_eq!(request.runtime_handler, "mutated");
+
+        sut.admit_container(&mut CreateContainerRequest::default())
+            .await
+    }
+
+    #[tokio::test]
+    async fn admit_fail_rejected() {
+        let mut sut = AdmissionChain::default();
+        sut.push(Arc::new(RejectAll));
+        sut.push(Arc::new(SetHandler));
+
+        let mut request = RunPodSandboxRequest::default();
+        let err = sut.admit_pod_sandbox(&mut request).await.err();
+        assert!(err
+            .map(|e| format!("{:#}", e).contains("reject-all"))
+            .unwrap_or_default());
+        assert!(request.runtime_handler.is_empty());
+
+        assert!(sut
+            .admit_container(&mut CreateContainerRequest::default())
+            .await
+            .is_err());
+    }
+}
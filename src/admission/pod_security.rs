@@ -0,0 +1,483 @@
+//! Enforcement of the Kubernetes Pod Security Standards
+//!
+//! The standards are usually enforced by the admission of the API server. Enforcing them again
+//! on the runtime level rejects workloads which bypassed the API server, for example static pods
+//! or requests from a compromised kubelet.
+//!
+//! The `baseline` level prevents known privilege escalations, whereas the `restricted` level
+//! additionally enforces the current pod hardening best practices. Checks which depend on the
+//! pod spec, like the allowed volume types, are not visible on the runtime level and therefore
+//! skipped.
+
+use crate::{
+    admission::Admission,
+    config::PodSecurityLevel,
+    criapi::{
+        CreateContainerRequest, LinuxContainerSecurityContext, NamespaceMode, NamespaceOption,
+        RunPodSandboxRequest, SeLinuxOption,
+    },
+};
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+/// The capabilities which are allowed to be added on the baseline level.
+const BASELINE_CAPABILITIES: &[&str] = &[
+    "AUDIT_WRITE",
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "FOWNER",
+    "FSETID",
+    "KILL",
+    "MKNOD",
+    "NET_BIND_SERVICE",
+    "SETFCAP",
+    "SETGID",
+    "SETPCAP",
+    "SETUID",
+    "SYS_CHROOT",
+];
+
+/// The capabilities which are allowed to be added on the restricted level.
+const RESTRICTED_CAPABILITIES: &[&str] = &["NET_BIND_SERVICE"];
+
+/// The sysctls which are considered safe on the baseline level.
+const SAFE_SYSCTLS: &[&str] = &[
+    "kernel.shm_rmid_forced",
+    "net.ipv4.ip_local_port_range",
+    "net.ipv4.ip_unprivileged_port_start",
+    "net.ipv4.tcp_syncookies",
+    "net.ipv4.ping_group_range",
+];
+
+/// The SELinux types which are allowed on the baseline level.
+const SELINUX_TYPES: &[&str] = &["container_t", "container_init_t", "container_kvm_t"];
+
+/// The seccomp profiles which are allowed on the restricted level, besides localhost profiles.
+const SECCOMP_PROFILES: &[&str] = &["runtime/default", "docker/default"];
+
+/// The prefix of seccomp and AppArmor profiles which are loaded from the node.
+const LOCALHOST_PREFIX: &str = "localhost/";
+
+/// The seccomp and AppArmor profile which disables the confinement.
+const UNCONFINED: &str = "unconfined";
+
+/// PodSecurity is the admission which rejects pod sandboxes and containers violating the Pod
+/// Security Standard of the configured level.
+pub struct PodSecurity {
+    /// The enforced level.
+    level: PodSecurityLevel,
+}
+
+impl PodSecurity {
+    /// Create a new admission enforcing the provided `level`.
+    pub fn new(level: PodSecurityLevel) -> Self {
+        Self { level }
+    }
+
+    /// Returns true if the restricted level is being enforced.
+    fn restricted(&self) -> bool {
+        self.level == PodSecurityLevel::Restricted
+    }
+
+    /// Returns true if nothing is being enforced.
+    fn privileged(&self) -> bool {
+        self.level == PodSecurityLevel::Privileged
+    }
+
+    /// Collect the violations of the pod level namespace `options`.
+    fn validate_namespaces(options: Option<&NamespaceOption>, violations: &mut Vec<String>) {
+        let options = match options {
+            Some(options) => options,
+            None => return,
+        };
+        for (name, mode) in &[
+            ("network", options.network),
+            ("PID", options.pid),
+            ("IPC", options.ipc),
+        ] {
+            if *mode == NamespaceMode::Node as i32 {
+                violations.push(format!("host {} namespace is not allowed", name));
+            }
+        }
+    }
+
+    /// Collect the violations of the SELinux `options`.
+    fn validate_selinux(options: Option<&SeLinuxOption>, violations: &mut Vec<String>) {
+        let options = match options {
+            Some(options) => options,
+            None => return,
+        };
+        if !options.r#type.is_empty() && !SELINUX_TYPES.contains(&options.r#type.as_str()) {
+            violations.push(format!("SELinux type {} is not allowed", options.r#type));
+        }
+        if !options.user.is_empty() || !options.role.is_empty() {
+            violations.push("custom SELinux user or role is not allowed".into());
+        }
+    }
+
+    /// Collect the violations of the `sysctls`.
+    fn validate_sysctls(sysctls: &HashMap<String, String>, violations: &mut Vec<String>) {
+        let mut unsafe_sysctls: Vec<&str> = sysctls
+            .keys()
+            .map(String::as_str)
+            .filter(|x| !SAFE_SYSCTLS.contains(x))
+            .collect();
+        if !unsafe_sysctls.is_empty() {
+            unsafe_sysctls.sort_unstable();
+            violations.push(format!(
+                "unsafe sysctls are not allowed: {}",
+                unsafe_sysctls.join(", ")
+            ));
+        }
+    }
+
+    /// Collect the violations of the container security `context`.
+    fn validate_container(
+        &self,
+        context: &LinuxContainerSecurityContext,
+        violations: &mut Vec<String>,
+    ) {
+        if context.privileged {
+            violations.push("privileged containers are not allowed".into());
+        }
+        Self::validate_namespaces(context.namespace_options.as_ref(), violations);
+        Self::validate_selinux(context.selinux_options.as_ref(), violations);
+
+        let apparmor = context.apparmor_profile.as_str();
+        if apparmor == UNCONFINED {
+            violations.push("unconfined AppArmor profile is not allowed".into());
+        }
+
+        let seccomp = context.seccomp_profile_path.as_str();
+        if self.restricted() {
+            if !SECCOMP_PROFILES.contains(&seccomp) && !seccomp.starts_with(LOCALHOST_PREFIX) {
+                violations
+                    .push("seccomp profile has to be set to runtime/default or localhost".into());
+            }
+        } else if seccomp == UNCONFINED {
+            violations.push("unconfined seccomp profile is not allowed".into());
+        }
+
+        let (added, dropped): (Vec<String>, Vec<String>) = context
+            .capabilities
+            .as_ref()
+            .map(|x| {
+                (
+                    x.add_capabilities.iter().map(|x| normalize(x)).collect(),
+                    x.drop_capabilities.iter().map(|x| normalize(x)).collect(),
+                )
+            })
+            .unwrap_or_else(|| (vec![], vec![]));
+        let allowed = if self.restricted() {
+            RESTRICTED_CAPABILITIES
+        } else {
+            BASELINE_CAPABILITIES
+        };
+        let forbidden: Vec<&str> = added
+            .iter()
+            .map(String::as_str)
+            .filter(|x| !allowed.contains(x))
+            .collect();
+        if !forbidden.is_empty() {
+            violations.push(format!(
+                "adding capabilities is not allowed: {}",
+                forbidden.join(", ")
+            ));
+        }
+
+        if self.restricted() {
+            if !dropped.iter().any(|x| x == "ALL") {
+                violations.push("all capabilities have to be dropped".into());
+            }
+            if !context.no_new_privs {
+                violations.push("privilege escalation is not allowed".into());
+            }
+            // The user of the image is unknown here, which requires an explicit non-root user
+            match context.run_as_user.as_ref().map(|x| x.value) {
+                Some(uid) if uid > 0 => {}
+                Some(_) => violations.push("running as root is not allowed".into()),
+                None => violations.push("a non-root user ID has to be set".into()),
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Admission for PodSecurity {
+    fn name(&self) -> &str {
+        "pod-security"
+    }
+
+    async fn admit_pod_sandbox(&self, request: &mut RunPodSandboxRequest) -> Result<()> {
+        let config = match request.config.as_ref() {
+            Some(config) if !self.privileged() => config,
+            _ => return Ok(()),
+        };
+        let mut violations = vec![];
+
+        let linux = config.linux.as_ref();
+        if let Some(context) = linux.and_then(|x| x.security_context.as_ref()) {
+            if context.privileged {
+                violations.push("privileged pod sandboxes are not allowed".into());
+            }
+            Self::validate_namespaces(context.namespace_options.as_ref(), &mut violations);
+            Self::validate_selinux(context.selinux_options.as_ref(), &mut violations);
+            if context.seccomp_profile_path == UNCONFINED {
+                violations.push("unconfined seccomp profile is not allowed".into());
+            }
+        }
+        if let Some(linux) = linux {
+            Self::validate_sysctls(&linux.sysctls, &mut violations);
+        }
+        if config.port_mappings.iter().any(|x| x.host_port != 0) {
+            violations.push("host ports are not allowed".into());
+        }
+
+        check(self.level, violations)
+    }
+
+    async fn admit_container(&self, request: &mut CreateContainerRequest) -> Result<()> {
+        if self.privileged() {
+            return Ok(());
+        }
+        let mut violations = vec![];
+
+        let default_context = LinuxContainerSecurityContext::default();
+        let context = request
+            .config
+            .as_ref()
+            .and_then(|x| x.linux.as_ref())
+            .and_then(|x| x.security_context.as_ref())
+            .unwrap_or(&default_context);
+        self.validate_container(context, &mut violations);
+
+        check(self.level, violations)
+    }
+}
+
+/// Fail with all `violations` of the `level`, if there are any.
+fn check(level: PodSecurityLevel, violations: Vec<String>) -> Result<()> {
+    if !violations.is_empty() {
+        bail!(
+            "violates pod security standard {:?}: {}",
+            level,
+            violations.join("; ")
+        )
+    }
+    Ok(())
+}
+
+/// Normalize the `capability` to its upper case name without the `CAP_` prefix.
+fn normalize(capability: &str) -> String {
+    let capability = capability.to_uppercase();
+    capability
+        .strip_prefix("CAP_")
+        .map(Into::into)
+        .unwrap_or(capability)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::criapi::{
+        Capability, ContainerConfig, Int64Value, LinuxContainerConfig, LinuxPodSandboxConfig,
+        LinuxSandboxSecurityContext, PodSandboxConfig, PortMapping,
+    };
+
+    fn sandbox_request(context: LinuxSandboxSecurityContext) -> RunPodSandboxRequest {
+        RunPodSandboxRequest {
+            config: Some(PodSandboxConfig {
+                linux: Some(LinuxPodSandboxConfig {
+                    security_context: Some(context),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn container_request(context: LinuxContainerSecurityContext) -> CreateContainerRequest {
+        CreateContainerRequest {
+            config: Some(ContainerConfig {
+                linux: Some(LinuxContainerConfig {
+                    security_context: Some(context),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn restricted_context() -> LinuxContainerSecurityContext {
+        LinuxContainerSecurityContext {
+            capabilities: Some(Capability {
+                add_capabilities: vec!["NET_BIND_SERVICE".into()],
+                drop_capabilities: vec!["ALL".into()],
+            }),
+            run_as_user: Some(Int64Value { value: 1000 }),
+            seccomp_profile_path: "runtime/default".into(),
+            no_new_privs: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn normalize_success() {
+        assert_eq!(normalize("net_admin"), "NET_ADMIN");
+        assert_eq!(normalize("CAP_SYS_ADMIN"), "SYS_ADMIN");
+    }
+
+    #[tokio::test]
+    async fn admit_success_privileged() -> Result<()> {
+        let sut = PodSecurity::new(PodSecurityLevel::Privileged);
+        sut.admit_pod_sandbox(&mut sandbox_request(LinuxSandboxSecurityContext {
+            privileged: true,
+            ..Default::default()
+        }))
+        .await?;
+        sut.admit_container(&mut container_request(LinuxContainerSecurityContext {
+            privileged: true,
+            ..Default::default()
+        }))
+        .await
+    }
+
+    #[tokio::test]
+    async fn admit_pod_sandbox_baseline() -> Result<()> {
+        let sut = PodSecurity::new(PodSecurityLevel::Baseline);
+        sut.admit_pod_sandbox(&mut sandbox_request(Default::default()))
+            .await?;
+
+        let mut request = sandbox_request(LinuxSandboxSecurityContext {
+            namespace_options: Some(NamespaceOption {
+                network: NamespaceMode::Node as i32,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let err = sut.admit_pod_sandbox(&mut request).await.err();
+        assert!(err
+            .map(|e| e.to_string().contains("host network namespace"))
+            .unwrap_or_default());
+
+        let mut request = sandbox_request(Default::default());
+        if let Some(config) = request.config.as_mut() {
+            config.port_mappings.push(PortMapping {
+                host_port: 8080,
+                ..Default::default()
+            });
+            if let Some(linux) = config.linux.as_mut() {
+                linux
+                    .sysctls
+                    .insert("net.ipv4.tcp_syncookies".into(), "1".into());
+            }
+        }
+        assert!(sut.admit_pod_sandbox(&mut request).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admit_pod_sandbox_fail_sysctls() {
+        let sut = PodSecurity::new(PodSecurityLevel::Baseline);
+        let mut request = sandbox_request(Default::default());
+        if let Some(linux) = request.config.as_mut().and_then(|x| x.linux.as_mut()) {
+            linux.sysctls.insert("kernel.msgmax".into(), "1".into());
+        }
+        let err = sut.admit_pod_sandbox(&mut request).await.err();
+        assert!(err
+            .map(|e| e.to_string().contains("kernel.msgmax"))
+            .unwrap_or_default());
+    }
+
+    #[tokio::test]
+    async fn admit_container_baseline() -> Result<()> {
+        let sut = PodSecurity::new(PodSecurityLevel::Baseline);
+        sut.admit_container(&mut container_request(Default::default()))
+            .await?;
+        sut.admit_container(&mut CreateContainerRequest::default())
+            .await?;
+        sut.admit_container(&mut container_request(LinuxContainerSecurityContext {
+            capabilities: Some(Capability {
+                add_capabilities: vec!["cap_chown".into()],
+                drop_capabilities: vec![],
+            }),
+            ..Default::default()
+        }))
+        .await?;
+
+        for context in &[
+            LinuxContainerSecurityContext {
+                privileged: true,
+                ..Default::default()
+            },
+            LinuxContainerSecurityContext {
+                capabilities: Some(Capability {
+                    add_capabilities: vec!["SYS_ADMIN".into()],
+                    drop_capabilities: vec![],
+                }),
+                ..Default::default()
+            },
+            LinuxContainerSecurityContext {
+                seccomp_profile_path: UNCONFINED.into(),
+                ..Default::default()
+            },
+            LinuxContainerSecurityContext {
+                apparmor_profile: UNCONFINED.into(),
+                ..Default::default()
+            },
+            LinuxContainerSecurityContext {
+                selinux_options: Some(SeLinuxOption {
+                    r#type: "spc_t".into(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        ] {
+            assert!(sut
+                .admit_container(&mut container_request(context.clone()))
+                .await
+                .is_err());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admit_container_restricted() -> Result<()> {
+        let sut = PodSecurity::new(PodSecurityLevel::Restricted);
+        sut.admit_container(&mut container_request(restricted_context()))
+            .await?;
+
+        // Nothing set violates every restricted check at once
+        let err = sut
+            .admit_container(&mut container_request(Default::default()))
+            .await
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(err.contains("Restricted"));
+        assert!(err.contains("seccomp"));
+        assert!(err.contains("dropped"));
+        assert!(err.contains("privilege escalation"));
+        assert!(err.contains("non-root"));
+
+        let mut context = restricted_context();
+        context.run_as_user = Some(Int64Value { value: 0 });
+        assert!(sut
+            .admit_container(&mut container_request(context))
+            .await
+            .is_err());
+
+        let mut context = restricted_context();
+        context.capabilities = Some(Capability {
+            add_capabilities: vec!["CHOWN".into()],
+            drop_capabilities: vec!["ALL".into()],
+        });
+        assert!(sut
+            .admit_container(&mut container_request(context))
+            .await
+            .is_err());
+        Ok(())
+    }
+}
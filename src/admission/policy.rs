@@ -0,0 +1,441 @@
+//! Policies scoped by the Kubernetes namespace of pod sandboxes and containers
+//!
+//! The policies get loaded from a JSON file, which contains a `default` policy as well as
+//! optional overrides per namespace:
+//!
+//! ```json
+//! {
+//!   "default": {
+//!     "allowed_registries": ["quay.io/tenant"],
+//!     "allow_privileged": false,
+//!     "require_seccomp": true,
+//!     "max_millicpus": 2000,
+//!     "max_memory": 4294967296
+//!   },
+//!   "namespaces": {
+//!     "kube-system": {}
+//!   }
+//! }
+//! ```
+//!
+//! A namespace specific policy fully replaces the default policy and unset fields are
+//! unrestricted.
+
+use crate::{
+    admission::Admission,
+    criapi::{CreateContainerRequest, PodSandboxConfig, RunPodSandboxRequest},
+};
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs::File, path::Path};
+
+/// The seccomp profile which disables seccomp, equal to an empty profile.
+const SECCOMP_UNCONFINED: &str = "unconfined";
+
+/// The registry used for image references without an explicit registry.
+const DEFAULT_REGISTRY: &str = "docker.io";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+/// Policies is the admission which enforces a policy depending on the namespace of the request.
+pub struct Policies {
+    /// The policy for all namespaces which do not have a dedicated one.
+    default: Policy,
+
+    /// Policies per namespace, which take precedence over the default policy.
+    namespaces: HashMap<String, Policy>,
+}
+
+#[derive(Clone, Debug, CopyGetters, Deserialize, Getters, Serialize)]
+#[serde(default, deny_unknown_fields)]
+/// Policy contains all restrictions which apply to a single namespace.
+pub struct Policy {
+    #[get = "pub"]
+    /// Registries or repository prefixes containers are allowed to use images from, like
+    /// `quay.io` or `quay.io/tenant`. All registries are allowed if not set.
+    allowed_registries: Option<Vec<String>>,
+
+    #[get_copy = "pub"]
+    /// Whether privileged pod sandboxes and containers are allowed.
+    allow_privileged: bool,
+
+    #[get_copy = "pub"]
+    /// Whether unprivileged containers are required to run with an enabled seccomp profile.
+    require_seccomp: bool,
+
+    #[get_copy = "pub"]
+    /// The maximum CPU limit of a single container in millicpus. Containers without a CPU limit
+    /// are rejected if set.
+    max_millicpus: Option<u64>,
+
+    #[get_copy = "pub"]
+    /// The maximum memory limit of a single container in bytes. Containers without a memory
+    /// limit are rejected if set.
+    max_memory: Option<u64>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            allowed_registries: None,
+            allow_privileged: true,
+            require_seccomp: false,
+            max_millicpus: None,
+            max_memory: None,
+        }
+    }
+}
+
+impl Policies {
+    /// Load the policies from the provided file `Path`.
+    pub fn from(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("open file {}", path.display()))?;
+        serde_json::from_reader(file)
+            .with_context(|| format!("deserialize policies from file {}", path.display()))
+    }
+
+    /// Retrieve the policy for the provided `namespace`.
+    pub fn policy(&self, namespace: &str) -> &Policy {
+        self.namespaces.get(namespace).unwrap_or(&self.default)
+    }
+
+    /// Retrieve the policy for the namespace of the provided sandbox `config`.
+    fn policy_for(&self, config: Option<&PodSandboxConfig>) -> &Policy {
+        let namespace = config
+            .and_then(|x| x.metadata.as_ref())
+            .map(|x| x.namespace.as_str())
+            .unwrap_or_default();
+        self.policy(namespace)
+    }
+}
+
+#[tonic::async_trait]
+impl Admission for Policies {
+    fn name(&self) -> &str {
+        "namespace-policy"
+    }
+
+    async fn admit_pod_sandbox(&self, request: &mut RunPodSandboxRequest) -> Result<()> {
+        let policy = self.policy_for(request.config.as_ref());
+        let privileged = request
+            .config
+            .as_ref()
+            .and_then(|x| x.linux.as_ref())
+            .and_then(|x| x.security_context.as_ref())
+            .map(|x| x.privileged)
+            .unwrap_or_default();
+        if privileged && !policy.allow_privileged() {
+            bail!("privileged pod sandboxes are not allowed")
+        }
+        Ok(())
+    }
+
+    async fn admit_container(&self, request: &mut CreateContainerRequest) -> Result<()> {
+        let policy = self.policy_for(request.sandbox_config.as_ref());
+        let config = request.config.as_ref().context("no container config")?;
+
+        if let Some(allowed) = policy.allowed_registries() {
+            let image = config
+                .image
+                .as_ref()
+                .map(|x| x.image.as_str())
+                .unwrap_or_default();
+            policy.validate_image(allowed, image)?;
+        }
+
+        let linux = config.linux.as_ref();
+        let security_context = linux.and_then(|x| x.security_context.as_ref());
+        let privileged = security_context.map(|x| x.privileged).unwrap_or_default();
+        if privileged && !policy.allow_privileged() {
+            bail!("privileged containers are not allowed")
+        }
+        if !privileged && policy.require_seccomp() {
+            let profile = security_context
+                .map(|x| x.seccomp_profile_path.as_str())
+                .unwrap_or_default();
+            if profile.is_empty() || profile == SECCOMP_UNCONFINED {
+                bail!("containers are required to run with a seccomp profile")
+            }
+        }
+
+        let resources = linux.and_then(|x| x.resources.as_ref());
+        if let Some(max) = policy.max_millicpus() {
+            let millicpus = resources
+                .filter(|x| x.cpu_quota > 0 && x.cpu_period > 0)
+                .map(|x| (x.cpu_quota as u64 * 1000) / x.cpu_period as u64);
+            match millicpus {
+                Some(millicpus) if millicpus <= max => {}
+                Some(millicpus) => bail!(
+                    "CPU limit of {} millicpus exceeds the maximum of {}",
+                    millicpus,
+                    max
+                ),
+                None => bail!("CPU limit required, maximum is {} millicpus", max),
+            }
+        }
+        if let Some(max) = policy.max_memory() {
+            let memory = resources
+                .map(|x| x.memory_limit_in_bytes)
+                .filter(|x| *x > 0);
+            match memory {
+                Some(memory) if memory as u64 <= max => {}
+                Some(memory) => bail!(
+                    "memory limit of {} bytes exceeds the maximum of {}",
+                    memory,
+                    max
+                ),
+                None => bail!("memory limit required, maximum is {} bytes", max),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Policy {
+    /// Validate that the `image` reference points to one of the `allowed` registries. Image IDs
+    /// cannot be attributed to a registry and are therefore admitted.
+    fn validate_image(&self, allowed: &[String], image: &str) -> Result<()> {
+        if is_image_id(image) {
+            debug!("Skipping registry validation of image ID {}", image);
+            return Ok(());
+        }
+
+        let reference = normalize(image);
+        if allowed
+            .iter()
+            .map(|x| x.trim_end_matches('/'))
+            .any(|x| reference == x || reference.starts_with(&format!("{}/", x)))
+        {
+            return Ok(());
+        }
+        bail!("image {} is not from an allowed registry", image)
+    }
+}
+
+/// Returns true if the `image` is an image ID instead of a reference.
+fn is_image_id(image: &str) -> bool {
+    let id = image.trim_start_matches("sha256:");
+    id.len() == 64 && id.chars().all(|x| x.is_ascii_hexdigit())
+}
+
+/// Normalize the `image` reference to always contain the registry.
+fn normalize(image: &str) -> String {
+    match image.split('/').next() {
+        Some(first)
+            if image.contains('/')
+                && (first.contains('.') || first.contains(':') || first == "localhost") =>
+        {
+            image.into()
+        }
+        _ => format!("{}/{}", DEFAULT_REGISTRY, image),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::criapi::{
+        ContainerConfig, ImageSpec, LinuxContainerConfig, LinuxContainerResources,
+        LinuxContainerSecurityContext, LinuxPodSandboxConfig, LinuxSandboxSecurityContext,
+        PodSandboxMetadata,
+    };
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn new_policies() -> Result<Policies> {
+        let mut file = NamedTempFile::new()?;
+        write!(
+            file,
+            r#"{{
+                "default": {{
+                    "allowed_registries": ["quay.io/tenant", "localhost:5000"],
+                    "allow_privileged": false,
+                    "require_seccomp": true,
+                    "max_millicpus": 2000,
+                    "max_memory": 1073741824
+                }},
+                "namespaces": {{
+                    "kube-system": {{}}
+                }}
+            }}"#
+        )?;
+        Policies::from(file.path())
+    }
+
+    fn sandbox_config(namespace: &str) -> Option<PodSandboxConfig> {
+        Some(PodSandboxConfig {
+            metadata: Some(PodSandboxMetadata {
+                namespace: namespace.into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn container_request(namespace: &str, image: &str, privileged: bool) -> CreateContainerRequest {
+        CreateContainerRequest {
+            sandbox_config: sandbox_config(namespace),
+            config: Some(ContainerConfig {
+                image: Some(ImageSpec {
+                    image: image.into(),
+                    ..Default::default()
+                }),
+                linux: Some(LinuxContainerConfig {
+                    resources: Some(LinuxContainerResources {
+                        cpu_period: 100_000,
+                        cpu_quota: 150_000,
+                        memory_limit_in_bytes: 512 * 1024 * 1024,
+                        ..Default::default()
+                    }),
+                    security_context: Some(LinuxContainerSecurityContext {
+                        privileged,
+                        seccomp_profile_path: "runtime/default".into(),
+                        ..Default::default()
+                    }),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn from_failure() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        write!(file, r#"{{"default": {{"unknown": true}}}}"#)?;
+        assert!(Policies::from(file.path()).is_err());
+        assert!(Policies::from(Path::new("/some/invalid/path")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn policy_scoped_by_namespace() -> Result<()> {
+        let sut = new_policies()?;
+        assert!(!sut.policy("tenant").allow_privileged());
+        assert!(sut.policy("kube-system").allow_privileged());
+        assert!(sut.policy("kube-system").allowed_registries().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_success() {
+        assert_eq!(normalize("nginx"), "docker.io/nginx");
+        assert_eq!(
+            normalize("library/nginx:1.19"),
+            "docker.io/library/nginx:1.19"
+        );
+        assert_eq!(normalize("quay.io/tenant/app"), "quay.io/tenant/app");
+        assert_eq!(normalize("localhost/app"), "localhost/app");
+        assert_eq!(normalize("localhost:5000/app"), "localhost:5000/app");
+    }
+
+    #[test]
+    fn is_image_id_success() {
+        let id = "a".repeat(64);
+        assert!(is_image_id(&id));
+        assert!(is_image_id(&format!("sha256:{}", id)));
+        assert!(!is_image_id("nginx"));
+    }
+
+    #[tokio::test]
+    async fn admit_pod_sandbox_privileged() -> Result<()> {
+        let sut = new_policies()?;
+        let request = |namespace: &str| {
+            let mut config = sandbox_config(namespace).unwrap_or_default();
+            config.linux = Some(LinuxPodSandboxConfig {
+                security_context: Some(LinuxSandboxSecurityContext {
+                    privileged: true,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+            RunPodSandboxRequest {
+                config: Some(config),
+                ..Default::default()
+            }
+        };
+
+        assert!(sut.admit_pod_sandbox(&mut request("tenant")).await.is_err());
+        sut.admit_pod_sandbox(&mut request("kube-system")).await
+    }
+
+    #[tokio::test]
+    async fn admit_container_success() -> Result<()> {
+        let sut = new_policies()?;
+        sut.admit_container(&mut container_request(
+            "tenant",
+            "quay.io/tenant/app",
+            false,
+        ))
+        .await?;
+        sut.admit_container(&mut container_request(
+            "tenant",
+            "localhost:5000/app",
+            false,
+        ))
+        .await?;
+        sut.admit_container(&mut container_request("kube-system", "nginx", true))
+            .await
+    }
+
+    #[tokio::test]
+    async fn admit_container_failure_registry() -> Result<()> {
+        let sut = new_policies()?;
+        for image in &["nginx", "quay.io/other/app", "quay.io/tenant-evil/app"] {
+            assert!(sut
+                .admit_container(&mut container_request("tenant", image, false))
+                .await
+                .is_err());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admit_container_failure_security() -> Result<()> {
+        let sut = new_policies()?;
+        let image = "quay.io/tenant/app";
+        assert!(sut
+            .admit_container(&mut container_request("tenant", image, true))
+            .await
+            .is_err());
+
+        let mut request = container_request("tenant", image, false);
+        if let Some(context) = request
+            .config
+            .as_mut()
+            .and_then(|x| x.linux.as_mut())
+            .and_then(|x| x.security_context.as_mut())
+        {
+            context.seccomp_profile_path = SECCOMP_UNCONFINED.into();
+        }
+        assert!(sut.admit_container(&mut request).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admit_container_failure_resources() -> Result<()> {
+        let sut = new_policies()?;
+        let image = "quay.io/tenant/app";
+
+        let mut request = container_request("tenant", image, false);
+        if let Some(resources) = request
+            .config
+            .as_mut()
+            .and_then(|x| x.linux.as_mut())
+            .and_then(|x| x.resources.as_mut())
+        {
+            resources.cpu_quota = 300_000;
+        }
+        assert!(sut.admit_container(&mut request).await.is_err());
+
+        let mut request = container_request("tenant", image, false);
+        if let Some(linux) = request.config.as_mut().and_then(|x| x.linux.as_mut()) {
+            linux.resources = None;
+        }
+        assert!(sut.admit_container(&mut request).await.is_err());
+        Ok(())
+    }
+}
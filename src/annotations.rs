@@ -0,0 +1,355 @@
+//! Support for low-level tuning annotations known from other container runtimes like CRI-O.
+
+use anyhow::{bail, Context, Error, Result};
+use getset::Getters;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
+
+/// Prefix of the annotations which set cgroup v2 unified resources for a single container. The
+/// container name has to be appended, whereas the value is a semicolon separated list of
+/// `KEY=VALUE` pairs, like `memory.high=1073741824;memory.swap.max=0`.
+pub const UNIFIED_CGROUP_ANNOTATION: &str = "io.kubernetes.cri-o.UnifiedCgroup";
+
+/// The annotation for the size of `/dev/shm`, like `64Mi` or `1G`.
+pub const SHM_SIZE_ANNOTATION: &str = "io.kubernetes.cri-o.ShmSize";
+
+/// The annotation for the user namespace mode, like `auto` or `auto:size=65536`.
+pub const USERNS_MODE_ANNOTATION: &str = "io.kubernetes.cri-o.userns-mode";
+
+/// The annotation for requesting host devices, using the same format as the
+/// `device::DEVICES_ANNOTATION`.
+pub const DEVICES_ANNOTATION: &str = "io.kubernetes.cri-o.Devices";
+
+#[derive(Debug, Default, Getters, PartialEq)]
+/// Tuning contains the low-level settings requested via allowed annotations.
+pub struct Tuning {
+    #[get = "pub"]
+    /// Unified cgroup resources of the container.
+    unified: HashMap<String, String>,
+
+    #[get = "pub"]
+    /// Size of `/dev/shm` in bytes.
+    shm_size: Option<u64>,
+
+    #[get = "pub"]
+    /// Requested user namespace mode.
+    userns_mode: Option<String>,
+
+    #[get = "pub"]
+    /// Requested host devices.
+    devices: Option<String>,
+}
+
+impl Tuning {
+    /// Parse the tuning for the container `container_name` from the provided `annotations`.
+    /// Annotations which are not part of the `allowed` ones will be ignored. An allowed entry
+    /// matches the annotation itself as well as all annotations using it as prefix followed by a
+    /// dot, which means that `io.kubernetes.cri-o.UnifiedCgroup` allows unified resources for all
+    /// containers.
+    pub fn from_annotations(
+        annotations: &HashMap<String, String>,
+        container_name: &str,
+        allowed: &[String],
+    ) -> Result<Self> {
+        let get = |key: &str| {
+            let value = annotations.get(key);
+            if value.is_some() && !Self::is_allowed(key, allowed) {
+                debug!("Ignoring annotation {} because it is not allowed", key);
+                return None;
+            }
+            value
+        };
+
+        let mut tuning = Self::default();
+
+        let unified_key = format!("{}.{}", UNIFIED_CGROUP_ANNOTATION, container_name);
+        if let Some(value) = get(&unified_key) {
+            tuning.unified = Self::parse_unified(value)
+                .with_context(|| format!("parse annotation {}", unified_key))?;
+        }
+
+        if let Some(value) = get(SHM_SIZE_ANNOTATION) {
+            tuning.shm_size = Some(
+                parse_size(value)
+                    .with_context(|| format!("parse annotation {}", SHM_SIZE_ANNOTATION))?,
+            );
+        }
+
+        if let Some(value) = get(USERNS_MODE_ANNOTATION) {
+            let mode = value.splitn(2, ':').next().unwrap_or_default();
+            if !["auto", "host", "private"].contains(&mode) {
+                bail!("invalid user namespace mode {}", value)
+            }
+            tuning.userns_mode = Some(value.into());
+        }
+
+        tuning.devices = get(DEVICES_ANNOTATION).cloned();
+
+        Ok(tuning)
+    }
+
+    /// Check if the annotation `key` is part of the `allowed` ones.
+    fn is_allowed(key: &str, allowed: &[String]) -> bool {
+        allowed.iter().any(|x| matches_allowed(key, x))
+    }
+
+    /// Parse semicolon separated `KEY=VALUE` pairs.
+    fn parse_unified(value: &str) -> Result<HashMap<String, String>> {
+        value
+            .split(';')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                let mut parts = x.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(k), Some(v)) if !k.is_empty() => Ok((k.into(), v.into())),
+                    _ => bail!("invalid unified resource {}", x),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Getters, PartialEq, Serialize)]
+/// RuntimeAnnotation allows an annotation to be passed to the OCI runtime of a runtime handler.
+pub struct RuntimeAnnotation {
+    #[get = "pub"]
+    /// The runtime handler, like `kata`, whereas `default` refers to the empty one.
+    handler: String,
+
+    #[get = "pub"]
+    /// The allowed annotation, which matches like the allowed tuning annotations.
+    annotation: String,
+}
+
+impl FromStr for RuntimeAnnotation {
+    type Err = Error;
+
+    /// Parse a runtime annotation in the format `HANDLER=ANNOTATION`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(handler), Some(annotation)) if !handler.is_empty() && !annotation.is_empty() => {
+                Ok(Self {
+                    handler: handler.into(),
+                    annotation: annotation.into(),
+                })
+            }
+            _ => bail!(
+                "invalid runtime annotation {}, expected HANDLER=ANNOTATION",
+                s
+            ),
+        }
+    }
+}
+
+/// Filter the `annotations` passed into the OCI spec of a container running with the runtime
+/// `handler`. Only the `allowed` annotations of the runtime handler are kept and all others get
+/// stripped, which means that runtime handlers without allowed annotations receive none.
+pub fn runtime_annotations(
+    annotations: &HashMap<String, String>,
+    handler: &str,
+    allowed: &[RuntimeAnnotation],
+) -> HashMap<String, String> {
+    let allowed = handler_annotations(handler, allowed);
+    annotations
+        .iter()
+        .filter(|(key, _)| {
+            let keep = allowed.iter().any(|x| matches_allowed(key, x));
+            if !keep {
+                debug!(
+                    "Stripping annotation {} because it is not allowed for runtime handler {}",
+                    key, handler
+                );
+            }
+            keep
+        })
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// Retrieve the annotations of the runtime `handler` which are part of the `allowed` ones,
+/// whereas the empty handler refers to the `default` one.
+pub fn handler_annotations<'a>(handler: &str, allowed: &'a [RuntimeAnnotation]) -> Vec<&'a str> {
+    let handler = if handler.is_empty() {
+        "default"
+    } else {
+        handler
+    };
+    allowed
+        .iter()
+        .filter(|x| x.handler() == handler)
+        .map(|x| x.annotation().as_str())
+        .collect()
+}
+
+/// Check if the annotation `key` matches the `allowed` one, which is the case for the annotation
+/// itself as well as for all annotations using it as prefix followed by a dot.
+fn matches_allowed(key: &str, allowed: &str) -> bool {
+    key == allowed || (key.starts_with(allowed) && key[allowed.len()..].starts_with('.'))
+}
+
+/// Parse a size in bytes, which can use binary (`Ki`, `Mi`, `Gi`, `Ti`) or decimal (`k`, `M`,
+/// `G`, `T`) suffixes like Kubernetes quantities.
+pub fn parse_size(value: &str) -> Result<u64> {
+    const SUFFIXES: &[(&str, u64)] = &[
+        ("Ki", 1 << 10),
+        ("Mi", 1 << 20),
+        ("Gi", 1 << 30),
+        ("Ti", 1 << 40),
+        ("k", 1_000),
+        ("M", 1_000_000),
+        ("G", 1_000_000_000),
+        ("T", 1_000_000_000_000),
+    ];
+
+    let value = value.trim();
+    let (number, multiplier) = SUFFIXES
+        .iter()
+        .find(|(suffix, _)| value.ends_with(suffix))
+        .map(|(suffix, multiplier)| (&value[..value.len() - suffix.len()], *multiplier))
+        .unwrap_or((value, 1));
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|x| x.checked_mul(multiplier))
+        .with_context(|| format!("invalid size {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotations(values: &[(&str, &str)]) -> HashMap<String, String> {
+        values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn allow_all() -> Vec<String> {
+        vec![
+            UNIFIED_CGROUP_ANNOTATION.into(),
+            SHM_SIZE_ANNOTATION.into(),
+            USERNS_MODE_ANNOTATION.into(),
+            DEVICES_ANNOTATION.into(),
+        ]
+    }
+
+    #[test]
+    fn from_annotations_success() -> Result<()> {
+        let annotations = annotations(&[
+            (
+                "io.kubernetes.cri-o.UnifiedCgroup.ctr",
+                "memory.high=1024;memory.swap.max=0",
+            ),
+            ("io.kubernetes.cri-o.UnifiedCgroup.other", "memory.high=1"),
+            (SHM_SIZE_ANNOTATION, "64Mi"),
+            (USERNS_MODE_ANNOTATION, "auto:size=65536"),
+            (DEVICES_ANNOTATION, "/dev/fuse"),
+        ]);
+
+        let tuning = Tuning::from_annotations(&annotations, "ctr", &allow_all())?;
+        assert_eq!(tuning.unified().len(), 2);
+        assert_eq!(
+            tuning.unified().get("memory.high").map(String::as_str),
+            Some("1024")
+        );
+        assert_eq!(tuning.shm_size(), &Some(64 * 1024 * 1024));
+        assert_eq!(tuning.userns_mode().as_deref(), Some("auto:size=65536"));
+        assert_eq!(tuning.devices().as_deref(), Some("/dev/fuse"));
+        Ok(())
+    }
+
+    #[test]
+    fn from_annotations_success_not_allowed() -> Result<()> {
+        let annotations = annotations(&[
+            ("io.kubernetes.cri-o.UnifiedCgroup.ctr", "memory.high=1024"),
+            (SHM_SIZE_ANNOTATION, "wrong"),
+        ]);
+
+        let tuning = Tuning::from_annotations(&annotations, "ctr", &[])?;
+        assert_eq!(tuning, Tuning::default());
+        Ok(())
+    }
+
+    #[test]
+    fn from_annotations_fail_invalid() {
+        for (k, v) in &[
+            ("io.kubernetes.cri-o.UnifiedCgroup.ctr", "memory.high"),
+            (SHM_SIZE_ANNOTATION, "64Xi"),
+            (USERNS_MODE_ANNOTATION, "wrong"),
+        ] {
+            assert!(
+                Tuning::from_annotations(&annotations(&[(*k, *v)]), "ctr", &allow_all()).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn is_allowed() {
+        let allowed = vec![UNIFIED_CGROUP_ANNOTATION.to_string()];
+        assert!(Tuning::is_allowed(
+            "io.kubernetes.cri-o.UnifiedCgroup.ctr",
+            &allowed
+        ));
+        assert!(Tuning::is_allowed(UNIFIED_CGROUP_ANNOTATION, &allowed));
+        assert!(!Tuning::is_allowed(
+            "io.kubernetes.cri-o.UnifiedCgroupWrong",
+            &allowed
+        ));
+        assert!(!Tuning::is_allowed(SHM_SIZE_ANNOTATION, &allowed));
+    }
+
+    #[test]
+    fn runtime_annotation_from_str() -> Result<()> {
+        let sut: RuntimeAnnotation = "kata=io.katacontainers".parse()?;
+        assert_eq!(sut.handler(), "kata");
+        assert_eq!(sut.annotation(), "io.katacontainers");
+        for input in &["kata", "=io.katacontainers", "kata="] {
+            assert!(input.parse::<RuntimeAnnotation>().is_err(), "{}", input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_annotations_success() -> Result<()> {
+        let annotations = annotations(&[
+            ("io.katacontainers.config.hypervisor.kernel_params", "quiet"),
+            ("dev.gvisor.spec.mount.shared.share", "pod"),
+            ("other", "value"),
+        ]);
+        let allowed = vec!["kata=io.katacontainers".parse()?, "default=other".parse()?];
+
+        let kata = runtime_annotations(&annotations, "kata", &allowed);
+        assert_eq!(kata.len(), 1);
+        assert!(kata.contains_key("io.katacontainers.config.hypervisor.kernel_params"));
+
+        let default = runtime_annotations(&annotations, "", &allowed);
+        assert_eq!(default.keys().collect::<Vec<_>>(), vec!["other"]);
+
+        // Runtime handlers without allowed annotations receive none of them
+        assert!(runtime_annotations(&annotations, "runsc", &allowed).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_size_success() -> Result<()> {
+        assert_eq!(parse_size("1024")?, 1024);
+        assert_eq!(parse_size("1Ki")?, 1024);
+        assert_eq!(parse_size("2Gi")?, 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size("5M")?, 5_000_000);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_size_failure() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("Mi").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("1.5Gi").is_err());
+        assert!(parse_size("100000000Ti").is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use clap::{crate_version, AppSettings, Clap};
+use cri::{Config, SandboxNetns};
+use std::{
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{exit, Command},
+};
+
+#[derive(Clap)]
+#[clap(
+    about("Run a host command inside the network namespace of a pod sandbox"),
+    after_help("Example: crinetns 3f2a -- tcpdump -i eth0"),
+    global_setting(AppSettings::ColoredHelp),
+    setting(AppSettings::TrailingVarArg),
+    version(crate_version!()),
+)]
+/// Args are the command line arguments.
+struct Args {
+    #[clap(env("CRI_NETNS_PATH"), long("netns-path"), value_name("PATH"))]
+    /// The directory of the pinned network namespaces, which defaults to the one of the server.
+    netns_path: Option<PathBuf>,
+
+    #[clap(value_name("POD_SANDBOX_ID"))]
+    /// The ID of the pod sandbox or a unique prefix of it.
+    pod_sandbox_id: String,
+
+    #[clap(required(true), value_name("COMMAND"))]
+    /// The host command to run and its arguments. The command only enters the network namespace,
+    /// which makes the binaries and the filesystem of the host available.
+    command: Vec<String>,
+}
+
+fn main() {
+    match run(Args::parse()) {
+        Ok(code) => exit(code),
+        Err(e) => {
+            println!(
+                "Unable to run command: {}",
+                &e.chain()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(": "),
+            );
+            exit(1);
+        }
+    }
+}
+
+/// Run the command of the `args` and return its exit code.
+fn run(args: Args) -> Result<i32> {
+    let dir = args.netns_path.unwrap_or_else(Config::default_netns_path);
+    let netns = SandboxNetns::find(&dir, &args.pod_sandbox_id)?;
+
+    let (program, rest) = args.command.split_first().context("no command provided")?;
+    let mut command = Command::new(program);
+    command.args(rest);
+    let status = netns.exec(command)?;
+
+    // Report commands killed by a signal like shells do
+    Ok(status
+        .code()
+        .or_else(|| status.signal().map(|x| 128 + x))
+        .unwrap_or(1))
+}
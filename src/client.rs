@@ -0,0 +1,289 @@
+//! A typed client for the Container Runtime Interface endpoint of this runtime.
+//!
+//! The client wraps the generated gRPC clients and provides convenience methods for the most
+//! common requests. All other requests can be done via the raw clients, which are accessible via
+//! [`Client::runtime_mut`](struct.Client.html#method.runtime_mut) and
+//! [`Client::image_mut`](struct.Client.html#method.image_mut).
+
+use crate::criapi::{
+    image_service_client::ImageServiceClient, runtime_service_client::RuntimeServiceClient, Image,
+    ListImagesRequest, PodSandboxConfig, PodSandboxMetadata, RemovePodSandboxRequest,
+    RunPodSandboxRequest, StatusRequest, StatusResponse, StopPodSandboxRequest, VersionRequest,
+    VersionResponse,
+};
+use getset::MutGetters;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    error, fmt,
+    path::{Path, PathBuf},
+};
+use tokio::net::UnixStream;
+use tonic::{
+    transport::{self, Channel, Endpoint, Uri},
+    Request, Status,
+};
+use tower::service_fn;
+
+/// The version of the CRI API used by the client.
+const API_VERSION: &str = "v1alpha2";
+
+#[derive(Debug)]
+/// Error is the error type returned by the client.
+pub enum Error {
+    /// Establishing the connection to the runtime failed.
+    Connect(transport::Error),
+
+    /// The runtime rejected the request.
+    Status(Status),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect(e) => write!(f, "connect to runtime: {}", e),
+            Error::Status(s) => write!(f, "request failed with {:?}: {}", s.code(), s.message()),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Connect(e) => Some(e),
+            Error::Status(s) => Some(s),
+        }
+    }
+}
+
+impl From<transport::Error> for Error {
+    fn from(e: transport::Error) -> Self {
+        Error::Connect(e)
+    }
+}
+
+impl From<Status> for Error {
+    fn from(s: Status) -> Self {
+        Error::Status(s)
+    }
+}
+
+/// The result type returned by the client.
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, MutGetters)]
+/// Client is a connection to the runtime and image service of a runtime.
+pub struct Client {
+    #[get_mut = "pub"]
+    /// The raw runtime service client.
+    runtime: RuntimeServiceClient<Channel>,
+
+    #[get_mut = "pub"]
+    /// The raw image service client.
+    image: ImageServiceClient<Channel>,
+}
+
+impl Client {
+    /// Connect to the runtime listening on the unix domain socket at `sock_path`.
+    pub async fn connect<P: AsRef<Path>>(sock_path: P) -> Result<Self> {
+        let sock_path: PathBuf = sock_path.as_ref().into();
+
+        // The URI is ignored by the connector but has to be valid
+        let channel = Endpoint::try_from("http://[::]:50051")?
+            .connect_with_connector(service_fn(move |_: Uri| {
+                UnixStream::connect(sock_path.clone())
+            }))
+            .await?;
+
+        Ok(Self {
+            runtime: RuntimeServiceClient::new(channel.clone()),
+            image: ImageServiceClient::new(channel),
+        })
+    }
+
+    /// Retrieve the version of the runtime.
+    pub async fn version(&mut self) -> Result<VersionResponse> {
+        let request = VersionRequest {
+            version: API_VERSION.into(),
+        };
+        Ok(self
+            .runtime
+            .version(Request::new(request))
+            .await?
+            .into_inner())
+    }
+
+    /// Retrieve the status of the runtime, including additional information if `verbose` is set.
+    pub async fn status(&mut self, verbose: bool) -> Result<StatusResponse> {
+        let request = StatusRequest { verbose };
+        Ok(self
+            .runtime
+            .status(Request::new(request))
+            .await?
+            .into_inner())
+    }
+
+    /// Run a new pod sandbox for the provided `config` and return its ID.
+    pub async fn run_pod_sandbox(
+        &mut self,
+        config: PodSandboxConfig,
+        runtime_handler: &str,
+    ) -> Result<String> {
+        let request = RunPodSandboxRequest {
+            config: Some(config),
+            runtime_handler: runtime_handler.into(),
+        };
+        Ok(self
+            .runtime
+            .run_pod_sandbox(Request::new(request))
+            .await?
+            .into_inner()
+            .pod_sandbox_id)
+    }
+
+    /// Stop the pod sandbox with the provided `id`.
+    pub async fn stop_pod_sandbox(&mut self, id: &str) -> Result<()> {
+        let request = StopPodSandboxRequest {
+            pod_sandbox_id: id.into(),
+        };
+        self.runtime.stop_pod_sandbox(Request::new(request)).await?;
+        Ok(())
+    }
+
+    /// Remove the pod sandbox with the provided `id`.
+    pub async fn remove_pod_sandbox(&mut self, id: &str) -> Result<()> {
+        let request = RemovePodSandboxRequest {
+            pod_sandbox_id: id.into(),
+        };
+        self.runtime
+            .remove_pod_sandbox(Request::new(request))
+            .await?;
+        Ok(())
+    }
+
+    /// List all images known by the runtime.
+    pub async fn list_images(&mut self) -> Result<Vec<Image>> {
+        let request = ListImagesRequest { filter: None };
+        Ok(self
+            .image
+            .list_images(Request::new(request))
+            .await?
+            .into_inner()
+            .images)
+    }
+}
+
+/// PodSandboxConfigBuilder eases building pod sandbox configurations for the most common fields.
+pub struct PodSandboxConfigBuilder {
+    /// The configuration to be built.
+    config: PodSandboxConfig,
+}
+
+impl PodSandboxConfigBuilder {
+    /// Create a new builder for the pod with the provided `name`, `uid` and `namespace`.
+    pub fn new(name: &str, uid: &str, namespace: &str) -> Self {
+        Self {
+            config: PodSandboxConfig {
+                metadata: Some(PodSandboxMetadata {
+                    name: name.into(),
+                    uid: uid.into(),
+                    namespace: namespace.into(),
+                    attempt: 0,
+                }),
+                hostname: String::new(),
+                log_directory: String::new(),
+                dns_config: None,
+                port_mappings: vec![],
+                labels: HashMap::new(),
+                annotations: HashMap::new(),
+                linux: None,
+            },
+        }
+    }
+
+    /// Set the attempt of the pod sandbox.
+    pub fn attempt(mut self, attempt: u32) -> Self {
+        if let Some(metadata) = self.config.metadata.as_mut() {
+            metadata.attempt = attempt;
+        }
+        self
+    }
+
+    /// Set the hostname of the pod sandbox.
+    pub fn hostname(mut self, hostname: &str) -> Self {
+        self.config.hostname = hostname.into();
+        self
+    }
+
+    /// Set the log directory of the pod sandbox.
+    pub fn log_directory(mut self, log_directory: &str) -> Self {
+        self.config.log_directory = log_directory.into();
+        self
+    }
+
+    /// Add a label to the pod sandbox.
+    pub fn label(mut self, key: &str, value: &str) -> Self {
+        self.config.labels.insert(key.into(), value.into());
+        self
+    }
+
+    /// Add an annotation to the pod sandbox.
+    pub fn annotation(mut self, key: &str, value: &str) -> Self {
+        self.config.annotations.insert(key.into(), value.into());
+        self
+    }
+
+    /// Build the pod sandbox configuration.
+    pub fn build(self) -> PodSandboxConfig {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tonic::Code;
+
+    #[test]
+    fn pod_sandbox_config_builder() {
+        let config = PodSandboxConfigBuilder::new("name", "uid", "namespace")
+            .attempt(2)
+            .hostname("host")
+            .log_directory("/var/log/pods/uid")
+            .label("app", "test")
+            .annotation("key", "value")
+            .build();
+
+        let metadata = config.metadata.as_ref();
+        assert_eq!(metadata.map(|x| x.name.as_str()), Some("name"));
+        assert_eq!(metadata.map(|x| x.uid.as_str()), Some("uid"));
+        assert_eq!(metadata.map(|x| x.namespace.as_str()), Some("namespace"));
+        assert_eq!(metadata.map(|x| x.attempt), Some(2));
+        assert_eq!(config.hostname, "host");
+        assert_eq!(config.log_directory, "/var/log/pods/uid");
+        assert_eq!(config.labels.get("app").map(String::as_str), Some("test"));
+        assert_eq!(
+            config.annotations.get("key").map(String::as_str),
+            Some("value")
+        );
+    }
+
+    #[test]
+    fn error_display() {
+        let err = Error::from(Status::not_found("no such sandbox"));
+        assert_eq!(
+            err.to_string(),
+            "request failed with NotFound: no such sandbox"
+        );
+        assert!(matches!(err, Error::Status(s) if s.code() == Code::NotFound));
+    }
+
+    #[tokio::test]
+    async fn connect_fail_no_socket() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let res = Client::connect(dir.path().join("missing.sock")).await;
+        assert!(matches!(res, Err(Error::Connect(_))));
+        Ok(())
+    }
+}
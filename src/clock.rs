@@ -0,0 +1,116 @@
+//! Sources of the current time.
+//!
+//! Everything depending on the passing of time, like timestamps, the expiry of streaming
+//! sessions or the reuse of samples within an interval, reads the time from a `Clock`. The server
+//! uses the system clock, whereas tests use a fake clock which only advances on demand, so that
+//! time dependent behavior can be tested without sleeping.
+
+use anyhow::{Context, Result};
+use std::{
+    fmt,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Clock is a source of the current time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Retrieve the current wall clock time.
+    fn now(&self) -> SystemTime;
+
+    /// Retrieve the current monotonic time, which is meant for measuring durations.
+    fn instant(&self) -> Instant;
+
+    /// Retrieve the current wall clock time in nanoseconds since the Unix epoch.
+    fn unix_nanos(&self) -> Result<i64> {
+        Ok(self
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .context("get current time")?
+            .as_nanos() as i64)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+/// SystemClock reads the time of the operating system.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[derive(Clone, Debug)]
+    /// A clock which stands still until it gets advanced.
+    pub struct FakeClock {
+        /// The current wall clock and monotonic time.
+        now: Arc<Mutex<(SystemTime, Instant)>>,
+    }
+
+    impl Default for FakeClock {
+        fn default() -> Self {
+            Self {
+                now: Arc::new(Mutex::new((SystemTime::now(), Instant::now()))),
+            }
+        }
+    }
+
+    impl FakeClock {
+        /// Advance the clock by `duration`.
+        pub fn advance(&self, duration: Duration) {
+            if let Ok(mut now) = self.now.lock() {
+                now.0 += duration;
+                now.1 += duration;
+            }
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> SystemTime {
+            self.now
+                .lock()
+                .map(|x| x.0)
+                .unwrap_or_else(|_| SystemTime::now())
+        }
+
+        fn instant(&self) -> Instant {
+            self.now
+                .lock()
+                .map(|x| x.1)
+                .unwrap_or_else(|_| Instant::now())
+        }
+    }
+
+    #[test]
+    fn fake_clock_advance() -> Result<()> {
+        let sut = FakeClock::default();
+        let (now, instant, nanos) = (sut.now(), sut.instant(), sut.unix_nanos()?);
+        assert_eq!(sut.now(), now);
+        assert_eq!(sut.instant(), instant);
+
+        sut.advance(Duration::from_secs(5));
+        assert_eq!(sut.now().duration_since(now)?, Duration::from_secs(5));
+        assert_eq!(sut.instant() - instant, Duration::from_secs(5));
+        assert_eq!(sut.unix_nanos()? - nanos, 5_000_000_000);
+        Ok(())
+    }
+
+    #[test]
+    fn system_clock_now() -> Result<()> {
+        let sut = SystemClock;
+        assert!(sut.unix_nanos()? > 0);
+        assert!(sut.instant() <= Instant::now());
+        Ok(())
+    }
+}
@@ -1,76 +1,1335 @@
 //! Configuration related structures
-use clap::{crate_name, crate_version, AppSettings, Clap};
+use crate::{
+    annotations::RuntimeAnnotation,
+    feature::Feature,
+    health::HealthCheckSpec,
+    image::{layout::LocalImageSource, registries::RegistryMirror},
+    latency::LatencyBudget,
+    listener::ListenAddress,
+    quota::QuotaLimit,
+    sandbox::{dns::DnsOption, hosts::HostEntry},
+    timeout::MethodTimeout,
+};
+use anyhow::{bail, Context, Result};
+use clap::{
+    crate_name, crate_version, AppSettings, ArgMatches, ArgSettings, Clap, FromArgMatches, IntoApp,
+};
 use derive_builder::Builder;
 use getset::{CopyGetters, Getters};
 use lazy_static::lazy_static;
 use log::LevelFilter;
 use nix::unistd::{self, Uid};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{
+    env,
+    ffi::OsString,
+    fs, iter,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
 use strum::EnumString;
+use toml::{value::Table, Value};
+
+/// The name of the argument referencing the configuration file.
+const CONFIG_ARG: &str = "config";
+
+/// The key of the runtime handler tables in the configuration file.
+const RUNTIMES_KEY: &str = "runtimes";
+
+/// The name of the argument populated by the runtime handler tables of the configuration file.
+const RUNTIME_ANNOTATIONS_ARG: &str = "runtime_allowed_annotations";
 
 lazy_static! {
     static ref DEFAULT_SOCK_PATH: String = Config::default_sock_path().display().to_string();
     static ref DEFAULT_STORAGE_PATH: String = Config::default_storage_path().display().to_string();
+    static ref DEFAULT_NETNS_PATH: String = Config::default_netns_path().display().to_string();
+    static ref DEFAULT_LOG_PATH: String = Config::default_log_path().display().to_string();
+    static ref DEFAULT_SANDBOX_PATH: String = Config::default_sandbox_path().display().to_string();
+    static ref DEFAULT_CONTAINER_PATH: String =
+        Config::default_container_path().display().to_string();
+    static ref DEFAULT_IMAGE_PATH: String = Config::default_image_path().display().to_string();
 }
 
-#[derive(Builder, Clap, CopyGetters, Getters, Deserialize, Serialize)]
-#[builder(default, pattern = "owned", setter(into))]
-#[serde(rename_all = "kebab-case")]
-#[clap(
-    about("CRI - The Kubernetes Container Runtime written in Rust"),
-    after_help("More info at: https://github.com/cri-o/cri"),
-    global_setting(AppSettings::ColoredHelp),
-    version(crate_version!()),
-)]
-/// Config is the main configuration structure for the server.
-pub struct Config {
+#[derive(Builder, Clap, Clone, CopyGetters, Getters, Deserialize, Serialize)]
+#[builder(default, pattern = "owned", setter(into))]
+#[serde(rename_all = "kebab-case")]
+#[clap(
+    about("CRI - The Kubernetes Container Runtime written in Rust"),
+    after_help("More info at: https://github.com/cri-o/cri"),
+    global_setting(AppSettings::ColoredHelp),
+    version(crate_version!()),
+)]
+/// Config is the main configuration structure for the server.
+///
+/// Every option is looked up in the command line flags first, followed by the environment
+/// variables, the configuration file and finally the built-in default values.
+pub struct Config {
+    #[get = "pub"]
+    #[clap(env("CRI_CONFIG"), long("config"), value_name("PATH"))]
+    /// The path to a TOML configuration file, whose keys are the long names of the command line
+    /// flags, like `log-level = "debug"`. Command line flags and environment variables take
+    /// precedence over the values of the file.
+    config: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("info"),
+        env("CRI_LOG_LEVEL"),
+        long("log-level"),
+        possible_values(&["trace", "debug", "info", "warn", "error", "off"]),
+        short('l'),
+        value_name("LEVEL")
+    )]
+    /// The logging level of the application.
+    log_level: LevelFilter,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("lib"),
+        env("CRI_LOG_SCOPE"),
+        long("log-scope"),
+        possible_values(&["lib", "global"]),
+        value_name("SCOPE")
+    )]
+    /// The logging scope of the application. If set to `global`, then all dependent crates will
+    /// log on the provided level, too. Otherwise the logs are scoped to this application only.
+    log_scope: LogScope,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("text"),
+        env("CRI_LOG_FORMAT"),
+        long("log-format"),
+        possible_values(&["text", "json"]),
+        value_name("FORMAT")
+    )]
+    /// The format of the log records written to stderr or the log file. The `json` format
+    /// writes one object per line including the structured fields of the record, like the
+    /// request ID, the pod UID and the container ID.
+    log_format: LogFormat,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("stderr"),
+        env("CRI_LOG_DRIVER"),
+        long("log-driver"),
+        possible_values(&["stderr", "journald", "file"]),
+        value_name("DRIVER")
+    )]
+    /// The destination of the log records. The `journald` driver passes the structured fields
+    /// as journal fields, whereas the `file` driver appends to the log file.
+    log_driver: LogDriver,
+
+    #[get = "pub"]
+    #[clap(env("CRI_LOG_FILE"), long("log-file"), value_name("PATH"))]
+    /// The path of the log file used by the `file` log driver.
+    log_file: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        default_value(&DEFAULT_SOCK_PATH),
+        env("CRI_SOCK_PATH"),
+        long("sock-path"),
+        value_name("PATH")
+    )]
+    /// The path to the unix socket for the server.
+    sock_path: PathBuf,
+
+    #[get = "pub"]
+    #[clap(env("CRI_LISTEN"), long("listen"), value_name("ADDRESS"))]
+    /// The address the server listens on instead of the unix socket at the socket path, like
+    /// `tcp://127.0.0.1:10010` or `vsock://3:1024`. Connections via TCP or vsock are neither
+    /// authenticated nor encrypted, unless TLS is configured.
+    listen: Option<ListenAddress>,
+
+    #[get = "pub"]
+    #[clap(env("CRI_TLS_CERT"), long("tls-cert"), value_name("PATH"))]
+    /// The PEM encoded certificate of the gRPC server. The server uses TLS if the certificate and
+    /// its key are set.
+    tls_cert: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(env("CRI_TLS_KEY"), long("tls-key"), value_name("PATH"))]
+    /// The PEM encoded private key of the gRPC server certificate.
+    tls_key: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(env("CRI_TLS_CLIENT_CA"), long("tls-client-ca"), value_name("PATH"))]
+    /// The PEM encoded certificate authority for verifying clients. If set, then only clients
+    /// presenting a certificate signed by this authority are able to connect.
+    tls_client_ca: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        default_value(&DEFAULT_STORAGE_PATH),
+        env("CRI_STORAGE_PATH"),
+        long("storage-path"),
+        value_name("PATH")
+    )]
+    /// The path to the persistent storage for the server.
+    storage_path: PathBuf,
+
+    #[get = "pub"]
+    #[clap(
+        default_value(&DEFAULT_NETNS_PATH),
+        env("CRI_NETNS_PATH"),
+        long("netns-path"),
+        value_name("PATH")
+    )]
+    /// The path to the directory for pinning the network namespaces of pod sandboxes.
+    netns_path: PathBuf,
+
+    #[get = "pub"]
+    #[clap(
+        default_value("/etc/cni/net.d"),
+        env("CRI_CNI_CONFIG_DIR"),
+        long("cni-config-dir"),
+        value_name("PATH")
+    )]
+    /// The directory containing the CNI network configurations. The first configuration in
+    /// lexical order is used for all pod sandboxes which do not use the host network.
+    cni_config_dir: PathBuf,
+
+    #[get = "pub"]
+    #[clap(
+        default_value("/opt/cni/bin"),
+        env("CRI_CNI_PLUGIN_DIRS"),
+        long("cni-plugin-dirs"),
+        use_delimiter(true),
+        value_name("PATH")
+    )]
+    /// The directories searched in order for the CNI plugin binaries.
+    cni_plugin_dirs: Vec<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_CNI_CONFIG_TEMPLATE"),
+        long("cni-config-template"),
+        value_name("PATH")
+    )]
+    /// The template of the CNI network configuration, which gets rendered into the CNI config
+    /// directory whenever the kubelet updates the pod CIDR of the node. The placeholders
+    /// `{{.PodCIDR}}` and `{{.PodCIDRRanges}}` get replaced by the first CIDR and by the
+    /// host-local IPAM ranges of all CIDRs.
+    cni_config_template: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        default_value("/var/lib/cni/networks"),
+        env("CRI_CNI_IPAM_STATE_PATH"),
+        long("cni-ipam-state-path"),
+        value_name("PATH")
+    )]
+    /// The directory of the allocations of the host-local IPAM plugin, which contains a directory
+    /// per network and a file named by every allocated address.
+    cni_ipam_state_path: PathBuf,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("600"),
+        env("CRI_NETWORK_LEAK_SCAN_INTERVAL"),
+        long("network-leak-scan-interval"),
+        value_name("SECONDS")
+    )]
+    /// The interval in seconds of scanning for network namespaces and IP allocations without a
+    /// pod sandbox, which get released once found by two consecutive scans. A value of `0`
+    /// disables the scans.
+    network_leak_scan_interval: u64,
+
+    #[get = "pub"]
+    #[clap(
+        default_value(&DEFAULT_LOG_PATH),
+        env("CRI_LOG_PATH"),
+        long("log-path"),
+        value_name("PATH")
+    )]
+    /// The path to the directory for log files written by the server itself.
+    log_path: PathBuf,
+
+    #[get = "pub"]
+    #[clap(
+        default_value(&DEFAULT_SANDBOX_PATH),
+        env("CRI_SANDBOX_PATH"),
+        long("sandbox-path"),
+        value_name("PATH")
+    )]
+    /// The path to the directory containing the state of the pod sandboxes.
+    sandbox_path: PathBuf,
+
+    #[get = "pub"]
+    #[clap(
+        default_value("sleep infinity"),
+        env("CRI_INFRA_COMMAND"),
+        long("infra-command"),
+        value_name("COMMAND")
+    )]
+    /// The whitespace separated command line of the infra process, which keeps a pod sandbox
+    /// alive until it gets stopped.
+    infra_command: String,
+
+    #[get = "pub"]
+    #[clap(
+        default_value(&DEFAULT_CONTAINER_PATH),
+        env("CRI_CONTAINER_PATH"),
+        long("container-path"),
+        value_name("PATH")
+    )]
+    /// The path to the directory containing the OCI bundles of the containers.
+    container_path: PathBuf,
+
+    #[get = "pub"]
+    #[clap(
+        default_value("runc"),
+        env("CRI_OCI_RUNTIME"),
+        long("oci-runtime"),
+        value_name("PATH")
+    )]
+    /// The OCI runtime binary used for running containers, like `runc` or `crun`.
+    oci_runtime: PathBuf,
+
+    #[get = "pub"]
+    #[clap(
+        default_value(&DEFAULT_IMAGE_PATH),
+        env("CRI_IMAGE_PATH"),
+        long("image-path"),
+        value_name("PATH")
+    )]
+    /// The path to the directory containing the blobs and unpacked layers of pulled images.
+    image_path: PathBuf,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_ALLOWED_DEVICES"),
+        long("allowed-devices"),
+        use_delimiter(true),
+        value_name("PATH")
+    )]
+    /// Host devices which can be requested by unprivileged pods via annotation. Every entry can be
+    /// either a path or a glob pattern, like `/dev/fuse` or `/dev/nvidia*`.
+    allowed_devices: Vec<String>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("30"),
+        env("CRI_STOP_TIMEOUT"),
+        long("stop-timeout"),
+        value_name("SECONDS")
+    )]
+    /// The default grace period in seconds for stopping containers, which applies if the kubelet
+    /// does not request a timeout.
+    stop_timeout: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("CRI_CPU_BURST"),
+        long("cpu-burst"),
+        value_name("MICROSECONDS")
+    )]
+    /// The default CPU burst of containers with a CPU quota, capped at the quota. A value of `0`
+    /// disables the burst, whereas containers can override it via the
+    /// `io.kubernetes.cri.cpu-burst` annotation.
+    cpu_burst: u64,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_FEATURES"),
+        long("features"),
+        possible_values(&[
+            "userns",
+            "checkpoint",
+            "lazy-pull",
+            "nri",
+            "request-trace",
+            "hardened"
+        ]),
+        use_delimiter(true),
+        value_name("FEATURE")
+    )]
+    /// Optional runtime features which should be enabled.
+    features: Vec<Feature>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_ALLOWED_ANNOTATIONS"),
+        long("allowed-annotations"),
+        use_delimiter(true),
+        value_name("ANNOTATION")
+    )]
+    /// Low-level tuning annotations which are allowed to be used by the pods of all runtime
+    /// handlers, like `io.kubernetes.cri-o.ShmSize`. The runtime allowed annotations additionally
+    /// allow them per runtime handler, whereas all other tuning annotations will be ignored.
+    allowed_annotations: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_RUNTIME_ALLOWED_ANNOTATIONS"),
+        long("runtime-allowed-annotations"),
+        use_delimiter(true),
+        value_name("HANDLER=ANNOTATION")
+    )]
+    /// Annotations which are passed into the OCI spec of containers of a runtime handler, like
+    /// `kata=io.katacontainers`. The configuration file sets them in the `allowed-annotations` of
+    /// the `[runtimes.HANDLER]` tables. All other annotations are stripped, which means that
+    /// runtime handlers without allowed annotations receive none of them. The `default` handler
+    /// refers to the default runtime handler.
+    runtime_allowed_annotations: Vec<RuntimeAnnotation>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("CRI_LOG_RATE_LIMIT"),
+        long("log-rate-limit"),
+        value_name("BYTES")
+    )]
+    /// The maximum log throughput of a single container in bytes per second. Lines exceeding the
+    /// limit will be dropped, whereas `0` disables the limit.
+    log_rate_limit: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("CRI_LOG_BURST"),
+        long("log-burst"),
+        value_name("BYTES")
+    )]
+    /// The number of bytes a container can log at once before the rate limit applies. Defaults
+    /// to the rate limit if set to `0`.
+    log_burst: u64,
+
+    #[get = "pub"]
+    #[clap(env("CRI_DAEMON_CGROUP"), long("daemon-cgroup"), value_name("PATH"))]
+    /// The cgroup the server moves itself into at startup, relative to the cgroup root. The server
+    /// remains in its current cgroup if not set.
+    daemon_cgroup: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("CRI_DAEMON_CPU_LIMIT"),
+        long("daemon-cpu-limit"),
+        value_name("MILLICPUS")
+    )]
+    /// The CPU limit of the server cgroup in thousandths of a CPU, whereas `0` means unlimited.
+    daemon_cpu_limit: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("CRI_DAEMON_MEMORY_LIMIT"),
+        long("daemon-memory-limit"),
+        value_name("BYTES")
+    )]
+    /// The memory limit of the server cgroup in bytes, whereas `0` means unlimited.
+    daemon_memory_limit: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        allow_hyphen_values(true),
+        default_value("-999"),
+        env("CRI_OOM_SCORE_ADJ"),
+        long("oom-score-adj"),
+        value_name("SCORE")
+    )]
+    /// The OOM score adjustment of the server, which should be low to not be killed before the
+    /// workloads.
+    oom_score_adj: i32,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("300"),
+        env("CRI_REQUEST_TIMEOUT"),
+        long("request-timeout"),
+        value_name("SECONDS")
+    )]
+    /// The server side deadline of all requests in seconds, whereas `0` means unbounded. Shorter
+    /// deadlines requested by the client take precedence.
+    request_timeout: u64,
+
+    #[get = "pub"]
+    #[clap(
+        default_value("PullImage=0"),
+        env("CRI_METHOD_TIMEOUTS"),
+        long("method-timeouts"),
+        use_delimiter(true),
+        value_name("METHOD=SECONDS")
+    )]
+    /// Per method overrides of the request timeout, like `PullImage=600`.
+    method_timeouts: Vec<MethodTimeout>,
+
+    #[get = "pub"]
+    #[clap(
+        default_value("RunPodSandbox=5000"),
+        env("CRI_LATENCY_BUDGETS"),
+        long("latency-budgets"),
+        use_delimiter(true),
+        value_name("METHOD=MILLISECONDS")
+    )]
+    /// The time operations of a method are expected to take in milliseconds, like
+    /// `RunPodSandbox=5000`. Slower operations get logged and published as event together with
+    /// the durations of their steps.
+    latency_budgets: Vec<LatencyBudget>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("30"),
+        env("CRI_SHUTDOWN_TIMEOUT"),
+        long("shutdown-timeout"),
+        value_name("SECONDS")
+    )]
+    /// The time in seconds to wait for in-flight requests and image pulls on shutdown, before
+    /// they get aborted.
+    shutdown_timeout: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("300"),
+        env("CRI_STORAGE_SNAPSHOT_INTERVAL"),
+        long("storage-snapshot-interval"),
+        value_name("SECONDS")
+    )]
+    /// The interval in seconds for writing compacted snapshots of the storage, which are used for
+    /// recovering a corrupted storage. A value of `0` disables periodic snapshots.
+    storage_snapshot_interval: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("restore"),
+        env("CRI_STORAGE_RECOVERY"),
+        long("storage-recovery"),
+        possible_values(&["fail", "restore"]),
+        value_name("MODE")
+    )]
+    /// The behavior if the storage cannot be opened. If set to `restore`, then the corrupted
+    /// storage is moved aside and restored from the latest snapshot. Otherwise the server fails to
+    /// start.
+    storage_recovery: StorageRecovery,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("sled"),
+        env("CRI_STORAGE_BACKEND"),
+        long("storage-backend"),
+        possible_values(&["sled", "memory"]),
+        value_name("BACKEND")
+    )]
+    /// The backend of the storage. The `memory` backend does not persist anything, which means
+    /// that all pods and containers are forgotten on restart. It is intended for tests and CI
+    /// environments.
+    storage_backend: StorageBackend,
+
+    #[get = "pub"]
+    #[clap(env("CRI_POLICY_PATH"), long("policy-path"), value_name("PATH"))]
+    /// The JSON file containing the policies per Kubernetes namespace, like allowed registries,
+    /// privileged permissions and maximum resources. No policies apply if not set.
+    policy_path: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("privileged"),
+        env("CRI_POD_SECURITY"),
+        long("pod-security"),
+        possible_values(&["privileged", "baseline", "restricted"]),
+        value_name("LEVEL")
+    )]
+    /// The Pod Security Standard every pod sandbox and container has to comply with, which
+    /// backs up the admission of the API server. Nothing gets enforced on the `privileged` level.
+    pod_security: PodSecurityLevel,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_NAMESPACE_QUOTAS"),
+        long("namespace-quotas"),
+        use_delimiter(true),
+        value_name("NAMESPACE=SANDBOXES:CONTAINERS")
+    )]
+    /// The maximum number of pod sandboxes and containers per Kubernetes namespace, like
+    /// `tenant=10:50`, whereas `0` means unlimited. The quota of `*` applies to every namespace
+    /// without a dedicated one.
+    namespace_quotas: Vec<QuotaLimit>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_HANDLER_QUOTAS"),
+        long("handler-quotas"),
+        use_delimiter(true),
+        value_name("HANDLER=SANDBOXES:CONTAINERS")
+    )]
+    /// The maximum number of pod sandboxes and containers per runtime handler, like `kata=5:20`,
+    /// whereas `0` means unlimited. The default runtime handler is called `default`.
+    handler_quotas: Vec<QuotaLimit>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_ADMIN_SOCK_PATH"),
+        long("admin-sock-path"),
+        value_name("PATH")
+    )]
+    /// The path to the read-only admin socket, which is accessible by its owner and group and
+    /// therefore allows monitoring agents of that group to observe the runtime and stream its
+    /// events without being able to mutate workloads. The admin socket is disabled if not set.
+    admin_sock_path: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_LAYER_CACHE_PATH"),
+        long("layer-cache-path"),
+        value_name("PATH")
+    )]
+    /// The path to a layer cache which is consulted before fetching layers from registries. The
+    /// cache may be shared between multiple nodes, for example via NFS.
+    layer_cache_path: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_CONTAINERD_ROOT"),
+        long("containerd-root"),
+        value_name("PATH")
+    )]
+    /// The root directory of a previous containerd installation, like `/var/lib/containerd`.
+    /// Blobs of its content store are imported instead of being fetched from registries.
+    containerd_root: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_REGISTRY_AUTH_FILE"),
+        long("registry-auth-file"),
+        value_name("PATH")
+    )]
+    /// The credentials of the node per registry in the format of `containers-auth.json` or the
+    /// docker `config.json`. They are used for pulls whose request provides no credentials.
+    registry_auth_file: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_REGISTRY_MIRRORS"),
+        long("registry-mirrors"),
+        use_delimiter(true),
+        value_name("REGISTRY=HOST")
+    )]
+    /// Mirrors tried in order before pulling from a registry itself, like
+    /// `docker.io=mirror.local:5000`. Credentials of the auth file are looked up by the host of
+    /// the mirror.
+    registry_mirrors: Vec<RegistryMirror>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_INSECURE_REGISTRIES"),
+        long("insecure-registries"),
+        use_delimiter(true),
+        value_name("HOST")
+    )]
+    /// Registries and mirrors which may be accessed via plain HTTP or with self-signed
+    /// certificates, like `mirror.local:5000`.
+    insecure_registries: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_BLOCKED_REGISTRIES"),
+        long("blocked-registries"),
+        use_delimiter(true),
+        value_name("REGISTRY")
+    )]
+    /// Registries or repositories no images may be pulled from, like `docker.io` or
+    /// `quay.io/tenant`.
+    blocked_registries: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(env("CRI_PEER_ENDPOINT"), long("peer-endpoint"), value_name("URL"))]
+    /// The node-local peer to peer distribution daemon, like Dragonfly or Spegel, which serves
+    /// the blobs of images before they get fetched from the registry, like
+    /// `http://127.0.0.1:5001`.
+    peer_endpoint: Option<String>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("5"),
+        env("CRI_PEER_TIMEOUT"),
+        long("peer-timeout"),
+        value_name("SECONDS")
+    )]
+    /// The time in seconds the peer to peer distribution daemon has to start serving a blob,
+    /// before it gets fetched from the registry instead.
+    peer_timeout: u64,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_SIGNATURE_POLICY_PATH"),
+        long("signature-policy-path"),
+        value_name("PATH")
+    )]
+    /// The JSON file mapping registries and repositories to the cosign public keys or keyless
+    /// identities their images have to be signed by.
+    signature_policy_path: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("off"),
+        env("CRI_SIGNATURE_VERIFICATION"),
+        long("signature-verification"),
+        possible_values(&["off", "warn", "enforce"]),
+        value_name("MODE")
+    )]
+    /// Whether pulled images get verified against the signature policy. Images failing the
+    /// verification are only logged in the `warn` mode and rejected in the `enforce` mode.
+    signature_verification: SignatureVerification,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("kubelet"),
+        env("CRI_IMAGE_PULL_POLICY"),
+        long("image-pull-policy"),
+        possible_values(&["kubelet", "always", "never"]),
+        value_name("POLICY")
+    )]
+    /// The image pull policy enforced regardless of the one requested by the kubelet. The
+    /// `always` policy reports images looked up by tag as missing, so that the kubelet pulls them
+    /// and their tags get resolved against the registry for every container. The `never` policy
+    /// serves local images only and never contacts a registry, as needed on air-gapped nodes.
+    image_pull_policy: ImagePullPolicy,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("3"),
+        env("CRI_MAX_PARALLEL_LAYERS"),
+        long("max-parallel-layers"),
+        value_name("LAYERS")
+    )]
+    /// The maximum number of layers fetched and unpacked in parallel per image pull.
+    max_parallel_layers: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("CRI_MAX_CONCURRENT_PULLS"),
+        long("max-concurrent-pulls"),
+        value_name("PULLS")
+    )]
+    /// The maximum number of images pulled at the same time on the node, whereas further pulls
+    /// wait for a running one to finish. Pulls are unlimited if set to `0`.
+    max_concurrent_pulls: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("CRI_IMAGE_GC_HIGH_THRESHOLD"),
+        long("image-gc-high-threshold"),
+        value_name("PERCENT")
+    )]
+    /// The usage of the image filesystem in percent which starts the garbage collection of unused
+    /// images. Images which are reused often are collected last, whereas `0` disables the
+    /// collection.
+    image_gc_high_threshold: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("80"),
+        env("CRI_IMAGE_GC_LOW_THRESHOLD"),
+        long("image-gc-low-threshold"),
+        value_name("PERCENT")
+    )]
+    /// The usage of the image filesystem in percent the garbage collection of images tries to
+    /// reach.
+    image_gc_low_threshold: u64,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_PRE_PULL_IMAGES"),
+        long("pre-pull-images"),
+        use_delimiter(true),
+        value_name("IMAGE")
+    )]
+    /// Images pulled in the background when the server starts, so that critical system images
+    /// exist before the kubelet schedules pods, like `registry.k8s.io/pause:3.2`.
+    pre_pull_images: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_PINNED_IMAGES"),
+        long("pinned-images"),
+        use_delimiter(true),
+        value_name("IMAGE")
+    )]
+    /// Images which are pre-pulled like the `pre-pull-images` and never removed by the garbage
+    /// collection of images.
+    pinned_images: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_LOCAL_IMAGE_LAYOUT"),
+        long("local-image-layout"),
+        value_name("PATH")
+    )]
+    /// The OCI image layout directory images get resolved from before consulting registries,
+    /// which allows bootstrapping static pods on nodes without registry access.
+    local_image_layout: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_LOCAL_IMAGE_SOURCES"),
+        long("local-image-sources"),
+        use_delimiter(true),
+        value_name("PREFIX=POLICY")
+    )]
+    /// The image prefixes resolved from the local image layout, like `registry.k8s.io/=only`.
+    /// Images missing from the layout get pulled from registries with the `prefer` policy and
+    /// fail with the `only` policy. All images prefer the layout if no prefixes are configured.
+    local_image_sources: Vec<LocalImageSource>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("CRI_EXITED_CONTAINERS_PER_POD"),
+        long("exited-containers-per-pod"),
+        value_name("COUNT")
+    )]
+    /// The maximum number of exited containers retained per pod sandbox, whereas older ones get
+    /// removed together with their logs regardless of the garbage collection of the kubelet. The
+    /// latest attempt of every container is always retained. A value of `0` retains all of them.
+    exited_containers_per_pod: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env("CRI_EXITED_CONTAINER_MAX_AGE"),
+        long("exited-container-max-age"),
+        value_name("SECONDS")
+    )]
+    /// The time in seconds exited containers are retained after they finished, whereas the latest
+    /// attempt of every container is always retained. A value of `0` retains them forever.
+    exited_container_max_age: u64,
+
+    #[get = "pub"]
+    #[clap(env("CRI_CORE_DUMP_PATH"), long("core-dump-path"), value_name("PATH"))]
+    /// The host directory receiving the core dumps of containers, which get their own directory
+    /// named after their namespace, pod and container. Core dumps are disabled if not set.
+    core_dump_path: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("1073741824"),
+        env("CRI_CORE_DUMP_SIZE"),
+        long("core-dump-size"),
+        value_name("BYTES")
+    )]
+    /// The maximum size of a single core dump in bytes. Containers can lower it or disable core
+    /// dumps via the `io.kubernetes.cri.core-dump` annotation.
+    core_dump_size: u64,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_CHECKPOINT_PATH"),
+        long("checkpoint-path"),
+        value_name("PATH")
+    )]
+    /// The host directory receiving the checkpoints of pods, which has to survive reboots together
+    /// with the storage path for restoring them afterwards. Requires the `checkpoint` feature.
+    checkpoint_path: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_EXTRA_HOSTS"),
+        long("extra-hosts"),
+        use_delimiter(true),
+        value_name("NAME=IP")
+    )]
+    /// Entries added to the hosts file of every pod, like `mirror.local=10.0.0.1` for a node
+    /// local registry mirror. Entries provided by the kubelet take precedence.
+    extra_hosts: Vec<HostEntry>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_DNS_OPTIONS"),
+        long("dns-options"),
+        use_delimiter(true),
+        value_name("OPTION")
+    )]
+    /// Resolver options added to the resolv.conf of every pod, like `ndots:2`. Supported are
+    /// `ndots`, `timeout`, `attempts` and `use-vc`, whereas options of the kubelet take precedence.
+    dns_options: Vec<DnsOption>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("127.0.0.1"),
+        env("CRI_STREAMING_ADDRESS"),
+        long("streaming-address"),
+        value_name("ADDRESS")
+    )]
+    /// The address of the streaming server, which serves the exec and port forward sessions
+    /// requested by the kubelet.
+    streaming_address: IpAddr,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("10010"),
+        env("CRI_STREAMING_PORT"),
+        long("streaming-port"),
+        value_name("PORT")
+    )]
+    /// The port of the streaming server.
+    streaming_port: u16,
+
+    #[get_copy = "pub"]
+    #[clap(
+        env("CRI_METRICS_ADDRESS"),
+        long("metrics-address"),
+        value_name("ADDRESS")
+    )]
+    /// The address of the HTTP server exposing Prometheus metrics at `/metrics`, like
+    /// `127.0.0.1:9090`. Metrics are not served if unset.
+    metrics_address: Option<SocketAddr>,
+
+    #[get = "pub"]
+    #[clap(env("CRI_OTLP_ENDPOINT"), long("otlp-endpoint"), value_name("URL"))]
+    /// The URL of an OTLP/HTTP collector receiving a span of every RPC, like
+    /// `http://127.0.0.1:4318`. Spans are only logged on debug level if unset.
+    otlp_endpoint: Option<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_STREAMING_TLS_CERT"),
+        long("streaming-tls-cert"),
+        value_name("PATH")
+    )]
+    /// The PEM encoded certificate of the streaming server. The streaming server uses TLS if the
+    /// certificate and its key are set.
+    streaming_tls_cert: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_STREAMING_TLS_KEY"),
+        long("streaming-tls-key"),
+        value_name("PATH")
+    )]
+    /// The PEM encoded private key of the streaming server certificate.
+    streaming_tls_key: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("shared"),
+        env("CRI_ATTACH_STDIN"),
+        long("attach-stdin"),
+        possible_values(&["shared", "exclusive"]),
+        value_name("MODE")
+    )]
+    /// How sessions attached to the same container share its input. All sessions write the
+    /// input in the `shared` mode, whereas only the first one requesting it does in the
+    /// `exclusive` mode, until it detaches.
+    attach_stdin: AttachStdin,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_SESSION_RECORDING_PATH"),
+        long("session-recording-path"),
+        value_name("PATH")
+    )]
+    /// The directory exec and attach sessions get recorded into for audits, including their input
+    /// and output with timestamps. Sessions are not recorded if unset.
+    session_recording_path: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_SESSION_RECORDING_NAMESPACES"),
+        long("session-recording-namespaces"),
+        use_delimiter(true),
+        value_name("NAMESPACE")
+    )]
+    /// The pod namespaces whose sessions get recorded, whereas the sessions of all namespaces get
+    /// recorded if empty.
+    session_recording_namespaces: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env("CRI_OUTPUT_TEE_PATH"),
+        long("output-tee-path"),
+        value_name("PATH")
+    )]
+    /// The directory containing the files or FIFOs the output of containers can be teed to via
+    /// the `io.kubernetes.cri.output-tee` annotation, besides the CRI log. Teeing is disabled if
+    /// unset.
+    output_tee_path: Option<PathBuf>,
+
     #[get_copy = "pub"]
     #[clap(
-        default_value("info"),
-        env("CRI_LOG_LEVEL"),
-        long("log-level"),
-        possible_values(&["trace", "debug", "info", "warn", "error", "off"]),
-        short('l'),
-        value_name("LEVEL")
+        default_value("5"),
+        env("CRI_STATS_INTERVAL"),
+        long("stats-interval"),
+        value_name("SECONDS")
     )]
-    /// The logging level of the application.
-    log_level: LevelFilter,
+    /// The sampling interval of container statistics in seconds. Statistics requested within the
+    /// interval are served from the previous sample, whereas `0` samples on every request.
+    stats_interval: u64,
 
     #[get_copy = "pub"]
     #[clap(
-        default_value("lib"),
-        env("CRI_LOG_SCOPE"),
-        long("log-scope"),
-        possible_values(&["lib", "global"]),
-        value_name("SCOPE")
+        default_value("60"),
+        env("CRI_IMAGE_FS_INTERVAL"),
+        long("image-fs-interval"),
+        value_name("SECONDS")
     )]
-    /// The logging scope of the application. If set to `global`, then all dependent crates will
-    /// log on the provided level, too. Otherwise the logs are scoped to this application only.
-    log_scope: LogScope,
+    /// The interval in seconds of sampling the usage of the image filesystem reported to the
+    /// kubelet in the background, whereas `0` samples on every request.
+    image_fs_interval: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("60"),
+        env("CRI_STATS_HISTORY"),
+        long("stats-history"),
+        value_name("SAMPLES")
+    )]
+    /// The number of statistics samples kept in memory per container, which get reported by
+    /// verbose container status requests and diagnostic dumps. `0` disables the history.
+    stats_history: usize,
 
     #[get = "pub"]
     #[clap(
-        default_value(&DEFAULT_SOCK_PATH),
-        env("CRI_SOCK_PATH"),
-        long("sock-path"),
+        env("CRI_DIAGNOSTICS_PATH"),
+        long("diagnostics-path"),
         value_name("PATH")
     )]
-    /// The path to the unix socket for the server.
-    sock_path: PathBuf,
+    /// The directory of the diagnostic dumps, which the server writes when receiving SIGUSR1.
+    /// Dumps are written to the log if not set.
+    diagnostics_path: Option<PathBuf>,
 
     #[get = "pub"]
     #[clap(
-        default_value(&DEFAULT_STORAGE_PATH),
-        env("CRI_STORAGE_PATH"),
-        long("storage-path"),
-        value_name("PATH")
+        env("CRI_HEALTH_CHECKS"),
+        long("health-checks"),
+        use_delimiter(true),
+        value_name("NAME=KIND:ARGUMENT")
     )]
-    /// The path to the persistent storage for the server.
-    storage_path: PathBuf,
+    /// Custom health checks reported as runtime conditions on every Status request, like
+    /// `RegistryMirrorReachable=tcp:mirror.local:5000`. Supported kinds are `exec` running a
+    /// script, `tcp` connecting to an address and `cni-version` querying a CNI plugin binary.
+    health_checks: Vec<HealthCheckSpec>,
+
+    #[get = "pub"]
+    #[clap(subcommand)]
+    #[serde(skip)]
+    /// The command to run instead of the server.
+    command: Option<Command>,
+}
+
+#[derive(Clap, Clone, Debug, PartialEq)]
+/// Command is a command which can be run instead of the server.
+pub enum Command {
+    #[clap(subcommand)]
+    /// Inspect the configuration.
+    Config(ConfigCommand),
+
+    /// Print the records of a container log file, whereas its index allows skipping the older
+    /// records of large files. New records can be followed like `tail -F` does. Resolving the
+    /// log file of a container by its ID requires the admin socket of the running server.
+    Logs(LogsCommand),
+
+    /// Print the files of a container which have been added (A), changed (C) or deleted (D)
+    /// compared to its image. Requires the admin socket of the running server.
+    Diff(DiffCommand),
+
+    /// Create a new image from the changes of a container compared to its image. Requires the
+    /// unix listen socket of the running server.
+    Commit(CommitCommand),
+
+    /// Push an image to its registry with the credentials of the node. Requires the unix listen
+    /// socket of the running server.
+    Push(PushCommand),
+
+    /// Checkpoint and stop the running containers of pods for node maintenance. Requires the unix
+    /// listen socket of the running server and the `checkpoint` feature.
+    Checkpoint(PodsCommand),
+
+    /// Restore the checkpointed containers of pods, for example after a reboot. Requires the unix
+    /// listen socket of the running server and the `checkpoint` feature.
+    Restore(PodsCommand),
+}
+
+#[derive(Clap, Clone, Debug, PartialEq)]
+/// ConfigCommand inspects the configuration.
+pub enum ConfigCommand {
+    /// Print the effective configuration in the format of the configuration file.
+    Default,
+}
+
+#[derive(Clap, Clone, CopyGetters, Debug, Getters, PartialEq)]
+/// LogsCommand queries a container log file.
+pub struct LogsCommand {
+    #[get = "pub"]
+    #[clap(value_name("CONTAINER"))]
+    /// The path of the container log file, like `/var/log/pods/<pod>/<container>/0.log`, or the
+    /// ID of the container or a unique prefix of it, which gets resolved via the admin socket.
+    container: String,
+
+    #[get_copy = "pub"]
+    #[clap(long("since-seconds"), value_name("SECONDS"))]
+    /// Only print the records written during the last seconds.
+    since_seconds: Option<u64>,
+
+    #[get_copy = "pub"]
+    #[clap(long("tail"), value_name("RECORDS"))]
+    /// Only print the last records.
+    tail: Option<usize>,
+
+    #[get_copy = "pub"]
+    #[clap(long("follow"), short('f'))]
+    /// Keep printing new records once they are written, even if the log file gets rotated.
+    follow: bool,
+}
+
+#[derive(Clap, Clone, Debug, Getters, PartialEq)]
+/// DiffCommand lists the changes of a container.
+pub struct DiffCommand {
+    #[get = "pub"]
+    #[clap(value_name("CONTAINER_ID"))]
+    /// The ID of the container or a unique prefix of it.
+    container_id: String,
+}
+
+#[derive(Clap, Clone, Debug, Getters, PartialEq)]
+/// CommitCommand creates an image from a container.
+pub struct CommitCommand {
+    #[get = "pub"]
+    #[clap(value_name("CONTAINER_ID"))]
+    /// The ID of the container or a unique prefix of it.
+    container_id: String,
+
+    #[get = "pub"]
+    #[clap(value_name("IMAGE"))]
+    /// The tagged reference of the new image, like `localhost/app:debug`.
+    image: String,
+
+    #[get = "pub"]
+    #[clap(default_value(""), long("message"), short('m'), value_name("MESSAGE"))]
+    /// The description of the new layer in the image history.
+    message: String,
+}
+
+#[derive(Clap, Clone, Debug, Getters, PartialEq)]
+/// PushCommand uploads an image to a registry.
+pub struct PushCommand {
+    #[get = "pub"]
+    #[clap(value_name("IMAGE"))]
+    /// The reference or ID of the image.
+    image: String,
+
+    #[get = "pub"]
+    #[clap(value_name("REFERENCE"))]
+    /// The tagged reference the image gets pushed as, like `quay.io/tenant/app:v2`. Defaults to
+    /// the image itself.
+    reference: Option<String>,
+}
+
+#[derive(Clap, Clone, Debug, Getters, PartialEq)]
+/// PodsCommand selects pod sandboxes.
+pub struct PodsCommand {
+    #[get = "pub"]
+    #[clap(required(true), value_name("POD_SANDBOX_ID"))]
+    /// The IDs of the pod sandboxes.
+    pod_sandbox_ids: Vec<String>,
 }
 
 impl Config {
+    /// Load the configuration from the arguments of the process like `load_from`.
+    pub fn load() -> Result<Self> {
+        Self::load_from(env::args_os())
+    }
+
+    /// Load the configuration from the command line `args`, the environment and the
+    /// configuration file referenced by them. Invalid arguments exit the process, like the
+    /// parsers of `Clap` do, whereas invalid values of the configuration file are returned as
+    /// error.
+    pub fn load_from<I, T>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        Self::with_matches(&args, |x| Self::from_arg_matches(x).validate())
+    }
+
+    /// Validate the values which can not be checked while parsing them individually.
+    fn validate(self) -> Result<Self> {
+        // Reserved features would be reported as enabled without having any effect
+        if let Some(feature) = self.features().iter().find(|x| !x.is_supported()) {
+            bail!("feature {} is not supported yet", feature.as_ref())
+        }
+        Ok(self)
+    }
+
+    /// Render the effective configuration of the command line `args` like `load_from`, but in
+    /// the TOML format of the configuration file.
+    pub fn effective_toml<I, T>(args: I) -> Result<String>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        Self::with_matches(&args, |matches| {
+            let mut table = Table::new();
+            for arg in Self::into_app().get_arguments() {
+                let values = match matches.values_of(arg.get_name()) {
+                    Some(values) if arg.get_name() != CONFIG_ARG => values.map(toml_value),
+                    _ => continue,
+                };
+                let value = if arg.is_set(ArgSettings::UseValueDelimiter) {
+                    Value::Array(values.collect())
+                } else {
+                    values.last().context("no value provided")?
+                };
+                table.insert(arg.get_name().replace('_', "-"), value);
+            }
+            toml::to_string(&table).context("serialize configuration")
+        })
+    }
+
+    /// Run `f` on the argument matches of `args`, whereas the values of the configuration file
+    /// are used as default values.
+    fn with_matches<F, R>(args: &[OsString], f: F) -> Result<R>
+    where
+        F: FnOnce(&ArgMatches) -> Result<R>,
+    {
+        let matches = Self::into_app().get_matches_from(args);
+        let defaults = match matches.value_of_os(CONFIG_ARG) {
+            Some(path) => Self::file_defaults(Path::new(path))?,
+            None => vec![],
+        };
+
+        let mut app = Self::into_app();
+        for (name, values) in &defaults {
+            let values: Vec<&str> = values.iter().map(String::as_str).collect();
+            app = app.mut_arg(name.as_str(), |x| x.default_values(&values));
+        }
+        let matches = app
+            .try_get_matches_from(args)
+            .context("parse configuration")?;
+        f(&matches)
+    }
+
+    /// Apply the settings of `other` which can be changed while the server is running, which
+    /// are the log level, the CNI directories and the registries. Returns the names of the
+    /// changed settings.
+    pub fn reload_from(&mut self, other: &Config) -> Vec<&'static str> {
+        let mut changed = vec![];
+        if self.log_level != other.log_level {
+            self.log_level = other.log_level;
+            changed.push("log-level");
+        }
+        if self.cni_config_dir != other.cni_config_dir {
+            self.cni_config_dir = other.cni_config_dir.clone();
+            changed.push("cni-config-dir");
+        }
+        if self.cni_plugin_dirs != other.cni_plugin_dirs {
+            self.cni_plugin_dirs = other.cni_plugin_dirs.clone();
+            changed.push("cni-plugin-dirs");
+        }
+        if self.registry_mirrors != other.registry_mirrors {
+            self.registry_mirrors = other.registry_mirrors.clone();
+            changed.push("registry-mirrors");
+        }
+        if self.insecure_registries != other.insecure_registries {
+            self.insecure_registries = other.insecure_registries.clone();
+            changed.push("insecure-registries");
+        }
+        if self.blocked_registries != other.blocked_registries {
+            self.blocked_registries = other.blocked_registries.clone();
+            changed.push("blocked-registries");
+        }
+        changed
+    }
+
+    /// Read the configuration file at `path` into the argument names and their values.
+    fn file_defaults(path: &Path) -> Result<Vec<(String, Vec<String>)>> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("read configuration file {}", path.display()))?;
+        let table: Table = toml::from_str(&content)
+            .with_context(|| format!("parse configuration file {}", path.display()))?;
+
+        let app = Self::into_app();
+        let mut defaults: Vec<(String, Vec<String>)> = vec![];
+        for (key, value) in table {
+            // The keys are sorted, which is why a flat runtime-allowed-annotations comes first
+            if key == RUNTIMES_KEY {
+                let values = Self::runtime_defaults(value)?;
+                match defaults
+                    .iter_mut()
+                    .find(|(x, _)| x == RUNTIME_ANNOTATIONS_ARG)
+                {
+                    Some((_, existing)) => existing.extend(values),
+                    None => defaults.push((RUNTIME_ANNOTATIONS_ARG.into(), values)),
+                }
+                continue;
+            }
+            let name = key.replace('-', "_");
+            let known = app
+                .get_arguments()
+                .any(|x| x.get_name() == name && x.is_set(ArgSettings::TakesValue));
+            if name == CONFIG_ARG || !known {
+                bail!("unknown configuration key {}", key)
+            }
+            let values = match value {
+                Value::Array(values) => values.into_iter().map(cli_value).collect(),
+                value => cli_value(value).map(|x| vec![x]),
+            }
+            .with_context(|| format!("invalid {}", key))?;
+
+            // Default values are not validated, which is why they are checked like flags
+            let flags = values.iter().map(|x| format!("--{}={}", key, x));
+            Self::into_app()
+                .try_get_matches_from(iter::once(crate_name!().to_string()).chain(flags))
+                .with_context(|| format!("invalid {}", key))?;
+            defaults.push((name, values));
+        }
+        Ok(defaults)
+    }
+
+    /// Convert the `[runtimes.HANDLER]` tables of the configuration file into the values of the
+    /// runtime allowed annotations.
+    fn runtime_defaults(value: Value) -> Result<Vec<String>> {
+        let handlers = match value {
+            Value::Table(handlers) => handlers,
+            _ => bail!(
+                "invalid {}, expected tables per runtime handler",
+                RUNTIMES_KEY
+            ),
+        };
+        let mut values = vec![];
+        for (handler, value) in handlers {
+            let table = match value {
+                Value::Table(table) => table,
+                _ => bail!("invalid {}.{}, expected a table", RUNTIMES_KEY, handler),
+            };
+            for (key, value) in table {
+                if key != "allowed-annotations" {
+                    bail!(
+                        "unknown configuration key {}.{}.{}",
+                        RUNTIMES_KEY,
+                        handler,
+                        key
+                    )
+                }
+                let annotations = match value {
+                    Value::Array(values) => values,
+                    _ => bail!(
+                        "invalid {}.{}.{}, expected an array",
+                        RUNTIMES_KEY,
+                        handler,
+                        key
+                    ),
+                };
+                for annotation in annotations {
+                    let annotation = cli_value(annotation)
+                        .with_context(|| format!("invalid {}.{}.{}", RUNTIMES_KEY, handler, key))?;
+                    let value = format!("{}={}", handler, annotation);
+                    value
+                        .parse::<RuntimeAnnotation>()
+                        .with_context(|| format!("invalid {}.{}.{}", RUNTIMES_KEY, handler, key))?;
+                    values.push(value);
+                }
+            }
+        }
+        Ok(values)
+    }
+
     /// Return the default socket path depending if running as root or not.
     fn default_sock_path() -> PathBuf {
         Self::default_run_path(unistd::getuid())
@@ -83,6 +1342,71 @@ impl Config {
         Self::default_run_path(unistd::getuid()).join("storage")
     }
 
+    /// Return the default network namespace path depending if running as root or not.
+    pub fn default_netns_path() -> PathBuf {
+        Self::default_run_path(unistd::getuid()).join("netns")
+    }
+
+    /// Return the default log path depending if running as root or not.
+    fn default_log_path() -> PathBuf {
+        Self::default_run_path(unistd::getuid()).join("logs")
+    }
+
+    /// Return the default sandbox path depending if running as root or not.
+    fn default_sandbox_path() -> PathBuf {
+        Self::default_run_path(unistd::getuid()).join("sandboxes")
+    }
+
+    /// Return the default container path depending if running as root or not.
+    fn default_container_path() -> PathBuf {
+        Self::default_run_path(unistd::getuid()).join("containers")
+    }
+
+    /// Return the default image path depending if running as root or not.
+    fn default_image_path() -> PathBuf {
+        Self::default_run_path(unistd::getuid()).join("images")
+    }
+
+    /// Return the address the server listens on, which is the unix socket at the socket path if
+    /// no other address is configured.
+    pub fn listen_address(&self) -> ListenAddress {
+        self.listen()
+            .clone()
+            .unwrap_or_else(|| ListenAddress::Unix(self.sock_path().clone()))
+    }
+
+    /// Return all paths the server has to be able to write to.
+    pub fn writable_paths(&self) -> Vec<(&'static str, PathBuf)> {
+        let mut paths = vec![];
+        if let ListenAddress::Unix(path) = self.listen_address() {
+            if let Some(sock_dir) = path.parent() {
+                paths.push(("socket directory", sock_dir.into()));
+            }
+        }
+        if let Some(sock_dir) = self.admin_sock_path().as_ref().and_then(|x| x.parent()) {
+            paths.push(("admin socket directory", sock_dir.into()));
+        }
+        paths.push(("storage path", self.storage_path().clone()));
+        paths.push(("network namespace path", self.netns_path().clone()));
+        paths.push(("log path", self.log_path().clone()));
+        paths.push(("sandbox path", self.sandbox_path().clone()));
+        paths.push(("container path", self.container_path().clone()));
+        paths.push(("image path", self.image_path().clone()));
+        if let Some(path) = self.layer_cache_path() {
+            paths.push(("layer cache path", path.clone()));
+        }
+        if let Some(path) = self.core_dump_path() {
+            paths.push(("core dump path", path.clone()));
+        }
+        if let Some(path) = self.checkpoint_path() {
+            paths.push(("checkpoint path", path.clone()));
+        }
+        if let Some(path) = self.diagnostics_path() {
+            paths.push(("diagnostics path", path.clone()));
+        }
+        paths
+    }
+
     /// Return the default run path depending on the provided user ID.
     fn default_run_path(uid: Uid) -> PathBuf {
         if uid.is_root() {
@@ -95,6 +1419,25 @@ impl Config {
     }
 }
 
+/// Convert a TOML value of the configuration file into its command line representation.
+fn cli_value(value: Value) -> Result<String> {
+    match value {
+        Value::String(x) => Ok(x),
+        Value::Integer(x) => Ok(x.to_string()),
+        Value::Float(x) => Ok(x.to_string()),
+        Value::Boolean(x) => Ok(x.to_string()),
+        value => bail!("unsupported value {}", value),
+    }
+}
+
+/// Convert a command line value into its TOML representation.
+fn toml_value(value: &str) -> Value {
+    match value.parse() {
+        Ok(x) => Value::Integer(x),
+        Err(_) => Value::String(value.into()),
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self::parse()
@@ -113,10 +1456,122 @@ pub enum LogScope {
     Global,
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, EnumString, PartialEq, Serialize)]
+/// Defines the format of log records.
+pub enum LogFormat {
+    #[strum(serialize = "text")]
+    /// Human readable lines.
+    Text,
+
+    #[strum(serialize = "json")]
+    /// One JSON object per line.
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, EnumString, PartialEq, Serialize)]
+/// Defines the destination of log records.
+pub enum LogDriver {
+    #[strum(serialize = "stderr")]
+    /// The standard error of the server.
+    Stderr,
+
+    #[strum(serialize = "journald")]
+    /// The native protocol of the systemd journal.
+    Journald,
+
+    #[strum(serialize = "file")]
+    /// A file which gets appended to.
+    File,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, EnumString, PartialEq, Serialize)]
+/// Defines the behavior for storages which cannot be opened.
+pub enum StorageRecovery {
+    #[strum(serialize = "fail")]
+    /// Refuse to start the server.
+    Fail,
+
+    #[strum(serialize = "restore")]
+    /// Restore the storage from its latest snapshot.
+    Restore,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, EnumString, PartialEq, Serialize)]
+/// Defines the implementation of the storage.
+pub enum StorageBackend {
+    #[strum(serialize = "sled")]
+    /// The embedded sled database inside the storage path.
+    Sled,
+
+    #[strum(serialize = "memory")]
+    /// An in-memory storage, which is lost on restart.
+    Memory,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, EnumString, PartialEq, Serialize)]
+/// Defines the Pod Security Standard which gets enforced.
+pub enum PodSecurityLevel {
+    #[strum(serialize = "privileged")]
+    /// Unrestricted, nothing gets enforced.
+    Privileged,
+
+    #[strum(serialize = "baseline")]
+    /// Prevent known privilege escalations.
+    Baseline,
+
+    #[strum(serialize = "restricted")]
+    /// Enforce the current pod hardening best practices.
+    Restricted,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, EnumString, PartialEq, Serialize)]
+/// Defines how the signatures of pulled images get verified.
+pub enum SignatureVerification {
+    #[strum(serialize = "off")]
+    /// Signatures are not verified.
+    Off,
+
+    #[strum(serialize = "warn")]
+    /// Images failing the verification are logged but still pulled.
+    Warn,
+
+    #[strum(serialize = "enforce")]
+    /// Images failing the verification are rejected.
+    Enforce,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, EnumString, PartialEq, Serialize)]
+/// Defines the image pull policy enforced by the runtime.
+pub enum ImagePullPolicy {
+    #[strum(serialize = "kubelet")]
+    /// The pull policy of the kubelet applies.
+    Kubelet,
+
+    #[strum(serialize = "always")]
+    /// Tags get resolved against the registry for every container, even if the image exists.
+    Always,
+
+    #[strum(serialize = "never")]
+    /// Only local images are used, pulls of missing images fail.
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, EnumString, PartialEq, Serialize)]
+/// Defines how attached sessions share the input of a container.
+pub enum AttachStdin {
+    #[strum(serialize = "shared")]
+    /// All sessions write the input.
+    Shared,
+
+    #[strum(serialize = "exclusive")]
+    /// Only the session holding the input writes it.
+    Exclusive,
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use anyhow::Result;
+    use tempfile::NamedTempFile;
 
     #[test]
     fn default_config() {
@@ -127,16 +1582,254 @@ pub mod tests {
     #[test]
     fn build_config() -> Result<()> {
         let c = ConfigBuilder::default()
+            .config(Some(PathBuf::from("/some/config.toml")))
             .log_level(LevelFilter::Warn)
             .sock_path("/some/path")
+            .listen(Some("tcp://127.0.0.1:10010".parse()?))
+            .tls_cert(Some(PathBuf::from("/some/server.crt")))
+            .tls_key(Some(PathBuf::from("/some/server.key")))
+            .tls_client_ca(Some(PathBuf::from("/some/ca.crt")))
             .log_scope(LogScope::Global)
+            .log_format(LogFormat::Json)
+            .log_driver(LogDriver::File)
+            .log_file(Some(PathBuf::from("/some/cri.log")))
             .storage_path("/some/other/path")
+            .netns_path("/some/netns/path")
+            .cni_config_dir("/some/cni/config")
+            .cni_plugin_dirs(vec![PathBuf::from("/some/cni/bin")])
+            .cni_config_template(Some(PathBuf::from("/some/cni/template.conflist")))
+            .cni_ipam_state_path("/some/cni/networks")
+            .network_leak_scan_interval(60u64)
+            .log_path("/some/log/path")
+            .sandbox_path("/some/sandbox/path")
+            .infra_command("/pause")
+            .container_path("/some/container/path")
+            .oci_runtime("/usr/bin/crun")
+            .image_path("/some/image/path")
+            .allowed_devices(vec!["/dev/fuse".into()])
+            .stop_timeout(10u64)
+            .cpu_burst(20_000u64)
+            .features(vec![Feature::Hardened])
+            .allowed_annotations(vec!["io.kubernetes.cri-o.ShmSize".into()])
+            .runtime_allowed_annotations(vec!["kata=io.katacontainers".parse()?])
+            .log_rate_limit(1024u64)
+            .log_burst(4096u64)
+            .daemon_cgroup(Some(PathBuf::from("/system.slice/cri.service")))
+            .daemon_cpu_limit(500u64)
+            .daemon_memory_limit(1024u64)
+            .oom_score_adj(-500)
+            .request_timeout(60u64)
+            .method_timeouts(vec!["PullImage=600".parse()?])
+            .latency_budgets(vec!["RunPodSandbox=2000".parse()?])
+            .shutdown_timeout(10u64)
+            .storage_snapshot_interval(60u64)
+            .storage_recovery(StorageRecovery::Fail)
+            .storage_backend(StorageBackend::Memory)
+            .policy_path(Some(PathBuf::from("/some/policy.json")))
+            .pod_security(PodSecurityLevel::Restricted)
+            .namespace_quotas(vec!["tenant=10:50".parse()?])
+            .handler_quotas(vec!["kata=5:20".parse()?])
+            .admin_sock_path(Some(PathBuf::from("/some/admin.sock")))
+            .layer_cache_path(Some(PathBuf::from("/some/cache")))
+            .containerd_root(Some(PathBuf::from("/var/lib/containerd")))
+            .registry_auth_file(Some(PathBuf::from("/etc/containers/auth.json")))
+            .registry_mirrors(vec!["docker.io=mirror.local:5000".parse()?])
+            .insecure_registries(vec!["mirror.local:5000".into()])
+            .blocked_registries(vec!["quay.io/tenant".into()])
+            .peer_endpoint(Some("http://127.0.0.1:5001".into()))
+            .peer_timeout(2u64)
+            .signature_policy_path(Some(PathBuf::from("/etc/cri/signatures.json")))
+            .signature_verification(SignatureVerification::Enforce)
+            .image_pull_policy(ImagePullPolicy::Never)
+            .max_parallel_layers(5usize)
+            .max_concurrent_pulls(2usize)
+            .image_gc_high_threshold(85u64)
+            .image_gc_low_threshold(75u64)
+            .pre_pull_images(vec!["quay.io/tenant/agent:1.0".into()])
+            .pinned_images(vec!["registry.k8s.io/pause:3.2".into()])
+            .local_image_layout(Some(PathBuf::from("/some/layout")))
+            .local_image_sources(vec!["registry.k8s.io/=only".parse()?])
+            .exited_containers_per_pod(3usize)
+            .exited_container_max_age(3600u64)
+            .core_dump_path(Some(PathBuf::from("/some/cores")))
+            .core_dump_size(1024u64)
+            .checkpoint_path(Some(PathBuf::from("/some/checkpoints")))
+            .extra_hosts(vec!["mirror.local=10.0.0.1".parse()?])
+            .dns_options(vec!["ndots:2".parse()?, "use-vc".parse()?])
+            .streaming_address(IpAddr::from([0, 0, 0, 0]))
+            .streaming_port(8080u16)
+            .metrics_address(Some(SocketAddr::from(([127, 0, 0, 1], 9090))))
+            .otlp_endpoint(Some("http://127.0.0.1:4318".into()))
+            .streaming_tls_cert(Some(PathBuf::from("/some/streaming.crt")))
+            .streaming_tls_key(Some(PathBuf::from("/some/streaming.key")))
+            .attach_stdin(AttachStdin::Exclusive)
+            .session_recording_path(Some(PathBuf::from("/some/recordings")))
+            .session_recording_namespaces(vec!["audited".into()])
+            .output_tee_path(Some(PathBuf::from("/some/tee")))
+            .stats_interval(10u64)
+            .image_fs_interval(30u64)
+            .stats_history(30usize)
+            .diagnostics_path(Some(PathBuf::from("/some/diagnostics")))
+            .health_checks(vec![
+                "RegistryMirrorReachable=tcp:mirror.local:5000".parse()?
+            ])
             .build()?;
 
+        assert_eq!(c.config().as_deref(), Some(Path::new("/some/config.toml")));
         assert_eq!(c.log_level(), LevelFilter::Warn);
         assert_eq!(&c.sock_path().display().to_string(), "/some/path");
+        assert_eq!(
+            c.listen_address(),
+            ListenAddress::Tcp(([127, 0, 0, 1], 10010).into())
+        );
+        assert_eq!(c.tls_cert().as_deref(), Some(Path::new("/some/server.crt")));
+        assert_eq!(c.tls_key().as_deref(), Some(Path::new("/some/server.key")));
+        assert_eq!(
+            c.tls_client_ca().as_deref(),
+            Some(Path::new("/some/ca.crt"))
+        );
         assert_eq!(c.log_scope(), LogScope::Global);
+        assert_eq!(c.log_format(), LogFormat::Json);
+        assert_eq!(c.log_driver(), LogDriver::File);
+        assert_eq!(c.log_file().as_deref(), Some(Path::new("/some/cri.log")));
         assert_eq!(&c.storage_path().display().to_string(), "/some/other/path");
+        assert_eq!(&c.netns_path().display().to_string(), "/some/netns/path");
+        assert_eq!(c.cni_config_dir(), Path::new("/some/cni/config"));
+        assert_eq!(c.cni_plugin_dirs(), &[PathBuf::from("/some/cni/bin")]);
+        assert_eq!(
+            c.cni_config_template().as_deref(),
+            Some(Path::new("/some/cni/template.conflist"))
+        );
+        assert_eq!(c.cni_ipam_state_path(), Path::new("/some/cni/networks"));
+        assert_eq!(c.network_leak_scan_interval(), 60);
+        assert_eq!(&c.log_path().display().to_string(), "/some/log/path");
+        assert_eq!(
+            &c.sandbox_path().display().to_string(),
+            "/some/sandbox/path"
+        );
+        assert_eq!(c.infra_command(), "/pause");
+        assert_eq!(
+            &c.container_path().display().to_string(),
+            "/some/container/path"
+        );
+        assert_eq!(c.oci_runtime(), Path::new("/usr/bin/crun"));
+        assert_eq!(c.image_path(), Path::new("/some/image/path"));
+        assert_eq!(c.allowed_devices(), &["/dev/fuse"]);
+        assert_eq!(c.stop_timeout(), 10);
+        assert_eq!(c.cpu_burst(), 20_000);
+        assert_eq!(c.features(), &[Feature::Hardened]);
+        assert_eq!(c.allowed_annotations(), &["io.kubernetes.cri-o.ShmSize"]);
+        assert_eq!(c.runtime_allowed_annotations()[0].handler(), "kata");
+        assert_eq!(c.log_rate_limit(), 1024);
+        assert_eq!(c.log_burst(), 4096);
+        assert_eq!(
+            c.daemon_cgroup().as_deref(),
+            Some(Path::new("/system.slice/cri.service"))
+        );
+        assert_eq!(c.daemon_cpu_limit(), 500);
+        assert_eq!(c.daemon_memory_limit(), 1024);
+        assert_eq!(c.oom_score_adj(), -500);
+        assert_eq!(c.request_timeout(), 60);
+        assert_eq!(c.method_timeouts().len(), 1);
+        assert_eq!(c.method_timeouts()[0].method(), "PullImage");
+        assert_eq!(c.latency_budgets()[0].budget(), 2000);
+        assert_eq!(c.shutdown_timeout(), 10);
+        assert_eq!(c.storage_snapshot_interval(), 60);
+        assert_eq!(c.storage_recovery(), StorageRecovery::Fail);
+        assert_eq!(c.storage_backend(), StorageBackend::Memory);
+        assert_eq!(
+            c.policy_path().as_deref(),
+            Some(Path::new("/some/policy.json"))
+        );
+        assert_eq!(c.pod_security(), PodSecurityLevel::Restricted);
+        assert_eq!(c.namespace_quotas()[0].scope(), "tenant");
+        assert_eq!(c.handler_quotas()[0].containers(), 20);
+        assert_eq!(
+            c.admin_sock_path().as_deref(),
+            Some(Path::new("/some/admin.sock"))
+        );
+        assert_eq!(
+            c.layer_cache_path().as_deref(),
+            Some(Path::new("/some/cache"))
+        );
+        assert_eq!(
+            c.containerd_root().as_deref(),
+            Some(Path::new("/var/lib/containerd"))
+        );
+        assert_eq!(
+            c.registry_auth_file().as_deref(),
+            Some(Path::new("/etc/containers/auth.json"))
+        );
+        assert_eq!(c.registry_mirrors()[0].host(), "mirror.local:5000");
+        assert_eq!(c.insecure_registries(), &["mirror.local:5000"]);
+        assert_eq!(c.blocked_registries(), &["quay.io/tenant"]);
+        assert_eq!(c.peer_endpoint().as_deref(), Some("http://127.0.0.1:5001"));
+        assert_eq!(c.peer_timeout(), 2);
+        assert_eq!(
+            c.signature_policy_path().as_deref(),
+            Some(Path::new("/etc/cri/signatures.json"))
+        );
+        assert_eq!(c.signature_verification(), SignatureVerification::Enforce);
+        assert_eq!(c.image_pull_policy(), ImagePullPolicy::Never);
+        assert_eq!(c.max_parallel_layers(), 5);
+        assert_eq!(c.max_concurrent_pulls(), 2);
+        assert_eq!(c.image_gc_high_threshold(), 85);
+        assert_eq!(c.image_gc_low_threshold(), 75);
+        assert_eq!(c.pre_pull_images(), &["quay.io/tenant/agent:1.0"]);
+        assert_eq!(c.pinned_images(), &["registry.k8s.io/pause:3.2"]);
+        assert_eq!(
+            c.local_image_layout().as_deref(),
+            Some(Path::new("/some/layout"))
+        );
+        assert_eq!(
+            c.local_image_sources(),
+            &["registry.k8s.io/=only".parse::<LocalImageSource>()?]
+        );
+        assert_eq!(c.exited_containers_per_pod(), 3);
+        assert_eq!(c.exited_container_max_age(), 3600);
+        assert_eq!(
+            c.core_dump_path().as_deref(),
+            Some(Path::new("/some/cores"))
+        );
+        assert_eq!(c.core_dump_size(), 1024);
+        assert_eq!(
+            c.checkpoint_path().as_deref(),
+            Some(Path::new("/some/checkpoints"))
+        );
+        assert_eq!(c.extra_hosts().len(), 1);
+        assert_eq!(c.extra_hosts()[0].name(), "mirror.local");
+        assert_eq!(c.dns_options(), &[DnsOption::Ndots(2), DnsOption::UseVc]);
+        assert_eq!(c.streaming_address().to_string(), "0.0.0.0");
+        assert_eq!(
+            c.metrics_address().map(|x| x.to_string()).as_deref(),
+            Some("127.0.0.1:9090")
+        );
+        assert_eq!(c.otlp_endpoint().as_deref(), Some("http://127.0.0.1:4318"));
+        assert_eq!(c.streaming_port(), 8080);
+        assert_eq!(
+            c.streaming_tls_cert().as_deref(),
+            Some(Path::new("/some/streaming.crt"))
+        );
+        assert_eq!(
+            c.streaming_tls_key().as_deref(),
+            Some(Path::new("/some/streaming.key"))
+        );
+        assert_eq!(c.attach_stdin(), AttachStdin::Exclusive);
+        assert_eq!(
+            c.session_recording_path().as_deref(),
+            Some(Path::new("/some/recordings"))
+        );
+        assert_eq!(c.session_recording_namespaces(), &["audited"]);
+        assert_eq!(c.output_tee_path().as_deref(), Some(Path::new("/some/tee")));
+        assert_eq!(c.stats_interval(), 10);
+        assert_eq!(c.image_fs_interval(), 30);
+        assert_eq!(c.stats_history(), 30);
+        assert_eq!(
+            c.diagnostics_path().as_deref(),
+            Some(Path::new("/some/diagnostics"))
+        );
+        assert_eq!(c.health_checks().len(), 1);
+        assert_eq!(c.health_checks()[0].name(), "RegistryMirrorReachable");
 
         Ok(())
     }
@@ -176,4 +1869,278 @@ pub mod tests {
             .to_string()
             .contains("storage"));
     }
+
+    #[test]
+    fn default_netns_path() {
+        assert!(Config::default_netns_path().ends_with("netns"));
+    }
+
+    #[test]
+    fn default_log_path() {
+        assert!(Config::default_log_path().ends_with("logs"));
+    }
+
+    #[test]
+    fn default_sandbox_path() {
+        assert!(Config::default_sandbox_path().ends_with("sandboxes"));
+    }
+
+    #[test]
+    fn default_container_path() {
+        assert!(Config::default_container_path().ends_with("containers"));
+    }
+
+    #[test]
+    fn default_image_path() {
+        assert!(Config::default_image_path().ends_with("images"));
+    }
+
+    #[test]
+    fn writable_paths() -> Result<()> {
+        let c = ConfigBuilder::default()
+            .sock_path("/run/cri.sock")
+            .storage_path("/storage")
+            .netns_path("/netns")
+            .log_path("/logs")
+            .sandbox_path("/sandboxes")
+            .container_path("/containers")
+            .image_path("/images")
+            .build()?;
+
+        let paths: Vec<PathBuf> = c.writable_paths().into_iter().map(|(_, x)| x).collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/run"),
+                PathBuf::from("/storage"),
+                PathBuf::from("/netns"),
+                PathBuf::from("/logs"),
+                PathBuf::from("/sandboxes"),
+                PathBuf::from("/containers"),
+                PathBuf::from("/images"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn writable_paths_tcp() -> Result<()> {
+        let c = ConfigBuilder::default()
+            .sock_path("/run/cri.sock")
+            .listen(Some("tcp://127.0.0.1:10010".parse()?))
+            .build()?;
+        assert!(c
+            .writable_paths()
+            .iter()
+            .all(|(_, x)| x != Path::new("/run")));
+        Ok(())
+    }
+
+    /// Write the configuration file `content` into a new temporary file.
+    fn config_file(content: &str) -> Result<NamedTempFile> {
+        let file = NamedTempFile::new()?;
+        fs::write(file.path(), content)?;
+        Ok(file)
+    }
+
+    #[test]
+    fn load_from_file() -> Result<()> {
+        let file = config_file(
+            r#"
+            log-level = "debug"
+            stop-timeout = 45
+            allowed-devices = ["/dev/fuse", "/dev/kvm"]
+            "#,
+        )?;
+        let path = file.path().display().to_string();
+
+        let c = Config::load_from(&["cri", "--config", path.as_str()])?;
+        assert_eq!(c.config().as_deref(), Some(file.path()));
+        assert_eq!(c.log_level(), LevelFilter::Debug);
+        assert_eq!(c.stop_timeout(), 45);
+        assert_eq!(c.allowed_devices(), &["/dev/fuse", "/dev/kvm"]);
+        assert_eq!(c.log_scope(), LogScope::Lib);
+
+        // Command line flags take precedence over the file
+        let c = Config::load_from(&["cri", "--config", path.as_str(), "--stop-timeout", "5"])?;
+        assert_eq!(c.stop_timeout(), 5);
+        assert_eq!(c.log_level(), LevelFilter::Debug);
+        Ok(())
+    }
+
+    #[test]
+    fn load_from_file_fail() -> Result<()> {
+        let file = config_file(r#"unknown-option = "value""#)?;
+        let path = file.path().display().to_string();
+        let err = Config::load_from(&["cri", "--config", path.as_str()])
+            .err()
+            .context("no error")?;
+        assert!(err.to_string().contains("unknown-option"));
+
+        let file = config_file(r#"stop-timeout = { seconds = 10 }"#)?;
+        let path = file.path().display().to_string();
+        assert!(Config::load_from(&["cri", "--config", path.as_str()]).is_err());
+
+        let file = config_file(r#"log-level = "loud""#)?;
+        let path = file.path().display().to_string();
+        assert!(Config::load_from(&["cri", "--config", path.as_str()]).is_err());
+
+        assert!(Config::load_from(&["cri", "--config", "/does/not/exist.toml"]).is_err());
+
+        let file = config_file(r#"features = ["checkpoint", "nri"]"#)?;
+        let path = file.path().display().to_string();
+        let err = Config::load_from(&["cri", "--config", path.as_str()])
+            .err()
+            .context("no error")?;
+        assert_eq!(err.to_string(), "feature nri is not supported yet");
+        Ok(())
+    }
+
+    #[test]
+    fn load_from_file_runtimes() -> Result<()> {
+        let file = config_file(
+            r#"
+            runtime-allowed-annotations = ["default=io.kubernetes.cri-o.Devices"]
+
+            [runtimes.kata]
+            allowed-annotations = ["io.katacontainers"]
+
+            [runtimes.runsc]
+            allowed-annotations = ["dev.gvisor.spec"]
+            "#,
+        )?;
+        let path = file.path().display().to_string();
+
+        let c = Config::load_from(&["cri", "--config", path.as_str()])?;
+        let allowed: Vec<String> = c
+            .runtime_allowed_annotations()
+            .iter()
+            .map(|x| format!("{}={}", x.handler(), x.annotation()))
+            .collect();
+        assert_eq!(
+            allowed,
+            vec![
+                "default=io.kubernetes.cri-o.Devices",
+                "kata=io.katacontainers",
+                "runsc=dev.gvisor.spec",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_from_file_runtimes_fail() -> Result<()> {
+        for content in &[
+            "runtimes = \"kata\"",
+            "[runtimes]\nkata = [\"io.katacontainers\"]",
+            "[runtimes.kata]\nallowed-annotations = \"io.katacontainers\"",
+            "[runtimes.kata]\nallowed-annotations = [\"\"]",
+        ] {
+            let file = config_file(content)?;
+            let path = file.path().display().to_string();
+            assert!(
+                Config::load_from(&["cri", "--config", path.as_str()]).is_err(),
+                "{}",
+                content
+            );
+        }
+
+        let file = config_file("[runtimes.kata]\nprivileged = true")?;
+        let path = file.path().display().to_string();
+        let err = Config::load_from(&["cri", "--config", path.as_str()])
+            .err()
+            .context("no error")?;
+        assert!(err
+            .to_string()
+            .contains("unknown configuration key runtimes.kata.privileged"));
+        Ok(())
+    }
+
+    #[test]
+    fn load_from_command() -> Result<()> {
+        let c = Config::load_from(&["cri", "config", "default"])?;
+        assert_eq!(c.command(), &Some(Command::Config(ConfigCommand::Default)));
+        assert!(Config::load_from(&["cri"])?.command().is_none());
+
+        let c = Config::load_from(&["cri", "logs", "/some/0.log", "--tail", "10"])?;
+        match c.command() {
+            Some(Command::Logs(command)) => {
+                assert_eq!(command.container(), "/some/0.log");
+                assert_eq!(command.since_seconds(), None);
+                assert_eq!(command.tail(), Some(10));
+                assert!(!command.follow());
+            }
+            command => bail!("unexpected command {:?}", command),
+        }
+        let c = Config::load_from(&["cri", "logs", "3f2a", "-f"])?;
+        match c.command() {
+            Some(Command::Logs(command)) => {
+                assert_eq!(command.container(), "3f2a");
+                assert!(command.follow());
+            }
+            command => bail!("unexpected command {:?}", command),
+        }
+        let c = Config::load_from(&["cri", "diff", "3f2a"])?;
+        match c.command() {
+            Some(Command::Diff(command)) => assert_eq!(command.container_id(), "3f2a"),
+            command => bail!("unexpected command {:?}", command),
+        }
+        let c = Config::load_from(&["cri", "commit", "3f2a", "localhost/app:debug", "-m", "fix"])?;
+        match c.command() {
+            Some(Command::Commit(command)) => {
+                assert_eq!(command.container_id(), "3f2a");
+                assert_eq!(command.image(), "localhost/app:debug");
+                assert_eq!(command.message(), "fix");
+            }
+            command => bail!("unexpected command {:?}", command),
+        }
+        let c = Config::load_from(&["cri", "push", "localhost/app:debug", "quay.io/app:v2"])?;
+        match c.command() {
+            Some(Command::Push(command)) => {
+                assert_eq!(command.image(), "localhost/app:debug");
+                assert_eq!(command.reference().as_deref(), Some("quay.io/app:v2"));
+            }
+            command => bail!("unexpected command {:?}", command),
+        }
+        let c = Config::load_from(&["cri", "checkpoint", "0123", "4567"])?;
+        match c.command() {
+            Some(Command::Checkpoint(command)) => {
+                assert_eq!(command.pod_sandbox_ids(), &["0123", "4567"])
+            }
+            command => bail!("unexpected command {:?}", command),
+        }
+        let c = Config::load_from(&["cri", "restore", "0123"])?;
+        match c.command() {
+            Some(Command::Restore(command)) => assert_eq!(command.pod_sandbox_ids(), &["0123"]),
+            command => bail!("unexpected command {:?}", command),
+        }
+        assert!(Config::load_from(&["cri", "restore"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn effective_toml_success() -> Result<()> {
+        let file = config_file(r#"allowed-devices = ["/dev/fuse", "/dev/kvm"]"#)?;
+        let path = file.path().display().to_string();
+        let content =
+            Config::effective_toml(&["cri", "--config", path.as_str(), "--log-level", "warn"])?;
+        let table: Table = toml::from_str(&content)?;
+
+        assert_eq!(table["log-level"].as_str(), Some("warn"));
+        assert_eq!(table["stop-timeout"].as_integer(), Some(30));
+        assert_eq!(
+            table["allowed-devices"],
+            Value::Array(vec!["/dev/fuse".into(), "/dev/kvm".into()])
+        );
+        assert!(!table.contains_key("config"));
+        assert!(!table.contains_key("listen"));
+
+        // The effective configuration is a valid configuration file
+        let file = config_file(&content)?;
+        let path = file.path().display().to_string();
+        let c = Config::load_from(&["cri", "--config", path.as_str()])?;
+        assert_eq!(c.log_level(), LevelFilter::Warn);
+        assert_eq!(c.allowed_devices(), &["/dev/fuse", "/dev/kvm"]);
+        Ok(())
+    }
 }
@@ -0,0 +1,255 @@
+//! Core dump handling of containers.
+//!
+//! The kernel writes core dumps according to the global `core_pattern`. Absolute patterns are
+//! resolved within the mount namespace of the crashing process, which allows redirecting the dumps
+//! into a host directory by bind mounting it to the directory of the pattern. Piped patterns are
+//! handled by a helper on the host, so only the size limit applies to them.
+
+use crate::{
+    annotations::parse_size,
+    oci_spec::runtime::{Mount, MountBuilder, POSIXRlimit, POSIXRlimitBuilder},
+};
+use anyhow::{bail, format_err, Context, Result};
+use log::{debug, warn};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The annotation which can be used to disable core dumps (`disabled`) or to lower their size
+/// limit (like `512Mi`) for a container.
+pub const CORE_DUMP_ANNOTATION: &str = "io.kubernetes.cri.core-dump";
+
+/// The path to the kernel core pattern.
+pub const CORE_PATTERN_PATH: &str = "/proc/sys/kernel/core_pattern";
+
+#[derive(Clone, Debug, PartialEq)]
+/// The core dump policy of a single container.
+pub enum CoreDumpPolicy {
+    /// The container is not allowed to write core dumps.
+    Disabled,
+
+    /// Core dumps up to `size` bytes are written to the host directory `dir`.
+    Limited {
+        /// The maximum size of a single core dump.
+        size: u64,
+
+        /// The host directory which receives the core dumps of the container.
+        dir: PathBuf,
+    },
+}
+
+impl CoreDumpPolicy {
+    /// Retrieve the policy for a container. Core dumps are disabled if the server has no
+    /// `core_dump_path` configured. Otherwise every container gets its own directory below it,
+    /// which is named after the namespace, pod and container names.
+    pub fn new(
+        core_dump_path: Option<&Path>,
+        core_dump_size: u64,
+        annotations: &HashMap<String, String>,
+        names: [&str; 3],
+    ) -> Result<Self> {
+        let path = match core_dump_path {
+            Some(path) if core_dump_size > 0 => path,
+            _ => return Ok(CoreDumpPolicy::Disabled),
+        };
+
+        let size = match annotations.get(CORE_DUMP_ANNOTATION).map(|x| x.trim()) {
+            Some("disabled") => return Ok(CoreDumpPolicy::Disabled),
+            Some(value) => parse_size(value)
+                .with_context(|| format!("parse annotation {}", CORE_DUMP_ANNOTATION))?
+                .min(core_dump_size),
+            None => core_dump_size,
+        };
+        if size == 0 {
+            return Ok(CoreDumpPolicy::Disabled);
+        }
+
+        if names.iter().any(|x| x.is_empty() || x.contains('/')) {
+            bail!("invalid core dump directory name {}", names.join("_"))
+        }
+        Ok(CoreDumpPolicy::Limited {
+            size,
+            dir: path.join(names.join("_")),
+        })
+    }
+
+    /// Retrieve the `RLIMIT_CORE` of the container process.
+    pub fn rlimit(&self) -> Result<POSIXRlimit> {
+        let size = match self {
+            CoreDumpPolicy::Disabled => 0,
+            CoreDumpPolicy::Limited { size, .. } => *size,
+        };
+        POSIXRlimitBuilder::default()
+            .typ("RLIMIT_CORE")
+            .hard(size)
+            .soft(size)
+            .build()
+            .map_err(|e| format_err!("build core rlimit: {}", e))
+    }
+
+    /// Retrieve the mount which redirects core dumps into the host directory for the provided
+    /// kernel `core_pattern`. The host directory gets created if necessary. Returns `None` if the
+    /// dumps cannot be redirected.
+    pub fn mount(&self, core_pattern: &str) -> Result<Option<Mount>> {
+        let dir = match self {
+            CoreDumpPolicy::Disabled => return Ok(None),
+            CoreDumpPolicy::Limited { dir, .. } => dir,
+        };
+
+        let core_pattern = core_pattern.trim();
+        if core_pattern.starts_with('|') {
+            debug!("Core dumps are handled by the host via {}", core_pattern);
+            return Ok(None);
+        }
+        let destination = match Path::new(core_pattern).parent() {
+            Some(parent) if Path::new(core_pattern).is_absolute() => parent,
+            _ => {
+                warn!(
+                    "Core dumps are written into the working directory of the container, \
+                     because the core pattern {} is not absolute",
+                    core_pattern
+                );
+                return Ok(None);
+            }
+        };
+
+        fs::create_dir_all(dir)
+            .with_context(|| format!("create core dump directory {}", dir.display()))?;
+        MountBuilder::default()
+            .destination(destination)
+            .typ("bind")
+            .source(dir.clone())
+            .options(vec![
+                "rbind".into(),
+                "rw".into(),
+                "nosuid".into(),
+                "nodev".into(),
+                "noexec".into(),
+            ])
+            .build()
+            .map(Some)
+            .map_err(|e| format_err!("build core dump mount: {}", e))
+    }
+}
+
+/// Read the kernel core pattern from the file at `path`.
+pub fn core_pattern(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .map(|x| x.trim().to_string())
+        .with_context(|| format!("read core pattern {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::{tempdir, NamedTempFile};
+
+    const NAMES: [&str; 3] = ["default", "pod", "container"];
+
+    fn annotation(value: &str) -> HashMap<String, String> {
+        let mut annotations = HashMap::new();
+        annotations.insert(CORE_DUMP_ANNOTATION.into(), value.into());
+        annotations
+    }
+
+    #[test]
+    fn new_success() -> Result<()> {
+        let path = Path::new("/var/lib/cores");
+        let none = HashMap::new();
+
+        assert_eq!(
+            CoreDumpPolicy::new(None, 1024, &none, NAMES)?,
+            CoreDumpPolicy::Disabled
+        );
+        assert_eq!(
+            CoreDumpPolicy::new(Some(path), 0, &none, NAMES)?,
+            CoreDumpPolicy::Disabled
+        );
+        assert_eq!(
+            CoreDumpPolicy::new(Some(path), 1024, &none, NAMES)?,
+            CoreDumpPolicy::Limited {
+                size: 1024,
+                dir: path.join("default_pod_container"),
+            }
+        );
+        assert_eq!(
+            CoreDumpPolicy::new(Some(path), 1 << 30, &annotation("1Mi"), NAMES)?,
+            CoreDumpPolicy::Limited {
+                size: 1 << 20,
+                dir: path.join("default_pod_container"),
+            }
+        );
+        assert_eq!(
+            CoreDumpPolicy::new(Some(path), 1024, &annotation("1Gi"), NAMES)?,
+            CoreDumpPolicy::Limited {
+                size: 1024,
+                dir: path.join("default_pod_container"),
+            }
+        );
+        assert_eq!(
+            CoreDumpPolicy::new(Some(path), 1024, &annotation("disabled"), NAMES)?,
+            CoreDumpPolicy::Disabled
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn new_failure() {
+        let path = Some(Path::new("/var/lib/cores"));
+        let none = HashMap::new();
+        assert!(CoreDumpPolicy::new(path, 1024, &annotation("invalid"), NAMES).is_err());
+        assert!(
+            CoreDumpPolicy::new(path, 1024, &none, ["default", "../pod", "container"]).is_err()
+        );
+        assert!(CoreDumpPolicy::new(path, 1024, &none, ["", "pod", "container"]).is_err());
+    }
+
+    #[test]
+    fn rlimit_success() -> Result<()> {
+        let rlimit = CoreDumpPolicy::Disabled.rlimit()?;
+        assert_eq!(rlimit.typ(), "RLIMIT_CORE");
+        assert_eq!(rlimit.hard(), 0);
+
+        let policy = CoreDumpPolicy::Limited {
+            size: 1024,
+            dir: PathBuf::new(),
+        };
+        assert_eq!(policy.rlimit()?.soft(), 1024);
+        Ok(())
+    }
+
+    #[test]
+    fn mount_success() -> Result<()> {
+        let dir = tempdir()?;
+        let policy = CoreDumpPolicy::Limited {
+            size: 1024,
+            dir: dir.path().join("default_pod_container"),
+        };
+
+        let mount = policy.mount("/var/crash/core.%e.%p")?.context("no mount")?;
+        assert_eq!(mount.destination(), Path::new("/var/crash"));
+        assert_eq!(
+            mount.source().as_deref(),
+            Some(dir.path().join("default_pod_container").as_path())
+        );
+        assert!(dir.path().join("default_pod_container").is_dir());
+
+        assert!(policy
+            .mount("|/usr/lib/systemd/systemd-coredump %P")?
+            .is_none());
+        assert!(policy.mount("core")?.is_none());
+        assert!(CoreDumpPolicy::Disabled.mount("/var/crash/core")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn core_pattern_success() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        fs::write(file.path(), "core\n")?;
+        assert_eq!(core_pattern(file.path())?, "core");
+        assert!(core_pattern(Path::new("/some/invalid/path")).is_err());
+        Ok(())
+    }
+}
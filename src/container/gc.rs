@@ -0,0 +1,173 @@
+//! Garbage collection of exited containers.
+//!
+//! Exited containers are retained per pod sandbox up to a maximum count and a maximum age since
+//! they finished, independent of the garbage collection settings of the kubelet. The most recently
+//! finished attempt of every container name is always retained, because the kubelet derives the
+//! restart count of a container from it.
+
+use crate::container::{Container, ContainerState};
+use getset::CopyGetters;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use strum::AsRefStr;
+
+#[derive(Clone, Copy, CopyGetters, Debug, PartialEq)]
+/// RetentionPolicy defines how long exited containers are retained.
+pub struct RetentionPolicy {
+    #[get_copy = "pub"]
+    /// The maximum number of exited containers per pod sandbox, whereas `0` retains all of them.
+    max_per_pod: usize,
+
+    #[get_copy = "pub"]
+    /// The maximum time exited containers are retained, whereas zero retains them forever.
+    max_age: Duration,
+}
+
+#[derive(AsRefStr, Clone, Copy, Debug, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+/// Reason is the reason why an exited container gets collected.
+pub enum Reason {
+    /// The pod sandbox has more exited containers than the maximum per pod.
+    MaxPerPod,
+
+    /// The container finished longer ago than the maximum age.
+    MaxAge,
+}
+
+impl RetentionPolicy {
+    /// Create a new policy from the `max_per_pod` and the `max_age`.
+    pub fn new(max_per_pod: usize, max_age: Duration) -> Self {
+        Self {
+            max_per_pod,
+            max_age,
+        }
+    }
+
+    /// Check if the policy retains all exited containers.
+    pub fn is_disabled(&self) -> bool {
+        self.max_per_pod == 0 && self.max_age == Duration::from_secs(0)
+    }
+
+    /// Select the exited `containers` to collect at `now` in nanoseconds since the Unix epoch,
+    /// together with the reason of their collection.
+    pub fn select<'a>(
+        &self,
+        containers: &'a [Container],
+        now: i64,
+    ) -> Vec<(&'a Container, Reason)> {
+        let mut pods: HashMap<&str, Vec<&Container>> = HashMap::new();
+        for container in containers
+            .iter()
+            .filter(|x| x.state() == ContainerState::Exited)
+        {
+            pods.entry(container.pod_sandbox_id())
+                .or_default()
+                .push(container);
+        }
+
+        let max_age = self.max_age.as_nanos() as i64;
+        let mut selected = vec![];
+        for (_, mut exited) in pods {
+            exited.sort_by_key(|x| -x.finished_at());
+            let (mut names, mut retained) = (HashSet::new(), 0);
+            for container in exited {
+                let latest = names.insert(container.name());
+                let reason = if self.max_per_pod != 0 && retained >= self.max_per_pod {
+                    Some(Reason::MaxPerPod)
+                } else if max_age != 0 && now.saturating_sub(container.finished_at()) > max_age {
+                    Some(Reason::MaxAge)
+                } else {
+                    None
+                };
+                match reason {
+                    Some(reason) if !latest => selected.push((container, reason)),
+                    _ => retained += 1,
+                }
+            }
+        }
+        selected.sort_by_key(|(x, _)| x.finished_at());
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::ContainerBuilder;
+    use anyhow::{format_err, Result};
+
+    fn new_exited_container(
+        pod: &str,
+        name: &str,
+        attempt: u32,
+        finished_at: i64,
+    ) -> Result<Container> {
+        let mut container = ContainerBuilder::default()
+            .id(Container::new_id(pod, name, attempt))
+            .pod_sandbox_id(pod)
+            .name(name)
+            .attempt(attempt)
+            .bundle("/bundle")
+            .build()
+            .map_err(|e| format_err!("build container: {}", e))?;
+        container.set_exited(finished_at);
+        Ok(container)
+    }
+
+    #[test]
+    fn select_success_max_per_pod() -> Result<()> {
+        let containers = vec![
+            new_exited_container("pod", "app", 0, 10)?,
+            new_exited_container("pod", "app", 1, 20)?,
+            new_exited_container("pod", "app", 2, 30)?,
+            new_exited_container("pod", "init", 0, 5)?,
+            new_exited_container("other", "app", 0, 1)?,
+        ];
+
+        let sut = RetentionPolicy::new(1, Duration::from_secs(0));
+        let selected: Vec<_> = sut
+            .select(&containers, 100)
+            .into_iter()
+            .map(|(x, reason)| (x.name().as_str(), x.attempt(), reason))
+            .collect();
+
+        // The latest attempt of every name is retained, even beyond the maximum
+        assert_eq!(
+            selected,
+            vec![("app", 0, Reason::MaxPerPod), ("app", 1, Reason::MaxPerPod)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn select_success_max_age() -> Result<()> {
+        let containers = vec![
+            new_exited_container("pod", "app", 0, 10)?,
+            new_exited_container("pod", "app", 1, 20)?,
+            new_exited_container("pod", "app", 2, 90)?,
+        ];
+
+        let sut = RetentionPolicy::new(0, Duration::from_nanos(50));
+        let selected: Vec<_> = sut
+            .select(&containers, 100)
+            .into_iter()
+            .map(|(x, reason)| (x.attempt(), reason))
+            .collect();
+        assert_eq!(selected, vec![(0, Reason::MaxAge), (1, Reason::MaxAge)]);
+        Ok(())
+    }
+
+    #[test]
+    fn select_skips_running() -> Result<()> {
+        let mut running = new_exited_container("pod", "app", 0, 0)?;
+        running.set_running(1);
+        let containers = vec![running, new_exited_container("pod", "app", 1, 1)?];
+
+        let sut = RetentionPolicy::new(1, Duration::from_secs(0));
+        assert!(sut.select(&containers, 100).is_empty());
+        assert!(RetentionPolicy::new(0, Duration::from_secs(0)).is_disabled());
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+//! Resolution of unique container ID prefixes
+
+use anyhow::{bail, Result};
+use std::{collections::BTreeSet, error, fmt, ops::Bound};
+
+#[derive(Clone, Debug, Default)]
+/// IdIndex contains all known container IDs and resolves unique prefixes to their full ID, like
+/// docker and crictl do.
+pub struct IdIndex {
+    /// All IDs of the index, sorted to allow efficient prefix lookups.
+    ids: BTreeSet<String>,
+}
+
+#[derive(Debug, PartialEq)]
+/// ResolveError is returned if a prefix does not resolve to a single ID.
+pub enum ResolveError {
+    /// The prefix is empty.
+    Empty,
+
+    /// No ID starts with the prefix.
+    NotFound(String),
+
+    /// The prefix, which matches at least the two IDs.
+    Ambiguous(String, String, String),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::Empty => write!(f, "empty ID prefix provided"),
+            ResolveError::NotFound(prefix) => write!(f, "no such container: {}", prefix),
+            ResolveError::Ambiguous(prefix, first, second) => write!(
+                f,
+                "ambiguous container ID prefix {}: matches {}, {} and possibly more",
+                prefix, first, second
+            ),
+        }
+    }
+}
+
+impl error::Error for ResolveError {}
+
+impl IdIndex {
+    /// Add a new `id` to the index. Returns false if the index already contained it.
+    pub fn add<S: Into<String>>(&mut self, id: S) -> Result<bool> {
+        let id = id.into();
+        if id.is_empty() {
+            bail!("empty ID provided")
+        }
+        Ok(self.ids.insert(id))
+    }
+
+    /// Remove the `id` from the index. Returns false if the index did not contain it.
+    pub fn remove(&mut self, id: &str) -> bool {
+        self.ids.remove(id)
+    }
+
+    /// Resolve the full ID for the provided `prefix`. Full IDs always resolve to themselves, even
+    /// if they are the prefix of another ID.
+    pub fn resolve(&self, prefix: &str) -> Result<&str, ResolveError> {
+        if prefix.is_empty() {
+            return Err(ResolveError::Empty);
+        }
+        if let Some(id) = self.ids.get(prefix) {
+            return Ok(id);
+        }
+
+        let mut matches = self
+            .ids
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|x| x.starts_with(prefix));
+        match (matches.next(), matches.next()) {
+            (Some(id), None) => Ok(id),
+            (None, _) => Err(ResolveError::NotFound(prefix.into())),
+            (Some(first), Some(second)) => Err(ResolveError::Ambiguous(
+                prefix.into(),
+                first.clone(),
+                second.clone(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_index() -> Result<IdIndex> {
+        let mut sut = IdIndex::default();
+        for id in &["abc123", "abd456", "abc1", "fff"] {
+            assert!(sut.add(*id)?);
+        }
+        Ok(sut)
+    }
+
+    #[test]
+    fn resolve_success() -> Result<()> {
+        let sut = new_index()?;
+        assert_eq!(sut.resolve("f")?, "fff");
+        assert_eq!(sut.resolve("abd")?, "abd456");
+        assert_eq!(sut.resolve("abc12")?, "abc123");
+
+        // Full IDs win over longer IDs with the same prefix
+        assert_eq!(sut.resolve("abc1")?, "abc1");
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_failure() -> Result<()> {
+        let sut = new_index()?;
+        assert_eq!(sut.resolve(""), Err(ResolveError::Empty));
+        assert_eq!(sut.resolve("x"), Err(ResolveError::NotFound("x".into())));
+        assert!(sut.resolve("abc1234").is_err());
+
+        let err = sut.resolve("ab").err().map(|e| e.to_string());
+        assert!(err.unwrap_or_default().contains("ambiguous"));
+        Ok(())
+    }
+
+    #[test]
+    fn add_remove() -> Result<()> {
+        let mut sut = new_index()?;
+        assert!(!sut.add("fff")?);
+        assert!(sut.add("").is_err());
+
+        assert!(sut.remove("abd456"));
+        assert!(!sut.remove("abd456"));
+        assert!(sut.resolve("abd").is_err());
+        Ok(())
+    }
+}
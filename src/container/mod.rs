@@ -0,0 +1,233 @@
+//! Basic container types
+
+pub mod core_dump;
+pub mod gc;
+pub mod id_index;
+pub mod process;
+pub mod stop;
+
+use crate::{
+    criapi,
+    criapi::ContainerMetadata,
+    id::stable_id,
+    storage::index::{self, Entry, Index},
+};
+use derive_builder::Builder;
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, path::PathBuf};
+
+/// The storage key prefix of all containers.
+const KEY_PREFIX: &str = "container/";
+
+/// The secondary index of all containers.
+pub const INDEX: Index = Index::new("container");
+
+/// The index field of the pod sandbox of the container.
+pub const POD_SANDBOX_FIELD: &str = "pod-sandbox";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+/// The lifecycle state of a container.
+pub enum ContainerState {
+    /// The container has been created by the OCI runtime, but not started yet.
+    Created,
+
+    /// The container process has been started.
+    Running,
+
+    /// The container process has been stopped.
+    Exited,
+}
+
+impl Default for ContainerState {
+    fn default() -> Self {
+        ContainerState::Created
+    }
+}
+
+impl From<ContainerState> for criapi::ContainerState {
+    fn from(state: ContainerState) -> Self {
+        match state {
+            ContainerState::Created => criapi::ContainerState::ContainerCreated,
+            ContainerState::Running => criapi::ContainerState::ContainerRunning,
+            ContainerState::Exited => criapi::ContainerState::ContainerExited,
+        }
+    }
+}
+
+#[derive(Builder, CopyGetters, Debug, Deserialize, Getters, Serialize)]
+#[builder(pattern = "owned", setter(into))]
+/// Container holds all data of a container which is required to manage its lifecycle.
+pub struct Container {
+    #[get = "pub"]
+    /// The unique identifier.
+    id: String,
+
+    #[get = "pub"]
+    /// The identifier of the pod sandbox the container belongs to.
+    pod_sandbox_id: String,
+
+    #[get = "pub"]
+    /// Name of the container, which is unique inside the pod sandbox.
+    name: String,
+
+    #[get_copy = "pub"]
+    /// Container creation attempt.
+    attempt: u32,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// The image reference as requested by the kubelet.
+    image: String,
+
+    #[get = "pub"]
+    /// The directory of the OCI bundle.
+    bundle: PathBuf,
+
+    #[get_copy = "pub"]
+    #[builder(default)]
+    /// Current lifecycle state.
+    state: ContainerState,
+
+    #[get_copy = "pub"]
+    #[builder(default)]
+    /// Creation time of the container in nanoseconds since the Unix epoch.
+    created_at: i64,
+
+    #[get_copy = "pub"]
+    #[builder(default)]
+    /// Start time of the container in nanoseconds since the Unix epoch.
+    started_at: i64,
+
+    #[get_copy = "pub"]
+    #[builder(default)]
+    /// Stop time of the container in nanoseconds since the Unix epoch.
+    finished_at: i64,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Labels of the container, which can be used to select it.
+    labels: HashMap<String, String>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Unstructured key value data of the container.
+    annotations: HashMap<String, String>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Path of the container log file relative to the log directory of the pod sandbox.
+    log_path: String,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Degradations of the last resource update, which the kernel did not fully apply.
+    resource_degradations: Vec<String>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// The `StopSignal` of the image config, if the image defines one.
+    image_stop_signal: Option<String>,
+}
+
+impl Container {
+    /// Generate the container identifier for the container `name` and its `attempt` inside the
+    /// pod sandbox `pod_sandbox_id`.
+    pub fn new_id(pod_sandbox_id: &str, name: &str, attempt: u32) -> String {
+        stable_id(&[pod_sandbox_id, name, &attempt.to_string()])
+    }
+
+    /// Retrieve the storage key for the container `id`.
+    pub fn key(id: &str) -> String {
+        format!("{}{}", KEY_PREFIX, id)
+    }
+
+    /// Retrieve the storage key prefix of all containers.
+    pub fn key_prefix() -> &'static str {
+        KEY_PREFIX
+    }
+
+    /// Retrieve the entries of the container in the secondary `INDEX`.
+    pub fn index_entries(&self) -> Vec<Entry> {
+        let mut entries = vec![(POD_SANDBOX_FIELD, self.pod_sandbox_id.clone())];
+        entries.extend(index::label_entries(&self.labels));
+        entries
+    }
+
+    /// Retrieve the CRI metadata of the container.
+    pub fn metadata(&self) -> ContainerMetadata {
+        ContainerMetadata {
+            name: self.name.clone(),
+            attempt: self.attempt,
+        }
+    }
+
+    /// Mark the container as running since `started_at`.
+    pub fn set_running(&mut self, started_at: i64) {
+        self.state = ContainerState::Running;
+        self.started_at = started_at;
+    }
+
+    /// Record the `degradations` of the last resource update.
+    pub fn set_resource_degradations(&mut self, degradations: Vec<String>) {
+        self.resource_degradations = degradations;
+    }
+
+    /// Mark the container as exited since `finished_at`.
+    pub fn set_exited(&mut self, finished_at: i64) {
+        self.state = ContainerState::Exited;
+        self.finished_at = finished_at;
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{format_err, Result};
+
+    #[test]
+    fn new_id() {
+        let id = Container::new_id("sandbox", "name", 0);
+        assert_eq!(id, Container::new_id("sandbox", "name", 0));
+        assert_ne!(id, Container::new_id("sandbox", "name", 1));
+        assert_ne!(id, Container::new_id("other", "name", 0));
+    }
+
+    #[test]
+    fn key() {
+        let key = Container::key("id");
+        assert!(key.starts_with(KEY_PREFIX));
+        assert!(key.ends_with("id"));
+    }
+
+    #[test]
+    fn lifecycle() -> Result<()> {
+        let mut container = ContainerBuilder::default()
+            .id("id")
+            .pod_sandbox_id("sandbox")
+            .name("name")
+            .attempt(1u32)
+            .bundle("/bundle")
+            .created_at(1)
+            .build()
+            .map_err(|e| format_err!("build container: {}", e))?;
+        assert_eq!(container.state(), ContainerState::Created);
+        assert_eq!(container.metadata().attempt, 1);
+        assert!(container.to_string().contains("name"));
+
+        container.set_running(2);
+        assert_eq!(container.state(), ContainerState::Running);
+        assert_eq!(container.started_at(), 2);
+
+        container.set_exited(3);
+        assert_eq!(container.state(), ContainerState::Exited);
+        assert_eq!(container.finished_at(), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+//! Resolution of the container process from the image and the CRI container config.
+
+use crate::{criapi::ContainerConfig, oci_spec::image::ImageConfig};
+use anyhow::{bail, Context, Result};
+use getset::Getters;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The working directory used if neither the image nor the container config specify one.
+const DEFAULT_WORKING_DIR: &str = "/";
+
+#[derive(Debug, Getters, PartialEq)]
+/// The process of a container as merged from its image config and its CRI container config.
+pub struct ContainerProcess {
+    #[get = "pub"]
+    /// The full command line, including the executable as first element.
+    args: Vec<String>,
+
+    #[get = "pub"]
+    /// The environment in the `KEY=VALUE` format.
+    env: Vec<String>,
+
+    #[get = "pub"]
+    /// The absolute working directory inside the container.
+    cwd: String,
+
+    #[get = "pub"]
+    /// The user in the `user[:group]` format, where an empty string refers to the default user of
+    /// the image.
+    user: String,
+}
+
+impl ContainerProcess {
+    /// Merge the container `config` with the optional `image` config. The precedence rules are
+    /// the same as for Docker and other CRI runtimes:
+    ///
+    /// - if the container config specifies a `command`, then it replaces the image
+    ///   `Entrypoint` and the image `Cmd` gets ignored
+    /// - if the container config specifies `args`, then they replace the image `Cmd`
+    /// - environment variables of the container config override the ones of the image
+    /// - the `working_dir` and the user of the container config replace the ones of the image
+    pub fn merge(config: &ContainerConfig, image: Option<&ImageConfig>) -> Result<Self> {
+        let image_list = |f: fn(&ImageConfig) -> &Option<Vec<String>>| {
+            image.and_then(|x| f(x).clone()).unwrap_or_default()
+        };
+
+        let mut args = if !config.command.is_empty() {
+            config.command.clone()
+        } else {
+            image_list(ImageConfig::entrypoint)
+        };
+        if !config.args.is_empty() {
+            args.extend(config.args.iter().cloned());
+        } else if config.command.is_empty() {
+            args.extend(image_list(ImageConfig::cmd));
+        }
+        if args.is_empty() {
+            bail!("no command specified in container config or image")
+        }
+
+        let mut env = image_list(ImageConfig::env);
+        for kv in &config.envs {
+            let entry = format!("{}={}", kv.key, kv.value);
+            let prefix = format!("{}=", kv.key);
+            match env.iter_mut().find(|x| x.starts_with(&prefix)) {
+                Some(existing) => *existing = entry,
+                None => env.push(entry),
+            }
+        }
+
+        let cwd = Some(config.working_dir.clone())
+            .filter(|x| !x.is_empty())
+            .or_else(|| image.and_then(|x| x.working_dir().clone()))
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| DEFAULT_WORKING_DIR.into());
+        if !Path::new(&cwd).is_absolute() {
+            bail!("working directory {} is not absolute", cwd)
+        }
+
+        Ok(Self {
+            args,
+            env,
+            cwd,
+            user: Self::user(config, image)?,
+        })
+    }
+
+    /// Retrieve the user from the security context of the container config, whereas the image
+    /// `User` applies if none is set.
+    fn user(config: &ContainerConfig, image: Option<&ImageConfig>) -> Result<String> {
+        let security_context = config
+            .linux
+            .as_ref()
+            .and_then(|x| x.security_context.as_ref());
+
+        let (user, group) = match security_context {
+            Some(context) => {
+                if context.run_as_user.is_some() && !context.run_as_username.is_empty() {
+                    bail!("only one of run_as_user and run_as_username can be specified")
+                }
+                let user = context
+                    .run_as_user
+                    .as_ref()
+                    .map(|x| x.value.to_string())
+                    .or_else(|| Some(context.run_as_username.clone()).filter(|x| !x.is_empty()));
+                let group = context.run_as_group.as_ref().map(|x| x.value);
+                (user, group)
+            }
+            None => (None, None),
+        };
+
+        match (user, group) {
+            (Some(user), Some(group)) => Ok(format!("{}:{}", user, group)),
+            (Some(user), None) => Ok(user),
+            (None, Some(_)) => {
+                bail!("run_as_group specified without run_as_user or run_as_username")
+            }
+            (None, None) => Ok(image.and_then(|x| x.user().clone()).unwrap_or_default()),
+        }
+    }
+
+    /// Create the working directory below the provided `rootfs` if it does not exist yet, which
+    /// matches the behavior of other runtimes for images without a pre-created `WorkingDir`.
+    pub fn ensure_working_dir(&self, rootfs: &Path) -> Result<PathBuf> {
+        let path = rootfs.join(self.cwd.trim_start_matches('/'));
+        fs::create_dir_all(&path)
+            .with_context(|| format!("create working directory {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        criapi::{Int64Value, KeyValue, LinuxContainerConfig, LinuxContainerSecurityContext},
+        oci_spec::image::ImageConfigBuilder,
+    };
+    use tempfile::tempdir;
+
+    fn strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn image() -> Result<ImageConfig> {
+        Ok(ImageConfigBuilder::default()
+            .entrypoint(strings(&["/entrypoint.sh"]))
+            .cmd(strings(&["serve", "--port=80"]))
+            .env(strings(&["PATH=/usr/bin", "MODE=prod"]))
+            .working_dir("/app")
+            .user("nobody")
+            .build()?)
+    }
+
+    fn security_context(context: LinuxContainerSecurityContext) -> Option<LinuxContainerConfig> {
+        Some(LinuxContainerConfig {
+            security_context: Some(context),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn merge_image_only() -> Result<()> {
+        let image = image()?;
+        let process = ContainerProcess::merge(&ContainerConfig::default(), Some(&image))?;
+
+        assert_eq!(
+            process.args(),
+            &strings(&["/entrypoint.sh", "serve", "--port=80"])
+        );
+        assert_eq!(process.env(), &strings(&["PATH=/usr/bin", "MODE=prod"]));
+        assert_eq!(process.cwd(), "/app");
+        assert_eq!(process.user(), "nobody");
+        Ok(())
+    }
+
+    #[test]
+    fn merge_args_replace_cmd() -> Result<()> {
+        let image = image()?;
+        let config = ContainerConfig {
+            args: strings(&["debug"]),
+            ..Default::default()
+        };
+        let process = ContainerProcess::merge(&config, Some(&image))?;
+
+        assert_eq!(process.args(), &strings(&["/entrypoint.sh", "debug"]));
+        Ok(())
+    }
+
+    #[test]
+    fn merge_command_replaces_entrypoint_and_cmd() -> Result<()> {
+        let image = image()?;
+        let config = ContainerConfig {
+            command: strings(&["/bin/sh"]),
+            ..Default::default()
+        };
+        let process = ContainerProcess::merge(&config, Some(&image))?;
+        assert_eq!(process.args(), &strings(&["/bin/sh"]));
+
+        let config = ContainerConfig {
+            command: strings(&["/bin/sh"]),
+            args: strings(&["-c", "true"]),
+            ..Default::default()
+        };
+        let process = ContainerProcess::merge(&config, Some(&image))?;
+        assert_eq!(process.args(), &strings(&["/bin/sh", "-c", "true"]));
+        Ok(())
+    }
+
+    #[test]
+    fn merge_no_command() -> Result<()> {
+        let config = ContainerConfig {
+            working_dir: "/".into(),
+            ..Default::default()
+        };
+        assert!(ContainerProcess::merge(&config, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_env_and_working_dir() -> Result<()> {
+        let image = image()?;
+        let config = ContainerConfig {
+            working_dir: "/data".into(),
+            envs: vec![
+                KeyValue {
+                    key: "MODE".into(),
+                    value: "dev".into(),
+                },
+                KeyValue {
+                    key: "DEBUG".into(),
+                    value: "1".into(),
+                },
+            ],
+            ..Default::default()
+        };
+        let process = ContainerProcess::merge(&config, Some(&image))?;
+
+        assert_eq!(
+            process.env(),
+            &strings(&["PATH=/usr/bin", "MODE=dev", "DEBUG=1"])
+        );
+        assert_eq!(process.cwd(), "/data");
+        Ok(())
+    }
+
+    #[test]
+    fn merge_default_and_relative_working_dir() -> Result<()> {
+        let config = ContainerConfig {
+            command: strings(&["/bin/sh"]),
+            ..Default::default()
+        };
+        assert_eq!(ContainerProcess::merge(&config, None)?.cwd(), "/");
+
+        let config = ContainerConfig {
+            command: strings(&["/bin/sh"]),
+            working_dir: "relative".into(),
+            ..Default::default()
+        };
+        assert!(ContainerProcess::merge(&config, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_user() -> Result<()> {
+        let image = image()?;
+        let config = ContainerConfig {
+            linux: security_context(LinuxContainerSecurityContext {
+                run_as_user: Some(Int64Value { value: 1000 }),
+                run_as_group: Some(Int64Value { value: 2000 }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            ContainerProcess::merge(&config, Some(&image))?.user(),
+            "1000:2000"
+        );
+
+        let config = ContainerConfig {
+            linux: security_context(LinuxContainerSecurityContext {
+                run_as_username: "www-data".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            ContainerProcess::merge(&config, Some(&image))?.user(),
+            "www-data"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn merge_user_invalid() -> Result<()> {
+        let image = image()?;
+        let config = ContainerConfig {
+            linux: security_context(LinuxContainerSecurityContext {
+                run_as_group: Some(Int64Value { value: 2000 }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(ContainerProcess::merge(&config, Some(&image)).is_err());
+
+        let config = ContainerConfig {
+            linux: security_context(LinuxContainerSecurityContext {
+                run_as_user: Some(Int64Value { value: 1000 }),
+                run_as_username: "www-data".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(ContainerProcess::merge(&config, Some(&image)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ensure_working_dir() -> Result<()> {
+        let rootfs = tempdir()?;
+        let image = image()?;
+        let process = ContainerProcess::merge(&ContainerConfig::default(), Some(&image))?;
+
+        let path = process.ensure_working_dir(rootfs.path())?;
+        assert_eq!(path, rootfs.path().join("app"));
+        assert!(path.is_dir());
+
+        // Existing directories are fine, too
+        process.ensure_working_dir(rootfs.path())?;
+        Ok(())
+    }
+}
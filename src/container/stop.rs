@@ -0,0 +1,120 @@
+//! Helpers for stopping containers gracefully.
+
+use anyhow::{bail, Result};
+use nix::sys::signal::Signal;
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+/// The annotation which can be used to override the stop signal of a container.
+pub const STOP_SIGNAL_ANNOTATION: &str = "io.kubernetes.cri.stop-signal";
+
+/// Retrieve the signal used for stopping the container. The annotation has precedence over the
+/// `StopSignal` of the image config, whereas `SIGTERM` is being used if none of them is set.
+pub fn stop_signal(
+    annotations: &HashMap<String, String>,
+    image_stop_signal: Option<&str>,
+) -> Result<Signal> {
+    match annotations
+        .get(STOP_SIGNAL_ANNOTATION)
+        .map(String::as_str)
+        .or(image_stop_signal)
+        .filter(|x| !x.is_empty())
+    {
+        Some(signal) => parse_signal(signal),
+        None => Ok(Signal::SIGTERM),
+    }
+}
+
+/// Retrieve the grace period for stopping the container. The provided `default` applies if the
+/// kubelet does not request a positive timeout in seconds.
+pub fn stop_timeout(timeout: i64, default: Duration) -> Duration {
+    if timeout > 0 {
+        Duration::from_secs(timeout as u64)
+    } else {
+        default
+    }
+}
+
+/// Parse a signal from its name (`SIGTERM` or `TERM`, case insensitive) or its number (`15`).
+fn parse_signal(signal: &str) -> Result<Signal> {
+    if let Ok(number) = signal.parse::<i32>() {
+        return match Signal::iterator().find(|x| *x as i32 == number) {
+            Some(signal) => Ok(signal),
+            None => bail!("invalid signal number {}", number),
+        };
+    }
+
+    let name = signal.to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    match Signal::from_str(&name) {
+        Ok(signal) => Ok(signal),
+        Err(_) => bail!("invalid signal name {}", signal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_signal_default() -> Result<()> {
+        assert_eq!(stop_signal(&HashMap::new(), None)?, Signal::SIGTERM);
+        assert_eq!(stop_signal(&HashMap::new(), Some(""))?, Signal::SIGTERM);
+        Ok(())
+    }
+
+    #[test]
+    fn stop_signal_image() -> Result<()> {
+        assert_eq!(
+            stop_signal(&HashMap::new(), Some("SIGQUIT"))?,
+            Signal::SIGQUIT
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stop_signal_annotation() -> Result<()> {
+        let mut annotations = HashMap::new();
+        annotations.insert(STOP_SIGNAL_ANNOTATION.into(), "int".into());
+        assert_eq!(stop_signal(&annotations, Some("SIGQUIT"))?, Signal::SIGINT);
+        Ok(())
+    }
+
+    #[test]
+    fn stop_signal_fail_invalid() {
+        let mut annotations = HashMap::new();
+        annotations.insert(STOP_SIGNAL_ANNOTATION.into(), "SIGWRONG".into());
+        assert!(stop_signal(&annotations, None).is_err());
+    }
+
+    #[test]
+    fn parse_signal_success() -> Result<()> {
+        assert_eq!(parse_signal("SIGKILL")?, Signal::SIGKILL);
+        assert_eq!(parse_signal("usr1")?, Signal::SIGUSR1);
+        assert_eq!(parse_signal("15")?, Signal::SIGTERM);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_signal_failure() {
+        assert!(parse_signal("0").is_err());
+        assert!(parse_signal("1000").is_err());
+        assert!(parse_signal("SIG").is_err());
+    }
+
+    #[test]
+    fn stop_timeout_requested() {
+        let default = Duration::from_secs(30);
+        assert_eq!(stop_timeout(10, default), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn stop_timeout_default() {
+        let default = Duration::from_secs(30);
+        assert_eq!(stop_timeout(0, default), default);
+        assert_eq!(stop_timeout(-1, default), default);
+    }
+}
@@ -0,0 +1,139 @@
+//! Attaching to the standard streams of running containers.
+//!
+//! The output of a container is read by its log writer anyway, which fans it out to all attached
+//! sessions as well. Sessions lagging behind miss output instead of slowing down the container.
+//! The input of a container is shared by all sessions requesting it, whereas the last write
+//! wins, unless a session holds it exclusively.
+
+use crate::container_log::{format::Stream, pipe::AsyncPipe};
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{broadcast, Mutex},
+};
+
+/// The number of output chunks a session can lag behind before it misses output.
+pub const OUTPUT_CAPACITY: usize = 64;
+
+/// A chunk of the output of a container together with the stream it has been read from.
+pub type Output = (Stream, Vec<u8>);
+
+#[derive(Clone)]
+/// Stdin is the standard input of a container, which attached sessions write into.
+pub struct Stdin {
+    /// The write end of the input pipe, which is `None` once closed.
+    pipe: Arc<Mutex<Option<AsyncPipe>>>,
+
+    /// Whether the input gets closed once the first session writing it detaches.
+    once: bool,
+
+    /// Whether a session holds the input exclusively.
+    held: Arc<AtomicBool>,
+}
+
+impl Stdin {
+    /// Create a new input from the write end `file` of the input pipe of a container, which gets
+    /// closed after the first session if `once` is set.
+    pub fn new(file: File, once: bool) -> Result<Self> {
+        Ok(Self {
+            pipe: Arc::new(Mutex::new(Some(
+                AsyncPipe::new(file).context("open container input")?,
+            ))),
+            once,
+            held: Arc::default(),
+        })
+    }
+
+    /// Check if the input gets closed once the first session writing it detaches.
+    pub fn once(&self) -> bool {
+        self.once
+    }
+
+    /// Hold the input exclusively until the returned hold is dropped. Returns `None` if another
+    /// session holds it already.
+    pub fn hold(&self) -> Option<StdinHold> {
+        if self.held.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        Some(StdinHold {
+            held: self.held.clone(),
+        })
+    }
+
+    /// Write the `data` into the input. Returns `false` if the input is closed.
+    pub async fn write(&self, data: &[u8]) -> Result<bool> {
+        let mut pipe = self.pipe.lock().await;
+        match pipe.as_mut() {
+            Some(input) => {
+                input
+                    .write_all(data)
+                    .await
+                    .context("write container input")?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Close the input, which signals EOF to the container.
+    pub async fn close(&self) {
+        self.pipe.lock().await.take();
+    }
+}
+
+/// StdinHold marks the input of a container as held exclusively until it gets dropped.
+pub struct StdinHold {
+    /// The exclusive flag of the input.
+    held: Arc<AtomicBool>,
+}
+
+impl Drop for StdinHold {
+    fn drop(&mut self) {
+        self.held.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Attachment are the standard streams of a container for a single attached session.
+pub struct Attachment {
+    /// The receiver of the output of the container, which gets closed once the container exits.
+    pub output: broadcast::Receiver<Output>,
+
+    /// The input of the container, which is `None` if the container does not read one.
+    pub stdin: Option<Stdin>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container_log::pipe::pipe;
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn stdin_success() -> Result<()> {
+        let (mut read, write) = pipe()?;
+        let sut = Stdin::new(write, true)?;
+        assert!(sut.once());
+
+        let hold = sut.hold();
+        assert!(hold.is_some());
+        assert!(sut.clone().hold().is_none());
+        drop(hold);
+        assert!(sut.hold().is_some());
+
+        assert!(sut.write(b"input").await?);
+        sut.close().await;
+        assert!(!sut.write(b"closed").await?);
+
+        let mut buf = String::new();
+        read.read_to_string(&mut buf)?;
+        assert_eq!(buf, "input");
+        Ok(())
+    }
+}
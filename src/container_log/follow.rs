@@ -0,0 +1,215 @@
+//! Following of container log files, similar to `tail -F`.
+
+use anyhow::{Context, Result};
+use std::{
+    io::{ErrorKind, SeekFrom},
+    mem,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+    time::delay_for,
+};
+
+/// The interval for checking a log file for new content.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// LogFollower reads a log file line by line and waits for new content once it reaches its end.
+/// The follower is aware of log rotation, which means that it reopens the file if it got replaced
+/// (for example by the kubelet) or truncated.
+pub struct LogFollower {
+    /// The path to the followed log file.
+    path: PathBuf,
+
+    /// The reader of the currently opened file.
+    reader: BufReader<File>,
+
+    /// The inode of the currently opened file.
+    inode: u64,
+
+    /// The number of bytes already read from the currently opened file.
+    position: u64,
+
+    /// Content read from the file which is not terminated by a newline yet.
+    buffer: String,
+}
+
+impl LogFollower {
+    /// Start following the log file at the provided `path` from its beginning.
+    pub async fn open(path: &Path) -> Result<Self> {
+        Self::open_from(path, SeekFrom::Start(0)).await
+    }
+
+    /// Start following the log file at the provided `path` from its current end, which means that
+    /// only the lines written afterwards get retrieved.
+    pub async fn open_end(path: &Path) -> Result<Self> {
+        Self::open_from(path, SeekFrom::End(0)).await
+    }
+
+    /// Start following the log file at the provided `path` from the position `from`.
+    async fn open_from(path: &Path, from: SeekFrom) -> Result<Self> {
+        let (reader, inode, position) = Self::open_reader(path, from).await?;
+        Ok(Self {
+            path: path.into(),
+            reader,
+            inode,
+            position,
+            buffer: String::new(),
+        })
+    }
+
+    /// Retrieve the next line of the log file without its trailing newline. This method waits
+    /// until a full line is available.
+    pub async fn next_line(&mut self) -> Result<String> {
+        loop {
+            let read = self
+                .reader
+                .read_line(&mut self.buffer)
+                .await
+                .with_context(|| format!("read log file {}", self.path.display()))?;
+            self.position += read as u64;
+
+            if self.buffer.ends_with('\n') {
+                let mut line = mem::take(&mut self.buffer);
+                line.pop();
+                return Ok(line);
+            }
+
+            if read == 0 && !self.reopen_if_rotated().await? {
+                delay_for(POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    /// Reopen the log file if it got replaced or truncated. Returns `true` if the file has been
+    /// reopened.
+    async fn reopen_if_rotated(&mut self) -> Result<bool> {
+        let metadata = match fs::metadata(&self.path).await {
+            Ok(metadata) => metadata,
+            // The file got rotated away and has not been recreated yet
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => {
+                return Err(e).with_context(|| format!("get metadata of {}", self.path.display()))
+            }
+        };
+
+        if metadata.ino() == self.inode && metadata.len() >= self.position {
+            return Ok(false);
+        }
+
+        let (reader, inode, _) = Self::open_reader(&self.path, SeekFrom::Start(0)).await?;
+        self.reader = reader;
+        self.inode = inode;
+        self.position = 0;
+        self.buffer.clear();
+        Ok(true)
+    }
+
+    /// Open a new reader for the provided `path`, which starts reading at the position `from`,
+    /// and return it together with the files inode and the position.
+    async fn open_reader(path: &Path, from: SeekFrom) -> Result<(BufReader<File>, u64, u64)> {
+        let mut file = File::open(path)
+            .await
+            .with_context(|| format!("open log file {}", path.display()))?;
+        let inode = file
+            .metadata()
+            .await
+            .with_context(|| format!("get metadata of {}", path.display()))?
+            .ino();
+        let position = file
+            .seek(from)
+            .await
+            .with_context(|| format!("seek log file {}", path.display()))?;
+        Ok((BufReader::new(file), inode, position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::OpenOptions, io::Write};
+    use tempfile::TempDir;
+    use tokio::time::timeout;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn append(path: &Path, content: &str) -> Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(content.as_bytes())?;
+        Ok(())
+    }
+
+    async fn next_line(sut: &mut LogFollower) -> Result<String> {
+        timeout(TIMEOUT, sut.next_line()).await?
+    }
+
+    #[tokio::test]
+    async fn follow_success() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("0.log");
+        append(&path, "first\nsecond\n")?;
+
+        let mut sut = LogFollower::open(&path).await?;
+        assert_eq!(next_line(&mut sut).await?, "first");
+        assert_eq!(next_line(&mut sut).await?, "second");
+
+        append(&path, "thi")?;
+        append(&path, "rd\n")?;
+        assert_eq!(next_line(&mut sut).await?, "third");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follow_success_end() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("0.log");
+        append(&path, "first\n")?;
+
+        let mut sut = LogFollower::open_end(&path).await?;
+        append(&path, "second\n")?;
+        assert_eq!(next_line(&mut sut).await?, "second");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follow_success_rotated() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("0.log");
+        append(&path, "first\n")?;
+
+        let mut sut = LogFollower::open(&path).await?;
+        assert_eq!(next_line(&mut sut).await?, "first");
+
+        std::fs::rename(&path, dir.path().join("0.log.1"))?;
+        append(&path, "second\n")?;
+        assert_eq!(next_line(&mut sut).await?, "second");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follow_success_truncated() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("0.log");
+        append(&path, "first line\n")?;
+
+        let mut sut = LogFollower::open(&path).await?;
+        assert_eq!(next_line(&mut sut).await?, "first line");
+
+        std::fs::write(&path, "new\n")?;
+        assert_eq!(next_line(&mut sut).await?, "new");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follow_fail_not_existing() -> Result<()> {
+        let dir = TempDir::new()?;
+        assert!(LogFollower::open(&dir.path().join("0.log")).await.is_err());
+        Ok(())
+    }
+}
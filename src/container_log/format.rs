@@ -0,0 +1,112 @@
+//! The Kubernetes CRI logging format, which is read by the kubelet.
+//!
+//! Every record is a single line of the form `<timestamp> <stream> <tag> <content>`, where the
+//! timestamp uses RFC 3339 with nanoseconds and the tag is `F` for full lines or `P` for partial
+//! ones which get continued by the next record of the same stream.
+
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The number of seconds of a day.
+const SECONDS_PER_DAY: u64 = 86400;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The output stream a log record originates from.
+pub enum Stream {
+    /// The standard output of the container.
+    Stdout,
+
+    /// The standard error of the container.
+    Stderr,
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stream::Stdout => write!(f, "stdout"),
+            Stream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// Build the log record of the `content` from the `stream` at `time`. The `content` must not
+/// contain a newline.
+pub fn record(time: SystemTime, stream: Stream, partial: bool, content: &[u8]) -> Vec<u8> {
+    let tag = if partial { "P" } else { "F" };
+    let mut record = format!("{} {} {} ", timestamp(time), stream, tag).into_bytes();
+    record.extend_from_slice(content);
+    record.push(b'\n');
+    record
+}
+
+/// Format the `time` as UTC RFC 3339 timestamp with nanoseconds, like
+/// `2016-10-06T00:17:09.669794202Z`.
+pub fn timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (days, seconds) = (
+        since.as_secs() / SECONDS_PER_DAY,
+        since.as_secs() % SECONDS_PER_DAY,
+    );
+    let (year, month, day) = civil_date(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        since.subsec_nanos()
+    )
+}
+
+/// Convert the number of `days` since the Unix epoch into the year, month and day of the
+/// proleptic Gregorian calendar.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Shift the epoch to 0000-03-01, which puts leap days at the end of the 400 year eras
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn timestamp_success() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000000000Z");
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::new(1_475_713_029, 669_794_202)),
+            "2016-10-06T00:17:09.669794202Z"
+        );
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000000000Z"
+        );
+    }
+
+    #[test]
+    fn record_success() {
+        let time = UNIX_EPOCH + Duration::from_secs(1);
+        assert_eq!(
+            record(time, Stream::Stdout, false, b"hello"),
+            b"1970-01-01T00:00:01.000000000Z stdout F hello\n".to_vec()
+        );
+        assert_eq!(
+            record(time, Stream::Stderr, true, b""),
+            b"1970-01-01T00:00:01.000000000Z stderr P \n".to_vec()
+        );
+    }
+}
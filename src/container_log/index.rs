@@ -0,0 +1,365 @@
+//! An index of container log files by time, which allows querying recent records without
+//! reading the whole file.
+//!
+//! The log writer records the time, the byte offset and the number of preceding records of a
+//! log record roughly every `INDEX_INTERVAL` bytes into a hidden file next to the log, like
+//! `.0.log.idx` for `0.log`. The index starts with the inode of the log file, so that an index
+//! outliving its log after a rotation is never applied to the new file. Queries seek to the
+//! closest indexed offset and only read the records from there.
+
+use crate::container_log::format;
+use anyhow::{Context, Result};
+use std::{
+    collections::VecDeque,
+    convert::TryInto,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The number of log bytes between two index entries.
+const INDEX_INTERVAL: u64 = 64 * 1024;
+
+/// The size of the index header containing the inode of the log file.
+const HEADER_SIZE: usize = 8;
+
+/// The size of a single encoded index entry.
+const ENTRY_SIZE: usize = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// An indexed log record.
+struct Entry {
+    /// The time of the record in nanoseconds since the Unix epoch.
+    time: u64,
+
+    /// The byte offset of the record inside the log file.
+    offset: u64,
+
+    /// The number of records between the first indexed record and this one.
+    records: u64,
+}
+
+impl Entry {
+    /// Encode the entry in its on-disk format.
+    fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let mut buf = [0; ENTRY_SIZE];
+        buf[..8].copy_from_slice(&self.time.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        buf[16..].copy_from_slice(&self.records.to_le_bytes());
+        buf
+    }
+
+    /// Decode an entry from its on-disk format in `buf`.
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let u64_at = |i: usize| buf.get(i..i + 8)?.try_into().ok().map(u64::from_le_bytes);
+        Some(Self {
+            time: u64_at(0)?,
+            offset: u64_at(8)?,
+            records: u64_at(16)?,
+        })
+    }
+}
+
+/// LogIndex maintains the index of a log file while it is being written.
+pub struct LogIndex {
+    /// The opened index file.
+    file: File,
+
+    /// The path of the index file.
+    path: PathBuf,
+
+    /// The size of the log file.
+    offset: u64,
+
+    /// The number of records written since the first indexed record.
+    records: u64,
+
+    /// The offset of the last indexed record, if any.
+    last: Option<u64>,
+}
+
+impl LogIndex {
+    /// Open the index of the existing log file at `log_path`, which continues a valid index or
+    /// starts a new one at the current end of the log.
+    pub fn open(log_path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(log_path)
+            .with_context(|| format!("get metadata of {}", log_path.display()))?;
+        let path = index_path(log_path)?;
+        let entries = read_entries(&path, metadata.ino(), metadata.len()).unwrap_or_default();
+        let (last, records) = match entries.last() {
+            Some(last) => (
+                Some(last.offset),
+                last.records + count_records(log_path, last.offset)?,
+            ),
+            None => (None, 0),
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("open log index {}", path.display()))?;
+        let len = (HEADER_SIZE + entries.len() * ENTRY_SIZE) as u64;
+        file.set_len(len)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(&metadata.ino().to_le_bytes()))
+            .and_then(|_| file.seek(SeekFrom::Start(len)))
+            .with_context(|| format!("truncate log index {}", path.display()))?;
+        Ok(Self {
+            file,
+            path,
+            offset: metadata.len(),
+            records,
+            last,
+        })
+    }
+
+    /// Account for a log record of `len` bytes written at `time`, which gets indexed if the
+    /// last indexed record is at least `INDEX_INTERVAL` bytes away. Has to be called before
+    /// writing the record.
+    pub fn record(&mut self, time: SystemTime, len: usize) -> Result<()> {
+        if self
+            .last
+            .map_or(true, |x| self.offset - x >= INDEX_INTERVAL)
+        {
+            let entry = Entry {
+                time: nanos(time),
+                offset: self.offset,
+                records: self.records,
+            };
+            self.file
+                .write_all(&entry.encode())
+                .with_context(|| format!("write log index {}", self.path.display()))?;
+            self.last = Some(self.offset);
+        }
+        self.offset += len as u64;
+        self.records += 1;
+        Ok(())
+    }
+}
+
+/// Retrieve the records of the log file at `path`, optionally only the ones written `since` a
+/// point in time and only the last `tail` ones. The index of the log is used to skip the older
+/// records, whereas the whole file is read if it has no valid index.
+pub fn query_log(
+    path: &Path,
+    since: Option<SystemTime>,
+    tail: Option<usize>,
+) -> Result<Vec<String>> {
+    let metadata =
+        fs::metadata(path).with_context(|| format!("get metadata of {}", path.display()))?;
+    let entries =
+        read_entries(&index_path(path)?, metadata.ino(), metadata.len()).unwrap_or_default();
+
+    // Records are written in order, which means that all records before an entry older than
+    // `since` are older as well
+    let mut start = 0;
+    if let Some(since) = since {
+        if let Some(entry) = entries.iter().rev().find(|x| x.time < nanos(since)) {
+            start = entry.offset;
+        }
+    }
+    if let (Some(tail), Some(last)) = (tail, entries.last()) {
+        let total = last.records + count_records(path, last.offset)?;
+        if let Some(target) = total.checked_sub(tail as u64) {
+            if let Some(entry) = entries.iter().rev().find(|x| x.records <= target) {
+                start = start.max(entry.offset);
+            }
+        }
+    }
+
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    file.seek(SeekFrom::Start(start))
+        .with_context(|| format!("seek {}", path.display()))?;
+    let since = since.map(format::timestamp);
+    let mut reader = BufReader::new(file);
+    let (mut records, mut buf) = (VecDeque::new(), vec![]);
+    loop {
+        buf.clear();
+        if reader
+            .read_until(b'\n', &mut buf)
+            .with_context(|| format!("read {}", path.display()))?
+            == 0
+        {
+            break;
+        }
+        // Container output is not required to be valid UTF-8
+        let line = String::from_utf8_lossy(&buf)
+            .trim_end_matches('\n')
+            .to_string();
+        if let Some(since) = &since {
+            // Timestamps of the CRI logging format have a fixed width and compare like strings
+            if line.split(' ').next().map_or(true, |x| x < since.as_str()) {
+                continue;
+            }
+        }
+        records.push_back(line);
+        if tail.map_or(false, |x| records.len() > x) {
+            records.pop_front();
+        }
+    }
+    Ok(records.into())
+}
+
+/// Retrieve the path of the index of the log file at `log_path`.
+pub fn index_path(log_path: &Path) -> Result<PathBuf> {
+    let name = log_path
+        .file_name()
+        .with_context(|| format!("invalid log file {}", log_path.display()))?;
+    Ok(log_path.with_file_name(format!(".{}.idx", name.to_string_lossy())))
+}
+
+/// Read the entries of the index at `path`. Returns `None` if the index does not exist or does
+/// not belong to the log file with the `inode` and size `len`.
+fn read_entries(path: &Path, inode: u64, len: u64) -> Option<Vec<Entry>> {
+    let mut content = vec![];
+    File::open(path).ok()?.read_to_end(&mut content).ok()?;
+    let header = content.get(..HEADER_SIZE)?.try_into().ok()?;
+    if u64::from_le_bytes(header) != inode {
+        return None;
+    }
+    // A partially written last entry gets ignored
+    let entries: Vec<Entry> = content[HEADER_SIZE..]
+        .chunks_exact(ENTRY_SIZE)
+        .map(Entry::decode)
+        .collect::<Option<_>>()?;
+    let ordered = entries
+        .windows(2)
+        .all(|x| x[0].offset < x[1].offset && x[0].records < x[1].records);
+    if !ordered || entries.last().map_or(false, |x| x.offset > len) {
+        return None;
+    }
+    Some(entries)
+}
+
+/// Count the records of the log file at `path` after the byte `offset`.
+fn count_records(path: &Path, offset: u64) -> Result<u64> {
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    file.seek(SeekFrom::Start(offset))
+        .with_context(|| format!("seek {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let (mut records, mut line) = (0, vec![]);
+    while reader
+        .read_until(b'\n', &mut line)
+        .with_context(|| format!("read {}", path.display()))?
+        > 0
+    {
+        records += 1;
+        line.clear();
+    }
+    Ok(records)
+}
+
+/// Convert the `time` into nanoseconds since the Unix epoch.
+fn nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container_log::format::Stream;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    /// Append the records `range` to the log at `path` like the log writer does, every record
+    /// being written one second after the epoch per number.
+    fn write_records(path: &Path, range: std::ops::Range<u64>) -> Result<()> {
+        let mut log = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut sut = LogIndex::open(path)?;
+        for i in range {
+            let time = UNIX_EPOCH + Duration::from_secs(i);
+            let record = format::record(
+                time,
+                Stream::Stdout,
+                false,
+                format!("{:0100}", i).as_bytes(),
+            );
+            sut.record(time, record.len())?;
+            log.write_all(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve the content of the `records`.
+    fn contents(records: &[String]) -> Vec<u64> {
+        records
+            .iter()
+            .filter_map(|x| x.rsplit(' ').next()?.parse().ok())
+            .collect()
+    }
+
+    #[test]
+    fn query_log_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("0.log");
+        fs::write(&path, "")?;
+        write_records(&path, 0..2000)?;
+        // The index continues after reopening the log
+        write_records(&path, 2000..3000)?;
+
+        let metadata = fs::metadata(&path)?;
+        let entries = read_entries(&index_path(&path)?, metadata.ino(), metadata.len())
+            .context("no index")?;
+        assert!(entries.len() > 4);
+
+        let since = Some(UNIX_EPOCH + Duration::from_secs(2995));
+        assert_eq!(
+            contents(&query_log(&path, since, None)?),
+            (2995..3000).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            contents(&query_log(&path, None, Some(3))?),
+            vec![2997, 2998, 2999]
+        );
+        assert_eq!(
+            contents(&query_log(&path, since, Some(2))?),
+            vec![2998, 2999]
+        );
+        assert_eq!(query_log(&path, None, Some(5000))?.len(), 3000);
+        assert_eq!(query_log(&path, None, None)?.len(), 3000);
+        Ok(())
+    }
+
+    #[test]
+    fn query_log_success_without_index() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("0.log");
+        fs::write(&path, "")?;
+        write_records(&path, 0..10)?;
+
+        // A rotated log leaves the index of the old file behind
+        fs::rename(&path, dir.path().join("0.log.20201111-111111"))?;
+        fs::write(
+            &path,
+            format::record(UNIX_EPOCH, Stream::Stderr, false, b"new"),
+        )?;
+        assert_eq!(query_log(&path, None, Some(10))?.len(), 1);
+        let since = Some(UNIX_EPOCH + Duration::from_secs(5));
+        assert!(query_log(&path, since, None)?.is_empty());
+
+        // Opening the index of the new log starts over
+        LogIndex::open(&path)?;
+        let metadata = fs::metadata(&path)?;
+        assert_eq!(
+            read_entries(&index_path(&path)?, metadata.ino(), metadata.len()),
+            Some(vec![])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn entry_encode_decode() {
+        let entry = Entry {
+            time: 1,
+            offset: 2,
+            records: 3,
+        };
+        assert_eq!(Entry::decode(&entry.encode()), Some(entry));
+        assert_eq!(Entry::decode(&[0; 8]), None);
+    }
+}
@@ -0,0 +1,628 @@
+//! Capturing of the container output into log files in the CRI logging format.
+
+use crate::{
+    clock::{Clock, SystemClock},
+    container_log::{
+        attach::{self, Attachment, Output, Stdin},
+        format::{self, Stream},
+        index::LogIndex,
+        pipe::AsyncPipe,
+        tee::Tee,
+        throttle::{self, Admission, LogThrottle},
+    },
+};
+use anyhow::{format_err, Context, Result};
+use futures_util::future::{AbortHandle, Abortable};
+use log::{debug, warn};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    mem,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    sync::{broadcast, mpsc, oneshot},
+};
+
+/// The size of the buffer for reading the container output.
+const BUFFER_SIZE: usize = 32 * 1024;
+
+/// The maximum length of log record content, longer lines are split into partial records.
+const MAX_LINE_SIZE: usize = 16 * 1024;
+
+/// The number of records which can be queued for writing.
+const QUEUE_SIZE: usize = 64;
+
+/// A request to reopen the log file, which gets answered once the new file is used.
+type Reopen = oneshot::Sender<Result<()>>;
+
+#[derive(Debug)]
+/// A single line or partial line of the container output.
+struct Line {
+    /// The stream the line has been read from.
+    stream: Stream,
+
+    /// Whether the line continues in the next record of the stream.
+    partial: bool,
+
+    /// The content of the line without its trailing newline.
+    content: Vec<u8>,
+}
+
+/// The handles of an active log writer.
+struct Handle {
+    /// The generation of the writer, which tells apart writers of the same container ID.
+    generation: u64,
+
+    /// The sender of the reopen requests.
+    reopen: mpsc::Sender<Reopen>,
+
+    /// The handles for stopping the readers of the container output.
+    readers: Vec<AbortHandle>,
+
+    /// The sender of the container output to the attached sessions.
+    output: broadcast::Sender<Output>,
+
+    /// The input of the container, if it reads one.
+    stdin: Option<Stdin>,
+}
+
+#[derive(Clone)]
+/// LogManager writes the output of containers into their log files and reopens them on request,
+/// for example after the kubelet rotated them.
+pub struct LogManager {
+    /// The handles of the active log writers by their container ID.
+    writers: Arc<Mutex<HashMap<String, Handle>>>,
+
+    /// The generation of the next started writer.
+    generation: Arc<AtomicU64>,
+
+    /// The clock the timestamps of the records and the throttling are based on.
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for LogManager {
+    fn default() -> Self {
+        Self {
+            writers: Arc::default(),
+            generation: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl LogManager {
+    /// Base the timestamps of the records and the throttling on the `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Start writing the `stdout` and `stderr` of the container `id` into the log file at `path`,
+    /// which gets created if necessary. Writing ends once both outputs are closed, which means
+    /// that the container exited. The `throttle` limits the log throughput if set.
+    pub fn start(
+        &self,
+        id: &str,
+        path: &Path,
+        stdout: File,
+        stderr: File,
+        throttle: Option<LogThrottle>,
+    ) -> Result<()> {
+        let file = open(path)?;
+        let outputs = vec![
+            (Stream::Stdout, AsyncPipe::new(stdout)?),
+            (Stream::Stderr, AsyncPipe::new(stderr)?),
+        ];
+        let (tx, lines) = mpsc::channel(QUEUE_SIZE);
+        let (output, _) = broadcast::channel(attach::OUTPUT_CAPACITY);
+        let mut readers = vec![];
+        for (stream, pipe) in outputs {
+            let (reader, registration) = AbortHandle::new_pair();
+            tokio::spawn(Abortable::new(
+                read_lines(stream, pipe, tx.clone(), output.clone()),
+                registration,
+            ));
+            readers.push(reader);
+        }
+        drop(tx);
+
+        let (reopen, requests) = mpsc::channel(1);
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut writers) = self.writers.lock() {
+            let handle = Handle {
+                generation,
+                reopen,
+                readers,
+                output,
+                stdin: None,
+            };
+            writers.insert(id.into(), handle);
+        }
+        let writer = Writer {
+            path: path.into(),
+            file: tokio::fs::File::from_std(file),
+            index: open_index(path),
+            throttle,
+            clock: self.clock.clone(),
+        };
+        let (id, writers) = (id.to_string(), self.writers.clone());
+        tokio::spawn(async move {
+            if let Err(e) = writer.run(lines, requests).await {
+                warn!("Unable to write log of container {}: {:#}", id, e);
+            }
+            debug!("Finished writing log of container {}", id);
+
+            // A writer started for the same container afterwards keeps its handle
+            if let Ok(mut writers) = writers.lock() {
+                if writers
+                    .get(&id)
+                    .map_or(false, |x| x.generation == generation)
+                {
+                    writers.remove(&id);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Connect the `stdin` to the log writer of the container `id`, so that attached sessions
+    /// can write it. The input gets dropped together with the writer once the container exited.
+    pub fn set_stdin(&self, id: &str, stdin: Stdin) {
+        if let Ok(mut writers) = self.writers.lock() {
+            if let Some(handle) = writers.get_mut(id) {
+                handle.stdin = Some(stdin);
+            }
+        }
+    }
+
+    /// Tee the output of the container `id` via the `tee` as well, until the container exits.
+    /// Returns `false` if the output of the container is not being read.
+    pub fn tee(&self, id: &str, tee: Tee) -> bool {
+        let output = match self.writers.lock() {
+            Ok(writers) => writers.get(id).map(|x| x.output.subscribe()),
+            Err(_) => None,
+        };
+        match output {
+            Some(output) => {
+                tokio::spawn(tee.run(id.into(), output));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Attach a new session to the standard streams of the container `id`. Returns `None` if the
+    /// output of the container is not being read.
+    pub fn attach(&self, id: &str) -> Option<Attachment> {
+        let writers = self.writers.lock().ok()?;
+        let handle = writers.get(id)?;
+        Some(Attachment {
+            output: handle.output.subscribe(),
+            stdin: handle.stdin.clone(),
+        })
+    }
+
+    /// Stop writing the log of the container `id`, for example because creating the container
+    /// failed and its output will never be closed otherwise. Everything read so far still gets
+    /// written.
+    pub fn stop(&self, id: &str) {
+        if let Ok(writers) = self.writers.lock() {
+            if let Some(handle) = writers.get(id) {
+                handle.readers.iter().for_each(AbortHandle::abort);
+            }
+        }
+    }
+
+    /// Reopen the log file of the container `id`, which gets created again if it has been moved
+    /// away. Returns `false` if the log of the container is not being written.
+    pub async fn reopen(&self, id: &str) -> Result<bool> {
+        let writer = self
+            .writers
+            .lock()
+            .ok()
+            .and_then(|x| x.get(id).map(|x| x.reopen.clone()));
+        let mut writer = match writer {
+            Some(writer) => writer,
+            None => return Ok(false),
+        };
+
+        let (tx, rx) = oneshot::channel();
+        if writer.send(tx).await.is_err() {
+            return Ok(false);
+        }
+        match rx.await {
+            Ok(res) => res.map(|()| true),
+            Err(_) => Ok(false),
+        }
+    }
+}
+
+/// Open the log file at `path` for appending.
+fn open(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("create log directory {}", parent.display()))?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open log file {}", path.display()))
+}
+
+/// Open the index of the log file at `path`. A log without index can still be written, which is
+/// why failures only get logged.
+fn open_index(path: &Path) -> Option<LogIndex> {
+    LogIndex::open(path)
+        .map_err(|e| warn!("Unable to index log file {}: {:#}", path.display(), e))
+        .ok()
+}
+
+/// Split everything read from the `pipe` of the `stream` into lines and send them to `tx`. The
+/// unsplit output gets sent to the attached sessions of `output` as well.
+async fn read_lines<R: AsyncRead + Unpin>(
+    stream: Stream,
+    mut pipe: R,
+    mut tx: mpsc::Sender<Line>,
+    output: broadcast::Sender<Output>,
+) {
+    let mut buf = vec![0; BUFFER_SIZE];
+    let mut line = vec![];
+    loop {
+        let n = match pipe.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        output.send((stream, buf[..n].to_vec())).ok();
+
+        let mut lines = vec![];
+        let mut data = &buf[..n];
+        while let Some(pos) = data.iter().position(|x| *x == b'\n') {
+            line.extend_from_slice(&data[..pos]);
+            lines.push((false, mem::take(&mut line)));
+            data = &data[pos + 1..];
+        }
+        line.extend_from_slice(data);
+        while line.len() >= MAX_LINE_SIZE {
+            let rest = line.split_off(MAX_LINE_SIZE);
+            lines.push((true, mem::replace(&mut line, rest)));
+        }
+
+        for (partial, content) in lines {
+            let line = Line {
+                stream,
+                partial,
+                content,
+            };
+            if tx.send(line).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    // The last line is complete once the output closed, even without a trailing newline
+    if !line.is_empty() {
+        let line = Line {
+            stream,
+            partial: false,
+            content: line,
+        };
+        tx.send(line).await.ok();
+    }
+}
+
+/// Writer owns the log file of a single container.
+struct Writer {
+    /// The path of the log file.
+    path: PathBuf,
+
+    /// The currently opened log file.
+    file: tokio::fs::File,
+
+    /// The index of the currently opened log file, if it could be opened.
+    index: Option<LogIndex>,
+
+    /// The throughput limit of the log.
+    throttle: Option<LogThrottle>,
+
+    /// The clock the timestamps of the records and the throttling are based on.
+    clock: Arc<dyn Clock>,
+}
+
+impl Writer {
+    /// Write the `lines` until all outputs are closed and handle the reopen `requests`
+    /// in between.
+    async fn run(
+        mut self,
+        mut lines: mpsc::Receiver<Line>,
+        mut requests: mpsc::Receiver<Reopen>,
+    ) -> Result<()> {
+        loop {
+            tokio::select! {
+                line = lines.recv() => match line {
+                    Some(line) => self.write(line).await?,
+                    None => break,
+                },
+                Some(request) = requests.recv() => {
+                    request.send(self.reopen().await).ok();
+                }
+            }
+        }
+        self.file.flush().await.context("flush log file")
+    }
+
+    /// Write the `line` into the log file, unless the throttle drops it.
+    async fn write(&mut self, line: Line) -> Result<()> {
+        let now = self.clock.now();
+        if let Some(throttle) = self.throttle.as_mut() {
+            match throttle.admit(line.content.len(), self.clock.instant()) {
+                Admission::Drop => return Ok(()),
+                Admission::Accept(0) => {}
+                Admission::Accept(dropped) => {
+                    let marker = throttle::marker(dropped);
+                    let record = format::record(now, line.stream, false, marker.as_bytes());
+                    self.write_record(now, &record).await?;
+                }
+            }
+        }
+        let record = format::record(now, line.stream, line.partial, &line.content);
+        self.write_record(now, &record).await
+    }
+
+    /// Write a single formatted `record` created at `time` into the log file.
+    async fn write_record(&mut self, time: SystemTime, record: &[u8]) -> Result<()> {
+        if let Some(index) = self.index.as_mut() {
+            if let Err(e) = index.record(time, record.len()) {
+                warn!(
+                    "Disabling index of log file {}: {:#}",
+                    self.path.display(),
+                    e
+                );
+                self.index = None;
+            }
+        }
+        self.file
+            .write_all(record)
+            .await
+            .with_context(|| format!("write log file {}", self.path.display()))
+    }
+
+    /// Replace the log file with a newly opened one at the same path.
+    async fn reopen(&mut self) -> Result<()> {
+        self.file.flush().await.context("flush log file")?;
+        let path = self.path.clone();
+        let (file, index) =
+            tokio::task::spawn_blocking(move || open(&path).map(|x| (x, open_index(&path))))
+                .await
+                .map_err(|e| format_err!("join reopening task: {}", e))??;
+        self.file = tokio::fs::File::from_std(file);
+        self.index = index;
+        debug!("Reopened log file {}", self.path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::tests::FakeClock,
+        container_log::{index::query_log, pipe::pipe},
+    };
+    use std::{io::Write, time::Duration};
+    use tempfile::tempdir;
+    use tokio::time;
+
+    /// Wait until the log of the container `id` is not being written anymore.
+    async fn wait_finished(sut: &LogManager, id: &str) -> Result<()> {
+        for _ in 0..100 {
+            if !sut.reopen(id).await? {
+                return Ok(());
+            }
+            time::delay_for(Duration::from_millis(10)).await;
+        }
+        Err(format_err!("log of container {} still being written", id))
+    }
+
+    /// Retrieve the content of the log records in the file at `path`.
+    fn records(path: &Path) -> Result<Vec<(String, String, String)>> {
+        fs::read_to_string(path)?
+            .lines()
+            .map(|x| {
+                let mut parts = x.splitn(4, ' ');
+                let timestamp = parts.next().context("no timestamp")?;
+                assert!(timestamp.ends_with('Z'), "{}", timestamp);
+                let stream = parts.next().context("no stream")?;
+                let tag = parts.next().context("no tag")?;
+                let content = parts.next().context("no content")?;
+                Ok((stream.into(), tag.into(), content.into()))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn start_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("name").join("0.log");
+        let sut = LogManager::default();
+        let ((stdout, mut stdout_write), (stderr, mut stderr_write)) = (pipe()?, pipe()?);
+        sut.start("id", &path, stdout, stderr, None)?;
+
+        stdout_write.write_all(b"first\nsec")?;
+        stdout_write.write_all(b"ond\nlast")?;
+        drop(stdout_write);
+        stderr_write.write_all(&vec![b'x'; MAX_LINE_SIZE + 1])?;
+        stderr_write.write_all(b"\n")?;
+        drop(stderr_write);
+        wait_finished(&sut, "id").await?;
+
+        let mut records = records(&path)?;
+        let stderr: Vec<_> = records
+            .iter()
+            .filter(|x| x.0 == "stderr")
+            .cloned()
+            .collect();
+        records.retain(|x| x.0 == "stdout");
+        assert_eq!(
+            records,
+            vec![
+                ("stdout".into(), "F".into(), "first".into()),
+                ("stdout".into(), "F".into(), "second".into()),
+                ("stdout".into(), "F".into(), "last".into()),
+            ]
+        );
+        assert_eq!(stderr.len(), 2);
+        assert_eq!(
+            (stderr[0].1.as_str(), stderr[0].2.len()),
+            ("P", MAX_LINE_SIZE)
+        );
+        assert_eq!((stderr[1].1.as_str(), stderr[1].2.as_str()), ("F", "x"));
+
+        // The writer maintains the index of the log
+        assert!(path.with_file_name(".0.log.idx").exists());
+        assert_eq!(query_log(&path, None, Some(2))?.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn start_success_throttled() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("0.log");
+        let clock = FakeClock::default();
+        let sut = LogManager::default().with_clock(Arc::new(clock.clone()));
+        let ((stdout, mut stdout_write), (stderr, stderr_write)) = (pipe()?, pipe()?);
+        let throttle = LogThrottle::new(1, 10, clock.instant());
+        sut.start("id", &path, stdout, stderr, throttle)?;
+
+        stdout_write.write_all(b"0123456789\n")?;
+        for _ in 0..100 {
+            if path.exists() && !fs::read_to_string(&path)?.is_empty() {
+                break;
+            }
+            time::delay_for(Duration::from_millis(10)).await;
+        }
+
+        // The burst is used up, so only the refill of the passed time can be written
+        clock.advance(Duration::from_secs(5));
+        stdout_write.write_all(b"dropped\nabc\n")?;
+        drop((stdout_write, stderr_write));
+        wait_finished(&sut, "id").await?;
+
+        let records = records(&path)?;
+        assert_eq!(
+            records,
+            vec![
+                ("stdout".into(), "F".into(), "0123456789".into()),
+                ("stdout".into(), "F".into(), throttle::marker(1)),
+                ("stdout".into(), "F".into(), "abc".into()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reopen_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("0.log");
+        let sut = LogManager::default();
+        let ((stdout, mut stdout_write), (stderr, stderr_write)) = (pipe()?, pipe()?);
+        sut.start("id", &path, stdout, stderr, None)?;
+
+        stdout_write.write_all(b"before\n")?;
+        let rotated = dir.path().join("0.log.1");
+        for _ in 0..100 {
+            if path.exists() && !fs::read_to_string(&path)?.is_empty() {
+                break;
+            }
+            time::delay_for(Duration::from_millis(10)).await;
+        }
+        fs::rename(&path, &rotated)?;
+        assert!(sut.reopen("id").await?);
+        assert!(path.exists());
+
+        stdout_write.write_all(b"after\n")?;
+        drop((stdout_write, stderr_write));
+        wait_finished(&sut, "id").await?;
+
+        assert_eq!(records(&rotated)?[0].2, "before");
+        assert_eq!(records(&path)?[0].2, "after");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stop_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("0.log");
+        let sut = LogManager::default();
+        let ((stdout, _stdout_write), (stderr, _stderr_write)) = (pipe()?, pipe()?);
+        sut.start("id", &path, stdout, stderr, None)?;
+
+        // The writer finishes although the outputs are still open
+        sut.stop("id");
+        wait_finished(&sut, "id").await?;
+        sut.stop("id");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn start_success_restarted() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("0.log");
+        let sut = LogManager::default();
+        let ((stdout, stdout_write), (stderr, stderr_write)) = (pipe()?, pipe()?);
+        sut.start("id", &path, stdout, stderr, None)?;
+        let ((stdout, _stdout_write), (stderr, _stderr_write)) = (pipe()?, pipe()?);
+        sut.start("id", &path, stdout, stderr, None)?;
+
+        // The finished first writer does not remove the handle of the second one
+        drop((stdout_write, stderr_write));
+        time::delay_for(Duration::from_millis(50)).await;
+        assert!(sut.reopen("id").await?);
+        sut.stop("id");
+        wait_finished(&sut, "id").await
+    }
+
+    #[tokio::test]
+    async fn attach_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("0.log");
+        let sut = LogManager::default();
+        assert!(sut.attach("id").is_none());
+
+        let ((stdout, mut stdout_write), (stderr, stderr_write)) = (pipe()?, pipe()?);
+        sut.start("id", &path, stdout, stderr, None)?;
+        let (_, stdin_write) = pipe()?;
+        sut.set_stdin("id", Stdin::new(stdin_write, false)?);
+
+        // Every attached session receives the output
+        let mut first = sut.attach("id").context("not attached")?;
+        let mut second = sut.attach("id").context("not attached")?;
+        assert!(first.stdin.is_some());
+        stdout_write.write_all(b"output\n")?;
+        for attachment in &mut [&mut first, &mut second] {
+            let (stream, data) = attachment.output.recv().await?;
+            assert_eq!(stream, Stream::Stdout);
+            assert_eq!(data, b"output\n");
+        }
+
+        // The output closes once the container exited
+        drop((stdout_write, stderr_write));
+        wait_finished(&sut, "id").await?;
+        assert!(first.output.recv().await.is_err());
+        assert!(sut.attach("id").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reopen_not_found() -> Result<()> {
+        assert!(!LogManager::default().reopen("id").await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+//! Container log handling
+
+pub mod attach;
+pub mod follow;
+pub mod format;
+pub mod index;
+pub mod manager;
+pub mod pipe;
+pub mod tee;
+pub mod throttle;
+
+use anyhow::{Context, Result};
+use std::{fs, io::ErrorKind, path::Path};
+
+/// Remove the log file at `log_path` together with its index. Missing files are not an error,
+/// because the kubelet may have removed them already.
+pub fn remove(log_path: &Path) -> Result<()> {
+    for path in &[log_path.to_path_buf(), index::index_path(log_path)?] {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("remove log file {}", path.display()))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn remove_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("0.log");
+        fs::write(&path, "log")?;
+        fs::write(index::index_path(&path)?, "index")?;
+
+        remove(&path)?;
+        assert!(!path.exists());
+        assert!(!index::index_path(&path)?.exists());
+        remove(&path)
+    }
+}
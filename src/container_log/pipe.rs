@@ -0,0 +1,154 @@
+//! Pipes connecting the standard streams of containers to the server.
+//!
+//! The container keeps the blocking end of a pipe, whereas the end of the server is switched to
+//! non-blocking mode and driven by the reactor of tokio. Otherwise every container would park a
+//! thread of the blocking pool for as long as it runs.
+
+use anyhow::{Context, Result};
+use mio::{unix::EventedFd, Evented, Poll, PollOpt, Ready, Token};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::unix::io::AsRawFd,
+    pin::Pin,
+    task::{Context as TaskContext, Poll as TaskPoll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, PollEvented};
+
+#[cfg(target_os = "linux")]
+use nix::unistd::pipe2;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::FromRawFd;
+
+#[cfg(not(target_os = "linux"))]
+use anyhow::bail;
+
+#[cfg(target_os = "linux")]
+/// Create a new pipe for a standard stream of a container and return its read and write ends.
+pub fn pipe() -> Result<(File, File)> {
+    let (read, write) = pipe2(OFlag::O_CLOEXEC).context("create pipe")?;
+    Ok(unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) })
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Create a new pipe for a standard stream of a container, which is only supported on Linux,
+/// because the pipe has to be closed on exec atomically.
+pub fn pipe() -> Result<(File, File)> {
+    bail!("creating container pipes is not supported on this platform")
+}
+
+/// The file descriptor of a pipe end, which can be registered at the reactor.
+struct PipeFd(File);
+
+impl Evented for PipeFd {
+    fn register(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+impl Read for PipeFd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for PipeFd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// AsyncPipe is the end of a pipe owned by the server, which gets read or written without
+/// blocking a thread.
+pub struct AsyncPipe(PollEvented<PipeFd>);
+
+impl AsyncPipe {
+    /// Switch the pipe end `file` into non-blocking mode and register it at the reactor, which
+    /// requires a running tokio runtime.
+    pub fn new(file: File) -> Result<Self> {
+        let flags = fcntl(file.as_raw_fd(), FcntlArg::F_GETFL).context("get pipe flags")?;
+        let flags = OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK;
+        fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(flags)).context("set pipe flags")?;
+        Ok(Self(
+            PollEvented::new(PipeFd(file)).context("register pipe")?,
+        ))
+    }
+}
+
+impl AsyncRead for AsyncPipe {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut [u8],
+    ) -> TaskPoll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for AsyncPipe {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> TaskPoll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> TaskPoll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> TaskPoll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn async_pipe_success() -> Result<()> {
+        let (read, write) = pipe()?;
+        let (mut read, mut write) = (AsyncPipe::new(read)?, AsyncPipe::new(write)?);
+
+        let writer = tokio::spawn(async move {
+            write.write_all(b"data").await?;
+            Ok::<_, io::Error>(())
+        });
+        let mut buf = vec![];
+        read.read_to_end(&mut buf).await?;
+        writer.await??;
+        assert_eq!(buf, b"data");
+        Ok(())
+    }
+}
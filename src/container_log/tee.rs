@@ -0,0 +1,222 @@
+//! Teeing of the container output to additional host files or FIFOs.
+//!
+//! Telemetry agents on some platforms read the output of containers from a FIFO instead of the
+//! CRI log. Containers request the target via annotation, which has to be a path relative to the
+//! configured tee directory, so that pods cannot write arbitrary files of the host. The raw output
+//! of both streams gets written, whereas output is dropped while a FIFO has no reader or the target
+//! cannot keep up, instead of slowing down the container.
+
+use crate::container_log::{attach::Output, pipe::AsyncPipe};
+use anyhow::{bail, Context, Result};
+use log::debug;
+use nix::fcntl::OFlag;
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    os::unix::fs::{FileTypeExt, OpenOptionsExt},
+    path::{Component, Path, PathBuf},
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::broadcast::{self, RecvError},
+};
+
+/// The annotation for the tee target of a container, relative to the tee directory.
+pub const OUTPUT_TEE_ANNOTATION: &str = "io.kubernetes.cri.output-tee";
+
+/// An opened tee target.
+type Target = Box<dyn AsyncWrite + Send + Unpin>;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Tee writes the output of a container to an additional file or FIFO.
+pub struct Tee {
+    /// The path of the target.
+    path: PathBuf,
+}
+
+impl Tee {
+    /// Retrieve the tee requested via the `annotations` of a container. Returns `None` if no tee
+    /// is requested, whereas requests fail if teeing is disabled because there is no `dir`.
+    pub fn from_annotations(
+        annotations: &HashMap<String, String>,
+        dir: Option<&Path>,
+    ) -> Result<Option<Self>> {
+        let target = match annotations.get(OUTPUT_TEE_ANNOTATION) {
+            Some(target) => Path::new(target),
+            None => return Ok(None),
+        };
+        let dir = match dir {
+            Some(dir) => dir,
+            None => bail!(
+                "annotation {} requires a tee directory",
+                OUTPUT_TEE_ANNOTATION
+            ),
+        };
+        let relative = target
+            .components()
+            .all(|x| matches!(x, Component::Normal(_)));
+        if target.as_os_str().is_empty() || !relative {
+            bail!(
+                "tee target {} has to be relative to the tee directory",
+                target.display()
+            )
+        }
+        Ok(Some(Self {
+            path: dir.join(target),
+        }))
+    }
+
+    /// Retrieve the path of the target.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write all chunks received from the `output` of the container `id` to the target, until the
+    /// container exits.
+    pub async fn run(self, id: String, mut output: broadcast::Receiver<Output>) {
+        let mut target: Option<Target> = None;
+        loop {
+            let data = match output.recv().await {
+                Ok((_, data)) => data,
+                Err(RecvError::Lagged(n)) => {
+                    debug!("Tee of container {} missed {} output chunks", id, n);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            // FIFOs can only be opened while they have a reader
+            if target.is_none() {
+                target = self
+                    .open()
+                    .map_err(|e| debug!("Unable to open tee of container {}: {:#}", id, e))
+                    .ok();
+            }
+            if let Some(writer) = target.as_mut() {
+                let res = async {
+                    writer.write_all(&data).await?;
+                    writer.flush().await
+                };
+                if let Err(e) = res.await {
+                    debug!("Unable to write tee of container {}: {}", id, e);
+                    target = None;
+                }
+            }
+        }
+        debug!("Finished teeing output of container {}", id);
+    }
+
+    /// Open the target for writing, which is created as regular file if it does not exist.
+    fn open(&self) -> Result<Target> {
+        let path = &self.path;
+        let fifo = fs::metadata(path).map_or(false, |x| x.file_type().is_fifo());
+        if fifo {
+            let file = OpenOptions::new()
+                .write(true)
+                .custom_flags(OFlag::O_NONBLOCK.bits())
+                .open(path)
+                .with_context(|| format!("open tee FIFO {}", path.display()))?;
+            return Ok(Box::new(AsyncPipe::new(file)?));
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("create tee directory {}", parent.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open tee file {}", path.display()))?;
+        Ok(Box::new(tokio::fs::File::from_std(file)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container_log::format::Stream;
+    use nix::{sys::stat::Mode, unistd::mkfifo};
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    fn annotations(target: &str) -> HashMap<String, String> {
+        let mut annotations = HashMap::new();
+        annotations.insert(OUTPUT_TEE_ANNOTATION.into(), target.into());
+        annotations
+    }
+
+    #[test]
+    fn from_annotations_success() -> Result<()> {
+        let dir = Path::new("/run/tee");
+        let sut =
+            Tee::from_annotations(&annotations("agent/app.fifo"), Some(dir))?.context("no tee")?;
+        assert_eq!(sut.path(), Path::new("/run/tee/agent/app.fifo"));
+        assert!(Tee::from_annotations(&HashMap::new(), None)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn from_annotations_fail() -> Result<()> {
+        let dir = Path::new("/run/tee");
+        for target in &[
+            "",
+            "/etc/passwd",
+            "../etc/passwd",
+            "agent/../../etc",
+            "./app",
+        ] {
+            assert!(
+                Tee::from_annotations(&annotations(target), Some(dir)).is_err(),
+                "{}",
+                target
+            );
+        }
+        assert!(Tee::from_annotations(&annotations("app"), None).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_success_file() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = Tee::from_annotations(&annotations("agent/app.log"), Some(dir.path()))?
+            .context("no tee")?;
+        let path = sut.path().to_path_buf();
+        let (tx, rx) = broadcast::channel(4);
+        let tee = tokio::spawn(sut.run("id".into(), rx));
+
+        tx.send((Stream::Stdout, b"out\n".to_vec()))?;
+        tx.send((Stream::Stderr, b"err\n".to_vec()))?;
+        drop(tx);
+        tee.await?;
+        assert_eq!(fs::read_to_string(path)?, "out\nerr\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_success_fifo() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("app.fifo");
+        mkfifo(&path, Mode::S_IRUSR | Mode::S_IWUSR)?;
+        let sut =
+            Tee::from_annotations(&annotations("app.fifo"), Some(dir.path()))?.context("no tee")?;
+        let (tx, rx) = broadcast::channel(4);
+        let tee = tokio::spawn(sut.run("id".into(), rx));
+
+        // The output gets dropped while the FIFO has no reader
+        tx.send((Stream::Stdout, b"dropped\n".to_vec()))?;
+        tokio::task::yield_now().await;
+        let mut reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(OFlag::O_NONBLOCK.bits())
+            .open(&path)?;
+        tx.send((Stream::Stdout, b"out\n".to_vec()))?;
+        drop(tx);
+        tee.await?;
+
+        let mut buf = vec![0; 16];
+        let n = reader.read(&mut buf)?;
+        assert_eq!(&buf[..n], b"out\n");
+        Ok(())
+    }
+}
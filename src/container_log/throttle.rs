@@ -0,0 +1,174 @@
+//! Throughput limiting of container logs to protect the node from log bombs.
+
+use anyhow::{Context, Result};
+use std::{collections::HashMap, time::Instant};
+
+/// The annotation which can be used by pods to override the log rate limit. The value is either
+/// `RATE` or `RATE:BURST` in bytes, whereas a rate of `0` disables the limit.
+pub const LOG_RATE_LIMIT_ANNOTATION: &str = "io.kubernetes.cri.log-rate-limit";
+
+#[derive(Debug, PartialEq)]
+/// Admission is the decision of the throttle about a single log line.
+pub enum Admission {
+    /// The line should be written. If lines have been dropped before, a marker record containing
+    /// their number should be written first.
+    Accept(u64),
+
+    /// The line should be dropped.
+    Drop,
+}
+
+/// LogThrottle limits the log throughput of a single container by using a token bucket.
+pub struct LogThrottle {
+    /// The allowed throughput in bytes per second.
+    rate: u64,
+
+    /// The maximum number of bytes which can be written at once.
+    burst: u64,
+
+    /// The currently available bytes.
+    tokens: f64,
+
+    /// The last time the tokens have been refilled.
+    last: Instant,
+
+    /// The number of lines dropped since the last accepted one.
+    dropped: u64,
+}
+
+impl LogThrottle {
+    /// Create a new throttle from the provided `rate` and `burst` in bytes, starting at `now`. A
+    /// burst of `0` defaults to the rate. Returns `None` if the rate is `0`, which means
+    /// unlimited.
+    pub fn new(rate: u64, burst: u64, now: Instant) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+        let burst = if burst == 0 { rate } else { burst };
+        Some(Self {
+            rate,
+            burst,
+            tokens: burst as f64,
+            last: now,
+            dropped: 0,
+        })
+    }
+
+    /// Create a new throttle for a container, whereas the container `annotations` take
+    /// precedence over the configured `rate` and `burst`.
+    pub fn from_annotations(
+        annotations: &HashMap<String, String>,
+        rate: u64,
+        burst: u64,
+        now: Instant,
+    ) -> Result<Option<Self>> {
+        let (rate, burst) = match annotations.get(LOG_RATE_LIMIT_ANNOTATION) {
+            Some(value) => {
+                let err = || format!("parse annotation {}", LOG_RATE_LIMIT_ANNOTATION);
+                let mut parts = value.splitn(2, ':');
+                let rate = parts
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .parse()
+                    .with_context(err)?;
+                let burst = match parts.next() {
+                    Some(burst) => burst.trim().parse().with_context(err)?,
+                    None => 0,
+                };
+                (rate, burst)
+            }
+            None => (rate, burst),
+        };
+        Ok(Self::new(rate, burst, now))
+    }
+
+    /// Decide if a line of `len` bytes can be written at `now`.
+    pub fn admit(&mut self, len: usize, now: Instant) -> Admission {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst as f64);
+        self.last = now;
+
+        let len = len as f64;
+        if len > self.tokens {
+            self.dropped += 1;
+            return Admission::Drop;
+        }
+        self.tokens -= len;
+        Admission::Accept(std::mem::take(&mut self.dropped))
+    }
+}
+
+/// The content of the marker record written after `dropped` lines.
+pub fn marker(dropped: u64) -> String {
+    format!(
+        "{} log lines dropped because the log rate limit has been exceeded",
+        dropped
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn new_unlimited() {
+        assert!(LogThrottle::new(0, 10, Instant::now()).is_none());
+    }
+
+    #[test]
+    fn admit_success() -> Result<()> {
+        let now = Instant::now();
+        let mut sut = LogThrottle::new(10, 20, now).context("no throttle")?;
+
+        assert_eq!(sut.admit(15, now), Admission::Accept(0));
+        assert_eq!(sut.admit(10, now), Admission::Drop);
+        assert_eq!(sut.admit(10, now), Admission::Drop);
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(sut.admit(10, later), Admission::Accept(2));
+        assert_eq!(sut.admit(1, later), Admission::Drop);
+        Ok(())
+    }
+
+    #[test]
+    fn admit_burst_cap() -> Result<()> {
+        let now = Instant::now();
+        let mut sut = LogThrottle::new(10, 0, now).context("no throttle")?;
+
+        let later = now + Duration::from_secs(100);
+        assert_eq!(sut.admit(10, later), Admission::Accept(0));
+        assert_eq!(sut.admit(1, later), Admission::Drop);
+        Ok(())
+    }
+
+    #[test]
+    fn from_annotations_success() -> Result<()> {
+        let now = Instant::now();
+        let mut annotations = HashMap::new();
+        assert!(LogThrottle::from_annotations(&annotations, 0, 0, now)?.is_none());
+        assert!(LogThrottle::from_annotations(&annotations, 10, 0, now)?.is_some());
+
+        annotations.insert(LOG_RATE_LIMIT_ANNOTATION.into(), "0".into());
+        assert!(LogThrottle::from_annotations(&annotations, 10, 0, now)?.is_none());
+
+        annotations.insert(LOG_RATE_LIMIT_ANNOTATION.into(), "5:50".into());
+        let sut = LogThrottle::from_annotations(&annotations, 0, 0, now)?.context("no throttle")?;
+        assert_eq!(sut.rate, 5);
+        assert_eq!(sut.burst, 50);
+        Ok(())
+    }
+
+    #[test]
+    fn from_annotations_failure() {
+        let mut annotations = HashMap::new();
+        annotations.insert(LOG_RATE_LIMIT_ANNOTATION.into(), "a:b".into());
+        assert!(LogThrottle::from_annotations(&annotations, 0, 0, Instant::now()).is_err());
+    }
+
+    #[test]
+    fn marker_success() {
+        assert!(marker(3).starts_with("3 log lines dropped"));
+    }
+}
@@ -1,27 +1,488 @@
-use crate::storage::default_key_value_storage::DefaultKeyValueStorage;
+use crate::{
+    admission::AdmissionChain,
+    clock::{Clock, SystemClock},
+    config::Config,
+    container::id_index::IdIndex,
+    container_log::manager::LogManager,
+    diagnostics::ActiveRpcs,
+    event::EventBus,
+    feature::Feature,
+    health::HealthChecks,
+    image::{fs_usage::ImageFsUsage, limiter::PullLimiter, prefetch::Prefetcher},
+    latency::Timeline,
+    listener::peers::Peers,
+    metrics::Metrics,
+    quota::QuotaReservations,
+    reload::LiveConfig,
+    request_log,
+    scheduler::OperationQueue,
+    stats::StatsCache,
+    storage::{default_key_value_storage::DefaultKeyValueStorage, KeyValueStorage},
+    streaming::session::SessionCache,
+    supervisor::Supervisor,
+    telemetry::Tracer,
+    timeout::grpc_timeout,
+};
+use getset::Getters;
+use log::{info, warn};
+use std::{
+    future::Future,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::time;
+use tonic::{Code, Request, Response, Status};
 
-#[derive(Clone)]
-pub struct CRIService {
-    storage: DefaultKeyValueStorage,
+#[derive(Clone, Getters)]
+pub struct CRIService<S = DefaultKeyValueStorage> {
+    #[get = "pub"]
+    config: Arc<Config>,
+
+    #[get = "pub"]
+    storage: S,
+
+    #[get = "pub"]
+    admission: AdmissionChain,
+
+    #[get = "pub"]
+    supervisor: Supervisor,
+
+    #[get = "pub"]
+    streaming: SessionCache,
+
+    #[get = "pub"]
+    logs: LogManager,
+
+    #[get = "pub"]
+    events: EventBus,
+
+    #[get = "pub"]
+    stats: StatsCache,
+
+    #[get = "pub"]
+    rpcs: ActiveRpcs,
+
+    #[get = "pub"]
+    operations: OperationQueue,
+
+    #[get = "pub"]
+    live_config: LiveConfig,
+
+    #[get = "pub"]
+    prefetches: Prefetcher,
+
+    #[get = "pub"]
+    pull_limiter: PullLimiter,
+
+    #[get = "pub"]
+    image_fs: ImageFsUsage,
+
+    #[get = "pub"]
+    clock: Arc<dyn Clock>,
+
+    #[get = "pub"]
+    metrics: Metrics,
+
+    #[get = "pub"]
+    tracer: Tracer,
+
+    #[get = "pub"]
+    peers: Peers,
+
+    #[get = "pub"]
+    health: HealthChecks,
+
+    #[get = "pub"]
+    quota_reservations: QuotaReservations,
+
+    #[get = "pub"]
+    container_ids: Arc<RwLock<IdIndex>>,
+
+    #[cfg(test)]
+    /// The temporary directories of the test fixture, which get removed after the storage has
+    /// been closed.
+    fixture: Option<Arc<tests::Fixture>>,
 }
 
-impl CRIService {
-    pub fn new(storage: DefaultKeyValueStorage) -> Self {
-        Self { storage }
+impl<S: KeyValueStorage> CRIService<S> {
+    pub fn new(config: Arc<Config>, storage: S, admission: AdmissionChain) -> Self {
+        Self::with_clock(config, storage, admission, Arc::new(SystemClock))
+    }
+
+    /// Create a new service whose components read the time from the `clock`.
+    pub fn with_clock(
+        config: Arc<Config>,
+        storage: S,
+        admission: AdmissionChain,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let live_config = LiveConfig::new(config.clone());
+        Self {
+            streaming: SessionCache::new(&config).with_clock(clock.clone()),
+            stats: StatsCache::new(
+                Duration::from_secs(config.stats_interval()),
+                config.stats_history(),
+            )
+            .with_clock(clock.clone()),
+            live_config: live_config.clone(),
+            tracer: Tracer::new(config.otlp_endpoint().clone()).with_clock(clock.clone()),
+            health: HealthChecks::from_specs(config.health_checks(), &live_config),
+            pull_limiter: PullLimiter::new(
+                config.max_concurrent_pulls(),
+                config.max_parallel_layers(),
+            ),
+            image_fs: ImageFsUsage::new(config.image_path()).with_clock(clock.clone()),
+            supervisor: Supervisor::default().with_clock(clock.clone()),
+            logs: LogManager::default().with_clock(clock.clone()),
+            rpcs: ActiveRpcs::default().with_clock(clock.clone()),
+            peers: Peers::default().with_clock(clock.clone()),
+            clock,
+            config,
+            storage,
+            admission,
+            events: EventBus::default(),
+            operations: OperationQueue::default(),
+            prefetches: Prefetcher::default(),
+            metrics: Metrics::default(),
+            quota_reservations: QuotaReservations::default(),
+            container_ids: Arc::default(),
+            #[cfg(test)]
+            fixture: None,
+        }
+    }
+
+    /// Run the handler `f` for the gRPC `method` bounded by its deadline. The handler gets
+    /// cancelled if the deadline is exceeded. The result and latency are recorded as metrics and
+    /// as span, which continues the trace propagated by the client, and counted for the
+    /// connection the request arrived on.
+    pub async fn bounded<R, T, F, Fut>(
+        &self,
+        method: &str,
+        request: Request<R>,
+        f: F,
+    ) -> Result<Response<T>, Status>
+    where
+        F: FnOnce(Request<R>) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let _rpc = self.rpcs().track(method);
+        if let Some(address) = request.remote_addr() {
+            self.peers().record_rpc(address);
+        }
+        let started = self.clock().instant();
+        let span = self.tracer().start(method, request.metadata());
+        let timeout = self.timeout(method, &request);
+        let trace_id = Some(request.metadata())
+            .filter(|_| self.config().features().contains(&Feature::RequestTracing))
+            .and_then(request_log::trace_id);
+        let response = async move {
+            match timeout {
+                Some(timeout) => time::timeout(timeout, f(request)).await.map_err(|_| {
+                    Status::deadline_exceeded(format!(
+                        "{} exceeded deadline of {:?}",
+                        method, timeout
+                    ))
+                })?,
+                None => f(request).await,
+            }
+        };
+        let response = match trace_id {
+            Some(id) => {
+                info!("Tracing {} as request {}", method, id);
+                request_log::scope(id, response).await
+            }
+            None => response.await,
+        };
+        let code = response.as_ref().err().map_or(Code::Ok, Status::code);
+        self.metrics()
+            .observe_rpc(method, code, self.clock().instant() - started);
+        self.tracer().finish(span, code);
+        response
+    }
+
+    /// Report the `timeline` of an operation of the gRPC `method` on the `subject` as slow
+    /// operation if it exceeded the latency budget of the method.
+    pub fn observe_latency(&self, method: &str, subject: &str, timeline: &Timeline) {
+        let budget = match self
+            .config()
+            .latency_budgets()
+            .iter()
+            .find(|x| x.method() == method)
+        {
+            Some(budget) => Duration::from_millis(budget.budget()),
+            None => return,
+        };
+        if let Some(event) = timeline.slow_operation(method, subject, budget) {
+            warn!("{}", event);
+            self.events().publish(event);
+        }
+    }
+
+    /// Retrieve the deadline for the gRPC `method`, whereas a shorter deadline of the client
+    /// takes precedence. Returns `None` if the method is unbounded.
+    fn timeout<R>(&self, method: &str, request: &Request<R>) -> Option<Duration> {
+        let seconds = self
+            .config()
+            .method_timeouts()
+            .iter()
+            .find(|x| x.method() == method)
+            .map(|x| x.timeout())
+            .unwrap_or_else(|| self.config().request_timeout());
+        let server = Some(Duration::from_secs(seconds)).filter(|x| *x > Duration::from_secs(0));
+
+        let client = grpc_timeout(request.metadata()).unwrap_or_else(|e| {
+            warn!("Ignoring client deadline: {:#}", e);
+            None
+        });
+
+        match (server, client) {
+            (Some(s), Some(c)) => Some(s.min(c)),
+            (s, c) => s.or(c),
+        }
     }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::storage::KeyValueStorage;
+    use crate::{
+        admission::Admission, clock::tests::FakeClock, config::ConfigBuilder, event::Event,
+        oci::runtime::tests::fake_runtime,
+    };
     use anyhow::Result;
+    use futures_util::future;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
     use tempfile::TempDir;
 
+    /// The prefix of the temporary directories created by `test_config`.
+    const FIXTURE_PREFIX: &str = "cri-fixture-";
+
+    /// Fixture owns the temporary directories of a test service.
+    pub struct Fixture {
+        /// The directory of the storage, which gets removed last.
+        _storage: TempDir,
+
+        /// The directories of the config created by `test_config`.
+        paths: Vec<PathBuf>,
+    }
+
+    impl Fixture {
+        /// Take over the `storage` directory and the directories of the `config` created by
+        /// `test_config`. Directories provided by the test itself are left alone.
+        fn new(storage: TempDir, config: &Config) -> Self {
+            let paths = [
+                config.sandbox_path(),
+                config.container_path(),
+                config.image_path(),
+                config.cni_config_dir(),
+            ]
+            .iter()
+            .filter(|x| {
+                x.file_name()
+                    .map_or(false, |x| x.to_string_lossy().starts_with(FIXTURE_PREFIX))
+            })
+            .map(|x| x.to_path_buf())
+            .collect();
+            Self {
+                _storage: storage,
+                paths,
+            }
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            for path in &self.paths {
+                fs::remove_dir_all(path).ok();
+            }
+        }
+    }
+
+    /// Create a temporary directory, which gets removed together with the service using it.
+    fn fixture_dir() -> Result<PathBuf> {
+        Ok(tempfile::Builder::new()
+            .prefix(FIXTURE_PREFIX)
+            .tempdir()?
+            .into_path())
+    }
+
+    /// Create a config builder for tests, whose sandboxes and containers live in temporary
+    /// directories. The directories outlive this function, because the sandboxes and containers
+    /// of the test use them later on, and get removed once the service created via
+    /// `new_cri_service_with_config` gets dropped.
+    pub fn test_config() -> Result<ConfigBuilder> {
+        Ok(ConfigBuilder::default()
+            .sandbox_path(fixture_dir()?)
+            .infra_command("sleep 30")
+            .container_path(fixture_dir()?)
+            .image_path(fixture_dir()?)
+            .cni_config_dir(fixture_dir()?))
+    }
+
     pub fn new_cri_service() -> Result<CRIService> {
+        new_cri_service_with_config(test_config()?.build()?)
+    }
+
+    /// Create a new service using a fake OCI runtime in `dir`, which reports all containers as
+    /// `status`.
+    pub fn new_cri_service_with_runtime(dir: &Path, status: &str) -> Result<CRIService> {
+        new_cri_service_with_config(
+            test_config()?
+                .oci_runtime(fake_runtime(dir, status)?)
+                .build()?,
+        )
+    }
+
+    /// Create a new service using the `config`, whose storage lives in a temporary directory.
+    pub fn new_cri_service_with_config(config: Config) -> Result<CRIService> {
+        new_test_service(config, Arc::new(SystemClock))
+    }
+
+    /// Create a new service using the `config`, whose time is based on the fake `clock`.
+    pub fn new_cri_service_with_clock(config: Config, clock: &FakeClock) -> Result<CRIService> {
+        new_test_service(config, Arc::new(clock.clone()))
+    }
+
+    /// Create a new service using the `config` and the `clock`, whose storage lives in a
+    /// temporary directory.
+    fn new_test_service(config: Config, clock: Arc<dyn Clock>) -> Result<CRIService> {
         let dir = TempDir::new()?;
-        Ok(CRIService {
-            storage: DefaultKeyValueStorage::open(dir.path())?,
-        })
+        let mut sut = CRIService::with_clock(
+            Arc::new(config),
+            DefaultKeyValueStorage::open(dir.path())?,
+            AdmissionChain::default(),
+            clock,
+        );
+        sut.fixture = Some(Arc::new(Fixture::new(dir, sut.config())));
+        Ok(sut)
+    }
+
+    pub fn new_cri_service_with_admission(admission: Arc<dyn Admission>) -> Result<CRIService> {
+        let mut sut = new_cri_service()?;
+        sut.admission.push(admission);
+        Ok(sut)
+    }
+
+    #[tokio::test]
+    async fn bounded_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let response = sut
+            .bounded("Version", Request::new(()), |_| async {
+                Ok(Response::new(42))
+            })
+            .await?;
+        assert_eq!(*response.get_ref(), 42);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bounded_success_traced() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            ConfigBuilder::default()
+                .features(vec![Feature::RequestTracing])
+                .build()?,
+        )?;
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(request_log::TRACE_METADATA_KEY, "issue-42".parse()?);
+
+        let response = sut
+            .bounded("Version", request, |_| async {
+                Ok(Response::new(request_log::current()))
+            })
+            .await?;
+        assert_eq!(response.get_ref().as_deref(), Some("issue-42"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bounded_fail_deadline_exceeded() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            ConfigBuilder::default()
+                .method_timeouts(vec!["Version=1".parse()?])
+                .build()?,
+        )?;
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("grpc-timeout", "10m".parse()?);
+
+        let response = sut
+            .bounded("Version", request, |_| async {
+                future::pending::<()>().await;
+                Ok(Response::new(()))
+            })
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::DeadlineExceeded)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn timeout_success() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            ConfigBuilder::default()
+                .request_timeout(60u64)
+                .method_timeouts(vec!["PullImage=0".parse()?, "Status=5".parse()?])
+                .build()?,
+        )?;
+
+        let request = Request::new(());
+        assert_eq!(
+            sut.timeout("Version", &request),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            sut.timeout("Status", &request),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(sut.timeout("PullImage", &request), None);
+
+        let mut request = Request::new(());
+        request.metadata_mut().insert("grpc-timeout", "2S".parse()?);
+        assert_eq!(
+            sut.timeout("Version", &request),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            sut.timeout("PullImage", &request),
+            Some(Duration::from_secs(2))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn observe_latency_success() -> Result<()> {
+        let clock = FakeClock::default();
+        let sut = new_cri_service_with_clock(
+            ConfigBuilder::default()
+                .latency_budgets(vec!["RunPodSandbox=1".parse()?])
+                .build()?,
+            &clock,
+        )?;
+        let mut events = sut.events().subscribe();
+        let mut timeline = Timeline::start(sut.clock().clone());
+        clock.advance(Duration::from_millis(5));
+        timeline.step("cni");
+
+        // Methods without budget are never slow
+        sut.observe_latency("CreateContainer", "container", &timeline);
+        sut.observe_latency("RunPodSandbox", "pod", &timeline);
+        match events.recv().await? {
+            Event::SlowOperation {
+                method, subject, ..
+            } => {
+                assert_eq!(method, "RunPodSandbox");
+                assert_eq!(subject, "pod");
+            }
+            event => anyhow::bail!("unexpected event {:?}", event),
+        }
+        Ok(())
     }
 }
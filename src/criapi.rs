@@ -1 +1,4 @@
+//! The generated Container Runtime Interface API types and services.
+#![allow(missing_docs)]
+
 tonic::include_proto!("runtime.v1alpha2");
@@ -0,0 +1,304 @@
+//! Host device handling for unprivileged containers.
+
+use crate::oci_spec::runtime::{
+    LinuxDevice, LinuxDeviceBuilder, LinuxDeviceCgroup, LinuxDeviceCgroupBuilder,
+};
+use anyhow::{bail, format_err, Context, Result};
+use getset::Getters;
+#[cfg(target_os = "linux")]
+use nix::sys::stat::{self, SFlag};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+/// The annotation which can be used by pods to request host devices. The value is a comma
+/// separated list of `HOST_PATH[:CONTAINER_PATH[:PERMISSIONS]]` entries.
+pub const DEVICES_ANNOTATION: &str = "io.kubernetes.cri.devices";
+
+/// The default cgroup permissions of a requested device.
+const DEFAULT_PERMISSIONS: &str = "rwm";
+
+#[derive(Clone, Debug, Getters)]
+/// Device is a single host device which has been approved to be injected into a container.
+pub struct Device {
+    #[get = "pub"]
+    /// The device node which has to be created inside the container.
+    node: LinuxDevice,
+
+    #[get = "pub"]
+    /// The device cgroup rule which allows the container to access the node.
+    cgroup: LinuxDeviceCgroup,
+}
+
+/// Retrieve all devices requested via the `DEVICES_ANNOTATION` which are part of the `allowlist`.
+/// Every entry of the allowlist can be either a plain path or a glob pattern (supporting `*` and
+/// `?`). The allowlist applies to the host path with all symlinks resolved, so that neither `..`
+/// nor links can escape it. Requesting a device which is not allowed results in an error.
+pub fn allowed_devices(
+    annotations: &HashMap<String, String>,
+    allowlist: &[String],
+) -> Result<Vec<Device>> {
+    match annotations.get(DEVICES_ANNOTATION) {
+        Some(value) => requested_devices(value, allowlist),
+        None => Ok(vec![]),
+    }
+}
+
+/// Retrieve the devices of the comma separated `HOST_PATH[:CONTAINER_PATH[:PERMISSIONS]]`
+/// entries in `value`, which have to be part of the `allowlist` like for `allowed_devices`.
+pub fn requested_devices(value: &str, allowlist: &[String]) -> Result<Vec<Device>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|entry| {
+            let mut parts = entry.splitn(3, ':');
+            let host_path = parts.next().unwrap_or_default();
+            let container_path = parts.next().filter(|x| !x.is_empty()).unwrap_or(host_path);
+            let permissions = parts
+                .next()
+                .filter(|x| !x.is_empty())
+                .unwrap_or(DEFAULT_PERMISSIONS);
+
+            verify_path(host_path).context("invalid device host path")?;
+            verify_path(container_path).context("invalid device container path")?;
+            let resolved = fs::canonicalize(host_path)
+                .with_context(|| format!("resolve device {}", host_path))?;
+            if !allowlist
+                .iter()
+                .any(|x| glob_match(x, &resolved.to_string_lossy()))
+            {
+                bail!(
+                    "device {} ({}) is not part of the allowed devices",
+                    host_path,
+                    resolved.display()
+                )
+            }
+            if permissions.chars().any(|x| !"rwm".contains(x)) {
+                bail!(
+                    "invalid permissions {} for device {}",
+                    permissions,
+                    host_path
+                )
+            }
+
+            Device::new(&resolved, container_path, permissions)
+        })
+        .collect()
+}
+
+impl Device {
+    #[cfg(not(target_os = "linux"))]
+    /// Host devices are only supported on Linux.
+    fn new(host_path: &Path, _: &str, _: &str) -> Result<Self> {
+        bail!(
+            "unable to use device {}: not supported on this platform",
+            host_path.display()
+        )
+    }
+
+    #[cfg(target_os = "linux")]
+    /// Create a new device by inspecting the provided host path.
+    fn new(host_path: &Path, container_path: &str, permissions: &str) -> Result<Self> {
+        let st = stat::stat(host_path)
+            .with_context(|| format!("stat device {}", host_path.display()))?;
+
+        let kind = SFlag::from_bits_truncate(st.st_mode) & SFlag::S_IFMT;
+        let typ = if kind == SFlag::S_IFCHR {
+            "c"
+        } else if kind == SFlag::S_IFBLK {
+            "b"
+        } else {
+            bail!("{} is not a character or block device", host_path.display())
+        };
+        let major = stat::major(st.st_rdev) as i64;
+        let minor = stat::minor(st.st_rdev) as i64;
+
+        Ok(Self {
+            node: LinuxDeviceBuilder::default()
+                .path(PathBuf::from(container_path))
+                .typ(typ)
+                .major(major)
+                .minor(minor)
+                .file_mode(st.st_mode & 0o777)
+                .uid(st.st_uid)
+                .gid(st.st_gid)
+                .build()
+                .map_err(|e| format_err!("build linux device: {}", e))?,
+            cgroup: LinuxDeviceCgroupBuilder::default()
+                .allow(true)
+                .typ(typ)
+                .major(major)
+                .minor(minor)
+                .access(permissions)
+                .build()
+                .map_err(|e| format_err!("build linux device cgroup: {}", e))?,
+        })
+    }
+}
+
+/// Verify that the device `path` is absolute and normalized, which means that it neither
+/// contains `.` or `..` components nor redundant or trailing separators.
+fn verify_path(path: &str) -> Result<()> {
+    let normalized = Path::new(path)
+        .components()
+        .all(|x| matches!(x, Component::RootDir | Component::Normal(_)));
+    if !Path::new(path).is_absolute()
+        || !normalized
+        || Path::new(path).components().collect::<PathBuf>().to_str() != Some(path)
+    {
+        bail!("{} is not an absolute and normalized path", path)
+    }
+    Ok(())
+}
+
+/// Match the provided `text` against a shell like `pattern`, whereas `*` matches any sequence of
+/// characters and `?` matches exactly a single character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack = None;
+
+    while ti < t.len() {
+        match p.get(pi) {
+            Some('*') => {
+                backtrack = Some((pi, ti));
+                pi += 1;
+            }
+            Some(&c) if c == '?' || c == t[ti] => {
+                pi += 1;
+                ti += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    pi = bp + 1;
+                    ti = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotations(value: &str) -> HashMap<String, String> {
+        let mut annotations = HashMap::new();
+        annotations.insert(DEVICES_ANNOTATION.into(), value.into());
+        annotations
+    }
+
+    #[test]
+    fn glob_match_success() {
+        assert!(glob_match("/dev/fuse", "/dev/fuse"));
+        assert!(glob_match("/dev/nvidia*", "/dev/nvidia0"));
+        assert!(glob_match("/dev/nvidia*", "/dev/nvidia"));
+        assert!(glob_match("/dev/tty?", "/dev/tty1"));
+        assert!(glob_match("/dev/*/a*b", "/dev/x/aXXbYb"));
+    }
+
+    #[test]
+    fn glob_match_failure() {
+        assert!(!glob_match("/dev/fuse", "/dev/fuse0"));
+        assert!(!glob_match("/dev/nvidia*", "/dev/null"));
+        assert!(!glob_match("/dev/tty?", "/dev/tty12"));
+        assert!(!glob_match("/dev/*/a*b", "/dev/x/aXXbYc"));
+    }
+
+    #[test]
+    fn allowed_devices_no_annotation() -> Result<()> {
+        let devices = allowed_devices(&HashMap::new(), &["/dev/*".into()])?;
+        assert!(devices.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn allowed_devices_success() -> Result<()> {
+        let devices = allowed_devices(&annotations("/dev/null:/dev/foo:rw"), &["/dev/nu*".into()])?;
+
+        assert_eq!(devices.len(), 1);
+        let device = &devices[0];
+        assert_eq!(device.node().path(), &PathBuf::from("/dev/foo"));
+        assert_eq!(device.node().typ(), "c");
+        assert_eq!(device.node().major(), 1);
+        assert_eq!(device.node().minor(), 3);
+        assert_eq!(device.cgroup().access().as_deref(), Some("rw"));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn allowed_devices_success_defaults() -> Result<()> {
+        let devices = allowed_devices(&annotations("/dev/null"), &["/dev/null".into()])?;
+
+        assert_eq!(devices.len(), 1);
+        let device = &devices[0];
+        assert_eq!(device.node().path(), &PathBuf::from("/dev/null"));
+        assert_eq!(
+            device.cgroup().access().as_deref(),
+            Some(DEFAULT_PERMISSIONS)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn allowed_devices_fail_not_allowed() {
+        assert!(allowed_devices(&annotations("/dev/null"), &["/dev/fuse".into()]).is_err());
+        assert!(allowed_devices(&annotations("/dev/null"), &[]).is_err());
+    }
+
+    #[test]
+    fn allowed_devices_fail_invalid_permissions() {
+        assert!(
+            allowed_devices(&annotations("/dev/null:/dev/null:rwx"), &["/dev/*".into()]).is_err()
+        );
+    }
+
+    #[test]
+    fn allowed_devices_fail_not_normalized() {
+        for value in &[
+            "/dev/../dev/null",
+            "/dev/./null",
+            "/dev//null",
+            "dev/null",
+            "/dev/null:/dev/../foo",
+            "/dev/null:foo",
+        ] {
+            assert!(
+                allowed_devices(&annotations(value), &["*".into()]).is_err(),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn allowed_devices_fail_symlink() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let link = dir.path().join("null");
+        std::os::unix::fs::symlink("/dev/null", &link)?;
+        let value = link.display().to_string();
+
+        // The allowlist applies to the resolved path rather than to the link
+        let allowlist = [format!("{}/*", dir.path().display())];
+        assert!(allowed_devices(&annotations(&value), &allowlist).is_err());
+        let devices = allowed_devices(&annotations(&value), &["/dev/null".into()])?;
+        assert_eq!(devices[0].node().path(), &link);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn allowed_devices_fail_no_device() {
+        assert!(allowed_devices(&annotations("/dev"), &["/dev*".into()]).is_err());
+    }
+}
@@ -0,0 +1,363 @@
+//! Diagnostic dumps of the runtime state
+//!
+//! A dump is a snapshot of everything which helps to debug a hanging or misbehaving server: the
+//! RPCs in flight, the connected clients, the queued operations per pod, the holder of the
+//! storage lock, the health of all background tasks and a summary of the stored pod sandboxes,
+//! containers and images including their recent resource usage. The server writes a dump
+//! whenever it receives SIGUSR1.
+
+use crate::{
+    clock::{Clock, SystemClock},
+    config::StorageBackend,
+    container::{Container, ContainerState},
+    container_log::format::timestamp,
+    cri_service::CRIService,
+    image::store::ImageStore,
+    listener::peers::Peer,
+    sandbox::{infra::InfraSandbox, Sandbox},
+    stats::UsageSample,
+    storage::{lock::LOCK_FILE, KeyValueStorage},
+    supervisor::TaskHealth,
+};
+use anyhow::{Context, Result};
+use log::info;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Clone)]
+/// ActiveRpcs keeps track of the RPCs which are currently being handled.
+pub struct ActiveRpcs {
+    /// The identifier of the next tracked RPC.
+    next: Arc<AtomicU64>,
+
+    /// The method and start time of all RPCs in flight by their identifier.
+    rpcs: Arc<Mutex<BTreeMap<u64, (String, Instant)>>>,
+
+    /// The clock the time since the arrival of the RPCs is measured with.
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for ActiveRpcs {
+    fn default() -> Self {
+        Self {
+            next: Arc::default(),
+            rpcs: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+/// RpcGuard removes the tracked RPC when being dropped.
+pub struct RpcGuard {
+    /// The identifier of the tracked RPC.
+    id: u64,
+
+    /// The tracker the RPC belongs to.
+    rpcs: ActiveRpcs,
+}
+
+impl Drop for RpcGuard {
+    fn drop(&mut self) {
+        if let Ok(mut rpcs) = self.rpcs.rpcs.lock() {
+            rpcs.remove(&self.id);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+/// An RPC which is currently being handled.
+pub struct ActiveRpc {
+    /// The gRPC method of the request.
+    method: String,
+
+    /// The time since the request has been received in milliseconds.
+    elapsed_ms: u64,
+}
+
+impl ActiveRpcs {
+    /// Measure the time since the arrival of the RPCs with the `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Track the RPC to `method` until the returned guard gets dropped.
+    pub fn track(&self, method: &str) -> RpcGuard {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut rpcs) = self.rpcs.lock() {
+            rpcs.insert(id, (method.into(), self.clock.instant()));
+        }
+        RpcGuard {
+            id,
+            rpcs: self.clone(),
+        }
+    }
+
+    /// Retrieve all RPCs in flight, ordered by their arrival.
+    pub fn list(&self) -> Vec<ActiveRpc> {
+        let now = self.clock.instant();
+        self.rpcs
+            .lock()
+            .map(|x| {
+                x.values()
+                    .map(|(method, started)| ActiveRpc {
+                        method: method.clone(),
+                        elapsed_ms: (now - *started).as_millis() as u64,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// Dump is the diagnostic snapshot of the runtime.
+pub struct Dump {
+    #[serde(skip)]
+    /// The time the dump has been collected, which names the written file.
+    collected: SystemTime,
+
+    /// The time the dump has been collected.
+    timestamp: String,
+
+    /// The process ID of the server.
+    pid: u32,
+
+    /// The RPCs in flight.
+    active_rpcs: Vec<ActiveRpc>,
+
+    /// The clients connected via unix domain sockets.
+    peers: Vec<Peer>,
+
+    /// The number of pending and running operations by pod sandbox ID.
+    operations: BTreeMap<String, usize>,
+
+    /// The locks held by the server and their holders.
+    locks: Vec<LockHolder>,
+
+    /// The health of all background tasks by their name.
+    tasks: BTreeMap<String, TaskHealth>,
+
+    /// Statistics of the storage.
+    storage: StorageStats,
+
+    /// A summary of all pod sandboxes.
+    sandboxes: Vec<SandboxSummary>,
+
+    /// A summary of all containers.
+    containers: Vec<ContainerSummary>,
+}
+
+#[derive(Debug, Serialize)]
+/// A lock held by a process.
+pub struct LockHolder {
+    /// The path of the lock file.
+    path: PathBuf,
+
+    /// The process ID of the holder.
+    pid: u32,
+}
+
+#[derive(Debug, Serialize)]
+/// The number of records in the storage by their type.
+pub struct StorageStats {
+    /// The configured backend.
+    backend: StorageBackend,
+
+    /// The number of stored pod sandboxes.
+    sandboxes: usize,
+
+    /// The number of stored containers.
+    containers: usize,
+
+    /// The number of stored images.
+    images: usize,
+}
+
+#[derive(Debug, Serialize)]
+/// The summary of a pod sandbox.
+pub struct SandboxSummary {
+    /// The ID of the sandbox.
+    id: String,
+
+    /// The namespace and name of the sandbox.
+    name: String,
+
+    /// The readiness of the sandbox, which is `None` if it could not be checked.
+    ready: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+/// The summary of a container.
+pub struct ContainerSummary {
+    /// The ID of the container.
+    id: String,
+
+    /// The ID of the pod sandbox the container belongs to.
+    pod_sandbox_id: String,
+
+    /// The name and the creation attempt of the container.
+    name: String,
+
+    /// The current lifecycle state.
+    state: ContainerState,
+
+    /// The image reference of the container.
+    image: String,
+
+    /// The recent resource usage of the container, oldest first.
+    stats_history: Vec<UsageSample>,
+}
+
+impl Dump {
+    /// Collect a diagnostic dump of the `cri_service`.
+    pub fn collect<S: KeyValueStorage>(cri_service: &CRIService<S>) -> Result<Self> {
+        let mut storage = cri_service.storage().clone();
+        let mut sandboxes = storage
+            .scan_prefix::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key_prefix())
+            .context("list pod sandboxes")?;
+        let containers = storage
+            .scan_prefix::<_, Container>(Container::key_prefix())
+            .context("list containers")?;
+        let images = ImageStore::list(&mut storage).context("list images")?;
+
+        let collected = cri_service.clock().now();
+        Ok(Self {
+            collected,
+            timestamp: timestamp(collected),
+            pid: process::id(),
+            active_rpcs: cri_service.rpcs().list(),
+            peers: cri_service.peers().list(),
+            operations: cri_service.operations().depths(),
+            locks: vec![LockHolder {
+                path: cri_service.config().storage_path().join(LOCK_FILE),
+                pid: process::id(),
+            }],
+            tasks: cri_service.supervisor().health(),
+            storage: StorageStats {
+                backend: cri_service.config().storage_backend(),
+                sandboxes: sandboxes.len(),
+                containers: containers.len(),
+                images: images.len(),
+            },
+            sandboxes: sandboxes
+                .iter_mut()
+                .map(|x| {
+                    let metadata = x.data().metadata();
+                    SandboxSummary {
+                        id: x.id().into(),
+                        name: format!("{}/{}", metadata.namespace, metadata.name),
+                        ready: x.ready().ok(),
+                    }
+                })
+                .collect(),
+            containers: containers
+                .iter()
+                .map(|x| ContainerSummary {
+                    id: x.id().clone(),
+                    pod_sandbox_id: x.pod_sandbox_id().clone(),
+                    name: format!("{}.{}", x.name(), x.attempt()),
+                    state: x.state(),
+                    image: x.image().clone(),
+                    stats_history: cri_service.stats().history(x.id()),
+                })
+                .collect(),
+        })
+    }
+
+    /// Write the dump as JSON into a new file inside the directory `path`, or to the log if no
+    /// directory is provided.
+    pub fn write(&self, path: Option<&Path>) -> Result<()> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                let content = serde_json::to_string(self).context("serialize dump")?;
+                info!("Diagnostic dump: {}", content);
+                return Ok(());
+            }
+        };
+
+        fs::create_dir_all(path)
+            .with_context(|| format!("create diagnostics path {}", path.display()))?;
+        let created = self
+            .collected
+            .duration_since(UNIX_EPOCH)
+            .context("get current time")?
+            .as_millis();
+        let file = path.join(format!("diagnostics-{}.json", created));
+        let content = serde_json::to_vec_pretty(self).context("serialize dump")?;
+        fs::write(&file, content).with_context(|| format!("write {}", file.display()))?;
+        info!("Wrote diagnostic dump to {}", file.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::tests::FakeClock, cri_service::tests::new_cri_service,
+        runtime_service::run_pod_sandbox::tests::new_pod_sandbox,
+    };
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn track_success() {
+        let clock = FakeClock::default();
+        let sut = ActiveRpcs::default().with_clock(Arc::new(clock.clone()));
+        let first = sut.track("Version");
+        clock.advance(Duration::from_millis(20));
+        let second = sut.track("PullImage");
+        assert_eq!(
+            sut.list()
+                .into_iter()
+                .map(|x| (x.method, x.elapsed_ms))
+                .collect::<Vec<_>>(),
+            vec![("Version".into(), 20), ("PullImage".into(), 0)]
+        );
+
+        drop(first);
+        assert_eq!(sut.list().len(), 1);
+        drop(second);
+        assert!(sut.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn collect_write_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        let _rpc = sut.rpcs().track("ListContainers");
+        let _peer = sut.peers().register("/run/cri.sock", None);
+
+        let dump = Dump::collect(&sut)?;
+        assert_eq!(dump.pid, process::id());
+        assert_eq!(dump.active_rpcs.len(), 1);
+        assert_eq!(dump.peers.len(), 1);
+        assert!(dump.operations.is_empty());
+        assert_eq!(dump.storage.sandboxes, 1);
+        assert_eq!(dump.storage.containers, 0);
+        assert_eq!(dump.sandboxes[0].id, sandbox_id);
+
+        let dir = tempdir()?;
+        dump.write(Some(dir.path()))?;
+        let file = fs::read_dir(dir.path())?
+            .next()
+            .context("no dump written")??
+            .path();
+        let content: serde_json::Value = serde_json::from_slice(&fs::read(file)?)?;
+        assert_eq!(content["active_rpcs"][0]["method"], "ListContainers");
+        assert_eq!(content["sandboxes"][0]["id"], sandbox_id.as_str());
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+//! Structured details for gRPC error statuses
+//!
+//! The details are encoded as `google.rpc.Status` into the `grpc-status-details-bin` trailer,
+//! which is the standard gRPC mechanism for rich errors. The most important details get
+//! additionally appended to the status message, because the kubelet only forwards the message
+//! into the pod events.
+
+use log::warn;
+use prost::Message;
+use std::collections::HashMap;
+use tonic::{Code, Status};
+
+/// The error domain of the details.
+const DOMAIN: &str = "cri";
+
+/// The maximum size of the stderr excerpt in bytes.
+const MAX_STDERR: usize = 1024;
+
+#[derive(Debug, Default)]
+/// ErrorDetails describes why and where a request failed.
+pub struct ErrorDetails {
+    /// The step of the request which failed, like `run pod sandbox`.
+    step: String,
+
+    /// The underlying command which failed.
+    command: Option<String>,
+
+    /// The stderr output of the failed command.
+    stderr: Option<String>,
+
+    /// A hint which explains how to resolve the error.
+    hint: Option<String>,
+}
+
+impl ErrorDetails {
+    /// Create new error details for the failed `step`.
+    pub fn new<S: Into<String>>(step: S) -> Self {
+        Self {
+            step: step.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the underlying command which failed.
+    pub fn command<S: Into<String>>(mut self, command: S) -> Self {
+        self.command = Some(command.into());
+        self
+    }
+
+    /// Set the stderr output of the failed command.
+    pub fn stderr<S: Into<String>>(mut self, stderr: S) -> Self {
+        self.stderr = Some(stderr.into());
+        self
+    }
+
+    /// Set a hint which explains how to resolve the error.
+    pub fn hint<S: Into<String>>(mut self, hint: S) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Create a new `Status` with the provided `code` and `message`, which contains the details.
+    pub fn status<M: std::fmt::Display>(&self, code: Code, message: M) -> Status {
+        let mut message = format!("{}: {}", self.step, message);
+        if let Some(hint) = &self.hint {
+            message.push_str(&format!(" (hint: {})", hint));
+        }
+
+        let details = RpcStatus {
+            code: code as i32,
+            message: message.clone(),
+            details: self.details(),
+        };
+        let mut buf = Vec::with_capacity(details.encoded_len());
+        match details.encode(&mut buf) {
+            Ok(()) => Status::with_details(code, message, buf.into()),
+            Err(e) => {
+                warn!("Unable to encode error details: {}", e);
+                Status::new(code, message)
+            }
+        }
+    }
+
+    /// Build the details as `Any` messages.
+    fn details(&self) -> Vec<Any> {
+        let mut metadata = HashMap::new();
+        if let Some(command) = &self.command {
+            metadata.insert("command".into(), command.clone());
+        }
+        if let Some(hint) = &self.hint {
+            metadata.insert("hint".into(), hint.clone());
+        }
+        let mut details = vec![Any::pack(
+            "google.rpc.ErrorInfo",
+            &ErrorInfo {
+                reason: self.step.clone(),
+                domain: DOMAIN.into(),
+                metadata,
+            },
+        )];
+
+        if let Some(stderr) = &self.stderr {
+            details.push(Any::pack(
+                "google.rpc.DebugInfo",
+                &DebugInfo {
+                    stack_entries: vec![],
+                    detail: excerpt(stderr).into(),
+                },
+            ));
+        }
+        details
+    }
+}
+
+/// Retrieve the last `MAX_STDERR` bytes of `stderr`, since the end usually contains the cause.
+fn excerpt(stderr: &str) -> &str {
+    let stderr = stderr.trim_end();
+    if stderr.len() <= MAX_STDERR {
+        return stderr;
+    }
+    let mut start = stderr.len() - MAX_STDERR;
+    while !stderr.is_char_boundary(start) {
+        start += 1;
+    }
+    &stderr[start..]
+}
+
+#[derive(Clone, PartialEq, Message)]
+/// The `google.rpc.Status` message.
+pub struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    /// The status code.
+    pub code: i32,
+
+    #[prost(string, tag = "2")]
+    /// The error message.
+    pub message: String,
+
+    #[prost(message, repeated, tag = "3")]
+    /// The error details.
+    pub details: Vec<Any>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+/// The `google.protobuf.Any` message.
+pub struct Any {
+    #[prost(string, tag = "1")]
+    /// The type URL of the serialized message.
+    pub type_url: String,
+
+    #[prost(bytes, tag = "2")]
+    /// The serialized message.
+    pub value: Vec<u8>,
+}
+
+impl Any {
+    /// Serialize the `message` of the fully qualified type `name`.
+    fn pack<M: Message>(name: &str, message: &M) -> Self {
+        let mut value = Vec::with_capacity(message.encoded_len());
+        if let Err(e) = message.encode(&mut value) {
+            warn!("Unable to encode {}: {}", name, e);
+        }
+        Self {
+            type_url: format!("type.googleapis.com/{}", name),
+            value,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+/// The `google.rpc.ErrorInfo` message.
+pub struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    /// The reason of the error.
+    pub reason: String,
+
+    #[prost(string, tag = "2")]
+    /// The logical grouping to which the reason belongs.
+    pub domain: String,
+
+    #[prost(map = "string, string", tag = "3")]
+    /// Additional structured details.
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+/// The `google.rpc.DebugInfo` message.
+pub struct DebugInfo {
+    #[prost(string, repeated, tag = "1")]
+    /// The stack trace entries.
+    pub stack_entries: Vec<String>,
+
+    #[prost(string, tag = "2")]
+    /// Additional debugging information.
+    pub detail: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, Result};
+
+    #[test]
+    fn status_success() -> Result<()> {
+        let status = ErrorDetails::new("run pod sandbox")
+            .command("runc create")
+            .stderr("some output\nfailed to mount\n")
+            .hint("check the mounts")
+            .status(Code::Internal, "exit status 1");
+
+        assert_eq!(status.code(), Code::Internal);
+        assert_eq!(
+            status.message(),
+            "run pod sandbox: exit status 1 (hint: check the mounts)"
+        );
+
+        let details = RpcStatus::decode(status.details())?;
+        assert_eq!(details.code, Code::Internal as i32);
+        assert_eq!(details.message, status.message());
+        assert_eq!(details.details.len(), 2);
+
+        let error_info = details.details.get(0).context("no error info")?;
+        assert_eq!(
+            error_info.type_url,
+            "type.googleapis.com/google.rpc.ErrorInfo"
+        );
+        let error_info = ErrorInfo::decode(error_info.value.as_slice())?;
+        assert_eq!(error_info.reason, "run pod sandbox");
+        assert_eq!(error_info.domain, DOMAIN);
+        assert_eq!(
+            error_info.metadata.get("command").map(String::as_str),
+            Some("runc create")
+        );
+
+        let debug_info = details.details.get(1).context("no debug info")?;
+        let debug_info = DebugInfo::decode(debug_info.value.as_slice())?;
+        assert_eq!(debug_info.detail, "some output\nfailed to mount");
+        Ok(())
+    }
+
+    #[test]
+    fn status_success_minimal() -> Result<()> {
+        let status = ErrorDetails::new("validate resources")
+            .status(Code::ResourceExhausted, "not enough memory");
+
+        assert_eq!(status.message(), "validate resources: not enough memory");
+        let details = RpcStatus::decode(status.details())?;
+        assert_eq!(details.details.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn excerpt_success() {
+        assert_eq!(excerpt("short\n"), "short");
+
+        let long = format!("{}end", "ä".repeat(MAX_STDERR));
+        let res = excerpt(&long);
+        assert!(res.len() <= MAX_STDERR);
+        assert!(res.ends_with("end"));
+    }
+}
@@ -0,0 +1,373 @@
+//! The internal event bus, which notifies subscribers about the progress of runtime operations.
+
+use std::{collections::HashMap, fmt, time::Duration};
+use strum::AsRefStr;
+use tokio::sync::broadcast;
+
+/// The number of events a slow subscriber can lag behind before it misses events.
+const CAPACITY: usize = 256;
+
+#[derive(AsRefStr, Clone, Debug, PartialEq)]
+/// An Event describes a step of a runtime operation.
+pub enum Event {
+    /// The pull of an image started.
+    PullStarted {
+        /// The reference of the pulled image.
+        image: String,
+    },
+
+    /// A layer of a pulled image is available.
+    PullProgress {
+        /// The reference of the pulled image.
+        image: String,
+
+        /// The digest of the available layer.
+        layer: String,
+
+        /// The number of layers which are available.
+        completed_layers: usize,
+
+        /// The number of layers of the image.
+        total_layers: usize,
+
+        /// The compressed size of the available layers in bytes.
+        completed_bytes: u64,
+
+        /// The compressed size of all layers in bytes.
+        total_bytes: u64,
+    },
+
+    /// A stage of the pull of an image finished, which tells whether resolving the manifest,
+    /// fetching the blobs or unpacking the layers dominates the pull time.
+    PullStage {
+        /// The reference of the pulled image.
+        image: String,
+
+        /// The finished stage.
+        stage: PullStage,
+
+        /// The digest of the resolved manifest, the fetched blob or the unpacked layer.
+        digest: String,
+
+        /// The size of the manifest, the blob or the compressed layer in bytes.
+        bytes: u64,
+
+        /// The time the stage took.
+        elapsed: Duration,
+    },
+
+    /// The pull of an image finished successfully.
+    PullCompleted {
+        /// The reference of the pulled image.
+        image: String,
+
+        /// The ID of the pulled image.
+        id: String,
+    },
+
+    /// The pull of an image failed.
+    PullFailed {
+        /// The reference of the image.
+        image: String,
+
+        /// The error which caused the failure.
+        cause: String,
+    },
+
+    /// An exited container got removed by the garbage collection of exited containers.
+    ContainerCollected {
+        /// The ID of the removed container.
+        id: String,
+
+        /// The ID of the pod sandbox of the container.
+        pod_sandbox_id: String,
+
+        /// The reason of the removal, like `max_age`.
+        reason: String,
+    },
+
+    /// An operation exceeded the latency budget of its method.
+    SlowOperation {
+        /// The gRPC method of the operation, like `RunPodSandbox`.
+        method: String,
+
+        /// The object the operation worked on, like the pod sandbox.
+        subject: String,
+
+        /// The time the operation took.
+        elapsed: Duration,
+
+        /// The latency budget of the method.
+        budget: Duration,
+
+        /// The steps of the operation with their durations in execution order.
+        steps: Vec<(String, Duration)>,
+    },
+
+    /// The recording of an exec or attach session is complete.
+    SessionRecorded {
+        /// The kind of the session, like `exec`.
+        kind: String,
+
+        /// The ID of the container the session was connected to.
+        container_id: String,
+
+        /// The path of the recording file.
+        path: String,
+    },
+}
+
+#[derive(AsRefStr, Clone, Copy, Debug, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+/// PullStage is a stage of the pull of an image.
+pub enum PullStage {
+    /// Retrieving the manifest of the image from the registry.
+    Resolve,
+
+    /// Downloading a blob, like the image config or a compressed layer.
+    Fetch,
+
+    /// Unpacking a compressed layer.
+    Unpack,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::PullStarted { image } => write!(f, "Pulling image {}", image),
+            Event::PullProgress {
+                image,
+                completed_layers,
+                total_layers,
+                completed_bytes,
+                total_bytes,
+                ..
+            } => write!(
+                f,
+                "Pulling image {}: {}/{} layers, {}/{} bytes",
+                image, completed_layers, total_layers, completed_bytes, total_bytes
+            ),
+            Event::PullStage {
+                image,
+                stage,
+                digest,
+                bytes,
+                elapsed,
+            } => write!(
+                f,
+                "Pulling image {}: {} {} of {} bytes took {:?}",
+                image,
+                stage.as_ref(),
+                digest,
+                bytes,
+                elapsed
+            ),
+            Event::PullCompleted { image, id } => write!(f, "Pulled image {} as {}", image, id),
+            Event::PullFailed { image, cause } => {
+                write!(f, "Failed to pull image {}: {}", image, cause)
+            }
+            Event::ContainerCollected {
+                id,
+                pod_sandbox_id,
+                reason,
+            } => write!(
+                f,
+                "Collected exited container {} of pod sandbox {}: {}",
+                id, pod_sandbox_id, reason
+            ),
+            Event::SlowOperation {
+                method,
+                subject,
+                elapsed,
+                budget,
+                steps,
+            } => {
+                write!(
+                    f,
+                    "Slow operation {} of {}: elapsed={:?} budget={:?}",
+                    method, subject, elapsed, budget
+                )?;
+                if let Some((name, _)) = steps.iter().max_by_key(|(_, x)| *x) {
+                    write!(f, " slowest={}", name)?;
+                }
+                for (name, duration) in steps {
+                    write!(f, " {}={:?}", name, duration)?;
+                }
+                Ok(())
+            }
+            Event::SessionRecorded {
+                kind,
+                container_id,
+                path,
+            } => write!(
+                f,
+                "Recorded {} session of container {} to {}",
+                kind, container_id, path
+            ),
+        }
+    }
+}
+
+impl Event {
+    /// Retrieve the structured fields of the event, which allow observers to display the progress
+    /// of an operation without parsing its description.
+    pub fn fields(&self) -> HashMap<String, String> {
+        let fields: Vec<(&str, String)> = match self {
+            Event::PullStarted { image } => vec![("image", image.clone())],
+            Event::PullProgress {
+                image,
+                layer,
+                completed_layers,
+                total_layers,
+                completed_bytes,
+                total_bytes,
+            } => vec![
+                ("image", image.clone()),
+                ("layer", layer.clone()),
+                ("completed_layers", completed_layers.to_string()),
+                ("total_layers", total_layers.to_string()),
+                ("completed_bytes", completed_bytes.to_string()),
+                ("total_bytes", total_bytes.to_string()),
+            ],
+            Event::PullStage {
+                image,
+                stage,
+                digest,
+                bytes,
+                elapsed,
+            } => vec![
+                ("image", image.clone()),
+                ("stage", stage.as_ref().into()),
+                ("digest", digest.clone()),
+                ("bytes", bytes.to_string()),
+                ("elapsed_ms", elapsed.as_millis().to_string()),
+            ],
+            Event::PullCompleted { image, id } => {
+                vec![("image", image.clone()), ("id", id.clone())]
+            }
+            Event::PullFailed { image, cause } => {
+                vec![("image", image.clone()), ("cause", cause.clone())]
+            }
+            Event::ContainerCollected {
+                id,
+                pod_sandbox_id,
+                reason,
+            } => vec![
+                ("id", id.clone()),
+                ("pod_sandbox_id", pod_sandbox_id.clone()),
+                ("reason", reason.clone()),
+            ],
+            Event::SlowOperation {
+                method,
+                subject,
+                elapsed,
+                budget,
+                ..
+            } => vec![
+                ("method", method.clone()),
+                ("subject", subject.clone()),
+                ("elapsed_ms", elapsed.as_millis().to_string()),
+                ("budget_ms", budget.as_millis().to_string()),
+            ],
+            Event::SessionRecorded {
+                kind,
+                container_id,
+                path,
+            } => vec![
+                ("kind", kind.clone()),
+                ("container_id", container_id.clone()),
+                ("path", path.clone()),
+            ],
+        };
+        fields.into_iter().map(|(k, v)| (k.into(), v)).collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+/// EventBus distributes the published events to all current subscribers.
+pub struct EventBus {
+    /// The sending side of the broadcast channel, which creates the receivers as well.
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+}
+
+impl EventBus {
+    /// Publish the `event` to all subscribers. Events without subscribers get discarded.
+    pub fn publish(&self, event: Event) {
+        self.sender.send(event).ok();
+    }
+
+    /// Subscribe to all events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn publish_subscribe() -> Result<()> {
+        let sut = EventBus::default();
+        sut.publish(Event::PullStarted {
+            image: "unobserved".into(),
+        });
+
+        let mut events = sut.subscribe();
+        let event = Event::PullFailed {
+            image: "app".into(),
+            cause: "not found".into(),
+        };
+        sut.clone().publish(event.clone());
+        assert_eq!(events.recv().await?, event);
+        assert_eq!(event.to_string(), "Failed to pull image app: not found");
+        Ok(())
+    }
+
+    #[test]
+    fn fields_success() {
+        let event = Event::PullProgress {
+            image: "app".into(),
+            layer: "sha256:abc".into(),
+            completed_layers: 1,
+            total_layers: 3,
+            completed_bytes: 100,
+            total_bytes: 300,
+        };
+        let fields = event.fields();
+        assert_eq!(fields.get("image").map(String::as_str), Some("app"));
+        assert_eq!(
+            fields.get("completed_layers").map(String::as_str),
+            Some("1")
+        );
+        assert_eq!(fields.get("total_bytes").map(String::as_str), Some("300"));
+        assert_eq!(fields.len(), 6);
+    }
+
+    #[test]
+    fn fields_success_pull_stage() {
+        let event = Event::PullStage {
+            image: "app".into(),
+            stage: PullStage::Unpack,
+            digest: "sha256:abc".into(),
+            bytes: 100,
+            elapsed: Duration::from_millis(1500),
+        };
+        let fields = event.fields();
+        assert_eq!(fields.get("stage").map(String::as_str), Some("unpack"));
+        assert_eq!(fields.get("bytes").map(String::as_str), Some("100"));
+        assert_eq!(fields.get("elapsed_ms").map(String::as_str), Some("1500"));
+        assert_eq!(
+            event.to_string(),
+            "Pulling image app: unpack sha256:abc of 100 bytes took 1.5s"
+        );
+    }
+}
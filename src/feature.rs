@@ -0,0 +1,98 @@
+//! Runtime feature flags
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use strum::{AsRefStr, EnumIter, EnumString, IntoEnumIterator};
+
+#[derive(
+    AsRefStr, Clone, Copy, Debug, Deserialize, EnumIter, EnumString, Eq, PartialEq, Serialize,
+)]
+/// Defines the optional features of the runtime, which can be enabled via the configuration.
+pub enum Feature {
+    #[strum(serialize = "userns")]
+    /// Running pods in user namespaces, which is not supported yet.
+    UserNamespaces,
+
+    #[strum(serialize = "checkpoint")]
+    /// Checkpointing and restoring the containers of pods via the admin service.
+    Checkpointing,
+
+    #[strum(serialize = "lazy-pull")]
+    /// Starting containers before their image has been fully pulled, which is not supported yet
+    /// because every layer gets unpacked before a container can use it.
+    LazyPulls,
+
+    #[strum(serialize = "nri")]
+    /// Node Resource Interface plugins, which are not supported yet.
+    Nri,
+
+    #[strum(serialize = "request-trace")]
+    /// Trace logging of single requests via the `cri-trace` metadata key.
+    RequestTracing,
+
+    #[strum(serialize = "hardened")]
+    /// Additional masked paths and no pseudo filesystem mounts for all containers, including
+    /// privileged ones.
+    Hardened,
+}
+
+impl Feature {
+    /// Returns true if the feature is implemented, whereas the others are only reserved and can
+    /// not be enabled.
+    pub fn is_supported(self) -> bool {
+        !matches!(
+            self,
+            Feature::UserNamespaces | Feature::LazyPulls | Feature::Nri
+        )
+    }
+
+    /// Retrieve the enablement state of all known features, whereas `enabled` contains the
+    /// features which are enabled.
+    pub fn states(enabled: &[Feature]) -> BTreeMap<String, bool> {
+        Self::iter()
+            .map(|x| (x.as_ref().into(), enabled.contains(&x)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn from_str_success() -> Result<()> {
+        assert_eq!(Feature::from_str("userns")?, Feature::UserNamespaces);
+        assert_eq!(Feature::from_str("lazy-pull")?, Feature::LazyPulls);
+        assert_eq!(Feature::from_str("request-trace")?, Feature::RequestTracing);
+        assert_eq!(Feature::from_str("hardened")?, Feature::Hardened);
+        assert!(Feature::from_str("wrong").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn is_supported() {
+        assert!(Feature::Checkpointing.is_supported());
+        assert!(Feature::Hardened.is_supported());
+        assert!(!Feature::UserNamespaces.is_supported());
+        assert!(!Feature::LazyPulls.is_supported());
+        assert!(!Feature::Nri.is_supported());
+    }
+
+    #[test]
+    fn states_none_enabled() {
+        let states = Feature::states(&[]);
+        assert_eq!(states.len(), Feature::iter().count());
+        assert!(states.values().all(|x| !x));
+    }
+
+    #[test]
+    fn states_enabled() {
+        let states = Feature::states(&[Feature::Checkpointing, Feature::Nri]);
+        assert_eq!(states.get("checkpoint"), Some(&true));
+        assert_eq!(states.get("nri"), Some(&true));
+        assert_eq!(states.get("userns"), Some(&false));
+        assert_eq!(states.get("lazy-pull"), Some(&false));
+    }
+}
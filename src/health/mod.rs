@@ -0,0 +1,206 @@
+//! Custom health checks of the runtime
+//!
+//! Health checks are run on every Status request and reported as additional runtime conditions,
+//! which allows the node-problem-detector to observe problems the runtime knows about. Besides
+//! the built-in probes, checks can be provided by scripts. Every check is bounded by a timeout,
+//! so checks should be cheap.
+
+use crate::criapi::RuntimeCondition;
+use anyhow::{bail, Error, Result};
+use futures_util::future;
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+use tokio::time;
+
+pub mod probes;
+
+/// The time a single check may take before it is considered failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The condition reason of passed checks.
+const REASON_PASSED: &str = "HealthCheckPassed";
+
+/// The condition reason of failed checks.
+const REASON_FAILED: &str = "HealthCheckFailed";
+
+/// The condition reason of checks exceeding their timeout.
+const REASON_TIMED_OUT: &str = "HealthCheckTimedOut";
+
+#[tonic::async_trait]
+/// The health check trait which defines the methods a probe implementation has to fulfill.
+pub trait HealthCheck: Send + Sync {
+    /// Run the check. Returns a message describing the healthy state, or an error describing the
+    /// problem.
+    async fn check(&self) -> Result<String>;
+}
+
+#[derive(Clone, Default)]
+/// HealthChecks runs all registered checks and converts their results into runtime conditions.
+pub struct HealthChecks {
+    /// The checks by their condition type in registration order.
+    checks: Vec<(String, Arc<dyn HealthCheck>)>,
+}
+
+impl HealthChecks {
+    /// Register the `check`, whose result gets reported as condition of the type `name`.
+    pub fn push(&mut self, name: &str, check: Arc<dyn HealthCheck>) {
+        self.checks.push((name.into(), check))
+    }
+
+    /// Run all checks concurrently and retrieve their conditions in registration order.
+    pub async fn conditions(&self) -> Vec<RuntimeCondition> {
+        future::join_all(self.checks.iter().map(|(name, check)| async move {
+            let (status, reason, message) = match time::timeout(CHECK_TIMEOUT, check.check()).await
+            {
+                Ok(Ok(message)) => (true, REASON_PASSED, message),
+                Ok(Err(e)) => (false, REASON_FAILED, format!("{:#}", e)),
+                Err(_) => (
+                    false,
+                    REASON_TIMED_OUT,
+                    format!("check exceeded timeout of {:?}", CHECK_TIMEOUT),
+                ),
+            };
+            RuntimeCondition {
+                r#type: name.clone(),
+                status,
+                reason: reason.into(),
+                message,
+            }
+        }))
+        .await
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+/// The kind of a configured health check.
+pub enum Probe {
+    /// Run the script at the path, which passes if it exits successfully.
+    Exec(PathBuf),
+
+    /// Connect to the TCP address like `mirror.local:5000`, for example a registry mirror.
+    Tcp(String),
+
+    /// Query the versions of the CNI plugin binary with the name.
+    CniVersion(String),
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Getters, PartialEq, Serialize)]
+/// HealthCheckSpec is the configuration of a single health check.
+pub struct HealthCheckSpec {
+    #[get = "pub"]
+    /// The condition type the result is reported as, like `RegistryMirrorReachable`.
+    name: String,
+
+    #[get = "pub"]
+    /// The kind of the check.
+    probe: Probe,
+}
+
+impl FromStr for HealthCheckSpec {
+    type Err = Error;
+
+    /// Parse a health check in the format `NAME=exec:PATH`, `NAME=tcp:HOST:PORT` or
+    /// `NAME=cni-version:PLUGIN`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '=');
+        let (name, probe) = match (parts.next(), parts.next()) {
+            (Some(name), Some(probe)) if !name.is_empty() && !name.contains(':') => (name, probe),
+            _ => bail!("invalid health check {}, expected NAME=KIND:ARGUMENT", s),
+        };
+        let mut parts = probe.splitn(2, ':');
+        let probe = match (parts.next(), parts.next()) {
+            (Some("exec"), Some(path)) if path.starts_with('/') => Probe::Exec(path.into()),
+            (Some("tcp"), Some(address)) if address.contains(':') => Probe::Tcp(address.into()),
+            (Some("cni-version"), Some(plugin)) if !plugin.is_empty() && !plugin.contains('/') => {
+                Probe::CniVersion(plugin.into())
+            }
+            _ => bail!(
+                "invalid probe {} of health check {}, expected exec:PATH, tcp:HOST:PORT or \
+                 cni-version:PLUGIN",
+                probe,
+                name
+            ),
+        };
+        Ok(Self {
+            name: name.into(),
+            probe,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A check which always returns the same result.
+    struct Fixed(bool);
+
+    #[tonic::async_trait]
+    impl HealthCheck for Fixed {
+        async fn check(&self) -> Result<String> {
+            if self.0 {
+                Ok("fine".into())
+            } else {
+                bail!("broken")
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn conditions_success() {
+        let mut sut = HealthChecks::default();
+        sut.push("Passing", Arc::new(Fixed(true)));
+        sut.push("Failing", Arc::new(Fixed(false)));
+
+        let conditions = sut.conditions().await;
+        assert_eq!(conditions.len(), 2);
+        assert_eq!(conditions[0].r#type, "Passing");
+        assert!(conditions[0].status);
+        assert_eq!(conditions[0].reason, REASON_PASSED);
+        assert_eq!(conditions[0].message, "fine");
+        assert!(!conditions[1].status);
+        assert_eq!(conditions[1].reason, REASON_FAILED);
+        assert_eq!(conditions[1].message, "broken");
+    }
+
+    #[test]
+    fn spec_from_str_success() -> Result<()> {
+        for (input, name, probe) in vec![
+            (
+                "DiskHealthy=exec:/usr/libexec/check-disk",
+                "DiskHealthy",
+                Probe::Exec("/usr/libexec/check-disk".into()),
+            ),
+            (
+                "RegistryMirrorReachable=tcp:mirror.local:5000",
+                "RegistryMirrorReachable",
+                Probe::Tcp("mirror.local:5000".into()),
+            ),
+            (
+                "BridgePluginUsable=cni-version:bridge",
+                "BridgePluginUsable",
+                Probe::CniVersion("bridge".into()),
+            ),
+        ] {
+            let spec: HealthCheckSpec = input.parse()?;
+            assert_eq!(spec.name(), name);
+            assert_eq!(spec.probe(), &probe);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn spec_from_str_fail() {
+        for input in &[
+            "exec:/bin/true",
+            "=exec:/bin/true",
+            "Check=exec:relative",
+            "Check=tcp:no-port",
+            "Check=cni-version:../bridge",
+            "Check=http://mirror.local",
+        ] {
+            assert!(input.parse::<HealthCheckSpec>().is_err(), "{}", input);
+        }
+    }
+}
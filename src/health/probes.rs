@@ -0,0 +1,182 @@
+//! The built-in health check probes.
+
+use crate::{
+    health::{HealthCheck, HealthCheckSpec, HealthChecks, Probe},
+    network::cni,
+    reload::LiveConfig,
+};
+use anyhow::{bail, Context, Result};
+use std::{path::PathBuf, process::Stdio, sync::Arc};
+use tokio::{net::TcpStream, process::Command};
+
+/// ExecProbe runs a script, which passes if it exits successfully. The first line of its output
+/// becomes the condition message.
+pub struct ExecProbe {
+    /// The path of the script.
+    path: PathBuf,
+}
+
+#[tonic::async_trait]
+impl HealthCheck for ExecProbe {
+    async fn check(&self) -> Result<String> {
+        let output = Command::new(&self.path)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("run {}", self.path.display()))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stdout
+            .lines()
+            .chain(stderr.lines())
+            .map(str::trim)
+            .find(|x| !x.is_empty())
+            .unwrap_or_default()
+            .to_string();
+        if !output.status.success() {
+            bail!(
+                "{} failed with {}: {}",
+                self.path.display(),
+                output.status,
+                message
+            )
+        }
+        Ok(message)
+    }
+}
+
+/// TcpProbe passes if a TCP connection to its address can be established, like to a registry
+/// mirror.
+pub struct TcpProbe {
+    /// The address to connect to.
+    address: String,
+}
+
+#[tonic::async_trait]
+impl HealthCheck for TcpProbe {
+    async fn check(&self) -> Result<String> {
+        TcpStream::connect(self.address.as_str())
+            .await
+            .with_context(|| format!("connect to {}", self.address))?;
+        Ok(format!("{} is reachable", self.address))
+    }
+}
+
+/// CniVersionProbe passes if a CNI plugin binary of the currently configured plugin directories
+/// reports its supported versions.
+pub struct CniVersionProbe {
+    /// The name of the plugin binary.
+    plugin: String,
+
+    /// The configuration containing the plugin directories.
+    live_config: LiveConfig,
+}
+
+#[tonic::async_trait]
+impl HealthCheck for CniVersionProbe {
+    async fn check(&self) -> Result<String> {
+        let config = self.live_config.current();
+        let versions = cni::plugin_versions(config.cni_plugin_dirs(), &self.plugin).await?;
+        Ok(format!(
+            "CNI plugin {} supports {}",
+            self.plugin,
+            versions.join(", ")
+        ))
+    }
+}
+
+impl HealthChecks {
+    /// Create the health checks of the configured `specs`, whereas probes depending on reloadable
+    /// settings use the `live_config`.
+    pub fn from_specs(specs: &[HealthCheckSpec], live_config: &LiveConfig) -> Self {
+        let mut checks = Self::default();
+        for spec in specs {
+            let check: Arc<dyn HealthCheck> = match spec.probe() {
+                Probe::Exec(path) => Arc::new(ExecProbe { path: path.clone() }),
+                Probe::Tcp(address) => Arc::new(TcpProbe {
+                    address: address.clone(),
+                }),
+                Probe::CniVersion(plugin) => Arc::new(CniVersionProbe {
+                    plugin: plugin.clone(),
+                    live_config: live_config.clone(),
+                }),
+            };
+            checks.push(spec.name(), check);
+        }
+        checks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ConfigBuilder, network::cni::tests::fake_plugin};
+    use std::{fs, os::unix::fs::PermissionsExt};
+    use tempfile::tempdir;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn exec_probe_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("check");
+        fs::write(&path, "#!/bin/sh\necho \"disk ok\"\n")?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        assert_eq!(ExecProbe { path }.check().await?, "disk ok");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exec_probe_fail() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("check");
+        fs::write(&path, "#!/bin/sh\necho \"disk full\" >&2\nexit 1\n")?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        let e = ExecProbe { path }
+            .check()
+            .await
+            .err()
+            .context("check passed")?;
+        assert!(e.to_string().ends_with(": disk full"), "{}", e);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tcp_probe_success() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?.to_string();
+        assert!(TcpProbe { address }.check().await.is_ok());
+
+        drop(listener);
+        let address = "127.0.0.1:0".into();
+        assert!(TcpProbe { address }.check().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn from_specs_success() -> Result<()> {
+        let dir = tempdir()?;
+        fake_plugin(dir.path())?;
+        let live_config = LiveConfig::new(Arc::new(
+            ConfigBuilder::default()
+                .cni_plugin_dirs(vec![dir.path().into()])
+                .build()?,
+        ));
+        let specs = vec![
+            "FakePluginUsable=cni-version:fake".parse()?,
+            "MissingPluginUsable=cni-version:missing".parse()?,
+        ];
+
+        let conditions = HealthChecks::from_specs(&specs, &live_config)
+            .conditions()
+            .await;
+        assert_eq!(conditions[0].r#type, "FakePluginUsable");
+        assert!(conditions[0].status);
+        assert_eq!(
+            conditions[0].message,
+            "CNI plugin fake supports 0.3.1, 0.4.0"
+        );
+        assert!(!conditions[1].status);
+        Ok(())
+    }
+}
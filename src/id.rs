@@ -0,0 +1,37 @@
+//! Stable identifiers for sandboxes and containers.
+
+/// The offset basis of the 64 bit FNV-1a hash.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// The prime of the 64 bit FNV-1a hash.
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Generate a stable identifier from the provided `parts`, which are separated by a null byte to
+/// avoid ambiguities like `["a", "bc"]` and `["ab", "c"]`.
+pub fn stable_id(parts: &[&str]) -> String {
+    let hash = parts
+        .iter()
+        .enumerate()
+        .flat_map(|(i, part)| {
+            let separator = if i > 0 { Some(0) } else { None };
+            separator.into_iter().chain(part.bytes())
+        })
+        .fold(FNV_OFFSET, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(FNV_PRIME)
+        });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_id_success() {
+        let id = stable_id(&["uid", "0"]);
+        assert_eq!(id.len(), 16);
+        assert_eq!(id, stable_id(&["uid", "0"]));
+        assert_ne!(id, stable_id(&["uid", "1"]));
+        assert_ne!(stable_id(&["a", "bc"]), stable_id(&["ab", "c"]));
+    }
+}
@@ -0,0 +1,62 @@
+//! Idempotency of retried mutating requests.
+//!
+//! The kubelet retries RunPodSandbox and CreateContainer if they time out, although the runtime
+//! might have already succeeded. Every successful request therefore records the resulting ID
+//! under a key derived from the request metadata, so that retries can return the original result.
+
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+
+/// The storage key prefix of all idempotency records.
+const KEY_PREFIX: &str = "idempotency/";
+
+#[derive(Debug, Deserialize, Getters, PartialEq, Serialize)]
+/// An IdempotencyRecord stores the result of a successful mutating request.
+pub struct IdempotencyRecord {
+    #[get = "pub"]
+    /// The ID of the created pod sandbox or container.
+    id: String,
+}
+
+impl IdempotencyRecord {
+    /// Create a new record for the created object `id`.
+    pub fn new(id: String) -> Self {
+        Self { id }
+    }
+
+    /// Retrieve the storage key for running the pod sandbox with the provided `uid` and
+    /// `attempt`.
+    pub fn pod_sandbox_key(uid: &str, attempt: u32) -> String {
+        format!("{}sandbox/{}/{}", KEY_PREFIX, uid, attempt)
+    }
+
+    /// Retrieve the storage key for creating the container `name` with the provided `attempt`
+    /// inside the pod sandbox `pod_sandbox_id`.
+    pub fn container_key(pod_sandbox_id: &str, name: &str, attempt: u32) -> String {
+        format!(
+            "{}container/{}/{}/{}",
+            KEY_PREFIX, pod_sandbox_id, name, attempt
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pod_sandbox_key() {
+        let key = IdempotencyRecord::pod_sandbox_key("uid", 1);
+        assert!(key.starts_with(KEY_PREFIX));
+        assert!(key.ends_with("uid/1"));
+        assert_ne!(key, IdempotencyRecord::pod_sandbox_key("uid", 2));
+    }
+
+    #[test]
+    fn container_key() {
+        let key = IdempotencyRecord::container_key("sandbox", "name", 0);
+        assert!(key.starts_with(KEY_PREFIX));
+        assert!(key.ends_with("sandbox/name/0"));
+        assert_ne!(key, IdempotencyRecord::pod_sandbox_key("sandbox", 0));
+    }
+}
@@ -0,0 +1,204 @@
+//! OCI artifacts, which are content other than container images, like WASM modules, seccomp
+//! profile bundles or CA bundles.
+//!
+//! Pods and containers reference artifacts by annotation, whereas the artifacts of a pod are
+//! available to all of its containers. Every layer of an artifact is a file named by its title
+//! annotation, and all files of an artifact get mounted read-only into the containers using it
+//! below `/run/artifacts/<name>`. Artifacts are pulled into the image store, but they have their
+//! own lifecycle: they get removed once no pod sandbox or container references them anymore.
+
+use crate::{image::reference::Reference, oci_spec::image::Descriptor, storage::KeyValueStorage};
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// The annotation listing the artifacts of a pod or a container. The value is a comma separated
+/// list of names and artifact references, like `filter=quay.io/tenant/filter:v1`.
+pub const ARTIFACTS_ANNOTATION: &str = "io.kubernetes.cri.artifacts";
+
+/// The directory inside of containers the artifacts get mounted into by their name.
+pub const ARTIFACTS_MOUNT_DIR: &str = "/run/artifacts";
+
+/// The annotation of artifact layers naming their file.
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// The storage key prefix of all artifact records.
+const KEY_PREFIX: &str = "artifact/";
+
+#[derive(Clone, CopyGetters, Debug, Deserialize, Getters, PartialEq, Serialize)]
+/// ArtifactRecord is the metadata of a pulled artifact.
+pub struct ArtifactRecord {
+    #[get = "pub"]
+    /// The digest of the manifest of the artifact.
+    pub(super) digest: String,
+
+    #[get = "pub"]
+    /// The references pointing to the artifact, like `quay.io/tenant/filter:v1`.
+    pub(super) references: Vec<String>,
+
+    #[get = "pub"]
+    /// The digests of the layers, which are the files of the artifact in order.
+    pub(super) layers: Vec<String>,
+
+    #[get = "pub"]
+    /// The names of the files of the artifact in the order of its layers.
+    pub(super) files: Vec<String>,
+
+    #[get_copy = "pub"]
+    /// The size of all layers in bytes.
+    pub(super) size: u64,
+
+    #[get_copy = "pub"]
+    /// The time the artifact has been pulled in nanoseconds since the Unix epoch.
+    pub(super) pulled_at: i64,
+}
+
+impl ArtifactRecord {
+    /// Retrieve the storage key for the artifact with the manifest `digest`.
+    pub fn key(digest: &str) -> String {
+        format!("{}{}", KEY_PREFIX, digest)
+    }
+
+    /// Retrieve the records of all artifacts.
+    pub fn list<S: KeyValueStorage>(storage: &mut S) -> Result<Vec<Self>> {
+        storage.scan_prefix(KEY_PREFIX)
+    }
+
+    /// Retrieve the record of the artifact `reference`.
+    pub fn find<S: KeyValueStorage>(
+        storage: &mut S,
+        reference: &Reference,
+    ) -> Result<Option<Self>> {
+        Ok(Self::list(storage)?
+            .into_iter()
+            .find(|x| x.matches(reference)))
+    }
+
+    /// Returns true if the record is referenced by the `reference`.
+    pub fn matches(&self, reference: &Reference) -> bool {
+        match reference.digest() {
+            Some(digest) => digest == &self.digest,
+            None => self.references.contains(&reference.to_string()),
+        }
+    }
+}
+
+/// Retrieve the artifacts referenced by the pod `sandbox` annotations and the container
+/// `annotations` by their name, whereas the artifacts of the container take precedence.
+pub fn artifacts(
+    sandbox: &HashMap<String, String>,
+    annotations: &HashMap<String, String>,
+) -> Result<BTreeMap<String, Reference>> {
+    let mut artifacts = BTreeMap::new();
+    for annotations in &[sandbox, annotations] {
+        let value = match annotations.get(ARTIFACTS_ANNOTATION) {
+            Some(value) => value,
+            None => continue,
+        };
+        for entry in value.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let mut parts = entry.splitn(2, '=');
+            let (name, reference) = match (parts.next(), parts.next()) {
+                (Some(name), Some(reference)) => (name.trim(), reference.trim()),
+                _ => bail!("invalid artifact {}, expected NAME=REFERENCE", entry),
+            };
+            validate_name(name)?;
+            let reference = reference
+                .parse()
+                .with_context(|| format!("parse reference of artifact {}", name))?;
+            artifacts.insert(name.to_string(), reference);
+        }
+    }
+    Ok(artifacts)
+}
+
+/// Retrieve the file name of the artifact `layer`, which is its title or otherwise its digest.
+pub fn file_name(layer: &Descriptor) -> Result<String> {
+    let name = match layer.annotations().get(TITLE_ANNOTATION) {
+        Some(title) => title.clone(),
+        None => layer.digest().replace(':', "-"),
+    };
+    validate_name(&name).with_context(|| format!("invalid title of layer {}", layer.digest()))?;
+    Ok(name)
+}
+
+/// Validate that the `name` is usable as file name, which excludes paths.
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        bail!("invalid name {}", name)
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oci_spec::image::DescriptorBuilder;
+
+    #[test]
+    fn artifacts_success() -> Result<()> {
+        let sandbox = vec![(
+            ARTIFACTS_ANNOTATION.to_string(),
+            "filter=quay.io/tenant/filter:v1, certs=quay.io/tenant/certs:v1".to_string(),
+        )]
+        .into_iter()
+        .collect();
+        let container = vec![(
+            ARTIFACTS_ANNOTATION.to_string(),
+            "filter=quay.io/tenant/filter:v2".to_string(),
+        )]
+        .into_iter()
+        .collect();
+
+        let artifacts = artifacts(&sandbox, &container)?;
+        let artifacts: Vec<(&str, String)> = artifacts
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.to_string()))
+            .collect();
+        assert_eq!(
+            artifacts,
+            vec![
+                ("certs", "quay.io/tenant/certs:v1".to_string()),
+                ("filter", "quay.io/tenant/filter:v2".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn artifacts_failure() {
+        for value in &["filter", "../filter=quay.io/filter:v1", "filter=Invalid:v1"] {
+            let annotations = vec![(ARTIFACTS_ANNOTATION.to_string(), value.to_string())]
+                .into_iter()
+                .collect();
+            assert!(artifacts(&HashMap::new(), &annotations).is_err());
+        }
+    }
+
+    #[test]
+    fn file_name_success() -> Result<()> {
+        let mut annotations = HashMap::new();
+        let layer = DescriptorBuilder::default()
+            .digest("sha256:abc")
+            .build()
+            .map_err(anyhow::Error::msg)?;
+        assert_eq!(file_name(&layer)?, "sha256-abc");
+
+        annotations.insert(TITLE_ANNOTATION.to_string(), "filter.wasm".to_string());
+        let layer = DescriptorBuilder::default()
+            .digest("sha256:abc")
+            .annotations(annotations.clone())
+            .build()
+            .map_err(anyhow::Error::msg)?;
+        assert_eq!(file_name(&layer)?, "filter.wasm");
+
+        annotations.insert(TITLE_ANNOTATION.to_string(), "../filter.wasm".to_string());
+        let layer = DescriptorBuilder::default()
+            .digest("sha256:abc")
+            .annotations(annotations)
+            .build()
+            .map_err(anyhow::Error::msg)?;
+        assert!(file_name(&layer).is_err());
+        Ok(())
+    }
+}
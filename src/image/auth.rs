@@ -0,0 +1,293 @@
+//! Credentials for authenticating to registries.
+//!
+//! The kubelet passes the credentials of the image pull secrets matching an image with every
+//! `PullImage` request. Credentials for the whole node can be configured via an auth file in the
+//! format of `containers-auth.json` or the docker `config.json`, which get used if the request
+//! does not provide any.
+
+use crate::criapi::AuthConfig;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{collections::HashMap, fs, io::ErrorKind, path::Path};
+
+/// The registry the docker hub aliases get normalized to.
+const DOCKER_HUB: &str = "docker.io";
+
+/// The aliases of the docker hub as found in auth files.
+const DOCKER_HUB_ALIASES: &[&str] = &["index.docker.io", "registry-1.docker.io"];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Credentials for a registry.
+pub enum Credentials {
+    /// A username and password, which get sent to registries requiring basic authentication and
+    /// to the token servers of registries requiring bearer tokens.
+    Basic {
+        /// The username.
+        username: String,
+
+        /// The password.
+        password: String,
+    },
+
+    /// An OAuth2 refresh token, which gets exchanged for a bearer token at the token server.
+    IdentityToken(String),
+
+    /// A bearer token, which gets sent to the registry as is.
+    RegistryToken(String),
+}
+
+impl Credentials {
+    /// Convert the `AuthConfig` of a request into credentials. Returns `None` if it is empty.
+    pub fn from_auth_config(config: &AuthConfig) -> Result<Option<Self>> {
+        Self::parse(&AuthEntry {
+            auth: config.auth.clone(),
+            username: config.username.clone(),
+            password: config.password.clone(),
+            identity_token: config.identity_token.clone(),
+            registry_token: config.registry_token.clone(),
+        })
+    }
+
+    /// Convert an `entry` of an auth file or request into credentials, whereas tokens take
+    /// precedence over a username and password.
+    fn parse(entry: &AuthEntry) -> Result<Option<Self>> {
+        if !entry.registry_token.is_empty() {
+            return Ok(Some(Self::RegistryToken(entry.registry_token.clone())));
+        }
+        if !entry.identity_token.is_empty() {
+            return Ok(Some(Self::IdentityToken(entry.identity_token.clone())));
+        }
+        if !entry.username.is_empty() {
+            return Ok(Some(Self::Basic {
+                username: entry.username.clone(),
+                password: entry.password.clone(),
+            }));
+        }
+        if entry.auth.is_empty() {
+            return Ok(None);
+        }
+        let decoded = String::from_utf8(decode_base64(entry.auth.trim())?)
+            .context("auth is not valid UTF-8")?;
+        let mut parts = decoded.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(username), Some(password)) if !username.is_empty() => Ok(Some(Self::Basic {
+                username: username.into(),
+                password: password.into(),
+            })),
+            _ => bail!("auth is no base64 encoded USERNAME:PASSWORD"),
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+/// The credentials of a single registry within an auth file.
+struct AuthEntry {
+    #[serde(default)]
+    /// The base64 encoded `username:password`.
+    auth: String,
+
+    #[serde(default)]
+    /// The username.
+    username: String,
+
+    #[serde(default)]
+    /// The password.
+    password: String,
+
+    #[serde(default, rename = "identitytoken")]
+    /// The OAuth2 refresh token.
+    identity_token: String,
+
+    #[serde(default, rename = "registrytoken")]
+    /// The bearer token.
+    registry_token: String,
+}
+
+#[derive(Default, Deserialize)]
+/// AuthFile contains the credentials of the node per registry, like
+/// `{"auths": {"quay.io": {"auth": "dXNlcjpwYXNz"}}}`.
+pub struct AuthFile {
+    #[serde(default)]
+    /// The credentials per registry or repository, like `quay.io/tenant`.
+    auths: HashMap<String, AuthEntry>,
+}
+
+impl AuthFile {
+    /// Load the auth file at `path`. A missing file contains no credentials, which allows
+    /// provisioning it after the server started.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("parse auth file {}", path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("read auth file {}", path.display())),
+        }
+    }
+
+    /// Retrieve the credentials for the image `name` without tag, like `quay.io/tenant/app`. The
+    /// most specific entry wins, which means that an entry for the repository takes precedence
+    /// over one for its registry.
+    pub fn credentials(&self, name: &str) -> Result<Option<Credentials>> {
+        let entry = self
+            .auths
+            .iter()
+            .map(|(key, entry)| (normalize(key), entry))
+            .filter(|(key, _)| {
+                name.strip_prefix(key.as_str())
+                    .map_or(false, |x| x.is_empty() || x.starts_with('/'))
+            })
+            .max_by_key(|(key, _)| key.len());
+        match entry {
+            Some((key, entry)) => Credentials::parse(entry)
+                .with_context(|| format!("invalid credentials for {} in auth file", key)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Normalize the `key` of an auth file, which may be an URL like `https://index.docker.io/v1/`,
+/// into the name of a registry or repository.
+fn normalize(key: &str) -> String {
+    let key = key
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_end_matches('/');
+    let key = key
+        .strip_suffix("/v1")
+        .or_else(|| key.strip_suffix("/v2"))
+        .unwrap_or(key);
+    let mut parts = key.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(host), rest) if DOCKER_HUB_ALIASES.contains(&host) => match rest {
+            Some(rest) => format!("{}/{}", DOCKER_HUB, rest),
+            None => DOCKER_HUB.into(),
+        },
+        _ => key.into(),
+    }
+}
+
+/// Decode the standard base64 `input`, with or without padding.
+pub fn decode_base64(input: &str) -> Result<Vec<u8>> {
+    let mut res = Vec::with_capacity(input.len() * 3 / 4);
+    let (mut buf, mut bits) = (0u32, 0);
+    for c in input.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => bail!("invalid base64 character {:?}", c as char),
+        };
+        buf = (buf << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            res.push((buf >> bits) as u8);
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn decode_base64_success() -> Result<()> {
+        assert_eq!(decode_base64("dXNlcjpwYXNz")?, b"user:pass");
+        assert_eq!(decode_base64("dXNlcjpwYXNzMQ==")?, b"user:pass1");
+        assert_eq!(decode_base64("dXNlcjpwYXNzMTI")?, b"user:pass12");
+        assert!(decode_base64("dXNl*jpw").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn from_auth_config_success() -> Result<()> {
+        assert_eq!(Credentials::from_auth_config(&AuthConfig::default())?, None);
+        assert_eq!(
+            Credentials::from_auth_config(&AuthConfig {
+                auth: "dXNlcjpwYXNz".into(),
+                ..Default::default()
+            })?,
+            Some(Credentials::Basic {
+                username: "user".into(),
+                password: "pass".into()
+            })
+        );
+        assert_eq!(
+            Credentials::from_auth_config(&AuthConfig {
+                username: "<token>".into(),
+                identity_token: "refresh".into(),
+                ..Default::default()
+            })?,
+            Some(Credentials::IdentityToken("refresh".into()))
+        );
+        assert_eq!(
+            Credentials::from_auth_config(&AuthConfig {
+                registry_token: "bearer".into(),
+                ..Default::default()
+            })?,
+            Some(Credentials::RegistryToken("bearer".into()))
+        );
+        assert!(Credentials::from_auth_config(&AuthConfig {
+            auth: "bm9jb2xvbg==".into(),
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn auth_file_credentials_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("auth.json");
+        assert!(AuthFile::load(&path)?
+            .credentials("docker.io/library/nginx")?
+            .is_none());
+
+        fs::write(
+            &path,
+            r#"{
+              "auths": {
+                "https://index.docker.io/v1/": {"auth": "aHViOnNlY3JldA=="},
+                "quay.io": {"username": "registry", "password": "secret"},
+                "quay.io/tenant": {"identitytoken": "refresh"}
+              },
+              "credsStore": "desktop"
+            }"#,
+        )?;
+        let sut = AuthFile::load(&path)?;
+        assert_eq!(
+            sut.credentials("docker.io/library/nginx")?,
+            Some(Credentials::Basic {
+                username: "hub".into(),
+                password: "secret".into()
+            })
+        );
+        assert_eq!(
+            sut.credentials("quay.io/other/app")?,
+            Some(Credentials::Basic {
+                username: "registry".into(),
+                password: "secret".into()
+            })
+        );
+        assert_eq!(
+            sut.credentials("quay.io/tenant/app")?,
+            Some(Credentials::IdentityToken("refresh".into()))
+        );
+        assert!(sut.credentials("quay.io.evil/app")?.is_none());
+        assert!(sut.credentials("gcr.io/app")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn auth_file_load_fail_invalid() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("auth.json");
+        fs::write(&path, "{")?;
+        assert!(AuthFile::load(&path).is_err());
+        Ok(())
+    }
+}
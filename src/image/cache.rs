@@ -0,0 +1,165 @@
+//! A layer cache which can be shared between multiple nodes.
+//!
+//! The cache directory may reside on a network filesystem like NFS. Layers are stored by their
+//! digest and every write is protected by an exclusive lock, so that concurrently pulling nodes
+//! download a layer only once and never observe partially written layers.
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use nix::fcntl::{flock, FlockArg};
+use std::{
+    fs::{self, File, OpenOptions},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+};
+
+#[derive(Clone, Debug)]
+/// LayerCache stores layer blobs below a shared directory.
+pub struct LayerCache {
+    /// The root directory of the cache.
+    path: PathBuf,
+}
+
+impl LayerCache {
+    /// Open the cache at `path`, which gets created if it does not exist.
+    pub fn open(path: &Path) -> Result<Self> {
+        fs::create_dir_all(path)
+            .with_context(|| format!("create layer cache path {}", path.display()))?;
+        Ok(Self { path: path.into() })
+    }
+
+    /// Retrieve the path of the cached layer with the provided `digest`, if it exists.
+    pub fn lookup(&self, digest: &str) -> Result<Option<PathBuf>> {
+        let path = self.blob_path(digest)?;
+        Ok(Some(path).filter(|x| x.is_file()))
+    }
+
+    /// Store the layer with the provided `digest` by calling `write`, unless another writer
+    /// already stored it. The caller is responsible for verifying the content against its digest
+    /// before `write` returns. Returns the path of the cached layer.
+    pub fn store<F>(&self, digest: &str, write: F) -> Result<PathBuf>
+    where
+        F: FnOnce(&mut File) -> Result<()>,
+    {
+        let path = self.blob_path(digest)?;
+        let dir = path.parent().context("get layer directory")?;
+        fs::create_dir_all(dir).with_context(|| format!("create directory {}", dir.display()))?;
+
+        // Serialize all writers of the same layer, even across nodes
+        let lock_path = path.with_extension("lock");
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&lock_path)
+            .with_context(|| format!("open lock file {}", lock_path.display()))?;
+        flock(lock.as_raw_fd(), FlockArg::LockExclusive)
+            .with_context(|| format!("lock {}", lock_path.display()))?;
+
+        if path.is_file() {
+            debug!("Layer {} got stored by another writer", digest);
+            return Ok(path);
+        }
+
+        // Write into a temporary file first, which makes the layer appear atomically
+        let tmp_path = path.with_extension(format!("tmp-{}", process::id()));
+        let result = File::create(&tmp_path)
+            .with_context(|| format!("create file {}", tmp_path.display()))
+            .and_then(|mut file| {
+                write(&mut file)?;
+                file.sync_all().context("sync layer")
+            })
+            .and_then(|_| {
+                fs::rename(&tmp_path, &path)
+                    .with_context(|| format!("rename {}", tmp_path.display()))
+            });
+        if let Err(e) = result {
+            fs::remove_file(&tmp_path).ok();
+            return Err(e.context(format!("store layer {}", digest)));
+        }
+
+        debug!("Stored layer {} in {}", digest, path.display());
+        Ok(path)
+    }
+
+    /// Retrieve the path of the layer with the provided `digest`, like `sha256:abc…`.
+    fn blob_path(&self, digest: &str) -> Result<PathBuf> {
+        let mut parts = digest.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(algorithm), Some(encoded))
+                if !algorithm.is_empty()
+                    && algorithm
+                        .chars()
+                        .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit())
+                    && !encoded.is_empty()
+                    && encoded.chars().all(|x| x.is_ascii_hexdigit()) =>
+            {
+                Ok(self.path.join(algorithm).join(encoded))
+            }
+            _ => bail!("invalid digest {}", digest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::format_err;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    const DIGEST: &str = "sha256:0123456789abcdef";
+
+    #[test]
+    fn store_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = LayerCache::open(&dir.path().join("cache"))?;
+        assert!(sut.lookup(DIGEST)?.is_none());
+
+        let path = sut.store(DIGEST, |file| Ok(file.write_all(b"layer")?))?;
+        assert_eq!(fs::read(&path)?, b"layer");
+        assert_eq!(sut.lookup(DIGEST)?, Some(path.clone()));
+
+        // Already cached layers are not written again
+        let stored = sut.store(DIGEST, |_| Err(format_err!("should not be called")))?;
+        assert_eq!(stored, path);
+        Ok(())
+    }
+
+    #[test]
+    fn store_failure_write() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = LayerCache::open(dir.path())?;
+
+        assert!(sut
+            .store(DIGEST, |_| Err(format_err!("digest mismatch")))
+            .is_err());
+        assert!(sut.lookup(DIGEST)?.is_none());
+
+        let layer_dir = dir.path().join("sha256");
+        let leftovers = fs::read_dir(&layer_dir)?
+            .filter_map(|x| x.ok())
+            .filter(|x| x.file_name().to_string_lossy().contains("tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_digest() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = LayerCache::open(dir.path())?;
+
+        for digest in &[
+            "",
+            "sha256",
+            "sha256:",
+            ":abc",
+            "sha256:../../etc",
+            "SHA256:abc",
+        ] {
+            assert!(sut.lookup(digest).is_err());
+        }
+        Ok(())
+    }
+}
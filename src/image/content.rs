@@ -0,0 +1,113 @@
+//! Content stores the image store can import blobs from.
+//!
+//! A content store holds blobs by their digest, like the layer cache or the content store of a
+//! containerd installation. The image store consults them before fetching a blob from a
+//! registry, which allows migrating a node from containerd without pulling all of its images
+//! again. Imported blobs are copied and verified like fetched ones, so content stores are never
+//! written to.
+//!
+//! Content stores are the only pluggable part of image storage: the image store itself stays a
+//! concrete type, because its records live in the key value storage of the server and its
+//! unpacked layers are what container root filesystems get built from. Snapshots of containerd
+//! are not read either, so the layers of imported images get unpacked again.
+
+use crate::image::{cache::LayerCache, reference::validate_digest};
+use anyhow::{bail, Context, Result};
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+};
+
+/// The directory of the containerd content store below its root.
+const CONTAINERD_CONTENT_DIR: &str = "io.containerd.content.v1.content";
+
+/// ContentStore is a source of blobs stored by their digest.
+pub trait ContentStore: Debug + Send + Sync {
+    /// Retrieve the path of the blob with the provided `digest`, if it exists. The caller is
+    /// responsible for verifying the content.
+    fn lookup(&self, digest: &str) -> Result<Option<PathBuf>>;
+}
+
+impl ContentStore for LayerCache {
+    fn lookup(&self, digest: &str) -> Result<Option<PathBuf>> {
+        LayerCache::lookup(self, digest)
+    }
+}
+
+#[derive(Clone, Debug)]
+/// ContainerdContentStore reads the blobs of an existing containerd installation.
+pub struct ContainerdContentStore {
+    /// The directory containing the blobs by their algorithm and encoded digest.
+    blobs: PathBuf,
+}
+
+impl ContainerdContentStore {
+    /// Open the content store of the containerd installation with the `root` directory, like
+    /// `/var/lib/containerd`.
+    pub fn open(root: &Path) -> Result<Self> {
+        let blobs = root.join(CONTAINERD_CONTENT_DIR).join("blobs");
+        if !blobs.is_dir() {
+            bail!("containerd content store {} not found", blobs.display())
+        }
+        Ok(Self { blobs })
+    }
+}
+
+impl ContentStore for ContainerdContentStore {
+    fn lookup(&self, digest: &str) -> Result<Option<PathBuf>> {
+        validate_digest(digest)?;
+        let mut parts = digest.splitn(2, ':');
+        let path = match (parts.next(), parts.next()) {
+            (Some(algorithm), Some(encoded)) => self.blobs.join(algorithm).join(encoded),
+            _ => bail!("invalid digest {}", digest),
+        };
+        Ok(Some(path).filter(|x| x.is_file()))
+    }
+}
+
+/// Find the blob with the provided `digest` in the first of the content `stores` containing it.
+pub fn lookup(stores: &[&dyn ContentStore], digest: &str) -> Result<Option<PathBuf>> {
+    for store in stores {
+        if let Some(path) = store
+            .lookup(digest)
+            .with_context(|| format!("lookup blob {} in {:?}", digest, store))?
+        {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const DIGEST: &str = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn containerd_lookup_success() -> Result<()> {
+        let root = tempdir()?;
+        let dir = root
+            .path()
+            .join(CONTAINERD_CONTENT_DIR)
+            .join("blobs/sha256");
+        fs::create_dir_all(&dir)?;
+        let sut = ContainerdContentStore::open(root.path())?;
+        assert_eq!(sut.lookup(DIGEST)?, None);
+
+        let blob = dir.join(DIGEST.trim_start_matches("sha256:"));
+        fs::write(&blob, "hello")?;
+        assert_eq!(sut.lookup(DIGEST)?, Some(blob.clone()));
+        assert_eq!(lookup(&[&sut], DIGEST)?, Some(blob));
+        assert!(sut.lookup("sha256:../../etc/passwd").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn containerd_open_fail_missing() -> Result<()> {
+        assert!(ContainerdContentStore::open(tempdir()?.path()).is_err());
+        Ok(())
+    }
+}
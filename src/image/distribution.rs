@@ -0,0 +1,618 @@
+//! Retrieval of image manifests and blobs from OCI registries, and their upload.
+//!
+//! Uploads always go to the registry of an image instead of its mirrors. Blobs which exist in
+//! the repository are skipped, whereas blobs of another repository of the same registry get
+//! mounted without uploading their content. All other blobs are uploaded in chunks.
+
+use crate::{
+    image::{
+        auth::Credentials,
+        reference::Reference,
+        registries::{Endpoint, Registries},
+    },
+    oci_spec::image::{
+        MEDIA_TYPE_DOCKER_MANIFEST, MEDIA_TYPE_DOCKER_MANIFEST_LIST, MEDIA_TYPE_INDEX,
+        MEDIA_TYPE_MANIFEST,
+    },
+};
+use anyhow::{bail, format_err, Context, Result};
+use log::debug;
+use reqwest::{
+    header::{ACCEPT, CONTENT_RANGE, CONTENT_TYPE, LOCATION, WWW_AUTHENTICATE},
+    Client, RequestBuilder, Response, StatusCode,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+#[tonic::async_trait]
+/// Distribution is a source of image manifests and blobs.
+pub trait Distribution: Send + Sync {
+    /// Retrieve the manifest or index of the `reference` together with its media type.
+    async fn manifest(&self, reference: &Reference) -> Result<(String, Vec<u8>)>;
+
+    /// Retrieve the blob with the provided `digest` from the repository of the `reference` and
+    /// write it into `file`. The caller is responsible for verifying the content.
+    async fn blob(&self, reference: &Reference, digest: &str, file: &mut File) -> Result<()>;
+}
+
+#[tonic::async_trait]
+/// Upload is a destination of image manifests and blobs.
+pub trait Upload: Send + Sync {
+    /// Upload the blob at `path` with the provided `digest` to the repository of the `reference`,
+    /// unless it exists there. The blob gets mounted from the repository of `mount_from` instead
+    /// of uploading it, if that is a different repository of the same registry.
+    async fn push_blob(
+        &self,
+        reference: &Reference,
+        digest: &str,
+        path: &Path,
+        mount_from: Option<&Reference>,
+    ) -> Result<()>;
+
+    /// Upload the manifest `content` of the `media_type` as the `reference`.
+    async fn push_manifest(
+        &self,
+        reference: &Reference,
+        media_type: &str,
+        content: &[u8],
+    ) -> Result<()>;
+}
+
+/// The client ID sent to token servers when exchanging identity tokens.
+const CLIENT_ID: &str = "cri";
+
+/// The size of the chunks blobs get uploaded in.
+const CHUNK_SIZE: u64 = 16 << 20;
+
+#[derive(Clone, Default)]
+/// Registry retrieves images via the OCI distribution API. The mirrors of a registry are tried
+/// before the registry itself. Bearer tokens get requested on demand and reused per repository,
+/// either anonymously or with the provided credentials.
+pub struct Registry {
+    /// The HTTP client used for all requests to secure hosts.
+    client: Client,
+
+    /// The HTTP client used for requests to insecure hosts, which accepts any certificate.
+    insecure_client: Client,
+
+    /// The mirrors, insecure and blocked registries.
+    registries: Registries,
+
+    /// The credentials per host.
+    credentials: HashMap<String, Credentials>,
+
+    /// The authorizations per registry and repository.
+    authorizations: Arc<Mutex<HashMap<String, Authorization>>>,
+}
+
+#[derive(Clone)]
+/// The authorization of requests to a repository.
+enum Authorization {
+    /// Basic authentication with a username and password.
+    Basic(String, String),
+
+    /// A bearer token.
+    Bearer(String),
+}
+
+#[derive(Clone, Copy)]
+/// An upload to a registry.
+enum Push<'a> {
+    /// The blob at the path with the digest, which may be mounted from the repository of the
+    /// reference.
+    Blob(&'a str, &'a Path, Option<&'a Reference>),
+
+    /// The manifest content of the media type.
+    Manifest(&'a str, &'a [u8]),
+}
+
+/// An authentication challenge of a registry.
+enum Challenge {
+    /// The registry requires basic authentication.
+    Basic,
+
+    /// The registry requires a bearer token, whose server is described by the parameters.
+    Bearer(HashMap<String, String>),
+}
+
+#[derive(Deserialize)]
+/// The response of a token server.
+struct TokenResponse {
+    #[serde(default)]
+    /// The bearer token.
+    token: String,
+
+    #[serde(default)]
+    /// The OAuth2 compatible alias of the bearer token.
+    access_token: String,
+}
+
+impl Registry {
+    /// Use the `registries` configuration for resolving the hosts of images.
+    pub fn with_registries(mut self, registries: Registries) -> Result<Self> {
+        self.insecure_client = Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .context("create insecure HTTP client")?;
+        self.registries = registries;
+        Ok(self)
+    }
+
+    /// Use the `credentials` to authenticate to the `host`.
+    pub fn with_credentials(mut self, host: &str, credentials: Option<Credentials>) -> Self {
+        if let Some(credentials) = credentials {
+            self.credentials.insert(host.into(), credentials);
+        }
+        self
+    }
+
+    /// Send a GET request for the `path` of the repository of `reference` to the first endpoint
+    /// of its registry which is able to serve it.
+    async fn get(&self, reference: &Reference, path: &str, accept: &str) -> Result<Response> {
+        let mut error = None;
+        for endpoint in self.registries.endpoints(reference) {
+            for scheme in endpoint.schemes() {
+                match self
+                    .get_from(&endpoint, scheme, reference, path, accept)
+                    .await
+                {
+                    Ok(response) => return Ok(response),
+                    Err(e) => {
+                        debug!(
+                            "Unable to retrieve {} via {}: {:#}",
+                            path,
+                            endpoint.host(),
+                            e
+                        );
+                        error = Some(e);
+                    }
+                }
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => bail!("no endpoint for {}", reference),
+        }
+    }
+
+    /// Send a GET request for the `path` of the repository of `reference` to the `endpoint`,
+    /// whereas the request gets authorized if the endpoint requires it.
+    async fn get_from(
+        &self,
+        endpoint: &Endpoint,
+        scheme: &str,
+        reference: &Reference,
+        path: &str,
+        accept: &str,
+    ) -> Result<Response> {
+        let url = format!(
+            "{}://{}/v2/{}/{}",
+            scheme,
+            endpoint.host(),
+            reference.repository(),
+            path
+        );
+        self.send(endpoint, reference, &url, |x| {
+            x.get(&url).header(ACCEPT, accept)
+        })
+        .await?
+        .error_for_status()
+        .with_context(|| format!("request {}", url))
+    }
+
+    /// Send the request for the `url` built by `request` to the `endpoint`, whereas the request
+    /// gets authorized for the repository of `reference` if the endpoint requires it. Returns
+    /// the response regardless of its status, unless the authorization fails.
+    async fn send<F>(
+        &self,
+        endpoint: &Endpoint,
+        reference: &Reference,
+        url: &str,
+        request: F,
+    ) -> Result<Response>
+    where
+        F: Fn(&Client) -> RequestBuilder + Send + Sync,
+    {
+        let key = format!("{}/{}", endpoint.host(), reference.repository());
+        let client = if endpoint.insecure() {
+            &self.insecure_client
+        } else {
+            &self.client
+        };
+        let credentials = self.credentials.get(endpoint.host());
+
+        // A registry token rejected by the registry cannot be replaced by a better one
+        let mut authenticated = matches!(credentials, Some(Credentials::RegistryToken(_)));
+        loop {
+            let mut builder = request(client);
+            let authorization = self
+                .authorizations
+                .lock()
+                .ok()
+                .and_then(|x| x.get(&key).cloned())
+                .or_else(|| match credentials {
+                    Some(Credentials::RegistryToken(token)) => {
+                        Some(Authorization::Bearer(token.clone()))
+                    }
+                    _ => None,
+                });
+            builder = match authorization {
+                Some(Authorization::Basic(username, password)) => {
+                    builder.basic_auth(username, Some(password))
+                }
+                Some(Authorization::Bearer(token)) => builder.bearer_auth(token),
+                None => builder,
+            };
+            let response = builder
+                .send()
+                .await
+                .with_context(|| format!("request {}", url))?;
+
+            // Tokens for pulling get replaced by tokens for pushing on demand
+            if response.status() != StatusCode::UNAUTHORIZED || authenticated {
+                return Ok(response);
+            }
+            let challenge = response
+                .headers()
+                .get(WWW_AUTHENTICATE)
+                .and_then(|x| x.to_str().ok())
+                .and_then(parse_challenge)
+                .with_context(|| format!("unsupported authentication challenge of {}", url))?;
+            let authorization = match challenge {
+                Challenge::Bearer(params) => {
+                    Authorization::Bearer(token(client, credentials, &params).await?)
+                }
+                Challenge::Basic => match credentials {
+                    Some(Credentials::Basic { username, password }) => {
+                        Authorization::Basic(username.clone(), password.clone())
+                    }
+                    _ => bail!("{} requires a username and password", url),
+                },
+            };
+            if let Ok(mut authorizations) = self.authorizations.lock() {
+                authorizations.insert(key.clone(), authorization);
+            }
+            authenticated = true;
+        }
+    }
+
+    /// Run the `push` to the registry of the `reference`, trying all URL schemes of it.
+    async fn push(&self, reference: &Reference, push: Push<'_>) -> Result<()> {
+        let endpoint = self
+            .registries
+            .endpoints(reference)
+            .into_iter()
+            .find(|x| !x.mirror())
+            .with_context(|| format!("no endpoint for {}", reference))?;
+        let mut error = None;
+        for scheme in endpoint.schemes() {
+            let pushed = match push {
+                Push::Blob(digest, path, mount_from) => {
+                    self.push_blob_to(&endpoint, scheme, reference, digest, path, mount_from)
+                        .await
+                }
+                Push::Manifest(media_type, content) => {
+                    let url = format!(
+                        "{}://{}/v2/{}/manifests/{}",
+                        scheme,
+                        endpoint.host(),
+                        reference.repository(),
+                        reference.object()
+                    );
+                    let request = |x: &Client| {
+                        x.put(&url)
+                            .header(CONTENT_TYPE, media_type)
+                            .body(content.to_vec())
+                    };
+                    self.send(&endpoint, reference, &url, request)
+                        .await
+                        .and_then(|x| {
+                            x.error_for_status()
+                                .with_context(|| format!("request {}", url))
+                        })
+                        .map(|_| ())
+                }
+            };
+            match pushed {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    debug!("Unable to push to {}: {:#}", endpoint.host(), e);
+                    error = Some(e);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(|| format_err!("no scheme for {}", endpoint.host())))
+    }
+
+    /// Upload the blob at `path` with the `digest` via the `scheme` to the repository of the
+    /// `reference` at the `endpoint`, or mount it from the repository of `mount_from`.
+    async fn push_blob_to(
+        &self,
+        endpoint: &Endpoint,
+        scheme: &str,
+        reference: &Reference,
+        digest: &str,
+        path: &Path,
+        mount_from: Option<&Reference>,
+    ) -> Result<()> {
+        let base = format!(
+            "{}://{}/v2/{}",
+            scheme,
+            endpoint.host(),
+            reference.repository()
+        );
+        let url = format!("{}/blobs/{}", base, digest);
+        let response = self
+            .send(endpoint, reference, &url, |x| x.head(&url))
+            .await?;
+        if response.status().is_success() {
+            debug!("Blob {} exists in {}", digest, reference.name());
+            return Ok(());
+        }
+
+        let mut url = format!("{}/blobs/uploads/", base);
+        if let Some(from) = mount_from.filter(|x| {
+            x.registry() == reference.registry() && x.repository() != reference.repository()
+        }) {
+            url = format!("{}?mount={}&from={}", url, digest, from.repository());
+        }
+        let response = self
+            .send(endpoint, reference, &url, |x| x.post(&url))
+            .await?
+            .error_for_status()
+            .with_context(|| format!("request {}", url))?;
+        if response.status() == StatusCode::CREATED {
+            debug!("Mounted blob {} into {}", digest, reference.name());
+            return Ok(());
+        }
+
+        let mut location = upload_location(&response, scheme, endpoint)?;
+        let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+        let mut offset = 0;
+        loop {
+            let mut chunk = vec![];
+            (&mut file)
+                .take(CHUNK_SIZE)
+                .read_to_end(&mut chunk)
+                .with_context(|| format!("read {}", path.display()))?;
+            if chunk.is_empty() {
+                break;
+            }
+            let range = format!("{}-{}", offset, offset + chunk.len() as u64 - 1);
+            let request = |x: &Client| {
+                x.patch(&location)
+                    .header(CONTENT_TYPE, "application/octet-stream")
+                    .header(CONTENT_RANGE, range.as_str())
+                    .body(chunk.clone())
+            };
+            let response = self
+                .send(endpoint, reference, &location, request)
+                .await?
+                .error_for_status()
+                .with_context(|| format!("upload chunk {} of blob {}", range, digest))?;
+            offset += chunk.len() as u64;
+            location = upload_location(&response, scheme, endpoint)?;
+        }
+
+        let separator = if location.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}digest={}", location, separator, digest);
+        self.send(endpoint, reference, &url, |x| x.put(&url))
+            .await?
+            .error_for_status()
+            .with_context(|| format!("complete upload of blob {}", digest))?;
+        debug!("Uploaded blob {} into {}", digest, reference.name());
+        Ok(())
+    }
+}
+
+/// Retrieve the absolute URL of the upload `response` of the `endpoint` for the next request,
+/// which may be relative to the `scheme` and host.
+fn upload_location(response: &Response, scheme: &str, endpoint: &Endpoint) -> Result<String> {
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|x| x.to_str().ok())
+        .context("no upload location in response")?;
+    if location.starts_with("http://") || location.starts_with("https://") {
+        Ok(location.into())
+    } else {
+        Ok(format!("{}://{}{}", scheme, endpoint.host(), location))
+    }
+}
+
+/// Request a bearer token from the token server of the `challenge` via the `client`. Identity
+/// tokens of the `credentials` get exchanged via the OAuth2 refresh token grant, whereas a
+/// username and password are sent via basic authentication.
+async fn token(
+    client: &Client,
+    credentials: Option<&Credentials>,
+    challenge: &HashMap<String, String>,
+) -> Result<String> {
+    let realm = challenge.get("realm").context("no realm in challenge")?;
+    let mut query: Vec<(&str, &str)> = ["service", "scope"]
+        .iter()
+        .filter_map(|k| challenge.get(*k).map(|v| (*k, v.as_str())))
+        .collect();
+    debug!("Requesting bearer token from {}", realm);
+
+    let request = match credentials {
+        Some(Credentials::IdentityToken(token)) => {
+            query.extend_from_slice(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", token.as_str()),
+                ("client_id", CLIENT_ID),
+            ]);
+            client.post(realm).form(&query)
+        }
+        Some(Credentials::Basic { username, password }) => client
+            .get(realm)
+            .query(&query)
+            .basic_auth(username, Some(password)),
+        _ => client.get(realm).query(&query),
+    };
+    let response: TokenResponse = request
+        .send()
+        .await
+        .and_then(Response::error_for_status)
+        .with_context(|| format!("request token from {}", realm))?
+        .json()
+        .await
+        .with_context(|| format!("decode token from {}", realm))?;
+    match (response.token, response.access_token) {
+        (token, _) if !token.is_empty() => Ok(token),
+        (_, token) if !token.is_empty() => Ok(token),
+        _ => bail!("no token provided by {}", realm),
+    }
+}
+
+#[tonic::async_trait]
+impl Distribution for Registry {
+    async fn manifest(&self, reference: &Reference) -> Result<(String, Vec<u8>)> {
+        let accept = [
+            MEDIA_TYPE_MANIFEST,
+            MEDIA_TYPE_INDEX,
+            MEDIA_TYPE_DOCKER_MANIFEST,
+            MEDIA_TYPE_DOCKER_MANIFEST_LIST,
+        ]
+        .join(", ");
+        let response = self
+            .get(
+                reference,
+                &format!("manifests/{}", reference.object()),
+                &accept,
+            )
+            .await?;
+        let media_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.split(';').next())
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let content = response
+            .bytes()
+            .await
+            .with_context(|| format!("read manifest of {}", reference))?;
+        Ok((media_type, content.to_vec()))
+    }
+
+    async fn blob(&self, reference: &Reference, digest: &str, file: &mut File) -> Result<()> {
+        let mut response = self
+            .get(reference, &format!("blobs/{}", digest), "*/*")
+            .await?;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("read blob {}", digest))?
+        {
+            file.write_all(&chunk)
+                .with_context(|| format!("write blob {}", digest))?;
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Upload for Registry {
+    async fn push_blob(
+        &self,
+        reference: &Reference,
+        digest: &str,
+        path: &Path,
+        mount_from: Option<&Reference>,
+    ) -> Result<()> {
+        self.push(reference, Push::Blob(digest, path, mount_from))
+            .await
+            .with_context(|| format!("push blob {}", digest))
+    }
+
+    async fn push_manifest(
+        &self,
+        reference: &Reference,
+        media_type: &str,
+        content: &[u8],
+    ) -> Result<()> {
+        self.push(reference, Push::Manifest(media_type, content))
+            .await
+            .with_context(|| format!("push manifest of {}", reference))
+    }
+}
+
+/// Parse an authentication challenge, like `Basic realm="registry"` or
+/// `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`.
+fn parse_challenge(header: &str) -> Option<Challenge> {
+    let header = header.trim();
+    if header == "Basic" || header.starts_with("Basic ") {
+        return Some(Challenge::Basic);
+    }
+    let params = header.strip_prefix("Bearer ")?;
+    let mut res = HashMap::new();
+
+    let (mut key, mut value) = (String::new(), String::new());
+    let (mut in_value, mut quoted) = (false, false);
+    for c in params.chars() {
+        match c {
+            '"' if in_value => quoted = !quoted,
+            '=' if !in_value => in_value = true,
+            ',' if !quoted => {
+                res.insert(key.trim().to_lowercase(), value.clone());
+                key.clear();
+                value.clear();
+                in_value = false;
+            }
+            c if in_value => value.push(c),
+            c => key.push(c),
+        }
+    }
+    if !key.trim().is_empty() {
+        res.insert(key.trim().to_lowercase(), value);
+    }
+    Some(Challenge::Bearer(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_challenge_success() {
+        let res = match parse_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull,push""#,
+        ) {
+            Some(Challenge::Bearer(res)) => res,
+            _ => HashMap::new(),
+        };
+        assert_eq!(
+            res.get("realm").map(String::as_str),
+            Some("https://auth.docker.io/token")
+        );
+        assert_eq!(
+            res.get("service").map(String::as_str),
+            Some("registry.docker.io")
+        );
+        assert_eq!(
+            res.get("scope").map(String::as_str),
+            Some("repository:library/nginx:pull,push")
+        );
+    }
+
+    #[test]
+    fn parse_challenge_success_basic() {
+        assert!(matches!(
+            parse_challenge(r#"Basic realm="registry""#),
+            Some(Challenge::Basic)
+        ));
+    }
+
+    #[test]
+    fn parse_challenge_failure() {
+        assert!(parse_challenge(r#"Digest realm="registry",nonce="abc""#).is_none());
+    }
+}
@@ -0,0 +1,149 @@
+//! Usage of the filesystem backing the image store, as reported via `ImageFsInfo`.
+//!
+//! The eviction manager of the kubelet polls the image filesystem frequently, whereas walking the
+//! image store is expensive. The usage is therefore sampled on a background interval and the
+//! latest sample gets served to the kubelet.
+
+use crate::{
+    clock::{Clock, SystemClock},
+    criapi::{FilesystemIdentifier, FilesystemUsage, UInt64Value},
+    stats::disk_usage,
+};
+use anyhow::{Context, Result};
+use log::{debug, error};
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{task, time};
+
+#[derive(Clone, Debug)]
+/// ImageFsUsage samples the usage of the image store.
+pub struct ImageFsUsage {
+    /// The root directory of the image store.
+    path: PathBuf,
+
+    /// The latest sample, if any.
+    sample: Arc<Mutex<Option<FilesystemUsage>>>,
+
+    /// The clock the timestamps of the samples are based on.
+    clock: Arc<dyn Clock>,
+}
+
+impl ImageFsUsage {
+    /// Create a new sampler of the image store at `path`.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.into(),
+            sample: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Base the timestamps of the samples on the `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Retrieve the latest sample, whereas the usage gets sampled if there is none yet.
+    pub fn get(&self) -> Result<FilesystemUsage> {
+        if let Ok(sample) = self.sample.lock() {
+            if let Some(sample) = sample.as_ref() {
+                return Ok(sample.clone());
+            }
+        }
+        self.refresh()
+    }
+
+    /// Sample the usage of the image store and keep it as the latest sample.
+    pub fn refresh(&self) -> Result<FilesystemUsage> {
+        let (used_bytes, inodes_used) = disk_usage(&self.path)
+            .with_context(|| format!("get disk usage of {}", self.path.display()))?;
+        let usage = FilesystemUsage {
+            timestamp: self.clock.unix_nanos()?,
+            fs_id: Some(FilesystemIdentifier {
+                mountpoint: mountpoint(&self.path)?.display().to_string(),
+            }),
+            used_bytes: Some(UInt64Value { value: used_bytes }),
+            inodes_used: Some(UInt64Value { value: inodes_used }),
+        };
+        if let Ok(mut sample) = self.sample.lock() {
+            *sample = Some(usage.clone());
+        }
+        debug!(
+            "Image filesystem uses {} bytes and {} inodes",
+            used_bytes, inodes_used
+        );
+        Ok(usage)
+    }
+
+    /// Refresh the sample every `interval`. Failing samples keep the previous one.
+    pub async fn run(self, interval: Duration) -> Result<()> {
+        let mut interval = time::interval(interval);
+        loop {
+            interval.tick().await;
+            let usage = self.clone();
+            match task::spawn_blocking(move || usage.refresh()).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!("Unable to sample image filesystem usage: {:#}", e),
+                Err(e) => error!("Unable to wait for image filesystem usage: {}", e),
+            }
+        }
+    }
+}
+
+/// Retrieve the mount point of the filesystem containing `path`, which is the topmost parent
+/// directory on the same device.
+fn mountpoint(path: &Path) -> Result<PathBuf> {
+    let path = fs::canonicalize(path).with_context(|| format!("resolve {}", path.display()))?;
+    let dev = fs::metadata(&path)
+        .with_context(|| format!("stat {}", path.display()))?
+        .dev();
+    let mut mountpoint = path.as_path();
+    while let Some(parent) = mountpoint.parent() {
+        match fs::metadata(parent) {
+            Ok(metadata) if metadata.dev() == dev => mountpoint = parent,
+            _ => break,
+        }
+    }
+    Ok(mountpoint.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::tests::FakeClock;
+    use tempfile::tempdir;
+
+    #[test]
+    fn get_success() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("blob"), vec![0; 8192])?;
+        let clock = FakeClock::default();
+        let sut = ImageFsUsage::new(dir.path()).with_clock(Arc::new(clock.clone()));
+
+        let usage = sut.get()?;
+        assert_eq!(usage.timestamp, clock.unix_nanos()?);
+        assert!(usage.used_bytes.map(|x| x.value).unwrap_or_default() >= 8192);
+        assert_eq!(usage.inodes_used.map(|x| x.value), Some(2));
+        let mountpoint = usage.fs_id.map(|x| x.mountpoint).unwrap_or_default();
+        assert!(fs::canonicalize(dir.path())?.starts_with(&mountpoint));
+
+        // The sample is served until it gets refreshed
+        fs::write(dir.path().join("other"), "content")?;
+        assert_eq!(sut.get()?.inodes_used.map(|x| x.value), Some(2));
+        assert_eq!(sut.refresh()?.inodes_used.map(|x| x.value), Some(3));
+        assert_eq!(sut.get()?.inodes_used.map(|x| x.value), Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn get_failure() {
+        let sut = ImageFsUsage::new(Path::new("/does/not/exist"));
+        assert!(sut.get().is_err());
+    }
+}
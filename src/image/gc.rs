@@ -0,0 +1,240 @@
+//! Garbage collection of images on disk pressure.
+//!
+//! The collection starts once the usage of the filesystem containing the image store exceeds the
+//! high threshold and removes unused images until the usage drops below the low threshold.
+//! Images are evicted by their retention, which grows with every container created from them, so
+//! that frequently reused images survive longer than one-off images.
+
+use crate::{
+    container::Container,
+    image::{
+        store::{ImageRecord, ImageStore},
+        usage::ImageUsage,
+    },
+    storage::KeyValueStorage,
+};
+use anyhow::{Context, Result};
+use getset::CopyGetters;
+use log::{info, warn};
+use nix::sys::statvfs::statvfs;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+#[derive(Clone, Copy, CopyGetters, Debug, PartialEq)]
+/// GcPolicy defines when images get collected.
+pub struct GcPolicy {
+    #[get_copy = "pub"]
+    /// The filesystem usage in percent which starts the collection.
+    high_threshold: u64,
+
+    #[get_copy = "pub"]
+    /// The filesystem usage in percent the collection tries to reach.
+    low_threshold: u64,
+}
+
+impl GcPolicy {
+    /// Create a new policy from the `high_threshold` and `low_threshold` in percent.
+    pub fn new(high_threshold: u64, low_threshold: u64) -> Self {
+        Self {
+            high_threshold,
+            low_threshold: low_threshold.min(high_threshold),
+        }
+    }
+
+    /// Retrieve the number of bytes to free on a filesystem of `capacity` bytes with `available`
+    /// bytes left, which is zero if the usage is below the high threshold.
+    pub fn bytes_to_free(&self, capacity: u64, available: u64) -> u64 {
+        let (capacity, used) = (
+            u128::from(capacity),
+            u128::from(capacity.saturating_sub(available)),
+        );
+        if capacity == 0 || used * 100 < u128::from(self.high_threshold) * capacity {
+            return 0;
+        }
+        let target = u128::from(self.low_threshold) * capacity / 100;
+        used.saturating_sub(target) as u64
+    }
+
+    /// Select the `images` to remove for freeing `bytes`, ordered by their retention according
+    /// to `usages`. Images in `in_use` are never selected.
+    pub fn select(
+        &self,
+        images: Vec<ImageRecord>,
+        usages: &HashMap<String, ImageUsage>,
+        in_use: &HashSet<String>,
+        bytes: u64,
+    ) -> Vec<ImageRecord> {
+        let mut candidates: Vec<ImageRecord> = images
+            .into_iter()
+            .filter(|x| !in_use.contains(x.id()))
+            .collect();
+        candidates.sort_by_key(|x| {
+            usages
+                .get(x.id())
+                .map(ImageUsage::retained_until)
+                .unwrap_or_default()
+        });
+
+        let mut freed = 0;
+        candidates
+            .into_iter()
+            .take_while(|x| {
+                let take = freed < bytes;
+                freed += x.size();
+                take
+            })
+            .collect()
+    }
+
+    /// Run a collection of the `store` at `path` if the filesystem is under pressure, which
+    /// never removes the `pinned` images. Returns the IDs of the removed images.
+    pub fn collect<S: KeyValueStorage>(
+        &self,
+        store: &ImageStore,
+        storage: &mut S,
+        path: &Path,
+        pinned: &[String],
+    ) -> Result<Vec<String>> {
+        let stat = statvfs(path).with_context(|| format!("stat filesystem {}", path.display()))?;
+        let fragment_size = stat.fragment_size() as u64;
+        let bytes = self.bytes_to_free(
+            stat.blocks() as u64 * fragment_size,
+            stat.blocks_available() as u64 * fragment_size,
+        );
+        if bytes == 0 {
+            return Ok(vec![]);
+        }
+
+        let images = ImageStore::list(storage).context("list images")?;
+        let usages = ImageUsage::list(storage).context("list image usages")?;
+        let in_use = images_in_use(storage, pinned)?;
+        let mut removed = vec![];
+        for record in self.select(images, &usages, &in_use, bytes) {
+            match store.remove(storage, &record) {
+                Ok(()) => removed.push(record.id().clone()),
+                Err(e) => warn!("Unable to collect image {}: {:#}", record.id(), e),
+            }
+        }
+        info!(
+            "Collected {} images to free {} bytes in {}",
+            removed.len(),
+            bytes,
+            path.display()
+        );
+        Ok(removed)
+    }
+}
+
+/// Retrieve the IDs of all images used by stored containers or referenced by the `pinned`
+/// images. Unresolvable images are skipped, because they cannot refer to a stored image.
+fn images_in_use<S: KeyValueStorage>(
+    storage: &mut S,
+    pinned: &[String],
+) -> Result<HashSet<String>> {
+    let containers = storage
+        .scan_prefix::<_, Container>(Container::key_prefix())
+        .context("list containers")?;
+    Ok(containers
+        .iter()
+        .map(Container::image)
+        .chain(pinned)
+        .filter_map(|x| ImageStore::find(storage, x).ok().flatten())
+        .map(|x| x.id().clone())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        image::{reference::Reference, store::tests::FakeDistribution},
+        storage::default_key_value_storage::DefaultKeyValueStorage,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn bytes_to_free_success() {
+        let sut = GcPolicy::new(85, 80);
+        assert_eq!(sut.bytes_to_free(1000, 200), 0);
+        assert_eq!(sut.bytes_to_free(1000, 150), 50);
+        assert_eq!(sut.bytes_to_free(1000, 0), 200);
+        assert_eq!(sut.bytes_to_free(0, 0), 0);
+    }
+
+    #[test]
+    fn new_clamps_low_threshold() {
+        assert_eq!(GcPolicy::new(50, 80).low_threshold(), 50);
+    }
+
+    #[tokio::test]
+    async fn select_prefers_reused_images() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let store = ImageStore::open(&dir.path().join("images"), None)?;
+        let mut source = FakeDistribution::default();
+        let mut ids = vec![];
+        for tag in &["reused", "once", "running"] {
+            ids.push(source.add_image(tag, tag)?);
+            let reference: Reference = format!("quay.io/tenant/app:{}", tag).parse()?;
+            store.pull(&mut storage, &source, &reference).await?;
+        }
+
+        // All images got pulled at the same time, but the reused one earned more retention
+        for i in 0..5 {
+            ImageUsage::record_use(&mut storage, &ids[0], i)?;
+        }
+        ImageUsage::record_use(&mut storage, &ids[1], 10)?;
+
+        let sut = GcPolicy::new(85, 80);
+        let images = ImageStore::list(&mut storage)?;
+        let usages = ImageUsage::list(&mut storage)?;
+        let in_use = vec![ids[2].clone()].into_iter().collect();
+
+        let selected = sut.select(images.clone(), &usages, &in_use, 1);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].id(), &ids[1]);
+
+        let selected = sut.select(images.clone(), &usages, &in_use, u64::MAX);
+        let selected: Vec<_> = selected.iter().map(|x| x.id().clone()).collect();
+        assert_eq!(selected, vec![ids[1].clone(), ids[0].clone()]);
+
+        assert!(sut.select(images, &usages, &in_use, 0).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn collect_without_pressure() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let path = dir.path().join("images");
+        let store = ImageStore::open(&path, None)?;
+        let (source, id) = FakeDistribution::with_image("latest", "file")?;
+        let reference: Reference = "quay.io/tenant/app:latest".parse()?;
+        store.pull(&mut storage, &source, &reference).await?;
+
+        let sut = GcPolicy::new(100, 100);
+        assert!(sut.collect(&store, &mut storage, &path, &[])?.is_empty());
+        assert!(ImageStore::find(&mut storage, &id)?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn images_in_use_success_pinned() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let store = ImageStore::open(&dir.path().join("images"), None)?;
+        let (source, id) = FakeDistribution::with_image("latest", "file")?;
+        let reference: Reference = "quay.io/tenant/app:latest".parse()?;
+        store.pull(&mut storage, &source, &reference).await?;
+
+        assert!(images_in_use(&mut storage, &[])?.is_empty());
+        let pinned = vec!["quay.io/tenant/app:latest".into(), "missing".into()];
+        assert_eq!(
+            images_in_use(&mut storage, &pinned)?,
+            vec![id].into_iter().collect()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+//! Resolution of images from a local OCI image layout directory.
+//!
+//! Nodes bootstrapping their control plane from static pods may not reach any registry yet, so
+//! the images of these pods can be shipped as an OCI layout, like the ones written by `skopeo
+//! copy` or `ctr images export`. Images are looked up in the `index.json` of the layout by their
+//! `org.opencontainers.image.ref.name` or `io.containerd.image.name` annotation, which has to be
+//! a reference like `registry.k8s.io/pause:3.2`. Manifests and blobs referenced by digest are
+//! read from the `blobs` directory directly.
+
+use crate::{
+    image::{
+        distribution::Distribution,
+        reference::{validate_digest, Reference},
+    },
+    oci_spec::image::MEDIA_TYPE_MANIFEST,
+};
+use anyhow::{bail, format_err, Context, Error, Result};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The file marking a directory as OCI image layout.
+const LAYOUT_FILE: &str = "oci-layout";
+
+/// The file listing the images of the layout.
+const INDEX_FILE: &str = "index.json";
+
+/// The directory containing the blobs by their digest algorithm and hex value.
+const BLOBS_DIR: &str = "blobs";
+
+/// The annotations of index entries naming the image, in the order they are looked up.
+const NAME_ANNOTATIONS: &[&str] = &[
+    "org.opencontainers.image.ref.name",
+    "io.containerd.image.name",
+];
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+/// LocalImagePolicy defines whether images may be pulled from registries if the local layout
+/// does not contain them.
+pub enum LocalImagePolicy {
+    /// The registries are consulted if the image is not part of the layout.
+    Prefer,
+
+    /// The image is only resolved from the layout and never pulled from a registry.
+    Only,
+}
+
+#[derive(Clone, CopyGetters, Debug, Deserialize, Getters, PartialEq, Serialize)]
+/// LocalImageSource defines which images get resolved from the local layout.
+pub struct LocalImageSource {
+    #[get = "pub"]
+    /// The prefix of the references, like `registry.k8s.io/`.
+    prefix: String,
+
+    #[get_copy = "pub"]
+    /// Whether the registries are consulted as well.
+    policy: LocalImagePolicy,
+}
+
+impl LocalImageSource {
+    /// Find the policy of the first of the `sources` matching the `reference`. Every image
+    /// prefers the layout if there are no sources at all.
+    pub fn policy(sources: &[Self], reference: &Reference) -> Option<LocalImagePolicy> {
+        if sources.is_empty() {
+            return Some(LocalImagePolicy::Prefer);
+        }
+        let image = reference.to_string();
+        sources
+            .iter()
+            .find(|x| image.starts_with(&x.prefix))
+            .map(|x| x.policy)
+    }
+}
+
+impl FromStr for LocalImageSource {
+    type Err = Error;
+
+    /// Parse a source in the format `PREFIX=prefer` or `PREFIX=only`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.rsplitn(2, '=');
+        let (policy, prefix) = match (parts.next(), parts.next()) {
+            (Some(policy), Some(prefix)) if !prefix.is_empty() => (policy, prefix),
+            _ => bail!("invalid local image source {}, expected PREFIX=POLICY", s),
+        };
+        let policy = match policy {
+            "prefer" => LocalImagePolicy::Prefer,
+            "only" => LocalImagePolicy::Only,
+            _ => bail!(
+                "invalid policy {} of local image source {}, expected prefer or only",
+                policy,
+                prefix
+            ),
+        };
+        Ok(Self {
+            prefix: prefix.into(),
+            policy,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+/// LocalLayout serves image manifests and blobs from an OCI image layout directory.
+pub struct LocalLayout {
+    /// The root directory of the layout.
+    path: PathBuf,
+}
+
+impl LocalLayout {
+    /// Open the layout at `path`, which has to contain the `oci-layout` marker file.
+    pub fn open(path: &Path) -> Result<Self> {
+        let marker = path.join(LAYOUT_FILE);
+        let content = fs::read(&marker)
+            .with_context(|| format!("read image layout file {}", marker.display()))?;
+        let version = serde_json::from_slice::<Value>(&content)
+            .with_context(|| format!("parse image layout file {}", marker.display()))?;
+        if version["imageLayoutVersion"].as_str().is_none() {
+            bail!("no image layout version in {}", marker.display())
+        }
+        Ok(Self { path: path.into() })
+    }
+
+    /// Retrieve the path of the blob with the `digest`.
+    fn blob_path(&self, digest: &str) -> Result<PathBuf> {
+        validate_digest(digest)?;
+        let mut parts = digest.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(algorithm), Some(encoded)) => {
+                Ok(self.path.join(BLOBS_DIR).join(algorithm).join(encoded))
+            }
+            _ => bail!("invalid digest {}", digest),
+        }
+    }
+
+    /// Read the blob with the `digest`.
+    fn read_blob(&self, digest: &str) -> Result<Vec<u8>> {
+        let path = self.blob_path(digest)?;
+        fs::read(&path).with_context(|| format!("read blob {} of image layout", digest))
+    }
+
+    /// Find the descriptor of the `reference` in the index of the layout.
+    fn find(&self, reference: &Reference) -> Result<Value> {
+        let path = self.path.join(INDEX_FILE);
+        let index: Value = serde_json::from_slice(
+            &fs::read(&path).with_context(|| format!("read image index {}", path.display()))?,
+        )
+        .with_context(|| format!("parse image index {}", path.display()))?;
+
+        let manifests = index["manifests"].as_array().cloned().unwrap_or_default();
+        manifests
+            .into_iter()
+            .find(|descriptor| {
+                NAME_ANNOTATIONS.iter().any(|x| {
+                    descriptor["annotations"][x]
+                        .as_str()
+                        .and_then(|x| x.parse::<Reference>().ok())
+                        .map_or(false, |x| &x == reference)
+                })
+            })
+            .ok_or_else(|| format_err!("image {} not found in {}", reference, path.display()))
+    }
+}
+
+#[tonic::async_trait]
+impl Distribution for LocalLayout {
+    async fn manifest(&self, reference: &Reference) -> Result<(String, Vec<u8>)> {
+        // Manifests referenced by digest may be nested within an index of the layout
+        if let Some(digest) = reference.digest() {
+            let manifest = self.read_blob(digest)?;
+            let media_type = serde_json::from_slice::<Value>(&manifest)
+                .ok()
+                .and_then(|x| x["mediaType"].as_str().map(String::from))
+                .unwrap_or_else(|| MEDIA_TYPE_MANIFEST.into());
+            return Ok((media_type, manifest));
+        }
+
+        let descriptor = self.find(reference)?;
+        let digest = descriptor["digest"]
+            .as_str()
+            .with_context(|| format!("no digest for image {} in image layout", reference))?;
+        let media_type = descriptor["mediaType"]
+            .as_str()
+            .unwrap_or(MEDIA_TYPE_MANIFEST)
+            .to_string();
+        Ok((media_type, self.read_blob(digest)?))
+    }
+
+    async fn blob(&self, _: &Reference, digest: &str, file: &mut File) -> Result<()> {
+        let path = self.blob_path(digest)?;
+        let mut blob =
+            File::open(&path).with_context(|| format!("open blob {} of image layout", digest))?;
+        io::copy(&mut blob, file).with_context(|| format!("copy blob {}", digest))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use std::io::{Read, Seek, SeekFrom};
+    use tempfile::tempdir;
+
+    /// Write the `content` as blob into the layout at `dir` and return its digest.
+    fn write_blob(dir: &Path, content: &[u8]) -> Result<String> {
+        let encoded = format!("{:x}", Sha256::digest(content));
+        let path = dir.join(BLOBS_DIR).join("sha256");
+        fs::create_dir_all(&path)?;
+        fs::write(path.join(&encoded), content)?;
+        Ok(format!("sha256:{}", encoded))
+    }
+
+    #[tokio::test]
+    async fn local_layout_success() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join(LAYOUT_FILE),
+            r#"{"imageLayoutVersion":"1.0.0"}"#,
+        )?;
+        let manifest = br#"{"schemaVersion":2}"#;
+        let digest = write_blob(dir.path(), manifest)?;
+        let index = json!({
+            "schemaVersion": 2,
+            "manifests": [{
+                "mediaType": MEDIA_TYPE_MANIFEST,
+                "digest": digest,
+                "size": manifest.len(),
+                "annotations": {"org.opencontainers.image.ref.name": "registry.k8s.io/pause:3.2"},
+            }],
+        });
+        fs::write(dir.path().join(INDEX_FILE), serde_json::to_vec(&index)?)?;
+        let sut = LocalLayout::open(dir.path())?;
+
+        let reference: Reference = "registry.k8s.io/pause:3.2".parse()?;
+        let expected = (MEDIA_TYPE_MANIFEST.to_string(), manifest.to_vec());
+        assert_eq!(sut.manifest(&reference).await?, expected);
+        assert_eq!(
+            sut.manifest(&reference.with_digest(&digest)).await?,
+            expected
+        );
+        assert!(sut.manifest(&"pause:3.2".parse()?).await.is_err());
+
+        let mut file = tempfile::tempfile()?;
+        sut.blob(&reference, &digest, &mut file).await?;
+        let mut content = vec![];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut content)?;
+        assert_eq!(content, manifest);
+        assert!(sut
+            .blob(&reference, "sha256:../../etc", &mut file)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn local_layout_fail_no_layout() -> Result<()> {
+        let dir = tempdir()?;
+        assert!(LocalLayout::open(dir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn local_image_source_success() -> Result<()> {
+        let sources: Vec<LocalImageSource> = vec![
+            "registry.k8s.io/=only".parse()?,
+            "docker.io/=prefer".parse()?,
+        ];
+        let policy =
+            |image: &str| -> Result<_> { Ok(LocalImageSource::policy(&sources, &image.parse()?)) };
+        assert_eq!(
+            policy("registry.k8s.io/pause:3.2")?,
+            Some(LocalImagePolicy::Only)
+        );
+        assert_eq!(policy("nginx")?, Some(LocalImagePolicy::Prefer));
+        assert_eq!(policy("quay.io/tenant/app")?, None);
+        assert_eq!(
+            LocalImageSource::policy(&[], &"quay.io/tenant/app".parse()?),
+            Some(LocalImagePolicy::Prefer)
+        );
+
+        for source in &["registry.k8s.io/", "=only", "registry.k8s.io/=always"] {
+            assert!(source.parse::<LocalImageSource>().is_err(), "{}", source);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+//! Concurrency limits of image pulls.
+//!
+//! The limiter is shared by all pulls of the node. It bounds the number of images being pulled
+//! at the same time as well as the number of layers fetched in parallel per image. Every blob
+//! gets fetched under a lock of its digest, which makes concurrent pulls of the same image or of
+//! images sharing a layer wait for a single download instead of fetching the blob twice.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, Weak},
+};
+use tokio::sync::{Mutex as AsyncMutex, Semaphore, SemaphorePermit};
+
+/// The number of layers fetched in parallel per image if not configured otherwise.
+const DEFAULT_PARALLEL_LAYERS: usize = 3;
+
+#[derive(Clone)]
+/// PullLimiter bounds the concurrency of image pulls.
+pub struct PullLimiter {
+    /// The permits of concurrent pulls, which are unlimited if not set.
+    pulls: Option<Arc<Semaphore>>,
+
+    /// The maximum number of layers fetched in parallel per image.
+    parallel_layers: usize,
+
+    /// The locks of the blobs being fetched by their digest.
+    blobs: Arc<Mutex<HashMap<String, Weak<AsyncMutex<()>>>>>,
+}
+
+impl Default for PullLimiter {
+    fn default() -> Self {
+        Self::new(0, DEFAULT_PARALLEL_LAYERS)
+    }
+}
+
+impl fmt::Debug for PullLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PullLimiter")
+            .field("pulls", &self.pulls.as_ref().map(|x| x.available_permits()))
+            .field("parallel_layers", &self.parallel_layers)
+            .finish()
+    }
+}
+
+impl PullLimiter {
+    /// Create a new limiter allowing `max_pulls` concurrent pulls, whereas `0` means unlimited,
+    /// and `parallel_layers` layers being fetched in parallel per image.
+    pub fn new(max_pulls: usize, parallel_layers: usize) -> Self {
+        Self {
+            pulls: if max_pulls == 0 {
+                None
+            } else {
+                Some(Arc::new(Semaphore::new(max_pulls)))
+            },
+            parallel_layers: parallel_layers.max(1),
+            blobs: Arc::default(),
+        }
+    }
+
+    /// Retrieve the maximum number of layers fetched in parallel per image.
+    pub fn parallel_layers(&self) -> usize {
+        self.parallel_layers
+    }
+
+    /// Wait until another pull may start. The pull is accounted for until the returned permit
+    /// gets dropped.
+    pub async fn acquire_pull(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.pulls {
+            Some(pulls) => Some(pulls.acquire().await),
+            None => None,
+        }
+    }
+
+    /// Retrieve the lock of the blob with the `digest`, which has to be held while fetching it.
+    pub fn blob_lock(&self, digest: &str) -> Arc<AsyncMutex<()>> {
+        let mut blobs = match self.blobs.lock() {
+            Ok(blobs) => blobs,
+            // A poisoned map only loses the deduplication, not the correctness of the fetch
+            Err(_) => return Arc::new(AsyncMutex::new(())),
+        };
+        if let Some(lock) = blobs.get(digest).and_then(Weak::upgrade) {
+            return lock;
+        }
+        blobs.retain(|_, x| x.strong_count() > 0);
+        let lock = Arc::new(AsyncMutex::new(()));
+        blobs.insert(digest.into(), Arc::downgrade(&lock));
+        lock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::time::Duration;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn acquire_pull_success() -> Result<()> {
+        let sut = PullLimiter::new(1, 2);
+        let first = sut.acquire_pull().await;
+        assert!(first.is_some());
+        assert!(time::timeout(Duration::from_millis(10), sut.acquire_pull())
+            .await
+            .is_err());
+        drop(first);
+        assert!(sut.acquire_pull().await.is_some());
+
+        let sut = PullLimiter::new(0, 0);
+        assert!(sut.acquire_pull().await.is_none());
+        assert_eq!(sut.parallel_layers(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn blob_lock_success() -> Result<()> {
+        let sut = PullLimiter::default();
+        let lock = sut.blob_lock("sha256:a");
+        let guard = lock.lock().await;
+        let same = sut.blob_lock("sha256:a");
+        assert!(same.try_lock().is_err());
+        assert!(sut.blob_lock("sha256:b").try_lock().is_ok());
+
+        drop(guard);
+        drop((lock, same));
+        assert!(sut.blob_lock("sha256:a").try_lock().is_ok());
+        if let Ok(blobs) = sut.blobs.lock() {
+            assert_eq!(blobs.len(), 1);
+        }
+        Ok(())
+    }
+}
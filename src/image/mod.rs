@@ -0,0 +1,19 @@
+//! Image handling
+
+pub mod artifact;
+pub mod auth;
+pub mod cache;
+pub mod content;
+pub mod distribution;
+pub mod fs_usage;
+pub mod gc;
+pub mod layout;
+pub mod limiter;
+pub mod peer;
+pub mod prefetch;
+pub mod reference;
+pub mod registries;
+pub mod rootfs;
+pub mod signature;
+pub mod store;
+pub mod usage;
@@ -0,0 +1,166 @@
+//! Retrieval of blobs from a node-local peer to peer distribution system.
+//!
+//! Peer to peer systems like Dragonfly or Spegel run a daemon on every node, which serves blobs
+//! already pulled by other nodes of the cluster via the OCI distribution API. Blobs are requested
+//! from the daemon like from a registry mirror, whereas the registry of the image is passed via
+//! the `ns` query parameter. Manifests are always resolved by the registry itself, because tags
+//! are not content addressed. Blobs which the peer is unable to serve or which do not match their
+//! digest are fetched from the registry instead.
+
+use crate::image::reference::Reference;
+use anyhow::{bail, Context, Result};
+use log::debug;
+use reqwest::{Client, Url};
+use sha2::{Digest, Sha256};
+use std::{fmt, fs::File, io::Write, time::Duration};
+use tokio::time;
+
+#[derive(Clone)]
+/// PeerSource fetches blobs from the peer to peer distribution daemon of the node.
+pub struct PeerSource {
+    /// The HTTP client used for all requests to the daemon.
+    client: Client,
+
+    /// The base URL of the daemon, like `http://127.0.0.1:5001`.
+    endpoint: Url,
+
+    /// The time the daemon has to start responding to a request.
+    timeout: Duration,
+}
+
+impl fmt::Debug for PeerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerSource")
+            .field("endpoint", &self.endpoint.as_str())
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl PeerSource {
+    /// Create a new source for the daemon at `endpoint`, which has to start responding to a
+    /// request within the `timeout`.
+    pub fn new(endpoint: &str, timeout: Duration) -> Result<Self> {
+        let endpoint =
+            Url::parse(endpoint).with_context(|| format!("parse peer endpoint {}", endpoint))?;
+        if !["http", "https"].contains(&endpoint.scheme()) {
+            bail!("unsupported scheme of peer endpoint {}", endpoint)
+        }
+        Ok(Self {
+            client: Client::new(),
+            endpoint,
+            timeout,
+        })
+    }
+
+    /// Retrieve the blob with the provided `digest` from the repository of the `reference` and
+    /// write it into `file`. Fails if the content does not match the digest, in which case the
+    /// `file` contains partial content.
+    pub async fn blob(&self, reference: &Reference, digest: &str, file: &mut File) -> Result<()> {
+        let mut url = self
+            .endpoint
+            .join(&format!("v2/{}/blobs/{}", reference.repository(), digest))
+            .with_context(|| format!("build peer URL of blob {}", digest))?;
+        url.query_pairs_mut()
+            .append_pair("ns", reference.registry());
+
+        debug!("Requesting blob {} from peer {}", digest, self.endpoint);
+        let mut response = time::timeout(self.timeout, self.client.get(url.clone()).send())
+            .await
+            .with_context(|| format!("wait for response of {}", url))?
+            .and_then(|x| x.error_for_status())
+            .with_context(|| format!("request {}", url))?;
+
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("read blob {}", digest))?
+        {
+            hasher.update(&chunk);
+            file.write_all(&chunk)
+                .with_context(|| format!("write blob {}", digest))?;
+        }
+        let actual = format!("sha256:{:x}", hasher.finalize());
+        if actual != digest {
+            bail!("peer served {} instead of blob {}", actual, digest)
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, fs, net::SocketAddr};
+    use tempfile::tempdir;
+    use warp::{http::StatusCode, Filter};
+
+    /// Serve the `blobs` by their digest for the repository `library/image` of `docker.io`.
+    fn serve(blobs: HashMap<String, Vec<u8>>) -> Result<SocketAddr> {
+        let routes = warp::path!("v2" / "library" / "image" / "blobs" / String)
+            .and(warp::query::<HashMap<String, String>>())
+            .map(move |digest: String, query: HashMap<String, String>| {
+                match (blobs.get(&digest), query.get("ns").map(String::as_str)) {
+                    (Some(blob), Some("docker.io")) => {
+                        warp::reply::with_status(blob.clone(), StatusCode::OK)
+                    }
+                    _ => warp::reply::with_status(vec![], StatusCode::NOT_FOUND),
+                }
+            });
+        let (address, server) = warp::serve(routes).try_bind_ephemeral(([127, 0, 0, 1], 0))?;
+        tokio::spawn(server);
+        Ok(address)
+    }
+
+    #[tokio::test]
+    async fn blob_success() -> Result<()> {
+        let digest = format!("sha256:{:x}", Sha256::digest(b"content"));
+        let mut blobs = HashMap::new();
+        blobs.insert(digest.clone(), b"content".to_vec());
+        let address = serve(blobs)?;
+
+        let sut = PeerSource::new(&format!("http://{}", address), Duration::from_secs(5))?;
+        let dir = tempdir()?;
+        let path = dir.path().join("blob");
+        let reference: Reference = "image".parse()?;
+        sut.blob(&reference, &digest, &mut File::create(&path)?)
+            .await?;
+        assert_eq!(fs::read(&path)?, b"content");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn blob_failure() -> Result<()> {
+        let digest = format!("sha256:{:x}", Sha256::digest(b"content"));
+        let mut blobs = HashMap::new();
+        blobs.insert(digest.clone(), b"other".to_vec());
+        let address = serve(blobs)?;
+
+        let sut = PeerSource::new(&format!("http://{}", address), Duration::from_secs(5))?;
+        let dir = tempdir()?;
+        let mut file = File::create(dir.path().join("blob"))?;
+        let reference: Reference = "image".parse()?;
+        assert!(sut.blob(&reference, &digest, &mut file).await.is_err());
+
+        // Blobs unknown to the peer and other registries are not served
+        let reference: Reference = "quay.io/library/image".parse()?;
+        assert!(sut.blob(&reference, &digest, &mut file).await.is_err());
+        assert!(sut
+            .blob(&reference, "sha256:unknown", &mut file)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn new_failure() {
+        for endpoint in &["127.0.0.1:5001", "unix:///run/peer.sock", ""] {
+            assert!(
+                PeerSource::new(endpoint, Duration::from_secs(1)).is_err(),
+                "{}",
+                endpoint
+            );
+        }
+    }
+}
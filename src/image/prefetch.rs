@@ -0,0 +1,134 @@
+//! Prefetching of images which are needed soon.
+//!
+//! A prefetch pulls an image in the background before the kubelet asks for it. Pulls of an image
+//! whose prefetch is still running wait for it instead of fetching the same blobs in parallel.
+
+use futures_util::future::{self, BoxFuture, FutureExt, Shared};
+use log::debug;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone, Default)]
+/// Prefetcher keeps track of the running prefetches.
+pub struct Prefetcher {
+    /// The running prefetches by their image.
+    running: Arc<Mutex<HashMap<String, Shared<BoxFuture<'static, ()>>>>>,
+}
+
+impl Prefetcher {
+    /// Run the prefetch `f` of the `image` in the background, unless the image is already being
+    /// prefetched. The prefetch has to handle its own errors.
+    pub fn spawn<F>(&self, image: &str, f: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let prefetch = f.boxed().shared();
+        match self.running.lock() {
+            Ok(mut running) if !running.contains_key(image) => {
+                running.insert(image.into(), prefetch.clone());
+            }
+            _ => return,
+        }
+
+        debug!("Prefetching image {}", image);
+        let (prefetcher, image) = (self.clone(), image.to_string());
+        tokio::spawn(async move {
+            prefetch.await;
+            if let Ok(mut running) = prefetcher.running.lock() {
+                running.remove(&image);
+            }
+        });
+    }
+
+    /// Wait until the running prefetch of the `image` has finished. Returns immediately if the
+    /// image is not being prefetched.
+    pub async fn wait(&self, image: &str) {
+        let prefetch = self.running.lock().ok().and_then(|x| x.get(image).cloned());
+        if let Some(prefetch) = prefetch {
+            debug!("Waiting for prefetch of image {}", image);
+            prefetch.await
+        }
+    }
+
+    /// Wait until all running prefetches have finished.
+    pub async fn wait_all(&self) {
+        let running: Vec<_> = self
+            .running
+            .lock()
+            .map(|x| x.values().cloned().collect())
+            .unwrap_or_default();
+        future::join_all(running).await;
+    }
+
+    /// Check if the `image` is being prefetched.
+    pub fn is_running(&self, image: &str) -> bool {
+        self.running.lock().map_or(false, |x| x.contains_key(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use tokio::{sync::oneshot, time};
+
+    #[tokio::test]
+    async fn spawn_wait_success() -> Result<()> {
+        let sut = Prefetcher::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let (release, released) = oneshot::channel::<()>();
+        sut.spawn("image", {
+            let runs = runs.clone();
+            async move {
+                released.await.ok();
+                runs.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        // Running prefetches are not started twice
+        sut.spawn("image", {
+            let runs = runs.clone();
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        assert!(sut.is_running("image"));
+
+        let waiting = time::timeout(Duration::from_millis(10), sut.wait("image")).await;
+        assert!(waiting.is_err());
+
+        release.send(()).ok();
+        sut.wait("image").await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // The prefetch gets forgotten once finished
+        time::delay_for(Duration::from_millis(10)).await;
+        assert!(!sut.is_running("image"));
+        sut.wait("image").await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_all_success() -> Result<()> {
+        let sut = Prefetcher::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+        for image in &["first", "second"] {
+            let runs = runs.clone();
+            sut.spawn(image, async move {
+                time::delay_for(Duration::from_millis(10)).await;
+                runs.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        sut.wait_all().await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+//! Parsing of image references like `quay.io/tenant/app:v1` or `nginx`.
+
+use anyhow::{bail, Error, Result};
+use getset::Getters;
+use std::{fmt, str::FromStr};
+
+/// The registry used for image references without an explicit registry.
+pub const DEFAULT_REGISTRY: &str = "docker.io";
+
+/// The host serving the API of the default registry.
+const DEFAULT_REGISTRY_HOST: &str = "registry-1.docker.io";
+
+/// The repository namespace of official images on the default registry.
+const DEFAULT_NAMESPACE: &str = "library";
+
+/// The tag used for image references without a tag and digest.
+const DEFAULT_TAG: &str = "latest";
+
+#[derive(Clone, Debug, Eq, Getters, PartialEq)]
+/// Reference is a fully qualified image reference.
+pub struct Reference {
+    #[get = "pub"]
+    /// The registry, like `quay.io`.
+    registry: String,
+
+    #[get = "pub"]
+    /// The repository within the registry, like `tenant/app`.
+    repository: String,
+
+    #[get = "pub"]
+    /// The tag, which is `latest` if neither a tag nor a digest has been provided.
+    tag: Option<String>,
+
+    #[get = "pub"]
+    /// The content digest of the manifest, like `sha256:abc…`.
+    digest: Option<String>,
+}
+
+impl Reference {
+    /// Retrieve the name of the image without tag and digest, like `quay.io/tenant/app`.
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.registry, self.repository)
+    }
+
+    /// Retrieve the host serving the registry API.
+    pub fn host(&self) -> &str {
+        if self.registry == DEFAULT_REGISTRY {
+            DEFAULT_REGISTRY_HOST
+        } else {
+            &self.registry
+        }
+    }
+
+    /// Retrieve the digest or the tag, which identifies the manifest within the repository.
+    pub fn object(&self) -> &str {
+        self.digest
+            .as_deref()
+            .or_else(|| self.tag.as_deref())
+            .unwrap_or(DEFAULT_TAG)
+    }
+
+    /// Retrieve the reference pinned to the provided manifest `digest`.
+    pub fn with_digest(&self, digest: &str) -> Self {
+        Self {
+            tag: None,
+            digest: Some(digest.into()),
+            ..self.clone()
+        }
+    }
+
+    /// Retrieve the reference to the provided `tag` within the same repository.
+    pub fn with_tag(&self, tag: &str) -> Self {
+        Self {
+            tag: Some(tag.into()),
+            digest: None,
+            ..self.clone()
+        }
+    }
+}
+
+impl FromStr for Reference {
+    type Err = Error;
+
+    /// Parse and normalize a reference in the format `[REGISTRY/]REPOSITORY[:TAG][@DIGEST]`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (rest, digest) = match s.find('@') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        if let Some(digest) = digest {
+            validate_digest(digest)?;
+        }
+
+        // A colon after the last slash separates the tag, otherwise it belongs to a registry port
+        let (name, tag) = match rest.rfind(':') {
+            Some(i) if !rest[i..].contains('/') => (&rest[..i], Some(&rest[i + 1..])),
+            _ => (rest, None),
+        };
+        if let Some(tag) = tag {
+            if tag.is_empty()
+                || tag.len() > 128
+                || !tag
+                    .chars()
+                    .all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '.' || x == '-')
+            {
+                bail!("invalid tag in image reference {}", s)
+            }
+        }
+
+        let (registry, repository) = match name.find('/') {
+            Some(i)
+                if name[..i].contains('.')
+                    || name[..i].contains(':')
+                    || &name[..i] == "localhost" =>
+            {
+                (&name[..i], name[i + 1..].to_string())
+            }
+            Some(_) => (DEFAULT_REGISTRY, name.to_string()),
+            None => (DEFAULT_REGISTRY, format!("{}/{}", DEFAULT_NAMESPACE, name)),
+        };
+        if repository.is_empty()
+            || !repository.split('/').all(|x| {
+                !x.is_empty()
+                    && x.chars().all(|x| {
+                        x.is_ascii_lowercase()
+                            || x.is_ascii_digit()
+                            || x == '_'
+                            || x == '.'
+                            || x == '-'
+                    })
+            })
+        {
+            bail!("invalid repository in image reference {}", s)
+        }
+
+        Ok(Self {
+            registry: registry.into(),
+            repository,
+            tag: tag
+                .map(Into::into)
+                .or_else(|| Some(DEFAULT_TAG.into()).filter(|_| digest.is_none())),
+            digest: digest.map(Into::into),
+        })
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+/// Validate that the `digest` is in the format `ALGORITHM:HEX`.
+pub fn validate_digest(digest: &str) -> Result<()> {
+    let mut parts = digest.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(algorithm), Some(encoded))
+            if !algorithm.is_empty()
+                && algorithm
+                    .chars()
+                    .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit())
+                && !encoded.is_empty()
+                && encoded.chars().all(|x| x.is_ascii_hexdigit()) =>
+        {
+            Ok(())
+        }
+        _ => bail!("invalid digest {}", digest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:0123456789abcdef";
+
+    #[test]
+    fn from_str_success() -> Result<()> {
+        for (input, expected) in &[
+            ("nginx", "docker.io/library/nginx:latest"),
+            ("library/nginx:1.19", "docker.io/library/nginx:1.19"),
+            ("quay.io/tenant/app:v1", "quay.io/tenant/app:v1"),
+            ("localhost/app", "localhost/app:latest"),
+            ("localhost:5000/app:v1", "localhost:5000/app:v1"),
+            (
+                "localhost:5000/app@sha256:0123456789abcdef",
+                "localhost:5000/app@sha256:0123456789abcdef",
+            ),
+            (
+                "nginx:1.19@sha256:0123456789abcdef",
+                "docker.io/library/nginx:1.19@sha256:0123456789abcdef",
+            ),
+        ] {
+            assert_eq!(input.parse::<Reference>()?.to_string(), *expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn from_str_failure() {
+        for input in &[
+            "",
+            "Nginx",
+            "nginx:",
+            "nginx:in valid",
+            "quay.io/",
+            "nginx@sha256",
+            "nginx@sha256:xyz",
+        ] {
+            assert!(input.parse::<Reference>().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn object_and_host() -> Result<()> {
+        let reference: Reference = "nginx".parse()?;
+        assert_eq!(reference.host(), DEFAULT_REGISTRY_HOST);
+        assert_eq!(reference.object(), DEFAULT_TAG);
+        assert_eq!(reference.name(), "docker.io/library/nginx");
+
+        let pinned = reference.with_digest(DIGEST);
+        assert_eq!(pinned.object(), DIGEST);
+        assert_eq!(
+            pinned.to_string(),
+            format!("docker.io/library/nginx@{}", DIGEST)
+        );
+
+        let reference: Reference = "quay.io/tenant/app:v1".parse()?;
+        assert_eq!(reference.host(), "quay.io");
+        assert_eq!(reference.object(), "v1");
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Node level configuration of the registries images get pulled from.
+//!
+//! Pulls of a registry try its configured mirrors in order before falling back to the registry
+//! itself, which allows air-gapped clusters to serve all images from a local mirror. Insecure
+//! registries and mirrors are accessed without verifying their certificate or via plain HTTP,
+//! whereas pulls from blocked registries are refused.
+
+use crate::{config::Config, image::reference::Reference};
+use anyhow::{bail, Error, Result};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Clone, Debug, Deserialize, Eq, Getters, PartialEq, Serialize)]
+/// RegistryMirror is a host serving the images of a registry.
+pub struct RegistryMirror {
+    #[get = "pub"]
+    /// The mirrored registry, like `docker.io`.
+    registry: String,
+
+    #[get = "pub"]
+    /// The host of the mirror, like `mirror.local:5000`.
+    host: String,
+}
+
+impl FromStr for RegistryMirror {
+    type Err = Error;
+
+    /// Parse a mirror in the format `REGISTRY=HOST`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+            (Some(registry), Some(host))
+                if !registry.is_empty()
+                    && !registry.contains('/')
+                    && !host.is_empty()
+                    && !host.contains('/') =>
+            {
+                Ok(Self {
+                    registry: registry.into(),
+                    host: host.into(),
+                })
+            }
+            _ => bail!("invalid registry mirror {}, expected REGISTRY=HOST", s),
+        }
+    }
+}
+
+#[derive(Clone, CopyGetters, Debug, Eq, Getters, PartialEq)]
+/// Endpoint is a host an image can be pulled from.
+pub struct Endpoint {
+    #[get = "pub"]
+    /// The host serving the registry API, like `mirror.local:5000`.
+    host: String,
+
+    #[get_copy = "pub"]
+    /// Whether the host may be accessed without a verified certificate or via plain HTTP.
+    insecure: bool,
+
+    #[get_copy = "pub"]
+    /// Whether the host is a mirror instead of the registry of the image.
+    mirror: bool,
+}
+
+impl Endpoint {
+    /// Retrieve the URL schemes to try in order. Hosts on the loopback interface are only
+    /// accessed via plain HTTP.
+    pub fn schemes(&self) -> &'static [&'static str] {
+        if self.host.starts_with("localhost") {
+            &["http"]
+        } else if self.insecure {
+            &["https", "http"]
+        } else {
+            &["https"]
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Getters)]
+/// Registries contains the mirrors, insecure and blocked registries of the node.
+pub struct Registries {
+    #[get = "pub"]
+    /// The mirrors in the order they are tried.
+    mirrors: Vec<RegistryMirror>,
+
+    #[get = "pub"]
+    /// The registries and mirrors accessed insecurely.
+    insecure: Vec<String>,
+
+    #[get = "pub"]
+    /// The blocked registries and repositories, like `quay.io/tenant`.
+    blocked: Vec<String>,
+}
+
+impl Registries {
+    /// Retrieve the registries of the `config`.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            mirrors: config.registry_mirrors().clone(),
+            insecure: config.insecure_registries().clone(),
+            blocked: config.blocked_registries().clone(),
+        }
+    }
+
+    /// Check if pulling the `reference` is blocked, either by its registry or by one of its
+    /// parent repositories.
+    pub fn is_blocked(&self, reference: &Reference) -> bool {
+        let name = reference.name();
+        self.blocked.iter().any(|x| {
+            name.strip_prefix(x.trim_end_matches('/'))
+                .map_or(false, |x| x.is_empty() || x.starts_with('/'))
+        })
+    }
+
+    /// Retrieve the endpoints the `reference` can be pulled from, whereas the mirrors of its
+    /// registry come first.
+    pub fn endpoints(&self, reference: &Reference) -> Vec<Endpoint> {
+        let is_insecure = |x: &str| self.insecure.iter().any(|y| y == x);
+        self.mirrors
+            .iter()
+            .filter(|x| x.registry() == reference.registry())
+            .map(|x| Endpoint {
+                host: x.host().clone(),
+                insecure: is_insecure(x.host()),
+                mirror: true,
+            })
+            .chain(Some(Endpoint {
+                host: reference.host().into(),
+                insecure: is_insecure(reference.registry()) || is_insecure(reference.host()),
+                mirror: false,
+            }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+
+    fn registries() -> Result<Registries> {
+        Ok(Registries::from_config(
+            &ConfigBuilder::default()
+                .registry_mirrors(vec![
+                    "docker.io=mirror.local:5000".parse()?,
+                    "docker.io=mirror.example.com".parse()?,
+                    "quay.io=quay-mirror.local".parse()?,
+                ])
+                .insecure_registries(vec!["mirror.local:5000".into(), "registry.local".into()])
+                .blocked_registries(vec!["evil.io".into(), "quay.io/tenant".into()])
+                .build()?,
+        ))
+    }
+
+    #[test]
+    fn mirror_from_str_success() -> Result<()> {
+        let mirror: RegistryMirror = "docker.io=mirror.local:5000".parse()?;
+        assert_eq!(mirror.registry(), "docker.io");
+        assert_eq!(mirror.host(), "mirror.local:5000");
+        Ok(())
+    }
+
+    #[test]
+    fn mirror_from_str_fail() {
+        for input in &[
+            "",
+            "docker.io",
+            "=mirror.local",
+            "docker.io=",
+            "docker.io=https://mirror.local",
+            "docker.io/library=mirror.local",
+        ] {
+            assert!(input.parse::<RegistryMirror>().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn is_blocked_success() -> Result<()> {
+        let sut = registries()?;
+        assert!(sut.is_blocked(&"evil.io/app".parse()?));
+        assert!(sut.is_blocked(&"quay.io/tenant/app".parse()?));
+        assert!(!sut.is_blocked(&"quay.io/tenant2/app".parse()?));
+        assert!(!sut.is_blocked(&"evil.io.example.com/app".parse()?));
+        assert!(!sut.is_blocked(&"nginx".parse()?));
+        Ok(())
+    }
+
+    #[test]
+    fn endpoints_success() -> Result<()> {
+        let sut = registries()?;
+        let endpoints = sut.endpoints(&"nginx".parse()?);
+        let hosts: Vec<_> = endpoints.iter().map(|x| x.host().as_str()).collect();
+        assert_eq!(
+            hosts,
+            vec![
+                "mirror.local:5000",
+                "mirror.example.com",
+                "registry-1.docker.io"
+            ]
+        );
+        assert_eq!(endpoints[0].schemes(), &["https", "http"]);
+        assert!(endpoints[0].mirror());
+        assert_eq!(endpoints[1].schemes(), &["https"]);
+        assert!(!endpoints[2].mirror());
+
+        let endpoints = sut.endpoints(&"registry.local/app".parse()?);
+        assert_eq!(endpoints.len(), 1);
+        assert!(endpoints[0].insecure());
+
+        let endpoints = sut.endpoints(&"localhost:5000/app".parse()?);
+        assert_eq!(endpoints[0].schemes(), &["http"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,492 @@
+//! Assembly of container root filesystems from the unpacked layers of an image.
+//!
+//! The layers of the image store are shared by all containers using them, which is why every
+//! container gets its own copy of them. The layers are copied in order starting with the base
+//! layer, whereas the OCI whiteouts of a layer remove the content of the layers below it: a
+//! `.wh.NAME` file removes `NAME` and a `.wh..wh..opq` file removes all lower content of its
+//! directory. Merging the layers the same way without copying them allows diffing the root
+//! filesystem of a container against its image afterwards. The changes can be archived as a new
+//! layer, which records the deleted paths as whiteouts again.
+
+use crate::image::store::{ImageRecord, ImageStore};
+use anyhow::{Context, Result};
+use getset::{CopyGetters, Getters};
+use log::{debug, warn};
+use nix::unistd::{self, fchownat, FchownatFlags, Gid, Uid};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::unix::fs::{self as unix_fs, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+use tar::{Builder, EntryType, Header};
+
+/// The prefix of whiteout files, which remove the file named after the prefix.
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// The opaque whiteout, which removes the lower content of its directory.
+const WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// ChangeKind is the kind of a change of the root filesystem compared to its image.
+pub enum ChangeKind {
+    /// The path does not exist in the image.
+    Added,
+
+    /// The type, permissions, ownership or content of the path differ from the image.
+    Changed,
+
+    /// The path of the image does not exist anymore.
+    Deleted,
+}
+
+#[derive(Clone, CopyGetters, Debug, Getters, PartialEq)]
+/// Change is a single difference between a root filesystem and its image.
+pub struct Change {
+    #[get = "pub"]
+    /// The absolute path inside the root filesystem, like `/etc/passwd`.
+    path: PathBuf,
+
+    #[get_copy = "pub"]
+    /// The kind of the change.
+    kind: ChangeKind,
+}
+
+impl Change {
+    /// Create the change of the `kind` at the `path` relative to the root filesystem.
+    fn new(path: &Path, kind: ChangeKind) -> Self {
+        Self {
+            path: Path::new("/").join(path),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    /// Format the change like `docker diff` does, for example `C /etc/passwd`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ChangeKind::Added => 'A',
+            ChangeKind::Changed => 'C',
+            ChangeKind::Deleted => 'D',
+        };
+        write!(f, "{} {}", kind, self.path.display())
+    }
+}
+
+/// Build the root filesystem of a container at `rootfs` from the layers of the image `record`.
+pub fn build(store: &ImageStore, record: &ImageRecord, rootfs: &Path) -> Result<()> {
+    let preserve_ownership = unistd::getuid().is_root();
+    fs::create_dir_all(rootfs)
+        .with_context(|| format!("create root filesystem {}", rootfs.display()))?;
+    for layer in record.layers() {
+        let path = store.layer_path(layer)?;
+        copy_dir(&path, rootfs, preserve_ownership)
+            .with_context(|| format!("copy layer {} into {}", layer, rootfs.display()))?;
+    }
+    debug!(
+        "Built root filesystem {} from {} layers of image {}",
+        rootfs.display(),
+        record.layers().len(),
+        record.id()
+    );
+    Ok(())
+}
+
+/// Retrieve the changes of the root filesystem at `rootfs` compared to the layers of the image
+/// `record`, sorted by their path. Deleted directories are reported without their content.
+pub fn diff(store: &ImageStore, record: &ImageRecord, rootfs: &Path) -> Result<Vec<Change>> {
+    let layers = record
+        .layers()
+        .iter()
+        .map(|x| store.layer_path(x))
+        .collect::<Result<Vec<_>>>()?;
+    diff_layers(&layers, rootfs)
+}
+
+/// Retrieve the changes of the root filesystem at `rootfs` compared to the merged `layers`.
+fn diff_layers(layers: &[PathBuf], rootfs: &Path) -> Result<Vec<Change>> {
+    let mut image = BTreeMap::new();
+    for layer in layers {
+        merge_layer(layer, Path::new(""), &mut image)
+            .with_context(|| format!("merge layer {}", layer.display()))?;
+    }
+    let mut container = BTreeMap::new();
+    walk(rootfs, Path::new(""), &mut container)?;
+
+    // Ownerships are only copied when building the root filesystem by root
+    let ownership = unistd::getuid().is_root();
+    let mut changes = vec![];
+    for (path, dest) in &container {
+        match image.get(path) {
+            None => changes.push(Change::new(path, ChangeKind::Added)),
+            Some(source) if differs(source, dest, ownership)? => {
+                changes.push(Change::new(path, ChangeKind::Changed))
+            }
+            Some(_) => {}
+        }
+    }
+    for path in image.keys() {
+        let parent_exists = path.parent().map_or(true, |x| {
+            x.as_os_str().is_empty() || container.contains_key(x)
+        });
+        if parent_exists && !container.contains_key(path) {
+            changes.push(Change::new(path, ChangeKind::Deleted));
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// Write the `changes` of the root filesystem at `rootfs` as an uncompressed layer archive into
+/// the `writer`, whereas deleted paths become whiteouts. Special files like sockets are skipped.
+pub fn archive<W: Write>(rootfs: &Path, changes: &[Change], writer: W) -> Result<W> {
+    let mut builder = Builder::new(writer);
+    builder.follow_symlinks(false);
+    for change in changes {
+        let path = change.path().strip_prefix("/").unwrap_or(change.path());
+        if change.kind() == ChangeKind::Deleted {
+            let name = path
+                .file_name()
+                .with_context(|| format!("invalid deleted path {}", change.path().display()))?;
+            let whiteout =
+                path.with_file_name(format!("{}{}", WHITEOUT_PREFIX, name.to_string_lossy()));
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(0);
+            builder
+                .append_data(&mut header, &whiteout, io::empty())
+                .with_context(|| format!("archive whiteout {}", whiteout.display()))?;
+            continue;
+        }
+
+        let source = rootfs.join(path);
+        let file_type = fs::symlink_metadata(&source)
+            .with_context(|| format!("stat {}", source.display()))?
+            .file_type();
+        if !(file_type.is_dir() || file_type.is_file() || file_type.is_symlink()) {
+            debug!("Skipping special file {}", source.display());
+            continue;
+        }
+        builder
+            .append_path_with_name(&source, path)
+            .with_context(|| format!("archive {}", source.display()))?;
+    }
+    builder.into_inner().context("finish layer archive")
+}
+
+/// Merge the directory `dir` of the `layer` into the `tree`, which maps the paths relative to the
+/// root filesystem to the layer file providing them, and apply its whiteouts.
+fn merge_layer(layer: &Path, dir: &Path, tree: &mut BTreeMap<PathBuf, PathBuf>) -> Result<()> {
+    let source = layer.join(dir);
+    let mut entries = fs::read_dir(&source)
+        .and_then(|x| x.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("read directory {}", source.display()))?;
+    entries.sort_by_key(|x| x.file_name());
+
+    if entries.iter().any(|x| x.file_name() == WHITEOUT_OPAQUE) {
+        tree.retain(|k, _| k == dir || !k.starts_with(dir));
+    }
+    for entry in &entries {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name != WHITEOUT_OPAQUE && name.starts_with(WHITEOUT_PREFIX) {
+            let path = dir.join(&name[WHITEOUT_PREFIX.len()..]);
+            tree.retain(|k, _| !k.starts_with(&path));
+        }
+    }
+
+    for entry in entries {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(WHITEOUT_PREFIX)
+        {
+            continue;
+        }
+        let (source, path) = (entry.path(), dir.join(entry.file_name()));
+        let file_type = fs::symlink_metadata(&source)
+            .with_context(|| format!("stat {}", source.display()))?
+            .file_type();
+        if file_type.is_dir() {
+            let lower_dir = tree.get(&path).map_or(false, |x| {
+                fs::symlink_metadata(x).map_or(false, |x| x.is_dir())
+            });
+            if !lower_dir {
+                tree.retain(|k, _| !k.starts_with(&path));
+            }
+            tree.insert(path.clone(), source);
+            merge_layer(layer, &path, tree)?;
+        } else if file_type.is_file() || file_type.is_symlink() {
+            tree.retain(|k, _| !k.starts_with(&path));
+            tree.insert(path, source);
+        }
+    }
+    Ok(())
+}
+
+/// Add all entries of the directory `dir` below `root` to the `tree`, which maps the paths
+/// relative to the root to the files.
+fn walk(root: &Path, dir: &Path, tree: &mut BTreeMap<PathBuf, PathBuf>) -> Result<()> {
+    let source = root.join(dir);
+    for entry in fs::read_dir(&source).with_context(|| format!("read {}", source.display()))? {
+        let entry = entry.with_context(|| format!("read {}", source.display()))?;
+        let path = dir.join(entry.file_name());
+        let is_dir = entry
+            .file_type()
+            .with_context(|| format!("stat {}", entry.path().display()))?
+            .is_dir();
+        tree.insert(path.clone(), entry.path());
+        if is_dir {
+            walk(root, &path, tree)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check whether the file at `dest` differs from the layer file `source` by its type, permissions,
+/// ownership if `ownership` is set, or content. Directories only differ by their metadata.
+fn differs(source: &Path, dest: &Path, ownership: bool) -> Result<bool> {
+    let stat = |path: &Path| {
+        fs::symlink_metadata(path).with_context(|| format!("stat {}", path.display()))
+    };
+    let (a, b) = (stat(source)?, stat(dest)?);
+    if a.file_type() != b.file_type()
+        || (!a.file_type().is_symlink() && a.mode() & 0o7777 != b.mode() & 0o7777)
+        || (ownership && (a.uid() != b.uid() || a.gid() != b.gid()))
+    {
+        return Ok(true);
+    }
+    if a.file_type().is_symlink() {
+        let link =
+            |path: &Path| fs::read_link(path).with_context(|| format!("read {}", path.display()));
+        return Ok(link(source)? != link(dest)?);
+    }
+    if !a.is_file() {
+        return Ok(false);
+    }
+    if a.len() != b.len() {
+        return Ok(true);
+    }
+
+    let open = |path: &Path| File::open(path).with_context(|| format!("open {}", path.display()));
+    let (mut a, mut b) = (open(source)?, open(dest)?);
+    let (mut buf_a, mut buf_b) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let n = a
+            .read(&mut buf_a)
+            .with_context(|| format!("read {}", source.display()))?;
+        if n == 0 {
+            return Ok(false);
+        }
+        b.read_exact(&mut buf_b[..n])
+            .with_context(|| format!("read {}", dest.display()))?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(true);
+        }
+    }
+}
+
+/// Copy the content of the layer directory `source` into the directory `dest` and apply its
+/// whiteouts. Ownerships are only copied if `preserve_ownership` is set, because changing them
+/// requires privileges.
+fn copy_dir(source: &Path, dest: &Path, preserve_ownership: bool) -> Result<()> {
+    let mut entries = fs::read_dir(source)
+        .and_then(|x| x.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("read directory {}", source.display()))?;
+    entries.sort_by_key(|x| x.file_name());
+
+    // Whiteouts apply to the lower layers only, so they go before the content of this layer
+    if entries.iter().any(|x| x.file_name() == WHITEOUT_OPAQUE) {
+        for entry in fs::read_dir(dest).with_context(|| format!("read {}", dest.display()))? {
+            remove(&entry?.path())?;
+        }
+    }
+    for entry in &entries {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name != WHITEOUT_OPAQUE && name.starts_with(WHITEOUT_PREFIX) {
+            remove(&dest.join(&name[WHITEOUT_PREFIX.len()..]))?;
+        }
+    }
+
+    for entry in entries {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(WHITEOUT_PREFIX)
+        {
+            continue;
+        }
+        let (source, dest) = (entry.path(), dest.join(entry.file_name()));
+        let metadata =
+            fs::symlink_metadata(&source).with_context(|| format!("stat {}", source.display()))?;
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            if !fs::symlink_metadata(&dest).map_or(false, |x| x.is_dir()) {
+                remove(&dest)?;
+                fs::create_dir(&dest).with_context(|| format!("create {}", dest.display()))?;
+            }
+            copy_dir(&source, &dest, preserve_ownership)?;
+        } else if file_type.is_symlink() {
+            remove(&dest)?;
+            let target =
+                fs::read_link(&source).with_context(|| format!("read {}", source.display()))?;
+            unix_fs::symlink(&target, &dest)
+                .with_context(|| format!("create symlink {}", dest.display()))?;
+        } else if file_type.is_file() {
+            remove(&dest)?;
+            fs::copy(&source, &dest).with_context(|| format!("copy {}", source.display()))?;
+        } else {
+            warn!("Skipping special file {} of layer", source.display());
+            continue;
+        }
+
+        if preserve_ownership {
+            fchownat(
+                None,
+                &dest,
+                Some(Uid::from_raw(metadata.uid())),
+                Some(Gid::from_raw(metadata.gid())),
+                FchownatFlags::NoFollowSymlink,
+            )
+            .with_context(|| format!("change owner of {}", dest.display()))?;
+        }
+        // The permissions of directories apply last, so that read only ones can be populated
+        if !file_type.is_symlink() {
+            fs::set_permissions(&dest, fs::Permissions::from_mode(metadata.mode() & 0o7777))
+                .with_context(|| format!("set permissions of {}", dest.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the file or directory at `path` if it exists.
+fn remove(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => {
+            fs::remove_dir_all(path).with_context(|| format!("remove {}", path.display()))
+        }
+        Ok(_) => fs::remove_file(path).with_context(|| format!("remove {}", path.display())),
+        Err(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn copy_dir_whiteouts() -> Result<()> {
+        let dir = tempdir()?;
+        let (base, upper, rootfs) = (
+            dir.path().join("base"),
+            dir.path().join("upper"),
+            dir.path().join("rootfs"),
+        );
+        fs::create_dir_all(base.join("etc"))?;
+        fs::create_dir_all(base.join("var/cache"))?;
+        fs::write(base.join("etc/passwd"), "root")?;
+        fs::write(base.join("etc/shadow"), "secret")?;
+        fs::write(base.join("var/cache/old"), "old")?;
+        unix_fs::symlink("etc/passwd", base.join("passwd"))?;
+
+        fs::create_dir_all(upper.join("etc"))?;
+        fs::create_dir_all(upper.join("var/cache"))?;
+        fs::write(upper.join("etc/passwd"), "root\nuser")?;
+        fs::write(upper.join("etc/.wh.shadow"), "")?;
+        fs::write(upper.join("var/cache/.wh..wh..opq"), "")?;
+        fs::write(upper.join("var/cache/new"), "new")?;
+
+        fs::create_dir_all(&rootfs)?;
+        copy_dir(&base, &rootfs, false)?;
+        copy_dir(&upper, &rootfs, false)?;
+
+        assert_eq!(fs::read_to_string(rootfs.join("etc/passwd"))?, "root\nuser");
+        assert_eq!(fs::read_to_string(rootfs.join("passwd"))?, "root\nuser");
+        assert!(!rootfs.join("etc/shadow").exists());
+        assert!(!rootfs.join("etc/.wh.shadow").exists());
+        assert!(!rootfs.join("var/cache/old").exists());
+        assert!(!rootfs.join("var/cache/.wh..wh..opq").exists());
+        assert_eq!(fs::read_to_string(rootfs.join("var/cache/new"))?, "new");
+
+        // The layers stay untouched by changes of the root filesystem
+        fs::write(rootfs.join("etc/passwd"), "changed")?;
+        assert_eq!(fs::read_to_string(upper.join("etc/passwd"))?, "root\nuser");
+        Ok(())
+    }
+
+    #[test]
+    fn diff_layers_success() -> Result<()> {
+        let dir = tempdir()?;
+        let (base, upper, rootfs) = (
+            dir.path().join("base"),
+            dir.path().join("upper"),
+            dir.path().join("rootfs"),
+        );
+        fs::create_dir_all(base.join("etc"))?;
+        fs::create_dir_all(base.join("usr/share/doc"))?;
+        fs::write(base.join("etc/passwd"), "root")?;
+        fs::write(base.join("etc/shadow"), "secret")?;
+        fs::write(base.join("etc/hosts"), "localhost")?;
+        fs::write(base.join("usr/share/doc/README"), "docs")?;
+        fs::create_dir_all(upper.join("etc"))?;
+        fs::write(upper.join("etc/passwd"), "root\nuser")?;
+        fs::write(upper.join("etc/.wh.shadow"), "")?;
+        let layers = vec![base, upper];
+
+        fs::create_dir_all(&rootfs)?;
+        for layer in &layers {
+            copy_dir(layer, &rootfs, false)?;
+        }
+        assert!(diff_layers(&layers, &rootfs)?.is_empty());
+
+        fs::write(rootfs.join("etc/passwd"), "root\nchanged")?;
+        fs::set_permissions(rootfs.join("etc/hosts"), fs::Permissions::from_mode(0o600))?;
+        fs::write(rootfs.join("etc/shadow"), "new")?;
+        fs::remove_dir_all(rootfs.join("usr/share"))?;
+        let changes: Vec<String> = diff_layers(&layers, &rootfs)?
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                "C /etc/hosts",
+                "C /etc/passwd",
+                "A /etc/shadow",
+                "D /usr/share",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn archive_success() -> Result<()> {
+        let dir = tempdir()?;
+        let (rootfs, layer) = (dir.path().join("rootfs"), dir.path().join("layer"));
+        fs::create_dir_all(rootfs.join("etc"))?;
+        fs::write(rootfs.join("etc/passwd"), "root\nuser")?;
+        unix_fs::symlink("etc/passwd", rootfs.join("passwd"))?;
+        let changes = vec![
+            Change::new(Path::new("etc"), ChangeKind::Changed),
+            Change::new(Path::new("etc/passwd"), ChangeKind::Changed),
+            Change::new(Path::new("etc/shadow"), ChangeKind::Deleted),
+            Change::new(Path::new("passwd"), ChangeKind::Added),
+        ];
+
+        let content = archive(&rootfs, &changes, vec![])?;
+        tar::Archive::new(&content[..]).unpack(&layer)?;
+        assert_eq!(fs::read_to_string(layer.join("etc/passwd"))?, "root\nuser");
+        assert_eq!(fs::read_to_string(layer.join("etc/.wh.shadow"))?, "");
+        assert_eq!(
+            fs::read_link(layer.join("passwd"))?,
+            Path::new("etc/passwd")
+        );
+        Ok(())
+    }
+}
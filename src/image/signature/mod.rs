@@ -0,0 +1,766 @@
+//! Verification of the cosign signatures of pulled images.
+//!
+//! The signature policy gets loaded from a JSON file, which maps registries and repositories to
+//! the public keys or keyless identities their images have to be signed by:
+//!
+//! ```json
+//! {
+//!   "fulcio_roots": "/etc/cri/fulcio.pem",
+//!   "repositories": {
+//!     "quay.io/tenant": {
+//!       "public_keys": ["/etc/cri/keys/tenant.pub"]
+//!     },
+//!     "ghcr.io/org": {
+//!       "identities": [
+//!         {"issuer": "https://accounts.google.com", "subject": "release@org.example.com"}
+//!       ]
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! The most specific entry matching an image applies, whereas images without a matching entry
+//! are not verified. Signatures are looked up at the `sha256-<hex>.sig` tag of the repository,
+//! like cosign stores them. An image is trusted if a single signature either matches one of the
+//! public keys or has been made by a Fulcio certificate of one of the identities. The Rekor
+//! transparency log and the validity period of Fulcio certificates are not checked.
+
+use crate::{
+    config::SignatureVerification,
+    image::{
+        auth::decode_base64, distribution::Distribution, reference::Reference, store::ImageStore,
+    },
+    oci_spec::image::{Descriptor, Manifest},
+};
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error, fmt, fs,
+    path::{Path, PathBuf},
+};
+use x509::{Certificate, Hash, PublicKey};
+
+mod x509;
+
+/// The annotation of signature layers containing the base64 encoded signature of the payload.
+const ANNOTATION_SIGNATURE: &str = "dev.cosignproject.cosign/signature";
+
+/// The annotation of keyless signature layers containing the PEM encoded Fulcio certificate.
+const ANNOTATION_CERTIFICATE: &str = "dev.sigstore.cosign/certificate";
+
+/// The annotation of keyless signature layers containing the PEM encoded intermediate
+/// certificates.
+const ANNOTATION_CHAIN: &str = "dev.sigstore.cosign/chain";
+
+/// The type of the payloads of cosign image signatures.
+const PAYLOAD_TYPE: &str = "cosign container image signature";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+/// SignaturePolicy contains the signatures required per registry or repository.
+pub struct SignaturePolicy {
+    /// The PEM file containing the trusted Fulcio root certificates, which is required for
+    /// keyless identities.
+    fulcio_roots: Option<PathBuf>,
+
+    /// The required signatures by registry or repository, like `quay.io/tenant`.
+    repositories: HashMap<String, Requirement>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+/// Requirement contains the signers an image may be signed by.
+struct Requirement {
+    /// The PEM files containing the cosign public keys.
+    public_keys: Vec<PathBuf>,
+
+    /// The keyless identities.
+    identities: Vec<Identity>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+/// Identity is a keyless signer, which authenticated to Fulcio via OIDC.
+struct Identity {
+    /// The OIDC issuer, like `https://accounts.google.com`.
+    issuer: String,
+
+    /// The email address or URI of the signer, like `release@example.com`.
+    subject: String,
+}
+
+#[derive(Debug)]
+/// The loaded signers of a registry or repository.
+struct Trust {
+    /// The cosign public keys.
+    keys: Vec<PublicKey>,
+
+    /// The keyless identities.
+    identities: Vec<Identity>,
+}
+
+impl SignaturePolicy {
+    /// Load the signature policy at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("read signature policy {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("parse signature policy {}", path.display()))
+    }
+}
+
+#[derive(Debug)]
+/// VerificationError is returned if an image lacks a valid signature of its signature policy.
+pub struct VerificationError {
+    /// The image failing the verification.
+    image: String,
+
+    /// The reason of the failure.
+    cause: String,
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "verify signatures of {}: {}", self.image, self.cause)
+    }
+}
+
+impl error::Error for VerificationError {}
+
+#[derive(Debug)]
+/// Verifier checks the signatures of images against a signature policy.
+pub struct Verifier {
+    /// The trusted Fulcio root certificates.
+    roots: Vec<Certificate>,
+
+    /// The signers per registry or repository.
+    repositories: HashMap<String, Trust>,
+
+    /// Whether failed verifications are only logged or rejected.
+    mode: SignatureVerification,
+}
+
+impl Verifier {
+    /// Create a new verifier for the `policy`, which loads all referenced keys and certificates.
+    pub fn new(policy: SignaturePolicy, mode: SignatureVerification) -> Result<Self> {
+        let roots = match &policy.fulcio_roots {
+            Some(path) => Certificate::from_pem(
+                &fs::read_to_string(path)
+                    .with_context(|| format!("read Fulcio roots {}", path.display()))?,
+            )
+            .with_context(|| format!("decode Fulcio roots {}", path.display()))?,
+            None => vec![],
+        };
+        let mut repositories = HashMap::new();
+        for (prefix, requirement) in policy.repositories {
+            if !requirement.identities.is_empty() && roots.is_empty() {
+                bail!(
+                    "keyless identities of {} require the Fulcio roots to be configured",
+                    prefix
+                )
+            }
+            let keys = requirement
+                .public_keys
+                .iter()
+                .map(|path| {
+                    fs::read_to_string(path)
+                        .context("read file")
+                        .and_then(|x| PublicKey::from_pem(&x))
+                        .with_context(|| format!("load public key {}", path.display()))
+                })
+                .collect::<Result<_>>()?;
+            repositories.insert(
+                prefix.trim_end_matches('/').into(),
+                Trust {
+                    keys,
+                    identities: requirement.identities,
+                },
+            );
+        }
+        Ok(Self {
+            roots,
+            repositories,
+            mode,
+        })
+    }
+
+    /// Verify that the manifest `digest` of the `reference` has a valid signature, whereas the
+    /// signatures get retrieved from the `source`. Failures are only logged in the `warn` mode
+    /// and returned as `VerificationError` otherwise.
+    pub async fn verify(
+        &self,
+        store: &ImageStore,
+        source: &dyn Distribution,
+        reference: &Reference,
+        digest: &str,
+    ) -> Result<()> {
+        if self.mode == SignatureVerification::Off {
+            return Ok(());
+        }
+        let trust = match self.trust(reference) {
+            Some(trust) => trust,
+            None => {
+                debug!("No signatures required for image {}", reference);
+                return Ok(());
+            }
+        };
+        match self
+            .verify_signatures(store, source, reference, digest, trust)
+            .await
+        {
+            Ok(()) => {
+                debug!("Verified signature of image {}", reference);
+                Ok(())
+            }
+            Err(e) if self.mode == SignatureVerification::Warn => {
+                warn!("Unable to verify signatures of {}: {:#}", reference, e);
+                Ok(())
+            }
+            Err(e) => Err(VerificationError {
+                image: reference.to_string(),
+                cause: format!("{:#}", e),
+            }
+            .into()),
+        }
+    }
+
+    /// Retrieve the signers of the most specific entry matching the `reference`.
+    fn trust(&self, reference: &Reference) -> Option<&Trust> {
+        let name = reference.name();
+        self.repositories
+            .iter()
+            .filter(|(prefix, _)| {
+                name.strip_prefix(prefix.as_str())
+                    .map_or(false, |x| x.is_empty() || x.starts_with('/'))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, trust)| trust)
+    }
+
+    /// Verify that at least one signature of the manifest `digest` is valid for the `trust`.
+    async fn verify_signatures(
+        &self,
+        store: &ImageStore,
+        source: &dyn Distribution,
+        reference: &Reference,
+        digest: &str,
+        trust: &Trust,
+    ) -> Result<()> {
+        let signatures = reference.with_tag(&format!("{}.sig", digest.replace(':', "-")));
+        let (_, content) = source
+            .manifest(&signatures)
+            .await
+            .with_context(|| format!("get signatures {}", signatures))?;
+        let manifest: Manifest =
+            serde_json::from_slice(&content).context("decode signatures manifest")?;
+
+        let mut failures = vec![];
+        for layer in manifest.layers() {
+            match self
+                .verify_signature(store, source, &signatures, layer, digest, trust)
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(e) => failures.push(format!("{}: {:#}", layer.digest(), e)),
+            }
+        }
+        if failures.is_empty() {
+            bail!("no signatures found")
+        }
+        bail!("no valid signature found: {}", failures.join("; "))
+    }
+
+    /// Verify the signature `layer` of the manifest `digest`.
+    async fn verify_signature(
+        &self,
+        store: &ImageStore,
+        source: &dyn Distribution,
+        signatures: &Reference,
+        layer: &Descriptor,
+        digest: &str,
+        trust: &Trust,
+    ) -> Result<()> {
+        let annotations = layer.annotations();
+        let signature = annotations
+            .get(ANNOTATION_SIGNATURE)
+            .context("no signature annotation")
+            .and_then(|x| decode_base64(x.trim()).context("decode signature"))?;
+        let payload = store
+            .fetch_content(source, signatures, layer)
+            .await
+            .context("fetch signature payload")?;
+        self.verify_signer(
+            trust,
+            &payload,
+            &signature,
+            annotations.get(ANNOTATION_CERTIFICATE),
+            annotations.get(ANNOTATION_CHAIN),
+        )?;
+        verify_payload(&payload, digest)
+    }
+
+    /// Verify that the `signature` of the `payload` has been made by one of the signers of the
+    /// `trust`, either via a public key or via the Fulcio `certificate` issued through the
+    /// intermediate certificates of the `chain`.
+    fn verify_signer(
+        &self,
+        trust: &Trust,
+        payload: &[u8],
+        signature: &[u8],
+        certificate: Option<&String>,
+        chain: Option<&String>,
+    ) -> Result<()> {
+        if trust
+            .keys
+            .iter()
+            .any(|x| x.verify(Hash::Sha256, payload, signature).is_ok())
+        {
+            return Ok(());
+        }
+        let certificate = match certificate {
+            Some(certificate) if !trust.identities.is_empty() => certificate,
+            _ => bail!("signature does not match any public key"),
+        };
+        let leaf = Certificate::from_pem(certificate)
+            .context("decode certificate")?
+            .into_iter()
+            .next()
+            .context("no certificate found")?;
+        let intermediates = match chain {
+            Some(chain) => Certificate::from_pem(chain).context("decode certificate chain")?,
+            None => vec![],
+        };
+        self.verify_chain(&leaf, &intermediates)?;
+        leaf.public_key()
+            .verify(Hash::Sha256, payload, signature)
+            .context("verify signature of certificate")?;
+
+        let issuer = leaf.oidc_issuer().as_deref().unwrap_or_default();
+        if trust
+            .identities
+            .iter()
+            .any(|x| x.issuer == issuer && leaf.identities().contains(&x.subject))
+        {
+            return Ok(());
+        }
+        bail!(
+            "certificate identity {} of issuer {} is not trusted",
+            leaf.identities().join(", "),
+            issuer
+        )
+    }
+
+    /// Verify that the `leaf` certificate has been issued by a Fulcio root, either directly or
+    /// through the certificate authorities of the `intermediates`.
+    fn verify_chain(&self, leaf: &Certificate, intermediates: &[Certificate]) -> Result<()> {
+        let mut current = leaf;
+        // Every intermediate can be used at most once, which rules out loops
+        for _ in 0..=intermediates.len() {
+            if self
+                .roots
+                .iter()
+                .any(|x| current.verify_issued_by(x).is_ok())
+            {
+                return Ok(());
+            }
+            current = intermediates
+                .iter()
+                .find(|x| x.ca() && current.verify_issued_by(x).is_ok())
+                .context("certificate has not been issued by a Fulcio root")?;
+        }
+        bail!("certificate chain exceeds its intermediates")
+    }
+}
+
+#[derive(Deserialize)]
+/// The signed payload of a cosign signature.
+struct Payload {
+    /// The signed claims.
+    critical: Critical,
+}
+
+#[derive(Deserialize)]
+/// The signed claims of a cosign signature.
+struct Critical {
+    #[serde(rename = "type")]
+    /// The type of the payload.
+    kind: String,
+
+    /// The signed image.
+    image: PayloadImage,
+}
+
+#[derive(Deserialize)]
+/// The image signed by a cosign signature.
+struct PayloadImage {
+    #[serde(rename = "docker-manifest-digest")]
+    /// The digest of the signed manifest.
+    digest: String,
+}
+
+/// Verify that the signed `payload` refers to the manifest `digest`, which prevents reusing the
+/// signature of another image of the same signer.
+fn verify_payload(payload: &[u8], digest: &str) -> Result<()> {
+    let payload: Payload = serde_json::from_slice(payload).context("decode signature payload")?;
+    if payload.critical.kind != PAYLOAD_TYPE {
+        bail!("unsupported signature payload {}", payload.critical.kind)
+    }
+    if payload.critical.image.digest != digest {
+        bail!(
+            "signature is for manifest {}, not {}",
+            payload.critical.image.digest,
+            digest
+        )
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        image::store::tests::FakeDistribution,
+        storage::default_key_value_storage::DefaultKeyValueStorage,
+    };
+    use tempfile::{tempdir, TempDir};
+
+    /// The manifest digest signed by the fixtures.
+    const DIGEST: &str = "sha256:05b3abf2579a5eb66403cd78be557fd860633a1fe2103c7642030defe32c657f";
+
+    /// The signed payload of the manifest.
+    const PAYLOAD: &str = concat!(
+        r#"{"critical":{"identity":{"docker-reference":"quay.io/tenant/app"},"image":{"dock"#,
+        r#"er-manifest-digest":"sha256:05b3abf2579a5eb66403cd78be557fd860633a1fe2103c764203"#,
+        r#"0defe32c657f"},"type":"cosign container image signature"},"optional":null}"#,
+    );
+
+    /// The cosign public key.
+    const PUBLIC_KEY: &str = "\
+-----BEGIN PUBLIC KEY-----\n\
+MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEmlN1C0MBUE+Um7lSPoE0jprOvZpZ\n\
+de65o5J5S9T5ZO/8wATU1/qM3xwgeE7tVGLA/vfRXIoaNnx2wliHXVQLSQ==\n\
+-----END PUBLIC KEY-----\n";
+
+    /// The signature of the payload by the public key.
+    const SIGNATURE: &str = concat!(
+        "MEQCICb9ibb8xABvU10SH6tI8ge9t7e2sTAjBTEWdXs6rDgzAiBaJr/D2u4jec0VAI8vZd6pKTJ0mbuq",
+        "4+cwmlQuTrTiKw==",
+    );
+
+    /// The P-384 Fulcio root certificate.
+    const ROOT: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBeDCB/qADAgECAhQBeCuK65c5uf3A+6d6a+ZGlu3usTAKBggqhkjOPQQDAzAT\n\
+MREwDwYDVQQDDAhzaWdzdG9yZTAeFw0yNDAxMDEwMDAwMDBaFw0zMzEyMjkwMDAw\n\
+MDBaMBMxETAPBgNVBAMMCHNpZ3N0b3JlMHYwEAYHKoZIzj0CAQYFK4EEACIDYgAE\n\
+LzNJxNfKyVPlh3JWWtrnbOGJmTnZ/jvJGZy4QgnLm1fEYY5e1Rvv0co4e6HJY1DO\n\
+Mjm7qu+1tXB0rk03o+LffsO1dMiRB7osWqRqKU+Qu/mS7iyhEWYt5G6wCSqSUj23\n\
+oxMwETAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMDA2kAMGYCMQCAYafyzKT4\n\
+O0oQp77mqToi02eg50Hq+m1sEMH9MA/PHsLgBWLiBbAahJngWAXFy6QCMQCL8fPQ\n\
+OuxouvIKys8mYaHbDaIrJLOW/6WyxmeKJc0hVY9I44RVG3aTFPOuzErQSmk=\n\
+-----END CERTIFICATE-----\n";
+
+    /// The P-384 intermediate certificate issued by the root.
+    const INTERMEDIATE: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBhTCCAQugAwIBAgIUNSyxAMiNKdUAP6RxFWRkmvq6L3kwCgYIKoZIzj0EAwMw\n\
+EzERMA8GA1UEAwwIc2lnc3RvcmUwHhcNMjQwMTAxMDAwMDAwWhcNMzMxMjI5MDAw\n\
+MDAwWjAgMR4wHAYDVQQDDBVzaWdzdG9yZS1pbnRlcm1lZGlhdGUwdjAQBgcqhkjO\n\
+PQIBBgUrgQQAIgNiAARpFYj6de9/vGQNdQ8rsDlQzdAofGSFpQCJSESKTUosuk66\n\
+7n54FxMl0T1yB0lJSF5i+ri5kB+JfIkW+oNY8SVK+oZCEJmE0D8u56v3RlAyqjGe\n\
+CWuaDqEFVT9n2b/AYOmjEzARMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwMD\n\
+aAAwZQIwZntAvIIhUkFuUkb5XeY84377tN43QkOEE1klO+g531b+064aYubjiEIU\n\
+Vmc5rP2wAjEAsByYQzwIzRiOwuMjzXKC/nzBXtCsDZRRtVW6aXPHe10VQvobmXey\n\
+IPSb6nnj56r/\n\
+-----END CERTIFICATE-----\n";
+
+    /// The P-256 certificate of `dev@example.com` issued by the intermediate.
+    const LEAF: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBmjCCASCgAwIBAgIUR66NJZ9Ptj+dBslJX6OybYyqa/AwCgYIKoZIzj0EAwMw\n\
+IDEeMBwGA1UEAwwVc2lnc3RvcmUtaW50ZXJtZWRpYXRlMB4XDTI0MDEwMTAwMDAw\n\
+MFoXDTMzMTIyOTAwMDAwMFowADBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABIfn\n\
+u3qpQNH4cjCYDrO6haf6w8F6eUDV76KzAqTgTsAEmgCnnGtp09RYzGNBfClZUadr\n\
+4ZmV88gzhFxFQ5bdXGyjWDBWMAwGA1UdEwEB/wQCMAAwGgYDVR0RBBMwEYEPZGV2\n\
+QGV4YW1wbGUuY29tMCoGCisGAQQBg78wAQEEHGh0dHBzOi8vYWNjb3VudHMuZXhh\n\
+bXBsZS5jb20wCgYIKoZIzj0EAwMDaAAwZQIxAIXSZhzQMY+IxGOCkDvH10jW80iD\n\
++gWUEWnGm18z0gViQNbVK2zED2X35lgu8tWxoAIwPJdiLeRuuQaOCkqpjwRnXUxA\n\
+2X1qpFbWFpwnmhBeWCOekUEw/HdrKYfeetmaSaau\n\
+-----END CERTIFICATE-----\n";
+
+    /// The signature of the payload by the leaf certificate.
+    const LEAF_SIGNATURE: &str = concat!(
+        "MEQCIAMGaTx9ldD9EzD5G8xNiFGkfmXe/MpM7zYp4tIIog/pAiArBfy5MmnmAJ5cBR0PO7B4fWHWaIBo",
+        "3xIwtNz/ry8ptA==",
+    );
+
+    /// The certificate of `admin@example.com` issued by the leaf certificate,
+    /// which is no certificate authority.
+    const EVIL: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBWzCCAQKgAwIBAgIUK3dA2L2X5lx3AsB1hyEHqcNEKrswCgYIKoZIzj0EAwIw\n\
+ADAeFw0yNDAxMDEwMDAwMDBaFw0zMzEyMjkwMDAwMDBaMAAwWTATBgcqhkjOPQIB\n\
+BggqhkjOPQMBBwNCAATEHognhinoC8HWl/XLFObtPrL5mWvUXV8cnUQeyVNaiHuN\n\
+WJKE/rf7f103Qn5gj6qAp8QDT9SRnKxvNRCbc5Rvo1owWDAMBgNVHRMBAf8EAjAA\n\
+MBwGA1UdEQQVMBOBEWFkbWluQGV4YW1wbGUuY29tMCoGCisGAQQBg78wAQEEHGh0\n\
+dHBzOi8vYWNjb3VudHMuZXhhbXBsZS5jb20wCgYIKoZIzj0EAwIDRwAwRAIgNo5T\n\
+lO54A7aca5a69XJTeiiq1NXrCsLoxFYb3R2nooUCIFQ+HchLjooaQm3ct43AJdz8\n\
+tf2pcj9fXj3u9OXNGW95\n\
+-----END CERTIFICATE-----\n";
+
+    /// The signature of the payload by the evil certificate.
+    const EVIL_SIGNATURE: &str = concat!(
+        "MEQCICrD2dBIfGsYm+ImGE4RJ9Dpti3c/fZBgsfql5vymQRiAiBEDw1UfF5YCofyXVEJ+tWFnaThoFJP",
+        "sRnF7byimxthHA==",
+    );
+
+    /// Create a verifier in the `mode` trusting the public key for `quay.io/tenant` and the
+    /// keyless identity `dev@example.com` for `ghcr.io/org`.
+    fn verifier(mode: SignatureVerification) -> Result<(TempDir, Verifier)> {
+        let dir = tempdir()?;
+        let key = dir.path().join("tenant.pub");
+        fs::write(&key, PUBLIC_KEY)?;
+        let roots = dir.path().join("fulcio.pem");
+        fs::write(&roots, ROOT)?;
+        let policy = dir.path().join("policy.json");
+        fs::write(
+            &policy,
+            serde_json::to_vec(&serde_json::json!({
+                "fulcio_roots": roots,
+                "repositories": {
+                    "quay.io/tenant": {"public_keys": [key]},
+                    "ghcr.io/org": {
+                        "identities": [
+                            {"issuer": "https://accounts.example.com", "subject": "dev@example.com"}
+                        ]
+                    },
+                    "ghcr.io/org/unsigned": {},
+                }
+            }))?,
+        )?;
+        let verifier = Verifier::new(SignaturePolicy::load(&policy)?, mode)?;
+        Ok((dir, verifier))
+    }
+
+    /// Add the signature `layers` of the manifest `digest` to the `source`.
+    fn add_signatures(
+        source: &mut FakeDistribution,
+        digest: &str,
+        layers: Vec<(&str, HashMap<&str, &str>)>,
+    ) -> Result<()> {
+        let layers: Vec<_> = layers
+            .into_iter()
+            .map(|(payload, annotations)| {
+                let mut descriptor = source.add_blob(payload.as_bytes().to_vec());
+                descriptor["annotations"] = serde_json::json!(annotations);
+                descriptor
+            })
+            .collect();
+        let config = source.add_blob(b"{}".to_vec());
+        source.add_manifest(
+            &format!("{}.sig", digest.replace(':', "-")),
+            "application/vnd.oci.image.manifest.v1+json",
+            serde_json::to_vec(&serde_json::json!({
+                "schemaVersion": 2,
+                "config": config,
+                "layers": layers,
+            }))?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn trust_success() -> Result<()> {
+        let (_dir, sut) = verifier(SignatureVerification::Enforce)?;
+        let trust = sut
+            .trust(&"quay.io/tenant/app".parse()?)
+            .context("no trust")?;
+        assert_eq!(trust.keys.len(), 1);
+        let trust = sut
+            .trust(&"ghcr.io/org/unsigned/app".parse()?)
+            .context("no trust")?;
+        assert!(trust.keys.is_empty() && trust.identities.is_empty());
+        assert!(sut.trust(&"quay.io/tenant2/app".parse()?).is_none());
+        assert!(sut.trust(&"nginx".parse()?).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn new_fail_identities_without_roots() -> Result<()> {
+        let policy: SignaturePolicy = serde_json::from_str(
+            r#"{"repositories": {"ghcr.io": {"identities": [{"issuer": "i", "subject": "s"}]}}}"#,
+        )?;
+        assert!(Verifier::new(policy, SignatureVerification::Enforce).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn certificate_success() -> Result<()> {
+        let leaf = Certificate::from_pem(LEAF)?;
+        assert_eq!(leaf.len(), 1);
+        assert!(!leaf[0].ca());
+        assert_eq!(leaf[0].identities(), &["dev@example.com"]);
+        assert_eq!(
+            leaf[0].oidc_issuer().as_deref(),
+            Some("https://accounts.example.com")
+        );
+        let chain = Certificate::from_pem(&format!("{}{}", INTERMEDIATE, ROOT))?;
+        assert_eq!(chain.len(), 2);
+        assert!(chain.iter().all(|x| x.ca()));
+        assert!(leaf[0].verify_issued_by(&chain[0]).is_ok());
+        assert!(leaf[0].verify_issued_by(&chain[1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn verify_signer_success_public_key() -> Result<()> {
+        let (_dir, sut) = verifier(SignatureVerification::Enforce)?;
+        let trust = sut
+            .trust(&"quay.io/tenant/app".parse()?)
+            .context("no trust")?;
+        let signature = decode_base64(SIGNATURE)?;
+        sut.verify_signer(trust, PAYLOAD.as_bytes(), &signature, None, None)?;
+        assert!(sut
+            .verify_signer(trust, b"{}", &signature, None, None)
+            .is_err());
+        assert!(sut
+            .verify_signer(
+                trust,
+                PAYLOAD.as_bytes(),
+                &decode_base64(LEAF_SIGNATURE)?,
+                None,
+                None
+            )
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn verify_signer_success_keyless() -> Result<()> {
+        let (_dir, sut) = verifier(SignatureVerification::Enforce)?;
+        let trust = sut.trust(&"ghcr.io/org/app".parse()?).context("no trust")?;
+        let signature = decode_base64(LEAF_SIGNATURE)?;
+        let (leaf, chain) = (LEAF.to_string(), INTERMEDIATE.to_string());
+        sut.verify_signer(
+            trust,
+            PAYLOAD.as_bytes(),
+            &signature,
+            Some(&leaf),
+            Some(&chain),
+        )?;
+
+        // The intermediate is required to reach the root
+        assert!(sut
+            .verify_signer(trust, PAYLOAD.as_bytes(), &signature, Some(&leaf), None)
+            .is_err());
+
+        // Other identities of the same root are not trusted
+        let trust = sut
+            .trust(&"ghcr.io/org/unsigned/app".parse()?)
+            .context("no trust")?;
+        assert!(sut
+            .verify_signer(
+                trust,
+                PAYLOAD.as_bytes(),
+                &signature,
+                Some(&leaf),
+                Some(&chain)
+            )
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn verify_signer_fail_issued_by_leaf() -> Result<()> {
+        let (_dir, sut) = verifier(SignatureVerification::Enforce)?;
+        let trust = Trust {
+            keys: vec![],
+            identities: vec![Identity {
+                issuer: "https://accounts.example.com".into(),
+                subject: "admin@example.com".into(),
+            }],
+        };
+        let (evil, chain) = (EVIL.to_string(), format!("{}{}", LEAF, INTERMEDIATE));
+        let res = sut.verify_signer(
+            &trust,
+            PAYLOAD.as_bytes(),
+            &decode_base64(EVIL_SIGNATURE)?,
+            Some(&evil),
+            Some(&chain),
+        );
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn verify_payload_success() -> Result<()> {
+        verify_payload(PAYLOAD.as_bytes(), DIGEST)?;
+        assert!(verify_payload(PAYLOAD.as_bytes(), &format!("sha256:{:064}", 0)).is_err());
+        assert!(verify_payload(
+            PAYLOAD
+                .replace(PAYLOAD_TYPE, "cosign attestation")
+                .as_bytes(),
+            DIGEST
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify_success() -> Result<()> {
+        let dir = tempdir()?;
+        let store = ImageStore::open(&dir.path().join("images"), None)?;
+        let (_policy, sut) = verifier(SignatureVerification::Enforce)?;
+        let mut source = FakeDistribution::default();
+        let mut annotations = HashMap::new();
+        annotations.insert(ANNOTATION_SIGNATURE, SIGNATURE);
+        add_signatures(
+            &mut source,
+            DIGEST,
+            vec![("{}", annotations.clone()), (PAYLOAD, annotations)],
+        )?;
+
+        let reference = "quay.io/tenant/app:v1".parse()?;
+        sut.verify(&store, &source, &reference, DIGEST).await?;
+
+        // Signatures of another repository do not apply
+        let reference = "ghcr.io/org/app:v1".parse()?;
+        let res = sut.verify(&store, &source, &reference, DIGEST).await;
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_fail_unsigned() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let (source, _) = FakeDistribution::with_image("v1", "hello")?;
+        let reference: Reference = "quay.io/tenant/app:v1".parse()?;
+
+        let (_policy, verifier) = verifier(SignatureVerification::Enforce)?;
+        let sut = ImageStore::open(&dir.path().join("images"), None)?.with_verifier(verifier);
+        let err = sut
+            .pull(&mut storage, &source, &reference)
+            .await
+            .err()
+            .context("unsigned image pulled")?;
+        assert!(
+            err.downcast_ref::<VerificationError>().is_some(),
+            "{:#}",
+            err
+        );
+        assert!(ImageStore::list(&mut storage)?.is_empty());
+
+        // Images of repositories without signature requirements are pulled
+        let (source, _) = FakeDistribution::with_image("v1", "hello")?;
+        sut.pull(&mut storage, &source, &"quay.io/other/app:v1".parse()?)
+            .await?;
+
+        let (_policy, verifier) = verifier(SignatureVerification::Warn)?;
+        let sut = ImageStore::open(&dir.path().join("images"), None)?.with_verifier(verifier);
+        sut.pull(&mut storage, &source, &reference).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+//! Decoding of the ECDSA public keys and X.509 certificates of image signatures.
+//!
+//! Only the subset of DER required for verifying signatures gets decoded: the public key, the
+//! issuer and subject names, the signature and the extensions describing the signing identity.
+
+use crate::image::auth::decode_base64;
+use anyhow::{bail, format_err, Context, Result};
+use getset::{CopyGetters, Getters};
+use ring::signature::{self, UnparsedPublicKey};
+
+/// The DER tag of booleans.
+const TAG_BOOLEAN: u8 = 0x01;
+
+/// The DER tag of integers.
+const TAG_INTEGER: u8 = 0x02;
+
+/// The DER tag of bit strings.
+const TAG_BIT_STRING: u8 = 0x03;
+
+/// The DER tag of octet strings.
+const TAG_OCTET_STRING: u8 = 0x04;
+
+/// The DER tag of object identifiers.
+const TAG_OID: u8 = 0x06;
+
+/// The DER tag of UTF-8 strings.
+const TAG_UTF8_STRING: u8 = 0x0c;
+
+/// The DER tag of sequences.
+const TAG_SEQUENCE: u8 = 0x30;
+
+/// The DER tag of the explicit certificate version.
+const TAG_VERSION: u8 = 0xa0;
+
+/// The DER tag of the explicit certificate extensions.
+const TAG_EXTENSIONS: u8 = 0xa3;
+
+/// The DER tag of email addresses in the subject alternative names.
+const TAG_RFC822_NAME: u8 = 0x81;
+
+/// The DER tag of URIs in the subject alternative names.
+const TAG_URI: u8 = 0x86;
+
+/// The object identifier of elliptic curve public keys.
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+
+/// The object identifier of the NIST P-256 curve.
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+
+/// The object identifier of the NIST P-384 curve.
+const OID_P384: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+
+/// The object identifier of ECDSA signatures using SHA-256.
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+
+/// The object identifier of ECDSA signatures using SHA-384.
+const OID_ECDSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+
+/// The object identifier of the basic constraints extension.
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+
+/// The object identifier of the subject alternative name extension.
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// The object identifier of the raw OIDC issuer extension of Fulcio certificates.
+const OID_FULCIO_ISSUER: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x01];
+
+/// The object identifier of the DER encoded OIDC issuer extension of Fulcio certificates.
+const OID_FULCIO_ISSUER_V2: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x08];
+
+/// Reader decodes consecutive DER elements.
+struct Reader<'a> {
+    /// The remaining input.
+    input: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Create a new reader of the elements in `input`.
+    fn new(input: &'a [u8]) -> Self {
+        Self { input }
+    }
+
+    /// Check if all elements have been read.
+    fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    /// Retrieve the tag of the next element without reading it.
+    fn peek(&self) -> Option<u8> {
+        self.input.first().copied()
+    }
+
+    /// Read the next element. Returns its tag, its content and its complete encoding.
+    fn next(&mut self) -> Result<(u8, &'a [u8], &'a [u8])> {
+        let tag = self.peek().context("unexpected end of DER input")?;
+        let first = *self.input.get(1).context("unexpected end of DER input")?;
+        let (len, header) = if first < 0x80 {
+            (first as usize, 2)
+        } else {
+            let n = (first & 0x7f) as usize;
+            if n == 0 || n > 4 {
+                bail!("unsupported DER length encoding")
+            }
+            let bytes = self
+                .input
+                .get(2..2 + n)
+                .context("unexpected end of DER input")?;
+            (
+                bytes.iter().fold(0, |res, x| (res << 8) | *x as usize),
+                2 + n,
+            )
+        };
+        let end = header
+            .checked_add(len)
+            .filter(|x| *x <= self.input.len())
+            .context("DER element exceeds its input")?;
+        let (raw, rest) = self.input.split_at(end);
+        self.input = rest;
+        Ok((tag, &raw[header..], raw))
+    }
+
+    /// Read the next element, which has to have the `tag`. Returns its content.
+    fn expect(&mut self, tag: u8) -> Result<&'a [u8]> {
+        match self.next()? {
+            (x, content, _) if x == tag => Ok(content),
+            (x, _, _) => bail!("unexpected DER tag {:#04x}, expected {:#04x}", x, tag),
+        }
+    }
+
+    /// Read the next element as bit string without unused bits.
+    fn bit_string(&mut self) -> Result<&'a [u8]> {
+        match self.expect(TAG_BIT_STRING)?.split_first() {
+            Some((0, bits)) => Ok(bits),
+            _ => bail!("unsupported DER bit string"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The hash function of a signature.
+pub enum Hash {
+    /// SHA-256
+    Sha256,
+
+    /// SHA-384
+    Sha384,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The elliptic curve of a public key.
+enum Curve {
+    /// NIST P-256
+    P256,
+
+    /// NIST P-384
+    P384,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// PublicKey is an ECDSA public key.
+pub struct PublicKey {
+    /// The curve of the key.
+    curve: Curve,
+
+    /// The uncompressed point of the key.
+    point: Vec<u8>,
+}
+
+impl PublicKey {
+    /// Decode the PEM encoded public key in `pem`, like a `cosign.pub` file.
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        match decode_pem(pem, "PUBLIC KEY")?.as_slice() {
+            [der] => Self::from_spki(der),
+            _ => bail!("expected a single PEM encoded public key"),
+        }
+    }
+
+    /// Decode the DER encoded `SubjectPublicKeyInfo` in `der`.
+    fn from_spki(der: &[u8]) -> Result<Self> {
+        let mut spki = Reader::new(Reader::new(der).expect(TAG_SEQUENCE)?);
+        let mut algorithm = Reader::new(spki.expect(TAG_SEQUENCE)?);
+        if algorithm.expect(TAG_OID)? != OID_EC_PUBLIC_KEY {
+            bail!("unsupported public key algorithm, expected ECDSA")
+        }
+        let curve = match algorithm.expect(TAG_OID)? {
+            OID_P256 => Curve::P256,
+            OID_P384 => Curve::P384,
+            _ => bail!("unsupported elliptic curve, expected P-256 or P-384"),
+        };
+        Ok(Self {
+            curve,
+            point: spki.bit_string()?.into(),
+        })
+    }
+
+    /// Verify the DER encoded ECDSA `signature` of the `message` hashed via `hash`.
+    pub fn verify(&self, hash: Hash, message: &[u8], signature: &[u8]) -> Result<()> {
+        let algorithm = match (self.curve, hash) {
+            (Curve::P256, Hash::Sha256) => &signature::ECDSA_P256_SHA256_ASN1,
+            (Curve::P256, Hash::Sha384) => &signature::ECDSA_P256_SHA384_ASN1,
+            (Curve::P384, Hash::Sha256) => &signature::ECDSA_P384_SHA256_ASN1,
+            (Curve::P384, Hash::Sha384) => &signature::ECDSA_P384_SHA384_ASN1,
+        };
+        UnparsedPublicKey::new(algorithm, &self.point)
+            .verify(message, signature)
+            .map_err(|_| format_err!("signature does not match the public key"))
+    }
+}
+
+#[derive(Clone, CopyGetters, Debug, Getters)]
+/// Certificate is a decoded X.509 certificate.
+pub struct Certificate {
+    /// The signed part of the certificate.
+    tbs: Vec<u8>,
+
+    /// The hash function of the signature of the issuer.
+    hash: Hash,
+
+    /// The signature of the issuer.
+    signature: Vec<u8>,
+
+    /// The encoded name of the issuer.
+    issuer: Vec<u8>,
+
+    /// The encoded name of the subject.
+    subject: Vec<u8>,
+
+    #[get = "pub"]
+    /// The public key of the subject.
+    public_key: PublicKey,
+
+    #[get_copy = "pub"]
+    /// Whether the certificate may issue other certificates.
+    ca: bool,
+
+    #[get = "pub"]
+    /// The email addresses and URIs of the subject alternative names.
+    identities: Vec<String>,
+
+    #[get = "pub"]
+    /// The OIDC issuer which authenticated the subject, as recorded by Fulcio.
+    oidc_issuer: Option<String>,
+}
+
+impl Certificate {
+    /// Decode all PEM encoded certificates in `pem`.
+    pub fn from_pem(pem: &str) -> Result<Vec<Self>> {
+        decode_pem(pem, "CERTIFICATE")?
+            .iter()
+            .map(|x| Self::from_der(x))
+            .collect()
+    }
+
+    /// Decode the DER encoded certificate in `der`.
+    fn from_der(der: &[u8]) -> Result<Self> {
+        let mut certificate = Reader::new(Reader::new(der).expect(TAG_SEQUENCE)?);
+        let tbs = match certificate.next()? {
+            (TAG_SEQUENCE, _, raw) => raw,
+            _ => bail!("invalid certificate"),
+        };
+        let mut algorithm = Reader::new(certificate.expect(TAG_SEQUENCE)?);
+        let hash = match algorithm.expect(TAG_OID)? {
+            OID_ECDSA_SHA256 => Hash::Sha256,
+            OID_ECDSA_SHA384 => Hash::Sha384,
+            _ => bail!("unsupported certificate signature algorithm, expected ECDSA"),
+        };
+        let signature = certificate.bit_string()?.into();
+
+        let mut fields = Reader::new(Reader::new(tbs).expect(TAG_SEQUENCE)?);
+        if fields.peek() == Some(TAG_VERSION) {
+            fields.next()?;
+        }
+        fields.expect(TAG_INTEGER).context("read serial number")?;
+        fields
+            .expect(TAG_SEQUENCE)
+            .context("read signature algorithm")?;
+        let (_, _, issuer) = fields.next().context("read issuer")?;
+        fields.expect(TAG_SEQUENCE).context("read validity")?;
+        let (_, _, subject) = fields.next().context("read subject")?;
+        let (_, _, spki) = fields.next().context("read public key")?;
+        let mut res = Self {
+            tbs: tbs.into(),
+            hash,
+            signature,
+            issuer: issuer.into(),
+            subject: subject.into(),
+            public_key: PublicKey::from_spki(spki)?,
+            ca: false,
+            identities: vec![],
+            oidc_issuer: None,
+        };
+        while !fields.is_empty() {
+            if let (TAG_EXTENSIONS, content, _) = fields.next()? {
+                res.decode_extensions(content)
+                    .context("decode certificate extensions")?;
+            }
+        }
+        Ok(res)
+    }
+
+    /// Decode the supported extensions of the explicitly tagged `content`.
+    fn decode_extensions(&mut self, content: &[u8]) -> Result<()> {
+        let mut extensions = Reader::new(Reader::new(content).expect(TAG_SEQUENCE)?);
+        while !extensions.is_empty() {
+            let mut extension = Reader::new(extensions.expect(TAG_SEQUENCE)?);
+            let id = extension.expect(TAG_OID)?;
+            if extension.peek() == Some(TAG_BOOLEAN) {
+                extension.next()?;
+            }
+            let value = extension.expect(TAG_OCTET_STRING)?;
+            match id {
+                OID_BASIC_CONSTRAINTS => {
+                    let mut constraints = Reader::new(Reader::new(value).expect(TAG_SEQUENCE)?);
+                    self.ca = constraints.peek() == Some(TAG_BOOLEAN)
+                        && constraints.expect(TAG_BOOLEAN)?.first() == Some(&0xff);
+                }
+                OID_SUBJECT_ALT_NAME => {
+                    let mut names = Reader::new(Reader::new(value).expect(TAG_SEQUENCE)?);
+                    while !names.is_empty() {
+                        match names.next()? {
+                            (TAG_RFC822_NAME, name, _) | (TAG_URI, name, _) => {
+                                self.identities.push(utf8(name)?)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                OID_FULCIO_ISSUER => self.oidc_issuer = Some(utf8(value)?),
+                OID_FULCIO_ISSUER_V2 => {
+                    self.oidc_issuer = Some(utf8(Reader::new(value).expect(TAG_UTF8_STRING)?)?)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify that the certificate has been issued by the `issuer`.
+    pub fn verify_issued_by(&self, issuer: &Certificate) -> Result<()> {
+        if self.issuer != issuer.subject {
+            bail!("certificate has been issued by another subject")
+        }
+        issuer
+            .public_key
+            .verify(self.hash, &self.tbs, &self.signature)
+    }
+}
+
+/// Convert the `bytes` of a DER string into a string.
+fn utf8(bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.into()).context("DER string is not valid UTF-8")
+}
+
+/// Decode all PEM blocks with the `label`, like `CERTIFICATE`, in `input`.
+fn decode_pem(input: &str, label: &str) -> Result<Vec<Vec<u8>>> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let (mut res, mut block): (_, Option<String>) = (vec![], None);
+    for line in input.lines().map(str::trim) {
+        if line == begin {
+            block = Some(String::new());
+        } else if line == end {
+            let content = block.take().context("PEM block ends without beginning")?;
+            res.push(decode_base64(&content).context("decode PEM block")?);
+        } else if let Some(block) = &mut block {
+            block.push_str(line);
+        }
+    }
+    if block.is_some() {
+        bail!("PEM block {} does not end", label)
+    }
+    Ok(res)
+}
@@ -0,0 +1,2099 @@
+//! A content addressed store for pulled images.
+//!
+//! All blobs, like image configs and compressed layers, are stored by their digest below
+//! `blobs/`, whereas every layer gets unpacked once into its own directory below `layers/`. The
+//! metadata of the images is recorded in the key value storage. Blobs are imported from the layer
+//! cache and additional content stores before being fetched from the peer to peer distribution
+//! daemon of the node, if configured, and finally from a registry.
+//!
+//! Layers are unpacked into a staging directory first, which gets renamed into place once the
+//! archive has been unpacked completely. Every unpack in progress is recorded in `journal/`, so
+//! that the unpacks interrupted by a crash can be discarded or resumed on startup instead of
+//! leaving a partial layer behind.
+//!
+//! Layers can be uncompressed, gzip or zstd compressed tar archives. eStargz layers are gzip
+//! compressed archives made of one gzip member per file, which get unpacked fully like all other
+//! layers, whereas their table of contents and prefetch landmarks are skipped.
+//!
+//! Resolving the manifest, fetching every blob and unpacking every layer are timed as the stages
+//! of a pull, which get recorded in the metrics and published as events together with their
+//! sizes. Blobs and layers which already exist do not add a stage.
+
+use crate::{
+    clock::{Clock, SystemClock},
+    criapi::{Image as CriImage, ImageSpec, Int64Value},
+    event::{Event, EventBus, PullStage},
+    image::{
+        artifact::{self, ArtifactRecord},
+        cache::LayerCache,
+        content::{self, ContentStore},
+        distribution::{Distribution, Upload},
+        limiter::PullLimiter,
+        peer::PeerSource,
+        reference::{validate_digest, Reference},
+        rootfs,
+        signature::Verifier,
+        usage::ImageUsage,
+    },
+    metrics::Metrics,
+    oci_spec::image::{
+        Descriptor, DescriptorBuilder, Image, Index, Manifest, ManifestBuilder,
+        MEDIA_TYPE_DOCKER_MANIFEST_LIST, MEDIA_TYPE_IMAGE_CONFIG, MEDIA_TYPE_INDEX,
+        MEDIA_TYPE_LAYER, MEDIA_TYPE_LAYER_GZIP, MEDIA_TYPE_LAYER_ZSTD, MEDIA_TYPE_MANIFEST,
+    },
+    storage::KeyValueStorage,
+};
+use anyhow::{bail, format_err, Context, Result};
+use flate2::read::MultiGzDecoder;
+use futures_util::stream::{self, StreamExt};
+use getset::{CopyGetters, Getters};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use nix::unistd;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    env::consts::ARCH,
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+use tar::{Archive, EntryType};
+use tokio::task;
+
+/// The storage key prefix of all image records.
+const KEY_PREFIX: &str = "image/";
+
+/// The directory containing all blobs by their digest.
+const BLOBS_DIR: &str = "blobs";
+
+/// The directory containing all unpacked layers by their digest.
+const LAYERS_DIR: &str = "layers";
+
+/// The directory containing an entry for every layer unpack in progress.
+const JOURNAL_DIR: &str = "journal";
+
+/// The directory containing the files of all artifacts by the digest of their manifest.
+const ARTIFACTS_DIR: &str = "artifacts";
+
+/// The only supported digest algorithm.
+const SHA256: &str = "sha256";
+
+/// The magic bytes of gzip compressed layers.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The magic bytes of zstd compressed layers.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The entries of eStargz layers which are metadata for lazy pulling instead of layer content,
+/// namely the table of contents and the prefetch landmarks.
+const ESTARGZ_ENTRIES: &[&str] = &[
+    "stargz.index.json",
+    ".prefetch.landmark",
+    ".no.prefetch.landmark",
+];
+
+lazy_static! {
+    /// The sequence number of the next staging path, which keeps concurrent pulls of the same
+    /// blob or layer apart.
+    static ref NEXT_STAGING: AtomicU64 = AtomicU64::new(0);
+
+    /// Serializes the updates of image and artifact records, because moving a tag reads and
+    /// writes multiple records, which would leave the tag on multiple images if concurrent
+    /// pulls, commits or pushes interleaved.
+    static ref RECORDS: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Clone, CopyGetters, Debug, Deserialize, Getters, Serialize)]
+/// ImageRecord holds the metadata of a pulled image.
+pub struct ImageRecord {
+    #[get = "pub"]
+    /// The ID of the image, which is the digest of its config.
+    id: String,
+
+    #[get = "pub"]
+    /// The tagged references pointing to the image, like `docker.io/library/nginx:latest`.
+    repo_tags: Vec<String>,
+
+    #[get = "pub"]
+    /// The digested references pointing to the image, like `docker.io/library/nginx@sha256:…`.
+    repo_digests: Vec<String>,
+
+    #[get = "pub"]
+    /// The digests of the layers, starting with the base layer.
+    layers: Vec<String>,
+
+    #[get_copy = "pub"]
+    /// The compressed size of the config and all layers in bytes.
+    size: u64,
+
+    #[get = "pub"]
+    /// The user of the image config, like `nobody` or `1000:1000`.
+    user: String,
+}
+
+impl ImageRecord {
+    /// Retrieve the storage key for the image `id`.
+    pub fn key(id: &str) -> String {
+        format!("{}{}", KEY_PREFIX, id)
+    }
+
+    /// Returns true if the record is referenced by the tagged or digested `reference`.
+    pub fn matches(&self, reference: &Reference) -> bool {
+        match reference.digest() {
+            Some(digest) => self
+                .repo_digests
+                .contains(&format!("{}@{}", reference.name(), digest)),
+            None => self.repo_tags.contains(&reference.to_string()),
+        }
+    }
+
+    /// Convert the record into its CRI representation.
+    pub fn cri_image(&self) -> CriImage {
+        let user = self.user.split(':').next().unwrap_or_default();
+        let (uid, username) = match user.parse::<i64>() {
+            Ok(uid) => (Some(Int64Value { value: uid }), String::new()),
+            Err(_) => (None, user.into()),
+        };
+        CriImage {
+            id: self.id.clone(),
+            repo_tags: self.repo_tags.clone(),
+            repo_digests: self.repo_digests.clone(),
+            size: self.size,
+            uid,
+            username,
+            spec: Some(ImageSpec {
+                image: self.id.clone(),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+/// A layer unpack in progress, which is recorded in the journal.
+struct JournalEntry {
+    /// The digest of the unpacked layer.
+    digest: String,
+
+    /// The staging directory the layer gets unpacked into.
+    staging: PathBuf,
+}
+
+#[derive(Clone, Copy, CopyGetters, Debug, Default, PartialEq)]
+/// The result of recovering the image store from interrupted pulls.
+pub struct Recovery {
+    #[get_copy = "pub"]
+    /// The number of interrupted layer unpacks which got resumed.
+    resumed: usize,
+
+    #[get_copy = "pub"]
+    /// The number of partial blobs and layers which got discarded.
+    discarded: usize,
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "resumed {} interrupted layer unpacks, discarded {} partial blobs and layers",
+            self.resumed, self.discarded
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+/// ImageStore pulls images into a directory on disk.
+pub struct ImageStore {
+    /// The root directory of the store.
+    path: PathBuf,
+
+    /// The optional layer cache consulted before fetching layers.
+    cache: Option<LayerCache>,
+
+    /// The read-only content stores consulted after the layer cache.
+    content: Vec<Arc<dyn ContentStore>>,
+
+    /// The bus the progress of pulls gets published on.
+    events: EventBus,
+
+    /// The optional verifier of the signatures of pulled images.
+    verifier: Option<Arc<Verifier>>,
+
+    /// The concurrency limits shared with all other pulls of the node.
+    limiter: PullLimiter,
+
+    /// The optional peer to peer distribution daemon consulted before the registry.
+    peer: Option<PeerSource>,
+
+    /// The metrics the durations and sizes of the pull stages get recorded in.
+    metrics: Metrics,
+
+    /// The clock the pull times are based on.
+    clock: Arc<dyn Clock>,
+}
+
+impl ImageStore {
+    /// Open the store at `path`, which gets created if it does not exist. Layers are looked up
+    /// in the layer cache at `cache_path` first, if provided.
+    pub fn open(path: &Path, cache_path: Option<&Path>) -> Result<Self> {
+        for dir in &[BLOBS_DIR, LAYERS_DIR, ARTIFACTS_DIR] {
+            let dir = path.join(dir).join(SHA256);
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("create image store directory {}", dir.display()))?;
+        }
+        let journal = path.join(JOURNAL_DIR);
+        fs::create_dir_all(&journal)
+            .with_context(|| format!("create image store directory {}", journal.display()))?;
+        Ok(Self {
+            path: path.into(),
+            cache: cache_path.map(LayerCache::open).transpose()?,
+            content: vec![],
+            events: EventBus::default(),
+            verifier: None,
+            limiter: PullLimiter::default(),
+            peer: None,
+            metrics: Metrics::default(),
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Publish the progress of pulls on the `events` bus.
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Import blobs from the content `store` if they are not in the layer cache.
+    pub fn with_content(mut self, store: Arc<dyn ContentStore>) -> Self {
+        self.content.push(store);
+        self
+    }
+
+    /// Verify the signatures of pulled images via the `verifier` before fetching their blobs.
+    pub fn with_verifier(mut self, verifier: Verifier) -> Self {
+        self.verifier = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Limit the concurrency of pulls via the `limiter`, which is shared with other stores.
+    pub fn with_limiter(mut self, limiter: PullLimiter) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Fetch blobs from the `peer` before falling back to the source of the pull.
+    pub fn with_peer(mut self, peer: PeerSource) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+
+    /// Record the durations and sizes of the pull stages in the `metrics`.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Base the pull times, which the garbage collection takes into account, on the `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Retrieve the record of the `image`, which is either an image ID or a reference.
+    pub fn find<S: KeyValueStorage>(storage: &mut S, image: &str) -> Result<Option<ImageRecord>> {
+        if let Some(id) = image_id(image) {
+            return storage.get(ImageRecord::key(&id));
+        }
+        let reference: Reference = image.parse()?;
+        Ok(Self::list(storage)?
+            .into_iter()
+            .find(|x| x.matches(&reference)))
+    }
+
+    /// Check if finding the `image` resolves a tag, which is the case for references without a
+    /// digest.
+    pub fn resolves_tag(image: &str) -> bool {
+        image_id(image).is_none()
+            && image
+                .parse::<Reference>()
+                .map_or(false, |x| x.digest().is_none())
+    }
+
+    /// Retrieve the records of all images.
+    pub fn list<S: KeyValueStorage>(storage: &mut S) -> Result<Vec<ImageRecord>> {
+        storage.scan_prefix(KEY_PREFIX)
+    }
+
+    /// Pull the image `reference` from the `source` and record it in the `storage`. Blobs and
+    /// layers which already exist are not fetched again, whereas the layers get fetched in
+    /// parallel.
+    pub async fn pull<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        source: &dyn Distribution,
+        reference: &Reference,
+    ) -> Result<ImageRecord> {
+        let _permit = self.limiter.acquire_pull().await;
+        let image = reference.to_string();
+        self.events.publish(Event::PullStarted {
+            image: image.clone(),
+        });
+        match self.pull_image(storage, source, reference).await {
+            Ok(record) => {
+                self.events.publish(Event::PullCompleted {
+                    image,
+                    id: record.id().clone(),
+                });
+                Ok(record)
+            }
+            Err(e) => {
+                self.events.publish(Event::PullFailed {
+                    image,
+                    cause: format!("{:#}", e),
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// Pull the image `reference` like `pull`, whereas only the layer progress and the stages
+    /// get published.
+    async fn pull_image<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        source: &dyn Distribution,
+        reference: &Reference,
+    ) -> Result<ImageRecord> {
+        let started = self.clock.instant();
+        let (media_type, content) = source
+            .manifest(reference)
+            .await
+            .with_context(|| format!("get manifest of {}", reference))?;
+        let (mut resolve_time, mut resolve_bytes) =
+            (self.clock.instant() - started, content.len() as u64);
+        let repo_digest = digest_of(&content);
+        if let Some(digest) = reference.digest() {
+            if digest != &repo_digest {
+                bail!("manifest digest {} does not match {}", repo_digest, digest)
+            }
+        }
+        if let Some(verifier) = &self.verifier {
+            verifier
+                .verify(self, source, reference, &repo_digest)
+                .await?;
+        }
+
+        // Multi platform images reference the manifest of every platform in an index
+        let manifest: Manifest = if media_type == MEDIA_TYPE_INDEX
+            || media_type == MEDIA_TYPE_DOCKER_MANIFEST_LIST
+        {
+            let index: Index = serde_json::from_slice(&content).context("decode image index")?;
+            let descriptor = platform_manifest(&index)?;
+            let pinned = reference.with_digest(descriptor.digest());
+            let started = self.clock.instant();
+            let (_, content) = source
+                .manifest(&pinned)
+                .await
+                .with_context(|| format!("get manifest of {}", pinned))?;
+            resolve_time += self.clock.instant() - started;
+            resolve_bytes += content.len() as u64;
+            if &digest_of(&content) != descriptor.digest() {
+                bail!("manifest of {} does not match its digest", pinned)
+            }
+            serde_json::from_slice(&content).context("decode image manifest")?
+        } else {
+            serde_json::from_slice(&content).context("decode image manifest")?
+        };
+        self.observe_stage(
+            reference,
+            PullStage::Resolve,
+            &repo_digest,
+            resolve_bytes,
+            resolve_time,
+        );
+
+        let config_path = {
+            let lock = self.limiter.blob_lock(manifest.config().digest());
+            let _guard = lock.lock().await;
+            self.fetch(source, reference, manifest.config()).await?
+        };
+        let total_bytes = manifest.layers().iter().map(|x| x.size()).sum::<u64>();
+        let (mut completed_layers, mut completed_bytes) = (0, 0);
+        let mut layers = stream::iter(manifest.layers())
+            .map(|layer| self.fetch_layer(source, reference, layer))
+            .buffer_unordered(self.limiter.parallel_layers());
+        while let Some(layer) = layers.next().await {
+            let layer = layer?;
+            completed_layers += 1;
+            completed_bytes += layer.size();
+            self.events.publish(Event::PullProgress {
+                image: reference.to_string(),
+                layer: layer.digest().clone(),
+                completed_layers,
+                total_layers: manifest.layers().len(),
+                completed_bytes,
+                total_bytes,
+            });
+        }
+        let config = Image::from(&config_path)?;
+        let user = config
+            .config()
+            .as_ref()
+            .and_then(|x| x.user().clone())
+            .unwrap_or_default();
+
+        let id = manifest.config().digest().clone();
+        let _records = lock_records()?;
+        let mut record = storage
+            .get::<_, ImageRecord>(ImageRecord::key(&id))?
+            .unwrap_or_else(|| ImageRecord {
+                id: id.clone(),
+                repo_tags: vec![],
+                repo_digests: vec![],
+                layers: manifest
+                    .layers()
+                    .iter()
+                    .map(|x| x.digest().clone())
+                    .collect(),
+                size: manifest.config().size()
+                    + manifest.layers().iter().map(|x| x.size()).sum::<u64>(),
+                user,
+            });
+        let repo_digest = format!("{}@{}", reference.name(), repo_digest);
+        if !record.repo_digests.contains(&repo_digest) {
+            record.repo_digests.push(repo_digest);
+        }
+
+        Self::tag(storage, &mut record, reference)?;
+        storage.insert(ImageRecord::key(&id), &record)?;
+        ImageUsage::record_pull(storage, &id, self.clock.unix_nanos()?)?;
+        info!("Pulled image {} as {}", reference, id);
+        Ok(record)
+    }
+
+    /// Add the tag of the `reference` to the image `record`, if it has one. A tag can only point
+    /// to a single image, which is why it moves away from all other images in the `storage`.
+    /// The caller has to hold the records lock until the `record` got written.
+    fn tag<S: KeyValueStorage>(
+        storage: &mut S,
+        record: &mut ImageRecord,
+        reference: &Reference,
+    ) -> Result<()> {
+        if reference.tag().is_none() {
+            return Ok(());
+        }
+        let tag = reference.to_string();
+        for mut other in Self::list(storage)? {
+            if other.id != record.id && other.repo_tags.contains(&tag) {
+                other.repo_tags.retain(|x| x != &tag);
+                storage.insert(ImageRecord::key(&other.id), &other)?;
+            }
+        }
+        if !record.repo_tags.contains(&tag) {
+            record.repo_tags.push(tag);
+        }
+        Ok(())
+    }
+
+    /// Commit the changes of the container root filesystem at `rootfs` compared to its image
+    /// `base` as a new image, which consists of the layers of `base` and one uncompressed layer
+    /// on top of them. The new image gets tagged with the `reference` in the `storage`, whereas
+    /// the `comment` describes the new layer in the history of the image config.
+    pub fn commit_container<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        base: &ImageRecord,
+        rootfs: &Path,
+        reference: &Reference,
+        comment: &str,
+    ) -> Result<ImageRecord> {
+        if reference.tag().is_none() {
+            bail!("image reference {} has no tag", reference)
+        }
+        let changes = rootfs::diff(self, base, rootfs).context("diff root filesystem")?;
+
+        // The digest of the layer is only known once it has been written completely
+        let staging = staging_path(
+            &self.path.join(BLOBS_DIR).join(SHA256).join("layer"),
+            "commit",
+        );
+        let archived = File::create(&staging)
+            .with_context(|| format!("create file {}", staging.display()))
+            .and_then(|x| rootfs::archive(rootfs, &changes, x))
+            .and_then(|x| x.sync_all().context("sync layer archive"))
+            .and_then(|_| digest_of_file(&staging));
+        let (layer, layer_size) = match archived {
+            Ok(archived) => archived,
+            Err(e) => {
+                fs::remove_file(&staging).ok();
+                return Err(e.context("archive changes"));
+            }
+        };
+        let blob = self.blob_path(&layer)?;
+        fs::rename(&staging, &blob).with_context(|| format!("rename {}", staging.display()))?;
+        self.unpack(&layer, &blob)?;
+
+        // The config gets extended as raw JSON, so that fields unknown to the runtime are kept
+        let base_config = self.blob_path(base.id())?;
+        let content =
+            fs::read(&base_config).with_context(|| format!("read {}", base_config.display()))?;
+        let mut config: serde_json::Value =
+            serde_json::from_slice(&content).context("decode image config")?;
+        match config["rootfs"]["diff_ids"].as_array_mut() {
+            Some(diff_ids) => diff_ids.push(layer.clone().into()),
+            None => config["rootfs"] = json!({ "type": "layers", "diff_ids": [layer] }),
+        }
+        let history = json!({ "created_by": "criserver commit", "comment": comment });
+        match config["history"].as_array_mut() {
+            Some(entries) => entries.push(history),
+            None => config["history"] = json!([history]),
+        }
+        let config = serde_json::to_vec(&config).context("encode image config")?;
+        let id = digest_of(&config);
+        write_synced(&self.blob_path(&id)?, &config)?;
+
+        let size =
+            (base.size + config.len() as u64 + layer_size).saturating_sub(content.len() as u64);
+        let _records = lock_records()?;
+        let mut record = storage
+            .get::<_, ImageRecord>(ImageRecord::key(&id))?
+            .unwrap_or_else(|| ImageRecord {
+                id: id.clone(),
+                repo_tags: vec![],
+                repo_digests: vec![],
+                layers: base.layers.iter().cloned().chain(Some(layer)).collect(),
+                size,
+                user: base.user.clone(),
+            });
+        Self::tag(storage, &mut record, reference)?;
+        storage.insert(ImageRecord::key(&id), &record)?;
+        ImageUsage::record_pull(storage, &id, self.clock.unix_nanos()?)?;
+        info!(
+            "Committed {} changes on top of image {} as {} ({})",
+            changes.len(),
+            base.id(),
+            reference,
+            id
+        );
+        Ok(record)
+    }
+
+    /// Push the image `record` to the `destination` as the tagged `reference`. Layers get
+    /// mounted from the repositories of the same registry which other images of the `storage`
+    /// using them have been pulled from. The pushed manifest gets recorded as digested reference
+    /// of the image, which gets tagged with the `reference` as well. Returns the digested
+    /// reference of the pushed manifest.
+    pub async fn push<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        destination: &dyn Upload,
+        record: &ImageRecord,
+        reference: &Reference,
+    ) -> Result<String> {
+        if reference.tag().is_none() {
+            bail!("image reference {} has no tag", reference)
+        }
+        let images = Self::list(storage)?;
+        let mut layers = vec![];
+        for digest in record.layers() {
+            let path = self.blob_path(digest)?;
+            let mount_from = images
+                .iter()
+                .filter(|x| x.layers.contains(digest))
+                .flat_map(|x| x.repo_digests.iter())
+                .filter_map(|x| x.parse::<Reference>().ok())
+                .find(|x| x.registry() == reference.registry());
+            destination
+                .push_blob(reference, digest, &path, mount_from.as_ref())
+                .await?;
+            layers.push(descriptor(&path, layer_media_type(&path), digest)?);
+        }
+        let config = self.blob_path(record.id())?;
+        destination
+            .push_blob(reference, record.id(), &config, None)
+            .await?;
+
+        let manifest = ManifestBuilder::default()
+            .schema_version(2u32)
+            .media_type(MEDIA_TYPE_MANIFEST)
+            .config(descriptor(&config, MEDIA_TYPE_IMAGE_CONFIG, record.id())?)
+            .layers(layers)
+            .build()
+            .map_err(|e| format_err!("build image manifest: {}", e))?;
+        let content = serde_json::to_vec(&manifest).context("encode image manifest")?;
+        destination
+            .push_manifest(reference, MEDIA_TYPE_MANIFEST, &content)
+            .await?;
+
+        // The record may have changed during the upload
+        let _records = lock_records()?;
+        let mut record = storage
+            .get::<_, ImageRecord>(ImageRecord::key(record.id()))?
+            .with_context(|| format!("image {} not found", record.id()))?;
+        let repo_digest = format!("{}@{}", reference.name(), digest_of(&content));
+        if !record.repo_digests.contains(&repo_digest) {
+            record.repo_digests.push(repo_digest.clone());
+        }
+        Self::tag(storage, &mut record, reference)?;
+        storage.insert(ImageRecord::key(record.id()), &record)?;
+        info!("Pushed image {} as {}", record.id(), repo_digest);
+        Ok(repo_digest)
+    }
+
+    /// Pull the artifact `reference` from the `source` and record it in the `storage`. The layers
+    /// of the artifact are stored as blobs, which get linked into the directory of the artifact
+    /// by their file name.
+    pub async fn pull_artifact<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        source: &dyn Distribution,
+        reference: &Reference,
+    ) -> Result<ArtifactRecord> {
+        let _permit = self.limiter.acquire_pull().await;
+        let (media_type, content) = source
+            .manifest(reference)
+            .await
+            .with_context(|| format!("get manifest of {}", reference))?;
+        let digest = digest_of(&content);
+        if let Some(expected) = reference.digest() {
+            if expected != &digest {
+                bail!("manifest digest {} does not match {}", digest, expected)
+            }
+        }
+        if media_type == MEDIA_TYPE_INDEX || media_type == MEDIA_TYPE_DOCKER_MANIFEST_LIST {
+            bail!("artifact {} is an index instead of a manifest", reference)
+        }
+        if let Some(verifier) = &self.verifier {
+            verifier.verify(self, source, reference, &digest).await?;
+        }
+        let manifest: Manifest =
+            serde_json::from_slice(&content).context("decode artifact manifest")?;
+        let files = manifest
+            .layers()
+            .iter()
+            .map(artifact::file_name)
+            .collect::<Result<Vec<_>>>()?;
+        if (1..files.len()).any(|i| files[..i].contains(&files[i])) {
+            bail!("artifact {} contains duplicate file names", reference)
+        }
+
+        let dest = self.artifact_path(&digest)?;
+        if !dest.exists() {
+            // The files appear atomically by linking them into a staging directory first
+            let staging = staging_path(&dest, "link");
+            fs::create_dir_all(&staging)
+                .with_context(|| format!("create directory {}", staging.display()))?;
+            let mut linked = Ok(());
+            for (layer, file) in manifest.layers().iter().zip(&files) {
+                linked = self
+                    .fetch(source, reference, layer)
+                    .await
+                    .and_then(|x| link(&x, &staging.join(file)));
+                if linked.is_err() {
+                    break;
+                }
+            }
+            let result = linked.and_then(|_| commit(&staging, &dest));
+            if result.is_err() {
+                fs::remove_dir_all(&staging).ok();
+            }
+            result.with_context(|| format!("store artifact {}", reference))?;
+        }
+
+        let _records = lock_records()?;
+        let mut record = storage
+            .get::<_, ArtifactRecord>(ArtifactRecord::key(&digest))?
+            .unwrap_or_else(|| ArtifactRecord {
+                digest: digest.clone(),
+                references: vec![],
+                layers: manifest
+                    .layers()
+                    .iter()
+                    .map(|x| x.digest().clone())
+                    .collect(),
+                files,
+                size: manifest.layers().iter().map(|x| x.size()).sum(),
+                pulled_at: 0,
+            });
+        if reference.tag().is_some() {
+            let tag = reference.to_string();
+            for mut other in ArtifactRecord::list(storage)? {
+                if other.digest != digest && other.references.contains(&tag) {
+                    other.references.retain(|x| x != &tag);
+                    storage.insert(ArtifactRecord::key(&other.digest), &other)?;
+                }
+            }
+            if !record.references.contains(&tag) {
+                record.references.push(tag);
+            }
+        }
+        record.pulled_at = self.clock.unix_nanos()?;
+        storage.insert(ArtifactRecord::key(&digest), &record)?;
+        info!("Pulled artifact {} as {}", reference, digest);
+        Ok(record)
+    }
+
+    /// Remove the artifact `record` from the `storage` together with its files and all blobs
+    /// which are not used by any image or other artifact.
+    pub fn remove_artifact<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        record: &ArtifactRecord,
+    ) -> Result<()> {
+        storage.remove(ArtifactRecord::key(record.digest()))?;
+        let dir = self.artifact_path(record.digest())?;
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("remove artifact {}", dir.display()))?;
+        }
+
+        let used: Vec<String> = Self::list(storage)?
+            .into_iter()
+            .flat_map(|x| x.layers.into_iter().chain(Some(x.id)))
+            .chain(
+                ArtifactRecord::list(storage)?
+                    .into_iter()
+                    .flat_map(|x| x.layers),
+            )
+            .collect();
+        for layer in record.layers().iter().filter(|x| !used.contains(x)) {
+            remove_file(&self.blob_path(layer)?)?;
+        }
+        info!("Removed artifact {}", record.digest());
+        Ok(())
+    }
+
+    /// Record the pull `stage` of the `digest` with its `bytes` for the image `reference`, which
+    /// took `elapsed`.
+    fn observe_stage(
+        &self,
+        reference: &Reference,
+        stage: PullStage,
+        digest: &str,
+        bytes: u64,
+        elapsed: Duration,
+    ) {
+        debug!(
+            "Pull stage {} of {} for image {} took {:?} for {} bytes",
+            stage.as_ref(),
+            digest,
+            reference,
+            elapsed,
+            bytes
+        );
+        self.metrics.observe_pull_stage(stage, bytes, elapsed);
+        self.events.publish(Event::PullStage {
+            image: reference.to_string(),
+            stage,
+            digest: digest.into(),
+            bytes,
+            elapsed,
+        });
+    }
+
+    /// Remove the image `record` from the `storage`, together with all blobs and layers which
+    /// are not used by any other image.
+    pub fn remove<S: KeyValueStorage>(&self, storage: &mut S, record: &ImageRecord) -> Result<()> {
+        storage.remove(ImageRecord::key(record.id()))?;
+        ImageUsage::remove(storage, record.id())?;
+
+        let used: Vec<String> = Self::list(storage)?
+            .into_iter()
+            .flat_map(|x| x.layers)
+            .collect();
+        for layer in record.layers().iter().filter(|x| !used.contains(x)) {
+            let dir = self.layer_path(layer)?;
+            if dir.exists() {
+                fs::remove_dir_all(&dir)
+                    .with_context(|| format!("remove layer {}", dir.display()))?;
+            }
+            remove_file(&self.blob_path(layer)?)?;
+        }
+        remove_file(&self.blob_path(record.id())?)?;
+        info!("Removed image {}", record.id());
+        Ok(())
+    }
+
+    /// Retrieve the config of the image `record`, which has been stored as blob while pulling.
+    pub fn config(&self, record: &ImageRecord) -> Result<Image> {
+        Image::from(&self.blob_path(record.id())?)
+    }
+
+    /// Retrieve the path of the unpacked layer with the provided `digest`.
+    pub fn layer_path(&self, digest: &str) -> Result<PathBuf> {
+        self.path_of(LAYERS_DIR, digest)
+    }
+
+    /// Retrieve the path of the directory containing the files of the artifact with the manifest
+    /// `digest`.
+    pub fn artifact_path(&self, digest: &str) -> Result<PathBuf> {
+        self.path_of(ARTIFACTS_DIR, digest)
+    }
+
+    /// Retrieve the path of the blob with the provided `digest`.
+    fn blob_path(&self, digest: &str) -> Result<PathBuf> {
+        self.path_of(BLOBS_DIR, digest)
+    }
+
+    /// Retrieve the path of the object with the provided `digest` inside of `dir`.
+    fn path_of(&self, dir: &str, digest: &str) -> Result<PathBuf> {
+        validate_digest(digest)?;
+        let mut parts = digest.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(SHA256), Some(encoded)) => Ok(self.path.join(dir).join(SHA256).join(encoded)),
+            _ => bail!("unsupported digest algorithm of {}", digest),
+        }
+    }
+
+    /// Fetch and unpack the `layer`. Concurrent pulls of the same layer wait for the first one
+    /// instead of fetching it again. Returns the unpacked layer.
+    async fn fetch_layer<'a>(
+        &self,
+        source: &dyn Distribution,
+        reference: &Reference,
+        layer: &'a Descriptor,
+    ) -> Result<&'a Descriptor> {
+        let lock = self.limiter.blob_lock(layer.digest());
+        let _guard = lock.lock().await;
+        let blob = self.fetch(source, reference, layer).await?;
+
+        // Decompressing is CPU bound and must not block the other layers of the pull
+        let (store, digest) = (self.clone(), layer.digest().clone());
+        let started = self.clock.instant();
+        let unpacked = task::spawn_blocking(move || store.unpack(&digest, &blob))
+            .await
+            .context("wait for unpacking layer")??;
+        if unpacked {
+            self.observe_stage(
+                reference,
+                PullStage::Unpack,
+                layer.digest(),
+                *layer.size(),
+                self.clock.instant() - started,
+            );
+        }
+        Ok(layer)
+    }
+
+    /// Fetch the blob of the `descriptor` from the layer cache or the `source`, unless it
+    /// already exists. Returns the path of the verified blob.
+    async fn fetch(
+        &self,
+        source: &dyn Distribution,
+        reference: &Reference,
+        descriptor: &Descriptor,
+    ) -> Result<PathBuf> {
+        let digest = descriptor.digest();
+        let path = self.blob_path(digest)?;
+        if path.is_file() {
+            debug!("Blob {} already exists", digest);
+            return Ok(path);
+        }
+
+        // Write into a temporary file first, which makes the blob appear atomically
+        let tmp_path = staging_path(&path, "tmp");
+        let started = self.clock.instant();
+        if let Err(e) = self
+            .download(source, reference, descriptor, &tmp_path)
+            .await
+            .and_then(|_| {
+                fs::rename(&tmp_path, &path)
+                    .with_context(|| format!("rename {}", tmp_path.display()))
+            })
+        {
+            fs::remove_file(&tmp_path).ok();
+            return Err(e);
+        }
+        self.observe_stage(
+            reference,
+            PullStage::Fetch,
+            digest,
+            *descriptor.size(),
+            self.clock.instant() - started,
+        );
+
+        if let Some(cache) = &self.cache {
+            cache.store(digest, |file| {
+                io::copy(&mut File::open(&path)?, file)?;
+                Ok(())
+            })?;
+        }
+        Ok(path)
+    }
+
+    /// Fetch the blob of the `descriptor` into memory without storing it, which is meant for
+    /// small blobs like the payloads of signatures.
+    pub async fn fetch_content(
+        &self,
+        source: &dyn Distribution,
+        reference: &Reference,
+        descriptor: &Descriptor,
+    ) -> Result<Vec<u8>> {
+        let path = staging_path(&self.blob_path(descriptor.digest())?, "read");
+        let res = self
+            .download(source, reference, descriptor, &path)
+            .await
+            .and_then(|_| fs::read(&path).with_context(|| format!("read {}", path.display())));
+        fs::remove_file(&path).ok();
+        res
+    }
+
+    /// Write the verified blob of the `descriptor` into `path`, whereas the layer cache and the
+    /// content stores take precedence over the `source`.
+    async fn download(
+        &self,
+        source: &dyn Distribution,
+        reference: &Reference,
+        descriptor: &Descriptor,
+        path: &Path,
+    ) -> Result<()> {
+        let digest = descriptor.digest();
+        let mut file =
+            File::create(path).with_context(|| format!("create file {}", path.display()))?;
+        let mut stores: Vec<&dyn ContentStore> = vec![];
+        if let Some(cache) = &self.cache {
+            stores.push(cache);
+        }
+        stores.extend(self.content.iter().map(AsRef::as_ref));
+        match content::lookup(&stores, digest)? {
+            Some(cached) => {
+                debug!("Using blob {} from {}", digest, cached.display());
+                io::copy(&mut File::open(&cached)?, &mut file)
+                    .with_context(|| format!("copy cached blob {}", digest))?;
+            }
+            None => self
+                .fetch_blob(source, reference, digest, &mut file)
+                .await
+                .with_context(|| format!("get blob {}", digest))?,
+        }
+        verify(path, descriptor)
+    }
+
+    /// Write the blob with the provided `digest` into `file`, whereas the peer takes precedence
+    /// over the `source`. Blobs the peer fails to serve are retrieved from the `source`.
+    async fn fetch_blob(
+        &self,
+        source: &dyn Distribution,
+        reference: &Reference,
+        digest: &str,
+        file: &mut File,
+    ) -> Result<()> {
+        if let Some(peer) = &self.peer {
+            match peer.blob(reference, digest, file).await {
+                Ok(()) => {
+                    debug!("Fetched blob {} from peer", digest);
+                    return Ok(());
+                }
+                Err(e) => debug!("Unable to fetch blob {} from peer: {:#}", digest, e),
+            }
+            file.set_len(0)
+                .and_then(|_| file.seek(SeekFrom::Start(0)))
+                .with_context(|| format!("truncate partial blob {}", digest))?;
+        }
+        source.blob(reference, digest, file).await
+    }
+
+    /// Unpack the layer `blob` with the provided `digest`, unless it is already unpacked.
+    /// Returns false if the layer has been unpacked before.
+    fn unpack(&self, digest: &str, blob: &Path) -> Result<bool> {
+        let dest = self.layer_path(digest)?;
+        if dest.exists() {
+            debug!("Layer {} already unpacked", digest);
+            return Ok(false);
+        }
+
+        // Unpack into a staging directory first, which makes the layer appear atomically. The
+        // journal entry has to exist before the staging directory, so that a crash can never
+        // leave an untracked partial layer behind.
+        let staging = staging_path(&dest, "unpack");
+        let entry = self.journal_path(&staging)?;
+        let journaled = serde_json::to_vec(&JournalEntry {
+            digest: digest.into(),
+            staging: staging.clone(),
+        })
+        .context("serialize journal entry")
+        .and_then(|x| write_synced(&entry, &x));
+        let result = journaled
+            .and_then(|_| unpack_archive(blob, &staging))
+            .and_then(|_| commit(&staging, &dest));
+        if result.is_err() {
+            fs::remove_dir_all(&staging).ok();
+        }
+        fs::remove_file(&entry).ok();
+        result.with_context(|| format!("unpack layer {}", digest))?;
+        debug!("Unpacked layer {} into {}", digest, dest.display());
+        Ok(true)
+    }
+
+    /// Retrieve the path of the journal entry of the layer unpack into `staging`.
+    fn journal_path(&self, staging: &Path) -> Result<PathBuf> {
+        let name = staging
+            .file_name()
+            .with_context(|| format!("invalid staging directory {}", staging.display()))?;
+        Ok(self.path.join(JOURNAL_DIR).join(name))
+    }
+
+    /// Recover the store from pulls interrupted by a crash. Layer unpacks recorded in the journal
+    /// get resumed if their verified blob exists, whereas all other partial blobs and layers get
+    /// discarded. Must not be called while pulls are in progress.
+    pub fn recover(&self) -> Result<Recovery> {
+        let mut recovery = Recovery::default();
+        for path in read_dir(&self.path.join(JOURNAL_DIR))? {
+            let entry = fs::read(&path)
+                .ok()
+                .and_then(|x| serde_json::from_slice::<JournalEntry>(&x).ok());
+            fs::remove_file(&path).with_context(|| format!("remove {}", path.display()))?;
+            let entry = match entry {
+                Some(entry) => entry,
+                None => {
+                    warn!("Discarding unreadable journal entry {}", path.display());
+                    continue;
+                }
+            };
+
+            // The unpack starts from scratch, because a partial layer cannot be trusted
+            let dest = self.layer_path(&entry.digest)?;
+            if entry.staging.parent() == dest.parent() && entry.staging.exists() {
+                fs::remove_dir_all(&entry.staging)
+                    .with_context(|| format!("remove {}", entry.staging.display()))?;
+            }
+            let blob = self.blob_path(&entry.digest)?;
+            if !dest.exists() && blob.is_file() {
+                info!("Resuming interrupted unpack of layer {}", entry.digest);
+                self.unpack(&entry.digest, &blob)?;
+                recovery.resumed += 1;
+            } else {
+                recovery.discarded += 1;
+            }
+        }
+
+        // Completed blobs, layers and artifacts are named by their encoded digest, everything
+        // else is a leftover of an interrupted pull
+        for dir in &[BLOBS_DIR, LAYERS_DIR, ARTIFACTS_DIR] {
+            for path in read_dir(&self.path.join(dir).join(SHA256))? {
+                if path.extension().is_none() {
+                    continue;
+                }
+                warn!("Discarding partial {}", path.display());
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                }
+                .with_context(|| format!("remove {}", path.display()))?;
+                recovery.discarded += 1;
+            }
+        }
+        Ok(recovery)
+    }
+}
+
+/// Retrieve a unique staging path next to `path` for the `purpose`, like `unpack`.
+fn staging_path(path: &Path, purpose: &str) -> PathBuf {
+    path.with_extension(format!(
+        "{}-{}-{}",
+        purpose,
+        process::id(),
+        NEXT_STAGING.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Move the completely unpacked `staging` directory to `dest`. A layer unpacked concurrently
+/// into `dest` wins, which makes the `staging` directory obsolete.
+fn commit(staging: &Path, dest: &Path) -> Result<()> {
+    if let Err(e) = fs::rename(staging, dest) {
+        if !dest.exists() {
+            return Err(e).with_context(|| format!("rename {}", staging.display()));
+        }
+        debug!("Layer {} has been unpacked concurrently", dest.display());
+        fs::remove_dir_all(staging).ok();
+    }
+    Ok(())
+}
+
+/// Link the file at `source` to `dest`, or copy it if linking is not possible.
+fn link(source: &Path, dest: &Path) -> Result<()> {
+    if fs::hard_link(source, dest).is_err() {
+        fs::copy(source, dest)
+            .with_context(|| format!("copy {} to {}", source.display(), dest.display()))?;
+    }
+    Ok(())
+}
+
+/// Write the `content` into the file at `path` and flush it to disk.
+fn write_synced(path: &Path, content: &[u8]) -> Result<()> {
+    File::create(path)
+        .and_then(|mut x| x.write_all(content).and_then(|_| x.sync_all()))
+        .with_context(|| format!("write {}", path.display()))
+}
+
+/// Retrieve the paths of all entries of the directory at `path`.
+fn read_dir(path: &Path) -> Result<Vec<PathBuf>> {
+    fs::read_dir(path)
+        .and_then(|x| {
+            x.map(|x| x.map(|x| x.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .with_context(|| format!("read directory {}", path.display()))
+}
+
+/// Unpack the optionally gzip or zstd compressed tar archive at `path` into the directory
+/// `dest`. The metadata entries of eStargz layers are skipped.
+fn unpack_archive(path: &Path, dest: &Path) -> Result<()> {
+    let mut magic = [0; 4];
+    let read = File::open(path)
+        .and_then(|mut x| x.read(&mut magic))
+        .unwrap_or_default();
+
+    let file =
+        BufReader::new(File::open(path).with_context(|| format!("open {}", path.display()))?);
+    let reader: Box<dyn Read> = if read >= GZIP_MAGIC.len() && magic[..2] == GZIP_MAGIC {
+        // eStargz layers consist of many gzip members, which is why all of them are decoded
+        Box::new(MultiGzDecoder::new(file))
+    } else if read == ZSTD_MAGIC.len() && magic == ZSTD_MAGIC {
+        Box::new(
+            zstd::Decoder::with_buffer(file)
+                .with_context(|| format!("create zstd decoder for {}", path.display()))?,
+        )
+    } else {
+        Box::new(file)
+    };
+
+    let mut archive = Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(unistd::getuid().is_root());
+    fs::create_dir_all(dest).with_context(|| format!("create directory {}", dest.display()))?;
+    let entries = archive
+        .entries()
+        .with_context(|| format!("read entries of {}", path.display()))?;
+
+    // Directories are unpacked last like `Archive::unpack` does, so that read only directories
+    // do not prevent unpacking their content
+    let mut directories = vec![];
+    for entry in entries {
+        let mut entry = entry.with_context(|| format!("read entry of {}", path.display()))?;
+        let entry_path = entry
+            .path()
+            .with_context(|| format!("read entry path of {}", path.display()))?
+            .into_owned();
+        if ESTARGZ_ENTRIES.iter().any(|x| entry_path == Path::new(x)) {
+            debug!("Skipping eStargz metadata {}", entry_path.display());
+            continue;
+        }
+        if entry.header().entry_type() == EntryType::Directory {
+            directories.push(entry);
+            continue;
+        }
+        entry
+            .unpack_in(dest)
+            .with_context(|| format!("unpack {} of {}", entry_path.display(), path.display()))?;
+    }
+    for mut entry in directories {
+        entry
+            .unpack_in(dest)
+            .with_context(|| format!("unpack directory of {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Select the manifest of the current platform from the `index`.
+fn platform_manifest(index: &Index) -> Result<&Descriptor> {
+    let architecture = match ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "powerpc64" => "ppc64le",
+        arch => arch,
+    };
+    index
+        .manifests()
+        .iter()
+        .find(|x| {
+            x.platform()
+                .as_ref()
+                .map(|x| x.os() == "linux" && x.architecture() == architecture)
+                .unwrap_or_default()
+        })
+        .ok_or_else(|| format_err!("no manifest for linux/{} found", architecture))
+}
+
+/// Verify that the file at `path` matches the size and digest of the `descriptor`.
+fn verify(path: &Path, descriptor: &Descriptor) -> Result<()> {
+    let (digest, size) = digest_of_file(path)?;
+    if size != *descriptor.size() {
+        bail!(
+            "size {} of blob {} does not match {}",
+            size,
+            descriptor.digest(),
+            descriptor.size()
+        )
+    }
+    if &digest != descriptor.digest() {
+        bail!("digest {} does not match {}", digest, descriptor.digest())
+    }
+    Ok(())
+}
+
+/// Calculate the digest of the `content`.
+fn digest_of(content: &[u8]) -> String {
+    format!("{}:{:x}", SHA256, Sha256::digest(content))
+}
+
+/// Create the descriptor of the blob at `path` with the `media_type` and `digest`.
+fn descriptor(path: &Path, media_type: &str, digest: &str) -> Result<Descriptor> {
+    let size = fs::metadata(path)
+        .with_context(|| format!("stat {}", path.display()))?
+        .len();
+    DescriptorBuilder::default()
+        .media_type(media_type)
+        .digest(digest)
+        .size(size)
+        .build()
+        .map_err(|e| format_err!("build descriptor of {}: {}", digest, e))
+}
+
+/// Retrieve the media type of the layer blob at `path` by its compression.
+fn layer_media_type(path: &Path) -> &'static str {
+    let mut magic = [0; 4];
+    let read = File::open(path)
+        .and_then(|mut x| x.read(&mut magic))
+        .unwrap_or_default();
+    if read >= GZIP_MAGIC.len() && magic[..2] == GZIP_MAGIC {
+        MEDIA_TYPE_LAYER_GZIP
+    } else if read == ZSTD_MAGIC.len() && magic == ZSTD_MAGIC {
+        MEDIA_TYPE_LAYER_ZSTD
+    } else {
+        MEDIA_TYPE_LAYER
+    }
+}
+
+/// Calculate the digest and the size of the file at `path`.
+fn digest_of_file(path: &Path) -> Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut File::open(path)?, &mut hasher)
+        .with_context(|| format!("hash {}", path.display()))?;
+    Ok((format!("{}:{:x}", SHA256, hasher.finalize()), size))
+}
+
+/// Retrieve the full image ID if the `image` is an image ID instead of a reference.
+fn image_id(image: &str) -> Option<String> {
+    let hex = image.trim_start_matches("sha256:");
+    Some(format!("{}:{}", SHA256, hex))
+        .filter(|_| hex.len() == 64 && hex.chars().all(|x| x.is_ascii_hexdigit()))
+}
+
+/// Lock the image and artifact records for a read-modify-write update.
+fn lock_records() -> Result<MutexGuard<'static, ()>> {
+    RECORDS
+        .lock()
+        .map_err(|_| format_err!("lock image records"))
+}
+
+/// Remove the file at `path` if it exists.
+fn remove_file(path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_file(path).with_context(|| format!("remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{
+        clock::tests::FakeClock, image::content::ContainerdContentStore,
+        storage::default_key_value_storage::DefaultKeyValueStorage,
+    };
+    use flate2::{write::GzEncoder, Compression};
+    use futures_util::future;
+    use std::{
+        collections::HashMap,
+        io::Write,
+        sync::{atomic::AtomicUsize, Mutex},
+    };
+    use tempfile::tempdir;
+    use tokio::time;
+
+    /// A distribution source serving blobs and manifests from memory.
+    #[derive(Default)]
+    pub struct FakeDistribution {
+        /// The manifests by tag and digest.
+        manifests: HashMap<String, (String, Vec<u8>)>,
+
+        /// The blobs by digest.
+        blobs: HashMap<String, Vec<u8>>,
+    }
+
+    impl FakeDistribution {
+        /// Create a new source serving a single layer image with the `file` at `tag`. Returns
+        /// the source and the ID of the image.
+        pub fn with_image(tag: &str, file: &str) -> Result<(Self, String)> {
+            let mut sut = Self::default();
+            let id = sut.add_image(tag, file)?;
+            Ok((sut, id))
+        }
+
+        /// Add a single layer image with the `file` at `tag` and return its ID.
+        pub fn add_image(&mut self, tag: &str, file: &str) -> Result<String> {
+            self.add_image_with_config(tag, file, serde_json::json!({"User": "1000"}))
+        }
+
+        /// Add a single layer image with the `file` at `tag`, whose image config is `config`,
+        /// and return its ID.
+        pub fn add_image_with_config(
+            &mut self,
+            tag: &str,
+            file: &str,
+            config: serde_json::Value,
+        ) -> Result<String> {
+            let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+            let mut header = tar::Header::new_gnu();
+            header.set_size(file.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, file, file.as_bytes())?;
+            let layer = builder.into_inner()?.finish()?;
+
+            let config = serde_json::json!({
+                "architecture": "amd64",
+                "os": "linux",
+                "config": config,
+                "layer": file,
+            });
+            let config_descriptor = self.add_blob(serde_json::to_vec(&config)?);
+            let layer_descriptor = self.add_blob(layer);
+            let manifest = serde_json::to_vec(&serde_json::json!({
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "config": config_descriptor,
+                "layers": [layer_descriptor],
+            }))?;
+            self.manifests.insert(
+                tag.into(),
+                (
+                    "application/vnd.oci.image.manifest.v1+json".into(),
+                    manifest,
+                ),
+            );
+            Ok(config_descriptor["digest"]
+                .as_str()
+                .unwrap_or_default()
+                .into())
+        }
+
+        /// Add an artifact at `tag`, whose layers are the `files` by their name and content.
+        pub fn add_artifact(&mut self, tag: &str, files: &[(&str, &str)]) -> Result<()> {
+            let config = self.add_blob(b"{}".to_vec());
+            let mut layers = vec![];
+            for (name, content) in files {
+                let mut layer = self.add_blob(content.as_bytes().to_vec());
+                layer["annotations"] =
+                    serde_json::json!({ "org.opencontainers.image.title": name });
+                layers.push(layer);
+            }
+            let manifest = serde_json::to_vec(&serde_json::json!({
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "config": config,
+                "layers": layers,
+            }))?;
+            self.add_manifest(tag, "application/vnd.oci.image.manifest.v1+json", manifest);
+            Ok(())
+        }
+
+        /// Add the `manifest` of the `media_type` at `tag`.
+        pub fn add_manifest(&mut self, tag: &str, media_type: &str, manifest: Vec<u8>) {
+            self.manifests
+                .insert(tag.into(), (media_type.into(), manifest));
+        }
+
+        /// Write the images into an OCI image layout at `dir`, whereas the manifest of every tag
+        /// is named after the tag within the repository `name`, like `registry.k8s.io/app`.
+        pub fn write_layout(&self, dir: &Path, name: &str) -> Result<()> {
+            let blobs = dir.join("blobs").join(SHA256);
+            fs::create_dir_all(&blobs)?;
+            for (digest, content) in &self.blobs {
+                fs::write(blobs.join(digest.trim_start_matches("sha256:")), content)?;
+            }
+            let mut manifests = vec![];
+            for (tag, (media_type, manifest)) in &self.manifests {
+                let digest = digest_of(manifest);
+                fs::write(blobs.join(digest.trim_start_matches("sha256:")), manifest)?;
+                manifests.push(serde_json::json!({
+                    "mediaType": media_type,
+                    "digest": digest,
+                    "size": manifest.len(),
+                    "annotations": {
+                        "org.opencontainers.image.ref.name": format!("{}:{}", name, tag),
+                    },
+                }));
+            }
+            fs::write(dir.join("oci-layout"), r#"{"imageLayoutVersion":"1.0.0"}"#)?;
+            fs::write(
+                dir.join("index.json"),
+                serde_json::to_vec(&serde_json::json!({
+                    "schemaVersion": 2,
+                    "manifests": manifests,
+                }))?,
+            )?;
+            Ok(())
+        }
+
+        /// Add the blob `content` and return its descriptor.
+        pub fn add_blob(&mut self, content: Vec<u8>) -> serde_json::Value {
+            let digest = digest_of(&content);
+            let descriptor = serde_json::json!({
+                "mediaType": "application/octet-stream",
+                "digest": digest,
+                "size": content.len(),
+            });
+            self.blobs.insert(digest, content);
+            descriptor
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Distribution for FakeDistribution {
+        async fn manifest(&self, reference: &Reference) -> Result<(String, Vec<u8>)> {
+            self.manifests
+                .get(reference.object())
+                .cloned()
+                .with_context(|| format!("manifest {} not found", reference))
+        }
+
+        async fn blob(&self, _: &Reference, digest: &str, file: &mut File) -> Result<()> {
+            let blob = self
+                .blobs
+                .get(digest)
+                .with_context(|| format!("blob {} not found", digest))?;
+            Ok(file.write_all(blob)?)
+        }
+    }
+
+    #[test]
+    fn resolves_tag_success() {
+        let id = format!("{}:{}", SHA256, "a".repeat(64));
+        for image in &["app", "quay.io/tenant/app:v1"] {
+            assert!(ImageStore::resolves_tag(image), "{}", image);
+        }
+        for image in &[
+            id.as_str(),
+            id.trim_start_matches("sha256:"),
+            "app@sha256:4e07408562bedb8b60ce05c1decfe3ad16b72230967de01f640b7e4729b49fce",
+            "Invalid:",
+        ] {
+            assert!(!ImageStore::resolves_tag(image), "{}", image);
+        }
+    }
+
+    #[tokio::test]
+    async fn pull_list_remove() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let clock = FakeClock::default();
+        let sut =
+            ImageStore::open(&dir.path().join("images"), None)?.with_clock(Arc::new(clock.clone()));
+        let (source, id) = FakeDistribution::with_image("v1", "hello")?;
+
+        let reference: Reference = "quay.io/tenant/app:v1".parse()?;
+        let record = sut.pull(&mut storage, &source, &reference).await?;
+        assert_eq!(record.id(), &id);
+        assert_eq!(record.repo_tags(), &["quay.io/tenant/app:v1"]);
+        assert!(record.repo_digests()[0].starts_with("quay.io/tenant/app@sha256:"));
+        assert_eq!(record.user(), "1000");
+        let config = sut.config(&record)?;
+        assert_eq!(
+            config.config().as_ref().and_then(|x| x.user().as_deref()),
+            Some("1000")
+        );
+        assert_eq!(record.cri_image().uid, Some(Int64Value { value: 1000 }));
+        let usage = ImageUsage::get(&mut storage, &id)?;
+        assert_eq!(usage.last_used(), clock.unix_nanos()?);
+        assert_eq!(usage.uses(), 0);
+
+        let layer = sut.layer_path(&record.layers()[0])?;
+        assert_eq!(fs::read_to_string(layer.join("hello"))?, "hello");
+
+        // Pulling again is served from the store
+        sut.pull(&mut storage, &source, &reference).await?;
+        assert_eq!(ImageStore::list(&mut storage)?.len(), 1);
+        assert!(ImageStore::find(&mut storage, "quay.io/tenant/app:v1")?.is_some());
+        assert!(ImageStore::find(&mut storage, &id)?.is_some());
+        assert!(ImageStore::find(&mut storage, &record.repo_digests()[0])?.is_some());
+        assert!(ImageStore::find(&mut storage, "quay.io/tenant/app:v2")?.is_none());
+
+        sut.remove(&mut storage, &record)?;
+        assert!(ImageStore::find(&mut storage, &id)?.is_none());
+        assert_eq!(ImageUsage::get(&mut storage, &id)?, ImageUsage::default());
+        assert!(!layer.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_moves_tag() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let sut = ImageStore::open(&dir.path().join("images"), None)?;
+        let reference: Reference = "quay.io/tenant/app:latest".parse()?;
+
+        let (source, old) = FakeDistribution::with_image("latest", "old")?;
+        sut.pull(&mut storage, &source, &reference).await?;
+        let (source, new) = FakeDistribution::with_image("latest", "new")?;
+        sut.pull(&mut storage, &source, &reference).await?;
+
+        let old = ImageStore::find(&mut storage, &old)?.context("old image not found")?;
+        assert!(old.repo_tags().is_empty());
+        let found = ImageStore::find(&mut storage, "quay.io/tenant/app")?;
+        assert_eq!(found.map(|x| x.id().clone()), Some(new));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn commit_container_success() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let sut = ImageStore::open(&dir.path().join("images"), None)?;
+        let (source, _) = FakeDistribution::with_image("v1", "hello")?;
+        let base = sut
+            .pull(&mut storage, &source, &"quay.io/tenant/app:v1".parse()?)
+            .await?;
+
+        let rootfs = dir.path().join("rootfs");
+        rootfs::build(&sut, &base, &rootfs)?;
+        fs::remove_file(rootfs.join("hello"))?;
+        fs::write(rootfs.join("world"), "world")?;
+        let reference: Reference = "localhost/app:committed".parse()?;
+        let record = sut.commit_container(&mut storage, &base, &rootfs, &reference, "debug")?;
+        assert_eq!(record.repo_tags(), &["localhost/app:committed"]);
+        assert_eq!(record.layers().len(), 2);
+        assert_eq!(&record.layers()[0], &base.layers()[0]);
+        assert_eq!(record.user(), "1000");
+        assert!(ImageStore::find(&mut storage, "localhost/app:committed")?.is_some());
+
+        // The new layer records the changes and the image builds the committed root filesystem
+        let layer = sut.layer_path(&record.layers()[1])?;
+        assert_eq!(fs::read_to_string(layer.join("world"))?, "world");
+        assert!(layer.join(".wh.hello").exists());
+        let content = fs::read(sut.blob_path(record.id())?)?;
+        let config: serde_json::Value = serde_json::from_slice(&content)?;
+        assert_eq!(config["rootfs"]["diff_ids"][0], record.layers()[1].as_str());
+        assert_eq!(config["history"][0]["comment"], "debug");
+        let built = dir.path().join("built");
+        rootfs::build(&sut, &record, &built)?;
+        assert!(!built.join("hello").exists());
+        assert_eq!(fs::read_to_string(built.join("world"))?, "world");
+        assert!(rootfs::diff(&sut, &record, &rootfs)?.is_empty());
+        Ok(())
+    }
+
+    /// An upload destination recording the pushed blobs and manifests.
+    #[derive(Default)]
+    struct FakeUpload {
+        /// The pushed blob digests together with the repository they got mounted from.
+        blobs: Mutex<Vec<(String, Option<String>)>>,
+
+        /// The pushed manifests by reference.
+        manifests: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    #[tonic::async_trait]
+    impl Upload for FakeUpload {
+        async fn push_blob(
+            &self,
+            _: &Reference,
+            digest: &str,
+            path: &Path,
+            mount_from: Option<&Reference>,
+        ) -> Result<()> {
+            verify(
+                path,
+                &DescriptorBuilder::default()
+                    .digest(digest)
+                    .size(fs::metadata(path)?.len())
+                    .build()
+                    .map_err(|e| format_err!("{}", e))?,
+            )?;
+            let mount_from = mount_from.map(|x| x.repository().clone());
+            if let Ok(mut blobs) = self.blobs.lock() {
+                blobs.push((digest.into(), mount_from));
+            }
+            Ok(())
+        }
+
+        async fn push_manifest(
+            &self,
+            reference: &Reference,
+            _: &str,
+            content: &[u8],
+        ) -> Result<()> {
+            if let Ok(mut manifests) = self.manifests.lock() {
+                manifests.insert(reference.to_string(), content.to_vec());
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn push_success() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let sut = ImageStore::open(&dir.path().join("images"), None)?;
+        let (source, id) = FakeDistribution::with_image("v1", "hello")?;
+        let record = sut
+            .pull(&mut storage, &source, &"quay.io/tenant/app:v1".parse()?)
+            .await?;
+
+        let destination = FakeUpload::default();
+        let reference: Reference = "quay.io/tenant/other:v2".parse()?;
+        let pushed = sut
+            .push(&mut storage, &destination, &record, &reference)
+            .await?;
+
+        // The layer gets mounted from the repository it has been pulled from
+        assert_eq!(
+            destination
+                .blobs
+                .lock()
+                .map(|x| x.clone())
+                .unwrap_or_default(),
+            vec![
+                (record.layers()[0].clone(), Some("tenant/app".into())),
+                (id.clone(), None),
+            ]
+        );
+        let content = destination
+            .manifests
+            .lock()
+            .ok()
+            .and_then(|x| x.get("quay.io/tenant/other:v2").cloned())
+            .context("manifest not pushed")?;
+        let manifest: Manifest = serde_json::from_slice(&content)?;
+        assert_eq!(*manifest.schema_version(), 2);
+        assert_eq!(manifest.config().digest(), &id);
+        assert_eq!(manifest.layers()[0].media_type(), MEDIA_TYPE_LAYER_GZIP);
+
+        assert_eq!(
+            pushed,
+            format!("quay.io/tenant/other@{}", digest_of(&content))
+        );
+        let found = ImageStore::find(&mut storage, &pushed)?.context("pushed image not found")?;
+        assert_eq!(found.id(), &id);
+        assert!(found
+            .repo_tags()
+            .contains(&"quay.io/tenant/other:v2".to_string()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_remove_artifact() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let sut = ImageStore::open(&dir.path().join("images"), None)?;
+        let mut source = FakeDistribution::default();
+        source.add_artifact("v1", &[("filter.wasm", "wasm"), ("README", "docs")])?;
+
+        let reference: Reference = "quay.io/tenant/filter:v1".parse()?;
+        let record = sut.pull_artifact(&mut storage, &source, &reference).await?;
+        assert_eq!(record.references(), &["quay.io/tenant/filter:v1"]);
+        assert_eq!(record.files(), &["filter.wasm", "README"]);
+        assert_eq!(record.size(), 8);
+        let path = sut.artifact_path(record.digest())?;
+        assert_eq!(fs::read_to_string(path.join("filter.wasm"))?, "wasm");
+        assert_eq!(fs::read_to_string(path.join("README"))?, "docs");
+        assert!(ArtifactRecord::find(&mut storage, &reference)?.is_some());
+
+        // Artifacts are no images
+        assert!(ImageStore::list(&mut storage)?.is_empty());
+
+        sut.remove_artifact(&mut storage, &record)?;
+        assert!(ArtifactRecord::find(&mut storage, &reference)?.is_none());
+        assert!(!path.exists());
+        assert!(!sut.blob_path(&record.layers()[0])?.exists());
+        Ok(())
+    }
+
+    /// A distribution source counting the fetched blobs, which takes a while for every blob.
+    struct SlowDistribution {
+        /// The source serving the content.
+        inner: FakeDistribution,
+
+        /// The number of fetched blobs.
+        blobs: AtomicUsize,
+    }
+
+    #[tonic::async_trait]
+    impl Distribution for SlowDistribution {
+        async fn manifest(&self, reference: &Reference) -> Result<(String, Vec<u8>)> {
+            self.inner.manifest(reference).await
+        }
+
+        async fn blob(&self, reference: &Reference, digest: &str, file: &mut File) -> Result<()> {
+            self.blobs.fetch_add(1, Ordering::SeqCst);
+            time::delay_for(Duration::from_millis(20)).await;
+            self.inner.blob(reference, digest, file).await
+        }
+    }
+
+    #[tokio::test]
+    async fn pull_concurrent_deduplicated() -> Result<()> {
+        let dir = tempdir()?;
+        let storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let sut = ImageStore::open(&dir.path().join("images"), None)?
+            .with_limiter(PullLimiter::new(2, 2));
+        let (inner, id) = FakeDistribution::with_image("v1", "hello")?;
+        let source = SlowDistribution {
+            inner,
+            blobs: AtomicUsize::new(0),
+        };
+
+        // Both pulls share the download of the config and the layer
+        let reference: Reference = "quay.io/tenant/app:v1".parse()?;
+        let (mut first, mut second) = (storage.clone(), storage.clone());
+        let (first, second) = future::join(
+            sut.pull(&mut first, &source, &reference),
+            sut.pull(&mut second, &source, &reference),
+        )
+        .await;
+        assert_eq!(first?.id(), &id);
+        assert_eq!(second?.id(), &id);
+        assert_eq!(source.blobs.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_with_cache() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let cache_path = dir.path().join("cache");
+        let (source, _) = FakeDistribution::with_image("v1", "hello")?;
+        let reference: Reference = "quay.io/tenant/app:v1".parse()?;
+
+        let sut = ImageStore::open(&dir.path().join("images"), Some(&cache_path))?;
+        let record = sut.pull(&mut storage, &source, &reference).await?;
+
+        // Another store is able to pull the layers from the cache only
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("other-storage"))?;
+        let sut = ImageStore::open(&dir.path().join("other-images"), Some(&cache_path))?;
+        let mut source_without_blobs = FakeDistribution::default();
+        source_without_blobs.manifests = source.manifests.clone();
+        let cached = sut
+            .pull(&mut storage, &source_without_blobs, &reference)
+            .await?;
+        assert_eq!(cached.id(), record.id());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_with_peer() -> Result<()> {
+        let dir = tempdir()?;
+        let (source, _) = FakeDistribution::with_image("v1", "hello")?;
+        let reference: Reference = "quay.io/tenant/app:v1".parse()?;
+        let blobs = source.blobs.clone();
+        let routes =
+            warp::path!("v2" / "tenant" / "app" / "blobs" / String).map(move |digest: String| {
+                match blobs.get(&digest) {
+                    Some(blob) => {
+                        warp::reply::with_status(blob.clone(), warp::http::StatusCode::OK)
+                    }
+                    None => warp::reply::with_status(vec![], warp::http::StatusCode::NOT_FOUND),
+                }
+            });
+        let (address, server) = warp::serve(routes).try_bind_ephemeral(([127, 0, 0, 1], 0))?;
+        tokio::spawn(server);
+
+        // The peer serves all blobs, which the registry does not have
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let mut source_without_blobs = FakeDistribution::default();
+        source_without_blobs.manifests = source.manifests.clone();
+        let peer = PeerSource::new(&format!("http://{}", address), Duration::from_secs(5))?;
+        let sut = ImageStore::open(&dir.path().join("images"), None)?.with_peer(peer);
+        let record = sut
+            .pull(&mut storage, &source_without_blobs, &reference)
+            .await?;
+
+        // Blobs unavailable from the peer fall back to the registry
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let unreachable = format!("http://{}", listener.local_addr()?);
+        drop(listener);
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("other-storage"))?;
+        let peer = PeerSource::new(&unreachable, Duration::from_secs(5))?;
+        let sut = ImageStore::open(&dir.path().join("other-images"), None)?.with_peer(peer);
+        let fallback = sut.pull(&mut storage, &source, &reference).await?;
+        assert_eq!(fallback.id(), record.id());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_with_containerd_content() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let (source, id) = FakeDistribution::with_image("v1", "hello")?;
+        let blobs = dir
+            .path()
+            .join("containerd/io.containerd.content.v1.content/blobs/sha256");
+        fs::create_dir_all(&blobs)?;
+        for (digest, blob) in &source.blobs {
+            fs::write(blobs.join(digest.trim_start_matches("sha256:")), blob)?;
+        }
+
+        let sut = ImageStore::open(&dir.path().join("images"), None)?.with_content(Arc::new(
+            ContainerdContentStore::open(&dir.path().join("containerd"))?,
+        ));
+        let mut source_without_blobs = FakeDistribution::default();
+        source_without_blobs.manifests = source.manifests.clone();
+        let reference: Reference = "quay.io/tenant/app:v1".parse()?;
+        let record = sut
+            .pull(&mut storage, &source_without_blobs, &reference)
+            .await?;
+        assert_eq!(record.id(), &id);
+        let layer = sut.layer_path(&record.layers()[0])?;
+        assert_eq!(fs::read_to_string(layer.join("hello"))?, "hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_publishes_events() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let events = EventBus::default();
+        let mut rx = events.subscribe();
+        let metrics = Metrics::default();
+        let sut = ImageStore::open(&dir.path().join("images"), None)?
+            .with_events(events)
+            .with_metrics(metrics.clone());
+        let (source, id) = FakeDistribution::with_image("v1", "hello")?;
+
+        let reference: Reference = "quay.io/tenant/app:v1".parse()?;
+        let record = sut.pull(&mut storage, &source, &reference).await?;
+        let image = reference.to_string();
+        assert_eq!(
+            rx.recv().await?,
+            Event::PullStarted {
+                image: image.clone()
+            }
+        );
+        let mut stages = vec![];
+        for _ in 0..4 {
+            match rx.recv().await? {
+                Event::PullStage {
+                    stage,
+                    digest,
+                    bytes,
+                    ..
+                } => stages.push((stage, digest, bytes)),
+                event => bail!("unexpected event {:?}", event),
+            }
+        }
+        let layer_size = record.size() - stages[1].2;
+        assert_eq!(stages[0].0, PullStage::Resolve);
+        assert!(record.repo_digests()[0].ends_with(&stages[0].1));
+        assert_eq!(stages[1].0, PullStage::Fetch);
+        assert_eq!(&stages[1].1, record.id());
+        assert_eq!(
+            stages[2],
+            (PullStage::Fetch, record.layers()[0].clone(), layer_size)
+        );
+        assert_eq!(
+            stages[3],
+            (PullStage::Unpack, record.layers()[0].clone(), layer_size)
+        );
+        let out = metrics.render(0, 0);
+        assert!(out.contains(&format!(
+            "cri_image_pull_stage_bytes_total{{stage=\"unpack\"}} {}\n",
+            layer_size
+        )));
+        assert!(out.contains("cri_image_pull_stage_duration_seconds_count{stage=\"fetch\"} 2\n"));
+        match rx.recv().await? {
+            Event::PullProgress {
+                layer,
+                completed_layers,
+                total_layers,
+                completed_bytes,
+                total_bytes,
+                ..
+            } => {
+                assert_eq!(&layer, &record.layers()[0]);
+                assert_eq!((completed_layers, total_layers), (1, 1));
+                assert_eq!(completed_bytes, total_bytes);
+            }
+            event => bail!("unexpected event {:?}", event),
+        }
+        assert_eq!(rx.recv().await?, Event::PullCompleted { image, id });
+
+        // Pulling again only resolves the manifest, because the blobs and layers exist
+        sut.pull(&mut storage, &source, &reference).await?;
+        rx.recv().await?;
+        match rx.recv().await? {
+            Event::PullStage { stage, .. } => assert_eq!(stage, PullStage::Resolve),
+            event => bail!("unexpected event {:?}", event),
+        }
+        assert!(matches!(rx.recv().await?, Event::PullProgress { .. }));
+        rx.recv().await?;
+
+        let reference: Reference = "quay.io/tenant/app:v2".parse()?;
+        assert!(sut.pull(&mut storage, &source, &reference).await.is_err());
+        rx.recv().await?;
+        match rx.recv().await? {
+            Event::PullFailed { cause, .. } => assert!(cause.contains("get manifest"), "{}", cause),
+            event => bail!("unexpected event {:?}", event),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_failure_digest_mismatch() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let sut = ImageStore::open(&dir.path().join("images"), None)?;
+        let (mut source, _) = FakeDistribution::with_image("v1", "hello")?;
+        for blob in source.blobs.values_mut() {
+            blob.push(0);
+        }
+
+        let reference: Reference = "quay.io/tenant/app:v1".parse()?;
+        assert!(sut.pull(&mut storage, &source, &reference).await.is_err());
+        assert!(ImageStore::list(&mut storage)?.is_empty());
+
+        let reference: Reference = format!("quay.io/tenant/app@{}", digest_of(b"other")).parse()?;
+        assert!(sut.pull(&mut storage, &source, &reference).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recover_success() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(&dir.path().join("storage"))?;
+        let sut = ImageStore::open(&dir.path().join("images"), None)?;
+        let (source, _) = FakeDistribution::with_image("v1", "hello")?;
+        let record = sut
+            .pull(&mut storage, &source, &"quay.io/tenant/app:v1".parse()?)
+            .await?;
+        assert_eq!(sut.recover()?, Recovery::default());
+
+        // Simulate a crash in the middle of unpacking the layer and fetching another blob
+        let digest = &record.layers()[0];
+        let layer = sut.layer_path(digest)?;
+        fs::remove_dir_all(&layer)?;
+        let staging = staging_path(&layer, "unpack");
+        fs::create_dir_all(&staging)?;
+        fs::write(staging.join("hello"), "hel")?;
+        fs::write(
+            sut.journal_path(&staging)?,
+            serde_json::to_vec(&JournalEntry {
+                digest: digest.clone(),
+                staging: staging.clone(),
+            })?,
+        )?;
+        let partial_blob = staging_path(&sut.blob_path(&digest_of(b"other"))?, "tmp");
+        fs::write(&partial_blob, "oth")?;
+
+        let recovery = sut.recover()?;
+        assert_eq!(recovery.resumed(), 1);
+        assert_eq!(recovery.discarded(), 1);
+        assert_eq!(fs::read_to_string(layer.join("hello"))?, "hello");
+        assert!(!staging.exists());
+        assert!(!partial_blob.exists());
+        assert!(read_dir(&dir.path().join("images").join(JOURNAL_DIR))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn recover_discard_without_blob() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = ImageStore::open(dir.path(), None)?;
+        let digest = digest_of(b"layer");
+        let staging = staging_path(&sut.layer_path(&digest)?, "unpack");
+        fs::create_dir_all(&staging)?;
+        fs::write(
+            sut.journal_path(&staging)?,
+            serde_json::to_vec(&JournalEntry {
+                digest: digest.clone(),
+                staging: staging.clone(),
+            })?,
+        )?;
+        fs::write(sut.path.join(JOURNAL_DIR).join("truncated"), "{")?;
+
+        let recovery = sut.recover()?;
+        assert_eq!(recovery.resumed(), 0);
+        assert_eq!(recovery.discarded(), 1);
+        assert!(!staging.exists());
+        assert!(!sut.layer_path(&digest)?.exists());
+        Ok(())
+    }
+
+    /// Build an uncompressed tar archive containing the `files` by their path.
+    fn tar_archive(files: &[(&str, &str)]) -> Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, content.as_bytes())?;
+        }
+        Ok(builder.into_inner()?)
+    }
+
+    #[test]
+    fn unpack_archive_zstd() -> Result<()> {
+        let dir = tempdir()?;
+        let blob = dir.path().join("blob");
+        fs::write(
+            &blob,
+            zstd::encode_all(&tar_archive(&[("file", "content")])?[..], 0)?,
+        )?;
+
+        unpack_archive(&blob, &dir.path().join("layer"))?;
+        assert_eq!(
+            fs::read_to_string(dir.path().join("layer").join("file"))?,
+            "content"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unpack_archive_estargz() -> Result<()> {
+        // Every entry is a gzip member of its own, followed by the table of contents
+        let archive = tar_archive(&[("first", "a"), ("second", "b"), ("stargz.index.json", "{}")])?;
+        let mut blob = vec![];
+        for chunk in archive.chunks(1024) {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(chunk)?;
+            blob.extend(encoder.finish()?);
+        }
+        let dir = tempdir()?;
+        let path = dir.path().join("blob");
+        fs::write(&path, blob)?;
+
+        let dest = dir.path().join("layer");
+        unpack_archive(&path, &dest)?;
+        assert_eq!(fs::read_to_string(dest.join("first"))?, "a");
+        assert_eq!(fs::read_to_string(dest.join("second"))?, "b");
+        assert!(!dest.join("stargz.index.json").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn commit_concurrent_unpack() -> Result<()> {
+        let dir = tempdir()?;
+        let dest = dir.path().join("layer");
+        fs::create_dir(&dest)?;
+        fs::write(dest.join("file"), "first")?;
+        let staging = staging_path(&dest, "unpack");
+        fs::create_dir(&staging)?;
+        fs::write(staging.join("file"), "second")?;
+
+        commit(&staging, &dest)?;
+        assert_eq!(fs::read_to_string(dest.join("file"))?, "first");
+        assert!(!staging.exists());
+        Ok(())
+    }
+}
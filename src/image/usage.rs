@@ -0,0 +1,141 @@
+//! Usage tracking of images, which lets the garbage collection prefer evicting images that are
+//! rarely used.
+
+use crate::storage::KeyValueStorage;
+use anyhow::Result;
+use getset::CopyGetters;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+/// The storage key prefix of all image usages.
+const KEY_PREFIX: &str = "image-usage/";
+
+/// The additional retention every use of an image earns.
+const RETENTION_PER_USE: Duration = Duration::from_secs(60 * 60);
+
+/// The number of uses which earn additional retention, so that images which have been popular
+/// once do not stay forever.
+const MAX_REWARDED_USES: u64 = 24;
+
+#[derive(Clone, Copy, CopyGetters, Debug, Default, Deserialize, PartialEq, Serialize)]
+/// ImageUsage records how often and how recently an image has been used by containers.
+pub struct ImageUsage {
+    #[get_copy = "pub"]
+    /// The time of the latest use or pull in nanoseconds since the Unix epoch.
+    last_used: i64,
+
+    #[get_copy = "pub"]
+    /// The number of containers which have been created from the image.
+    uses: u64,
+}
+
+impl ImageUsage {
+    /// Retrieve the storage key for the usage of image `id`.
+    pub fn key(id: &str) -> String {
+        format!("{}{}", KEY_PREFIX, id)
+    }
+
+    /// Retrieve the usage of the image `id`, which is empty if the image has never been used.
+    pub fn get<S: KeyValueStorage>(storage: &mut S, id: &str) -> Result<Self> {
+        Ok(storage
+            .get::<_, (String, Self)>(Self::key(id))?
+            .map(|(_, x)| x)
+            .unwrap_or_default())
+    }
+
+    /// Retrieve the usages of all images by their ID.
+    pub fn list<S: KeyValueStorage>(storage: &mut S) -> Result<HashMap<String, Self>> {
+        Ok(storage
+            .scan_prefix::<_, (String, Self)>(KEY_PREFIX)?
+            .into_iter()
+            .collect())
+    }
+
+    /// Record that a container has been created from the image `id` at `now`.
+    pub fn record_use<S: KeyValueStorage>(storage: &mut S, id: &str, now: i64) -> Result<Self> {
+        Self::update(storage, id, |x| {
+            x.last_used = x.last_used.max(now);
+            x.uses += 1;
+        })
+    }
+
+    /// Record that the image `id` has been pulled at `now`, which protects freshly pulled images
+    /// without counting as use.
+    pub fn record_pull<S: KeyValueStorage>(storage: &mut S, id: &str, now: i64) -> Result<Self> {
+        Self::update(storage, id, |x| x.last_used = x.last_used.max(now))
+    }
+
+    /// Remove the usage of the image `id`.
+    pub fn remove<S: KeyValueStorage>(storage: &mut S, id: &str) -> Result<()> {
+        if storage.get::<_, (String, Self)>(Self::key(id))?.is_some() {
+            storage.remove(Self::key(id))?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve the time until which the image should be kept in nanoseconds since the Unix
+    /// epoch. Every use extends the retention beyond the latest use.
+    pub fn retained_until(&self) -> i64 {
+        let bonus = self.uses.min(MAX_REWARDED_USES) as i64 * RETENTION_PER_USE.as_nanos() as i64;
+        self.last_used.saturating_add(bonus)
+    }
+
+    /// Update the usage of the image `id` via the provided closure `f`.
+    fn update<S, F>(storage: &mut S, id: &str, f: F) -> Result<Self>
+    where
+        S: KeyValueStorage,
+        F: FnOnce(&mut Self),
+    {
+        let mut usage = Self::get(storage, id)?;
+        f(&mut usage);
+        // The ID is stored alongside, which allows listing all usages by image
+        storage.insert(Self::key(id), (id, usage))?;
+        Ok(usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::default_key_value_storage::DefaultKeyValueStorage;
+    use tempfile::tempdir;
+
+    #[test]
+    fn record_list_remove() -> Result<()> {
+        let dir = tempdir()?;
+        let mut storage = DefaultKeyValueStorage::open(dir.path())?;
+        assert_eq!(ImageUsage::get(&mut storage, "a")?, ImageUsage::default());
+
+        ImageUsage::record_pull(&mut storage, "a", 10)?;
+        ImageUsage::record_use(&mut storage, "a", 20)?;
+        let usage = ImageUsage::record_use(&mut storage, "a", 15)?;
+        assert_eq!(usage.last_used(), 20);
+        assert_eq!(usage.uses(), 2);
+        ImageUsage::record_pull(&mut storage, "b", 30)?;
+
+        let usages = ImageUsage::list(&mut storage)?;
+        assert_eq!(usages.len(), 2);
+        assert_eq!(usages["a"], usage);
+        assert_eq!(usages["b"].uses(), 0);
+
+        ImageUsage::remove(&mut storage, "a")?;
+        ImageUsage::remove(&mut storage, "a")?;
+        assert_eq!(ImageUsage::get(&mut storage, "a")?, ImageUsage::default());
+        Ok(())
+    }
+
+    #[test]
+    fn retained_until_success() {
+        let hour = RETENTION_PER_USE.as_nanos() as i64;
+        let usage = |uses| ImageUsage {
+            last_used: 100,
+            uses,
+        };
+        assert_eq!(usage(0).retained_until(), 100);
+        assert_eq!(usage(2).retained_until(), 100 + 2 * hour);
+        assert_eq!(
+            usage(1000).retained_until(),
+            100 + MAX_REWARDED_USES as i64 * hour
+        );
+    }
+}
@@ -1,17 +1,96 @@
 use crate::{
     cri_service::CRIService,
     criapi::{ImageFsInfoRequest, ImageFsInfoResponse},
+    storage::KeyValueStorage,
 };
+use tokio::task;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_image_fs_info(
         &self,
         _request: Request<ImageFsInfoRequest>,
     ) -> Result<Response<ImageFsInfoResponse>, Status> {
+        // Walking the image store blocks, which is why it must not happen on the runtime
+        let (usage, sampled) = (
+            self.image_fs().clone(),
+            self.config().image_fs_interval() > 0,
+        );
+        let usage = task::spawn_blocking(move || {
+            if sampled {
+                usage.get()
+            } else {
+                usage.refresh()
+            }
+        })
+        .await
+        .map_err(|e| Status::internal(format!("wait for image filesystem usage: {}", e)))?
+        .map_err(|e| Status::internal(format!("get image filesystem usage: {:#}", e)))?;
+
         let resp = ImageFsInfoResponse {
-            image_filesystems: Vec::new(),
+            image_filesystems: vec![usage],
         };
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::{new_cri_service, new_cri_service_with_config, test_config},
+        criapi::image_service_server::ImageService,
+    };
+    use anyhow::{Context, Result};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn image_fs_info_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        fs::write(sut.config().image_path().join("blob"), vec![0; 8192])?;
+
+        let response = sut
+            .image_fs_info(Request::new(ImageFsInfoRequest {}))
+            .await?;
+        let usage = response
+            .get_ref()
+            .image_filesystems
+            .first()
+            .context("no image filesystem")?;
+        assert!(
+            usage
+                .used_bytes
+                .as_ref()
+                .map(|x| x.value)
+                .unwrap_or_default()
+                >= 8192
+        );
+        assert!(
+            usage
+                .inodes_used
+                .as_ref()
+                .map(|x| x.value)
+                .unwrap_or_default()
+                > 1
+        );
+        assert!(usage.fs_id.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn image_fs_info_fail_missing_store() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .image_path(dir.path().join("missing"))
+                .image_fs_interval(0u64)
+                .build()?,
+        )?;
+        assert!(sut
+            .image_fs_info(Request::new(ImageFsInfoRequest {}))
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -1,19 +1,130 @@
 use crate::{
+    config::ImagePullPolicy,
     cri_service::CRIService,
     criapi::{ImageStatusRequest, ImageStatusResponse},
+    image::store::ImageStore,
+    storage::KeyValueStorage,
 };
 use std::collections::HashMap;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_image_status(
         &self,
-        _request: Request<ImageStatusRequest>,
+        request: Request<ImageStatusRequest>,
     ) -> Result<Response<ImageStatusResponse>, Status> {
+        let request = request.into_inner();
+        let image = request.image.map(|x| x.image).unwrap_or_default();
+
+        // Unknown images are reported without an error, as expected by the kubelet
+        let record = ImageStore::find(&mut self.storage().clone(), &image)
+            .map_err(|e| Status::invalid_argument(format!("find image {}: {:#}", image, e)))?;
+
+        // Reporting tagged images as missing makes the kubelet pull and resolve them again
+        let record = record.filter(|_| {
+            self.config().image_pull_policy() != ImagePullPolicy::Always
+                || !ImageStore::resolves_tag(&image)
+        });
+
+        let mut info = HashMap::new();
+        if request.verbose {
+            if let Some(record) = &record {
+                info.insert(
+                    "info".into(),
+                    serde_json::to_string(record)
+                        .map_err(|e| Status::internal(format!("serialize image: {}", e)))?,
+                );
+            }
+        }
+
         let resp = ImageStatusResponse {
-            image: None,
-            info: HashMap::new(),
+            image: record.map(|x| x.cri_image()),
+            info,
         };
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::{new_cri_service, new_cri_service_with_config, test_config},
+        criapi::{image_service_server::ImageService, ImageSpec},
+        image::store::tests::FakeDistribution,
+    };
+    use anyhow::{Context, Result};
+
+    fn new_image_status_request(image: &str) -> ImageStatusRequest {
+        ImageStatusRequest {
+            image: Some(ImageSpec {
+                image: image.into(),
+                ..Default::default()
+            }),
+            verbose: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn image_status_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let (source, id) = FakeDistribution::with_image("latest", "file")?;
+        sut.image_store()?
+            .pull(&mut sut.storage().clone(), &source, &"app".parse()?)
+            .await?;
+
+        for image in &["app", "docker.io/library/app:latest", id.as_str()] {
+            let response = sut
+                .image_status(Request::new(new_image_status_request(image)))
+                .await?
+                .into_inner();
+            let image = response.image.context("no image")?;
+            assert_eq!(image.id, id);
+            assert_eq!(image.repo_tags, vec!["docker.io/library/app:latest"]);
+            assert!(response.info.contains_key("info"));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn image_status_success_always_pull() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .image_pull_policy(ImagePullPolicy::Always)
+                .build()?,
+        )?;
+        let (source, id) = FakeDistribution::with_image("latest", "file")?;
+        let record = sut
+            .image_store()?
+            .pull(&mut sut.storage().clone(), &source, &"app".parse()?)
+            .await?;
+
+        for image in &["app", "docker.io/library/app:latest"] {
+            let response = sut
+                .image_status(Request::new(new_image_status_request(image)))
+                .await?
+                .into_inner();
+            assert!(response.image.is_none(), "{}", image);
+        }
+        for image in &[&id, &record.repo_digests()[0]] {
+            let response = sut
+                .image_status(Request::new(new_image_status_request(image)))
+                .await?
+                .into_inner();
+            assert_eq!(response.image.context("no image")?.id, id);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn image_status_success_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let response = sut
+            .image_status(Request::new(new_image_status_request("app")))
+            .await?
+            .into_inner();
+        assert!(response.image.is_none());
+        assert!(response.info.is_empty());
+        Ok(())
+    }
+}
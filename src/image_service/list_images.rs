@@ -1,15 +1,80 @@
 use crate::{
     cri_service::CRIService,
     criapi::{ListImagesRequest, ListImagesResponse},
+    image::{reference::Reference, store::ImageStore},
+    storage::KeyValueStorage,
 };
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_list_images(
         &self,
-        _request: Request<ListImagesRequest>,
+        request: Request<ListImagesRequest>,
     ) -> Result<Response<ListImagesResponse>, Status> {
-        let resp = ListImagesResponse { images: Vec::new() };
+        let filter = request
+            .into_inner()
+            .filter
+            .and_then(|x| x.image)
+            .map(|x| x.image)
+            .filter(|x| !x.is_empty());
+
+        let mut storage = self.storage().clone();
+        let mut records = ImageStore::list(&mut storage)
+            .map_err(|e| Status::internal(format!("list images: {:#}", e)))?;
+        if let Some(filter) = filter {
+            let reference: Reference = filter.parse().map_err(|e| {
+                Status::invalid_argument(format!("parse image filter {}: {:#}", filter, e))
+            })?;
+            records.retain(|x| x.matches(&reference));
+        }
+
+        let resp = ListImagesResponse {
+            images: records.iter().map(|x| x.cri_image()).collect(),
+        };
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service,
+        criapi::{image_service_server::ImageService, ImageFilter, ImageSpec},
+        image::store::tests::FakeDistribution,
+    };
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn list_images_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let mut source = FakeDistribution::default();
+        let first = source.add_image("v1", "first")?;
+        source.add_image("v2", "second")?;
+        let store = sut.image_store()?;
+        let mut storage = sut.storage().clone();
+        for reference in &["quay.io/app:v1", "quay.io/app:v2"] {
+            store
+                .pull(&mut storage, &source, &reference.parse()?)
+                .await?;
+        }
+
+        let response = sut
+            .list_images(Request::new(ListImagesRequest::default()))
+            .await?;
+        assert_eq!(response.get_ref().images.len(), 2);
+
+        let request = ListImagesRequest {
+            filter: Some(ImageFilter {
+                image: Some(ImageSpec {
+                    image: "quay.io/app:v1".into(),
+                    ..Default::default()
+                }),
+            }),
+        };
+        let response = sut.list_images(Request::new(request)).await?;
+        assert_eq!(response.get_ref().images.len(), 1);
+        assert_eq!(response.get_ref().images[0].id, first);
+        Ok(())
+    }
+}
@@ -1,9 +1,31 @@
 use crate::{
+    config::{ImagePullPolicy, SignatureVerification},
+    container::Container,
     cri_service::CRIService,
     criapi::{self, image_service_server::ImageService},
+    image::{
+        artifact::{self, ArtifactRecord},
+        auth::{AuthFile, Credentials},
+        content::ContainerdContentStore,
+        distribution::Registry,
+        peer::PeerSource,
+        reference::Reference,
+        registries::Registries,
+        signature::{SignaturePolicy, Verifier},
+        store::ImageStore,
+    },
+    sandbox::{infra::InfraSandbox, Sandbox},
+    storage::KeyValueStorage,
 };
+use anyhow::{format_err, Context};
+use log::{debug, info, warn};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tonic::{Request, Response, Status};
 
+/// The time pulled artifacts are kept without being referenced, which covers the containers being
+/// created with them.
+const ARTIFACT_GRACE_PERIOD: Duration = Duration::from_secs(600);
+
 mod image_fs_info;
 mod image_status;
 mod list_images;
@@ -11,39 +33,383 @@ mod pull_image;
 mod remove_image;
 
 #[tonic::async_trait]
-impl ImageService for CRIService {
+impl<S: KeyValueStorage> ImageService for CRIService<S> {
     async fn list_images(
         &self,
         request: Request<criapi::ListImagesRequest>,
     ) -> Result<Response<criapi::ListImagesResponse>, Status> {
-        self.handle_list_images(request).await
+        self.bounded("ListImages", request, |r| self.handle_list_images(r))
+            .await
     }
 
     async fn pull_image(
         &self,
         request: Request<criapi::PullImageRequest>,
     ) -> Result<Response<criapi::PullImageResponse>, Status> {
-        self.handle_pull_image(request).await
+        self.bounded("PullImage", request, |r| self.handle_pull_image(r))
+            .await
     }
 
     async fn image_status(
         &self,
         request: Request<criapi::ImageStatusRequest>,
     ) -> Result<Response<criapi::ImageStatusResponse>, Status> {
-        self.handle_image_status(request).await
+        self.bounded("ImageStatus", request, |r| self.handle_image_status(r))
+            .await
     }
 
     async fn remove_image(
         &self,
         request: Request<criapi::RemoveImageRequest>,
     ) -> Result<Response<criapi::RemoveImageResponse>, Status> {
-        self.handle_remove_image(request).await
+        self.bounded("RemoveImage", request, |r| self.handle_remove_image(r))
+            .await
     }
 
     async fn image_fs_info(
         &self,
         request: Request<criapi::ImageFsInfoRequest>,
     ) -> Result<Response<criapi::ImageFsInfoResponse>, Status> {
-        self.handle_image_fs_info(request).await
+        self.bounded("ImageFsInfo", request, |r| self.handle_image_fs_info(r))
+            .await
+    }
+}
+
+impl<S: KeyValueStorage> CRIService<S> {
+    /// Open the image store of the configured image path, which imports blobs from the
+    /// configured containerd content store.
+    pub fn image_store(&self) -> Result<ImageStore, Status> {
+        ImageStore::open(
+            self.config().image_path(),
+            self.config().layer_cache_path().as_deref(),
+        )
+        .and_then(|x| match self.config().containerd_root() {
+            Some(root) => Ok(x.with_content(Arc::new(ContainerdContentStore::open(root)?))),
+            None => Ok(x),
+        })
+        .map(|x| {
+            x.with_events(self.events().clone())
+                .with_limiter(self.pull_limiter().clone())
+                .with_metrics(self.metrics().clone())
+                .with_clock(self.clock().clone())
+        })
+        .map_err(|e| Status::internal(format!("open image store: {:#}", e)))
+    }
+
+    /// Open the image store for pulling images, which verifies their signatures if a signature
+    /// policy is configured and the verification is not turned off. Blobs are fetched from the
+    /// configured peer to peer distribution daemon first.
+    pub fn pull_store(&self) -> Result<ImageStore, Status> {
+        let mut store = self.image_store()?;
+        if let Some(endpoint) = self.config().peer_endpoint() {
+            let timeout = Duration::from_secs(self.config().peer_timeout());
+            store = store.with_peer(
+                PeerSource::new(endpoint, timeout)
+                    .map_err(|e| Status::internal(format!("create peer source: {:#}", e)))?,
+            );
+        }
+        let mode = self.config().signature_verification();
+        match self.config().signature_policy_path() {
+            Some(path) if mode != SignatureVerification::Off => SignaturePolicy::load(path)
+                .and_then(|x| Verifier::new(x, mode))
+                .map(|x| store.with_verifier(x))
+                .map_err(|e| Status::internal(format!("load signature policy: {:#}", e))),
+            _ => Ok(store),
+        }
+    }
+
+    /// Create the registry client for pulling the `reference` via the currently configured
+    /// registries. The registry of the image authenticates with the `auth` of the request or
+    /// otherwise with the credentials of the configured auth file, whereas mirrors only use the
+    /// latter.
+    pub fn registry(
+        &self,
+        reference: &Reference,
+        auth: Option<&criapi::AuthConfig>,
+    ) -> Result<Registry, Status> {
+        let registries = Registries::from_config(&self.live_config().current());
+        if registries.is_blocked(reference) {
+            return Err(Status::permission_denied(format!(
+                "pulling image {} is blocked by the registries configuration of the node",
+                reference
+            )));
+        }
+        let credentials = auth
+            .map(Credentials::from_auth_config)
+            .transpose()
+            .map_err(|e| Status::invalid_argument(format!("invalid auth config: {:#}", e)))?
+            .flatten();
+        let auth_file = match self.config().registry_auth_file() {
+            Some(path) => AuthFile::load(path)
+                .map_err(|e| Status::internal(format!("load registry credentials: {:#}", e)))?,
+            None => AuthFile::default(),
+        };
+
+        let mut registry = Registry::default()
+            .with_registries(registries.clone())
+            .map_err(|e| Status::internal(format!("create registry client: {:#}", e)))?;
+        for endpoint in registries.endpoints(reference) {
+            let name = if endpoint.mirror() {
+                format!("{}/{}", endpoint.host(), reference.repository())
+            } else if credentials.is_some() {
+                registry = registry.with_credentials(endpoint.host(), credentials.clone());
+                continue;
+            } else {
+                reference.name()
+            };
+            let credentials = auth_file
+                .credentials(&name)
+                .map_err(|e| Status::internal(format!("get credentials for {}: {:#}", name, e)))?;
+            registry = registry.with_credentials(endpoint.host(), credentials);
+        }
+        Ok(registry)
+    }
+
+    /// Pull the configured pre-pull and pinned images in the background, which are prefetches
+    /// that pulls of the same image wait for. Existing images are only pulled again if the node
+    /// always pulls, whereas nodes which never pull skip the pre-pulls. Failures are logged and
+    /// counted by the metrics.
+    pub fn pre_pull_images(&self) {
+        let images: Vec<&String> = self
+            .config()
+            .pre_pull_images()
+            .iter()
+            .chain(self.config().pinned_images())
+            .collect();
+        let policy = self.config().image_pull_policy();
+        if policy == ImagePullPolicy::Never {
+            if !images.is_empty() {
+                warn!("Skipping pre-pull of images, the pull policy of the node is never");
+            }
+            return;
+        }
+
+        let mut storage = self.storage().clone();
+        for image in images {
+            let res = image
+                .parse::<Reference>()
+                .map_err(|e| Status::invalid_argument(format!("parse image: {:#}", e)))
+                .and_then(|reference| {
+                    let found = ImageStore::find(&mut storage, image)
+                        .map_err(|e| Status::internal(format!("find image: {:#}", e)))?;
+                    if found.is_some() && policy != ImagePullPolicy::Always {
+                        return Ok(None);
+                    }
+                    let registry = self.registry(&reference, None)?;
+                    Ok(Some((self.pull_store()?, registry, reference)))
+                });
+            let (store, registry, reference) = match res {
+                Ok(Some(x)) => x,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Unable to pre-pull image {}: {}", image, e.message());
+                    self.metrics().observe_pre_pull(false);
+                    continue;
+                }
+            };
+
+            let (mut storage, metrics) = (storage.clone(), self.metrics().clone());
+            self.prefetches().spawn(&reference.to_string(), async move {
+                match store.pull(&mut storage, &registry, &reference).await {
+                    Ok(record) => {
+                        info!("Pre-pulled image {} as {}", reference, record.id());
+                        metrics.observe_pull(record.size());
+                        metrics.observe_pre_pull(true);
+                    }
+                    Err(e) => {
+                        warn!("Unable to pre-pull image {}: {:#}", reference, e);
+                        metrics.observe_pre_pull(false);
+                    }
+                }
+            });
+        }
+    }
+
+    /// Prefetch the `images` one after another in the background, unless they exist already or
+    /// the node never pulls. Pulling sequentially keeps the prefetches from competing with the
+    /// pulls the kubelet waits for, whereas a pull of an image only waits for its prefetch once it
+    /// has started. Failures are only logged. Returns the number of queued prefetches.
+    pub fn prefetch_images(&self, storage: &mut S, images: &[String]) -> usize {
+        if self.config().image_pull_policy() == ImagePullPolicy::Never {
+            return 0;
+        }
+        let mut queued: Vec<(ImageStore, Registry, Reference)> = vec![];
+        for image in images {
+            let res = image
+                .parse::<Reference>()
+                .map_err(|e| Status::invalid_argument(format!("parse image: {:#}", e)))
+                .and_then(|reference| {
+                    let found = ImageStore::find(storage, image)
+                        .map_err(|e| Status::internal(format!("find image: {:#}", e)))?;
+                    if found.is_some() {
+                        return Ok(None);
+                    }
+                    let registry = self.registry(&reference, None)?;
+                    Ok(Some((self.pull_store()?, registry, reference)))
+                });
+            match res {
+                Ok(Some(x)) => queued.push(x),
+                Ok(None) => {}
+                Err(e) => warn!("Unable to prefetch image {}: {}", image, e.message()),
+            }
+        }
+
+        let count = queued.len();
+        if count == 0 {
+            return 0;
+        }
+        let (prefetches, storage) = (self.prefetches().clone(), storage.clone());
+        tokio::spawn(async move {
+            for (store, registry, reference) in queued {
+                let (image, mut storage) = (reference.to_string(), storage.clone());
+                prefetches.spawn(&image, async move {
+                    match store.pull(&mut storage, &registry, &reference).await {
+                        Ok(record) => info!("Prefetched image {} as {}", reference, record.id()),
+                        Err(e) => warn!("Unable to prefetch image {}: {:#}", reference, e),
+                    }
+                });
+                prefetches.wait(&image).await;
+            }
+        });
+        count
+    }
+
+    /// Remove the artifacts which are referenced by neither a pod sandbox nor a container, unless
+    /// they have been pulled within the `ARTIFACT_GRACE_PERIOD`. Returns the number of removed
+    /// artifacts.
+    pub fn collect_artifacts(&self) -> anyhow::Result<usize> {
+        let mut storage = self.storage().clone();
+        let mut used = vec![];
+        let sandboxes = storage
+            .scan_prefix::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key_prefix())
+            .context("list pod sandboxes")?;
+        let none = HashMap::new();
+        for sandbox in &sandboxes {
+            match artifact::artifacts(sandbox.data().annotations(), &none) {
+                Ok(artifacts) => used.extend(artifacts.into_iter().map(|x| x.1)),
+                Err(e) => debug!("Skipping artifacts of {}: {:#}", sandbox.data().id(), e),
+            }
+        }
+        let containers = storage
+            .scan_prefix::<_, Container>(Container::key_prefix())
+            .context("list containers")?;
+        for container in &containers {
+            match artifact::artifacts(&none, container.annotations()) {
+                Ok(artifacts) => used.extend(artifacts.into_iter().map(|x| x.1)),
+                Err(e) => debug!("Skipping artifacts of {}: {:#}", container.id(), e),
+            }
+        }
+
+        let store = self
+            .image_store()
+            .map_err(|e| format_err!("{}", e.message()))?;
+        let min_pulled_at = self.clock().unix_nanos()? - ARTIFACT_GRACE_PERIOD.as_nanos() as i64;
+        let mut removed = 0;
+        for record in ArtifactRecord::list(&mut storage)? {
+            if record.pulled_at() > min_pulled_at || used.iter().any(|x| record.matches(x)) {
+                continue;
+            }
+            store.remove_artifact(&mut storage, &record)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        clock::tests::FakeClock,
+        config::ImagePullPolicy,
+        cri_service::tests::{
+            new_cri_service_with_clock, new_cri_service_with_config, test_config,
+        },
+        image::{artifact::ArtifactRecord, store::tests::FakeDistribution},
+    };
+    use anyhow::Result;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn pre_pull_images_skips_existing() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .pre_pull_images(vec!["app".into()])
+                .pinned_images(vec!["Invalid:".into()])
+                .build()?,
+        )?;
+        let (source, _) = FakeDistribution::with_image("latest", "file")?;
+        sut.image_store()?
+            .pull(&mut sut.storage().clone(), &source, &"app".parse()?)
+            .await?;
+
+        sut.pre_pull_images();
+        assert!(!sut.prefetches().is_running("docker.io/library/app:latest"));
+        let out = sut.metrics().render(0, 0);
+        assert!(out.contains("cri_image_pre_pulls_total{result=\"failure\"} 1\n"));
+        assert!(!out.contains("cri_image_pre_pulls_total{result=\"success\"}"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pre_pull_images_skips_never_policy() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .image_pull_policy(ImagePullPolicy::Never)
+                .pre_pull_images(vec!["app".into()])
+                .build()?,
+        )?;
+        sut.pre_pull_images();
+        assert!(!sut.prefetches().is_running("docker.io/library/app:latest"));
+        assert!(!sut
+            .metrics()
+            .render(0, 0)
+            .contains("cri_image_pre_pulls_total{"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefetch_images_skips_existing() -> Result<()> {
+        let sut = new_cri_service_with_config(test_config()?.build()?)?;
+        let (source, _) = FakeDistribution::with_image("latest", "file")?;
+        let mut storage = sut.storage().clone();
+        sut.image_store()?
+            .pull(&mut storage, &source, &"app".parse()?)
+            .await?;
+
+        let images = vec!["app".into(), "Invalid:".into()];
+        assert_eq!(sut.prefetch_images(&mut storage, &images), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefetch_images_skips_never_policy() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .image_pull_policy(ImagePullPolicy::Never)
+                .build()?,
+        )?;
+        let images = vec!["other".into()];
+        assert_eq!(sut.prefetch_images(&mut sut.storage().clone(), &images), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn collect_artifacts_success() -> Result<()> {
+        let clock = FakeClock::default();
+        let sut = new_cri_service_with_clock(test_config()?.build()?, &clock)?;
+        let mut source = FakeDistribution::default();
+        source.add_artifact("v1", &[("filter.wasm", "wasm")])?;
+        let mut storage = sut.storage().clone();
+        let reference = "quay.io/tenant/filter:v1".parse()?;
+        sut.image_store()?
+            .pull_artifact(&mut storage, &source, &reference)
+            .await?;
+
+        // Unreferenced artifacts are kept during the grace period
+        assert_eq!(sut.collect_artifacts()?, 0);
+        clock.advance(Duration::from_secs(601));
+        assert_eq!(sut.collect_artifacts()?, 1);
+        assert!(ArtifactRecord::find(&mut storage, &reference)?.is_none());
+        Ok(())
     }
 }
@@ -1,17 +1,294 @@
 use crate::{
+    config::ImagePullPolicy,
     cri_service::CRIService,
     criapi::{PullImageRequest, PullImageResponse},
+    error_details::ErrorDetails,
+    image::{
+        layout::{LocalImagePolicy, LocalImageSource, LocalLayout},
+        reference::Reference,
+        signature::VerificationError,
+        store::{ImageRecord, ImageStore},
+    },
+    storage::KeyValueStorage,
 };
-use tonic::{Request, Response, Status};
+use anyhow::Error;
+use log::{debug, info};
+use tonic::{Code, Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_pull_image(
         &self,
-        _request: Request<PullImageRequest>,
+        request: Request<PullImageRequest>,
     ) -> Result<Response<PullImageResponse>, Status> {
+        let request = request.into_inner();
+        let image = request.image.map(|x| x.image).unwrap_or_default();
+        let reference: Reference = image
+            .parse()
+            .map_err(|e| Status::invalid_argument(format!("parse image {}: {:#}", image, e)))?;
+
+        // A running prefetch already fetches the blobs, which the pull reuses afterwards
+        self.prefetches().wait(&reference.to_string()).await;
+
+        // Static pods of nodes without registry access resolve from the local layout first
+        if let Some(record) = self.pull_local(&reference).await? {
+            self.metrics().observe_pull(record.size());
+            return Ok(Response::new(PullImageResponse {
+                image_ref: record.id().clone(),
+            }));
+        }
+
+        // Offline nodes serve the pull from the local images without contacting a registry
+        if self.config().image_pull_policy() == ImagePullPolicy::Never {
+            let record = ImageStore::find(&mut self.storage().clone(), &image)
+                .map_err(|e| Status::internal(format!("find image {}: {:#}", image, e)))?
+                .ok_or_else(|| {
+                    ErrorDetails::new("pull image")
+                        .hint("preload the image, the pull policy of the node is never")
+                        .status(
+                            Code::FailedPrecondition,
+                            format!("image {} not present and pulls are disabled", reference),
+                        )
+                })?;
+            return Ok(Response::new(PullImageResponse {
+                image_ref: record.id().clone(),
+            }));
+        }
+
+        let registry = self.registry(&reference, request.auth.as_ref())?;
+        let record = self
+            .pull_store()?
+            .pull(&mut self.storage().clone(), &registry, &reference)
+            .await
+            .map_err(|e| pull_error(&reference, e))?;
+        self.metrics().observe_pull(record.size());
+
         let resp = PullImageResponse {
-            image_ref: "some_image".into(),
+            image_ref: record.id().clone(),
         };
         Ok(Response::new(resp))
     }
+
+    /// Pull the `reference` from the configured local image layout if its prefix resolves from
+    /// there. Returns `None` if the image has to be pulled from a registry instead.
+    async fn pull_local(&self, reference: &Reference) -> Result<Option<ImageRecord>, Status> {
+        let path = match self.config().local_image_layout() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let policy = match LocalImageSource::policy(self.config().local_image_sources(), reference)
+        {
+            Some(policy) => policy,
+            None => return Ok(None),
+        };
+
+        let res = match LocalLayout::open(path) {
+            Ok(layout) => {
+                self.pull_store()?
+                    .pull(&mut self.storage().clone(), &layout, reference)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(record) => {
+                info!("Resolved image {} from local image layout", reference);
+                Ok(Some(record))
+            }
+            Err(e) if policy == LocalImagePolicy::Prefer => {
+                debug!(
+                    "Pulling image {} from registry instead of local image layout: {:#}",
+                    reference, e
+                );
+                Ok(None)
+            }
+            Err(e) if e.downcast_ref::<VerificationError>().is_some() => {
+                Err(pull_error(reference, e))
+            }
+            Err(e) => Err(ErrorDetails::new("pull image")
+                .hint(format!(
+                    "add the image to the local image layout {}, registries are never consulted \
+                     for it",
+                    path.display()
+                ))
+                .status(
+                    Code::FailedPrecondition,
+                    format!(
+                        "resolve image {} from local image layout: {:#}",
+                        reference, e
+                    ),
+                )),
+        }
+    }
+}
+
+/// Convert the error `e` of pulling the `reference` into a status, which tells apart images
+/// failing the signature verification.
+fn pull_error(reference: &Reference, e: Error) -> Status {
+    match e.downcast_ref::<VerificationError>() {
+        Some(e) => ErrorDetails::new("verify image signature")
+            .hint("sign the image by a key or identity of the signature policy of the node")
+            .status(Code::PermissionDenied, e),
+        None => Status::internal(format!("pull image {}: {:#}", reference, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::{new_cri_service, new_cri_service_with_config, test_config},
+        criapi::image_service_server::ImageService,
+        criapi::ImageSpec,
+        image::store::tests::FakeDistribution,
+    };
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    fn new_pull_image_request(image: &str) -> PullImageRequest {
+        PullImageRequest {
+            image: Some(ImageSpec {
+                image: image.into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn pull_image_fail_invalid_reference() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = PullImageRequest {
+            image: Some(ImageSpec {
+                image: "Invalid:".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let response = sut.pull_image(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_image_fail_blocked_registry() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .blocked_registries(vec!["docker.io".into()])
+                .build()?,
+        )?;
+        let request = PullImageRequest {
+            image: Some(ImageSpec {
+                image: "nginx".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let response = sut.pull_image(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_image_success_local_layout() -> Result<()> {
+        let dir = tempdir()?;
+        let (source, id) = FakeDistribution::with_image("3.2", "file")?;
+        source.write_layout(dir.path(), "registry.k8s.io/pause")?;
+
+        // The blocked registry is never contacted for images of the layout
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .blocked_registries(vec!["registry.k8s.io".into()])
+                .local_image_layout(Some(dir.path().into()))
+                .local_image_sources(vec!["registry.k8s.io/=only".parse()?])
+                .build()?,
+        )?;
+        let response = sut
+            .pull_image(Request::new(new_pull_image_request(
+                "registry.k8s.io/pause:3.2",
+            )))
+            .await?
+            .into_inner();
+        assert_eq!(response.image_ref, id);
+
+        let response = sut
+            .pull_image(Request::new(new_pull_image_request(
+                "registry.k8s.io/pause:3.1",
+            )))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_image_success_local_layout_prefer() -> Result<()> {
+        let dir = tempdir()?;
+        let (source, _) = FakeDistribution::with_image("3.2", "file")?;
+        source.write_layout(dir.path(), "registry.k8s.io/pause")?;
+
+        // Images missing from the layout are pulled from the registry, which is blocked here
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .blocked_registries(vec!["registry.k8s.io".into()])
+                .local_image_layout(Some(dir.path().into()))
+                .build()?,
+        )?;
+        let response = sut
+            .pull_image(Request::new(new_pull_image_request(
+                "registry.k8s.io/pause:3.1",
+            )))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_image_success_never_present() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .image_pull_policy(ImagePullPolicy::Never)
+                .blocked_registries(vec!["docker.io".into()])
+                .build()?,
+        )?;
+        let (source, id) = FakeDistribution::with_image("latest", "file")?;
+        sut.image_store()?
+            .pull(&mut sut.storage().clone(), &source, &"app".parse()?)
+            .await?;
+
+        // The registry of the image is never contacted, so that blocking it does not matter
+        let response = sut
+            .pull_image(Request::new(new_pull_image_request("app")))
+            .await?
+            .into_inner();
+        assert_eq!(response.image_ref, id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pull_image_fail_never_missing() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .image_pull_policy(ImagePullPolicy::Never)
+                .build()?,
+        )?;
+        let response = sut
+            .pull_image(Request::new(new_pull_image_request("app")))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        Ok(())
+    }
 }
@@ -1,15 +1,105 @@
 use crate::{
     cri_service::CRIService,
     criapi::{RemoveImageRequest, RemoveImageResponse},
+    error_details::ErrorDetails,
+    image::store::ImageStore,
+    storage::KeyValueStorage,
 };
-use tonic::{Request, Response, Status};
+use log::info;
+use tonic::{Code, Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_remove_image(
         &self,
-        _request: Request<RemoveImageRequest>,
+        request: Request<RemoveImageRequest>,
     ) -> Result<Response<RemoveImageResponse>, Status> {
+        let image = request
+            .into_inner()
+            .image
+            .map(|x| x.image)
+            .unwrap_or_default();
+
+        // Removing an unknown image is not an error, because it may have been removed already
+        let mut storage = self.storage().clone();
+        match ImageStore::find(&mut storage, &image)
+            .map_err(|e| Status::invalid_argument(format!("find image {}: {:#}", image, e)))?
+        {
+            Some(record) => {
+                for pinned in self.config().pinned_images() {
+                    let found = ImageStore::find(&mut storage, pinned).ok().flatten();
+                    if found.map_or(false, |x| x.id() == record.id()) {
+                        return Err(ErrorDetails::new("remove image")
+                            .hint("remove the image from the pinned images of the node first")
+                            .status(
+                                Code::FailedPrecondition,
+                                format!("image {} is pinned as {}", image, pinned),
+                            ));
+                    }
+                }
+                self.image_store()?
+                    .remove(&mut storage, &record)
+                    .map_err(|e| Status::internal(format!("remove image {}: {:#}", image, e)))?
+            }
+            None => info!("Image {} not found, nothing to remove", image),
+        }
+
         let resp = RemoveImageResponse {};
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::{new_cri_service, new_cri_service_with_config, test_config},
+        criapi::{image_service_server::ImageService, ImageSpec},
+        image::store::tests::FakeDistribution,
+    };
+    use anyhow::Result;
+
+    fn new_remove_image_request(image: &str) -> RemoveImageRequest {
+        RemoveImageRequest {
+            image: Some(ImageSpec {
+                image: image.into(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn remove_image_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let (source, id) = FakeDistribution::with_image("latest", "file")?;
+        sut.image_store()?
+            .pull(&mut sut.storage().clone(), &source, &"app".parse()?)
+            .await?;
+
+        for _ in 0..2 {
+            sut.remove_image(Request::new(new_remove_image_request("app")))
+                .await?;
+        }
+        assert!(ImageStore::find(&mut sut.storage().clone(), &id)?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_image_fail_pinned() -> Result<()> {
+        let sut =
+            new_cri_service_with_config(test_config()?.pinned_images(vec!["app".into()]).build()?)?;
+        let (source, id) = FakeDistribution::with_image("latest", "file")?;
+        sut.image_store()?
+            .pull(&mut sut.storage().clone(), &source, &"app".parse()?)
+            .await?;
+
+        let response = sut
+            .remove_image(Request::new(new_remove_image_request(&id)))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        assert!(ImageStore::find(&mut sut.storage().clone(), &id)?.is_some());
+        Ok(())
+    }
+}
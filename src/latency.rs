@@ -0,0 +1,148 @@
+//! Latency budgets of runtime operations.
+//!
+//! Operations like `RunPodSandbox` record the duration of each of their steps, like pinning the
+//! network namespace or invoking the CNI plugins. An operation exceeding the budget of its method
+//! gets reported as slow operation event including the durations of all steps, which points tail
+//! latency investigations directly to the slow step.
+
+use crate::{clock::Clock, event::Event};
+use anyhow::{bail, Context, Error, Result};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, CopyGetters, Debug, Deserialize, Eq, Getters, PartialEq, Serialize)]
+/// LatencyBudget is the time a single gRPC method is expected to take.
+pub struct LatencyBudget {
+    #[get = "pub"]
+    /// The name of the gRPC method, like `RunPodSandbox`.
+    method: String,
+
+    #[get_copy = "pub"]
+    /// The budget in milliseconds, whereas `0` disables it.
+    budget: u64,
+}
+
+impl FromStr for LatencyBudget {
+    type Err = Error;
+
+    /// Parse a latency budget in the format `METHOD=MILLISECONDS`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(method), Some(budget)) if !method.is_empty() => Ok(Self {
+                method: method.into(),
+                budget: budget
+                    .parse()
+                    .with_context(|| format!("parse latency budget of method {}", method))?,
+            }),
+            _ => bail!("invalid latency budget {}, expected METHOD=MILLISECONDS", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Timeline records the durations of the steps of an operation.
+pub struct Timeline {
+    /// The clock the durations are measured with.
+    clock: Arc<dyn Clock>,
+
+    /// The time the operation started.
+    started: Instant,
+
+    /// The time the last step finished.
+    last: Instant,
+
+    /// The finished steps with their durations in execution order.
+    steps: Vec<(String, Duration)>,
+}
+
+impl Timeline {
+    /// Start the timeline of an operation, whose durations are measured with the `clock`.
+    pub fn start(clock: Arc<dyn Clock>) -> Self {
+        let now = clock.instant();
+        Self {
+            clock,
+            started: now,
+            last: now,
+            steps: vec![],
+        }
+    }
+
+    /// Finish the step `name`, which started when the previous step finished.
+    pub fn step(&mut self, name: &str) {
+        let now = self.clock.instant();
+        self.steps.push((name.into(), now - self.last));
+        self.last = now;
+    }
+
+    /// Build the slow operation event of the `method` operating on `subject` if the timeline
+    /// exceeds the `budget`.
+    pub fn slow_operation(&self, method: &str, subject: &str, budget: Duration) -> Option<Event> {
+        let elapsed = self.clock.instant() - self.started;
+        if budget == Duration::from_secs(0) || elapsed <= budget {
+            return None;
+        }
+        Some(Event::SlowOperation {
+            method: method.into(),
+            subject: subject.into(),
+            elapsed,
+            budget,
+            steps: self.steps.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::tests::FakeClock;
+
+    #[test]
+    fn latency_budget_from_str_success() -> Result<()> {
+        let b = LatencyBudget::from_str("RunPodSandbox=5000")?;
+        assert_eq!(b.method(), "RunPodSandbox");
+        assert_eq!(b.budget(), 5000);
+        Ok(())
+    }
+
+    #[test]
+    fn latency_budget_from_str_failure() {
+        assert!(LatencyBudget::from_str("RunPodSandbox").is_err());
+        assert!(LatencyBudget::from_str("=10").is_err());
+        assert!(LatencyBudget::from_str("RunPodSandbox=1s").is_err());
+    }
+
+    #[test]
+    fn slow_operation_success() -> Result<()> {
+        let clock = FakeClock::default();
+        let mut sut = Timeline::start(Arc::new(clock.clone()));
+        sut.step("netns");
+        clock.advance(Duration::from_millis(20));
+        sut.step("cni");
+
+        assert!(sut
+            .slow_operation("RunPodSandbox", "pod", Duration::from_secs(10))
+            .is_none());
+        assert!(sut
+            .slow_operation("RunPodSandbox", "pod", Duration::from_secs(0))
+            .is_none());
+        let event = sut
+            .slow_operation("RunPodSandbox", "pod", Duration::from_millis(10))
+            .context("no slow operation")?;
+        match &event {
+            Event::SlowOperation { steps, .. } => {
+                assert_eq!(steps.len(), 2);
+                assert_eq!(steps[0].0, "netns");
+                assert_eq!(steps[1].1, Duration::from_millis(20));
+            }
+            _ => bail!("unexpected event {:?}", event),
+        }
+        assert!(event.to_string().contains("slowest=cni"), "{}", event);
+        Ok(())
+    }
+}
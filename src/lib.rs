@@ -1,16 +1,63 @@
 //! This is the main library interface for this project
 #![deny(missing_docs)]
 
+mod admin;
+mod adminapi;
+mod admission;
+mod annotations;
+#[cfg(feature = "client")]
+pub mod client;
+mod clock;
 mod config;
+mod container;
+mod container_log;
 mod cri_service;
+#[cfg(feature = "client")]
+pub mod criapi;
+#[cfg(not(feature = "client"))]
 mod criapi;
+mod device;
+mod diagnostics;
+mod error_details;
+mod event;
+mod feature;
+mod health;
+mod id;
+mod idempotency;
+mod image;
 mod image_service;
+mod latency;
+mod listener;
+mod logging;
+mod metrics;
+mod network;
+mod oci;
 mod oci_spec;
+mod quota;
+mod reload;
+mod request_log;
+mod resources;
 mod runtime_service;
 mod sandbox;
+mod scheduler;
 mod server;
+mod stats;
 mod storage;
-mod unix_stream;
+mod streaming;
+mod supervisor;
+mod systemd;
+mod telemetry;
+mod timeout;
 
-pub use config::Config;
+pub use admin::{
+    checkpoint_pods, commit_container, container_diff, container_log_path, push_image, restore_pods,
+};
+pub use clock::{Clock, SystemClock};
+pub use config::{
+    Command, CommitCommand, Config, ConfigCommand, DiffCommand, LogsCommand, PodsCommand,
+    PushCommand,
+};
+pub use container_log::{follow::LogFollower, index::query_log};
+pub use listener::ListenAddress;
+pub use network::netns::SandboxNetns;
 pub use server::Server;
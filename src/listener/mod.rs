@@ -0,0 +1,143 @@
+//! Listeners the server accepts connections from
+//!
+//! The server is not bound to a specific transport: every listener provides a stream of incoming
+//! connections, which can be served by the gRPC server.
+
+pub mod peers;
+pub mod tcp;
+#[cfg(unix)]
+pub mod unix;
+#[cfg(target_os = "linux")]
+pub mod vsock;
+
+use anyhow::{bail, Context, Error, Result};
+use futures_util::stream::Stream;
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, net::SocketAddr, path::PathBuf, str::FromStr};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tonic::transport::server::Connected;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+/// ListenAddress is an address the server can accept connections on.
+pub enum ListenAddress {
+    /// A unix domain socket at the path.
+    Unix(PathBuf),
+
+    /// A TCP socket bound to the address.
+    Tcp(SocketAddr),
+
+    /// A vsock socket, which allows reaching the server from the host if it runs inside a
+    /// virtual machine.
+    Vsock {
+        /// The context ID, whereas `4294967295` accepts connections from any context.
+        cid: u32,
+
+        /// The port of the socket.
+        port: u32,
+    },
+}
+
+impl FromStr for ListenAddress {
+    type Err = Error;
+
+    /// Parse a listen address in the format `unix:///PATH`, `tcp://IP:PORT` or
+    /// `vsock://CID:PORT`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, "://");
+        match (parts.next(), parts.next()) {
+            (Some("unix"), Some(path)) => Ok(ListenAddress::Unix(path.into())),
+            (Some("tcp"), Some(address)) => Ok(ListenAddress::Tcp(
+                address
+                    .parse()
+                    .with_context(|| format!("parse TCP address {}", address))?,
+            )),
+            (Some("vsock"), Some(address)) => {
+                let mut parts = address.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(cid), Some(port)) => Ok(ListenAddress::Vsock {
+                        cid: cid
+                            .parse()
+                            .with_context(|| format!("parse vsock context ID {}", cid))?,
+                        port: port
+                            .parse()
+                            .with_context(|| format!("parse vsock port {}", port))?,
+                    }),
+                    _ => bail!("invalid vsock address {}, expected CID:PORT", address),
+                }
+            }
+            _ => bail!(
+                "invalid listen address {}, expected unix://PATH, tcp://IP:PORT or vsock://CID:PORT",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Unix(path) => write!(f, "unix://{}", path.display()),
+            ListenAddress::Tcp(address) => write!(f, "tcp://{}", address),
+            ListenAddress::Vsock { cid, port } => write!(f, "vsock://{}:{}", cid, port),
+        }
+    }
+}
+
+/// The listener trait which defines the methods a transport implementation should fulfill.
+pub trait Listener {
+    /// A single accepted connection.
+    type Connection: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static;
+
+    /// The stream of accepted connections.
+    type Incoming: Stream<Item = io::Result<Self::Connection>> + Send + 'static;
+
+    /// The human readable address the listener is bound to.
+    fn address(&self) -> String;
+
+    /// Turn the listener into a stream of incoming connections.
+    fn incoming(self) -> Self::Incoming;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_address_from_str_success() -> Result<()> {
+        for (input, expected) in vec![
+            (
+                "unix:///run/cri.sock",
+                ListenAddress::Unix("/run/cri.sock".into()),
+            ),
+            (
+                "tcp://0.0.0.0:10010",
+                ListenAddress::Tcp(SocketAddr::from(([0, 0, 0, 0], 10010))),
+            ),
+            (
+                "vsock://4294967295:1024",
+                ListenAddress::Vsock {
+                    cid: u32::MAX,
+                    port: 1024,
+                },
+            ),
+        ] {
+            let address: ListenAddress = input.parse()?;
+            assert_eq!(address, expected);
+            assert_eq!(address.to_string(), input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn listen_address_from_str_failure() {
+        for input in &[
+            "/run/cri.sock",
+            "http://localhost:80",
+            "tcp://localhost",
+            "vsock://3",
+            "vsock://any:1024",
+        ] {
+            assert!(input.parse::<ListenAddress>().is_err(), "{}", input);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Tracking of the clients connected to the server.
+//!
+//! Every accepted connection registers itself together with the credentials of its peer and
+//! stays registered until it gets closed. Unix domain sockets have no remote address, which is
+//! why connections report a synthetic one encoding their identifier instead. This allows
+//! attributing RPCs to the connection they arrived on.
+
+use crate::clock::{Clock, SystemClock};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+/// The unique local IPv6 prefix of the synthetic peer addresses.
+const ADDRESS_PREFIX: u128 = 0xfd63_7269 << 96;
+
+/// The mask of the connection identifier inside a synthetic peer address.
+const ADDRESS_ID_MASK: u128 = u64::MAX as u128;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+/// The credentials of the process on the other side of a connection.
+pub struct Credentials {
+    /// The process ID of the peer.
+    pub pid: i32,
+
+    /// The user ID of the peer.
+    pub uid: u32,
+
+    /// The group ID of the peer.
+    pub gid: u32,
+}
+
+#[derive(Clone, Debug)]
+/// A registered connection.
+struct Connection {
+    /// The address of the listener which accepted the connection.
+    listener: String,
+
+    /// The credentials of the peer, if they could be retrieved.
+    credentials: Option<Credentials>,
+
+    /// The time the connection has been accepted.
+    connected: Instant,
+
+    /// The number of RPCs received via the connection.
+    rpcs: u64,
+}
+
+#[derive(Clone)]
+/// Peers keeps track of the open connections.
+pub struct Peers {
+    /// The identifier of the next registered connection.
+    next: Arc<AtomicU64>,
+
+    /// The open connections by their identifier.
+    connections: Arc<Mutex<BTreeMap<u64, Connection>>>,
+
+    /// The clock the age of the connections is measured with.
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Peers {
+    fn default() -> Self {
+        Self {
+            next: Arc::default(),
+            connections: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// PeerGuard removes the registered connection when being dropped.
+pub struct PeerGuard {
+    /// The identifier of the connection.
+    id: u64,
+
+    /// The tracker the connection belongs to.
+    connections: Arc<Mutex<BTreeMap<u64, Connection>>>,
+}
+
+impl PeerGuard {
+    /// Retrieve the synthetic address identifying the connection.
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(
+            IpAddr::V6(Ipv6Addr::from(ADDRESS_PREFIX | self.id as u128)),
+            0,
+        )
+    }
+}
+
+impl Drop for PeerGuard {
+    fn drop(&mut self) {
+        if let Ok(mut connections) = self.connections.lock() {
+            connections.remove(&self.id);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+/// A connected client.
+pub struct Peer {
+    /// The identifier of the connection.
+    id: u64,
+
+    /// The address of the listener which accepted the connection.
+    listener: String,
+
+    /// The credentials of the peer, if they could be retrieved.
+    credentials: Option<Credentials>,
+
+    /// The time since the connection has been accepted in seconds.
+    age_secs: u64,
+
+    /// The number of RPCs received via the connection.
+    rpcs: u64,
+}
+
+impl Peers {
+    /// Measure the age of the connections with the `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Register a connection accepted by `listener` from a peer with the `credentials` until the
+    /// returned guard gets dropped.
+    pub fn register(&self, listener: &str, credentials: Option<Credentials>) -> PeerGuard {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut connections) = self.connections.lock() {
+            connections.insert(
+                id,
+                Connection {
+                    listener: listener.into(),
+                    credentials,
+                    connected: self.clock.instant(),
+                    rpcs: 0,
+                },
+            );
+        }
+        PeerGuard {
+            id,
+            connections: self.connections.clone(),
+        }
+    }
+
+    /// Count an RPC received from the remote `address`. RPCs from addresses which do not belong
+    /// to a registered connection are ignored.
+    pub fn record_rpc(&self, address: SocketAddr) {
+        let id = match address {
+            SocketAddr::V6(x) if x.port() == 0 => u128::from(*x.ip()),
+            _ => return,
+        };
+        if id & !ADDRESS_ID_MASK != ADDRESS_PREFIX {
+            return;
+        }
+        if let Ok(mut connections) = self.connections.lock() {
+            if let Some(connection) = connections.get_mut(&((id & ADDRESS_ID_MASK) as u64)) {
+                connection.rpcs += 1;
+            }
+        }
+    }
+
+    /// Retrieve all open connections, ordered by their arrival.
+    pub fn list(&self) -> Vec<Peer> {
+        let now = self.clock.instant();
+        self.connections
+            .lock()
+            .map(|x| {
+                x.iter()
+                    .map(|(id, connection)| Peer {
+                        id: *id,
+                        listener: connection.listener.clone(),
+                        credentials: connection.credentials,
+                        age_secs: (now - connection.connected).as_secs(),
+                        rpcs: connection.rpcs,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::tests::FakeClock;
+    use anyhow::Result;
+    use std::time::Duration;
+
+    #[test]
+    fn register_success() {
+        let clock = FakeClock::default();
+        let sut = Peers::default().with_clock(Arc::new(clock.clone()));
+        let credentials = Credentials {
+            pid: 42,
+            uid: 0,
+            gid: 0,
+        };
+        let kubelet = sut.register("/run/cri.sock", Some(credentials));
+        clock.advance(Duration::from_secs(30));
+        let crictl = sut.register("/run/cri.sock", None);
+
+        sut.record_rpc(kubelet.address());
+        sut.record_rpc(kubelet.address());
+        sut.record_rpc(crictl.address());
+        let peers = sut.list();
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].credentials, Some(credentials));
+        assert_eq!(peers[0].rpcs, 2);
+        assert_eq!(peers[1].rpcs, 1);
+        assert_eq!((peers[0].age_secs, peers[1].age_secs), (30, 0));
+
+        drop(kubelet);
+        let peers = sut.list();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].id, 1);
+    }
+
+    #[test]
+    fn record_rpc_ignore_unknown() -> Result<()> {
+        let sut = Peers::default();
+        let guard = sut.register("/run/cri.sock", None);
+
+        // Real remote addresses of TCP connections are not attributed to any peer
+        sut.record_rpc("127.0.0.1:50051".parse()?);
+        sut.record_rpc("[::1]:0".parse()?);
+        sut.record_rpc(SocketAddr::new(
+            IpAddr::V6(Ipv6Addr::from(ADDRESS_PREFIX | 7)),
+            0,
+        ));
+        assert_eq!(sut.list()[0].rpcs, 0);
+
+        drop(guard);
+        assert!(sut.list().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+//! A listener based on TCP sockets.
+
+use crate::listener::Listener;
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use tokio::net;
+
+/// TcpSocketListener accepts connections on a TCP socket.
+pub struct TcpSocketListener {
+    /// The local address of the socket, which contains the actual port if binding to port `0`.
+    address: SocketAddr,
+
+    /// The bound listener.
+    listener: net::TcpListener,
+}
+
+impl TcpSocketListener {
+    /// Bind a new listener to the TCP `address`.
+    pub async fn bind(address: SocketAddr) -> Result<Self> {
+        let listener = net::TcpListener::bind(address)
+            .await
+            .with_context(|| format!("bind TCP socket to {}", address))?;
+        Ok(Self {
+            address: listener.local_addr().context("get local address")?,
+            listener,
+        })
+    }
+}
+
+impl Listener for TcpSocketListener {
+    type Connection = net::TcpStream;
+    type Incoming = net::TcpListener;
+
+    fn address(&self) -> String {
+        self.address.to_string()
+    }
+
+    fn incoming(self) -> Self::Incoming {
+        self.listener
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn bind_success() -> Result<()> {
+        let sut = TcpSocketListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let address = sut.address();
+        assert!(address.starts_with("127.0.0.1:"));
+        assert!(!address.ends_with(":0"));
+
+        let mut incoming = sut.incoming();
+        let client = net::TcpStream::connect(address.as_str()).await?;
+        let connection = incoming.next().await.context("no connection")??;
+        assert_eq!(connection.peer_addr()?, client.local_addr()?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bind_fail_in_use() -> Result<()> {
+        let first = TcpSocketListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        assert!(TcpSocketListener::bind(first.address).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+//! A listener based on unix domain sockets.
+
+use crate::listener::{
+    peers::{Credentials, PeerGuard, Peers},
+    Listener,
+};
+use anyhow::{bail, Context as _, Result};
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
+use log::debug;
+use std::{
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncWrite},
+    net,
+};
+use tonic::transport::server::Connected;
+
+#[derive(Debug)]
+/// UnixStream is a single connection accepted via a unix domain socket.
+pub struct UnixStream {
+    /// The accepted connection.
+    stream: net::UnixStream,
+
+    /// The registration of the connection, which lasts until the stream gets dropped.
+    peer: PeerGuard,
+}
+
+impl UnixStream {
+    /// Register the accepted `stream` of the `listener` at the `peers`.
+    fn new(stream: net::UnixStream, listener: &str, peers: &Peers) -> Self {
+        let credentials = peer_credentials(&stream);
+        debug!("Accepted connection on {} from {:?}", listener, credentials);
+        Self {
+            peer: peers.register(listener, credentials),
+            stream,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// Retrieve the credentials of the process on the other side of the `stream` via
+/// `SO_PEERCRED`.
+fn peer_credentials(stream: &net::UnixStream) -> Option<Credentials> {
+    use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+    use std::os::unix::io::AsRawFd;
+
+    getsockopt(stream.as_raw_fd(), PeerCredentials)
+        .map(|x| Credentials {
+            pid: x.pid(),
+            uid: x.uid(),
+            gid: x.gid(),
+        })
+        .map_err(|e| debug!("Unable to get peer credentials: {}", e))
+        .ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Retrieve the credentials of the process on the other side of the `stream`, which is only
+/// supported on Linux.
+fn peer_credentials(_: &net::UnixStream) -> Option<Credentials> {
+    None
+}
+
+impl Connected for UnixStream {
+    /// Unix domain sockets have no remote address, so the synthetic address of the registered
+    /// peer identifies the connection instead.
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(self.peer.address())
+    }
+}
+
+impl AsyncRead for UnixStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UnixStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// UnixSocketListener accepts connections on a unix domain socket.
+pub struct UnixSocketListener {
+    /// The path to the socket.
+    path: PathBuf,
+
+    /// The bound listener.
+    listener: net::UnixListener,
+
+    /// The tracker of the accepted connections.
+    peers: Peers,
+}
+
+impl UnixSocketListener {
+    /// Bind a new listener to the socket at `path`. An already existing socket file will be
+    /// replaced.
+    pub async fn bind(path: &Path) -> Result<Self> {
+        if !path.is_absolute() {
+            bail!("specified socket path {} is not absolute", path.display())
+        }
+        if path.exists() {
+            fs::remove_file(path)
+                .await
+                .with_context(|| format!("unable to remove socket file {}", path.display()))?;
+        } else {
+            let sock_dir = path.parent().context("get socket path directory")?;
+            fs::create_dir_all(sock_dir)
+                .await
+                .with_context(|| format!("create socket dir {}", sock_dir.display()))?;
+        }
+
+        Ok(Self {
+            path: path.into(),
+            listener: net::UnixListener::bind(path).context("bind socket from path")?,
+            peers: Peers::default(),
+        })
+    }
+
+    /// Create a new listener from the already bound `listener`, like a socket passed by systemd.
+    /// The socket file is owned by whoever bound it.
+    pub fn from_std(listener: std::os::unix::net::UnixListener) -> Result<Self> {
+        let path = listener
+            .local_addr()
+            .context("get local address of listener")?
+            .as_pathname()
+            .context("listener is not bound to a path")?
+            .into();
+        listener
+            .set_nonblocking(true)
+            .context("set listener to non-blocking")?;
+        Ok(Self {
+            path,
+            listener: net::UnixListener::from_std(listener).context("register listener")?,
+            peers: Peers::default(),
+        })
+    }
+
+    /// Register all accepted connections at the `peers`.
+    pub fn with_peers(mut self, peers: Peers) -> Self {
+        self.peers = peers;
+        self
+    }
+}
+
+impl Listener for UnixSocketListener {
+    type Connection = UnixStream;
+    type Incoming = BoxStream<'static, io::Result<UnixStream>>;
+
+    fn address(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn incoming(self) -> Self::Incoming {
+        let (address, peers) = (self.address(), self.peers);
+        self.listener
+            .map_ok(move |x| UnixStream::new(x, &address, &peers))
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::{tempdir, NamedTempFile};
+
+    #[tokio::test]
+    async fn bind_success() -> Result<()> {
+        let sock_path = &tempdir()?.path().join("test.sock");
+
+        assert!(!sock_path.exists());
+        let sut = UnixSocketListener::bind(sock_path).await?;
+        assert!(sock_path.exists());
+        assert_eq!(sut.address(), sock_path.display().to_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bind_success_exists() -> Result<()> {
+        let sock_path = NamedTempFile::new()?;
+
+        assert!(sock_path.path().exists());
+        UnixSocketListener::bind(sock_path.path()).await?;
+        assert!(sock_path.path().exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn incoming_success_peers() -> Result<()> {
+        let dir = tempdir()?;
+        let sock_path = dir.path().join("test.sock");
+        let peers = Peers::default();
+        let mut incoming = UnixSocketListener::bind(&sock_path)
+            .await?
+            .with_peers(peers.clone())
+            .incoming();
+
+        let client = net::UnixStream::connect(&sock_path).await?;
+        let stream = incoming.next().await.context("no connection")??;
+        assert!(stream.remote_addr().is_some());
+
+        let listed = peers.list();
+        assert_eq!(listed.len(), 1);
+        let value = serde_json::to_value(&listed[0])?;
+        assert_eq!(value["listener"], sock_path.display().to_string());
+        #[cfg(target_os = "linux")]
+        assert_eq!(value["credentials"]["pid"], std::process::id());
+
+        drop(client);
+        drop(stream);
+        assert!(peers.list().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn from_std_success() -> Result<()> {
+        let sock_path = &tempdir()?.path().join("test.sock");
+        let listener = std::os::unix::net::UnixListener::bind(sock_path)?;
+
+        let sut = UnixSocketListener::from_std(listener)?;
+        assert_eq!(sut.address(), sock_path.display().to_string());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bind_fail_not_absolute() {
+        assert!(UnixSocketListener::bind(Path::new("not/absolute/path"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,90 @@
+//! A listener based on vsock sockets, which connect virtual machines with their host.
+
+use crate::listener::Listener;
+use anyhow::{Context as _, Result};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use nix::sys::socket::SockAddr;
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_vsock::{VsockListener, VsockStream as Stream};
+use tonic::transport::server::Connected;
+
+#[derive(Debug)]
+/// VsockStream is a single connection accepted via a vsock socket.
+pub struct VsockStream(pub Stream);
+
+impl Connected for VsockStream {}
+
+impl AsyncRead for VsockStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for VsockStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// VsockSocketListener accepts connections on a vsock socket.
+pub struct VsockSocketListener {
+    /// The context ID the socket is bound to.
+    cid: u32,
+
+    /// The port the socket is bound to.
+    port: u32,
+
+    /// The bound listener.
+    listener: VsockListener,
+}
+
+impl VsockSocketListener {
+    /// Bind a new listener to the `port` of the context ID `cid`.
+    pub fn bind(cid: u32, port: u32) -> Result<Self> {
+        let listener = VsockListener::bind(&SockAddr::new_vsock(cid, port))
+            .with_context(|| format!("bind vsock socket to {}:{}", cid, port))?;
+        Ok(Self {
+            cid,
+            port,
+            listener,
+        })
+    }
+}
+
+impl Listener for VsockSocketListener {
+    type Connection = VsockStream;
+    type Incoming = BoxStream<'static, io::Result<VsockStream>>;
+
+    fn address(&self) -> String {
+        format!("vsock://{}:{}", self.cid, self.port)
+    }
+
+    fn incoming(self) -> Self::Incoming {
+        stream::unfold(self.listener, |mut listener| async move {
+            let connection = listener.accept().await.map(|(x, _)| VsockStream(x));
+            Some((connection, listener))
+        })
+        .boxed()
+    }
+}
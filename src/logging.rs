@@ -0,0 +1,386 @@
+//! Output of log records in the configured format and via the configured driver.
+//!
+//! Records can carry structured fields describing the pod and container an operation works on.
+//! The fields are scoped to the task handling the operation, like the IDs of traced requests, and
+//! get written as JSON keys or journal fields.
+
+use crate::{
+    clock::Clock,
+    config::{LogDriver, LogFormat},
+    container_log::format::timestamp,
+};
+use anyhow::{format_err, Context, Result};
+use clap::crate_name;
+use log::{Level, Record};
+use serde::Serialize;
+use std::{
+    fs::{File, OpenOptions},
+    future::Future,
+    io::Write,
+    os::unix::net::UnixDatagram,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// The socket accepting the native protocol of the systemd journal.
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+tokio::task_local! {
+    /// The structured fields of the operation handled by the current task.
+    static FIELDS: Fields;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+/// Fields are the structured fields attached to log records.
+pub struct Fields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The ID of the traced request.
+    request_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The UID of the pod.
+    pod_uid: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The ID of the pod sandbox.
+    pod_sandbox_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The ID of the container.
+    container_id: Option<String>,
+}
+
+impl Fields {
+    /// Create the fields of an operation on the pod sandbox `id`, whose pod has the `uid` if
+    /// known.
+    pub fn pod(id: &str, uid: Option<String>) -> Self {
+        Self {
+            pod_uid: uid,
+            pod_sandbox_id: Some(id.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Add the container `id` to the fields.
+    pub fn container(mut self, id: &str) -> Self {
+        self.container_id = Some(id.into());
+        self
+    }
+
+    /// Add the ID of the traced request to the fields.
+    pub fn request(mut self, id: Option<String>) -> Self {
+        self.request_id = id;
+        self
+    }
+
+    /// Retrieve the fields as journal field names and their values.
+    fn journal(&self) -> Vec<(&'static str, &str)> {
+        vec![
+            ("REQUEST_ID", &self.request_id),
+            ("POD_UID", &self.pod_uid),
+            ("POD_SANDBOX_ID", &self.pod_sandbox_id),
+            ("CONTAINER_ID", &self.container_id),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| v.as_deref().map(|v| (k, v)))
+        .collect()
+    }
+}
+
+/// Run the future `f` with its logs carrying the structured `fields`.
+pub async fn scope<F: Future>(fields: Fields, f: F) -> F::Output {
+    FIELDS.scope(fields, f).await
+}
+
+/// Retrieve the structured fields of the current task.
+pub fn current() -> Fields {
+    FIELDS.try_with(Clone::clone).unwrap_or_default()
+}
+
+#[derive(Serialize)]
+/// A log record in the JSON format.
+struct JsonRecord<'a> {
+    /// The time the record has been written.
+    timestamp: String,
+
+    /// The level of the record.
+    level: Level,
+
+    /// The target of the record, which is usually the module path.
+    target: &'a str,
+
+    /// The formatted message of the record.
+    message: String,
+
+    #[serde(flatten)]
+    /// The structured fields of the record.
+    fields: &'a Fields,
+}
+
+/// Sink writes log records in the configured format via the configured driver.
+pub struct Sink {
+    /// The format of the records written to stderr or the log file.
+    format: LogFormat,
+
+    /// The destination of the records.
+    output: Output,
+
+    /// The clock the records get timestamped by.
+    clock: Arc<dyn Clock>,
+}
+
+/// The destination of log records.
+enum Output {
+    /// The standard error of the server.
+    Stderr,
+
+    /// The log file, which gets appended to.
+    File(Mutex<File>),
+
+    /// The socket connected to the journal.
+    Journald(UnixDatagram),
+}
+
+impl Sink {
+    /// Create a new sink writing in the `format` via the `driver`, whose records get timestamped
+    /// by the `clock`. The `file` is required for the file driver.
+    pub fn new(
+        format: LogFormat,
+        driver: LogDriver,
+        file: Option<&Path>,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        let output = match driver {
+            LogDriver::Stderr => Output::Stderr,
+            LogDriver::File => {
+                let path = file.context("log file required for file driver")?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("open log file {}", path.display()))?;
+                Output::File(Mutex::new(file))
+            }
+            LogDriver::Journald => {
+                let socket = UnixDatagram::unbound().context("create journal socket")?;
+                socket
+                    .connect(JOURNAL_SOCKET)
+                    .with_context(|| format!("connect to journal {}", JOURNAL_SOCKET))?;
+                Output::Journald(socket)
+            }
+        };
+        Ok(Self {
+            format,
+            output,
+            clock,
+        })
+    }
+
+    /// Write the `record` together with its structured `fields`. Failures are reported on
+    /// stderr, because there is no other place left for them.
+    pub fn write(&self, record: &Record, fields: &Fields) {
+        let res = match &self.output {
+            Output::Stderr => self.line(record, fields).map(|x| eprint!("{}", x)),
+            Output::File(file) => self.line(record, fields).and_then(|x| {
+                let mut file = file.lock().map_err(|_| format_err!("lock log file"))?;
+                file.write_all(x.as_bytes()).context("write log file")
+            }),
+            Output::Journald(socket) => socket
+                .send(&journal_entry(record, fields))
+                .map(|_| ())
+                .context("send journal entry"),
+        };
+        if let Err(e) = res {
+            eprintln!("Unable to write log record: {:#}", e);
+        }
+    }
+
+    /// Flush the buffered records.
+    pub fn flush(&self) {
+        if let Output::File(file) = &self.output {
+            if let Ok(mut file) = file.lock() {
+                file.flush().ok();
+            }
+        }
+    }
+
+    /// Format the `record` and its `fields` as a single line.
+    fn line(&self, record: &Record, fields: &Fields) -> Result<String> {
+        let timestamp = timestamp(self.clock.now());
+        match self.format {
+            LogFormat::Text => {
+                let request = fields
+                    .request_id
+                    .as_ref()
+                    .map(|x| format!("[request {}] ", x))
+                    .unwrap_or_default();
+                Ok(format!(
+                    "[{} {:<5} {}] {}{}\n",
+                    timestamp,
+                    record.level(),
+                    record.target(),
+                    request,
+                    record.args()
+                ))
+            }
+            LogFormat::Json => {
+                let json = serde_json::to_string(&JsonRecord {
+                    timestamp,
+                    level: record.level(),
+                    target: record.target(),
+                    message: record.args().to_string(),
+                    fields,
+                })
+                .context("serialize log record")?;
+                Ok(format!("{}\n", json))
+            }
+        }
+    }
+}
+
+/// Encode the `record` and its `fields` in the native journal protocol.
+fn journal_entry(record: &Record, fields: &Fields) -> Vec<u8> {
+    let priority = match record.level() {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    };
+    let message = record.args().to_string();
+    let mut entry = vec![];
+    journal_field(&mut entry, "MESSAGE", &message);
+    journal_field(&mut entry, "PRIORITY", priority);
+    journal_field(&mut entry, "SYSLOG_IDENTIFIER", crate_name!());
+    journal_field(&mut entry, "TARGET", record.target());
+    for (key, value) in fields.journal() {
+        journal_field(&mut entry, key, value);
+    }
+    entry
+}
+
+/// Append the field `key` with its `value` to the journal `entry`. Values spanning multiple lines
+/// are prefixed by their length instead of being separated by `=`.
+fn journal_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{tests::FakeClock, SystemClock};
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// Build a record with the `message` and call `f` with it.
+    fn with_record<T>(message: &str, f: impl FnOnce(&Record) -> T) -> T {
+        f(&Record::builder()
+            .args(format_args!("{}", message))
+            .level(Level::Info)
+            .target("cri::server")
+            .build())
+    }
+
+    #[tokio::test]
+    async fn scope_success() {
+        assert_eq!(current(), Fields::default());
+        let fields = Fields::pod("sandbox", Some("uid".into())).container("id");
+        scope(fields.clone(), async {
+            assert_eq!(current(), fields);
+        })
+        .await;
+        assert_eq!(current(), Fields::default());
+    }
+
+    #[test]
+    fn line_success_text() -> Result<()> {
+        let clock = FakeClock::default();
+        let sut = Sink::new(
+            LogFormat::Text,
+            LogDriver::Stderr,
+            None,
+            Arc::new(clock.clone()),
+        )?;
+        let fields = Fields::pod("sandbox", None).request(Some("issue-42".into()));
+        let line = with_record("Started", |x| sut.line(x, &fields))?;
+        assert!(line.starts_with(&format!("[{} ", timestamp(clock.now()))));
+        assert!(line.ends_with(" INFO  cri::server] [request issue-42] Started\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn line_success_json() -> Result<()> {
+        let sut = Sink::new(
+            LogFormat::Json,
+            LogDriver::Stderr,
+            None,
+            Arc::new(SystemClock),
+        )?;
+        let fields = Fields::pod("sandbox", Some("uid".into())).container("id");
+        let line = with_record("Started", |x| sut.line(x, &fields))?;
+        let json: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!(json["level"], "INFO");
+        assert_eq!(json["target"], "cri::server");
+        assert_eq!(json["message"], "Started");
+        assert_eq!(json["pod_uid"], "uid");
+        assert_eq!(json["pod_sandbox_id"], "sandbox");
+        assert_eq!(json["container_id"], "id");
+        assert!(json.get("request_id").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn write_success_file() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("cri.log");
+        let sut = Sink::new(
+            LogFormat::Json,
+            LogDriver::File,
+            Some(&path),
+            Arc::new(SystemClock),
+        )?;
+        with_record("first", |x| sut.write(x, &Fields::default()));
+        with_record("second", |x| sut.write(x, &Fields::default()));
+        sut.flush();
+
+        let content = fs::read_to_string(&path)?;
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<_>>()?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["message"], "second");
+        Ok(())
+    }
+
+    #[test]
+    fn new_fail_file_missing() {
+        assert!(Sink::new(
+            LogFormat::Text,
+            LogDriver::File,
+            None,
+            Arc::new(SystemClock)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn journal_entry_success() {
+        let fields = Fields::default().container("id");
+        let entry = with_record("multiple\nlines", |x| journal_entry(x, &fields));
+
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&14u64.to_le_bytes());
+        expected.extend_from_slice(b"multiple\nlines\n");
+        expected.extend_from_slice(b"PRIORITY=6\nSYSLOG_IDENTIFIER=cri\nTARGET=cri::server\n");
+        expected.extend_from_slice(b"CONTAINER_ID=id\n");
+        assert_eq!(entry, expected);
+    }
+}
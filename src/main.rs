@@ -1,26 +1,153 @@
-use anyhow::Result;
-use cri::{Config, Server};
-use std::process::exit;
+use anyhow::{format_err, Error, Result};
+use cri::{
+    checkpoint_pods, commit_container, container_diff, container_log_path, push_image, query_log,
+    restore_pods, Clock, Command, Config, ConfigCommand, ListenAddress, LogFollower, Server,
+    SystemClock,
+};
+use std::{env, ffi::OsString, path::PathBuf, process::exit, time::Duration};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Parse CLI arguments
-    let config = Config::default();
+    // Parse CLI arguments, the environment and the configuration file
+    let args: Vec<OsString> = env::args_os().collect();
+    let config = Config::load_from(&args).unwrap_or_else(|e| fail("load configuration", e));
+
+    // Run the requested command instead of the server
+    if let Some(Command::Config(ConfigCommand::Default)) = config.command() {
+        let content =
+            Config::effective_toml(&args).unwrap_or_else(|e| fail("render configuration", e));
+        print!("{}", content);
+        return Ok(());
+    }
+    if let Some(Command::Logs(command)) = config.command() {
+        // Container IDs never contain a slash, unlike paths of log files
+        let path = if command.container().contains('/') {
+            PathBuf::from(command.container())
+        } else {
+            let sock_path = config.admin_sock_path().as_ref().unwrap_or_else(|| {
+                fail(
+                    "resolve container log",
+                    format_err!("no admin socket configured"),
+                )
+            });
+            container_log_path(sock_path, command.container())
+                .await
+                .unwrap_or_else(|e| fail("resolve container log", e))
+        };
+
+        // Following starts before the query, so that records written in between may be printed
+        // twice but never get lost
+        let mut follower = if command.follow() {
+            Some(
+                LogFollower::open_end(&path)
+                    .await
+                    .unwrap_or_else(|e| fail("follow container log", e)),
+            )
+        } else {
+            None
+        };
+        let since = command
+            .since_seconds()
+            .map(|x| SystemClock.now() - Duration::from_secs(x));
+        let records = query_log(&path, since, command.tail())
+            .unwrap_or_else(|e| fail("query container log", e));
+        for record in records {
+            println!("{}", record);
+        }
+        if let Some(follower) = follower.as_mut() {
+            loop {
+                let record = follower
+                    .next_line()
+                    .await
+                    .unwrap_or_else(|e| fail("follow container log", e));
+                println!("{}", record);
+            }
+        }
+        return Ok(());
+    }
+    if let Some(Command::Diff(command)) = config.command() {
+        let sock_path = config
+            .admin_sock_path()
+            .as_ref()
+            .unwrap_or_else(|| fail("diff container", format_err!("no admin socket configured")));
+        let changes = container_diff(sock_path, command.container_id())
+            .await
+            .unwrap_or_else(|e| fail("diff container", e));
+        for change in changes {
+            println!("{}", change);
+        }
+        return Ok(());
+    }
+    if let Some(Command::Commit(command)) = config.command() {
+        let sock_path = unix_listen_path(&config).unwrap_or_else(|e| fail("commit container", e));
+        let id = commit_container(
+            &sock_path,
+            command.container_id(),
+            command.image(),
+            command.message(),
+        )
+        .await
+        .unwrap_or_else(|e| fail("commit container", e));
+        println!("{}", id);
+        return Ok(());
+    }
+    if let Some(Command::Push(command)) = config.command() {
+        let sock_path = unix_listen_path(&config).unwrap_or_else(|e| fail("push image", e));
+        let repo_digest = push_image(&sock_path, command.image(), command.reference().as_deref())
+            .await
+            .unwrap_or_else(|e| fail("push image", e));
+        println!("{}", repo_digest);
+        return Ok(());
+    }
+    if let Some(Command::Checkpoint(command)) = config.command() {
+        let sock_path = unix_listen_path(&config).unwrap_or_else(|e| fail("checkpoint pods", e));
+        let ids = checkpoint_pods(&sock_path, command.pod_sandbox_ids())
+            .await
+            .unwrap_or_else(|e| fail("checkpoint pods", e));
+        for id in ids {
+            println!("{}", id);
+        }
+        return Ok(());
+    }
+    if let Some(Command::Restore(command)) = config.command() {
+        let sock_path = unix_listen_path(&config).unwrap_or_else(|e| fail("restore pods", e));
+        let ids = restore_pods(&sock_path, command.pod_sandbox_ids())
+            .await
+            .unwrap_or_else(|e| fail("restore pods", e));
+        for id in ids {
+            println!("{}", id);
+        }
+        return Ok(());
+    }
 
     // Spawn the server based on the configuration
     if let Err(e) = Server::new(config).start().await {
-        // Collect all errors and chain them together. Do not use the logger
-        // for printing here, because it could be possible that it fails before
-        // initializing it.
-        println!(
-            "Unable to run server: {}",
-            &e.chain()
-                .map(|x| x.to_string())
-                .collect::<Vec<_>>()
-                .join(": "),
-        );
-        exit(1);
+        fail("run server", e);
     }
 
     Ok(())
 }
+
+/// Retrieve the path of the unix socket the server listens on.
+fn unix_listen_path(config: &Config) -> Result<PathBuf> {
+    match config.listen_address() {
+        ListenAddress::Unix(path) => Ok(path),
+        _ => Err(format_err!("no unix listen socket configured")),
+    }
+}
+
+/// Print the error `e` of the failed `action` and exit the process.
+fn fail(action: &str, e: Error) -> ! {
+    // Collect all errors and chain them together. Do not use the logger
+    // for printing here, because it could be possible that it fails before
+    // initializing it.
+    println!(
+        "Unable to {}: {}",
+        action,
+        &e.chain()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(": "),
+    );
+    exit(1);
+}
@@ -0,0 +1,572 @@
+//! Prometheus metrics of the runtime.
+//!
+//! The RPCs are counted by their method and status code when their handler finishes, together
+//! with a latency histogram per method. The number of pod sandboxes and containers is read from
+//! the storage whenever the metrics get scraped via the HTTP `/metrics` endpoint. Image pulls are
+//! broken down into their stages, which tell whether the registry, the network or unpacking the
+//! layers is the bottleneck of slow pulls. The counters of the network interfaces of every pod
+//! sandbox are read from its network namespace on each scrape as well.
+//!
+//! For debugging, the same HTTP server streams the log of a container via `/logs/<container>`,
+//! whereas the container may be referenced by a unique ID prefix. The lines get sent as server
+//! sent events from the beginning of the log file and the log is followed across rotations until
+//! the client disconnects.
+
+use crate::{
+    container::Container,
+    container_log::follow::LogFollower,
+    cri_service::CRIService,
+    event::PullStage,
+    network::{
+        stats::{self, InterfaceStats},
+        NetworkStatus,
+    },
+    sandbox::{infra::InfraSandbox, Sandbox},
+    storage::KeyValueStorage,
+};
+use anyhow::{Context, Result};
+use futures_util::stream;
+use log::{debug, info, warn};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tonic::{Code, Status};
+use warp::{http::StatusCode, reply::with_status, sse, Filter, Reply};
+
+/// The upper bounds of the latency buckets of RPCs and pull stages in seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+#[derive(Clone, Debug, Default)]
+/// Metrics collects the metrics of the runtime.
+pub struct Metrics {
+    /// The number of finished RPCs by their method and status code.
+    rpcs: Arc<Mutex<BTreeMap<(String, String), u64>>>,
+
+    /// The latency histograms of the RPCs by their method.
+    latencies: Arc<Mutex<BTreeMap<String, Histogram>>>,
+
+    /// The total size of all pulled images in bytes.
+    pulled_bytes: Arc<AtomicU64>,
+
+    /// The duration histograms of the pull stages by their name.
+    pull_stages: Arc<Mutex<BTreeMap<String, Histogram>>>,
+
+    /// The size of the manifests, blobs and layers processed by the pull stages by their name.
+    pull_stage_bytes: Arc<Mutex<BTreeMap<String, u64>>>,
+
+    /// The number of creations rejected by quotas by their scope, name and kind.
+    quota_rejections: Arc<Mutex<BTreeMap<(String, String, String), u64>>>,
+
+    /// The number of finished pre-pulls of images by their result.
+    pre_pulls: Arc<Mutex<BTreeMap<String, u64>>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// A histogram with the `LATENCY_BUCKETS`.
+struct Histogram {
+    /// The number of observations per bucket, which are not cumulative.
+    buckets: Vec<u64>,
+
+    /// The sum of all observations in seconds.
+    sum: f64,
+
+    /// The number of all observations.
+    count: u64,
+}
+
+impl Histogram {
+    /// Add the observation of `seconds`.
+    fn observe(&mut self, seconds: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS.len()];
+        }
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|x| seconds <= *x) {
+            self.buckets[i] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+impl Metrics {
+    /// Record an RPC to `method`, which finished with the status `code` after `elapsed`.
+    pub fn observe_rpc(&self, method: &str, code: Code, elapsed: Duration) {
+        if let Ok(mut rpcs) = self.rpcs.lock() {
+            *rpcs
+                .entry((method.into(), format!("{:?}", code)))
+                .or_default() += 1;
+        }
+        if let Ok(mut latencies) = self.latencies.lock() {
+            latencies
+                .entry(method.into())
+                .or_default()
+                .observe(elapsed.as_secs_f64());
+        }
+    }
+
+    /// Record a pulled image of `bytes`.
+    pub fn observe_pull(&self, bytes: u64) {
+        self.pulled_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Record the pull `stage` of `bytes`, which finished after `elapsed`.
+    pub fn observe_pull_stage(&self, stage: PullStage, bytes: u64, elapsed: Duration) {
+        if let Ok(mut stages) = self.pull_stages.lock() {
+            stages
+                .entry(stage.as_ref().into())
+                .or_default()
+                .observe(elapsed.as_secs_f64());
+        }
+        if let Ok(mut stage_bytes) = self.pull_stage_bytes.lock() {
+            *stage_bytes.entry(stage.as_ref().into()).or_default() += bytes;
+        }
+    }
+
+    /// Record a creation rejected by the quota of a `scope`, like `namespace`, with the `name`
+    /// for an object of the `kind`.
+    pub fn observe_quota_rejection(&self, scope: &str, name: &str, kind: &str) {
+        if let Ok(mut rejections) = self.quota_rejections.lock() {
+            *rejections
+                .entry((scope.into(), name.into(), kind.into()))
+                .or_default() += 1;
+        }
+    }
+
+    /// Record a finished pre-pull of an image, which failed unless it was a `success`.
+    pub fn observe_pre_pull(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        if let Ok(mut pre_pulls) = self.pre_pulls.lock() {
+            *pre_pulls.entry(result.into()).or_default() += 1;
+        }
+    }
+
+    /// Render the metrics in the Prometheus text format, including the number of stored
+    /// `sandboxes` and `containers`.
+    pub fn render(&self, sandboxes: usize, containers: usize) -> String {
+        let mut out = String::new();
+        header(
+            &mut out,
+            "cri_rpc_requests_total",
+            "counter",
+            "The number of handled RPCs by method and status code.",
+        );
+        if let Ok(rpcs) = self.rpcs.lock() {
+            for ((method, code), count) in rpcs.iter() {
+                writeln!(
+                    out,
+                    "cri_rpc_requests_total{{method=\"{}\",code=\"{}\"}} {}",
+                    method, code, count
+                )
+                .ok();
+            }
+        }
+
+        header(
+            &mut out,
+            "cri_rpc_duration_seconds",
+            "histogram",
+            "The latency of handled RPCs by method.",
+        );
+        if let Ok(latencies) = self.latencies.lock() {
+            for (method, histogram) in latencies.iter() {
+                write_histogram(
+                    &mut out,
+                    "cri_rpc_duration_seconds",
+                    ("method", method),
+                    histogram,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "cri_image_pull_bytes_total",
+            "counter",
+            "The total size of all pulled images in bytes.",
+        );
+        writeln!(
+            out,
+            "cri_image_pull_bytes_total {}",
+            self.pulled_bytes.load(Ordering::Relaxed)
+        )
+        .ok();
+
+        header(
+            &mut out,
+            "cri_image_pull_stage_duration_seconds",
+            "histogram",
+            "The duration of the resolve, fetch and unpack stages of image pulls by stage.",
+        );
+        if let Ok(stages) = self.pull_stages.lock() {
+            for (stage, histogram) in stages.iter() {
+                write_histogram(
+                    &mut out,
+                    "cri_image_pull_stage_duration_seconds",
+                    ("stage", stage),
+                    histogram,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "cri_image_pull_stage_bytes_total",
+            "counter",
+            "The size of the manifests, blobs and layers processed by image pull stages in bytes.",
+        );
+        if let Ok(stage_bytes) = self.pull_stage_bytes.lock() {
+            for (stage, bytes) in stage_bytes.iter() {
+                writeln!(
+                    out,
+                    "cri_image_pull_stage_bytes_total{{stage=\"{}\"}} {}",
+                    stage, bytes
+                )
+                .ok();
+            }
+        }
+
+        header(
+            &mut out,
+            "cri_image_pre_pulls_total",
+            "counter",
+            "The number of finished pre-pulls of images by result.",
+        );
+        if let Ok(pre_pulls) = self.pre_pulls.lock() {
+            for (result, count) in pre_pulls.iter() {
+                writeln!(
+                    out,
+                    "cri_image_pre_pulls_total{{result=\"{}\"}} {}",
+                    result, count
+                )
+                .ok();
+            }
+        }
+
+        header(
+            &mut out,
+            "cri_quota_rejections_total",
+            "counter",
+            "The number of creations rejected by quotas by scope, name and kind.",
+        );
+        if let Ok(rejections) = self.quota_rejections.lock() {
+            for ((scope, name, kind), count) in rejections.iter() {
+                writeln!(
+                    out,
+                    "cri_quota_rejections_total{{scope=\"{}\",name=\"{}\",kind=\"{}\"}} {}",
+                    scope, name, kind, count
+                )
+                .ok();
+            }
+        }
+
+        header(
+            &mut out,
+            "cri_pod_sandboxes",
+            "gauge",
+            "The number of pod sandboxes.",
+        );
+        writeln!(out, "cri_pod_sandboxes {}", sandboxes).ok();
+
+        header(
+            &mut out,
+            "cri_containers",
+            "gauge",
+            "The number of containers.",
+        );
+        writeln!(out, "cri_containers {}", containers).ok();
+        out
+    }
+}
+
+/// Render the counters of the network `interfaces` by the ID of their pod sandbox in the
+/// Prometheus text format.
+pub fn render_network(interfaces: &[(String, Vec<InterfaceStats>)]) -> String {
+    let counters: &[(&str, &str, fn(&InterfaceStats) -> u64)] = &[
+        ("receive_bytes", "bytes received", InterfaceStats::rx_bytes),
+        (
+            "receive_packets",
+            "packets received",
+            InterfaceStats::rx_packets,
+        ),
+        (
+            "receive_errors",
+            "receive errors",
+            InterfaceStats::rx_errors,
+        ),
+        (
+            "transmit_bytes",
+            "bytes transmitted",
+            InterfaceStats::tx_bytes,
+        ),
+        (
+            "transmit_packets",
+            "packets transmitted",
+            InterfaceStats::tx_packets,
+        ),
+        (
+            "transmit_errors",
+            "transmit errors",
+            InterfaceStats::tx_errors,
+        ),
+    ];
+    let mut out = String::new();
+    for (counter, help, value) in counters {
+        let name = format!("cri_pod_network_{}_total", counter);
+        header(
+            &mut out,
+            &name,
+            "counter",
+            &format!(
+                "The number of {} by pod sandbox and network interface.",
+                help
+            ),
+        );
+        for (id, stats) in interfaces {
+            for interface in stats {
+                writeln!(
+                    out,
+                    "{}{{pod_sandbox_id=\"{}\",interface=\"{}\"}} {}",
+                    name,
+                    id,
+                    interface.name(),
+                    value(interface)
+                )
+                .ok();
+            }
+        }
+    }
+    out
+}
+
+/// Write the help and type header of the metric `name` into `out`.
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind).ok();
+}
+
+/// Write the samples of the `histogram` of the metric `name` with the `label` into `out`.
+fn write_histogram(out: &mut String, name: &str, label: (&str, &str), histogram: &Histogram) {
+    let (key, value) = label;
+    let mut cumulative = 0;
+    for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+        cumulative += count;
+        writeln!(
+            out,
+            "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}",
+            name, key, value, bound, cumulative
+        )
+        .ok();
+    }
+    writeln!(
+        out,
+        "{0}_bucket{{{1}=\"{2}\",le=\"+Inf\"}} {3}\n\
+         {0}_sum{{{1}=\"{2}\"}} {4}\n\
+         {0}_count{{{1}=\"{2}\"}} {3}",
+        name, key, value, histogram.count, histogram.sum
+    )
+    .ok();
+}
+
+/// Serve the metrics and the container logs of the `cri_service` via HTTP on `address` until
+/// the server fails.
+pub async fn serve<S: KeyValueStorage>(
+    address: SocketAddr,
+    cri_service: CRIService<S>,
+) -> Result<()> {
+    let service = cri_service.clone();
+    let metrics = warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || scrape(&service));
+    let logs = warp::path!("logs" / String)
+        .and(warp::get())
+        .and_then(move |id| follow_log(cri_service.clone(), id));
+    let routes = metrics.or(logs);
+    let (address, server) = warp::serve(routes)
+        .try_bind_ephemeral(address)
+        .context("bind metrics server")?;
+    info!("Metrics server listening on http://{}/metrics", address);
+    server.await;
+    Ok(())
+}
+
+/// Follow the log of the container `id` of the `cri_service` from its beginning.
+async fn follow_log<S: KeyValueStorage>(
+    cri_service: CRIService<S>,
+    id: String,
+) -> Result<Box<dyn Reply>, Infallible> {
+    let follower = match cri_service.container_log_path(&id) {
+        Ok(path) => LogFollower::open(&path)
+            .await
+            .map_err(|e| Status::not_found(format!("open log: {:#}", e))),
+        Err(e) => Err(e),
+    };
+    let follower = match follower {
+        Ok(follower) => follower,
+        Err(e) => {
+            let status = match e.code() {
+                Code::NotFound => StatusCode::NOT_FOUND,
+                Code::InvalidArgument => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            return Ok(Box::new(with_status(e.message().to_string(), status)));
+        }
+    };
+    debug!("Following log of container {}", id);
+
+    // The stream ends after the first failure, which is sent to the client as error
+    let lines = stream::unfold(Some(follower), |follower| async move {
+        let mut follower = follower?;
+        match follower.next_line().await {
+            Ok(line) => Some((Ok(sse::data(line)), Some(follower))),
+            Err(e) => Some((
+                Err(io::Error::new(io::ErrorKind::Other, format!("{:#}", e))),
+                None,
+            )),
+        }
+    });
+    Ok(Box::new(sse::reply(lines)))
+}
+
+/// Render the current metrics of the `cri_service`.
+fn scrape<S: KeyValueStorage>(cri_service: &CRIService<S>) -> Box<dyn Reply> {
+    let mut storage = cri_service.storage().clone();
+    let counts = storage
+        .scan_prefix::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key_prefix())
+        .context("list pod sandboxes")
+        .and_then(|sandboxes| {
+            let containers = storage
+                .scan_prefix::<_, Container>(Container::key_prefix())
+                .context("list containers")?;
+            Ok((sandboxes, containers.len()))
+        });
+    match counts {
+        Ok((sandboxes, containers)) => {
+            // Sandboxes whose namespace is gone in the meantime are skipped
+            let interfaces: Vec<_> = sandboxes
+                .iter()
+                .filter_map(|sandbox| {
+                    let status = storage
+                        .get::<_, NetworkStatus>(NetworkStatus::key(sandbox.id()))
+                        .ok()??;
+                    stats::read(status.netns())
+                        .map_err(|e| debug!("Skipping network stats of {}: {:#}", sandbox.id(), e))
+                        .ok()
+                        .map(|x| (sandbox.id().to_string(), x))
+                })
+                .collect();
+            let mut out = cri_service.metrics().render(sandboxes.len(), containers);
+            out.push_str(&render_network(&interfaces));
+            Box::new(out)
+        }
+        Err(e) => {
+            warn!("Unable to collect metrics: {:#}", e);
+            Box::new(with_status(
+                format!("{:#}", e),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service,
+        runtime_service::run_pod_sandbox::tests::new_pod_sandbox,
+    };
+
+    #[test]
+    fn render_success() {
+        let sut = Metrics::default();
+        sut.observe_rpc("Version", Code::Ok, Duration::from_millis(20));
+        sut.observe_rpc("Version", Code::Ok, Duration::from_secs(120));
+        sut.observe_rpc("Version", Code::NotFound, Duration::from_millis(1));
+        sut.observe_pull(1024);
+        sut.observe_pull_stage(PullStage::Fetch, 512, Duration::from_millis(200));
+        sut.observe_pull_stage(PullStage::Fetch, 256, Duration::from_secs(3));
+        sut.observe_pull_stage(PullStage::Unpack, 512, Duration::from_secs(1));
+        sut.observe_quota_rejection("namespace", "tenant", "container");
+        sut.observe_pre_pull(true);
+        sut.observe_pre_pull(false);
+        sut.observe_pre_pull(true);
+
+        let out = sut.render(2, 3);
+        for line in &[
+            "# TYPE cri_rpc_requests_total counter",
+            "cri_rpc_requests_total{method=\"Version\",code=\"Ok\"} 2",
+            "cri_rpc_requests_total{method=\"Version\",code=\"NotFound\"} 1",
+            "cri_rpc_duration_seconds_bucket{method=\"Version\",le=\"0.005\"} 1",
+            "cri_rpc_duration_seconds_bucket{method=\"Version\",le=\"0.025\"} 2",
+            "cri_rpc_duration_seconds_bucket{method=\"Version\",le=\"60\"} 2",
+            "cri_rpc_duration_seconds_bucket{method=\"Version\",le=\"+Inf\"} 3",
+            "cri_rpc_duration_seconds_count{method=\"Version\"} 3",
+            "cri_image_pull_bytes_total 1024",
+            "cri_image_pull_stage_duration_seconds_bucket{stage=\"fetch\",le=\"0.25\"} 1",
+            "cri_image_pull_stage_duration_seconds_bucket{stage=\"fetch\",le=\"5\"} 2",
+            "cri_image_pull_stage_duration_seconds_count{stage=\"unpack\"} 1",
+            "cri_image_pull_stage_bytes_total{stage=\"fetch\"} 768",
+            "cri_image_pull_stage_bytes_total{stage=\"unpack\"} 512",
+            "cri_image_pre_pulls_total{result=\"failure\"} 1",
+            "cri_image_pre_pulls_total{result=\"success\"} 2",
+            "cri_quota_rejections_total{scope=\"namespace\",name=\"tenant\",kind=\"container\"} 1",
+            "cri_pod_sandboxes 2",
+            "cri_containers 3",
+        ] {
+            assert!(out.lines().any(|x| &x == line), "missing {}", line);
+        }
+    }
+
+    #[test]
+    fn render_network_success() -> Result<()> {
+        let interfaces =
+            stats::parse("header\nheader\n  eth0: 2048 16 1 0 0 0 0 0 1024 8 2 0 0 0 0 0\n")?;
+        let out = render_network(&[("id".into(), interfaces)]);
+        for line in &[
+            "# TYPE cri_pod_network_receive_bytes_total counter",
+            "cri_pod_network_receive_bytes_total{pod_sandbox_id=\"id\",interface=\"eth0\"} 2048",
+            "cri_pod_network_receive_packets_total{pod_sandbox_id=\"id\",interface=\"eth0\"} 16",
+            "cri_pod_network_transmit_errors_total{pod_sandbox_id=\"id\",interface=\"eth0\"} 2",
+        ] {
+            assert!(out.lines().any(|x| &x == line), "missing {}", line);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scrape_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        new_pod_sandbox(&sut).await?;
+
+        let routes = warp::path("metrics").map(move || scrape(&sut));
+        let res = warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = String::from_utf8(res.body().to_vec())?;
+        assert!(body.contains("cri_pod_sandboxes 1\n"));
+        assert!(body.contains("cri_rpc_requests_total{method=\"RunPodSandbox\",code=\"Ok\"} 1\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follow_log_fail_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let routes = warp::path!("logs" / String).and_then(move |id| follow_log(sut.clone(), id));
+        let res = warp::test::request()
+            .path("/logs/unknown")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+}
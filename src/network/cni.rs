@@ -0,0 +1,770 @@
+//! Execution of CNI plugins according to the CNI specification.
+
+use crate::sandbox::{PortMapping, SandboxData};
+use anyhow::{bail, Context, Result};
+use getset::Getters;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    env, fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// The name of the network interface inside of pod sandboxes.
+pub const INTERFACE: &str = "eth0";
+
+/// The file extensions of CNI network configurations.
+const CONFIG_EXTENSIONS: &[&str] = &["conf", "conflist", "json"];
+
+/// The CNI capability of plugins exposing ports of the sandbox on the host.
+const PORT_MAPPINGS_CAPABILITY: &str = "portMappings";
+
+/// The CNI capability of IPAM plugins assigning requested IP addresses.
+const IPS_CAPABILITY: &str = "ips";
+
+#[derive(Clone, Debug, Getters)]
+/// CniNetwork is a CNI network configuration list, whose plugins get executed in order to attach
+/// a pod sandbox to the network.
+pub struct CniNetwork {
+    #[get = "pub"]
+    /// The name of the network.
+    name: String,
+
+    #[get = "pub"]
+    /// The CNI specification version of the configuration.
+    cni_version: String,
+
+    #[get = "pub"]
+    /// The raw configuration the network has been parsed from.
+    config: String,
+
+    /// The configurations of the plugins in execution order.
+    plugins: Vec<Map<String, Value>>,
+
+    /// The directories searched for the plugin binaries.
+    plugin_dirs: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+/// A plugin of a network configuration.
+pub struct PluginInfo {
+    /// The type of the plugin, which is the name of its binary.
+    pub r#type: String,
+
+    /// The path of the plugin binary, which is `None` if it has not been found.
+    pub binary: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+/// The error written by a failed plugin.
+struct PluginError {
+    /// The error message.
+    msg: String,
+
+    #[serde(default)]
+    /// Additional details of the error.
+    details: String,
+}
+
+impl CniNetwork {
+    /// Load the first valid network configuration in lexical order from `config_dir`. Returns
+    /// `None` if the directory does not contain any valid configuration.
+    pub fn load(config_dir: &Path, plugin_dirs: &[PathBuf]) -> Result<Option<Self>> {
+        if !config_dir.exists() {
+            return Ok(None);
+        }
+        let mut paths = fs::read_dir(config_dir)
+            .and_then(|x| {
+                x.map(|x| x.map(|x| x.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .with_context(|| format!("read CNI config directory {}", config_dir.display()))?;
+        paths.retain(|x| {
+            x.extension()
+                .and_then(|x| x.to_str())
+                .map_or(false, |x| CONFIG_EXTENSIONS.contains(&x))
+        });
+        paths.sort();
+
+        for path in paths {
+            let config = fs::read_to_string(&path)
+                .with_context(|| format!("read CNI config {}", path.display()))?;
+            match Self::parse(&config, plugin_dirs) {
+                Ok(network) => {
+                    debug!("Using CNI network {} of {}", network.name, path.display());
+                    return Ok(Some(network));
+                }
+                Err(e) => warn!("Skipping CNI config {}: {:#}", path.display(), e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Parse a network configuration list or a single network configuration from `config`.
+    pub fn parse(config: &str, plugin_dirs: &[PathBuf]) -> Result<Self> {
+        let mut value: Map<String, Value> =
+            serde_json::from_str(config).context("deserialize network configuration")?;
+        let name = string(&value, "name")?;
+        let cni_version = string(&value, "cniVersion")?;
+
+        let plugins = match value.remove("plugins") {
+            Some(Value::Array(plugins)) => plugins
+                .into_iter()
+                .map(|x| match x {
+                    Value::Object(x) => Ok(x),
+                    _ => bail!("plugin configuration is not an object"),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Some(_) => bail!("plugins of network configuration are not a list"),
+            None => vec![value],
+        };
+        if plugins.is_empty() {
+            bail!("network configuration contains no plugins")
+        }
+        for plugin in &plugins {
+            string(plugin, "type")?;
+        }
+
+        Ok(Self {
+            name,
+            cni_version,
+            config: config.into(),
+            plugins,
+            plugin_dirs: plugin_dirs.into(),
+        })
+    }
+
+    /// Attach the `sandbox` to the network inside the network namespace `netns`, whereas the
+    /// addresses in CIDR notation of `ips` get requested from the plugins supporting it. Returns
+    /// the raw result of the last plugin and the IP addresses assigned by it.
+    pub async fn add(
+        &self,
+        sandbox: &SandboxData,
+        netns: &Path,
+        ips: &[String],
+    ) -> Result<(String, Vec<IpAddr>)> {
+        if !sandbox.port_mappings().is_empty()
+            && !self
+                .plugins
+                .iter()
+                .any(|x| has_capability(x, PORT_MAPPINGS_CAPABILITY))
+        {
+            warn!(
+                "Ignoring port mappings of pod sandbox {}, because no plugin of network {} supports them",
+                sandbox.id(),
+                self.name
+            );
+        }
+        let mut result = None;
+        for plugin in &self.plugins {
+            let next = self
+                .exec("ADD", plugin, sandbox, netns, ips, result.as_ref())
+                .await?
+                .with_context(|| {
+                    format!("no result of CNI plugin {}", string_or(plugin, "type"))
+                })?;
+            result = Some(next);
+        }
+        let result = result.context("network configuration contains no plugins")?;
+        let ips = ips(&result)?;
+        Ok((result.to_string(), ips))
+    }
+
+    /// Detach the `sandbox` from the network inside the network namespace `netns`, whereas the
+    /// `prev_result` of attaching it is passed to the plugins. The plugins are executed in reverse
+    /// order and all of them run, even if a previous one failed.
+    pub async fn del(
+        &self,
+        sandbox: &SandboxData,
+        netns: &Path,
+        prev_result: Option<&str>,
+    ) -> Result<()> {
+        let prev_result: Option<Value> = prev_result
+            .map(serde_json::from_str)
+            .transpose()
+            .context("deserialize previous result")?;
+
+        let mut res = Ok(());
+        for plugin in self.plugins.iter().rev() {
+            if let Err(e) = self
+                .exec("DEL", plugin, sandbox, netns, &[], prev_result.as_ref())
+                .await
+            {
+                warn!("Unable to detach pod sandbox {}: {:#}", sandbox.id(), e);
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        res
+    }
+
+    /// Retrieve the plugins of the network in execution order together with their binaries.
+    pub fn plugins(&self) -> Vec<PluginInfo> {
+        self.plugins
+            .iter()
+            .map(|x| {
+                let typ = string_or(x, "type");
+                PluginInfo {
+                    r#type: typ.into(),
+                    binary: self.binary(typ),
+                }
+            })
+            .collect()
+    }
+
+    /// Find the binary of the plugin `typ` in the plugin directories.
+    fn binary(&self, typ: &str) -> Option<PathBuf> {
+        self.plugin_dirs
+            .iter()
+            .map(|x| x.join(typ))
+            .find(|x| x.is_file())
+    }
+
+    /// Execute the `plugin` with the CNI `command` and return its result, which is `None` if the
+    /// plugin did not write one. The requested `ips` are only passed to plugins supporting them.
+    async fn exec(
+        &self,
+        command: &str,
+        plugin: &Map<String, Value>,
+        sandbox: &SandboxData,
+        netns: &Path,
+        ips: &[String],
+        prev_result: Option<&Value>,
+    ) -> Result<Option<Value>> {
+        let typ = string_or(plugin, "type");
+        let binary = self
+            .binary(typ)
+            .with_context(|| format!("CNI plugin {} not found in {:?}", typ, self.plugin_dirs))?;
+
+        let mut config = plugin.clone();
+        config.insert("name".into(), self.name.clone().into());
+        config.insert("cniVersion".into(), self.cni_version.clone().into());
+        if let Some(prev_result) = prev_result {
+            config.insert("prevResult".into(), prev_result.clone());
+        }
+        // Runtime configuration is only passed to plugins declaring the matching capability
+        let mut runtime_config = Map::new();
+        if has_capability(plugin, PORT_MAPPINGS_CAPABILITY) && !sandbox.port_mappings().is_empty() {
+            runtime_config.insert(
+                PORT_MAPPINGS_CAPABILITY.into(),
+                port_mappings(sandbox.port_mappings()),
+            );
+        }
+        if has_capability(plugin, IPS_CAPABILITY) && !ips.is_empty() {
+            runtime_config.insert(IPS_CAPABILITY.into(), json!(ips));
+        }
+        if !runtime_config.is_empty() {
+            config.insert("runtimeConfig".into(), runtime_config.into());
+        }
+        let input = serde_json::to_vec(&config).context("serialize plugin configuration")?;
+
+        let args = format!(
+            "IgnoreUnknown=1;K8S_POD_NAMESPACE={};K8S_POD_NAME={};K8S_POD_INFRA_CONTAINER_ID={};K8S_POD_UID={}",
+            sandbox.namespace(),
+            sandbox.name(),
+            sandbox.id(),
+            sandbox.uid()
+        );
+        let path = env::join_paths(&self.plugin_dirs).context("join CNI plugin directories")?;
+        debug!(
+            "Running CNI plugin {} {} for pod sandbox {}",
+            typ,
+            command,
+            sandbox.id()
+        );
+
+        let mut child = Command::new(&binary)
+            .env("CNI_COMMAND", command)
+            .env("CNI_CONTAINERID", sandbox.id())
+            .env("CNI_NETNS", netns)
+            .env("CNI_IFNAME", INTERFACE)
+            .env("CNI_ARGS", args)
+            .env("CNI_PATH", path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("run CNI plugin {}", binary.display()))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(&input)
+                .await
+                .with_context(|| format!("write configuration of CNI plugin {}", typ))?;
+        }
+        let output = child
+            .wait_with_output()
+            .await
+            .with_context(|| format!("wait for CNI plugin {}", typ))?;
+
+        if !output.status.success() {
+            let message = match serde_json::from_slice::<PluginError>(&output.stdout) {
+                Ok(e) if e.details.is_empty() => e.msg,
+                Ok(e) => format!("{}: {}", e.msg, e.details),
+                Err(_) => String::from_utf8_lossy(&output.stderr).trim().into(),
+            };
+            bail!("CNI plugin {} {} failed: {}", typ, command, message)
+        }
+        if output.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
+        serde_json::from_slice(&output.stdout)
+            .map(Some)
+            .with_context(|| format!("deserialize result of CNI plugin {}", typ))
+    }
+}
+
+#[derive(Deserialize)]
+/// The result of the CNI `VERSION` command.
+struct VersionResult {
+    #[serde(rename = "supportedVersions")]
+    /// The specification versions supported by the plugin.
+    supported_versions: Vec<String>,
+}
+
+/// Retrieve the CNI specification versions supported by the plugin binary `name`, which is
+/// searched in `plugin_dirs`.
+pub async fn plugin_versions(plugin_dirs: &[PathBuf], name: &str) -> Result<Vec<String>> {
+    let binary = plugin_dirs
+        .iter()
+        .map(|x| x.join(name))
+        .find(|x| x.is_file())
+        .with_context(|| format!("CNI plugin {} not found in {:?}", name, plugin_dirs))?;
+    let mut child = Command::new(&binary)
+        .env("CNI_COMMAND", "VERSION")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("run CNI plugin {}", binary.display()))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(br#"{"cniVersion":"0.4.0"}"#)
+            .await
+            .with_context(|| format!("write version request of CNI plugin {}", name))?;
+    }
+    let output = child
+        .wait_with_output()
+        .await
+        .with_context(|| format!("wait for CNI plugin {}", name))?;
+    if !output.status.success() {
+        bail!(
+            "CNI plugin {} VERSION failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+    serde_json::from_slice::<VersionResult>(&output.stdout)
+        .map(|x| x.supported_versions)
+        .with_context(|| format!("deserialize version of CNI plugin {}", name))
+}
+
+/// Retrieve the string `key` of the configuration `value`.
+fn string(value: &Map<String, Value>, key: &str) -> Result<String> {
+    value
+        .get(key)
+        .and_then(Value::as_str)
+        .map(Into::into)
+        .with_context(|| format!("no {} in network configuration", key))
+}
+
+/// Retrieve the string `key` of the configuration `value`, which is empty if it does not exist.
+fn string_or<'a>(value: &'a Map<String, Value>, key: &str) -> &'a str {
+    value.get(key).and_then(Value::as_str).unwrap_or_default()
+}
+
+/// Check if the plugin `config` declares the CNI `capability`.
+fn has_capability(config: &Map<String, Value>, capability: &str) -> bool {
+    config
+        .get("capabilities")
+        .and_then(|x| x.get(capability))
+        .and_then(Value::as_bool)
+        .unwrap_or_default()
+}
+
+/// Encode the `mappings` as runtime configuration of the `portMappings` capability.
+fn port_mappings(mappings: &[PortMapping]) -> Value {
+    mappings
+        .iter()
+        .map(|x| {
+            let mut mapping = json!({
+                "hostPort": x.host_port,
+                "containerPort": x.container_port,
+                "protocol": x.protocol,
+            });
+            if !x.host_ip.is_empty() {
+                mapping["hostIP"] = x.host_ip.clone().into();
+            }
+            mapping
+        })
+        .collect()
+}
+
+/// Retrieve the IP addresses in CIDR notation of the raw plugin `result`, which can be requested
+/// again via the `ips` capability.
+pub fn addresses(result: &str) -> Result<Vec<String>> {
+    let result: Value = serde_json::from_str(result).context("deserialize plugin result")?;
+    Ok(cidrs(&result).into_iter().map(Into::into).collect())
+}
+
+/// Retrieve the IP addresses in CIDR notation of a plugin `result`. Results of the CNI
+/// specification up to 0.2.0 use the `ip4` and `ip6` fields instead of the `ips` list.
+fn cidrs(result: &Value) -> Vec<&str> {
+    match result.get("ips").and_then(Value::as_array) {
+        Some(ips) => ips
+            .iter()
+            .filter_map(|x| x.get("address").and_then(Value::as_str))
+            .collect(),
+        None => ["ip4", "ip6"]
+            .iter()
+            .filter_map(|x| result.get(x))
+            .filter_map(|x| x.get("ip").and_then(Value::as_str))
+            .collect(),
+    }
+}
+
+/// Retrieve the IP addresses of a plugin `result`.
+fn ips(result: &Value) -> Result<Vec<IpAddr>> {
+    cidrs(result)
+        .into_iter()
+        .map(|x| {
+            x.split('/')
+                .next()
+                .unwrap_or_default()
+                .parse()
+                .with_context(|| format!("parse IP address {}", x))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::sandbox::SandboxDataBuilder;
+    use anyhow::format_err;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    /// The network configuration list using the fake plugin twice.
+    pub const CONFIG_LIST: &str = r#"{
+        "cniVersion": "0.4.0",
+        "name": "test",
+        "plugins": [{"type": "fake", "first": true}, {"type": "fake"}]
+    }"#;
+
+    /// Write a fake CNI plugin into `dir`, which logs its command and configuration into
+    /// `plugin.log`, assigns `10.1.0.5` on `ADD` and supports the versions `0.3.1` and `0.4.0`.
+    /// A plugin configuration containing `fail` makes it fail.
+    pub fn fake_plugin(dir: &Path) -> Result<()> {
+        let path = dir.join("fake");
+        fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\n\
+                 config=$(cat)\n\
+                 echo \"$CNI_COMMAND $CNI_CONTAINERID $CNI_IFNAME $CNI_NETNS $config\" >> {log}\n\
+                 case \"$config\" in\n\
+                 *fail*) echo '{{\"code\":100,\"msg\":\"failure\"}}'; exit 1 ;;\n\
+                 *hang*) [ \"$CNI_COMMAND\" = ADD ] && exec sleep 30 ;;\n\
+                 esac\n\
+                 if [ \"$CNI_COMMAND\" = ADD ]; then\n\
+                 echo '{{\"cniVersion\":\"0.4.0\",\"ips\":[{{\"version\":\"4\",\"address\":\"10.1.0.5/24\"}}]}}'\n\
+                 elif [ \"$CNI_COMMAND\" = VERSION ]; then\n\
+                 echo '{{\"cniVersion\":\"0.4.0\",\"supportedVersions\":[\"0.3.1\",\"0.4.0\"]}}'\n\
+                 fi\n",
+                log = dir.join("plugin.log").display()
+            ),
+        )?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    /// Retrieve the lines the fake plugin in `dir` has logged.
+    pub fn fake_plugin_log(dir: &Path) -> Result<Vec<String>> {
+        Ok(fs::read_to_string(dir.join("plugin.log"))
+            .unwrap_or_default()
+            .lines()
+            .map(Into::into)
+            .collect())
+    }
+
+    fn new_sandbox_data() -> Result<SandboxData> {
+        SandboxDataBuilder::default()
+            .id("id")
+            .uid("uid")
+            .name("name")
+            .namespace("namespace")
+            .attempt(0u32)
+            .build()
+            .map_err(|e| format_err!("build sandbox data: {}", e))
+    }
+
+    #[test]
+    fn parse_success() -> Result<()> {
+        let sut = CniNetwork::parse(CONFIG_LIST, &[])?;
+        assert_eq!(sut.name(), "test");
+        assert_eq!(sut.cni_version(), "0.4.0");
+        assert_eq!(sut.plugins.len(), 2);
+
+        let sut = CniNetwork::parse(
+            r#"{"cniVersion": "0.3.1", "name": "single", "type": "bridge"}"#,
+            &[],
+        )?;
+        assert_eq!(sut.name(), "single");
+        assert_eq!(sut.plugins.len(), 1);
+        assert_eq!(string(&sut.plugins[0], "type")?, "bridge");
+        Ok(())
+    }
+
+    #[test]
+    fn plugins_success() -> Result<()> {
+        let dir = tempdir()?;
+        fake_plugin(dir.path())?;
+        let sut = CniNetwork::parse(
+            r#"{"cniVersion": "0.4.0", "name": "test", "plugins": [{"type": "fake"}, {"type": "missing"}]}"#,
+            &[dir.path().into()],
+        )?;
+
+        assert_eq!(
+            sut.plugins(),
+            vec![
+                PluginInfo {
+                    r#type: "fake".into(),
+                    binary: Some(dir.path().join("fake")),
+                },
+                PluginInfo {
+                    r#type: "missing".into(),
+                    binary: None,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_failure() {
+        for config in &[
+            "",
+            r#"{"cniVersion": "0.4.0", "type": "bridge"}"#,
+            r#"{"name": "test", "type": "bridge"}"#,
+            r#"{"cniVersion": "0.4.0", "name": "test"}"#,
+            r#"{"cniVersion": "0.4.0", "name": "test", "plugins": []}"#,
+            r#"{"cniVersion": "0.4.0", "name": "test", "plugins": [{}]}"#,
+        ] {
+            assert!(CniNetwork::parse(config, &[]).is_err(), "{}", config);
+        }
+    }
+
+    #[test]
+    fn load_success() -> Result<()> {
+        let dir = tempdir()?;
+        assert!(CniNetwork::load(&dir.path().join("missing"), &[])?.is_none());
+        assert!(CniNetwork::load(dir.path(), &[])?.is_none());
+
+        fs::write(dir.path().join("00-invalid.conf"), "{}")?;
+        fs::write(dir.path().join("05-ignored.txt"), CONFIG_LIST)?;
+        fs::write(dir.path().join("10-test.conflist"), CONFIG_LIST)?;
+        fs::write(
+            dir.path().join("20-other.conf"),
+            r#"{"cniVersion": "0.4.0", "name": "other", "type": "bridge"}"#,
+        )?;
+        let sut = CniNetwork::load(dir.path(), &[])?.context("no network")?;
+        assert_eq!(sut.name(), "test");
+        assert_eq!(sut.config(), CONFIG_LIST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_del_success() -> Result<()> {
+        let dir = tempdir()?;
+        fake_plugin(dir.path())?;
+        let sut = CniNetwork::parse(
+            CONFIG_LIST,
+            &[dir.path().join("missing"), dir.path().into()],
+        )?;
+        let data = new_sandbox_data()?;
+        let netns = Path::new("/run/netns/id");
+
+        let (result, ips) = sut.add(&data, netns, &[]).await?;
+        assert_eq!(ips, vec!["10.1.0.5".parse::<IpAddr>()?]);
+        sut.del(&data, netns, Some(&result)).await?;
+
+        let log = fake_plugin_log(dir.path())?;
+        assert_eq!(log.len(), 4);
+        assert!(log[0].starts_with("ADD id eth0 /run/netns/id "));
+        assert!(log[0].contains(r#""first":true"#));
+        assert!(!log[0].contains("prevResult"));
+        assert!(log[1].starts_with("ADD "));
+        assert!(log[1].contains("prevResult"));
+        assert!(log[2].starts_with("DEL "));
+        assert!(!log[2].contains("first"));
+        assert!(log[2].contains("prevResult"));
+        assert!(log[3].contains(r#""first":true"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_success_port_mappings() -> Result<()> {
+        let dir = tempdir()?;
+        fake_plugin(dir.path())?;
+        let sut = CniNetwork::parse(
+            r#"{"cniVersion": "0.4.0", "name": "test", "plugins": [
+                {"type": "fake"},
+                {"type": "fake", "capabilities": {"portMappings": true}}
+            ]}"#,
+            &[dir.path().into()],
+        )?;
+        let data = SandboxDataBuilder::default()
+            .id("id")
+            .uid("uid")
+            .name("name")
+            .namespace("namespace")
+            .attempt(0u32)
+            .port_mappings(vec![PortMapping {
+                protocol: "udp".into(),
+                container_port: 53,
+                host_port: 5353,
+                host_ip: "127.0.0.1".into(),
+            }])
+            .build()
+            .map_err(|e| format_err!("build sandbox data: {}", e))?;
+        let netns = Path::new("/run/netns/id");
+
+        let (result, _) = sut.add(&data, netns, &[]).await?;
+        sut.del(&data, netns, Some(&result)).await?;
+
+        let log = fake_plugin_log(dir.path())?;
+        assert_eq!(log.len(), 4);
+        assert!(!log[0].contains("runtimeConfig"));
+        for line in &[&log[1], &log[2]] {
+            assert!(line.contains(
+                r#""runtimeConfig":{"portMappings":[{"containerPort":53,"hostIP":"127.0.0.1","hostPort":5353,"protocol":"udp"}]}"#
+            ), "{}", line);
+        }
+        assert!(!log[3].contains("runtimeConfig"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_success_ips() -> Result<()> {
+        let dir = tempdir()?;
+        fake_plugin(dir.path())?;
+        let sut = CniNetwork::parse(
+            r#"{"cniVersion": "0.4.0", "name": "test", "plugins": [
+                {"type": "fake"},
+                {"type": "fake", "capabilities": {"ips": true}}
+            ]}"#,
+            &[dir.path().into()],
+        )?;
+        let data = new_sandbox_data()?;
+        let netns = Path::new("/run/netns/id");
+
+        sut.add(&data, netns, &["10.1.0.5/24".into()]).await?;
+
+        let log = fake_plugin_log(dir.path())?;
+        assert_eq!(log.len(), 2);
+        assert!(!log[0].contains("runtimeConfig"));
+        assert!(
+            log[1].contains(r#""runtimeConfig":{"ips":["10.1.0.5/24"]}"#),
+            "{}",
+            log[1]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_failure() -> Result<()> {
+        let dir = tempdir()?;
+        fake_plugin(dir.path())?;
+        let data = new_sandbox_data()?;
+        let netns = Path::new("/run/netns/id");
+
+        let sut = CniNetwork::parse(
+            r#"{"cniVersion": "0.4.0", "name": "test", "type": "fake", "fail": true}"#,
+            &[dir.path().into()],
+        )?;
+        let err = sut.add(&data, netns, &[]).await.err().context("no error")?;
+        assert!(format!("{:#}", err).contains("failure"));
+
+        let sut = CniNetwork::parse(
+            r#"{"cniVersion": "0.4.0", "name": "test", "type": "missing"}"#,
+            &[dir.path().into()],
+        )?;
+        assert!(sut.add(&data, netns, &[]).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn del_failure_runs_all_plugins() -> Result<()> {
+        let dir = tempdir()?;
+        fake_plugin(dir.path())?;
+        let sut = CniNetwork::parse(
+            r#"{
+                "cniVersion": "0.4.0",
+                "name": "test",
+                "plugins": [{"type": "fake"}, {"type": "fake", "fail": true}]
+            }"#,
+            &[dir.path().into()],
+        )?;
+        let data = new_sandbox_data()?;
+
+        assert!(sut
+            .del(&data, Path::new("/run/netns/id"), None)
+            .await
+            .is_err());
+        assert_eq!(fake_plugin_log(dir.path())?.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plugin_versions_success() -> Result<()> {
+        let dir = tempdir()?;
+        fake_plugin(dir.path())?;
+        let plugin_dirs = vec![dir.path().into()];
+
+        let versions = plugin_versions(&plugin_dirs, "fake").await?;
+        assert_eq!(versions, vec!["0.3.1", "0.4.0"]);
+        assert!(plugin_versions(&plugin_dirs, "missing").await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ips_success() -> Result<()> {
+        let result = json!({
+            "ips": [
+                {"version": "4", "address": "10.1.0.5/24"},
+                {"version": "6", "address": "fd00::5/64"},
+            ]
+        });
+        assert_eq!(
+            ips(&result)?,
+            vec!["10.1.0.5".parse::<IpAddr>()?, "fd00::5".parse::<IpAddr>()?]
+        );
+
+        let result = json!({"ip4": {"ip": "10.1.0.6/24"}});
+        assert_eq!(ips(&result)?, vec!["10.1.0.6".parse::<IpAddr>()?]);
+        assert!(ips(&json!({}))?.is_empty());
+        assert!(ips(&json!({"ips": [{"address": "invalid"}]})).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn addresses_success() -> Result<()> {
+        assert_eq!(
+            addresses(r#"{"ips": [{"address": "10.1.0.5/24"}, {"address": "fd00::5/64"}]}"#)?,
+            vec!["10.1.0.5/24", "fd00::5/64"]
+        );
+        assert_eq!(
+            addresses(r#"{"ip4": {"ip": "10.1.0.6/24"}}"#)?,
+            vec!["10.1.0.6/24"]
+        );
+        assert!(addresses("invalid").is_err());
+        Ok(())
+    }
+}
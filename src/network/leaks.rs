@@ -0,0 +1,160 @@
+//! Detection of network resources which outlived their pod sandbox.
+//!
+//! A crash between attaching a sandbox to the network and storing it, or between removing it and
+//! detaching its network, leaves a pinned network namespace or an IP address allocation behind
+//! which nobody releases anymore. Leaked allocations exhaust the address range of the node over
+//! time, so that no new pods can start. The scans cross-reference the pinned namespaces and the
+//! allocations of the host-local IPAM plugin with the stored pod sandboxes. Sandboxes being
+//! created are attached before they get stored, which is why only resources found by two
+//! consecutive scans are considered leaked.
+
+use anyhow::{Context, Result};
+use log::debug;
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// Leak is a network resource without an owning pod sandbox.
+pub enum Leak {
+    /// The pinned network namespace at the path.
+    Netns(PathBuf),
+
+    /// The IP address allocated to the pod sandbox ID.
+    Ip(IpAddr, String),
+}
+
+impl fmt::Display for Leak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Leak::Netns(path) => write!(f, "network namespace {}", path.display()),
+            Leak::Ip(ip, id) => write!(f, "IP address {} of pod sandbox {}", ip, id),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// LeakDetector remembers the resources without owner of the previous scan.
+pub struct LeakDetector {
+    /// The resources without owner found by the previous scan.
+    suspects: HashSet<Leak>,
+}
+
+impl LeakDetector {
+    /// Scan the pinned network namespaces in `netns_dir` and the IP allocations in `ipam_dir`,
+    /// which is the host-local IPAM directory of the network if there is one, for resources whose
+    /// pod sandbox is not part of the `owners`. Returns the resources which have also been found by
+    /// the previous scan.
+    pub fn scan(
+        &mut self,
+        netns_dir: &Path,
+        ipam_dir: Option<&Path>,
+        owners: &HashSet<String>,
+    ) -> Result<Vec<Leak>> {
+        let mut found = HashSet::new();
+        for (name, path) in entries(netns_dir)? {
+            if !owners.contains(&name) {
+                found.insert(Leak::Netns(path));
+            }
+        }
+        let allocations = ipam_dir.map(entries).transpose()?.unwrap_or_default();
+        for (name, path) in allocations {
+            // The directory contains the lock and the last reserved addresses as well
+            let ip = match name.parse::<IpAddr>() {
+                Ok(ip) => ip,
+                Err(_) => continue,
+            };
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) => {
+                    debug!("Skipping IP allocation {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            // Other runtimes sharing the network use longer container IDs
+            let id = content.lines().next().unwrap_or_default().trim();
+            if is_sandbox_id(id) && !owners.contains(id) {
+                found.insert(Leak::Ip(ip, id.into()));
+            }
+        }
+
+        let leaks = found.intersection(&self.suspects).cloned().collect();
+        self.suspects = found;
+        Ok(leaks)
+    }
+}
+
+/// Retrieve the names and paths of the entries of the directory `dir`, which are none if it does
+/// not exist.
+fn entries(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("read dir {}", dir.display()))? {
+        let entry = entry.with_context(|| format!("read entry of dir {}", dir.display()))?;
+        entries.push((entry.file_name().to_string_lossy().into(), entry.path()));
+    }
+    Ok(entries)
+}
+
+/// Check whether the `id` has the format of pod sandbox IDs, which are 16 hex digits.
+fn is_sandbox_id(id: &str) -> bool {
+    id.len() == 16 && id.chars().all(|x| x.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn scan_success() -> Result<()> {
+        let dir = tempdir()?;
+        let (netns, ipam) = (dir.path().join("netns"), dir.path().join("ipam"));
+        fs::create_dir_all(&netns)?;
+        fs::create_dir_all(&ipam)?;
+        for id in &["0123456789abcdef", "fedcba9876543210"] {
+            fs::write(netns.join(id), "")?;
+        }
+        fs::write(ipam.join("10.0.0.2"), "0123456789abcdef\r\neth0")?;
+        fs::write(ipam.join("10.0.0.3"), "fedcba9876543210\r\neth0")?;
+        fs::write(ipam.join("10.0.0.4"), format!("{}\r\neth0", "a".repeat(64)))?;
+        fs::write(ipam.join("last_reserved_ip.0"), "10.0.0.4")?;
+        fs::write(ipam.join("lock"), "")?;
+        let owners = vec!["0123456789abcdef".to_string()].into_iter().collect();
+
+        // Resources are only leaked if the previous scan found them too
+        let mut sut = LeakDetector::default();
+        assert!(sut.scan(&netns, Some(ipam.as_path()), &owners)?.is_empty());
+        let mut leaks = sut.scan(&netns, Some(ipam.as_path()), &owners)?;
+        leaks.sort_by_key(|x| format!("{:?}", x));
+        assert_eq!(
+            leaks,
+            vec![
+                Leak::Ip("10.0.0.3".parse()?, "fedcba9876543210".into()),
+                Leak::Netns(netns.join("fedcba9876543210")),
+            ]
+        );
+
+        // Resources which got an owner in the meantime are not leaked
+        let owners = vec!["fedcba9876543210".to_string()].into_iter().collect();
+        assert!(sut.scan(&netns, Some(ipam.as_path()), &owners)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn scan_success_missing_dirs() -> Result<()> {
+        let dir = tempdir()?;
+        let mut sut = LeakDetector::default();
+        let owners = HashSet::new();
+        let missing = dir.path().join("missing");
+        assert!(sut
+            .scan(&missing, Some(missing.as_path()), &owners)?
+            .is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! Networking of pod sandboxes via CNI plugins.
+
+pub mod cni;
+pub mod leaks;
+pub mod netns;
+pub mod stats;
+pub mod template;
+
+use crate::{latency::Timeline, network::cni::CniNetwork, sandbox::SandboxData};
+use anyhow::{format_err, Context, Result};
+use getset::Getters;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+/// The storage key prefix of all network statuses.
+const KEY_PREFIX: &str = "network/";
+
+#[derive(Clone, Debug, Deserialize, Getters, PartialEq, Serialize)]
+/// NetworkStatus records the attachment of a pod sandbox to a CNI network, which is required to
+/// detach it again with the same configuration.
+pub struct NetworkStatus {
+    #[get = "pub"]
+    /// The path of the pinned network namespace of the sandbox.
+    netns: PathBuf,
+
+    #[get = "pub"]
+    /// The raw CNI network configuration used for attaching the sandbox.
+    config: String,
+
+    #[get = "pub"]
+    /// The raw result of the CNI plugins, which gets passed to them when detaching.
+    result: String,
+
+    #[get = "pub"]
+    /// The IP addresses assigned to the sandbox, whereas the first one is the primary address.
+    ips: Vec<IpAddr>,
+}
+
+impl NetworkStatus {
+    /// Retrieve the storage key for the network status of the sandbox `id`.
+    pub fn key(id: &str) -> String {
+        format!("{}{}", KEY_PREFIX, id)
+    }
+}
+
+/// Pin a new network namespace at `netns` and attach the `sandbox` to the `network`, requesting
+/// the addresses in CIDR notation of `ips` from plugins supporting it. A failed attachment gets
+/// deleted again, as required by the CNI specification, and the namespace gets removed. Both
+/// steps are recorded in the `timeline`.
+pub async fn attach(
+    network: &CniNetwork,
+    sandbox: &SandboxData,
+    netns: &Path,
+    ips: &[String],
+    timeline: &mut Timeline,
+) -> Result<NetworkStatus> {
+    netns::pin(netns)?;
+    timeline.step("netns");
+    let added = network.add(sandbox, netns, ips).await;
+    timeline.step("cni");
+    match added {
+        Ok((result, ips)) => {
+            info!(
+                "Attached pod sandbox {} to network {} with IPs {:?}",
+                sandbox.id(),
+                network.name(),
+                ips
+            );
+            Ok(NetworkStatus {
+                netns: netns.into(),
+                config: network.config().clone(),
+                result,
+                ips,
+            })
+        }
+        Err(e) => {
+            if let Err(del_err) = network.del(sandbox, netns, None).await {
+                error!(
+                    "Unable to delete failed network of pod sandbox {}: {:#}",
+                    sandbox.id(),
+                    del_err
+                );
+            }
+            if let Err(unpin_err) = netns::unpin(netns) {
+                error!(
+                    "Unable to remove network namespace {}: {:#}",
+                    netns.display(),
+                    unpin_err
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Attach the `sandbox` again to the network of its `status`, whose network namespace got lost,
+/// for example because the node rebooted. The previous IP addresses get requested from plugins
+/// supporting it, whereas the plugins are looked up in the `plugin_dirs`. The steps of attaching
+/// are recorded in the `timeline`.
+pub async fn reattach(
+    status: &NetworkStatus,
+    sandbox: &SandboxData,
+    plugin_dirs: &[PathBuf],
+    timeline: &mut Timeline,
+) -> Result<NetworkStatus> {
+    let network = CniNetwork::parse(status.config(), plugin_dirs)
+        .context("parse network configuration of sandbox")?;
+
+    // IPAM plugins keep their allocations across reboots, which have to be released first
+    if let Err(e) = network
+        .del(sandbox, status.netns(), Some(status.result()))
+        .await
+    {
+        warn!(
+            "Unable to delete lost network of pod sandbox {}: {:#}",
+            sandbox.id(),
+            e
+        );
+    }
+    netns::unpin(status.netns()).context("remove lost network namespace")?;
+
+    let ips = cni::addresses(status.result()).context("get previous IP addresses")?;
+    let reattached = attach(&network, sandbox, status.netns(), &ips, timeline).await?;
+    if reattached.ips() != status.ips() {
+        warn!(
+            "Pod sandbox {} changed its IPs from {:?} to {:?} during network recovery",
+            sandbox.id(),
+            status.ips(),
+            reattached.ips()
+        );
+    }
+    Ok(reattached)
+}
+
+/// Detach the `sandbox` from the network of its `status` and remove its network namespace. The
+/// plugins are looked up in the `plugin_dirs`.
+pub async fn detach(
+    status: &NetworkStatus,
+    sandbox: &SandboxData,
+    plugin_dirs: &[PathBuf],
+) -> Result<()> {
+    let network = CniNetwork::parse(status.config(), plugin_dirs)
+        .context("parse network configuration of sandbox")?;
+    let deleted = network
+        .del(sandbox, status.netns(), Some(status.result()))
+        .await;
+    let unpinned = netns::unpin(status.netns());
+    match (deleted, unpinned) {
+        (Ok(()), Ok(())) => {
+            info!(
+                "Detached pod sandbox {} from network {}",
+                sandbox.id(),
+                network.name()
+            );
+            Ok(())
+        }
+        (Err(e), Ok(())) => Err(e.context("delete network")),
+        (Ok(()), Err(e)) => Err(e.context("remove network namespace")),
+        (Err(del), Err(unpin)) => Err(format_err!(
+            "delete network: {:#}, remove network namespace: {:#}",
+            del,
+            unpin
+        )),
+    }
+}
@@ -0,0 +1,315 @@
+//! Network namespaces which are pinned to files, so that they outlive their processes.
+
+use crate::container::id_index::{IdIndex, ResolveError};
+use anyhow::{bail, format_err, Context, Result};
+use getset::Getters;
+use std::{
+    fs,
+    net::{SocketAddr, TcpStream},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+
+#[cfg(target_os = "linux")]
+use nix::{
+    errno::Errno,
+    mount::{mount, umount2, MntFlags, MsFlags},
+    sched::{setns, unshare, CloneFlags},
+    unistd::gettid,
+};
+#[cfg(target_os = "linux")]
+use std::{os::unix::io::AsRawFd, thread};
+
+#[derive(Debug, Getters)]
+/// SandboxNetns is the pinned network namespace of a pod sandbox, which allows node operators to
+/// run host binaries like `tcpdump` or `ss` against pods which do not ship them.
+pub struct SandboxNetns {
+    #[get = "pub"]
+    /// The path of the pinned network namespace.
+    path: PathBuf,
+}
+
+impl SandboxNetns {
+    /// Find the network namespace of the pod sandbox `id` inside the directory `dir`, whereas a
+    /// unique prefix of the ID is sufficient.
+    pub fn find(dir: &Path, id: &str) -> Result<Self> {
+        if id.is_empty() || id.contains('/') || id.starts_with('.') {
+            bail!("invalid pod sandbox ID {:?}", id)
+        }
+        let path = dir.join(id);
+        if path.exists() {
+            return Ok(Self { path });
+        }
+
+        let mut ids = IdIndex::default();
+        for entry in fs::read_dir(dir).with_context(|| format!("read dir {}", dir.display()))? {
+            ids.add(entry?.file_name().to_string_lossy().into_owned())?;
+        }
+        let resolved = ids.resolve(id).map_err(|e| match e {
+            ResolveError::NotFound(_) => {
+                format_err!("no network namespace found for pod sandbox {}", id)
+            }
+            ResolveError::Ambiguous(_, first, second) => format_err!(
+                "pod sandbox ID prefix {} is ambiguous: matches {}, {} and possibly more",
+                id,
+                first,
+                second
+            ),
+            e => format_err!("{}", e),
+        })?;
+        Ok(Self {
+            path: dir.join(resolved),
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    /// Run the `command` inside the network namespace and wait for it to exit. The command keeps
+    /// all other namespaces of the caller, which means that it sees the host filesystem and
+    /// processes.
+    pub fn exec(&self, mut command: Command) -> Result<ExitStatus> {
+        let netns = fs::File::open(&self.path)
+            .with_context(|| format!("open network namespace {}", self.path.display()))?;
+
+        // Only the network namespace of the spawned thread changes, which the command inherits
+        thread::spawn(move || -> Result<ExitStatus> {
+            setns(netns.as_raw_fd(), CloneFlags::CLONE_NEWNET).context("join network namespace")?;
+            command
+                .status()
+                .with_context(|| format!("run {:?}", command))
+        })
+        .join()
+        .map_err(|_| format_err!("thread running the command in the network namespace panicked"))
+        .and_then(|x| x)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    /// Run the `command` inside the network namespace, which is only supported on Linux.
+    pub fn exec(&self, _: Command) -> Result<ExitStatus> {
+        bail!("joining network namespaces is not supported on this platform")
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// Create a new network namespace and pin it by bind mounting it to the file at `path`.
+pub fn pin(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("create network namespace directory {}", parent.display()))?;
+    }
+    fs::File::create(path)
+        .with_context(|| format!("create network namespace file {}", path.display()))?;
+
+    // Only the network namespace of the spawned thread changes, which exits afterwards
+    let target = path.to_path_buf();
+    let res = thread::spawn(move || -> Result<()> {
+        unshare(CloneFlags::CLONE_NEWNET).context("unshare network namespace")?;
+        let source = format!("/proc/self/task/{}/ns/net", gettid());
+        mount(
+            Some(source.as_str()),
+            target.as_path(),
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .with_context(|| format!("bind mount network namespace to {}", target.display()))
+    })
+    .join()
+    .map_err(|_| format_err!("thread pinning the network namespace panicked"))
+    .and_then(|x| x);
+
+    if res.is_err() {
+        fs::remove_file(path).ok();
+    }
+    res
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Create a new network namespace at `path`, which is only supported on Linux.
+pub fn pin(_: &Path) -> Result<()> {
+    bail!("pinning network namespaces is not supported on this platform")
+}
+
+#[cfg(target_os = "linux")]
+/// Connect to the TCP `address` from within the network namespace pinned at `path`. The returned
+/// stream stays in that namespace, although it is used from the current one.
+pub fn connect(path: &Path, address: SocketAddr) -> Result<TcpStream> {
+    let netns = fs::File::open(path)
+        .with_context(|| format!("open network namespace {}", path.display()))?;
+
+    // Only the network namespace of the spawned thread changes, which exits afterwards
+    thread::spawn(move || -> Result<TcpStream> {
+        setns(netns.as_raw_fd(), CloneFlags::CLONE_NEWNET).context("join network namespace")?;
+        TcpStream::connect(address).with_context(|| format!("connect to {}", address))
+    })
+    .join()
+    .map_err(|_| format_err!("thread connecting in the network namespace panicked"))
+    .and_then(|x| x)
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Connect to the TCP `address` from within a network namespace, which is only supported on
+/// Linux.
+pub fn connect(_: &Path, _: SocketAddr) -> Result<TcpStream> {
+    bail!("joining network namespaces is not supported on this platform")
+}
+
+#[cfg(target_os = "linux")]
+/// Read the file at `path` below `/proc/thread-self` from within the network namespace pinned at
+/// `netns`, like `net/dev`. The entries of `/proc/self` do not change with the namespace of a
+/// thread, because they belong to the main thread of the process.
+pub fn read_proc(netns: &Path, path: &str) -> Result<String> {
+    let file = fs::File::open(netns)
+        .with_context(|| format!("open network namespace {}", netns.display()))?;
+    let path = Path::new("/proc/thread-self").join(path);
+
+    // Only the network namespace of the spawned thread changes, which exits afterwards
+    thread::spawn(move || -> Result<String> {
+        setns(file.as_raw_fd(), CloneFlags::CLONE_NEWNET).context("join network namespace")?;
+        fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))
+    })
+    .join()
+    .map_err(|_| format_err!("thread reading in the network namespace panicked"))
+    .and_then(|x| x)
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Read a file of `/proc` from within a network namespace, which is only supported on Linux.
+pub fn read_proc(_: &Path, _: &str) -> Result<String> {
+    bail!("joining network namespaces is not supported on this platform")
+}
+
+#[cfg(target_os = "linux")]
+/// Unmount and remove the network namespace pinned at `path`, if it exists.
+pub fn unpin(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    // The file is not a mount point if pinning it failed half way
+    if is_mount_point(path)? {
+        match umount2(path, MntFlags::MNT_DETACH) {
+            Ok(()) | Err(nix::Error::Sys(Errno::EINVAL)) => {}
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("unmount network namespace {}", path.display()))
+            }
+        }
+    }
+    fs::remove_file(path)
+        .with_context(|| format!("remove network namespace file {}", path.display()))
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Remove the network namespace file at `path`, if it exists. Nothing is mounted there, because
+/// network namespaces are only pinned on Linux.
+pub fn unpin(path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_file(path)
+            .with_context(|| format!("remove network namespace file {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Check whether a network namespace is still pinned at `path`, which is not the case anymore
+/// after the node rebooted or the mount got removed by someone else.
+pub fn is_pinned(path: &Path) -> Result<bool> {
+    Ok(path.exists() && is_mount_point(path)?)
+}
+
+/// Check whether something is mounted at `path`, which resides on another device than its
+/// parent directory in that case.
+pub fn is_mount_point(path: &Path) -> Result<bool> {
+    let dev = |path: &Path| {
+        fs::metadata(path)
+            .map(|x| x.dev())
+            .with_context(|| format!("get metadata of {}", path.display()))
+    };
+    match path.parent() {
+        Some(parent) => Ok(dev(path)? != dev(parent)?),
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn find_success() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("abc123"), "")?;
+        fs::write(dir.path().join("abd456"), "")?;
+
+        let netns = SandboxNetns::find(dir.path(), "abc123")?;
+        assert_eq!(netns.path(), &dir.path().join("abc123"));
+        let netns = SandboxNetns::find(dir.path(), "abd")?;
+        assert_eq!(netns.path(), &dir.path().join("abd456"));
+        Ok(())
+    }
+
+    #[test]
+    fn find_fail() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("abc123"), "")?;
+        fs::write(dir.path().join("abd456"), "")?;
+
+        let err = SandboxNetns::find(dir.path(), "ab")
+            .err()
+            .map(|e| e.to_string());
+        assert!(err.unwrap_or_default().contains("ambiguous"));
+        assert!(SandboxNetns::find(dir.path(), "x").is_err());
+        assert!(SandboxNetns::find(dir.path(), "").is_err());
+        assert!(SandboxNetns::find(dir.path(), "../abc123").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn exec_fail_not_found() -> Result<()> {
+        let dir = tempdir()?;
+        let netns = SandboxNetns {
+            path: dir.path().join("netns"),
+        };
+        assert!(netns.exec(Command::new("true")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn connect_fail_not_found() -> Result<()> {
+        let dir = tempdir()?;
+        let address = SocketAddr::from(([127, 0, 0, 1], 80));
+        assert!(connect(&dir.path().join("netns"), address).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_proc_fail_not_found() -> Result<()> {
+        let dir = tempdir()?;
+        assert!(read_proc(&dir.path().join("netns"), "net/dev").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unpin_success_not_pinned() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("netns");
+        unpin(&path)?;
+
+        fs::write(&path, "")?;
+        unpin(&path)?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn is_pinned_success_not_pinned() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("netns");
+        assert!(!is_pinned(&path)?);
+
+        fs::write(&path, "")?;
+        assert!(!is_pinned(&path)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+//! Statistics of the network interfaces of pod sandboxes.
+//!
+//! The counters are read from `/proc/net/dev` within the network namespace of a sandbox, which
+//! lists the interface of the default network as well as additional ones attached by meta
+//! plugins for multiple networks. The loopback interface is never reported.
+
+use crate::{
+    criapi::{NetworkInterfaceUsage, NetworkUsage, UInt64Value},
+    network::{cni::INTERFACE, netns},
+};
+use anyhow::{format_err, Context, Result};
+use getset::{CopyGetters, Getters};
+use std::path::Path;
+
+/// The name of the loopback interface.
+const LOOPBACK: &str = "lo";
+
+#[derive(Clone, CopyGetters, Debug, Default, Getters, PartialEq)]
+/// InterfaceStats are the cumulative counters of a network interface.
+pub struct InterfaceStats {
+    #[get = "pub"]
+    /// The name of the interface, like `eth0`.
+    name: String,
+
+    #[get_copy = "pub"]
+    /// The number of bytes received.
+    rx_bytes: u64,
+
+    #[get_copy = "pub"]
+    /// The number of packets received.
+    rx_packets: u64,
+
+    #[get_copy = "pub"]
+    /// The number of receive errors.
+    rx_errors: u64,
+
+    #[get_copy = "pub"]
+    /// The number of bytes transmitted.
+    tx_bytes: u64,
+
+    #[get_copy = "pub"]
+    /// The number of packets transmitted.
+    tx_packets: u64,
+
+    #[get_copy = "pub"]
+    /// The number of transmit errors.
+    tx_errors: u64,
+}
+
+impl InterfaceStats {
+    /// Convert the counters into the CRI representation, which has no packet counters.
+    fn usage(&self) -> NetworkInterfaceUsage {
+        let value = |value| Some(UInt64Value { value });
+        NetworkInterfaceUsage {
+            name: self.name.clone(),
+            rx_bytes: value(self.rx_bytes),
+            rx_errors: value(self.rx_errors),
+            tx_bytes: value(self.tx_bytes),
+            tx_errors: value(self.tx_errors),
+        }
+    }
+}
+
+/// Read the statistics of all interfaces within the network namespace pinned at `path`.
+pub fn read(path: &Path) -> Result<Vec<InterfaceStats>> {
+    parse(&netns::read_proc(path, "net/dev")?)
+}
+
+/// Parse the `content` of `/proc/net/dev`, whose first two lines are headers.
+pub fn parse(content: &str) -> Result<Vec<InterfaceStats>> {
+    let mut interfaces = vec![];
+    for line in content.lines().skip(2) {
+        let mut parts = line.splitn(2, ':');
+        let (name, counters) = match (parts.next(), parts.next()) {
+            (Some(name), Some(counters)) => (name.trim(), counters),
+            _ => return Err(format_err!("invalid interface statistics {:?}", line)),
+        };
+        if name == LOOPBACK {
+            continue;
+        }
+        let counters = counters
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()
+            .with_context(|| format!("parse statistics of interface {}", name))?;
+        if counters.len() < 11 {
+            return Err(format_err!("missing statistics of interface {}", name));
+        }
+
+        // The receive counters are followed by as many transmit counters
+        interfaces.push(InterfaceStats {
+            name: name.into(),
+            rx_bytes: counters[0],
+            rx_packets: counters[1],
+            rx_errors: counters[2],
+            tx_bytes: counters[8],
+            tx_packets: counters[9],
+            tx_errors: counters[10],
+        });
+    }
+    Ok(interfaces)
+}
+
+/// Build the network usage at `timestamp` from the `interfaces`, whereas the default interface
+/// is the one attached to the default network.
+pub fn usage(interfaces: &[InterfaceStats], timestamp: i64) -> NetworkUsage {
+    NetworkUsage {
+        timestamp,
+        default_interface: interfaces
+            .iter()
+            .find(|x| x.name == INTERFACE)
+            .map(InterfaceStats::usage),
+        interfaces: interfaces
+            .iter()
+            .filter(|x| x.name != INTERFACE)
+            .map(InterfaceStats::usage)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NET_DEV: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:     120       2    0    0    0     0          0         0      120       2    0    0    0     0       0          0
+  eth0:    2048      16    1    0    0     0          0         0     1024       8    2    0    0     0       0          0
+  net1:     512       4    0    0    0     0          0         0      256       2    0    0    0     0       0          0
+";
+
+    #[test]
+    fn parse_success() -> Result<()> {
+        let interfaces = parse(NET_DEV)?;
+        assert_eq!(interfaces.len(), 2);
+        assert_eq!(
+            interfaces[0],
+            InterfaceStats {
+                name: "eth0".into(),
+                rx_bytes: 2048,
+                rx_packets: 16,
+                rx_errors: 1,
+                tx_bytes: 1024,
+                tx_packets: 8,
+                tx_errors: 2,
+            }
+        );
+        assert_eq!(interfaces[1].name(), "net1");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_fail_invalid() {
+        for content in &["h\nh\neth0 1 2 3", "h\nh\neth0: 1 2 3", "h\nh\neth0: x"] {
+            assert!(parse(content).is_err(), "{}", content);
+        }
+    }
+
+    #[test]
+    fn usage_success() -> Result<()> {
+        let usage = usage(&parse(NET_DEV)?, 10);
+        assert_eq!(usage.timestamp, 10);
+        let default = usage.default_interface.unwrap_or_default();
+        assert_eq!(default.name, "eth0");
+        assert_eq!(default.rx_bytes.map(|x| x.value), Some(2048));
+        assert_eq!(default.tx_errors.map(|x| x.value), Some(2));
+        assert_eq!(usage.interfaces.len(), 1);
+        assert_eq!(usage.interfaces[0].name, "net1");
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+//! Rendering of the CNI network configuration for the pod CIDR of the node.
+//!
+//! Clusters allocating a pod CIDR per node push it to the runtime via `UpdateRuntimeConfig`. The
+//! configured template, usually a bridge network with host-local IPAM, gets rendered for the
+//! CIDR into the CNI config directory. The rendered configuration sorts before the usual ones,
+//! which makes all subsequently created pod sandboxes get their addresses from the new range.
+
+use crate::network::cni::CniNetwork;
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::{fs, net::IpAddr, path::Path};
+
+/// The name of the rendered configuration inside the CNI config directory.
+pub const CONFIG_NAME: &str = "10-cri-net.conflist";
+
+/// The placeholder of the first pod CIDR.
+const POD_CIDR: &str = "{{.PodCIDR}}";
+
+/// The placeholder of the host-local IPAM ranges of all pod CIDRs.
+const POD_CIDR_RANGES: &str = "{{.PodCIDRRanges}}";
+
+/// Parse the comma separated `pod_cidr` of the kubelet, which contains an IPv4 and an IPv6
+/// range on dual-stack nodes.
+pub fn parse_pod_cidrs(pod_cidr: &str) -> Result<Vec<String>> {
+    let cidrs: Vec<String> = pod_cidr
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(Into::into)
+        .collect();
+    if cidrs.is_empty() {
+        bail!("no pod CIDR provided")
+    }
+    for cidr in &cidrs {
+        let mut parts = cidr.splitn(2, '/');
+        let (address, prefix) = match (parts.next(), parts.next()) {
+            (Some(address), Some(prefix)) => (address, prefix),
+            _ => bail!("pod CIDR {} has no prefix length", cidr),
+        };
+        let max = match address
+            .parse::<IpAddr>()
+            .with_context(|| format!("invalid address of pod CIDR {}", cidr))?
+        {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        match prefix.parse::<u8>() {
+            Ok(prefix) if prefix <= max => {}
+            _ => bail!("invalid prefix length of pod CIDR {}", cidr),
+        }
+    }
+    Ok(cidrs)
+}
+
+/// Render the CNI configuration `template` for the pod `cidrs`. The result has to be a valid
+/// network configuration.
+pub fn render(template: &str, cidrs: &[String]) -> Result<String> {
+    let first = cidrs.first().context("no pod CIDR provided")?;
+    let ranges: Vec<_> = cidrs.iter().map(|x| json!([{ "subnet": x }])).collect();
+    let rendered = template
+        .replace(POD_CIDR_RANGES, &json!(ranges).to_string())
+        .replace(POD_CIDR, first);
+    CniNetwork::parse(&rendered, &[]).context("rendered CNI config is invalid")?;
+    Ok(rendered)
+}
+
+/// Write the rendered `config` into the CNI `config_dir`. Returns `false` if it is unchanged.
+pub fn write(config_dir: &Path, config: &str) -> Result<bool> {
+    let path = config_dir.join(CONFIG_NAME);
+    if fs::read_to_string(&path).ok().as_deref() == Some(config) {
+        return Ok(false);
+    }
+    fs::create_dir_all(config_dir)
+        .with_context(|| format!("create CNI config directory {}", config_dir.display()))?;
+
+    // Write into a temporary file first, which prevents sandboxes from loading a partial config
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, config)
+        .and_then(|_| fs::rename(&tmp_path, &path))
+        .with_context(|| format!("write CNI config {}", path.display()))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const TEMPLATE: &str = r#"{
+      "cniVersion": "0.4.0",
+      "name": "cri",
+      "plugins": [
+        {
+          "type": "bridge",
+          "bridge": "cni0",
+          "ipam": {"type": "host-local", "ranges": {{.PodCIDRRanges}}, "routes": [{"dst": "0.0.0.0/0"}]}
+        },
+        {"type": "portmap", "note": "{{.PodCIDR}}"}
+      ]
+    }"#;
+
+    #[test]
+    fn parse_pod_cidrs_success() -> Result<()> {
+        assert_eq!(parse_pod_cidrs("10.1.0.0/24")?, vec!["10.1.0.0/24"]);
+        assert_eq!(
+            parse_pod_cidrs("10.1.0.0/24, fd00:1::/64")?,
+            vec!["10.1.0.0/24", "fd00:1::/64"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_pod_cidrs_fail() {
+        for pod_cidr in &["", ",", "10.1.0.0", "10.1.0.0/33", "fd00::/129", "cidr/24"] {
+            assert!(parse_pod_cidrs(pod_cidr).is_err(), "{}", pod_cidr);
+        }
+    }
+
+    #[test]
+    fn render_success() -> Result<()> {
+        let rendered = render(TEMPLATE, &["10.1.0.0/24".into(), "fd00:1::/64".into()])?;
+        assert!(rendered
+            .contains(r#""ranges": [[{"subnet":"10.1.0.0/24"}],[{"subnet":"fd00:1::/64"}]]"#));
+        assert!(rendered.contains(r#""note": "10.1.0.0/24""#));
+        Ok(())
+    }
+
+    #[test]
+    fn render_fail_invalid() {
+        assert!(render("{{.PodCIDR}}", &["10.1.0.0/24".into()]).is_err());
+        assert!(render(TEMPLATE, &[]).is_err());
+    }
+
+    #[test]
+    fn write_success() -> Result<()> {
+        let dir = tempdir()?;
+        let config_dir = dir.path().join("net.d");
+        assert!(write(&config_dir, "first")?);
+        assert!(!write(&config_dir, "first")?);
+        assert!(write(&config_dir, "second")?);
+        assert_eq!(fs::read_to_string(config_dir.join(CONFIG_NAME))?, "second");
+        assert!(!config_dir.join(CONFIG_NAME).with_extension("tmp").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,4 @@
+//! OCI runtime integration
+
+pub mod runtime;
+pub mod spec;
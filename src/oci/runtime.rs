@@ -0,0 +1,564 @@
+//! A client for OCI runtimes like runc or crun, which uses their command line interface.
+
+use crate::error_details::ErrorDetails;
+use anyhow::{Context, Result};
+use getset::{CopyGetters, Getters};
+use log::debug;
+use nix::sys::signal::Signal;
+use serde::Deserialize;
+use std::{
+    error, fmt,
+    fs::File,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::{process::Command, time};
+use tonic::Code;
+
+/// The file name of the container PID file inside the bundle.
+pub const PID_FILE: &str = "pid";
+
+/// The interval for polling the state of a container.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, Getters)]
+/// OciRuntime runs the lifecycle commands of the OCI runtime `binary`.
+pub struct OciRuntime {
+    #[get = "pub"]
+    /// The path to the runtime binary, which gets looked up in `$PATH` if not absolute.
+    binary: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// The status of a container as reported by the OCI runtime.
+pub enum RuntimeStatus {
+    /// The container is being created.
+    Creating,
+
+    /// The container has been created, but the user process has not been started yet.
+    Created,
+
+    /// The user process of the container is running.
+    Running,
+
+    /// The container processes have been frozen.
+    Paused,
+
+    /// The user process of the container has exited.
+    Stopped,
+}
+
+#[derive(CopyGetters, Debug, Deserialize, Getters)]
+/// The state of a container as reported by the OCI runtime.
+pub struct State {
+    #[get = "pub"]
+    /// The identifier of the container.
+    id: String,
+
+    #[get_copy = "pub"]
+    /// The runtime status of the container.
+    status: RuntimeStatus,
+
+    #[get_copy = "pub"]
+    #[serde(default)]
+    /// The PID of the container process on the host, which is zero if it is not running.
+    pid: u32,
+}
+
+#[derive(Debug, Getters)]
+/// CommandError is returned if the runtime command exits unsuccessfully.
+pub struct CommandError {
+    #[get = "pub"]
+    /// The command line of the failed command.
+    command: String,
+
+    #[get = "pub"]
+    /// The stderr output of the failed command.
+    stderr: String,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {}", self.command, self.stderr.trim())
+    }
+}
+
+impl error::Error for CommandError {}
+
+/// Convert the `error` of the failed `step` into a gRPC status, which contains the command line
+/// and the stderr output if the runtime command itself failed.
+pub fn error_status(step: &str, error: anyhow::Error) -> tonic::Status {
+    let details = ErrorDetails::new(step);
+    let details = match error.downcast_ref::<CommandError>() {
+        Some(e) => details.command(e.command()).stderr(e.stderr()),
+        None => details,
+    };
+    details.status(Code::Internal, format!("{:#}", error))
+}
+
+impl OciRuntime {
+    /// Create a new runtime client for the provided `binary`.
+    pub fn new<P: Into<PathBuf>>(binary: P) -> Self {
+        Self {
+            binary: binary.into(),
+        }
+    }
+
+    /// Create the container `id` from the OCI `bundle` and write the PID of its process into the
+    /// `pid_file`.
+    pub async fn create(&self, id: &str, bundle: &Path, pid_file: &Path) -> Result<()> {
+        let (bundle, pid_file) = (bundle.display().to_string(), pid_file.display().to_string());
+        self.run(&Self::create_args(id, &bundle, &pid_file))
+            .await
+            .map(|_| ())
+    }
+
+    /// Create the container `id` like `create`, but connect the standard output and error of
+    /// the container process to `stdout` and `stderr`. The runtime inherits them as well, which
+    /// means that its error messages end up in there instead of the returned error. The standard
+    /// input gets connected to `stdin` if set.
+    pub async fn create_with_output(
+        &self,
+        id: &str,
+        bundle: &Path,
+        pid_file: &Path,
+        stdin: Option<File>,
+        stdout: File,
+        stderr: File,
+    ) -> Result<()> {
+        let (bundle, pid_file) = (bundle.display().to_string(), pid_file.display().to_string());
+        self.run_with_output(
+            &Self::create_args(id, &bundle, &pid_file),
+            stdin,
+            stdout,
+            stderr,
+        )
+        .await
+    }
+
+    /// Build the arguments for creating the container `id`.
+    fn create_args<'a>(id: &'a str, bundle: &'a str, pid_file: &'a str) -> [&'a str; 6] {
+        ["create", "--bundle", bundle, "--pid-file", pid_file, id]
+    }
+
+    /// Checkpoint the running container `id` into the directory `image_path`, which stops the
+    /// container afterwards.
+    pub async fn checkpoint(&self, id: &str, image_path: &Path) -> Result<()> {
+        let image_path = image_path.display().to_string();
+        self.run(&["checkpoint", "--image-path", &image_path, id])
+            .await
+            .map(|_| ())
+    }
+
+    /// Restore the container `id` from the OCI `bundle` and the checkpoint in `image_path`, and
+    /// write the PID of its process into the `pid_file`. The restored process continues to run
+    /// in the background.
+    pub async fn restore(
+        &self,
+        id: &str,
+        bundle: &Path,
+        pid_file: &Path,
+        image_path: &Path,
+    ) -> Result<()> {
+        let (bundle, pid_file) = (bundle.display().to_string(), pid_file.display().to_string());
+        let image_path = image_path.display().to_string();
+        self.run(&Self::restore_args(id, &bundle, &pid_file, &image_path))
+            .await
+            .map(|_| ())
+    }
+
+    /// Restore the container `id` like `restore`, but connect the standard output and error of
+    /// the restored process to `stdout` and `stderr`, like `create_with_output` does.
+    pub async fn restore_with_output(
+        &self,
+        id: &str,
+        bundle: &Path,
+        pid_file: &Path,
+        image_path: &Path,
+        stdout: File,
+        stderr: File,
+    ) -> Result<()> {
+        let (bundle, pid_file) = (bundle.display().to_string(), pid_file.display().to_string());
+        let image_path = image_path.display().to_string();
+        let args = Self::restore_args(id, &bundle, &pid_file, &image_path);
+        self.run_with_output(&args, None, stdout, stderr).await
+    }
+
+    /// Build the arguments for restoring the container `id`.
+    fn restore_args<'a>(
+        id: &'a str,
+        bundle: &'a str,
+        pid_file: &'a str,
+        image_path: &'a str,
+    ) -> [&'a str; 9] {
+        [
+            "restore",
+            "--detach",
+            "--image-path",
+            image_path,
+            "--bundle",
+            bundle,
+            "--pid-file",
+            pid_file,
+            id,
+        ]
+    }
+
+    /// Run the runtime with the provided `args` like `run`, but connect its standard streams to
+    /// `stdin`, `stdout` and `stderr`.
+    async fn run_with_output(
+        &self,
+        args: &[&str],
+        stdin: Option<File>,
+        stdout: File,
+        stderr: File,
+    ) -> Result<()> {
+        let command = format!("{} {}", self.binary.display(), args.join(" "));
+        debug!("Running {}", command);
+
+        let status = Command::new(&self.binary)
+            .args(args)
+            .stdin(stdin.map_or_else(Stdio::null, Stdio::from))
+            .stdout(stdout)
+            .stderr(stderr)
+            .kill_on_drop(true)
+            .status()
+            .await
+            .with_context(|| format!("run {}", command))?;
+        if !status.success() {
+            return Err(CommandError {
+                command,
+                stderr: format!("{}, see the container log for details", status),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Start the user process of the created container `id`.
+    pub async fn start(&self, id: &str) -> Result<()> {
+        self.run(&["start", id]).await.map(|_| ())
+    }
+
+    /// Send the `signal` to the init process of the container `id`.
+    pub async fn kill(&self, id: &str, signal: Signal) -> Result<()> {
+        self.run(&["kill", id, &(signal as i32).to_string()])
+            .await
+            .map(|_| ())
+    }
+
+    /// Delete the container `id`, whereas a running container only gets killed if `force` is set.
+    pub async fn delete(&self, id: &str, force: bool) -> Result<()> {
+        let mut args = vec!["delete"];
+        if force {
+            args.push("--force");
+        }
+        args.push(id);
+        self.run(&args).await.map(|_| ())
+    }
+
+    /// Retrieve the state of the container `id`.
+    pub async fn state(&self, id: &str) -> Result<State> {
+        let output = self.run(&["state", id]).await?;
+        serde_json::from_slice(&output).context("deserialize container state")
+    }
+
+    /// Build the command which executes `args` inside the running container `id`. The process
+    /// gets a pseudo terminal, which is sent to the `console_socket` if set.
+    pub fn exec(&self, id: &str, args: &[String], console_socket: Option<&Path>) -> Command {
+        let mut command = Command::new(&self.binary);
+        command.arg("exec");
+        if let Some(console_socket) = console_socket {
+            command
+                .arg("--tty")
+                .arg("--console-socket")
+                .arg(console_socket);
+        }
+        command.arg(id).args(args);
+        command
+    }
+
+    /// Retrieve the version of the runtime, which is the first line it reports on `--version`.
+    pub async fn version(&self) -> Result<String> {
+        let output = self.run(&["--version"]).await?;
+        Ok(String::from_utf8_lossy(&output)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .into())
+    }
+
+    /// Wait until the container `id` is stopped or the `timeout` is exceeded. Returns whether the
+    /// container is stopped.
+    pub async fn wait_stopped(&self, id: &str, timeout: Duration) -> Result<bool> {
+        match time::timeout(timeout, self.poll_stopped(id)).await {
+            Ok(res) => res.map(|()| true),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Poll the state of the container `id` until it is stopped.
+    async fn poll_stopped(&self, id: &str) -> Result<()> {
+        loop {
+            if self.state(id).await?.status() == RuntimeStatus::Stopped {
+                return Ok(());
+            }
+            time::delay_for(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Run the runtime with the provided `args` and return its stdout.
+    async fn run(&self, args: &[&str]) -> Result<Vec<u8>> {
+        let command = format!("{} {}", self.binary.display(), args.join(" "));
+        debug!("Running {}", command);
+
+        let output = Command::new(&self.binary)
+            .args(args)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("run {}", command))?;
+        if !output.status.success() {
+            return Err(CommandError {
+                command,
+                stderr: String::from_utf8_lossy(&output.stderr).into(),
+            }
+            .into());
+        }
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    /// Write a fake OCI runtime into `dir`, which logs its arguments into `runtime.log`, reports
+    /// the container as `status` on `state`, runs the command of `exec` on the host and reports
+    /// its version as `fake version 1.0.0`.
+    pub fn fake_runtime(dir: &Path, status: &str) -> Result<PathBuf> {
+        let path = dir.join("runtime");
+        let log = dir.join("runtime.log");
+        fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\n\
+                 echo \"$@\" >> {log}\n\
+                 case \"$1\" in\n\
+                 state) echo '{{\"id\":\"'\"$2\"'\",\"status\":\"{status}\",\"pid\":1}}' ;;\n\
+                 exec) shift 2; exec \"$@\" ;;\n\
+                 --version) echo 'fake version 1.0.0' ;;\n\
+                 fail) echo 'failure' >&2; exit 1 ;;\n\
+                 esac\n",
+                log = log.display(),
+                status = status
+            ),
+        )?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        Ok(path)
+    }
+
+    /// Retrieve the commands the fake runtime in `dir` has been called with.
+    pub fn fake_runtime_log(dir: &Path) -> Result<Vec<String>> {
+        Ok(fs::read_to_string(dir.join("runtime.log"))
+            .unwrap_or_default()
+            .lines()
+            .map(Into::into)
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn lifecycle() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sut = OciRuntime::new(fake_runtime(dir.path(), "running")?);
+
+        sut.create("id", Path::new("/bundle"), Path::new("/bundle/pid"))
+            .await?;
+        sut.start("id").await?;
+        sut.kill("id", Signal::SIGTERM).await?;
+        sut.delete("id", true).await?;
+
+        assert_eq!(
+            fake_runtime_log(dir.path())?,
+            vec![
+                "create --bundle /bundle --pid-file /bundle/pid id",
+                "start id",
+                "kill id 15",
+                "delete --force id",
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_with_output() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sut = OciRuntime::new(fake_runtime(dir.path(), "running")?);
+        let output = dir.path().join("output");
+        let file = || {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&output)
+        };
+
+        sut.create_with_output(
+            "id",
+            Path::new("/bundle"),
+            Path::new("/pid"),
+            Some(file()?),
+            file()?,
+            file()?,
+        )
+        .await?;
+        assert_eq!(
+            fake_runtime_log(dir.path())?,
+            vec!["create --bundle /bundle --pid-file /pid id"]
+        );
+
+        let sut = OciRuntime::new("/bin/false");
+        let err = sut
+            .create_with_output(
+                "id",
+                Path::new("/bundle"),
+                Path::new("/pid"),
+                None,
+                file()?,
+                file()?,
+            )
+            .await
+            .err()
+            .context("no error")?;
+        assert!(err.downcast_ref::<CommandError>().is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checkpoint_restore() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sut = OciRuntime::new(fake_runtime(dir.path(), "running")?);
+        let output = dir.path().join("output");
+        let file = || {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&output)
+        };
+
+        sut.checkpoint("id", Path::new("/checkpoint")).await?;
+        sut.restore(
+            "id",
+            Path::new("/bundle"),
+            Path::new("/pid"),
+            Path::new("/checkpoint"),
+        )
+        .await?;
+        sut.restore_with_output(
+            "id",
+            Path::new("/bundle"),
+            Path::new("/pid"),
+            Path::new("/checkpoint"),
+            file()?,
+            file()?,
+        )
+        .await?;
+
+        let restore =
+            "restore --detach --image-path /checkpoint --bundle /bundle --pid-file /pid id";
+        assert_eq!(
+            fake_runtime_log(dir.path())?,
+            vec!["checkpoint --image-path /checkpoint id", restore, restore]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn state() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sut = OciRuntime::new(fake_runtime(dir.path(), "stopped")?);
+
+        let state = sut.state("id").await?;
+        assert_eq!(state.id(), "id");
+        assert_eq!(state.status(), RuntimeStatus::Stopped);
+        assert_eq!(state.pid(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exec() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sut = OciRuntime::new(fake_runtime(dir.path(), "running")?);
+
+        let output = sut
+            .exec("id", &["echo".into(), "hello".into()], None)
+            .output()
+            .await?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello\n");
+
+        let command = format!(
+            "{:?}",
+            sut.exec("id", &["sh".into()], Some(Path::new("/console.sock")))
+        );
+        assert!(command.contains("--console-socket"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn version() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sut = OciRuntime::new(fake_runtime(dir.path(), "running")?);
+        assert_eq!(sut.version().await?, "fake version 1.0.0");
+
+        assert!(OciRuntime::new("/bin/false").version().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_stopped() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sut = OciRuntime::new(fake_runtime(dir.path(), "stopped")?);
+        assert!(sut.wait_stopped("id", Duration::from_secs(1)).await?);
+
+        let sut = OciRuntime::new(fake_runtime(dir.path(), "running")?);
+        assert!(!sut.wait_stopped("id", Duration::from_millis(200)).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn error_status_command() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sut = OciRuntime::new(fake_runtime(dir.path(), "running")?);
+
+        let err = sut.run(&["fail"]).await.err().context("no error")?;
+        let status = error_status("start container", err);
+        assert_eq!(status.code(), Code::Internal);
+        assert!(status.message().starts_with("start container: "));
+        assert!(status.message().contains("failure"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_fail() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sut = OciRuntime::new(fake_runtime(dir.path(), "running")?);
+
+        let err = sut.run(&["fail"]).await.err().context("no error")?;
+        let err = err
+            .downcast_ref::<CommandError>()
+            .context("no command error")?;
+        assert!(err.command().ends_with("runtime fail"));
+        assert_eq!(err.stderr().trim(), "failure");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_fail_not_found() {
+        let sut = OciRuntime::new("/not/existing");
+        assert!(sut.start("id").await.is_err());
+    }
+}
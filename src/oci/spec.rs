@@ -0,0 +1,966 @@
+//! Translation of CRI container configs into OCI runtime specs.
+
+use crate::{
+    annotations::Tuning,
+    container::{core_dump::CoreDumpPolicy, process::ContainerProcess},
+    criapi::{
+        ContainerConfig, LinuxContainerResources, LinuxContainerSecurityContext, NamespaceMode,
+    },
+    device::Device,
+    oci_spec::{
+        image::ImageConfig,
+        runtime::{
+            LinuxBuilder, LinuxCPUBuilder, LinuxCapabilities, LinuxCapabilitiesBuilder,
+            LinuxMemoryBuilder, LinuxNamespace, LinuxNamespaceBuilder, LinuxNamespaceType,
+            LinuxResources, LinuxResourcesBuilder, Mount, MountBuilder, ProcessBuilder,
+            RootBuilder, Spec, SpecBuilder, User, UserBuilder,
+        },
+    },
+    resources::delegate::Delegation,
+    sandbox::{dns::RESOLV_CONF_PATH, ipc::mqueue_mount, SandboxData},
+};
+use anyhow::{bail, format_err, Result};
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+/// The file name of the OCI spec inside the bundle.
+pub const SPEC_FILE: &str = "config.json";
+
+/// The directory name of the root filesystem inside the bundle.
+pub const ROOTFS_DIR: &str = "rootfs";
+
+/// The capabilities of unprivileged containers, which are the same as the Docker defaults.
+const DEFAULT_CAPABILITIES: &[&str] = &[
+    "CAP_AUDIT_WRITE",
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_MKNOD",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_RAW",
+    "CAP_SETFCAP",
+    "CAP_SETGID",
+    "CAP_SETPCAP",
+    "CAP_SETUID",
+    "CAP_SYS_CHROOT",
+];
+
+/// The paths which are masked in unprivileged containers.
+const DEFAULT_MASKED_PATHS: &[&str] = &[
+    "/proc/acpi",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/sys/firmware",
+];
+
+/// The paths which are read-only in unprivileged containers.
+const DEFAULT_READONLY_PATHS: &[&str] = &[
+    "/proc/asound",
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+/// The paths which are additionally masked in all containers in hardened mode.
+const HARDENED_MASKED_PATHS: &[&str] = &[
+    "/proc/config.gz",
+    "/proc/kallsyms",
+    "/proc/kmsg",
+    "/proc/modules",
+    "/proc/pagetypeinfo",
+    "/proc/slabinfo",
+    "/proc/vmallocinfo",
+    "/proc/zoneinfo",
+    "/sys/devices/virtual/powercap",
+    "/sys/kernel/debug",
+];
+
+/// The paths which are additionally read-only in all containers in hardened mode.
+const HARDENED_READONLY_PATHS: &[&str] = &["/proc/driver", "/proc/sysvipc", "/proc/tty"];
+
+/// The pseudo filesystems which cannot be bind mounted in hardened mode, neither from the host nor
+/// over the ones of the container.
+const HARDENED_PROTECTED_PATHS: &[&str] = &["/proc", "/sys"];
+
+#[derive(Debug, Default)]
+/// SpecOptions are the inputs of a container spec apart from the CRI container config.
+pub struct SpecOptions<'a> {
+    /// The config of the image, which provides the defaults of the container process.
+    pub image: Option<&'a ImageConfig>,
+
+    /// The delegation granting the container a writable cgroup subtree, if any.
+    pub delegation: Option<&'a Delegation>,
+
+    /// The approved host devices which get injected into the container.
+    pub devices: &'a [Device],
+
+    /// The core dump policy of the container, which limits the size of its core dumps.
+    pub core_dump: Option<&'a CoreDumpPolicy>,
+
+    /// The kernel core pattern, which decides where the core dumps of the container go.
+    pub core_pattern: Option<&'a str>,
+
+    /// The low-level tuning requested via the allowed annotations of the pod.
+    pub tuning: Option<&'a Tuning>,
+
+    /// The hardened mode masks additional paths and rejects bind mounts of pseudo filesystems,
+    /// regardless of the security context of the container.
+    pub hardened: bool,
+}
+
+/// Build the OCI runtime spec for the container `config` running inside the `sandbox`, whereas
+/// the root filesystem of the container is located at `rootfs` and its cgroup at `cgroup_path`.
+/// The `options` provide the image config and the further inputs of the spec.
+pub fn container_spec(
+    config: &ContainerConfig,
+    sandbox: &SandboxData,
+    rootfs: &Path,
+    cgroup_path: &Path,
+    options: &SpecOptions,
+) -> Result<Spec> {
+    let security_context = config
+        .linux
+        .as_ref()
+        .and_then(|x| x.security_context.clone())
+        .unwrap_or_default();
+    let privileged = security_context.privileged;
+
+    let process = ContainerProcess::merge(config, options.image)?;
+    process.ensure_working_dir(rootfs)?;
+    let capabilities = if privileged {
+        all_capabilities()?
+    } else {
+        capabilities(&security_context)?
+    };
+    let mut process_builder = ProcessBuilder::default()
+        .terminal(config.tty)
+        .user(user(process.user())?)
+        .args(process.args().clone())
+        .env(process.env().clone())
+        .cwd(process.cwd().clone())
+        .capabilities(capabilities)
+        .no_new_privileges(security_context.no_new_privs);
+    if let Some(resources) = config.linux.as_ref().and_then(|x| x.resources.as_ref()) {
+        if resources.oom_score_adj != 0 {
+            process_builder = process_builder.oom_score_adj(resources.oom_score_adj as i32);
+        }
+    }
+    if let Some(policy) = options.core_dump {
+        process_builder = process_builder.rlimits(vec![policy.rlimit()?]);
+    }
+
+    let (masked_paths, readonly_paths) = if options.hardened {
+        verify_hardened_mounts(config)?;
+        (
+            union(
+                &security_context.masked_paths,
+                &[DEFAULT_MASKED_PATHS, HARDENED_MASKED_PATHS],
+            ),
+            union(
+                &security_context.readonly_paths,
+                &[DEFAULT_READONLY_PATHS, HARDENED_READONLY_PATHS],
+            ),
+        )
+    } else if privileged {
+        (vec![], vec![])
+    } else {
+        (
+            paths(&security_context.masked_paths, DEFAULT_MASKED_PATHS),
+            paths(&security_context.readonly_paths, DEFAULT_READONLY_PATHS),
+        )
+    };
+    let default_tuning = Tuning::default();
+    let tuning = options.tuning.unwrap_or(&default_tuning);
+    if let Some(mode) = tuning.userns_mode() {
+        if mode.splitn(2, ':').next() != Some("host") {
+            bail!("unsupported user namespace mode {}", mode)
+        }
+    }
+    let mut namespaces = namespaces(sandbox)?;
+    let mut mounts = mounts(config, sandbox, *tuning.shm_size())?;
+    if let Some(delegation) = options.delegation {
+        namespaces.push(delegation.namespace()?);
+        mounts.push(delegation.mount()?);
+    }
+    if let (Some(policy), Some(core_pattern)) = (options.core_dump, options.core_pattern) {
+        mounts.extend(policy.mount(core_pattern)?);
+    }
+    let mut linux_builder = LinuxBuilder::default()
+        .namespaces(namespaces)
+        .cgroups_path(cgroup_path)
+        .masked_paths(masked_paths)
+        .readonly_paths(readonly_paths);
+    let resources = config.linux.as_ref().and_then(|x| x.resources.as_ref());
+    if resources.is_some() || !options.devices.is_empty() || !tuning.unified().is_empty() {
+        linux_builder = linux_builder.resources(resources_spec(
+            resources,
+            options.devices,
+            tuning.unified(),
+        )?);
+    }
+    if !options.devices.is_empty() {
+        linux_builder = linux_builder.devices(
+            options
+                .devices
+                .iter()
+                .map(|x| x.node().clone())
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    let mut builder = SpecBuilder::default()
+        .process(
+            process_builder
+                .build()
+                .map_err(|e| format_err!("build process: {}", e))?,
+        )
+        .root(
+            RootBuilder::default()
+                .path(rootfs)
+                .readonly(security_context.readonly_rootfs)
+                .build()
+                .map_err(|e| format_err!("build root: {}", e))?,
+        )
+        .mounts(mounts)
+        .annotations(config.annotations.clone())
+        .linux(
+            linux_builder
+                .build()
+                .map_err(|e| format_err!("build linux config: {}", e))?,
+        );
+    if let Some(hostname) = sandbox.hostname() {
+        builder = builder.hostname(hostname.clone());
+    }
+    builder
+        .build()
+        .map_err(|e| format_err!("build OCI spec: {}", e))
+}
+
+/// Convert the `user[:group]` of the container process into an OCI user. Only numeric IDs are
+/// supported, because user names would have to be resolved inside the root filesystem.
+fn user(user: &str) -> Result<User> {
+    let mut split = user.splitn(2, ':');
+    let parse = |x: Option<&str>| -> Result<u32> {
+        match x.filter(|x| !x.is_empty()) {
+            Some(x) => x
+                .parse()
+                .map_err(|_| format_err!("unsupported non numeric user or group {}", x)),
+            None => Ok(0),
+        }
+    };
+    let uid = parse(split.next())?;
+    let gid = parse(split.next())?;
+    UserBuilder::default()
+        .uid(uid)
+        .gid(gid)
+        .build()
+        .map_err(|e| format_err!("build user: {}", e))
+}
+
+/// Retrieve the capabilities of an unprivileged container, which are the defaults modified by
+/// the capabilities of the security context.
+fn capabilities(context: &LinuxContainerSecurityContext) -> Result<LinuxCapabilities> {
+    let mut set: BTreeSet<String> = DEFAULT_CAPABILITIES.iter().map(|x| x.to_string()).collect();
+    if let Some(capabilities) = &context.capabilities {
+        for cap in &capabilities.drop_capabilities {
+            if cap.eq_ignore_ascii_case("ALL") {
+                set.clear();
+            } else {
+                set.remove(&capability_name(cap));
+            }
+        }
+        for cap in &capabilities.add_capabilities {
+            if cap.eq_ignore_ascii_case("ALL") {
+                bail!("adding all capabilities requires a privileged container")
+            }
+            set.insert(capability_name(cap));
+        }
+    }
+    capability_sets(set.into_iter().collect())
+}
+
+/// Retrieve all capabilities for privileged containers.
+fn all_capabilities() -> Result<LinuxCapabilities> {
+    capability_sets(
+        [
+            "CAP_AUDIT_CONTROL",
+            "CAP_AUDIT_READ",
+            "CAP_BLOCK_SUSPEND",
+            "CAP_DAC_READ_SEARCH",
+            "CAP_IPC_LOCK",
+            "CAP_IPC_OWNER",
+            "CAP_LEASE",
+            "CAP_LINUX_IMMUTABLE",
+            "CAP_MAC_ADMIN",
+            "CAP_MAC_OVERRIDE",
+            "CAP_NET_ADMIN",
+            "CAP_NET_BROADCAST",
+            "CAP_SYSLOG",
+            "CAP_SYS_ADMIN",
+            "CAP_SYS_BOOT",
+            "CAP_SYS_MODULE",
+            "CAP_SYS_NICE",
+            "CAP_SYS_PACCT",
+            "CAP_SYS_PTRACE",
+            "CAP_SYS_RAWIO",
+            "CAP_SYS_RESOURCE",
+            "CAP_SYS_TIME",
+            "CAP_SYS_TTY_CONFIG",
+            "CAP_WAKE_ALARM",
+        ]
+        .iter()
+        .chain(DEFAULT_CAPABILITIES)
+        .map(|x| x.to_string())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect(),
+    )
+}
+
+/// Normalize the CRI capability `name` into the `CAP_` prefixed OCI format.
+fn capability_name(name: &str) -> String {
+    let name = name.to_uppercase();
+    if name.starts_with("CAP_") {
+        name
+    } else {
+        format!("CAP_{}", name)
+    }
+}
+
+/// Use the capabilities `caps` for all sets apart from the ambient one.
+fn capability_sets(caps: Vec<String>) -> Result<LinuxCapabilities> {
+    LinuxCapabilitiesBuilder::default()
+        .bounding(caps.clone())
+        .effective(caps.clone())
+        .inheritable(caps.clone())
+        .permitted(caps)
+        .build()
+        .map_err(|e| format_err!("build capabilities: {}", e))
+}
+
+/// Retrieve the requested `paths`, or the `defaults` if none are requested.
+fn paths(paths: &[String], defaults: &[&str]) -> Vec<String> {
+    if paths.is_empty() {
+        defaults.iter().map(|x| x.to_string()).collect()
+    } else {
+        paths.to_vec()
+    }
+}
+
+/// Retrieve the sorted union of `paths` and all `defaults`, which cannot be unmasked by
+/// requesting an empty list.
+fn union(paths: &[String], defaults: &[&[&str]]) -> Vec<String> {
+    let mut set: BTreeSet<String> = paths.iter().cloned().collect();
+    set.extend(
+        defaults
+            .iter()
+            .flat_map(|x| x.iter())
+            .map(|x| x.to_string()),
+    );
+    set.into_iter().collect()
+}
+
+/// Verify that the mounts of the container `config` neither expose nor shadow the pseudo
+/// filesystems protected in hardened mode.
+fn verify_hardened_mounts(config: &ContainerConfig) -> Result<()> {
+    let protected = |path: &str| {
+        HARDENED_PROTECTED_PATHS
+            .iter()
+            .any(|x| Path::new(path).starts_with(x))
+    };
+    for m in &config.mounts {
+        if protected(&m.container_path) || protected(&m.host_path) {
+            bail!(
+                "mounting {} to {} is not allowed in hardened mode",
+                m.host_path,
+                m.container_path
+            )
+        }
+    }
+    Ok(())
+}
+
+/// Retrieve the namespaces of the container, which shares all host namespaces of the `sandbox`
+/// and joins its pinned network, IPC, UTS and PID namespaces, if any. All other namespaces are
+/// created for the container alone.
+fn namespaces(sandbox: &SandboxData) -> Result<Vec<LinuxNamespace>> {
+    let mut types = vec![LinuxNamespaceType::Mount];
+    if !*sandbox.host_network() {
+        types.push(LinuxNamespaceType::Network);
+        types.push(LinuxNamespaceType::Uts);
+    }
+    if !*sandbox.host_ipc() {
+        types.push(LinuxNamespaceType::Ipc);
+    }
+    if *sandbox.pid_mode() != NamespaceMode::Node as i32 {
+        types.push(LinuxNamespaceType::Pid);
+    }
+    types
+        .into_iter()
+        .map(|typ| {
+            let mut builder = LinuxNamespaceBuilder::default().typ(typ);
+            let pinned = match typ {
+                LinuxNamespaceType::Network => sandbox.netns().as_ref(),
+                LinuxNamespaceType::Ipc => sandbox.ipc_ns().as_ref(),
+                LinuxNamespaceType::Uts => sandbox.uts_ns().as_ref(),
+                LinuxNamespaceType::Pid => sandbox.pid_ns().as_ref(),
+                _ => None,
+            };
+            if let Some(path) = pinned {
+                builder = builder.path(path.clone());
+            }
+            builder
+                .build()
+                .map_err(|e| format_err!("build namespace: {}", e))
+        })
+        .collect()
+}
+
+/// Retrieve the default mounts of every container followed by the mounts of the container
+/// `config`. The `/dev/shm` of the container uses the `shm_size` in bytes, if set.
+fn mounts(
+    config: &ContainerConfig,
+    sandbox: &SandboxData,
+    shm_size: Option<u64>,
+) -> Result<Vec<Mount>> {
+    let mount = |destination: &str, typ: &str, source: &str, options: &[&str]| {
+        MountBuilder::default()
+            .destination(destination)
+            .typ(typ)
+            .source(source)
+            .options(options.iter().map(|x| x.to_string()).collect::<Vec<_>>())
+            .build()
+            .map_err(|e| format_err!("build mount {}: {}", destination, e))
+    };
+
+    let shm_size = shm_size.map_or_else(|| "size=65536k".into(), |x| format!("size={}", x));
+    let mut mounts = vec![
+        mount("/proc", "proc", "proc", &["nosuid", "noexec", "nodev"])?,
+        mount(
+            "/dev",
+            "tmpfs",
+            "tmpfs",
+            &["nosuid", "strictatime", "mode=755", "size=65536k"],
+        )?,
+        mount(
+            "/dev/pts",
+            "devpts",
+            "devpts",
+            &[
+                "nosuid",
+                "noexec",
+                "newinstance",
+                "ptmxmode=0666",
+                "mode=0620",
+            ],
+        )?,
+        mount(
+            "/dev/shm",
+            "tmpfs",
+            "shm",
+            &["nosuid", "noexec", "nodev", "mode=1777", &shm_size],
+        )?,
+        mqueue_mount(*sandbox.host_ipc())?,
+        mount(
+            "/sys",
+            "sysfs",
+            "sysfs",
+            &["nosuid", "noexec", "nodev", "ro"],
+        )?,
+    ];
+
+    // The containers share the resolv.conf of the sandbox, unless the config mounts its own one
+    if let Some(resolv_conf) = sandbox.resolv_conf() {
+        if !config
+            .mounts
+            .iter()
+            .any(|x| Path::new(&x.container_path) == Path::new(RESOLV_CONF_PATH))
+        {
+            let readonly = config
+                .linux
+                .as_ref()
+                .and_then(|x| x.security_context.as_ref())
+                .map_or(false, |x| x.readonly_rootfs);
+            mounts.push(mount(
+                RESOLV_CONF_PATH,
+                "bind",
+                &resolv_conf.to_string_lossy(),
+                &["rbind", if readonly { "ro" } else { "rw" }],
+            )?);
+        }
+    }
+
+    for m in &config.mounts {
+        if !Path::new(&m.container_path).is_absolute() {
+            bail!("mount destination {} is not absolute", m.container_path)
+        }
+        let access = if m.readonly { "ro" } else { "rw" };
+        mounts.push(mount(
+            &m.container_path,
+            "bind",
+            &m.host_path,
+            &["rbind", access],
+        )?);
+    }
+    Ok(mounts)
+}
+
+/// Convert the CRI container `resources` into OCI resources, which allow the access to the
+/// `devices` and set the `unified` cgroup v2 resources.
+fn resources_spec(
+    resources: Option<&LinuxContainerResources>,
+    devices: &[Device],
+    unified: &HashMap<String, String>,
+) -> Result<LinuxResources> {
+    let default = LinuxContainerResources::default();
+    let resources = resources.unwrap_or(&default);
+    let mut cpu = LinuxCPUBuilder::default();
+    if resources.cpu_shares > 0 {
+        cpu = cpu.shares(resources.cpu_shares as u64);
+    }
+    if resources.cpu_quota > 0 {
+        cpu = cpu.quota(resources.cpu_quota);
+    }
+    if resources.cpu_period > 0 {
+        cpu = cpu.period(resources.cpu_period as u64);
+    }
+    if !resources.cpuset_cpus.is_empty() {
+        cpu = cpu.cpus(resources.cpuset_cpus.clone());
+    }
+    if !resources.cpuset_mems.is_empty() {
+        cpu = cpu.mems(resources.cpuset_mems.clone());
+    }
+
+    let mut memory = LinuxMemoryBuilder::default();
+    if resources.memory_limit_in_bytes > 0 {
+        memory = memory.limit(resources.memory_limit_in_bytes);
+    }
+
+    let mut builder = LinuxResourcesBuilder::default();
+    if !devices.is_empty() {
+        builder = builder.devices(
+            devices
+                .iter()
+                .map(|x| x.cgroup().clone())
+                .collect::<Vec<_>>(),
+        );
+    }
+    if !unified.is_empty() {
+        builder = builder.unified(unified.clone());
+    }
+    builder
+        .cpu(cpu.build().map_err(|e| format_err!("build CPU: {}", e))?)
+        .memory(
+            memory
+                .build()
+                .map_err(|e| format_err!("build memory: {}", e))?,
+        )
+        .build()
+        .map_err(|e| format_err!("build resources: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        annotations::USERNS_MODE_ANNOTATION,
+        criapi::{Capability, LinuxContainerConfig, Mount as CriMount},
+        sandbox::SandboxDataBuilder,
+    };
+    use anyhow::Context;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    fn sandbox(host_network: bool) -> Result<SandboxData> {
+        SandboxDataBuilder::default()
+            .id("id")
+            .name("name")
+            .namespace("namespace")
+            .attempt(0u32)
+            .hostname(Some("hostname".into()))
+            .host_network(host_network)
+            .build()
+            .map_err(|e| format_err!("build sandbox data: {}", e))
+    }
+
+    fn config(context: LinuxContainerSecurityContext) -> ContainerConfig {
+        ContainerConfig {
+            command: vec!["/bin/sh".into()],
+            working_dir: "/work".into(),
+            linux: Some(LinuxContainerConfig {
+                resources: Some(LinuxContainerResources {
+                    cpu_shares: 512,
+                    memory_limit_in_bytes: 1024,
+                    ..Default::default()
+                }),
+                security_context: Some(context),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn container_spec_success() -> Result<()> {
+        let dir = tempdir()?;
+        let spec = container_spec(
+            &config(LinuxContainerSecurityContext::default()),
+            &sandbox(false)?,
+            dir.path(),
+            Path::new("/cri/id/container"),
+            &SpecOptions::default(),
+        )?;
+
+        assert!(dir.path().join("work").exists());
+        assert_eq!(spec.hostname().as_deref(), Some("hostname"));
+        assert_eq!(
+            spec.root().as_ref().map(|x| x.path().clone()),
+            Some(dir.path().to_path_buf())
+        );
+
+        let process = spec.process().as_ref().context("no process")?;
+        assert_eq!(
+            process.args().as_deref(),
+            Some(&["/bin/sh".to_string()][..])
+        );
+        assert_eq!(process.cwd(), "/work");
+        assert_eq!(process.user().uid(), 0);
+
+        let linux = spec.linux().as_ref().context("no linux")?;
+        assert_eq!(linux.namespaces().as_ref().map(|x| x.len()), Some(5));
+        assert_eq!(
+            linux.cgroups_path().as_deref(),
+            Some(Path::new("/cri/id/container"))
+        );
+        let resources = linux.resources().as_ref().context("no resources")?;
+        assert_eq!(
+            resources.cpu().as_ref().and_then(|x| *x.shares()),
+            Some(512)
+        );
+        assert_eq!(
+            resources.memory().as_ref().and_then(|x| *x.limit()),
+            Some(1024)
+        );
+        assert!(linux
+            .masked_paths()
+            .as_ref()
+            .map_or(false, |x| x.contains(&"/proc/kcore".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn container_spec_host_network() -> Result<()> {
+        let dir = tempdir()?;
+        let spec = container_spec(
+            &config(LinuxContainerSecurityContext::default()),
+            &sandbox(true)?,
+            dir.path(),
+            Path::new("/cri/id/container"),
+            &SpecOptions::default(),
+        )?;
+        let namespaces = spec
+            .linux()
+            .as_ref()
+            .and_then(|x| x.namespaces().as_ref())
+            .context("no namespaces")?;
+        assert_eq!(namespaces.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn container_spec_pinned_network() -> Result<()> {
+        let dir = tempdir()?;
+        let sandbox = SandboxDataBuilder::default()
+            .id("id")
+            .name("name")
+            .namespace("namespace")
+            .attempt(0u32)
+            .netns(Some(PathBuf::from("/run/netns/id")))
+            .build()
+            .map_err(|e| format_err!("build sandbox data: {}", e))?;
+        let spec = container_spec(
+            &config(LinuxContainerSecurityContext::default()),
+            &sandbox,
+            dir.path(),
+            Path::new("/cri/id/container"),
+            &SpecOptions::default(),
+        )?;
+        let namespaces = spec
+            .linux()
+            .as_ref()
+            .and_then(|x| x.namespaces().as_ref())
+            .context("no namespaces")?;
+        let network = namespaces
+            .iter()
+            .find(|x| matches!(x.typ(), LinuxNamespaceType::Network))
+            .context("no network namespace")?;
+        assert_eq!(network.path().as_deref(), Some(Path::new("/run/netns/id")));
+        assert!(namespaces
+            .iter()
+            .filter(|x| !matches!(x.typ(), LinuxNamespaceType::Network))
+            .all(|x| x.path().is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn container_spec_pinned_namespaces() -> Result<()> {
+        let dir = tempdir()?;
+        let sandbox = SandboxDataBuilder::default()
+            .id("id")
+            .name("name")
+            .namespace("namespace")
+            .attempt(0u32)
+            .ipc_ns(Some(PathBuf::from("/run/sandboxes/id/ns/ipc")))
+            .uts_ns(Some(PathBuf::from("/run/sandboxes/id/ns/uts")))
+            .pid_ns(Some(PathBuf::from("/run/sandboxes/id/ns/pid")))
+            .pid_mode(NamespaceMode::Pod as i32)
+            .build()
+            .map_err(|e| format_err!("build sandbox data: {}", e))?;
+        let spec = container_spec(
+            &config(LinuxContainerSecurityContext::default()),
+            &sandbox,
+            dir.path(),
+            Path::new("/cri/id/container"),
+            &SpecOptions::default(),
+        )?;
+        let namespaces = spec
+            .linux()
+            .as_ref()
+            .and_then(|x| x.namespaces().as_ref())
+            .context("no namespaces")?;
+        let path = |typ: fn(&LinuxNamespaceType) -> bool| {
+            namespaces
+                .iter()
+                .find(|x| typ(x.typ()))
+                .and_then(|x| x.path().clone())
+        };
+        assert_eq!(
+            path(|x| matches!(x, LinuxNamespaceType::Ipc)),
+            Some(PathBuf::from("/run/sandboxes/id/ns/ipc"))
+        );
+        assert_eq!(
+            path(|x| matches!(x, LinuxNamespaceType::Uts)),
+            Some(PathBuf::from("/run/sandboxes/id/ns/uts"))
+        );
+        assert_eq!(
+            path(|x| matches!(x, LinuxNamespaceType::Pid)),
+            Some(PathBuf::from("/run/sandboxes/id/ns/pid"))
+        );
+        assert_eq!(path(|x| matches!(x, LinuxNamespaceType::Mount)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn container_spec_delegated_cgroup() -> Result<()> {
+        let dir = tempdir()?;
+        let spec = container_spec(
+            &config(LinuxContainerSecurityContext::default()),
+            &sandbox(false)?,
+            dir.path(),
+            Path::new("/cri/id/container"),
+            &SpecOptions {
+                delegation: Some(&Delegation::default()),
+                ..Default::default()
+            },
+        )?;
+        assert!(spec
+            .linux()
+            .as_ref()
+            .and_then(|x| x.namespaces().as_ref())
+            .map_or(false, |x| x
+                .iter()
+                .any(|x| matches!(x.typ(), LinuxNamespaceType::Cgroup))));
+        assert!(spec.mounts().as_ref().map_or(false, |x| x
+            .iter()
+            .any(|x| x.destination() == Path::new("/sys/fs/cgroup"))));
+        Ok(())
+    }
+
+    #[test]
+    fn container_spec_fail_userns_mode() -> Result<()> {
+        let dir = tempdir()?;
+        let mut annotations = HashMap::new();
+        annotations.insert(USERNS_MODE_ANNOTATION.to_string(), "auto".to_string());
+        let tuning =
+            Tuning::from_annotations(&annotations, "name", &[USERNS_MODE_ANNOTATION.into()])?;
+        assert!(container_spec(
+            &config(LinuxContainerSecurityContext::default()),
+            &sandbox(false)?,
+            dir.path(),
+            Path::new("/cri/id/container"),
+            &SpecOptions {
+                tuning: Some(&tuning),
+                ..Default::default()
+            },
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn container_spec_privileged() -> Result<()> {
+        let dir = tempdir()?;
+        let spec = container_spec(
+            &config(LinuxContainerSecurityContext {
+                privileged: true,
+                ..Default::default()
+            }),
+            &sandbox(false)?,
+            dir.path(),
+            Path::new("/cri/id/container"),
+            &SpecOptions::default(),
+        )?;
+        let process = spec.process().as_ref().context("no process")?;
+        let caps = process
+            .capabilities()
+            .as_ref()
+            .and_then(|x| x.bounding().as_ref())
+            .context("no capabilities")?;
+        assert!(caps.contains(&"CAP_SYS_ADMIN".to_string()));
+        assert!(spec
+            .linux()
+            .as_ref()
+            .and_then(|x| x.masked_paths().as_ref())
+            .map_or(true, |x| x.is_empty()));
+        Ok(())
+    }
+
+    #[test]
+    fn container_spec_hardened() -> Result<()> {
+        let dir = tempdir()?;
+        let mut config = config(LinuxContainerSecurityContext {
+            privileged: true,
+            masked_paths: vec!["/custom".into()],
+            ..Default::default()
+        });
+        let spec = container_spec(
+            &config,
+            &sandbox(false)?,
+            dir.path(),
+            Path::new("/cri/id/container"),
+            &SpecOptions {
+                hardened: true,
+                ..Default::default()
+            },
+        )?;
+        let linux = spec.linux().as_ref().context("no linux")?;
+        let masked = linux.masked_paths().as_ref().context("no masked paths")?;
+        for path in &["/custom", "/proc/kcore", "/proc/kallsyms", "/sys/firmware"] {
+            assert!(masked.contains(&path.to_string()));
+        }
+        let readonly = linux
+            .readonly_paths()
+            .as_ref()
+            .context("no readonly paths")?;
+        assert!(readonly.contains(&"/proc/sys".to_string()));
+        assert!(readonly.contains(&"/proc/tty".to_string()));
+
+        for (host_path, container_path) in &[("/proc", "/host/proc"), ("/data", "/sys/kernel")] {
+            config.mounts = vec![CriMount {
+                container_path: container_path.to_string(),
+                host_path: host_path.to_string(),
+                ..Default::default()
+            }];
+            assert!(container_spec(
+                &config,
+                &sandbox(false)?,
+                dir.path(),
+                Path::new("/cri/id/container"),
+                &SpecOptions {
+                    hardened: true,
+                    ..Default::default()
+                }
+            )
+            .is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn container_spec_fail_user_name() -> Result<()> {
+        let dir = tempdir()?;
+        let config = config(LinuxContainerSecurityContext {
+            run_as_username: "nobody".into(),
+            ..Default::default()
+        });
+        assert!(container_spec(
+            &config,
+            &sandbox(false)?,
+            dir.path(),
+            Path::new("/cri/id/container"),
+            &SpecOptions::default()
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn user_success() -> Result<()> {
+        let user = user("1000:2000")?;
+        assert_eq!(user.uid(), 1000);
+        assert_eq!(user.gid(), 2000);
+
+        let user = super::user("")?;
+        assert_eq!(user.uid(), 0);
+        assert_eq!(user.gid(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn capabilities_success() -> Result<()> {
+        let caps = capabilities(&LinuxContainerSecurityContext {
+            capabilities: Some(Capability {
+                add_capabilities: vec!["sys_admin".into()],
+                drop_capabilities: vec!["ALL".into()],
+            }),
+            ..Default::default()
+        })?;
+        assert_eq!(
+            caps.bounding().as_deref(),
+            Some(&["CAP_SYS_ADMIN".to_string()][..])
+        );
+
+        assert!(capabilities(&LinuxContainerSecurityContext {
+            capabilities: Some(Capability {
+                add_capabilities: vec!["ALL".into()],
+                drop_capabilities: vec![],
+            }),
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mounts_success() -> Result<()> {
+        let mut config = ContainerConfig::default();
+        config.mounts.push(CriMount {
+            container_path: "/data".into(),
+            host_path: "/host/data".into(),
+            readonly: true,
+            ..Default::default()
+        });
+        let mounts = mounts(&config, &sandbox(false)?, None)?;
+        let data = mounts.last().context("no mounts")?;
+        assert_eq!(data.destination(), &PathBuf::from("/data"));
+        assert_eq!(data.source().as_deref(), Some(Path::new("/host/data")));
+        assert_eq!(
+            data.options().as_deref(),
+            Some(&["rbind".to_string(), "ro".to_string()][..])
+        );
+
+        config.mounts[0].container_path = "data".into();
+        assert!(super::mounts(&config, &sandbox(false)?, None).is_err());
+        Ok(())
+    }
+}
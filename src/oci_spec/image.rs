@@ -0,0 +1,304 @@
+//! OCI image spec
+
+use anyhow::{Context, Result};
+use derive_builder::Builder;
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs::File, path::Path};
+
+#[derive(Serialize, Deserialize, Debug, Default, Builder, Getters)]
+#[builder(default, pattern = "owned", setter(into, strip_option))]
+/// Image is the JSON structure which describes some basic information about the image.
+pub struct Image {
+    #[getset(get = "pub")]
+    /// Architecture is the CPU architecture which the binaries in this image are built to run on.
+    architecture: String,
+
+    #[getset(get = "pub")]
+    /// OS is the name of the operating system which the image is built to run on.
+    os: String,
+
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Config defines the execution parameters which should be used as a base when running a
+    /// container using the image.
+    config: Option<ImageConfig>,
+}
+
+impl Image {
+    /// Load a new image config from the provided file `Path`
+    pub fn from(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("open file {}", path.display()))?;
+        serde_json::from_reader(file)
+            .with_context(|| format!("deserialize OCI image from file {}", path.display()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Builder, Getters)]
+#[builder(default, pattern = "owned", setter(into, strip_option))]
+/// ImageConfig defines the execution parameters which should be used as a base when running a
+/// container using an image.
+pub struct ImageConfig {
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "User")]
+    /// User defines the username or UID which the process in the container should run as.
+    user: Option<String>,
+
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "Env")]
+    /// Env is a list of environment variables to be used in a container.
+    env: Option<Vec<String>>,
+
+    #[getset(get = "pub")]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "Entrypoint"
+    )]
+    /// Entrypoint defines a list of arguments to use as the command to execute when the container
+    /// starts.
+    entrypoint: Option<Vec<String>>,
+
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "Cmd")]
+    /// Cmd defines the default arguments to the entrypoint of the container.
+    cmd: Option<Vec<String>>,
+
+    #[getset(get = "pub")]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "WorkingDir"
+    )]
+    /// WorkingDir sets the current working directory of the entrypoint process in the container.
+    working_dir: Option<String>,
+
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "Labels")]
+    /// Labels contains arbitrary metadata for the container.
+    labels: Option<HashMap<String, String>>,
+
+    #[getset(get = "pub")]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "StopSignal"
+    )]
+    /// StopSignal contains the system call signal that will be sent to the container to exit.
+    stop_signal: Option<String>,
+}
+
+/// The media type of OCI image manifests.
+pub const MEDIA_TYPE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// The media type of OCI image indexes.
+pub const MEDIA_TYPE_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+
+/// The media type of OCI image configs.
+pub const MEDIA_TYPE_IMAGE_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+
+/// The media type of uncompressed OCI layers.
+pub const MEDIA_TYPE_LAYER: &str = "application/vnd.oci.image.layer.v1.tar";
+
+/// The media type of gzip compressed OCI layers.
+pub const MEDIA_TYPE_LAYER_GZIP: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+
+/// The media type of zstd compressed OCI layers.
+pub const MEDIA_TYPE_LAYER_ZSTD: &str = "application/vnd.oci.image.layer.v1.tar+zstd";
+
+/// The media type of Docker image manifests, which are compatible with OCI image manifests.
+pub const MEDIA_TYPE_DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+/// The media type of Docker manifest lists, which are compatible with OCI image indexes.
+pub const MEDIA_TYPE_DOCKER_MANIFEST_LIST: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, Builder, Getters)]
+#[builder(default, pattern = "owned", setter(into, strip_option))]
+#[serde(rename_all = "camelCase")]
+/// Descriptor references a content addressed blob, like a layer or a manifest.
+pub struct Descriptor {
+    #[getset(get = "pub")]
+    #[serde(default)]
+    /// MediaType describes the type of the referenced content.
+    media_type: String,
+
+    #[getset(get = "pub")]
+    /// Digest is the content digest of the referenced blob, like `sha256:abc…`.
+    digest: String,
+
+    #[getset(get = "pub")]
+    /// Size is the size of the referenced blob in bytes.
+    size: u64,
+
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Platform describes the platform of a manifest referenced by an index.
+    platform: Option<Platform>,
+
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    /// Annotations contains arbitrary metadata of the referenced content.
+    annotations: HashMap<String, String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, Builder, Getters)]
+#[builder(default, pattern = "owned", setter(into, strip_option))]
+#[serde(rename_all = "camelCase")]
+/// Platform describes the platform an image manifest is built for.
+pub struct Platform {
+    #[getset(get = "pub")]
+    /// Architecture is the CPU architecture, like `amd64`.
+    architecture: String,
+
+    #[getset(get = "pub")]
+    /// OS is the operating system, like `linux`.
+    os: String,
+
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Variant is the variant of the CPU architecture, like `v7` for `arm`.
+    variant: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, Builder, Getters)]
+#[builder(default, pattern = "owned", setter(into, strip_option))]
+#[serde(rename_all = "camelCase")]
+/// Manifest describes the config and the layers of a single image.
+pub struct Manifest {
+    #[getset(get = "pub")]
+    #[serde(default)]
+    /// SchemaVersion is the version of the manifest format, which is 2 for OCI manifests.
+    schema_version: u32,
+
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// MediaType is the media type of the manifest itself.
+    media_type: Option<String>,
+
+    #[getset(get = "pub")]
+    /// Config references the image config.
+    config: Descriptor,
+
+    #[getset(get = "pub")]
+    /// Layers references the layers of the image, starting with the base layer.
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, Builder, Getters)]
+#[builder(default, pattern = "owned", setter(into, strip_option))]
+#[serde(rename_all = "camelCase")]
+/// Index references the manifests of an image for multiple platforms.
+pub struct Index {
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// MediaType is the media type of the index itself.
+    media_type: Option<String>,
+
+    #[getset(get = "pub")]
+    /// Manifests references the image manifests per platform.
+    manifests: Vec<Descriptor>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn from_success() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        write!(
+            file,
+            r#"{{
+                "architecture": "amd64",
+                "os": "linux",
+                "config": {{
+                    "User": "nobody",
+                    "Env": ["PATH=/usr/bin"],
+                    "Entrypoint": ["/entrypoint.sh"],
+                    "Cmd": ["serve"],
+                    "WorkingDir": "/app",
+                    "StopSignal": "SIGQUIT"
+                }}
+            }}"#
+        )?;
+
+        let image = Image::from(file.path())?;
+        assert_eq!(image.architecture(), "amd64");
+        assert_eq!(image.os(), "linux");
+
+        let config = image.config().as_ref().context("no config")?;
+        assert_eq!(config.user().as_deref(), Some("nobody"));
+        assert_eq!(
+            config.env().as_deref(),
+            Some(&["PATH=/usr/bin".to_string()][..])
+        );
+        assert_eq!(
+            config.entrypoint().as_deref(),
+            Some(&["/entrypoint.sh".to_string()][..])
+        );
+        assert_eq!(config.cmd().as_deref(), Some(&["serve".to_string()][..]));
+        assert_eq!(config.working_dir().as_deref(), Some("/app"));
+        assert_eq!(config.stop_signal().as_deref(), Some("SIGQUIT"));
+        assert!(config.labels().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_success() -> Result<()> {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                "config": {
+                    "mediaType": "application/vnd.docker.container.image.v1+json",
+                    "digest": "sha256:abc",
+                    "size": 1024
+                },
+                "layers": [{
+                    "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                    "digest": "sha256:def",
+                    "size": 2048
+                }]
+            }"#,
+        )?;
+        assert_eq!(
+            manifest.media_type().as_deref(),
+            Some(MEDIA_TYPE_DOCKER_MANIFEST)
+        );
+        assert_eq!(*manifest.schema_version(), 2);
+        assert_eq!(manifest.config().digest(), "sha256:abc");
+        assert_eq!(manifest.layers().len(), 1);
+        assert_eq!(*manifest.layers()[0].size(), 2048);
+        Ok(())
+    }
+
+    #[test]
+    fn index_success() -> Result<()> {
+        let index: Index = serde_json::from_str(
+            r#"{
+                "schemaVersion": 2,
+                "manifests": [{
+                    "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                    "digest": "sha256:abc",
+                    "size": 512,
+                    "platform": {"architecture": "arm", "os": "linux", "variant": "v7"}
+                }]
+            }"#,
+        )?;
+        let platform = index.manifests()[0]
+            .platform()
+            .as_ref()
+            .context("no platform")?;
+        assert_eq!(platform.architecture(), "arm");
+        assert_eq!(platform.variant().as_deref(), Some("v7"));
+        Ok(())
+    }
+
+    #[test]
+    fn from_failure_no_file() {
+        assert!(Image::from(Path::new("/some/invalid/path")).is_err())
+    }
+}
@@ -1,3 +1,4 @@
 //! OCI runtime and image spec
 
+pub mod image;
 pub mod runtime;
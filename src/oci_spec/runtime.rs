@@ -112,11 +112,13 @@ impl Spec {
 #[builder(pattern = "owned", setter(into, strip_option))]
 /// Process contains information to start a specific application inside the container.
 pub struct Process {
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Terminal creates an interactive terminal for the container.
     terminal: Option<bool>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(
         default,
@@ -130,11 +132,13 @@ pub struct Process {
     #[getset(get = "pub")]
     user: User,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Args specifies the binary and arguments for the application to execute.
     args: Option<Vec<String>>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(
         default,
@@ -144,6 +148,7 @@ pub struct Process {
     /// CommandLine specifies the full command line for the application to execute on Windows.
     command_line: Option<String>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Env populates the process environment for the process.
@@ -154,16 +159,19 @@ pub struct Process {
     /// container's root.
     cwd: String,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Capabilities are Linux capabilities that are kept for the process.
     capabilities: Option<LinuxCapabilities>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Rlimits specifies rlimit options to apply to the process.
     rlimits: Option<Vec<POSIXRlimit>>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(
         default,
@@ -174,6 +182,7 @@ pub struct Process {
     /// container.
     no_new_privileges: Option<bool>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(
         default,
@@ -183,11 +192,13 @@ pub struct Process {
     /// ApparmorProfile specifies the apparmor profile for the container.
     apparmor_profile: Option<String>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(skip_serializing_if = "Option::is_none", rename = "oomScoreAdj")]
     /// Specify an oom_score_adj for the container.
     oom_score_adj: Option<i32>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(
         default,
@@ -242,7 +253,7 @@ pub struct Box {
 }
 
 /// User specifies specific user (and group) information for the container process.
-#[derive(Serialize, Deserialize, Debug, Builder, Getters)]
+#[derive(Serialize, Deserialize, Debug, Builder, CopyGetters, Getters)]
 #[builder(pattern = "owned", setter(into, strip_option))]
 pub struct User {
     #[getset(get_copy = "pub")]
@@ -253,11 +264,13 @@ pub struct User {
     /// GID is the group id.
     gid: u32,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Umask is the umask for the init process.
     umask: Option<u32>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(
         default,
@@ -267,6 +280,7 @@ pub struct User {
     /// AdditionalGids are additional group ids set for the container's process.
     additional_gids: Option<Vec<u32>>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Username is the user name.
@@ -281,6 +295,7 @@ pub struct Root {
     /// Path is the absolute path to the container's root filesystem.
     path: PathBuf,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Readonly makes the root filesystem for the container readonly before the process is
@@ -296,16 +311,19 @@ pub struct Mount {
     /// Destination is the absolute path where the mount will be placed in the container.
     destination: PathBuf,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none", rename = "type")]
     /// Type specifies the mount kind.
     typ: Option<String>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Source specifies the source path of the mount.
     source: Option<PathBuf>,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Options are fstab style mount options.
@@ -503,6 +521,7 @@ pub struct LinuxNamespace {
     /// Type is the type of namespace.
     typ: LinuxNamespaceType,
 
+    #[builder(default)]
     #[getset(get = "pub")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     /// Path is a path to an existing namespace persisted on disk that can be joined and is of the
@@ -528,7 +547,7 @@ pub enum LinuxNamespaceType {
     /// For isolating System V IPC, POSIX message queues.
     Ipc,
 
-    #[serde(rename = "utc")]
+    #[serde(rename = "uts")]
     /// For isolating hostname and NIS domain name.
     Uts,
 
@@ -886,7 +905,7 @@ pub struct LinuxResources {
     unified: Option<HashMap<String, String>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Builder, CopyGetters, Getters)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder, CopyGetters, Getters)]
 #[builder(pattern = "owned", setter(into, strip_option))]
 /// LinuxDevice represents the mknod information for a Linux special device file.
 pub struct LinuxDevice {
@@ -923,7 +942,7 @@ pub struct LinuxDevice {
     gid: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Builder, CopyGetters, Getters)]
+#[derive(Serialize, Deserialize, Clone, Debug, Builder, CopyGetters, Getters)]
 #[builder(pattern = "owned", setter(into, strip_option))]
 /// LinuxDeviceCgroup represents a device rule for the devices specified to the device controller.
 pub struct LinuxDeviceCgroup {
@@ -0,0 +1,500 @@
+//! Quotas on the number of pod sandboxes and containers per Kubernetes namespace and runtime
+//! handler.
+//!
+//! Quotas protect shared nodes from a single tenant creating pod sandboxes or containers without
+//! bounds, for example a runaway controller. Every existing pod sandbox and container counts
+//! towards the quotas of its namespace and runtime handler until it gets removed, no matter if it
+//! is still running. Creations exceeding a quota are rejected. Creations which passed the quotas
+//! but are not finished yet hold a reservation, which counts towards the quotas as well, so that
+//! concurrent creations cannot exceed a quota together.
+
+use crate::{config::Config, container::Container, sandbox::SandboxData};
+use anyhow::{bail, format_err, Context, Error, Result};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    error, fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// The scope of a quota applying to every namespace or runtime handler without a dedicated one.
+const WILDCARD: &str = "*";
+
+/// The scope of the quota of the default runtime handler, which has an empty name.
+const DEFAULT_HANDLER: &str = "default";
+
+#[derive(Clone, CopyGetters, Debug, Deserialize, Eq, Getters, PartialEq, Serialize)]
+/// QuotaLimit is the maximum number of pod sandboxes and containers of a single scope.
+pub struct QuotaLimit {
+    #[get = "pub"]
+    /// The namespace or runtime handler, or `*` for all without a dedicated quota.
+    scope: String,
+
+    #[get_copy = "pub"]
+    /// The maximum number of pod sandboxes, whereas `0` means unlimited.
+    sandboxes: usize,
+
+    #[get_copy = "pub"]
+    /// The maximum number of containers, whereas `0` means unlimited.
+    containers: usize,
+}
+
+impl FromStr for QuotaLimit {
+    type Err = Error;
+
+    /// Parse a quota in the format `SCOPE=SANDBOXES:CONTAINERS`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '=');
+        let (scope, limits) = match (parts.next(), parts.next()) {
+            (Some(scope), Some(limits)) if !scope.is_empty() => (scope, limits),
+            _ => bail!("invalid quota {}, expected SCOPE=SANDBOXES:CONTAINERS", s),
+        };
+        let mut limits = limits.splitn(2, ':');
+        match (limits.next(), limits.next()) {
+            (Some(sandboxes), Some(containers)) => Ok(Self {
+                scope: scope.into(),
+                sandboxes: sandboxes
+                    .parse()
+                    .with_context(|| format!("parse pod sandbox quota of {}", scope))?,
+                containers: containers
+                    .parse()
+                    .with_context(|| format!("parse container quota of {}", scope))?,
+            }),
+            _ => bail!("invalid quota {}, expected SCOPE=SANDBOXES:CONTAINERS", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The kind of object counted by a quota.
+pub enum QuotaKind {
+    /// A pod sandbox.
+    PodSandbox,
+
+    /// A container.
+    Container,
+}
+
+impl fmt::Display for QuotaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaKind::PodSandbox => write!(f, "pod_sandbox"),
+            QuotaKind::Container => write!(f, "container"),
+        }
+    }
+}
+
+#[derive(Debug, CopyGetters, Getters)]
+/// QuotaExceeded is returned if a creation would exceed a quota.
+pub struct QuotaExceeded {
+    #[get_copy = "pub"]
+    /// The scope of the quota, like `namespace` or `handler`.
+    scope: &'static str,
+
+    #[get = "pub"]
+    /// The namespace or runtime handler the quota applies to.
+    name: String,
+
+    #[get_copy = "pub"]
+    /// The kind of the rejected object.
+    kind: QuotaKind,
+
+    #[get_copy = "pub"]
+    /// The exceeded limit.
+    limit: usize,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            QuotaKind::PodSandbox => "pod sandboxes",
+            QuotaKind::Container => "containers",
+        };
+        let scope = match self.scope {
+            "handler" => "runtime handler",
+            scope => scope,
+        };
+        write!(
+            f,
+            "quota of {} {} for {} {} exceeded",
+            self.limit, kind, scope, self.name
+        )
+    }
+}
+
+impl error::Error for QuotaExceeded {}
+
+#[derive(Clone, Debug, Default, Getters)]
+/// Quotas contains the quotas per namespace and runtime handler.
+pub struct Quotas {
+    #[get = "pub"]
+    /// The quotas per namespace.
+    namespaces: Vec<QuotaLimit>,
+
+    #[get = "pub"]
+    /// The quotas per runtime handler.
+    handlers: Vec<QuotaLimit>,
+}
+
+impl Quotas {
+    /// Retrieve the quotas of the `config`.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            namespaces: config.namespace_quotas().clone(),
+            handlers: config.handler_quotas().clone(),
+        }
+    }
+
+    /// Check if no quotas are configured.
+    pub fn is_empty(&self) -> bool {
+        self.namespaces.is_empty() && self.handlers.is_empty()
+    }
+
+    /// Check if creating another object of the `kind` inside of the `namespace` with the runtime
+    /// `handler` would exceed a quota, given the existing `sandboxes` and `containers` and the
+    /// `pending` reservations of objects which are not stored yet.
+    pub fn check(
+        &self,
+        kind: QuotaKind,
+        namespace: &str,
+        handler: &str,
+        sandboxes: &[&SandboxData],
+        containers: &[Container],
+        pending: &[Reservation],
+    ) -> Result<(), QuotaExceeded> {
+        let handler_scope = if handler.is_empty() {
+            DEFAULT_HANDLER
+        } else {
+            handler
+        };
+        let checks: [(
+            &'static str,
+            &str,
+            &[QuotaLimit],
+            &dyn Fn(&str, &str) -> bool,
+        ); 2] = [
+            ("namespace", namespace, &self.namespaces, &|x, _| {
+                x == namespace
+            }),
+            ("handler", handler_scope, &self.handlers, &|_, x| {
+                x == handler
+            }),
+        ];
+        for (scope, name, quotas, matches) in checks.iter() {
+            let quota = match quotas
+                .iter()
+                .find(|x| x.scope() == *name)
+                .or_else(|| quotas.iter().find(|x| x.scope() == WILDCARD))
+            {
+                Some(quota) => quota,
+                None => continue,
+            };
+            let ids: HashSet<&str> = sandboxes
+                .iter()
+                .filter(|x| matches(x.namespace().as_str(), x.runtime_handler().as_str()))
+                .map(|x| x.id().as_str())
+                .collect();
+            let (limit, mut used) = match kind {
+                QuotaKind::PodSandbox => (quota.sandboxes(), ids),
+                QuotaKind::Container => (
+                    quota.containers(),
+                    containers
+                        .iter()
+                        .filter(|x| ids.contains(x.pod_sandbox_id().as_str()))
+                        .map(|x| x.id().as_str())
+                        .collect(),
+                ),
+            };
+
+            // Reservations of objects which got stored in the meantime only count once
+            used.extend(
+                pending
+                    .iter()
+                    .filter(|x| x.kind == kind && matches(x.namespace.as_str(), x.handler.as_str()))
+                    .map(|x| x.id.as_str()),
+            );
+            let used = used.len();
+            if limit > 0 && used >= limit {
+                return Err(QuotaExceeded {
+                    scope: *scope,
+                    name: (*name).into(),
+                    kind,
+                    limit,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Reservation is the pending creation of an object, which counts towards the quotas until the
+/// object is stored.
+pub struct Reservation {
+    /// The identifier of the created object.
+    id: String,
+
+    /// The kind of the created object.
+    kind: QuotaKind,
+
+    /// The namespace of the created object.
+    namespace: String,
+
+    /// The runtime handler of the created object.
+    handler: String,
+}
+
+impl Reservation {
+    /// Create a new reservation of the object `id` of the `kind` inside of the `namespace` with
+    /// the runtime `handler`.
+    pub fn new(id: &str, kind: QuotaKind, namespace: &str, handler: &str) -> Self {
+        Self {
+            id: id.into(),
+            kind,
+            namespace: namespace.into(),
+            handler: handler.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// QuotaReservations tracks the reservations of all creations in progress.
+pub struct QuotaReservations {
+    /// The pending reservations.
+    pending: Arc<Mutex<Vec<Reservation>>>,
+}
+
+impl QuotaReservations {
+    /// Add the `reservation` if `check` accepts it given the other pending reservations. Checking
+    /// and adding happen atomically, whereas the reservation gets released once the returned
+    /// guard is dropped.
+    pub fn reserve<F>(&self, reservation: Reservation, check: F) -> Result<QuotaReservation>
+    where
+        F: FnOnce(&[Reservation]) -> Result<()>,
+    {
+        let mut pending = self
+            .pending
+            .lock()
+            .map_err(|_| format_err!("lock quota reservations"))?;
+        check(&pending)?;
+        let guard = QuotaReservation {
+            pending: self.pending.clone(),
+            id: reservation.id.clone(),
+            kind: reservation.kind,
+        };
+        pending.push(reservation);
+        Ok(guard)
+    }
+}
+
+#[derive(Debug)]
+/// QuotaReservation releases its reservation once it gets dropped, which has to happen after the
+/// created object got stored or the creation failed.
+pub struct QuotaReservation {
+    /// The pending reservations.
+    pending: Arc<Mutex<Vec<Reservation>>>,
+
+    /// The identifier of the reserved object.
+    id: String,
+
+    /// The kind of the reserved object.
+    kind: QuotaKind,
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            if let Some(index) = pending
+                .iter()
+                .position(|x| x.id == self.id && x.kind == self.kind)
+            {
+                pending.remove(index);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{container::ContainerBuilder, sandbox::SandboxDataBuilder};
+    use anyhow::format_err;
+
+    fn sandbox(id: &str, namespace: &str, handler: &str) -> Result<SandboxData> {
+        Ok(SandboxDataBuilder::default()
+            .id(id)
+            .uid(id)
+            .name(id)
+            .namespace(namespace)
+            .attempt(0u32)
+            .runtime_handler(handler)
+            .build()
+            .map_err(|e| format_err!("build sandbox data: {}", e))?)
+    }
+
+    fn container(id: &str, sandbox: &str) -> Result<Container> {
+        Ok(ContainerBuilder::default()
+            .id(id)
+            .pod_sandbox_id(sandbox)
+            .name(id)
+            .attempt(0u32)
+            .bundle("/bundle")
+            .build()
+            .map_err(|e| format_err!("build container: {}", e))?)
+    }
+
+    #[test]
+    fn quota_limit_from_str_success() -> Result<()> {
+        let quota: QuotaLimit = "tenant=10:50".parse()?;
+        assert_eq!(quota.scope(), "tenant");
+        assert_eq!(quota.sandboxes(), 10);
+        assert_eq!(quota.containers(), 50);
+        assert_eq!("*=0:5".parse::<QuotaLimit>()?.sandboxes(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn quota_limit_from_str_failure() {
+        for input in &["tenant", "=1:2", "tenant=1", "tenant=a:2", "tenant=1:-2"] {
+            assert!(input.parse::<QuotaLimit>().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn check_success() -> Result<()> {
+        let sut = Quotas {
+            namespaces: vec!["tenant=2:3".parse()?, "*=1:0".parse()?],
+            handlers: vec!["kata=0:2".parse()?],
+        };
+        let (first, second, other) = (
+            sandbox("first", "tenant", "")?,
+            sandbox("second", "tenant", "kata")?,
+            sandbox("other", "other", "")?,
+        );
+        let containers = vec![
+            container("a", "first")?,
+            container("b", "first")?,
+            container("c", "second")?,
+        ];
+
+        let sandboxes = vec![&first];
+        sut.check(QuotaKind::PodSandbox, "tenant", "", &sandboxes, &[], &[])?;
+        sut.check(QuotaKind::PodSandbox, "new", "", &sandboxes, &[], &[])?;
+
+        let sandboxes = vec![&first, &second, &other];
+        let err = sut
+            .check(QuotaKind::PodSandbox, "tenant", "", &sandboxes, &[], &[])
+            .err()
+            .context("tenant quota not exceeded")?;
+        assert_eq!(err.scope(), "namespace");
+        assert_eq!(err.limit(), 2);
+        assert!(sut
+            .check(QuotaKind::PodSandbox, "other", "", &sandboxes, &[], &[])
+            .is_err());
+
+        // Containers count towards the namespace and runtime handler of their sandbox
+        let err = sut
+            .check(
+                QuotaKind::Container,
+                "tenant",
+                "",
+                &sandboxes,
+                &containers,
+                &[],
+            )
+            .err()
+            .context("tenant quota not exceeded")?;
+        assert_eq!(
+            err.to_string(),
+            "quota of 3 containers for namespace tenant exceeded"
+        );
+        sut.check(
+            QuotaKind::Container,
+            "other",
+            "kata",
+            &sandboxes,
+            &containers,
+            &[],
+        )?;
+        sut.check(
+            QuotaKind::Container,
+            "other",
+            "",
+            &sandboxes,
+            &containers,
+            &[],
+        )?;
+
+        // Pending reservations count towards the quotas, unless their object is stored already
+        let sandboxes = vec![&first];
+        let pending = vec![
+            Reservation::new("first", QuotaKind::PodSandbox, "tenant", ""),
+            Reservation::new("new", QuotaKind::PodSandbox, "tenant", ""),
+        ];
+        assert!(sut
+            .check(
+                QuotaKind::PodSandbox,
+                "tenant",
+                "",
+                &sandboxes,
+                &[],
+                &pending
+            )
+            .is_err());
+        sut.check(
+            QuotaKind::PodSandbox,
+            "tenant",
+            "",
+            &sandboxes,
+            &[],
+            &pending[..1],
+        )?;
+        let pending = vec![Reservation::new("d", QuotaKind::Container, "tenant", "")];
+        let err = sut
+            .check(
+                QuotaKind::Container,
+                "tenant",
+                "",
+                &sandboxes,
+                &containers[..2],
+                &pending,
+            )
+            .err()
+            .context("tenant quota not exceeded")?;
+        assert_eq!(err.limit(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn reserve_success() -> Result<()> {
+        let sut = QuotaReservations::default();
+        let quotas = Quotas {
+            namespaces: vec!["tenant=2:0".parse()?],
+            handlers: vec![],
+        };
+        let reserve = |id: &str| {
+            sut.reserve(
+                Reservation::new(id, QuotaKind::PodSandbox, "tenant", ""),
+                |pending| {
+                    quotas
+                        .check(QuotaKind::PodSandbox, "tenant", "", &[], &[], pending)
+                        .map_err(Into::into)
+                },
+            )
+        };
+
+        let first = reserve("first")?;
+        let second = reserve("second")?;
+        assert!(reserve("third").is_err());
+
+        // Failed creations release their reservation
+        drop(first);
+        let third = reserve("third")?;
+        assert!(reserve("fourth").is_err());
+        drop((second, third));
+        assert!(sut.pending.lock().map_or(false, |x| x.is_empty()));
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+//! Hot reloading of the configuration
+//!
+//! The server re-reads its configuration file and environment on SIGHUP. Only the settings
+//! supported by `Config::reload_from` are applied, all other changes require a restart.
+
+use crate::config::Config;
+use std::sync::{Arc, RwLock};
+
+#[derive(Clone)]
+/// LiveConfig holds the configuration including all reloaded settings.
+pub struct LiveConfig {
+    /// The current configuration, which gets replaced on reload.
+    current: Arc<RwLock<Arc<Config>>>,
+}
+
+impl LiveConfig {
+    /// Create a new live configuration starting with `config`.
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(config)),
+        }
+    }
+
+    /// Retrieve the current configuration.
+    pub fn current(&self) -> Arc<Config> {
+        match self.current.read() {
+            Ok(x) => x.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    /// Apply the reloadable settings of `config`. Returns the names of the changed settings.
+    pub fn reload(&self, config: &Config) -> Vec<&'static str> {
+        let mut current = (*self.current()).clone();
+        let changed = current.reload_from(config);
+        if !changed.is_empty() {
+            if let Ok(mut x) = self.current.write() {
+                *x = Arc::new(current);
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+    use anyhow::Result;
+    use log::LevelFilter;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn reload_success() -> Result<()> {
+        let sut = LiveConfig::new(Arc::new(
+            ConfigBuilder::default()
+                .log_level(LevelFilter::Info)
+                .stop_timeout(10u64)
+                .build()?,
+        ));
+        assert!(sut.reload(&sut.current()).is_empty());
+
+        let changed = sut.reload(
+            &ConfigBuilder::default()
+                .log_level(LevelFilter::Debug)
+                .cni_config_dir(PathBuf::from("/some/cni/config"))
+                .cni_plugin_dirs(sut.current().cni_plugin_dirs().clone())
+                .blocked_registries(vec!["docker.io".into()])
+                .stop_timeout(20u64)
+                .build()?,
+        );
+        assert_eq!(
+            changed,
+            vec!["log-level", "cni-config-dir", "blocked-registries"]
+        );
+
+        let current = sut.current();
+        assert_eq!(current.log_level(), LevelFilter::Debug);
+        assert_eq!(current.cni_config_dir(), Path::new("/some/cni/config"));
+        assert_eq!(current.blocked_registries(), &["docker.io"]);
+        // Settings which require a restart are not applied
+        assert_eq!(current.stop_timeout(), 10);
+        Ok(())
+    }
+}
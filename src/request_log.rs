@@ -0,0 +1,229 @@
+//! Scoped trace logging for single requests.
+//!
+//! Clients can request trace logs for a single RPC via the `cri-trace` metadata key, whose value
+//! is the ID tagging the output of the request. A random ID is used if the value is empty or
+//! invalid. The logs of tasks spawned by the request handler are not elevated.
+
+use crate::logging::{self, Sink};
+use anyhow::{Context, Result};
+use clap::crate_name;
+use log::{LevelFilter, Log, Metadata, Record};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::{
+    future::Future,
+    sync::{Arc, RwLock},
+};
+use tonic::metadata::MetadataMap;
+
+/// The metadata key which requests trace logging for a request.
+pub const TRACE_METADATA_KEY: &str = "cri-trace";
+
+/// The maximum length of client provided request IDs.
+const MAX_ID_LEN: usize = 64;
+
+/// The length of generated request IDs.
+const GENERATED_ID_LEN: usize = 8;
+
+tokio::task_local! {
+    /// The ID of the request whose logs are elevated to the trace level.
+    static TRACE_ID: String;
+}
+
+/// Retrieve the request ID if trace logging has been requested via the `metadata`.
+pub fn trace_id(metadata: &MetadataMap) -> Option<String> {
+    let value = metadata.get(TRACE_METADATA_KEY)?;
+    let id = value
+        .to_str()
+        .ok()
+        .filter(|x| !x.is_empty() && x.len() <= MAX_ID_LEN)
+        .filter(|x| {
+            x.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .map(String::from)
+        .unwrap_or_else(|| {
+            thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(GENERATED_ID_LEN)
+                .collect()
+        });
+    Some(id)
+}
+
+/// Run the future `f` with its logs elevated to the trace level and tagged by the request `id`.
+pub async fn scope<F: Future>(id: String, f: F) -> F::Output {
+    TRACE_ID.scope(id, f).await
+}
+
+/// Retrieve the ID of the request whose logs are elevated in the current task.
+pub fn current() -> Option<String> {
+    TRACE_ID.try_with(Clone::clone).ok()
+}
+
+/// ScopedLogger filters like the `inner` logger, whereas the records of elevated requests are
+/// written regardless of their level. All records are written to the `sink`.
+pub struct ScopedLogger {
+    /// The logger applying the configured filters.
+    inner: Arc<RwLock<env_logger::Logger>>,
+
+    /// The output of the records.
+    sink: Sink,
+}
+
+#[derive(Clone)]
+/// LoggerHandle replaces the filters of the installed global logger.
+pub struct LoggerHandle {
+    /// The logger applying the configured filters.
+    inner: Arc<RwLock<env_logger::Logger>>,
+
+    /// Whether elevated requests are supported.
+    scoped: bool,
+}
+
+impl LoggerHandle {
+    /// Replace the `inner` logger of the global logger, which applies its filters to all
+    /// subsequent records.
+    pub fn replace(&self, inner: env_logger::Logger) {
+        log::set_max_level(ScopedLogger::max_level(&inner, self.scoped));
+        if let Ok(mut x) = self.inner.write() {
+            *x = inner;
+        }
+    }
+}
+
+impl ScopedLogger {
+    /// Install the logger filtering via `inner` and writing to `sink` as the global logger.
+    /// Elevated requests are only supported if `scoped` is set, because all trace records have
+    /// to pass the global max level then. The returned handle allows replacing the filters later
+    /// on.
+    pub fn init(inner: env_logger::Logger, sink: Sink, scoped: bool) -> Result<LoggerHandle> {
+        let max_level = Self::max_level(&inner, scoped);
+        let inner = Arc::new(RwLock::new(inner));
+        log::set_boxed_logger(Box::new(Self {
+            inner: inner.clone(),
+            sink,
+        }))
+        .context("set global logger")?;
+        log::set_max_level(max_level);
+        Ok(LoggerHandle { inner, scoped })
+    }
+
+    /// Retrieve the global max level for the `inner` logger.
+    fn max_level(inner: &env_logger::Logger, scoped: bool) -> LevelFilter {
+        if scoped {
+            LevelFilter::Trace
+        } else {
+            inner.filter()
+        }
+    }
+
+    /// Check if records of the `target` can be elevated, which excludes dependencies to keep the
+    /// output focused on the runtime.
+    fn elevatable(target: &str) -> bool {
+        target == crate_name!() || target.starts_with(concat!(crate_name!(), "::"))
+    }
+}
+
+impl Log for ScopedLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let enabled = self.inner.read().map_or(false, |x| x.enabled(metadata));
+        enabled || (Self::elevatable(metadata.target()) && current().is_some())
+    }
+
+    fn log(&self, record: &Record) {
+        let matches = self.inner.read().map_or(false, |x| x.matches(record));
+        let id = current();
+        if matches || (id.is_some() && Self::elevatable(record.target())) {
+            self.sink.write(record, &logging::current().request(id));
+        }
+    }
+
+    fn flush(&self) {
+        self.sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::SystemClock,
+        config::{LogDriver, LogFormat},
+    };
+    use log::Level;
+
+    /// Create a new logger which only logs info records of this crate.
+    fn new_logger() -> Result<ScopedLogger> {
+        let inner = env_logger::Builder::new()
+            .parse_filters(&format!("{}=info", crate_name!()))
+            .build();
+        Ok(ScopedLogger {
+            inner: Arc::new(RwLock::new(inner)),
+            sink: Sink::new(
+                LogFormat::Text,
+                LogDriver::Stderr,
+                None,
+                Arc::new(SystemClock),
+            )?,
+        })
+    }
+
+    #[test]
+    fn trace_id_success() -> Result<()> {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(trace_id(&metadata), None);
+
+        metadata.insert(TRACE_METADATA_KEY, "issue-42".parse()?);
+        assert_eq!(trace_id(&metadata).as_deref(), Some("issue-42"));
+
+        metadata.insert(TRACE_METADATA_KEY, "".parse()?);
+        assert_eq!(trace_id(&metadata).map(|x| x.len()), Some(GENERATED_ID_LEN));
+
+        metadata.insert(TRACE_METADATA_KEY, "a b".parse()?);
+        assert_eq!(trace_id(&metadata).map(|x| x.len()), Some(GENERATED_ID_LEN));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn enabled_success() -> Result<()> {
+        let sut = new_logger()?;
+        let trace = |target| {
+            Metadata::builder()
+                .level(Level::Trace)
+                .target(target)
+                .build()
+        };
+        assert!(!sut.enabled(&trace("cri::server")));
+
+        scope("id".into(), async {
+            assert_eq!(current().as_deref(), Some("id"));
+            assert!(sut.enabled(&trace("cri::server")));
+            assert!(!sut.enabled(&trace("hyper::proto")));
+        })
+        .await;
+        assert_eq!(current(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn replace_success() -> Result<()> {
+        let sut = new_logger()?;
+        let handle = LoggerHandle {
+            inner: sut.inner.clone(),
+            scoped: true,
+        };
+        let debug = Metadata::builder()
+            .level(Level::Debug)
+            .target("cri::server")
+            .build();
+        assert!(!sut.enabled(&debug));
+
+        handle.replace(
+            env_logger::Builder::new()
+                .parse_filters(&format!("{}=debug", crate_name!()))
+                .build(),
+        );
+        assert!(sut.enabled(&debug));
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! CPU burst support based on the cgroup v2 `cpu.max.burst` interface file.
+//!
+//! The burst allows a container to accumulate unused quota of previous periods and to exceed its
+//! quota for a short time, which reduces throttling of latency sensitive services.
+
+use crate::criapi::LinuxContainerResources;
+use anyhow::{bail, Context, Result};
+use getset::CopyGetters;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The annotation which can be used to override the CPU burst of a container in microseconds.
+pub const CPU_BURST_ANNOTATION: &str = "io.kubernetes.cri.cpu-burst";
+
+/// Retrieve the CPU burst in microseconds for a container with the provided `resources`. The
+/// annotation has precedence over the configured `default`, whereas `None` is being returned if
+/// no burst applies. A burst requires a CPU quota and must not exceed it.
+pub fn cpu_burst(
+    annotations: &HashMap<String, String>,
+    default: u64,
+    resources: &LinuxContainerResources,
+) -> Result<Option<u64>> {
+    let burst = match annotations.get(CPU_BURST_ANNOTATION) {
+        Some(value) => value
+            .trim()
+            .parse::<u64>()
+            .with_context(|| format!("parse annotation {}", CPU_BURST_ANNOTATION))?,
+        None => default,
+    };
+    if burst == 0 {
+        return Ok(None);
+    }
+
+    let quota = resources.cpu_quota;
+    if quota <= 0 {
+        if annotations.contains_key(CPU_BURST_ANNOTATION) {
+            bail!("CPU burst requires a CPU quota")
+        }
+        return Ok(None);
+    }
+
+    // The default is capped at the quota, whereas explicitly requested bursts have to fit
+    let quota = quota as u64;
+    if burst > quota {
+        if annotations.contains_key(CPU_BURST_ANNOTATION) {
+            bail!("CPU burst of {}us exceeds the quota of {}us", burst, quota)
+        }
+        return Ok(Some(quota));
+    }
+    Ok(Some(burst))
+}
+
+#[derive(Clone, Copy, CopyGetters, Debug, Default, Deserialize, PartialEq, Serialize)]
+/// The burst statistics of a cgroup as exposed by its `cpu.stat` interface file.
+pub struct BurstStats {
+    #[get_copy = "pub"]
+    /// The number of periods in which the cgroup used more than its quota.
+    bursts: u64,
+
+    #[get_copy = "pub"]
+    /// The total CPU time used above the quota in microseconds.
+    burst_usec: u64,
+}
+
+impl BurstStats {
+    /// Parse the burst statistics from the `cpu.stat` `content`. Kernels without burst support
+    /// do not report them, which results in zero values.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut stats = Self::default();
+        for line in content.lines() {
+            let mut parts = line.split_whitespace();
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => continue,
+            };
+            let field = match key {
+                "nr_bursts" => &mut stats.bursts,
+                "burst_usec" => &mut stats.burst_usec,
+                _ => continue,
+            };
+            *field = value
+                .parse()
+                .with_context(|| format!("parse {} value {}", key, value))?;
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resources(cpu_quota: i64) -> LinuxContainerResources {
+        LinuxContainerResources {
+            cpu_period: 100_000,
+            cpu_quota,
+            ..Default::default()
+        }
+    }
+
+    fn annotation(value: &str) -> HashMap<String, String> {
+        let mut annotations = HashMap::new();
+        annotations.insert(CPU_BURST_ANNOTATION.into(), value.into());
+        annotations
+    }
+
+    #[test]
+    fn cpu_burst_success() -> Result<()> {
+        let none = HashMap::new();
+        assert_eq!(cpu_burst(&none, 0, &resources(50_000))?, None);
+        assert_eq!(cpu_burst(&none, 20_000, &resources(50_000))?, Some(20_000));
+        assert_eq!(cpu_burst(&none, 80_000, &resources(50_000))?, Some(50_000));
+        assert_eq!(cpu_burst(&none, 20_000, &resources(0))?, None);
+
+        assert_eq!(
+            cpu_burst(&annotation("30000"), 20_000, &resources(50_000))?,
+            Some(30_000)
+        );
+        assert_eq!(
+            cpu_burst(&annotation("0"), 20_000, &resources(50_000))?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cpu_burst_failure() {
+        assert!(cpu_burst(&annotation("invalid"), 0, &resources(50_000)).is_err());
+        assert!(cpu_burst(&annotation("60000"), 0, &resources(50_000)).is_err());
+        assert!(cpu_burst(&annotation("10000"), 0, &resources(-1)).is_err());
+    }
+
+    #[test]
+    fn parse_success() -> Result<()> {
+        let stats = BurstStats::parse(
+            "usage_usec 1000\n\
+             nr_periods 10\n\
+             nr_throttled 2\n\
+             throttled_usec 300\n\
+             nr_bursts 3\n\
+             burst_usec 4000\n",
+        )?;
+        assert_eq!(stats.bursts(), 3);
+        assert_eq!(stats.burst_usec(), 4000);
+
+        assert_eq!(
+            BurstStats::parse("usage_usec 1000\n")?,
+            BurstStats::default()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_failure() {
+        assert!(BurstStats::parse("nr_bursts invalid\n").is_err());
+    }
+}
@@ -0,0 +1,323 @@
+//! Validation of container resources against the capacity of the node.
+
+use crate::criapi::LinuxContainerResources;
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The default mount point of sysfs.
+const DEFAULT_SYS_ROOT: &str = "/sys";
+
+/// The default mount point of procfs.
+const DEFAULT_PROC_ROOT: &str = "/proc";
+
+#[derive(Debug, Default, PartialEq)]
+/// NodeCapacity describes the resources which are available on the node.
+pub struct NodeCapacity {
+    /// The online logical CPUs.
+    cpus: BTreeSet<u32>,
+
+    /// The online memory nodes.
+    mems: BTreeSet<u32>,
+
+    /// The total memory in bytes.
+    memory: u64,
+
+    /// The free huge pages in bytes per page size in kB.
+    hugepages: HashMap<u64, u64>,
+}
+
+impl NodeCapacity {
+    /// Read the capacity of the host.
+    pub fn host() -> Result<Self> {
+        Self::read(DEFAULT_SYS_ROOT, DEFAULT_PROC_ROOT)
+    }
+
+    /// Read the capacity from the sysfs and procfs mounted at `sys_root` and `proc_root`.
+    pub fn read<P: AsRef<Path>>(sys_root: P, proc_root: P) -> Result<Self> {
+        let (sys_root, proc_root) = (sys_root.as_ref(), proc_root.as_ref());
+        let read = |path: PathBuf| {
+            fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))
+        };
+
+        let cpus = parse_list(&read(sys_root.join("devices/system/cpu/online"))?)?;
+
+        // Kernels without NUMA support do not expose any memory nodes
+        let nodes_path = sys_root.join("devices/system/node/online");
+        let mems = if nodes_path.exists() {
+            parse_list(&read(nodes_path)?)?
+        } else {
+            (0..1).collect()
+        };
+
+        let memory = read(proc_root.join("meminfo"))?
+            .lines()
+            .find(|x| x.starts_with("MemTotal:"))
+            .and_then(|x| x.split_whitespace().nth(1))
+            .and_then(|x| x.parse::<u64>().ok())
+            .map(|x| x * 1024)
+            .context("no MemTotal found in meminfo")?;
+
+        let mut hugepages = HashMap::new();
+        let hugepages_dir = sys_root.join("kernel/mm/hugepages");
+        if hugepages_dir.exists() {
+            for entry in fs::read_dir(&hugepages_dir)
+                .with_context(|| format!("read dir {}", hugepages_dir.display()))?
+            {
+                let entry = entry?;
+                let size = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|x| x.strip_prefix("hugepages-"))
+                    .and_then(|x| x.strip_suffix("kB"))
+                    .and_then(|x| x.parse::<u64>().ok());
+                if let Some(size) = size {
+                    let free = read(entry.path().join("free_hugepages"))?
+                        .trim()
+                        .parse::<u64>()
+                        .context("parse free huge pages")?;
+                    hugepages.insert(size, free * size * 1024);
+                }
+            }
+        }
+
+        Ok(Self {
+            cpus,
+            mems,
+            memory,
+            hugepages,
+        })
+    }
+
+    /// Validate that the provided `resources` can be satisfied by the node. The error contains
+    /// the details about all resources which exceed the capacity.
+    pub fn validate(&self, resources: &LinuxContainerResources) -> Result<()> {
+        let mut errors = vec![];
+
+        if resources.cpu_quota > 0 && resources.cpu_period > 0 {
+            let cpus = resources.cpu_quota as f64 / resources.cpu_period as f64;
+            if cpus > self.cpus.len() as f64 {
+                errors.push(format!(
+                    "requested {:.2} CPUs but only {} are online",
+                    cpus,
+                    self.cpus.len()
+                ));
+            }
+        }
+
+        if resources.memory_limit_in_bytes > 0
+            && resources.memory_limit_in_bytes as u64 > self.memory
+        {
+            errors.push(format!(
+                "requested memory limit of {} bytes exceeds the total memory of {} bytes",
+                resources.memory_limit_in_bytes, self.memory
+            ));
+        }
+
+        for (name, value, online) in &[
+            ("CPUs", &resources.cpuset_cpus, &self.cpus),
+            ("memory nodes", &resources.cpuset_mems, &self.mems),
+        ] {
+            if value.is_empty() {
+                continue;
+            }
+            match parse_list(value) {
+                Ok(requested) => {
+                    let missing: Vec<String> =
+                        requested.difference(online).map(u32::to_string).collect();
+                    if !missing.is_empty() {
+                        errors.push(format!(
+                            "requested {} {} are not online",
+                            name,
+                            missing.join(",")
+                        ));
+                    }
+                }
+                Err(e) => errors.push(format!("invalid {} list {}: {}", name, value, e)),
+            }
+        }
+
+        for limit in &resources.hugepage_limits {
+            match parse_page_size(&limit.page_size) {
+                Some(size) => {
+                    let free = self.hugepages.get(&size).copied().unwrap_or_default();
+                    if limit.limit > free {
+                        errors.push(format!(
+                            "requested {} bytes of {} huge pages but only {} bytes are free",
+                            limit.limit, limit.page_size, free
+                        ));
+                    }
+                }
+                None => errors.push(format!("invalid huge page size {}", limit.page_size)),
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("{}", errors.join("; "))
+        }
+        Ok(())
+    }
+}
+
+/// Parse a list in the kernel format like `0-3,6,8-9`.
+fn parse_list(value: &str) -> Result<BTreeSet<u32>> {
+    let mut res = BTreeSet::new();
+    for part in value.trim().split(',').filter(|x| !x.is_empty()) {
+        let mut bounds = part.splitn(2, '-');
+        let start = bounds
+            .next()
+            .unwrap_or_default()
+            .parse::<u32>()
+            .with_context(|| format!("parse list entry {}", part))?;
+        let end = match bounds.next() {
+            Some(end) => end
+                .parse::<u32>()
+                .with_context(|| format!("parse list entry {}", part))?,
+            None => start,
+        };
+        if end < start {
+            bail!("invalid list range {}", part)
+        }
+        res.extend(start..=end);
+    }
+    Ok(res)
+}
+
+/// Parse a CRI huge page size like `2MB` or `1GB` into kB.
+fn parse_page_size(value: &str) -> Option<u64> {
+    let number = value.strip_suffix('B')?;
+    let (number, multiplier) = match number.chars().last()? {
+        'K' => (&number[..number.len() - 1], 1),
+        'M' => (&number[..number.len() - 1], 1 << 10),
+        'G' => (&number[..number.len() - 1], 1 << 20),
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::criapi::HugepageLimit;
+    use tempfile::TempDir;
+
+    fn new_node_capacity() -> NodeCapacity {
+        let mut hugepages = HashMap::new();
+        hugepages.insert(2048, 4 * 2048 * 1024);
+        NodeCapacity {
+            cpus: (0..4).collect(),
+            mems: (0..1).collect(),
+            memory: 1024 * 1024 * 1024,
+            hugepages,
+        }
+    }
+
+    #[test]
+    fn parse_list_success() -> Result<()> {
+        assert_eq!(parse_list("0")?, (0..1).collect());
+        assert_eq!(parse_list("0-3\n")?, (0..4).collect());
+        assert_eq!(
+            parse_list("0-1,4,6-7")?,
+            vec![0, 1, 4, 6, 7].into_iter().collect()
+        );
+        assert!(parse_list("")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_list_failure() {
+        assert!(parse_list("a").is_err());
+        assert!(parse_list("3-1").is_err());
+        assert!(parse_list("1-").is_err());
+    }
+
+    #[test]
+    fn parse_page_size_success() {
+        assert_eq!(parse_page_size("64KB"), Some(64));
+        assert_eq!(parse_page_size("2MB"), Some(2048));
+        assert_eq!(parse_page_size("1GB"), Some(1024 * 1024));
+        assert_eq!(parse_page_size("2M"), None);
+        assert_eq!(parse_page_size("MB"), None);
+        assert_eq!(parse_page_size("2TB"), None);
+    }
+
+    #[test]
+    fn read_success() -> Result<()> {
+        let (sys, proc) = (TempDir::new()?, TempDir::new()?);
+        fs::create_dir_all(sys.path().join("devices/system/cpu"))?;
+        fs::create_dir_all(sys.path().join("devices/system/node"))?;
+        fs::create_dir_all(sys.path().join("kernel/mm/hugepages/hugepages-2048kB"))?;
+        fs::write(sys.path().join("devices/system/cpu/online"), "0-3\n")?;
+        fs::write(sys.path().join("devices/system/node/online"), "0\n")?;
+        fs::write(
+            sys.path()
+                .join("kernel/mm/hugepages/hugepages-2048kB/free_hugepages"),
+            "4\n",
+        )?;
+        fs::write(
+            proc.path().join("meminfo"),
+            "MemTotal:        1048576 kB\nMemFree:          524288 kB\n",
+        )?;
+
+        let capacity = NodeCapacity::read(sys.path(), proc.path())?;
+        assert_eq!(capacity, new_node_capacity());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_success() -> Result<()> {
+        let resources = LinuxContainerResources {
+            cpu_period: 100_000,
+            cpu_quota: 400_000,
+            memory_limit_in_bytes: 1024,
+            cpuset_cpus: "0-3".into(),
+            cpuset_mems: "0".into(),
+            hugepage_limits: vec![HugepageLimit {
+                page_size: "2MB".into(),
+                limit: 2048 * 1024,
+            }],
+            ..Default::default()
+        };
+        new_node_capacity().validate(&resources)?;
+        new_node_capacity().validate(&LinuxContainerResources::default())
+    }
+
+    #[test]
+    fn validate_failure() {
+        for resources in vec![
+            LinuxContainerResources {
+                cpu_period: 100_000,
+                cpu_quota: 500_000,
+                ..Default::default()
+            },
+            LinuxContainerResources {
+                memory_limit_in_bytes: 2 * 1024 * 1024 * 1024,
+                ..Default::default()
+            },
+            LinuxContainerResources {
+                cpuset_cpus: "2-4".into(),
+                ..Default::default()
+            },
+            LinuxContainerResources {
+                cpuset_mems: "1".into(),
+                ..Default::default()
+            },
+            LinuxContainerResources {
+                cpuset_cpus: "wrong".into(),
+                ..Default::default()
+            },
+            LinuxContainerResources {
+                hugepage_limits: vec![HugepageLimit {
+                    page_size: "1GB".into(),
+                    limit: 1,
+                }],
+                ..Default::default()
+            },
+        ] {
+            assert!(new_node_capacity().validate(&resources).is_err());
+        }
+    }
+}
@@ -0,0 +1,695 @@
+//! Resource management based on the cgroup v2 unified hierarchy.
+//!
+//! Only the usage accounting supports the legacy cgroup v1 hierarchies as well, which allows
+//! reporting statistics on nodes which have not been migrated yet.
+
+use crate::{
+    criapi::LinuxContainerResources,
+    resources::{
+        burst::BurstStats,
+        pressure::{Pressure, ResourcePressure},
+        usage::{stat_value, Usage},
+        ResourceManager,
+    },
+};
+use anyhow::{bail, Context, Result};
+use nix::unistd::{chown, Gid, Uid};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The default mount point of the unified cgroup hierarchy.
+const DEFAULT_ROOT: &str = "/sys/fs/cgroup";
+
+/// The default CPU CFS period in microseconds.
+const DEFAULT_CPU_PERIOD: i64 = 100_000;
+
+/// CgroupManager applies container resources to the unified cgroup hierarchy.
+pub struct CgroupManager {
+    /// The mount point of the cgroup hierarchy.
+    root: PathBuf,
+}
+
+impl Default for CgroupManager {
+    fn default() -> Self {
+        Self::new(DEFAULT_ROOT)
+    }
+}
+
+impl CgroupManager {
+    /// Create a new cgroup manager for the hierarchy mounted at `root`.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Retrieve the full path for the provided `cgroup_path`, which can be relative or absolute
+    /// to the root of the hierarchy.
+    fn path(&self, cgroup_path: &Path) -> PathBuf {
+        self.root
+            .join(cgroup_path.strip_prefix("/").unwrap_or(cgroup_path))
+    }
+
+    /// Retrieve the full path for the provided `cgroup_path` inside the cgroup v1 hierarchy of
+    /// the `controller`.
+    fn v1_path(&self, controller: &str, cgroup_path: &Path) -> PathBuf {
+        self.root
+            .join(controller)
+            .join(cgroup_path.strip_prefix("/").unwrap_or(cgroup_path))
+    }
+
+    /// Check if the root is the unified hierarchy of cgroup v2.
+    fn unified(&self) -> bool {
+        self.root.join("cgroup.controllers").exists()
+    }
+
+    /// Retrieve the usage of the cgroup at `cgroup_path` from the cgroup v2 interface files.
+    fn usage_v2(&self, cgroup_path: &Path) -> Result<Usage> {
+        let path = self.path(cgroup_path);
+        if !path.exists() {
+            bail!("cgroup {} does not exist", path.display())
+        }
+        let cpu_usec = read_stat(&path, "cpu.stat", "usage_usec")?;
+        let memory = read_number(&path, "memory.current")?;
+        let inactive_file = read_stat(&path, "memory.stat", "inactive_file")?;
+        Ok(Usage::new(cpu_usec * 1000, memory, inactive_file))
+    }
+
+    /// Retrieve the usage of the cgroup at `cgroup_path` from the cgroup v1 interface files of
+    /// the `cpuacct` and `memory` controllers.
+    fn usage_v1(&self, cgroup_path: &Path) -> Result<Usage> {
+        let cpuacct = self.v1_path("cpuacct", cgroup_path);
+        let memory = self.v1_path("memory", cgroup_path);
+        for path in &[&cpuacct, &memory] {
+            if !path.exists() {
+                bail!("cgroup {} does not exist", path.display())
+            }
+        }
+        let cpu_nanos = read_number(&cpuacct, "cpuacct.usage")?;
+        let usage = read_number(&memory, "memory.usage_in_bytes")?;
+        let inactive_file = read_stat(&memory, "memory.stat", "total_inactive_file")?;
+        Ok(Usage::new(cpu_nanos, usage, inactive_file))
+    }
+}
+
+impl ResourceManager for CgroupManager {
+    fn update(&self, cgroup_path: &Path, resources: &LinuxContainerResources) -> Result<()> {
+        let path = self.path(cgroup_path);
+        fs::create_dir_all(&path).with_context(|| format!("create cgroup {}", path.display()))?;
+
+        for (file, value) in cgroup_values(resources) {
+            let file_path = path.join(file);
+            fs::write(&file_path, &value)
+                .with_context(|| format!("write {} to {}", value, file_path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn resources(&self, cgroup_path: &Path) -> Result<LinuxContainerResources> {
+        let path = self.path(cgroup_path);
+        if !path.exists() {
+            bail!("cgroup {} does not exist", path.display())
+        }
+        let mut resources = LinuxContainerResources::default();
+
+        if let Some(weight) = read_value(&path, "cpu.weight")? {
+            let weight = weight
+                .parse()
+                .with_context(|| format!("parse CPU weight {}", weight))?;
+            resources.cpu_shares = cpu_shares(weight);
+        }
+
+        if let Some(max) = read_value(&path, "cpu.max")? {
+            let mut fields = max.split_whitespace();
+            if let Some(quota) = fields.next().filter(|x| *x != "max") {
+                resources.cpu_quota = quota
+                    .parse()
+                    .with_context(|| format!("parse CPU quota {}", quota))?;
+            }
+            if let Some(period) = fields.next() {
+                resources.cpu_period = period
+                    .parse()
+                    .with_context(|| format!("parse CPU period {}", period))?;
+            }
+        }
+
+        if let Some(max) = read_value(&path, "memory.max")?.filter(|x| x != "max") {
+            resources.memory_limit_in_bytes = max
+                .parse()
+                .with_context(|| format!("parse memory limit {}", max))?;
+        }
+
+        resources.cpuset_cpus = read_value(&path, "cpuset.cpus")?.unwrap_or_default();
+        resources.cpuset_mems = read_value(&path, "cpuset.mems")?.unwrap_or_default();
+        Ok(resources)
+    }
+
+    fn verify(
+        &self,
+        cgroup_path: &Path,
+        resources: &LinuxContainerResources,
+    ) -> Result<Vec<String>> {
+        let path = self.path(cgroup_path);
+        let mut degradations = vec![];
+
+        // The kernel may normalize or silently ignore values, which is only visible when reading
+        // them back
+        for (file, value) in cgroup_values(resources) {
+            match read_value(&path, &file)? {
+                Some(actual) if actual == value => {}
+                Some(actual) => {
+                    degradations.push(format!("{} is {} instead of {}", file, actual, value))
+                }
+                None => degradations.push(format!("{} is not available", file)),
+            }
+        }
+
+        // Lowering the memory limit below the current usage succeeds, but the usage stays above
+        // the limit until the kernel is able to reclaim enough memory
+        if resources.memory_limit_in_bytes > 0 {
+            if let Some(current) = read_value(&path, "memory.current")? {
+                let current: i64 = current
+                    .parse()
+                    .with_context(|| format!("parse memory usage {}", current))?;
+                if current > resources.memory_limit_in_bytes {
+                    degradations.push(format!(
+                        "memory usage of {} bytes exceeds the limit of {} bytes",
+                        current, resources.memory_limit_in_bytes
+                    ));
+                }
+            }
+        }
+        Ok(degradations)
+    }
+
+    fn remove(&self, cgroup_path: &Path) -> Result<()> {
+        let path = self.path(cgroup_path);
+        if path.exists() {
+            fs::remove_dir(&path).with_context(|| format!("remove cgroup {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn add_process(&self, cgroup_path: &Path, pid: u32) -> Result<()> {
+        let file_path = self.path(cgroup_path).join("cgroup.procs");
+        fs::write(&file_path, pid.to_string())
+            .with_context(|| format!("write {} to {}", pid, file_path.display()))
+    }
+
+    fn pressure(&self, cgroup_path: &Path) -> Result<Pressure> {
+        let path = self.path(cgroup_path);
+        let read = |resource: &str| -> Result<Option<ResourcePressure>> {
+            let file_path = path.join(format!("{}.pressure", resource));
+            if !file_path.exists() {
+                return Ok(None);
+            }
+            let content = fs::read_to_string(&file_path)
+                .with_context(|| format!("read {}", file_path.display()))?;
+            ResourcePressure::parse(&content)
+                .with_context(|| format!("parse {}", file_path.display()))
+                .map(Some)
+        };
+
+        Ok(Pressure::new(read("cpu")?, read("memory")?, read("io")?))
+    }
+
+    fn usage(&self, cgroup_path: &Path) -> Result<Usage> {
+        if self.unified() {
+            self.usage_v2(cgroup_path)
+        } else {
+            self.usage_v1(cgroup_path)
+        }
+    }
+
+    fn set_cpu_burst(&self, cgroup_path: &Path, burst: u64) -> Result<()> {
+        let file_path = self.path(cgroup_path).join("cpu.max.burst");
+        fs::write(&file_path, burst.to_string())
+            .with_context(|| format!("write {} to {}", burst, file_path.display()))
+    }
+
+    fn burst_stats(&self, cgroup_path: &Path) -> Result<BurstStats> {
+        let file_path = self.path(cgroup_path).join("cpu.stat");
+        let content = fs::read_to_string(&file_path)
+            .with_context(|| format!("read {}", file_path.display()))?;
+        BurstStats::parse(&content).with_context(|| format!("parse {}", file_path.display()))
+    }
+
+    fn enable_controllers(&self, cgroup_path: &Path, controllers: &[String]) -> Result<()> {
+        let controllers = if controllers.is_empty() {
+            let file_path = self.root.join("cgroup.controllers");
+            fs::read_to_string(&file_path)
+                .with_context(|| format!("read {}", file_path.display()))?
+                .split_whitespace()
+                .map(Into::into)
+                .collect()
+        } else {
+            controllers.to_vec()
+        };
+        if controllers.is_empty() {
+            return Ok(());
+        }
+        let value = controllers
+            .iter()
+            .map(|x| format!("+{}", x))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        // The subtree control of the cgroup itself belongs to its owner
+        let mut path = self.root.clone();
+        let mut ancestors = vec![path.clone()];
+        let relative = cgroup_path.strip_prefix("/").unwrap_or(cgroup_path);
+        for component in relative.parent().into_iter().flat_map(Path::components) {
+            path.push(component);
+            ancestors.push(path.clone());
+        }
+        for ancestor in ancestors {
+            fs::create_dir_all(&ancestor)
+                .with_context(|| format!("create cgroup {}", ancestor.display()))?;
+            let file_path = ancestor.join("cgroup.subtree_control");
+            fs::write(&file_path, &value)
+                .with_context(|| format!("write {} to {}", value, file_path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn delegate(&self, cgroup_path: &Path, uid: u32, gid: u32) -> Result<()> {
+        let path = self.path(cgroup_path);
+        let (uid, gid) = (Some(Uid::from_raw(uid)), Some(Gid::from_raw(gid)));
+        chown(&path, uid, gid).with_context(|| format!("change owner of {}", path.display()))?;
+
+        // These are the interface files required for managing the subtree
+        for file in &["cgroup.procs", "cgroup.subtree_control", "cgroup.threads"] {
+            let file_path = path.join(file);
+            if file_path.exists() {
+                chown(&file_path, uid, gid)
+                    .with_context(|| format!("change owner of {}", file_path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Convert the CRI resources into cgroup interface files and their values. Resources which are not
+/// specified will be skipped.
+fn cgroup_values(resources: &LinuxContainerResources) -> Vec<(String, String)> {
+    let mut values = vec![];
+
+    if resources.cpu_shares > 0 {
+        values.push((
+            "cpu.weight".into(),
+            cpu_weight(resources.cpu_shares).to_string(),
+        ));
+    }
+
+    if resources.cpu_quota != 0 || resources.cpu_period != 0 {
+        let quota = if resources.cpu_quota > 0 {
+            resources.cpu_quota.to_string()
+        } else {
+            "max".into()
+        };
+        let period = if resources.cpu_period > 0 {
+            resources.cpu_period
+        } else {
+            DEFAULT_CPU_PERIOD
+        };
+        values.push(("cpu.max".into(), format!("{} {}", quota, period)));
+    }
+
+    if resources.memory_limit_in_bytes > 0 {
+        values.push((
+            "memory.max".into(),
+            resources.memory_limit_in_bytes.to_string(),
+        ));
+    }
+
+    if !resources.cpuset_cpus.is_empty() {
+        values.push(("cpuset.cpus".into(), resources.cpuset_cpus.clone()));
+    }
+
+    if !resources.cpuset_mems.is_empty() {
+        values.push(("cpuset.mems".into(), resources.cpuset_mems.clone()));
+    }
+
+    for limit in &resources.hugepage_limits {
+        values.push((
+            format!("hugetlb.{}.max", limit.page_size),
+            limit.limit.to_string(),
+        ));
+    }
+
+    values
+}
+
+/// Convert cgroup v1 CPU shares (2 - 262144) into a cgroup v2 CPU weight (1 - 10000).
+fn cpu_weight(shares: i64) -> u64 {
+    let shares = shares.max(2).min(262_144) as u64;
+    1 + ((shares - 2) * 9999) / 262_142
+}
+
+/// Convert a cgroup v2 CPU weight (1 - 10000) back into cgroup v1 CPU shares (2 - 262144).
+fn cpu_shares(weight: u64) -> i64 {
+    let weight = weight.max(1).min(10_000);
+    (2 + ((weight - 1) * 262_142) / 9999) as i64
+}
+
+/// Read the trimmed value of the interface `file` of the cgroup at `path`, if it exists.
+fn read_value(path: &Path, file: &str) -> Result<Option<String>> {
+    let file_path = path.join(file);
+    if !file_path.exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(&file_path)
+        .map(|x| Some(x.trim().into()))
+        .with_context(|| format!("read {}", file_path.display()))
+}
+
+/// Read the numeric interface `file` of the cgroup at `path`, which is `0` if it does not exist.
+fn read_number(path: &Path, file: &str) -> Result<u64> {
+    match read_value(path, file)? {
+        Some(value) => value
+            .parse()
+            .with_context(|| format!("parse {} value {:?}", file, value)),
+        None => Ok(0),
+    }
+}
+
+/// Read the value of `key` from the flat keyed interface `file` of the cgroup at `path`, which
+/// is `0` if either of them does not exist.
+fn read_stat(path: &Path, file: &str, key: &str) -> Result<u64> {
+    match read_value(path, file)? {
+        Some(content) => Ok(stat_value(&content, key)
+            .with_context(|| format!("parse {}", path.join(file).display()))?
+            .unwrap_or_default()),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::criapi::HugepageLimit;
+    use tempfile::TempDir;
+
+    #[test]
+    fn cpu_weight_success() {
+        assert_eq!(cpu_weight(0), 1);
+        assert_eq!(cpu_weight(2), 1);
+        assert_eq!(cpu_weight(1024), 39);
+        assert_eq!(cpu_weight(262_144), 10000);
+        assert_eq!(cpu_weight(1_000_000), 10000);
+    }
+
+    #[test]
+    fn cpu_shares_success() {
+        assert_eq!(cpu_shares(0), 2);
+        assert_eq!(cpu_shares(1), 2);
+        assert_eq!(cpu_shares(39), 998);
+        assert_eq!(cpu_shares(10000), 262_144);
+        assert_eq!(cpu_weight(cpu_shares(39)), 39);
+    }
+
+    #[test]
+    fn cgroup_values_empty() {
+        assert!(cgroup_values(&LinuxContainerResources::default()).is_empty());
+    }
+
+    #[test]
+    fn cgroup_values_success() {
+        let resources = LinuxContainerResources {
+            cpu_shares: 1024,
+            cpu_quota: 50_000,
+            memory_limit_in_bytes: 1024,
+            cpuset_cpus: "0-1".into(),
+            hugepage_limits: vec![HugepageLimit {
+                page_size: "2MB".into(),
+                limit: 2048,
+            }],
+            ..Default::default()
+        };
+
+        let expected: Vec<(String, String)> = vec![
+            ("cpu.weight".into(), "39".into()),
+            ("cpu.max".into(), "50000 100000".into()),
+            ("memory.max".into(), "1024".into()),
+            ("cpuset.cpus".into(), "0-1".into()),
+            ("hugetlb.2MB.max".into(), "2048".into()),
+        ];
+        assert_eq!(cgroup_values(&resources), expected);
+    }
+
+    #[test]
+    fn cgroup_values_unlimited_quota() {
+        let resources = LinuxContainerResources {
+            cpu_period: 10_000,
+            ..Default::default()
+        };
+        let expected: Vec<(String, String)> = vec![("cpu.max".into(), "max 10000".into())];
+        assert_eq!(cgroup_values(&resources), expected);
+    }
+
+    #[test]
+    fn update_and_remove_success() -> Result<()> {
+        let root = TempDir::new()?;
+        let sut = CgroupManager::new(root.path());
+        let cgroup_path = Path::new("/pod/container");
+        let resources = LinuxContainerResources {
+            memory_limit_in_bytes: 1024,
+            ..Default::default()
+        };
+
+        sut.update(cgroup_path, &resources)?;
+        let path = root.path().join("pod").join("container");
+        assert_eq!(fs::read_to_string(path.join("memory.max"))?, "1024");
+
+        fs::remove_file(path.join("memory.max"))?;
+        sut.remove(cgroup_path)?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn resources_success() -> Result<()> {
+        let root = TempDir::new()?;
+        let sut = CgroupManager::new(root.path());
+        let cgroup_path = Path::new("/pod/container");
+        assert!(sut.resources(cgroup_path).is_err());
+
+        let resources = LinuxContainerResources {
+            cpu_shares: 2,
+            cpu_quota: 50_000,
+            memory_limit_in_bytes: 4096,
+            cpuset_cpus: "0-1".into(),
+            ..Default::default()
+        };
+        sut.update(cgroup_path, &resources)?;
+        assert_eq!(
+            sut.resources(cgroup_path)?,
+            LinuxContainerResources {
+                cpu_period: DEFAULT_CPU_PERIOD,
+                ..resources
+            }
+        );
+
+        let path = root.path().join("pod").join("container");
+        fs::write(path.join("cpu.max"), "max 100000\n")?;
+        fs::write(path.join("memory.max"), "max\n")?;
+        let effective = sut.resources(cgroup_path)?;
+        assert_eq!(effective.cpu_quota, 0);
+        assert_eq!(effective.memory_limit_in_bytes, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn verify_success() -> Result<()> {
+        let root = TempDir::new()?;
+        let sut = CgroupManager::new(root.path());
+        let cgroup_path = Path::new("/pod/container");
+        let resources = LinuxContainerResources {
+            cpu_quota: 50_000,
+            memory_limit_in_bytes: 8192,
+            ..Default::default()
+        };
+
+        sut.update(cgroup_path, &resources)?;
+        assert!(sut.verify(cgroup_path, &resources)?.is_empty());
+
+        // The usage is still above the limit and the kernel rounded the quota
+        let path = root.path().join("pod").join("container");
+        fs::write(path.join("memory.current"), "16384\n")?;
+        fs::write(path.join("cpu.max"), "49000 100000\n")?;
+        assert_eq!(
+            sut.verify(cgroup_path, &resources)?,
+            vec![
+                "cpu.max is 49000 100000 instead of 50000 100000".to_string(),
+                "memory usage of 16384 bytes exceeds the limit of 8192 bytes".to_string(),
+            ]
+        );
+
+        fs::remove_file(path.join("memory.max"))?;
+        assert!(sut
+            .verify(cgroup_path, &resources)?
+            .contains(&"memory.max is not available".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn add_process_success() -> Result<()> {
+        let root = TempDir::new()?;
+        let sut = CgroupManager::new(root.path());
+        sut.update(Path::new("daemon"), &LinuxContainerResources::default())?;
+
+        sut.add_process(Path::new("daemon"), 42)?;
+        assert_eq!(
+            fs::read_to_string(root.path().join("daemon").join("cgroup.procs"))?,
+            "42"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pressure_success() -> Result<()> {
+        let root = TempDir::new()?;
+        let sut = CgroupManager::new(root.path());
+        let path = root.path().join("pod");
+        fs::create_dir_all(&path)?;
+        fs::write(
+            path.join("memory.pressure"),
+            "some avg10=0.00 avg60=0.00 avg300=0.00 total=10\n\
+             full avg10=0.00 avg60=0.00 avg300=0.00 total=5\n",
+        )?;
+
+        let pressure = sut.pressure(Path::new("/pod"))?;
+        assert!(pressure.cpu().is_none());
+        assert!(pressure.io().is_none());
+        assert_eq!(
+            pressure.memory().as_ref().map(|x| x.some().total()),
+            Some(10)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn usage_success_v2() -> Result<()> {
+        let root = TempDir::new()?;
+        let sut = CgroupManager::new(root.path());
+        fs::write(root.path().join("cgroup.controllers"), "cpu memory")?;
+        let path = root.path().join("pod");
+        fs::create_dir_all(&path)?;
+        fs::write(
+            path.join("cpu.stat"),
+            "usage_usec 1500
+user_usec 1000
+",
+        )?;
+        fs::write(
+            path.join("memory.current"),
+            "4096
+",
+        )?;
+        fs::write(
+            path.join("memory.stat"),
+            "anon 1024
+inactive_file 1024
+",
+        )?;
+
+        let usage = sut.usage(Path::new("/pod"))?;
+        assert_eq!(usage.cpu_nanos(), 1_500_000);
+        assert_eq!(usage.memory_working_set(), 3072);
+        assert!(sut.usage(Path::new("/other")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn usage_success_v1() -> Result<()> {
+        let root = TempDir::new()?;
+        let sut = CgroupManager::new(root.path());
+        let cpuacct = root.path().join("cpuacct").join("pod");
+        let memory = root.path().join("memory").join("pod");
+        fs::create_dir_all(&cpuacct)?;
+        fs::create_dir_all(&memory)?;
+        fs::write(
+            cpuacct.join("cpuacct.usage"),
+            "2000
+",
+        )?;
+        fs::write(
+            memory.join("memory.usage_in_bytes"),
+            "4096
+",
+        )?;
+        fs::write(
+            memory.join("memory.stat"),
+            "cache 2048
+total_inactive_file 2048
+",
+        )?;
+
+        let usage = sut.usage(Path::new("/pod"))?;
+        assert_eq!(usage.cpu_nanos(), 2000);
+        assert_eq!(usage.memory_working_set(), 2048);
+        Ok(())
+    }
+
+    #[test]
+    fn enable_controllers_success() -> Result<()> {
+        let root = TempDir::new()?;
+        let sut = CgroupManager::new(root.path());
+
+        sut.enable_controllers(
+            Path::new("/cri/pod/container"),
+            &["cpu".into(), "memory".into()],
+        )?;
+        for path in &[
+            root.path().to_path_buf(),
+            root.path().join("cri"),
+            root.path().join("cri/pod"),
+        ] {
+            assert_eq!(
+                fs::read_to_string(path.join("cgroup.subtree_control"))?,
+                "+cpu +memory"
+            );
+        }
+        assert!(!root.path().join("cri/pod/container").exists());
+
+        fs::write(root.path().join("cgroup.controllers"), "cpu io pids\n")?;
+        sut.enable_controllers(Path::new("container"), &[])?;
+        assert_eq!(
+            fs::read_to_string(root.path().join("cgroup.subtree_control"))?,
+            "+cpu +io +pids"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn delegate_success() -> Result<()> {
+        let root = TempDir::new()?;
+        let sut = CgroupManager::new(root.path());
+        let path = root.path().join("container");
+        fs::create_dir_all(&path)?;
+        fs::write(path.join("cgroup.procs"), "")?;
+
+        // Only the current user is allowed to own the files without privileges
+        let (uid, gid) = (Uid::current().as_raw(), Gid::current().as_raw());
+        sut.delegate(Path::new("/container"), uid, gid)?;
+        assert!(sut.delegate(Path::new("/missing"), uid, gid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cpu_burst_success() -> Result<()> {
+        let root = TempDir::new()?;
+        let sut = CgroupManager::new(root.path());
+        let path = root.path().join("pod");
+        fs::create_dir_all(&path)?;
+        fs::write(path.join("cpu.stat"), "nr_bursts 2\nburst_usec 300\n")?;
+
+        sut.set_cpu_burst(Path::new("/pod"), 20_000)?;
+        assert_eq!(fs::read_to_string(path.join("cpu.max.burst"))?, "20000");
+
+        let stats = sut.burst_stats(Path::new("/pod"))?;
+        assert_eq!(stats.bursts(), 2);
+        assert_eq!(stats.burst_usec(), 300);
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Confinement of the server process itself.
+
+use crate::{config::Config, criapi::LinuxContainerResources, resources::ResourceManager};
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use std::{fs, path::Path, process};
+
+/// The CPU CFS period in microseconds used for limiting the server.
+const CPU_PERIOD: i64 = 100_000;
+
+/// The path to the OOM score adjustment of the current process.
+const OOM_SCORE_ADJ_PATH: &str = "/proc/self/oom_score_adj";
+
+/// Move the server into its configured cgroup, limit its resources and adjust its OOM score.
+pub fn confine<M: ResourceManager>(config: &Config, manager: &M) -> Result<()> {
+    // Adjusting the OOM score below zero requires privileges, which rootless setups do not have
+    if let Err(e) = set_oom_score_adj(Path::new(OOM_SCORE_ADJ_PATH), config.oom_score_adj()) {
+        warn!("Unable to adjust OOM score of server: {:#}", e);
+    }
+
+    if let Some(cgroup) = config.daemon_cgroup() {
+        manager
+            .update(cgroup, &daemon_resources(config))
+            .with_context(|| format!("apply resources to cgroup {}", cgroup.display()))?;
+        manager
+            .add_process(cgroup, process::id())
+            .with_context(|| format!("move server into cgroup {}", cgroup.display()))?;
+        info!("Moved server into cgroup {}", cgroup.display());
+    }
+    Ok(())
+}
+
+/// Convert the configured limits of the server into resources.
+fn daemon_resources(config: &Config) -> LinuxContainerResources {
+    let mut resources = LinuxContainerResources {
+        memory_limit_in_bytes: config.daemon_memory_limit() as i64,
+        ..Default::default()
+    };
+    if config.daemon_cpu_limit() > 0 {
+        resources.cpu_period = CPU_PERIOD;
+        resources.cpu_quota = config.daemon_cpu_limit() as i64 * CPU_PERIOD / 1000;
+    }
+    resources
+}
+
+/// Write the OOM score adjustment `value` to the file at `path`.
+fn set_oom_score_adj(path: &Path, value: i32) -> Result<()> {
+    if !(-1000..=1000).contains(&value) {
+        bail!(
+            "OOM score adjustment {} is not within -1000 and 1000",
+            value
+        )
+    }
+    fs::write(path, value.to_string())
+        .with_context(|| format!("write OOM score adjustment to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigBuilder;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn daemon_resources_success() -> Result<()> {
+        let config = ConfigBuilder::default()
+            .daemon_memory_limit(1024u64)
+            .daemon_cpu_limit(500u64)
+            .build()?;
+
+        let resources = daemon_resources(&config);
+        assert_eq!(resources.memory_limit_in_bytes, 1024);
+        assert_eq!(resources.cpu_period, CPU_PERIOD);
+        assert_eq!(resources.cpu_quota, 50_000);
+        Ok(())
+    }
+
+    #[test]
+    fn daemon_resources_unlimited() -> Result<()> {
+        let config = ConfigBuilder::default().build()?;
+        assert_eq!(
+            daemon_resources(&config),
+            LinuxContainerResources::default()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn set_oom_score_adj_success() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        set_oom_score_adj(file.path(), -999)?;
+        assert_eq!(fs::read_to_string(file.path())?, "-999");
+        Ok(())
+    }
+
+    #[test]
+    fn set_oom_score_adj_fail_out_of_range() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        assert!(set_oom_score_adj(file.path(), -1001).is_err());
+        assert!(set_oom_score_adj(file.path(), 1001).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! Delegation of cgroup subtrees to containers which manage their own cgroups, like systemd or
+//! nested container runtimes.
+//!
+//! A delegated container runs in its own cgroup namespace, which makes its cgroup the root of the
+//! hierarchy mounted writable into the container. The ownership of the cgroup is transferred to
+//! the container user, whereas the delegated controllers have to be enabled in all ancestors of
+//! the cgroup to allow the container to enable them for its own subtree.
+
+use crate::oci_spec::runtime::{
+    LinuxNamespace, LinuxNamespaceBuilder, LinuxNamespaceType, Mount, MountBuilder,
+};
+use anyhow::{bail, format_err, Result};
+use getset::Getters;
+use log::debug;
+use std::collections::HashMap;
+
+/// The annotation which requests a delegated cgroup subtree for a container. The value is either
+/// `true` for delegating all available controllers, or a comma separated list of controllers like
+/// `cpu,memory,pids`. The annotation has to be part of the allowed annotations of the server.
+pub const CGROUP_DELEGATE_ANNOTATION: &str = "io.kubernetes.cri.cgroup-delegate";
+
+/// The mount point of the cgroup hierarchy inside of containers.
+const CGROUP_MOUNT_PATH: &str = "/sys/fs/cgroup";
+
+/// The cgroup v2 controllers which can be delegated.
+const CONTROLLERS: &[&str] = &[
+    "cpu", "cpuset", "hugetlb", "io", "memory", "misc", "pids", "rdma",
+];
+
+#[derive(Clone, Debug, Default, Getters, PartialEq)]
+/// Delegation describes the cgroup subtree delegated to a single container.
+pub struct Delegation {
+    #[get = "pub"]
+    /// The controllers to delegate, which are all available ones if empty.
+    controllers: Vec<String>,
+}
+
+impl Delegation {
+    /// Retrieve the delegation requested via the `annotations` of a container. Returns `None` if
+    /// no delegation has been requested or if the annotation is not part of the `allowed` ones.
+    pub fn from_annotations(
+        annotations: &HashMap<String, String>,
+        allowed: &[String],
+    ) -> Result<Option<Self>> {
+        let value = match annotations.get(CGROUP_DELEGATE_ANNOTATION) {
+            Some(value) => value.trim(),
+            None => return Ok(None),
+        };
+        if !allowed.iter().any(|x| x == CGROUP_DELEGATE_ANNOTATION) {
+            debug!(
+                "Ignoring annotation {} because it is not allowed",
+                CGROUP_DELEGATE_ANNOTATION
+            );
+            return Ok(None);
+        }
+
+        match value {
+            "" | "false" => Ok(None),
+            "true" => Ok(Some(Self::default())),
+            value => {
+                let mut controllers: Vec<String> = vec![];
+                for controller in value.split(',').map(str::trim) {
+                    if !CONTROLLERS.contains(&controller) {
+                        bail!(
+                            "invalid controller {:?} in annotation {}",
+                            controller,
+                            CGROUP_DELEGATE_ANNOTATION
+                        )
+                    }
+                    if !controllers.iter().any(|x| x == controller) {
+                        controllers.push(controller.into());
+                    }
+                }
+                Ok(Some(Self { controllers }))
+            }
+        }
+    }
+
+    /// Retrieve the writable mount of the cgroup hierarchy of the container.
+    pub fn mount(&self) -> Result<Mount> {
+        MountBuilder::default()
+            .destination(CGROUP_MOUNT_PATH)
+            .typ("cgroup")
+            .source("cgroup")
+            .options(vec![
+                "nosuid".into(),
+                "noexec".into(),
+                "nodev".into(),
+                "rw".into(),
+            ])
+            .build()
+            .map_err(|e| format_err!("build cgroup mount: {}", e))
+    }
+
+    /// Retrieve the cgroup namespace of the container.
+    pub fn namespace(&self) -> Result<LinuxNamespace> {
+        LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Cgroup)
+            .build()
+            .map_err(|e| format_err!("build cgroup namespace: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotation(value: &str) -> HashMap<String, String> {
+        let mut annotations = HashMap::new();
+        annotations.insert(CGROUP_DELEGATE_ANNOTATION.into(), value.into());
+        annotations
+    }
+
+    fn allowed() -> Vec<String> {
+        vec![CGROUP_DELEGATE_ANNOTATION.into()]
+    }
+
+    #[test]
+    fn from_annotations_success() -> Result<()> {
+        assert_eq!(
+            Delegation::from_annotations(&HashMap::new(), &allowed())?,
+            None
+        );
+        assert_eq!(
+            Delegation::from_annotations(&annotation("false"), &allowed())?,
+            None
+        );
+        assert_eq!(
+            Delegation::from_annotations(&annotation("true"), &allowed())?,
+            Some(Delegation::default())
+        );
+
+        let delegation = Delegation::from_annotations(&annotation("cpu, memory,cpu"), &allowed())?;
+        assert_eq!(
+            delegation.as_ref().map(Delegation::controllers),
+            Some(&vec!["cpu".to_string(), "memory".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn from_annotations_success_not_allowed() -> Result<()> {
+        assert_eq!(
+            Delegation::from_annotations(&annotation("true"), &[])?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn from_annotations_failure() {
+        for value in &["yes", "cpu,", "cpu,devices", "freezer"] {
+            assert!(
+                Delegation::from_annotations(&annotation(value), &allowed()).is_err(),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn mount_and_namespace_success() -> Result<()> {
+        let sut = Delegation::default();
+        let mount = sut.mount()?;
+        assert_eq!(mount.destination().display().to_string(), CGROUP_MOUNT_PATH);
+        assert!(mount
+            .options()
+            .as_ref()
+            .map_or(false, |x| x.contains(&"rw".to_string())));
+        assert!(matches!(sut.namespace()?.typ(), LinuxNamespaceType::Cgroup));
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+//! Container resource management
+//!
+//! The resource management is platform dependent: Linux builds apply the resources to cgroups,
+//! whereas all other platforms use a stub implementation, which allows the crate to be built for
+//! development purposes.
+
+pub mod burst;
+pub mod capacity;
+#[cfg(target_os = "linux")]
+pub mod cgroups;
+pub mod daemon;
+pub mod delegate;
+pub mod pressure;
+pub mod usage;
+
+#[cfg(not(target_os = "linux"))]
+pub mod stub;
+
+use crate::{
+    criapi::LinuxContainerResources,
+    resources::{burst::BurstStats, pressure::Pressure, usage::Usage},
+};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// The cgroup below which the cgroups of all pod sandboxes and their containers are created.
+pub const CGROUP_PARENT: &str = "/cri";
+
+#[cfg(target_os = "linux")]
+/// The resource manager of the current platform.
+pub type DefaultResourceManager = cgroups::CgroupManager;
+
+#[cfg(not(target_os = "linux"))]
+/// The resource manager of the current platform.
+pub type DefaultResourceManager = stub::StubManager;
+
+/// The resource manager trait which defines the methods a platform implementation should fulfill.
+pub trait ResourceManager {
+    /// Apply the provided resources to the cgroup at `cgroup_path`.
+    fn update(&self, cgroup_path: &Path, resources: &LinuxContainerResources) -> Result<()>;
+
+    /// Retrieve the resources which are currently effective for the cgroup at `cgroup_path`.
+    fn resources(&self, cgroup_path: &Path) -> Result<LinuxContainerResources>;
+
+    /// Verify that the kernel accepted the `resources` applied to the cgroup at `cgroup_path`.
+    /// Returns a description of every degradation, like a memory usage above the new limit.
+    fn verify(
+        &self,
+        cgroup_path: &Path,
+        resources: &LinuxContainerResources,
+    ) -> Result<Vec<String>>;
+
+    /// Remove the cgroup at `cgroup_path` if it exists.
+    fn remove(&self, cgroup_path: &Path) -> Result<()>;
+
+    /// Move the process `pid` into the cgroup at `cgroup_path`.
+    fn add_process(&self, cgroup_path: &Path, pid: u32) -> Result<()>;
+
+    /// Retrieve the pressure stall information of the cgroup at `cgroup_path`.
+    fn pressure(&self, cgroup_path: &Path) -> Result<Pressure>;
+
+    /// Retrieve the accumulated resource usage of the cgroup at `cgroup_path`.
+    fn usage(&self, cgroup_path: &Path) -> Result<Usage>;
+
+    /// Allow the cgroup at `cgroup_path` to exceed its CPU quota by `burst` microseconds.
+    fn set_cpu_burst(&self, cgroup_path: &Path, burst: u64) -> Result<()>;
+
+    /// Retrieve the CPU burst statistics of the cgroup at `cgroup_path`.
+    fn burst_stats(&self, cgroup_path: &Path) -> Result<BurstStats>;
+
+    /// Make the `controllers` available to the cgroup at `cgroup_path` by enabling them in all of
+    /// its ancestors. All controllers of the hierarchy are enabled if `controllers` is empty.
+    fn enable_controllers(&self, cgroup_path: &Path, controllers: &[String]) -> Result<()>;
+
+    /// Transfer the ownership of the cgroup at `cgroup_path` to the user `uid` and group `gid`,
+    /// which allows them to manage the subtree below it.
+    fn delegate(&self, cgroup_path: &Path, uid: u32, gid: u32) -> Result<()>;
+}
+
+/// Retrieve the cgroup path of the pod sandbox `sandbox_id`.
+pub fn sandbox_cgroup_path(sandbox_id: &str) -> PathBuf {
+    Path::new(CGROUP_PARENT).join(sandbox_id)
+}
+
+/// Retrieve the cgroup path of the container `id` running inside the pod sandbox `sandbox_id`.
+pub fn container_cgroup_path(sandbox_id: &str, id: &str) -> PathBuf {
+    sandbox_cgroup_path(sandbox_id).join(id)
+}
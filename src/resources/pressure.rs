@@ -0,0 +1,146 @@
+//! Pressure stall information (PSI) as exposed by the cgroup v2 `*.pressure` interface files.
+
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Deserialize, Getters, PartialEq, Serialize)]
+/// The pressure of all supported resources. A resource is `None` if the kernel does not provide
+/// any pressure information for it.
+pub struct Pressure {
+    #[get = "pub"]
+    /// The CPU pressure.
+    cpu: Option<ResourcePressure>,
+
+    #[get = "pub"]
+    /// The memory pressure.
+    memory: Option<ResourcePressure>,
+
+    #[get = "pub"]
+    /// The IO pressure.
+    io: Option<ResourcePressure>,
+}
+
+impl Pressure {
+    /// Create a new pressure from the provided resource pressures.
+    pub fn new(
+        cpu: Option<ResourcePressure>,
+        memory: Option<ResourcePressure>,
+        io: Option<ResourcePressure>,
+    ) -> Self {
+        Self { cpu, memory, io }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Getters, PartialEq, Serialize)]
+/// The pressure of a single resource.
+pub struct ResourcePressure {
+    #[get = "pub"]
+    /// The share of time in which at least some tasks were stalled.
+    some: PressureValues,
+
+    #[get = "pub"]
+    /// The share of time in which all tasks were stalled at the same time.
+    full: Option<PressureValues>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, CopyGetters, PartialEq, Serialize)]
+/// The stall ratios over the last 10, 60 and 300 seconds as well as the total stall time.
+pub struct PressureValues {
+    #[get_copy = "pub"]
+    /// Percentage of stalled time within the last 10 seconds.
+    avg10: f64,
+
+    #[get_copy = "pub"]
+    /// Percentage of stalled time within the last 60 seconds.
+    avg60: f64,
+
+    #[get_copy = "pub"]
+    /// Percentage of stalled time within the last 300 seconds.
+    avg300: f64,
+
+    #[get_copy = "pub"]
+    /// The total stalled time in microseconds.
+    total: u64,
+}
+
+impl ResourcePressure {
+    /// Parse the content of a `*.pressure` interface file, which looks like:
+    ///
+    /// ```text
+    /// some avg10=0.00 avg60=0.00 avg300=0.00 total=0
+    /// full avg10=0.00 avg60=0.00 avg300=0.00 total=0
+    /// ```
+    pub fn parse(content: &str) -> Result<Self> {
+        let (mut some, mut full) = (None, None);
+
+        for line in content.lines().map(str::trim).filter(|x| !x.is_empty()) {
+            let mut fields = line.split_whitespace();
+            let kind = fields.next().unwrap_or_default();
+            let mut values = PressureValues::default();
+
+            for field in fields {
+                let mut parts = field.splitn(2, '=');
+                let (key, value) = match (parts.next(), parts.next()) {
+                    (Some(k), Some(v)) => (k, v),
+                    _ => bail!("invalid pressure field {}", field),
+                };
+                let err = || format!("parse pressure value {}", field);
+                match key {
+                    "avg10" => values.avg10 = value.parse().with_context(err)?,
+                    "avg60" => values.avg60 = value.parse().with_context(err)?,
+                    "avg300" => values.avg300 = value.parse().with_context(err)?,
+                    "total" => values.total = value.parse().with_context(err)?,
+                    _ => {}
+                }
+            }
+
+            match kind {
+                "some" => some = Some(values),
+                "full" => full = Some(values),
+                _ => bail!("invalid pressure line {}", line),
+            }
+        }
+
+        Ok(Self {
+            some: some.context("no 'some' pressure line found")?,
+            full,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_success() -> Result<()> {
+        let pressure = ResourcePressure::parse(
+            "some avg10=1.50 avg60=0.25 avg300=0.00 total=12345\n\
+             full avg10=0.10 avg60=0.00 avg300=0.00 total=42\n",
+        )?;
+
+        assert_eq!(pressure.some().avg10(), 1.5);
+        assert_eq!(pressure.some().avg60(), 0.25);
+        assert_eq!(pressure.some().total(), 12345);
+        assert_eq!(pressure.full().map(|x| x.total()), Some(42));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_success_some_only() -> Result<()> {
+        let pressure = ResourcePressure::parse("some avg10=0.00 avg60=0.00 avg300=0.00 total=1")?;
+        assert_eq!(pressure.some().total(), 1);
+        assert!(pressure.full().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_failure() {
+        assert!(ResourcePressure::parse("").is_err());
+        assert!(ResourcePressure::parse("full avg10=0.00 total=1").is_err());
+        assert!(ResourcePressure::parse("some avg10=wrong").is_err());
+        assert!(ResourcePressure::parse("some avg10").is_err());
+        assert!(ResourcePressure::parse("other avg10=0.00").is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! Resource management for platforms without cgroup support.
+
+use crate::{
+    criapi::LinuxContainerResources,
+    resources::{burst::BurstStats, pressure::Pressure, usage::Usage, ResourceManager},
+};
+use anyhow::Result;
+use log::debug;
+use std::path::Path;
+
+#[derive(Default)]
+/// A resource manager which does not apply any resources at all.
+pub struct StubManager {}
+
+impl ResourceManager for StubManager {
+    fn update(&self, cgroup_path: &Path, _: &LinuxContainerResources) -> Result<()> {
+        debug!(
+            "Skipping resource update of {}: not supported on this platform",
+            cgroup_path.display()
+        );
+        Ok(())
+    }
+
+    fn resources(&self, _: &Path) -> Result<LinuxContainerResources> {
+        Ok(LinuxContainerResources::default())
+    }
+
+    fn verify(&self, cgroup_path: &Path, _: &LinuxContainerResources) -> Result<Vec<String>> {
+        debug!(
+            "Skipping resource verification of {}: not supported on this platform",
+            cgroup_path.display()
+        );
+        Ok(vec![])
+    }
+
+    fn remove(&self, cgroup_path: &Path) -> Result<()> {
+        debug!(
+            "Skipping removal of {}: not supported on this platform",
+            cgroup_path.display()
+        );
+        Ok(())
+    }
+
+    fn add_process(&self, cgroup_path: &Path, pid: u32) -> Result<()> {
+        debug!(
+            "Skipping adding process {} to {}: not supported on this platform",
+            pid,
+            cgroup_path.display()
+        );
+        Ok(())
+    }
+
+    fn pressure(&self, _: &Path) -> Result<Pressure> {
+        Ok(Pressure::default())
+    }
+
+    fn usage(&self, _: &Path) -> Result<Usage> {
+        Ok(Usage::default())
+    }
+
+    fn set_cpu_burst(&self, cgroup_path: &Path, _: u64) -> Result<()> {
+        debug!(
+            "Skipping CPU burst of {}: not supported on this platform",
+            cgroup_path.display()
+        );
+        Ok(())
+    }
+
+    fn burst_stats(&self, _: &Path) -> Result<BurstStats> {
+        Ok(BurstStats::default())
+    }
+
+    fn enable_controllers(&self, cgroup_path: &Path, _: &[String]) -> Result<()> {
+        debug!(
+            "Skipping enabling controllers for {}: not supported on this platform",
+            cgroup_path.display()
+        );
+        Ok(())
+    }
+
+    fn delegate(&self, cgroup_path: &Path, _: u32, _: u32) -> Result<()> {
+        debug!(
+            "Skipping delegation of {}: not supported on this platform",
+            cgroup_path.display()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+//! Resource usage accounting as exposed by the cgroup v1 and v2 interface files.
+
+use anyhow::{Context, Result};
+use getset::CopyGetters;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, CopyGetters, Debug, Default, Deserialize, PartialEq, Serialize)]
+/// The accumulated resource usage of a cgroup.
+pub struct Usage {
+    #[get_copy = "pub"]
+    /// The CPU time used by all tasks of the cgroup in nanoseconds.
+    cpu_nanos: u64,
+
+    #[get_copy = "pub"]
+    /// The memory usage without inactive page cache in bytes, which cannot be easily reclaimed
+    /// and is therefore what the kubelet bases evictions on.
+    memory_working_set: u64,
+}
+
+impl Usage {
+    /// Create a new usage from the `cpu_nanos` and the `memory_usage`, whereas the
+    /// `inactive_file` memory does not count towards the working set.
+    pub fn new(cpu_nanos: u64, memory_usage: u64, inactive_file: u64) -> Self {
+        Self {
+            cpu_nanos,
+            memory_working_set: memory_usage.saturating_sub(inactive_file),
+        }
+    }
+}
+
+/// Retrieve the value of `key` from the `content` of a flat keyed interface file like
+/// `cpu.stat` or `memory.stat`. Returns `None` if the key does not exist.
+pub fn stat_value(content: &str, key: &str) -> Result<Option<u64>> {
+    for line in content.lines() {
+        let mut parts = line.split_whitespace();
+        if parts.next() != Some(key) {
+            continue;
+        }
+        let value = parts.next().unwrap_or_default();
+        return value
+            .parse()
+            .map(Some)
+            .with_context(|| format!("parse {} value {:?}", key, value));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_success() {
+        let usage = Usage::new(10, 100, 30);
+        assert_eq!(usage.cpu_nanos(), 10);
+        assert_eq!(usage.memory_working_set(), 70);
+        assert_eq!(Usage::new(0, 10, 30).memory_working_set(), 0);
+    }
+
+    #[test]
+    fn stat_value_success() -> Result<()> {
+        let content = "usage_usec 1200\nuser_usec 1000\nsystem_usec 200\n";
+        assert_eq!(stat_value(content, "usage_usec")?, Some(1200));
+        assert_eq!(stat_value(content, "system_usec")?, Some(200));
+        assert_eq!(stat_value(content, "nr_periods")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn stat_value_failure() {
+        assert!(stat_value("usage_usec many", "usage_usec").is_err());
+    }
+}
@@ -1,15 +1,176 @@
 use crate::{
+    container::{Container, ContainerState},
     cri_service::CRIService,
     criapi::{AttachRequest, AttachResponse},
+    storage::KeyValueStorage,
+    streaming::session::Session,
 };
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_attach(
         &self,
-        _request: Request<AttachRequest>,
+        request: Request<AttachRequest>,
     ) -> Result<Response<AttachResponse>, Status> {
-        let resp = AttachResponse { url: "url".into() };
+        let request = request.into_inner();
+        if !(request.stdin || request.stdout || request.stderr) {
+            return Err(Status::invalid_argument(
+                "one of stdin, stdout and stderr has to be streamed",
+            ));
+        }
+
+        // Containers never get a terminal, the log writer reads their plain output
+        if request.tty {
+            return Err(Status::invalid_argument(
+                "attaching to a TTY is not supported",
+            ));
+        }
+
+        let container = self
+            .storage()
+            .clone()
+            .get::<_, Container>(Container::key(&request.container_id))
+            .map_err(|e| Status::internal(format!("get container: {}", e)))?
+            .ok_or_else(|| {
+                Status::not_found(format!("container {} not found", request.container_id))
+            })?;
+        if container.state() != ContainerState::Running {
+            return Err(Status::failed_precondition(format!(
+                "container {} is in state {:?}",
+                container,
+                container.state()
+            )));
+        }
+
+        // Only the output of containers with a log gets read by the runtime
+        let attachment = self.logs().attach(&request.container_id).ok_or_else(|| {
+            Status::failed_precondition(format!(
+                "output of container {} is not captured",
+                container
+            ))
+        })?;
+        if request.stdin && attachment.stdin.is_none() {
+            return Err(Status::failed_precondition(format!(
+                "container {} does not read stdin",
+                container
+            )));
+        }
+
+        let recording = self.session_recording(&container, "attach", &[])?;
+        let resp = AttachResponse {
+            url: self.streaming().insert(Session::Attach(request, recording)),
+        };
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        container_log::{attach::Stdin, pipe::pipe},
+        cri_service::tests::{new_cri_service, new_cri_service_with_runtime},
+        criapi::{runtime_service_server::RuntimeService, StartContainerRequest},
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use tonic::Code;
+
+    fn new_attach_request(id: &str) -> AttachRequest {
+        AttachRequest {
+            container_id: id.into(),
+            stdin: true,
+            tty: false,
+            stdout: true,
+            stderr: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn attach_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "running")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+        let request = StartContainerRequest {
+            container_id: id.clone(),
+        };
+        sut.start_container(Request::new(request)).await?;
+
+        // Containers without a log cannot be attached to
+        let response = sut.attach(Request::new(new_attach_request(&id))).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+
+        let ((stdout, _stdout_write), (stderr, _stderr_write)) = (pipe()?, pipe()?);
+        sut.logs()
+            .start(&id, &dir.path().join("0.log"), stdout, stderr, None)?;
+        let request = AttachRequest {
+            stdin: false,
+            ..new_attach_request(&id)
+        };
+        let url = sut.attach(Request::new(request)).await?.into_inner().url;
+        assert!(url.contains("/attach/"));
+
+        // The input can only be requested if the container reads it
+        let response = sut.attach(Request::new(new_attach_request(&id))).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        let (_, stdin_write) = pipe()?;
+        sut.logs().set_stdin(&id, Stdin::new(stdin_write, false)?);
+        sut.attach(Request::new(new_attach_request(&id))).await?;
+        sut.logs().stop(&id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn attach_fail_invalid() -> Result<()> {
+        let sut = new_cri_service()?;
+        let requests = vec![
+            AttachRequest {
+                stdin: false,
+                stdout: false,
+                stderr: false,
+                ..new_attach_request("id")
+            },
+            AttachRequest {
+                tty: true,
+                ..new_attach_request("id")
+            },
+        ];
+        for request in requests {
+            let response = sut.attach(Request::new(request)).await;
+            assert_eq!(
+                response.err().map(|x| x.code()),
+                Some(Code::InvalidArgument)
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn attach_fail_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let response = sut
+            .attach(Request::new(new_attach_request("unknown")))
+            .await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{
+    container::{self, Container, ContainerState, POD_SANDBOX_FIELD},
+    container_log::{pipe::pipe, throttle::LogThrottle},
+    cri_service::CRIService,
+    feature::Feature,
+    network::NetworkStatus,
+    oci::runtime::{error_status, OciRuntime, PID_FILE},
+    sandbox::{checkpoint::Checkpoint, infra::InfraSandbox, Sandbox, SandboxData},
+    storage::KeyValueStorage,
+};
+use log::{info, warn};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+use tonic::Status;
+
+impl<S: KeyValueStorage> CRIService<S> {
+    /// Checkpoint all running containers of the pod sandbox `id` for node maintenance, which
+    /// stops them afterwards. The checkpoint records the network of the sandbox as well, so that
+    /// `restore_pod_sandbox` can continue the containers after a reboot. Returns the IDs of the
+    /// checkpointed containers.
+    pub async fn checkpoint_pod_sandbox(&self, id: &str) -> Result<Vec<String>, Status> {
+        self.queued(id, None, self.checkpoint_containers(id)).await
+    }
+
+    /// Restore the containers of the checkpoint of the pod sandbox `id`, whereas the sandbox
+    /// gets started and attached to its network again first if the node rebooted in the
+    /// meantime. The checkpoint gets removed once all containers are running again. Returns the
+    /// IDs of the restored containers.
+    pub async fn restore_pod_sandbox(&self, id: &str) -> Result<Vec<String>, Status> {
+        self.queued(id, None, self.restore_containers(id)).await
+    }
+
+    async fn checkpoint_containers(&self, id: &str) -> Result<Vec<String>, Status> {
+        let dir = Checkpoint::dir(&self.checkpoint_path()?, id);
+        let mut storage = self.storage().clone();
+        let sandbox = self.checkpoint_sandbox(id)?;
+        let key = Checkpoint::key(id);
+        if storage
+            .get::<_, Checkpoint>(&key)
+            .map_err(|e| Status::internal(format!("get checkpoint: {}", e)))?
+            .is_some()
+        {
+            return Err(Status::already_exists(format!(
+                "pod sandbox {} is checkpointed already",
+                sandbox
+            )));
+        }
+
+        let ids = container::INDEX
+            .lookup(&mut storage, POD_SANDBOX_FIELD, id)
+            .map_err(|e| Status::internal(format!("select containers: {:#}", e)))?;
+        let mut containers = vec![];
+        for container_id in &ids {
+            if let Some(container) = storage
+                .get::<_, Container>(Container::key(container_id))
+                .map_err(|e| Status::internal(format!("get container: {}", e)))?
+                .filter(|x| x.state() == ContainerState::Running)
+            {
+                containers.push(container);
+            }
+        }
+        if containers.is_empty() {
+            return Err(Status::failed_precondition(format!(
+                "pod sandbox {} has no running containers",
+                sandbox
+            )));
+        }
+
+        // Every checkpointed container gets recorded immediately, so that a failing container
+        // does not lose the ones stopped before
+        let network = storage
+            .get::<_, NetworkStatus>(NetworkStatus::key(id))
+            .map_err(|e| Status::internal(format!("get network status: {}", e)))?;
+        let mut checkpoint = Checkpoint::new(network, self.unix_nanos()?);
+        let runtime = OciRuntime::new(self.config().oci_runtime());
+        for mut container in containers {
+            let image_path = dir.join(container.id());
+            fs::create_dir_all(&image_path).map_err(|e| {
+                Status::internal(format!(
+                    "create checkpoint dir {}: {}",
+                    image_path.display(),
+                    e
+                ))
+            })?;
+            runtime
+                .checkpoint(container.id(), &image_path)
+                .await
+                .map_err(|e| error_status("checkpoint container", e))?;
+            checkpoint.add_container(container.id());
+            storage
+                .insert(&key, &checkpoint)
+                .map_err(|e| Status::internal(format!("update checkpoint: {}", e)))?;
+
+            container.set_exited(self.unix_nanos()?);
+            storage
+                .insert(Container::key(container.id()), &container)
+                .map_err(|e| Status::internal(format!("update container: {}", e)))?;
+            info!("Checkpointed container {}", container);
+        }
+        info!("Checkpointed pod sandbox {}", sandbox);
+        Ok(checkpoint.containers().clone())
+    }
+
+    async fn restore_containers(&self, id: &str) -> Result<Vec<String>, Status> {
+        let dir = Checkpoint::dir(&self.checkpoint_path()?, id);
+        let mut storage = self.storage().clone();
+        let mut sandbox = self.checkpoint_sandbox(id)?;
+        let key = Checkpoint::key(id);
+        let checkpoint = storage
+            .get::<_, Checkpoint>(&key)
+            .map_err(|e| Status::internal(format!("get checkpoint: {}", e)))?
+            .ok_or_else(|| {
+                Status::not_found(format!("pod sandbox {} is not checkpointed", sandbox))
+            })?;
+
+        // The infra process and the network namespace of the sandbox do not survive reboots
+        if !sandbox
+            .ready()
+            .map_err(|e| Status::internal(format!("check pod sandbox: {:#}", e)))?
+        {
+            sandbox
+                .run()
+                .map_err(|e| Status::internal(format!("run pod sandbox: {:#}", e)))?;
+            storage
+                .insert(Sandbox::<InfraSandbox>::key(id), &sandbox)
+                .map_err(|e| Status::internal(format!("update pod sandbox: {}", e)))?;
+        }
+        let network = storage
+            .get::<_, NetworkStatus>(NetworkStatus::key(id))
+            .map_err(|e| Status::internal(format!("get network status: {}", e)))?
+            .or_else(|| checkpoint.network().clone());
+        if let Some(network) = network {
+            self.recover_network(sandbox.data(), &network)
+                .await
+                .map_err(|e| Status::internal(format!("recover network: {:#}", e)))?;
+        }
+
+        let runtime = OciRuntime::new(self.config().oci_runtime());
+        for container_id in checkpoint.containers() {
+            let mut container = storage
+                .get::<_, Container>(Container::key(container_id))
+                .map_err(|e| Status::internal(format!("get container: {}", e)))?
+                .ok_or_else(|| {
+                    Status::not_found(format!("container {} not found", container_id))
+                })?;
+
+            // Containers restored by an interrupted attempt are not restored twice
+            if container.state() == ContainerState::Running {
+                continue;
+            }
+            let (bundle, image_path) = (container.bundle(), dir.join(container_id));
+            let pid_file = bundle.join(PID_FILE);
+            let res = match self.restore_log(sandbox.data(), &container)? {
+                Some((stdout, stderr)) => {
+                    let res = runtime
+                        .restore_with_output(
+                            container_id,
+                            bundle,
+                            &pid_file,
+                            &image_path,
+                            stdout,
+                            stderr,
+                        )
+                        .await;
+                    if res.is_err() {
+                        self.logs().stop(container_id);
+                    }
+                    res
+                }
+                None => {
+                    runtime
+                        .restore(container_id, bundle, &pid_file, &image_path)
+                        .await
+                }
+            };
+            res.map_err(|e| error_status("restore container", e))?;
+
+            container.set_running(self.unix_nanos()?);
+            storage
+                .insert(Container::key(container_id), &container)
+                .map_err(|e| Status::internal(format!("update container: {}", e)))?;
+            info!("Restored container {}", container);
+        }
+
+        storage
+            .remove(&key)
+            .map_err(|e| Status::internal(format!("remove checkpoint: {}", e)))?;
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            warn!("Unable to remove checkpoint {}: {}", dir.display(), e);
+        }
+        info!("Restored pod sandbox {}", sandbox);
+        Ok(checkpoint.containers().clone())
+    }
+
+    /// Start writing the log of the restored `container` inside the `sandbox` again, if the
+    /// kubelet requested one. Returns the write ends of its standard output and error.
+    fn restore_log(
+        &self,
+        sandbox: &SandboxData,
+        container: &Container,
+    ) -> Result<Option<(File, File)>, Status> {
+        if sandbox.log_directory().is_empty() || container.log_path().is_empty() {
+            return Ok(None);
+        }
+        let path = Path::new(sandbox.log_directory()).join(container.log_path());
+        let throttle = LogThrottle::from_annotations(
+            container.annotations(),
+            self.config().log_rate_limit(),
+            self.config().log_burst(),
+            self.clock().instant(),
+        )
+        .map_err(|e| Status::invalid_argument(format!("parse log rate limit: {:#}", e)))?;
+
+        let new_pipe = || pipe().map_err(|e| Status::internal(format!("{:#}", e)));
+        let ((stdout, stdout_write), (stderr, stderr_write)) = (new_pipe()?, new_pipe()?);
+        self.logs()
+            .start(container.id(), &path, stdout, stderr, throttle)
+            .map_err(|e| Status::internal(format!("start container log: {:#}", e)))?;
+        Ok(Some((stdout_write, stderr_write)))
+    }
+
+    /// Retrieve the directory of the pod checkpoints, which requires the checkpoint feature.
+    fn checkpoint_path(&self) -> Result<PathBuf, Status> {
+        if !self.config().features().contains(&Feature::Checkpointing) {
+            return Err(Status::failed_precondition(
+                "the checkpoint feature is not enabled",
+            ));
+        }
+        self.config()
+            .checkpoint_path()
+            .clone()
+            .ok_or_else(|| Status::failed_precondition("no checkpoint path configured"))
+    }
+
+    /// Retrieve the pod sandbox `id` to checkpoint or restore.
+    fn checkpoint_sandbox(&self, id: &str) -> Result<Sandbox<InfraSandbox>, Status> {
+        self.storage()
+            .clone()
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(id))
+            .map_err(|e| Status::internal(format!("get pod sandbox: {}", e)))?
+            .ok_or_else(|| Status::not_found(format!("pod sandbox {} not found", id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::{new_cri_service_with_config, test_config},
+        criapi::{runtime_service_server::RuntimeService, StartContainerRequest},
+        oci::runtime::tests::{fake_runtime, fake_runtime_log},
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::{Context, Result};
+    use tempfile::tempdir;
+    use tonic::{Code, Request};
+
+    #[tokio::test]
+    async fn checkpoint_restore_success() -> Result<()> {
+        let dir = tempdir()?;
+        let checkpoints = dir.path().join("checkpoints");
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .oci_runtime(fake_runtime(dir.path(), "running")?)
+                .features(vec![Feature::Checkpointing])
+                .checkpoint_path(Some(checkpoints.clone()))
+                .build()?,
+        )?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+        let get_container = || -> Result<Container> {
+            sut.storage()
+                .clone()
+                .get::<_, Container>(Container::key(&id))?
+                .context("container not stored")
+        };
+
+        // Only running containers get checkpointed
+        let response = sut.checkpoint_pod_sandbox(&sandbox_id).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        let request = StartContainerRequest {
+            container_id: id.clone(),
+        };
+        sut.start_container(Request::new(request)).await?;
+
+        assert_eq!(
+            sut.checkpoint_pod_sandbox(&sandbox_id).await?,
+            vec![id.clone()]
+        );
+        let image_path = Checkpoint::dir(&checkpoints, &sandbox_id).join(&id);
+        assert!(image_path.exists());
+        assert_eq!(get_container()?.state(), ContainerState::Exited);
+        let response = sut.checkpoint_pod_sandbox(&sandbox_id).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::AlreadyExists));
+
+        assert_eq!(
+            sut.restore_pod_sandbox(&sandbox_id).await?,
+            vec![id.clone()]
+        );
+        assert_eq!(get_container()?.state(), ContainerState::Running);
+        assert!(!image_path.exists());
+        assert!(sut
+            .storage()
+            .clone()
+            .get::<_, Checkpoint>(Checkpoint::key(&sandbox_id))?
+            .is_none());
+        let log = fake_runtime_log(dir.path())?;
+        let image_path = image_path.display();
+        assert!(log.contains(&format!("checkpoint --image-path {} {}", image_path, id)));
+        assert!(log
+            .iter()
+            .any(|x| x.starts_with(&format!("restore --detach --image-path {} ", image_path))));
+
+        // Restoring needs a checkpoint
+        let response = sut.restore_pod_sandbox(&sandbox_id).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checkpoint_fail_disabled() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .features(vec![Feature::Checkpointing])
+                .build()?,
+        )?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        let response = sut.checkpoint_pod_sandbox(&sandbox_id).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .checkpoint_path(Some(dir.path().to_path_buf()))
+                .build()?,
+        )?;
+        let response = sut.restore_pod_sandbox(&sandbox_id).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        Ok(())
+    }
+}
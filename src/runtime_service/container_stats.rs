@@ -1,15 +1,53 @@
 use crate::{
+    container::Container,
     cri_service::CRIService,
     criapi::{ContainerStatsRequest, ContainerStatsResponse},
+    resources::DefaultResourceManager,
+    storage::KeyValueStorage,
 };
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_container_stats(
         &self,
-        _request: Request<ContainerStatsRequest>,
+        request: Request<ContainerStatsRequest>,
     ) -> Result<Response<ContainerStatsResponse>, Status> {
-        let resp = ContainerStatsResponse { stats: None };
+        let id = request.into_inner().container_id;
+        let container = self
+            .storage()
+            .clone()
+            .get::<_, Container>(Container::key(&id))
+            .map_err(|e| Status::internal(format!("get container: {}", e)))?
+            .ok_or_else(|| Status::not_found(format!("container {} not found", id)))?;
+
+        let stats = self
+            .stats()
+            .get(&DefaultResourceManager::default(), &container)
+            .map_err(|e| Status::internal(format!("get container stats: {:#}", e)))?;
+
+        let resp = ContainerStatsResponse { stats: Some(stats) };
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service, criapi::runtime_service_server::RuntimeService,
+    };
+    use anyhow::Result;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn container_stats_fail_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let response = sut
+            .container_stats(Request::new(ContainerStatsRequest {
+                container_id: "unknown".into(),
+            }))
+            .await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+}
@@ -1,19 +1,195 @@
 use crate::{
     cri_service::CRIService,
-    criapi::{ContainerStatusRequest, ContainerStatusResponse},
+    criapi::{
+        self, ContainerResources, ContainerStatus, ContainerStatusRequest, ContainerStatusResponse,
+        ImageSpec,
+    },
+    resources::{container_cgroup_path, DefaultResourceManager, ResourceManager},
+    storage::KeyValueStorage,
 };
+use log::debug;
 use std::collections::HashMap;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_container_status(
         &self,
-        _request: Request<ContainerStatusRequest>,
+        request: Request<ContainerStatusRequest>,
     ) -> Result<Response<ContainerStatusResponse>, Status> {
+        let request = request.into_inner();
+        let container = self.resolve_container(&request.container_id)?;
+
+        // Report the resources the kernel applied, which may differ from the requested ones
+        let cgroup_path = container_cgroup_path(container.pod_sandbox_id(), container.id());
+        let resources = match DefaultResourceManager::default().resources(&cgroup_path) {
+            Ok(linux) => Some(ContainerResources {
+                linux: Some(linux),
+                windows: None,
+            }),
+            Err(e) => {
+                debug!(
+                    "Unable to get resources of container {}: {:#}",
+                    container, e
+                );
+                None
+            }
+        };
+
+        // Extra information is only allowed on verbose requests
+        let mut info = HashMap::new();
+        if request.verbose && !container.resource_degradations().is_empty() {
+            let degradations = serde_json::to_string(container.resource_degradations())
+                .map_err(|e| Status::internal(format!("serialize degradations: {}", e)))?;
+            info.insert("resourceDegradations".into(), degradations);
+        }
+        let history = self.stats().history(container.id());
+        if request.verbose && !history.is_empty() {
+            let history = serde_json::to_string(&history)
+                .map_err(|e| Status::internal(format!("serialize stats history: {}", e)))?;
+            info.insert("statsHistory".into(), history);
+        }
+
+        let status = ContainerStatus {
+            id: container.id().clone(),
+            metadata: Some(container.metadata()),
+            state: criapi::ContainerState::from(container.state()) as i32,
+            created_at: container.created_at(),
+            started_at: container.started_at(),
+            finished_at: container.finished_at(),
+            exit_code: 0,
+            image: Some(ImageSpec {
+                image: container.image().clone(),
+                annotations: HashMap::new(),
+            }),
+            image_ref: container.image().clone(),
+            reason: "".into(),
+            message: "".into(),
+            labels: container.labels().clone(),
+            annotations: container.annotations().clone(),
+            mounts: vec![],
+            log_path: container.log_path().clone(),
+            resources,
+        };
+
         let resp = ContainerStatusResponse {
-            info: HashMap::new(),
-            status: None,
+            info,
+            status: Some(status),
         };
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        container::{Container, ContainerBuilder},
+        cri_service::tests::{new_cri_service, new_cri_service_with_runtime},
+        criapi::runtime_service_server::RuntimeService,
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::{format_err, Context, Result};
+    use tempfile::tempdir;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn container_status_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+
+        let request = ContainerStatusRequest {
+            container_id: id.clone(),
+            verbose: true,
+        };
+        let response = sut.container_status(Request::new(request)).await?;
+        assert!(response.get_ref().info.is_empty());
+        let status = response.get_ref().status.as_ref().context("no status")?;
+        assert_eq!(status.id, id);
+        assert_eq!(
+            status.state,
+            criapi::ContainerState::ContainerCreated as i32
+        );
+        assert_eq!(
+            status.metadata.as_ref().map(|x| x.name.as_str()),
+            Some("name")
+        );
+        assert!(status.created_at > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn container_status_success_id_prefix() -> Result<()> {
+        let sut = new_cri_service()?;
+        for id in &["abc123", "abd456", "abd"] {
+            let container = ContainerBuilder::default()
+                .id(*id)
+                .pod_sandbox_id("sandbox")
+                .name(*id)
+                .attempt(0u32)
+                .bundle("/bundle")
+                .build()
+                .map_err(|e| format_err!("build container: {}", e))?;
+            sut.storage()
+                .clone()
+                .insert(Container::key(id), &container)?;
+        }
+        sut.rebuild_indexes()?;
+        let request = |id: &str| {
+            Request::new(ContainerStatusRequest {
+                container_id: id.into(),
+                verbose: false,
+            })
+        };
+
+        let response = sut.container_status(request("abc")).await?;
+        assert_eq!(
+            response.get_ref().status.as_ref().map(|x| x.id.as_str()),
+            Some("abc123")
+        );
+
+        // Full IDs win over longer IDs with the same prefix
+        let response = sut.container_status(request("abd")).await?;
+        assert_eq!(
+            response.get_ref().status.as_ref().map(|x| x.id.as_str()),
+            Some("abd")
+        );
+
+        let response = sut.container_status(request("ab")).await;
+        let err = response.err().context("ambiguous prefix resolved")?;
+        assert_eq!(err.code(), Code::InvalidArgument);
+        assert!(err.message().contains("ambiguous"), "{}", err.message());
+        assert_eq!(
+            sut.container_status(request("abe"))
+                .await
+                .err()
+                .map(|x| x.code()),
+            Some(Code::NotFound)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn container_status_fail_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = ContainerStatusRequest {
+            container_id: "unknown".into(),
+            verbose: false,
+        };
+        let response = sut.container_status(Request::new(request)).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+}
@@ -1,17 +1,1323 @@
 use crate::{
+    annotations::{handler_annotations, runtime_annotations, Tuning},
+    container::{
+        self,
+        core_dump::{core_pattern, CoreDumpPolicy, CORE_PATTERN_PATH},
+        Container, ContainerBuilder,
+    },
+    container_log::{attach::Stdin, pipe::pipe, tee::Tee, throttle::LogThrottle},
     cri_service::CRIService,
-    criapi::{CreateContainerRequest, CreateContainerResponse},
+    criapi::{ContainerConfig, CreateContainerRequest, CreateContainerResponse, Mount},
+    device::{allowed_devices, requested_devices, Device},
+    error_details::ErrorDetails,
+    feature::Feature,
+    idempotency::IdempotencyRecord,
+    image::{
+        artifact::{self, ArtifactRecord, ARTIFACTS_MOUNT_DIR},
+        rootfs,
+        store::{ImageRecord, ImageStore},
+        usage::ImageUsage,
+    },
+    oci::{
+        runtime::{error_status, OciRuntime, PID_FILE},
+        spec::{container_spec, SpecOptions, ROOTFS_DIR, SPEC_FILE},
+    },
+    oci_spec::image::Image,
+    quota::QuotaKind,
+    resources::{
+        burst::cpu_burst, capacity::NodeCapacity, container_cgroup_path, delegate::Delegation,
+        DefaultResourceManager, ResourceManager,
+    },
+    sandbox::{
+        hosts::{hosts_file, HOSTS_FILE, HOSTS_PATH},
+        infra::InfraSandbox,
+        init_sequence::InitSequence,
+        Sandbox, SandboxData,
+    },
+    storage::KeyValueStorage,
 };
-use tonic::{Request, Response, Status};
+use log::{debug, info, warn};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio::task;
+use tonic::{Code, Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_create_container(
         &self,
-        _request: Request<CreateContainerRequest>,
+        request: Request<CreateContainerRequest>,
     ) -> Result<Response<CreateContainerResponse>, Status> {
+        // Run all admissions which may reject or mutate the request
+        let mut request = request.into_inner();
+        self.admission()
+            .admit_container(&mut request)
+            .await
+            .map_err(|e| {
+                ErrorDetails::new("admit container")
+                    .hint("check the namespace policies of the runtime")
+                    .status(Code::PermissionDenied, format!("{:#}", e))
+            })?;
+
+        // Return the original result if the request is a retry of an already successful one
+        let mut storage = self.storage().clone();
+        let idempotency_key = request
+            .config
+            .as_ref()
+            .and_then(|x| x.metadata.as_ref())
+            .map(|x| IdempotencyRecord::container_key(&request.pod_sandbox_id, &x.name, x.attempt));
+        if let Some(key) = &idempotency_key {
+            if let Some(record) = storage
+                .get::<_, IdempotencyRecord>(key)
+                .map_err(|e| Status::internal(format!("get idempotency record: {}", e)))?
+            {
+                info!("Container {} already created for request", record.id());
+                let resp = CreateContainerResponse {
+                    container_id: record.id().into(),
+                };
+                return Ok(Response::new(resp));
+            }
+        }
+
+        // Validate the requested resources before doing anything else
+        let resources = request
+            .config
+            .as_ref()
+            .and_then(|x| x.linux.as_ref())
+            .and_then(|x| x.resources.as_ref());
+        if let Some(resources) = resources {
+            match NodeCapacity::host() {
+                Ok(capacity) => capacity.validate(resources).map_err(|e| {
+                    ErrorDetails::new("validate resources")
+                        .hint("reduce the requested resources or use a larger node")
+                        .status(
+                            Code::ResourceExhausted,
+                            format!("insufficient node capacity: {}", e),
+                        )
+                })?,
+                Err(e) => warn!("Skipping resource validation: {}", e),
+            }
+        }
+
+        // Verify that the config exists
+        let config = request
+            .config
+            .take()
+            .ok_or_else(|| Status::invalid_argument("no container config provided"))?;
+        let metadata = config
+            .metadata
+            .as_ref()
+            .ok_or_else(|| Status::invalid_argument("no container metadata provided"))?;
+
+        // Containers can only be created inside of existing pod sandboxes
+        let sandbox = storage
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(&request.pod_sandbox_id))
+            .map_err(|e| Status::internal(format!("get pod sandbox: {}", e)))?
+            .ok_or_else(|| {
+                Status::not_found(format!("pod sandbox {} not found", request.pod_sandbox_id))
+            })?;
+        let id = Container::new_id(sandbox.id(), &metadata.name, metadata.attempt);
+        let _reservation = self.enforce_quota(
+            &id,
+            QuotaKind::Container,
+            sandbox.data().namespace(),
+            sandbox.data().runtime_handler(),
+        )?;
+
+        // Containers can only be created from pulled images
+        let image = self.container_image(&mut storage, &config)?;
+
+        // Create the container from its bundle and remove the bundle on failure
+        let bundle = self.config().container_path().join(&id);
+        let mut guard =
+            CreateGuard::new(OciRuntime::new(self.config().oci_runtime()), &id, &bundle);
+        if let Err(e) = self
+            .create_oci_container(&id, &bundle, &config, sandbox.data(), &image)
+            .await
+        {
+            guard.disarm();
+            if let Err(e) = fs::remove_dir_all(&bundle) {
+                warn!("Unable to remove bundle {}: {}", bundle.display(), e);
+            }
+            return Err(e);
+        }
+
+        let container = ContainerBuilder::default()
+            .id(id)
+            .pod_sandbox_id(sandbox.id())
+            .name(metadata.name.clone())
+            .attempt(metadata.attempt)
+            .image(
+                config
+                    .image
+                    .as_ref()
+                    .map(|x| x.image.clone())
+                    .unwrap_or_default(),
+            )
+            .bundle(bundle)
+            .created_at(self.unix_nanos()?)
+            .labels(config.labels.clone())
+            .annotations(config.annotations.clone())
+            .log_path(config.log_path.clone())
+            .image_stop_signal(
+                image
+                    .1
+                    .config()
+                    .as_ref()
+                    .and_then(|x| x.stop_signal().clone()),
+            )
+            .build()
+            .map_err(|e| Status::internal(format!("build container: {}", e)))?;
+        container::INDEX
+            .insert(&mut storage, container.id(), &container.index_entries())
+            .map_err(|e| Status::internal(format!("index container: {:#}", e)))?;
+        self.index_container_id(container.id())?;
+        storage
+            .insert(Container::key(container.id()), &container)
+            .map_err(|e| Status::internal(format!("insert container: {}", e)))?;
+        guard.disarm();
+        info!("Created container {} in pod sandbox {}", container, sandbox);
+        self.record_image_use(&mut storage, container.image(), container.created_at());
+        self.prefetch_next_image(&mut storage, sandbox.data(), container.name());
+
+        if let Some(key) = &idempotency_key {
+            storage
+                .insert(key, IdempotencyRecord::new(container.id().clone()))
+                .map_err(|e| Status::internal(format!("insert idempotency record: {}", e)))?;
+        }
+
         let resp = CreateContainerResponse {
-            container_id: "stub".into(),
+            container_id: container.id().clone(),
         };
         Ok(Response::new(resp))
     }
+
+    /// Retrieve the record and the config of the image of the container `config` from the image
+    /// store. The image has to be pulled before creating the container.
+    fn container_image(
+        &self,
+        storage: &mut S,
+        config: &ContainerConfig,
+    ) -> Result<(ImageRecord, Image), Status> {
+        let image = config
+            .image
+            .as_ref()
+            .map(|x| x.image.as_str())
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| Status::invalid_argument("no container image provided"))?;
+        let record = ImageStore::find(storage, image)
+            .map_err(|e| Status::internal(format!("find image {}: {:#}", image, e)))?
+            .ok_or_else(|| {
+                ErrorDetails::new("find image")
+                    .hint("pull the image before creating the container")
+                    .status(Code::NotFound, format!("image {} not found", image))
+            })?;
+        let config = self
+            .image_store()?
+            .config(&record)
+            .map_err(|e| Status::internal(format!("read config of image {}: {:#}", image, e)))?;
+        Ok((record, config))
+    }
+
+    /// Record the use of the `image` at `now` for the garbage collection. Failures do not affect
+    /// the created container and are only logged.
+    fn record_image_use(&self, storage: &mut S, image: &str, now: i64) {
+        if image.is_empty() {
+            return;
+        }
+        let res = ImageStore::find(storage, image).and_then(|x| {
+            x.map(|x| ImageUsage::record_use(storage, x.id(), now))
+                .transpose()
+        });
+        if let Err(e) = res {
+            warn!("Unable to record use of image {}: {:#}", image, e);
+        }
+    }
+
+    /// Prefetch the image of the container following `name` in the init sequence announced by the
+    /// `sandbox`. Failures do not affect the created container and are only logged.
+    fn prefetch_next_image(&self, storage: &mut S, sandbox: &SandboxData, name: &str) {
+        let sequence = match InitSequence::from_annotations(sandbox.annotations()) {
+            Ok(Some(sequence)) => sequence,
+            Ok(None) => return,
+            Err(e) => {
+                warn!(
+                    "Ignoring init sequence of pod sandbox {}: {:#}",
+                    sandbox.id(),
+                    e
+                );
+                return;
+            }
+        };
+        if let Some(step) = sequence.next(name) {
+            self.prefetch_images(storage, &[step.image().clone()]);
+        }
+    }
+
+    /// Write the OCI bundle of the container `id` from its `config` and `image` into `bundle` and
+    /// create the container via the OCI runtime.
+    async fn create_oci_container(
+        &self,
+        id: &str,
+        bundle: &Path,
+        config: &ContainerConfig,
+        sandbox: &SandboxData,
+        image: &(ImageRecord, Image),
+    ) -> Result<(), Status> {
+        // Copying the layers is IO bound and must not block the other requests
+        let rootfs = bundle.join(ROOTFS_DIR);
+        let (store, record, path) = (self.image_store()?, image.0.clone(), rootfs.clone());
+        task::spawn_blocking(move || rootfs::build(&store, &record, &path))
+            .await
+            .map_err(|e| Status::internal(format!("wait for root filesystem: {}", e)))?
+            .map_err(|e| Status::internal(format!("build root filesystem: {:#}", e)))?;
+
+        let mut config = config.clone();
+        self.mount_hosts_file(bundle, &mut config, sandbox)?;
+        self.mount_artifacts(&mut config, sandbox).await?;
+
+        let delegation =
+            Delegation::from_annotations(&config.annotations, self.config().allowed_annotations())
+                .map_err(|e| {
+                    Status::invalid_argument(format!("parse cgroup delegation: {:#}", e))
+                })?;
+        let throttle = LogThrottle::from_annotations(
+            &config.annotations,
+            self.config().log_rate_limit(),
+            self.config().log_burst(),
+            self.clock().instant(),
+        )
+        .map_err(|e| Status::invalid_argument(format!("parse log rate limit: {:#}", e)))?;
+        let tee = Tee::from_annotations(
+            &config.annotations,
+            self.config().output_tee_path().as_deref(),
+        )
+        .map_err(|e| Status::invalid_argument(format!("parse output tee: {:#}", e)))?;
+        if tee.is_some() && log_file(sandbox, &config).is_none() {
+            return Err(Status::invalid_argument(
+                "teeing the output requires a container log",
+            ));
+        }
+        let name = config.metadata.as_ref().map_or("", |x| x.name.as_str());
+        let core_dump = CoreDumpPolicy::new(
+            self.config().core_dump_path().as_deref(),
+            self.config().core_dump_size(),
+            &config.annotations,
+            [sandbox.namespace().as_str(), sandbox.name().as_str(), name],
+        )
+        .map_err(|e| Status::invalid_argument(format!("get core dump policy: {:#}", e)))?;
+        let tuning = self.tuning(sandbox, name)?;
+        let burst = match config.linux.as_ref().and_then(|x| x.resources.as_ref()) {
+            Some(resources) => cpu_burst(&config.annotations, self.config().cpu_burst(), resources)
+                .map_err(|e| Status::invalid_argument(format!("get CPU burst: {:#}", e)))?,
+            None => None,
+        };
+        let devices = self.devices(sandbox, &tuning)?;
+        let core_pattern = match core_dump {
+            CoreDumpPolicy::Disabled => None,
+            CoreDumpPolicy::Limited { .. } => core_pattern(Path::new(CORE_PATTERN_PATH))
+                .map_err(|e| warn!("Unable to redirect core dumps: {:#}", e))
+                .ok(),
+        };
+        let cgroup_path = container_cgroup_path(sandbox.id(), id);
+        let hardened = self.config().features().contains(&Feature::Hardened);
+        config.annotations = runtime_annotations(
+            &config.annotations,
+            sandbox.runtime_handler(),
+            self.config().runtime_allowed_annotations(),
+        );
+        let spec = container_spec(
+            &config,
+            sandbox,
+            &rootfs,
+            &cgroup_path,
+            &SpecOptions {
+                image: image.1.config().as_ref(),
+                delegation: delegation.as_ref(),
+                devices: &devices,
+                core_dump: Some(&core_dump),
+                core_pattern: core_pattern.as_deref(),
+                tuning: Some(&tuning),
+                hardened,
+            },
+        )
+        .map_err(|e| Status::invalid_argument(format!("build OCI spec: {:#}", e)))?;
+        spec.save(&bundle.join(SPEC_FILE))
+            .map_err(|e| Status::internal(format!("save OCI spec: {:#}", e)))?;
+
+        // The controllers have to be available before the runtime creates the cgroup
+        let manager = DefaultResourceManager::default();
+        if let Some(delegation) = &delegation {
+            manager
+                .enable_controllers(&cgroup_path, delegation.controllers())
+                .map_err(|e| Status::internal(format!("enable cgroup controllers: {:#}", e)))?;
+        }
+
+        // The log captures the output of the container if the kubelet requested it
+        let runtime = OciRuntime::new(self.config().oci_runtime());
+        let pid_file = bundle.join(PID_FILE);
+        let res = match log_file(sandbox, &config) {
+            Some(path) => {
+                let new_pipe = || pipe().map_err(|e| Status::internal(format!("{:#}", e)));
+                let ((stdout, stdout_write), (stderr, stderr_write)) = (new_pipe()?, new_pipe()?);
+                self.logs()
+                    .start(id, &path, stdout, stderr, throttle)
+                    .map_err(|e| Status::internal(format!("start container log: {:#}", e)))?;
+                if let Some(tee) = tee {
+                    self.logs().tee(id, tee);
+                }
+
+                // Attached sessions write the input of the container through the log writer
+                let stdin = if config.stdin {
+                    let (stdin, stdin_write) = new_pipe()?;
+                    let input = Stdin::new(stdin_write, config.stdin_once)
+                        .map_err(|e| Status::internal(format!("{:#}", e)))?;
+                    self.logs().set_stdin(id, input);
+                    Some(stdin)
+                } else {
+                    None
+                };
+                let res = runtime
+                    .create_with_output(id, bundle, &pid_file, stdin, stdout_write, stderr_write)
+                    .await;
+
+                // Leftover processes of a failed creation may keep the output open
+                if res.is_err() {
+                    self.logs().stop(id);
+                }
+                res
+            }
+            None => runtime.create(id, bundle, &pid_file).await,
+        };
+        res.map_err(|e| error_status("create container", e))?;
+
+        if delegation.is_some() {
+            let user = spec
+                .process()
+                .as_ref()
+                .map(|x| (x.user().uid(), x.user().gid()));
+            let (uid, gid) = user.unwrap_or_default();
+            if let Err(e) = manager.delegate(&cgroup_path, uid, gid) {
+                if let Err(e) = runtime.delete(id, true).await {
+                    warn!("Unable to delete container {}: {:#}", id, e);
+                }
+                return Err(Status::internal(format!("delegate cgroup: {:#}", e)));
+            }
+            info!(
+                "Delegated cgroup {} to container {}",
+                cgroup_path.display(),
+                id
+            );
+        }
+
+        // Kernels without burst support run the container without it
+        if let Some(burst) = burst {
+            if let Err(e) = manager.set_cpu_burst(&cgroup_path, burst) {
+                warn!("Unable to set CPU burst of container {}: {:#}", id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge the extra hosts into the hosts file of the kubelet and mount the result from the
+    /// `bundle` instead. The mounts of the `config` are unchanged if there is nothing to add.
+    fn mount_hosts_file(
+        &self,
+        bundle: &Path,
+        config: &mut ContainerConfig,
+        sandbox: &SandboxData,
+    ) -> Result<(), Status> {
+        let index = config
+            .mounts
+            .iter()
+            .position(|x| x.container_path == HOSTS_PATH);
+        let kubelet_hosts =
+            match index {
+                Some(index) => {
+                    let path = &config.mounts[index].host_path;
+                    Some(fs::read_to_string(path).map_err(|e| {
+                        Status::internal(format!("read hosts file {}: {}", path, e))
+                    })?)
+                }
+                None => None,
+            };
+
+        let content = match hosts_file(
+            kubelet_hosts.as_deref(),
+            self.config().extra_hosts(),
+            sandbox.annotations(),
+        )
+        .map_err(|e| Status::invalid_argument(format!("build hosts file: {:#}", e)))?
+        {
+            Some(content) => content,
+            None => return Ok(()),
+        };
+        let path = bundle.join(HOSTS_FILE);
+        fs::write(&path, content)
+            .map_err(|e| Status::internal(format!("write hosts file {}: {}", path.display(), e)))?;
+
+        let host_path = path.display().to_string();
+        match index {
+            Some(index) => config.mounts[index].host_path = host_path,
+            None => config.mounts.push(Mount {
+                container_path: HOSTS_PATH.into(),
+                host_path,
+                ..Default::default()
+            }),
+        }
+        Ok(())
+    }
+
+    /// Mount the artifacts referenced by the `sandbox` and the container `config` read-only below
+    /// the artifacts directory of the container. Artifacts which have not been pulled before get
+    /// pulled with the credentials of the node.
+    async fn mount_artifacts(
+        &self,
+        config: &mut ContainerConfig,
+        sandbox: &SandboxData,
+    ) -> Result<(), Status> {
+        let artifacts = artifact::artifacts(sandbox.annotations(), &config.annotations)
+            .map_err(|e| Status::invalid_argument(format!("parse artifacts: {:#}", e)))?;
+        if artifacts.is_empty() {
+            return Ok(());
+        }
+        let (store, mut storage) = (self.image_store()?, self.storage().clone());
+        for (name, reference) in artifacts {
+            let found = ArtifactRecord::find(&mut storage, &reference)
+                .map_err(|e| Status::internal(format!("find artifact {}: {:#}", reference, e)))?;
+            let record = match found {
+                Some(record) => record,
+                None => {
+                    let registry = self.registry(&reference, None)?;
+                    store
+                        .pull_artifact(&mut storage, &registry, &reference)
+                        .await
+                        .map_err(|e| {
+                            Status::internal(format!("pull artifact {}: {:#}", reference, e))
+                        })?
+                }
+            };
+            let path = store
+                .artifact_path(record.digest())
+                .map_err(|e| Status::internal(format!("get artifact path: {:#}", e)))?;
+            config.mounts.push(Mount {
+                container_path: format!("{}/{}", ARTIFACTS_MOUNT_DIR, name),
+                host_path: path.display().to_string(),
+                readonly: true,
+                ..Default::default()
+            });
+        }
+        Ok(())
+    }
+
+    /// Retrieve the low-level tuning of the container `name` requested via the annotations of
+    /// the `sandbox`. Tuning annotations are either allowed for all runtime handlers or only for
+    /// the runtime handler of the sandbox via its `[runtimes.HANDLER]` table.
+    fn tuning(&self, sandbox: &SandboxData, name: &str) -> Result<Tuning, Status> {
+        let mut allowed = self.config().allowed_annotations().clone();
+        allowed.extend(
+            handler_annotations(
+                sandbox.runtime_handler(),
+                self.config().runtime_allowed_annotations(),
+            )
+            .into_iter()
+            .map(String::from),
+        );
+        Tuning::from_annotations(sandbox.annotations(), name, &allowed)
+            .map_err(|e| Status::invalid_argument(format!("parse tuning annotations: {:#}", e)))
+    }
+
+    /// Retrieve the host devices requested by the `sandbox` via annotations, whereas all of them
+    /// have to be part of the allowed devices.
+    fn devices(&self, sandbox: &SandboxData, tuning: &Tuning) -> Result<Vec<Device>, Status> {
+        let allowlist = self.config().allowed_devices();
+        let requested = || -> anyhow::Result<Vec<Device>> {
+            let mut devices = allowed_devices(sandbox.annotations(), allowlist)?;
+            if let Some(value) = tuning.devices() {
+                devices.extend(requested_devices(value, allowlist)?);
+            }
+            Ok(devices)
+        };
+        requested().map_err(|e| {
+            ErrorDetails::new("resolve devices")
+                .hint("request only devices which are allowed by the runtime")
+                .status(
+                    Code::InvalidArgument,
+                    format!("invalid requested devices: {:#}", e),
+                )
+        })
+    }
+}
+
+/// Retrieve the path of the log file of the container `config` inside the `sandbox`. Returns
+/// `None` if the kubelet did not request a log file.
+fn log_file(sandbox: &SandboxData, config: &ContainerConfig) -> Option<PathBuf> {
+    if sandbox.log_directory().is_empty() || config.log_path.is_empty() {
+        return None;
+    }
+    Some(Path::new(sandbox.log_directory()).join(&config.log_path))
+}
+
+/// CreateGuard deletes a container and removes its bundle if its request gets cancelled before the
+/// container has been stored, for example because the deadline of the request exceeded while the
+/// OCI runtime created it. Otherwise the container would leak in the runtime.
+struct CreateGuard {
+    /// The runtime creating the container, which is `None` if the guard got disarmed.
+    runtime: Option<OciRuntime>,
+
+    /// The identifier of the container.
+    id: String,
+
+    /// The bundle of the container.
+    bundle: PathBuf,
+}
+
+impl CreateGuard {
+    /// Guard the container `id` with the `bundle`, which gets created by the `runtime`.
+    fn new(runtime: OciRuntime, id: &str, bundle: &Path) -> Self {
+        Self {
+            runtime: Some(runtime),
+            id: id.into(),
+            bundle: bundle.into(),
+        }
+    }
+
+    /// Disarm the guard, because the container got either stored or removed already.
+    fn disarm(&mut self) {
+        self.runtime = None;
+    }
+}
+
+impl Drop for CreateGuard {
+    fn drop(&mut self) {
+        let runtime = match self.runtime.take() {
+            Some(runtime) => runtime,
+            None => return,
+        };
+        warn!("Rolling back container {} of cancelled request", self.id);
+        let (id, bundle) = (self.id.clone(), self.bundle.clone());
+        tokio::spawn(async move {
+            // The runtime does not know the container if the request got cancelled before
+            if let Err(e) = runtime.delete(&id, true).await {
+                debug!("Unable to delete cancelled container {}: {:#}", id, e);
+            }
+            if bundle.exists() {
+                if let Err(e) = fs::remove_dir_all(&bundle) {
+                    warn!("Unable to remove bundle {}: {}", bundle.display(), e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{
+        admission::tests::RejectAll,
+        annotations::{SHM_SIZE_ANNOTATION, UNIFIED_CGROUP_ANNOTATION},
+        container::{core_dump::CORE_DUMP_ANNOTATION, ContainerState},
+        container_log::{tee::OUTPUT_TEE_ANNOTATION, throttle::LOG_RATE_LIMIT_ANNOTATION},
+        cri_service::tests::{
+            new_cri_service, new_cri_service_with_admission, new_cri_service_with_config,
+            new_cri_service_with_runtime, test_config,
+        },
+        criapi::{
+            runtime_service_server::RuntimeService, ContainerMetadata, DnsConfig, ImageSpec,
+            KeyValue, LinuxContainerConfig, LinuxContainerResources,
+        },
+        device::DEVICES_ANNOTATION,
+        image::store::tests::FakeDistribution,
+        oci::runtime::tests::{fake_runtime, fake_runtime_log},
+        oci_spec::runtime::Spec,
+        resources::{burst::CPU_BURST_ANNOTATION, delegate::CGROUP_DELEGATE_ANNOTATION},
+        runtime_service::run_pod_sandbox::tests::{new_pod_sandbox, new_run_pod_sandbox_request},
+        sandbox::dns::RESOLV_CONF_PATH,
+    };
+    use anyhow::{Context, Result};
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use tonic::Code;
+
+    /// The image of the containers created via `new_create_container_request`.
+    pub const TEST_IMAGE: &str = "quay.io/tenant/app:v1";
+
+    /// Pull the `TEST_IMAGE` with the image `config` into the store of the service. Its single
+    /// layer contains the file `hello`.
+    pub async fn new_image(sut: &CRIService, config: serde_json::Value) -> Result<()> {
+        let mut source = FakeDistribution::default();
+        source.add_image_with_config("v1", "hello", config)?;
+        sut.image_store()?
+            .pull(&mut sut.storage().clone(), &source, &TEST_IMAGE.parse()?)
+            .await?;
+        Ok(())
+    }
+
+    /// Pull the `TEST_IMAGE` without any image config defaults into the store of the service.
+    pub async fn new_test_image(sut: &CRIService) -> Result<()> {
+        new_image(sut, serde_json::json!({})).await
+    }
+
+    /// Create a new request for creating the container `name` inside the sandbox `id`, which
+    /// uses the `TEST_IMAGE`.
+    pub fn new_create_container_request(id: &str, name: &str) -> CreateContainerRequest {
+        CreateContainerRequest {
+            pod_sandbox_id: id.into(),
+            config: Some(ContainerConfig {
+                metadata: Some(ContainerMetadata {
+                    name: name.into(),
+                    attempt: 0,
+                }),
+                image: Some(ImageSpec {
+                    image: TEST_IMAGE.into(),
+                    ..Default::default()
+                }),
+                command: vec!["/bin/sh".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn create_container_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+
+        let response = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?;
+        let id = &response.get_ref().container_id;
+        assert_eq!(id, &Container::new_id(&sandbox_id, "name", 0));
+
+        let container = sut
+            .storage()
+            .clone()
+            .get::<_, Container>(Container::key(id))?
+            .context("container not stored")?;
+        assert_eq!(container.pod_sandbox_id(), &sandbox_id);
+        assert_eq!(container.state(), ContainerState::Created);
+        assert!(container.bundle().join(SPEC_FILE).exists());
+        assert_eq!(
+            fs::read_to_string(container.bundle().join(ROOTFS_DIR).join("hello"))?,
+            "hello"
+        );
+        assert!(fake_runtime_log(dir.path())?[0].starts_with("create --bundle"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_fail_image_not_found() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+
+        let response = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        assert!(fake_runtime_log(dir.path())?.is_empty());
+
+        let mut request = new_create_container_request(&sandbox_id, "name");
+        if let Some(config) = request.config.as_mut() {
+            config.image = None;
+        }
+        let response = sut.create_container(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_success_image_config() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_image(
+            &sut,
+            serde_json::json!({
+                "User": "1000",
+                "Env": ["PATH=/bin", "MODE=image"],
+                "Entrypoint": ["/entrypoint"],
+                "Cmd": ["serve"],
+                "WorkingDir": "/app",
+            }),
+        )
+        .await?;
+
+        let mut request = new_create_container_request(&sandbox_id, "name");
+        if let Some(config) = request.config.as_mut() {
+            config.command = vec![];
+            config.envs.push(KeyValue {
+                key: "MODE".into(),
+                value: "container".into(),
+            });
+        }
+        let response = sut.create_container(Request::new(request)).await?;
+
+        let bundle = sut
+            .config()
+            .container_path()
+            .join(&response.get_ref().container_id);
+        let spec = Spec::from(&bundle.join(SPEC_FILE))?;
+        let process = spec.process().as_ref().context("no process")?;
+        assert_eq!(
+            process.args().as_deref(),
+            Some(&["/entrypoint".to_string(), "serve".to_string()][..])
+        );
+        assert_eq!(process.cwd(), "/app");
+        assert_eq!(process.user().uid(), 1000);
+        let env = process.env().as_ref().context("no env")?;
+        assert!(env.contains(&"PATH=/bin".to_string()));
+        assert!(env.contains(&"MODE=container".to_string()));
+        assert!(!env.contains(&"MODE=image".to_string()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn create_container_success_devices() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .oci_runtime(fake_runtime(dir.path(), "created")?)
+                .allowed_devices(vec!["/dev/null".into()])
+                .build()?,
+        )?;
+        new_test_image(&sut).await?;
+        let run_pod_request = |uid: &str, devices: &str| {
+            let mut request = new_run_pod_sandbox_request(uid, 0);
+            if let Some(config) = request.config.as_mut() {
+                config
+                    .annotations
+                    .insert(DEVICES_ANNOTATION.into(), devices.into());
+            }
+            Request::new(request)
+        };
+
+        let sandbox_id = sut
+            .run_pod_sandbox(run_pod_request("1", "/dev/null:/dev/foo:rw"))
+            .await?
+            .into_inner()
+            .pod_sandbox_id;
+        let response = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?;
+        let bundle = sut
+            .config()
+            .container_path()
+            .join(&response.get_ref().container_id);
+        let spec = Spec::from(&bundle.join(SPEC_FILE))?;
+        let linux = spec.linux().as_ref().context("no linux")?;
+        let devices = linux.devices().as_ref().context("no devices")?;
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].path(), Path::new("/dev/foo"));
+        let rules = linux
+            .resources()
+            .as_ref()
+            .and_then(|x| x.devices().as_ref())
+            .context("no device rules")?;
+        assert_eq!(rules[0].access().as_deref(), Some("rw"));
+
+        // Devices outside of the allowlist are rejected, regardless of how the path is spelled
+        for (uid, devices) in &[("2", "/dev/zero"), ("3", "/dev/../dev/zero")] {
+            let sandbox_id = sut
+                .run_pod_sandbox(run_pod_request(uid, devices))
+                .await?
+                .into_inner()
+                .pod_sandbox_id;
+            let response = sut
+                .create_container(Request::new(new_create_container_request(
+                    &sandbox_id,
+                    "name",
+                )))
+                .await;
+            assert_eq!(
+                response.err().map(|x| x.code()),
+                Some(Code::InvalidArgument)
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_success_core_dump() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .oci_runtime(fake_runtime(dir.path(), "created")?)
+                .core_dump_path(Some(dir.path().join("cores")))
+                .core_dump_size(1024u64)
+                .build()?,
+        )?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+
+        for (name, annotation, size) in
+            &[("limited", None, 1024), ("disabled", Some("disabled"), 0)]
+        {
+            let mut request = new_create_container_request(&sandbox_id, name);
+            if let (Some(config), Some(value)) = (request.config.as_mut(), annotation) {
+                config
+                    .annotations
+                    .insert(CORE_DUMP_ANNOTATION.into(), value.to_string());
+            }
+            let response = sut.create_container(Request::new(request)).await?;
+
+            let bundle = sut
+                .config()
+                .container_path()
+                .join(&response.get_ref().container_id);
+            let spec = Spec::from(&bundle.join(SPEC_FILE))?;
+            let rlimits = spec
+                .process()
+                .as_ref()
+                .and_then(|x| x.rlimits().as_ref())
+                .context("no rlimits")?;
+            assert_eq!(rlimits.len(), 1);
+            assert_eq!(rlimits[0].typ(), "RLIMIT_CORE");
+            assert_eq!(rlimits[0].hard(), *size);
+            assert_eq!(rlimits[0].soft(), *size);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_success_resolv_conf() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        new_test_image(&sut).await?;
+        let mut request = new_run_pod_sandbox_request("123", 0);
+        if let Some(config) = request.config.as_mut() {
+            config.dns_config = Some(DnsConfig {
+                servers: vec!["10.0.0.10".into()],
+                ..Default::default()
+            });
+        }
+        let sandbox_id = sut
+            .run_pod_sandbox(Request::new(request))
+            .await?
+            .into_inner()
+            .pod_sandbox_id;
+        let response = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?;
+
+        let bundle = sut
+            .config()
+            .container_path()
+            .join(&response.get_ref().container_id);
+        let spec = Spec::from(&bundle.join(SPEC_FILE))?;
+        let mount = spec
+            .mounts()
+            .as_ref()
+            .and_then(|x| {
+                x.iter()
+                    .find(|x| x.destination() == Path::new(RESOLV_CONF_PATH))
+            })
+            .context("no resolv.conf mount")?;
+        let source = mount.source().as_ref().context("no mount source")?;
+        assert_eq!(fs::read_to_string(source)?, "nameserver 10.0.0.10\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_success_extra_hosts() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .oci_runtime(fake_runtime(dir.path(), "created")?)
+                .extra_hosts(vec!["mirror.local=10.0.0.1".parse()?])
+                .build()?,
+        )?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+
+        let kubelet_hosts = dir.path().join("etc-hosts");
+        fs::write(&kubelet_hosts, "127.0.0.1\tlocalhost\n")?;
+        let mut request = new_create_container_request(&sandbox_id, "name");
+        if let Some(config) = request.config.as_mut() {
+            config.mounts.push(Mount {
+                container_path: HOSTS_PATH.into(),
+                host_path: kubelet_hosts.display().to_string(),
+                ..Default::default()
+            });
+        }
+        let response = sut.create_container(Request::new(request)).await?;
+
+        // The kubelet hosts file stays untouched, the merged one is mounted instead
+        let bundle = sut
+            .config()
+            .container_path()
+            .join(&response.get_ref().container_id);
+        let hosts = fs::read_to_string(bundle.join(HOSTS_FILE))?;
+        assert!(hosts.starts_with("127.0.0.1\tlocalhost\n"));
+        assert!(hosts.ends_with("10.0.0.1\tmirror.local\n"));
+        assert_eq!(
+            fs::read_to_string(&kubelet_hosts)?,
+            "127.0.0.1\tlocalhost\n"
+        );
+        let spec = fs::read_to_string(bundle.join(SPEC_FILE))?;
+        assert!(spec.contains(&bundle.join(HOSTS_FILE).display().to_string()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_success_runtime_annotations() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .oci_runtime(fake_runtime(dir.path(), "created")?)
+                .runtime_allowed_annotations(vec!["default=io.katacontainers".parse()?])
+                .build()?,
+        )?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+
+        let mut request = new_create_container_request(&sandbox_id, "name");
+        if let Some(config) = request.config.as_mut() {
+            config.annotations.insert(
+                "io.katacontainers.config.hypervisor.kernel_params".into(),
+                "quiet".into(),
+            );
+            config
+                .annotations
+                .insert("dev.gvisor.spec.mount.shared.share".into(), "pod".into());
+        }
+        let response = sut.create_container(Request::new(request)).await?;
+
+        let bundle = sut
+            .config()
+            .container_path()
+            .join(&response.get_ref().container_id);
+        let spec = fs::read_to_string(bundle.join(SPEC_FILE))?;
+        assert!(spec.contains("io.katacontainers.config.hypervisor.kernel_params"));
+        assert!(!spec.contains("dev.gvisor.spec.mount.shared.share"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_success_tuning() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .oci_runtime(fake_runtime(dir.path(), "created")?)
+                .runtime_allowed_annotations(vec![
+                    format!("kata={}", SHM_SIZE_ANNOTATION).parse()?,
+                    format!("kata={}", UNIFIED_CGROUP_ANNOTATION).parse()?,
+                ])
+                .build()?,
+        )?;
+        new_test_image(&sut).await?;
+
+        // The tuning annotations are only allowed for the kata runtime handler
+        for (uid, handler, shm_size, memory_high) in &[
+            ("1", "kata", "size=1048576", Some("1024")),
+            ("2", "", "size=65536k", None),
+        ] {
+            let mut request = new_run_pod_sandbox_request(uid, 0);
+            request.runtime_handler = handler.to_string();
+            if let Some(config) = request.config.as_mut() {
+                config
+                    .annotations
+                    .insert(SHM_SIZE_ANNOTATION.into(), "1Mi".into());
+                config.annotations.insert(
+                    format!("{}.name", UNIFIED_CGROUP_ANNOTATION),
+                    "memory.high=1024".into(),
+                );
+            }
+            let sandbox_id = sut
+                .run_pod_sandbox(Request::new(request))
+                .await?
+                .into_inner()
+                .pod_sandbox_id;
+            let response = sut
+                .create_container(Request::new(new_create_container_request(
+                    &sandbox_id,
+                    "name",
+                )))
+                .await?;
+
+            let bundle = sut
+                .config()
+                .container_path()
+                .join(&response.get_ref().container_id);
+            let spec = Spec::from(&bundle.join(SPEC_FILE))?;
+            let shm = spec
+                .mounts()
+                .as_ref()
+                .and_then(|x| x.iter().find(|x| x.destination() == Path::new("/dev/shm")))
+                .context("no shm mount")?;
+            assert!(shm
+                .options()
+                .as_ref()
+                .map_or(false, |x| x.contains(&shm_size.to_string())));
+            let unified = spec
+                .linux()
+                .as_ref()
+                .and_then(|x| x.resources().as_ref())
+                .and_then(|x| x.unified().as_ref());
+            assert_eq!(
+                unified
+                    .and_then(|x| x.get("memory.high"))
+                    .map(String::as_str),
+                *memory_high
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_fail_cpu_burst() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+
+        // Bursts require a quota which they fit into
+        for (quota, burst) in &[(0, "10000"), (10_000, "50000"), (10_000, "invalid")] {
+            let mut request = new_create_container_request(&sandbox_id, "name");
+            if let Some(config) = request.config.as_mut() {
+                config
+                    .annotations
+                    .insert(CPU_BURST_ANNOTATION.into(), burst.to_string());
+                config.linux = Some(LinuxContainerConfig {
+                    resources: Some(LinuxContainerResources {
+                        cpu_quota: *quota,
+                        cpu_period: 100_000,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                });
+            }
+            let response = sut.create_container(Request::new(request)).await;
+            assert_eq!(
+                response.err().map(|x| x.code()),
+                Some(Code::InvalidArgument)
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_fail_runtime() -> Result<()> {
+        let sut = new_cri_service_with_config(test_config()?.oci_runtime("/bin/false").build()?)?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+
+        let response = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::Internal));
+        let bundle = sut
+            .config()
+            .container_path()
+            .join(Container::new_id(&sandbox_id, "name", 0));
+        assert!(!bundle.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_fail_no_config() -> Result<()> {
+        let sut = new_cri_service()?;
+        let response = sut
+            .create_container(Request::new(CreateContainerRequest::default()))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_fail_no_sandbox() -> Result<()> {
+        let sut = new_cri_service()?;
+        let response = sut
+            .create_container(Request::new(new_create_container_request(
+                "unknown", "name",
+            )))
+            .await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_success_idempotent() -> Result<()> {
+        let sut = new_cri_service()?;
+        let key = IdempotencyRecord::container_key("sandbox", "name", 1);
+        sut.storage()
+            .clone()
+            .insert(&key, IdempotencyRecord::new("existing".into()))?;
+
+        let request = CreateContainerRequest {
+            pod_sandbox_id: "sandbox".into(),
+            config: Some(ContainerConfig {
+                metadata: Some(ContainerMetadata {
+                    name: "name".into(),
+                    attempt: 1,
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let response = sut.create_container(Request::new(request)).await?;
+        assert_eq!(response.get_ref().container_id, "existing");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_fail_rejected() -> Result<()> {
+        let sut = new_cri_service_with_admission(Arc::new(RejectAll))?;
+        let response = sut
+            .create_container(Request::new(CreateContainerRequest::default()))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn create_container_fail_resource_exhausted() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = CreateContainerRequest {
+            config: Some(ContainerConfig {
+                linux: Some(LinuxContainerConfig {
+                    resources: Some(LinuxContainerResources {
+                        memory_limit_in_bytes: i64::MAX,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let response = sut.create_container(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::ResourceExhausted)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_fail_invalid_cgroup_delegation() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .oci_runtime(fake_runtime(dir.path(), "created")?)
+                .allowed_annotations(vec![CGROUP_DELEGATE_ANNOTATION.into()])
+                .build()?,
+        )?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+
+        let mut request = new_create_container_request(&sandbox_id, "name");
+        if let Some(config) = request.config.as_mut() {
+            config
+                .annotations
+                .insert(CGROUP_DELEGATE_ANNOTATION.into(), "devices".into());
+        }
+        let response = sut.create_container(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        assert!(fake_runtime_log(dir.path())?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_success_log() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let log_directory = dir.path().join("pods");
+        let mut request = new_run_pod_sandbox_request("123", 0);
+        if let Some(config) = request.config.as_mut() {
+            config.log_directory = log_directory.display().to_string();
+        }
+        let sandbox_id = sut
+            .run_pod_sandbox(Request::new(request))
+            .await?
+            .into_inner()
+            .pod_sandbox_id;
+        new_test_image(&sut).await?;
+
+        let mut request = new_create_container_request(&sandbox_id, "name");
+        if let Some(config) = request.config.as_mut() {
+            config.log_path = "name/0.log".into();
+        }
+        sut.create_container(Request::new(request)).await?;
+        assert!(log_directory.join("name").join("0.log").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_fail_invalid_log_rate_limit() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+
+        let mut request = new_create_container_request(&sandbox_id, "name");
+        if let Some(config) = request.config.as_mut() {
+            config
+                .annotations
+                .insert(LOG_RATE_LIMIT_ANNOTATION.into(), "fast".into());
+        }
+        let response = sut.create_container(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        assert!(fake_runtime_log(dir.path())?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_fail_output_tee_disabled() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+
+        // Containers cannot tee their output without a configured tee directory
+        let mut request = new_create_container_request(&sandbox_id, "name");
+        if let Some(config) = request.config.as_mut() {
+            config
+                .annotations
+                .insert(OUTPUT_TEE_ANNOTATION.into(), "agent.fifo".into());
+        }
+        let response = sut.create_container(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        assert!(fake_runtime_log(dir.path())?.is_empty());
+        Ok(())
+    }
 }
@@ -1,15 +1,195 @@
 use crate::{
+    container::{Container, ContainerState},
     cri_service::CRIService,
     criapi::{ExecRequest, ExecResponse},
+    sandbox::{infra::InfraSandbox, Sandbox},
+    storage::KeyValueStorage,
+    streaming::{
+        recording::{Recording, SessionInfo},
+        session::Session,
+    },
 };
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_exec(
         &self,
-        _request: Request<ExecRequest>,
+        request: Request<ExecRequest>,
     ) -> Result<Response<ExecResponse>, Status> {
-        let resp = ExecResponse { url: "url".into() };
+        let request = request.into_inner();
+        if request.cmd.is_empty() {
+            return Err(Status::invalid_argument("no command provided"));
+        }
+        if !(request.stdin || request.stdout || request.stderr) {
+            return Err(Status::invalid_argument(
+                "one of stdin, stdout and stderr has to be streamed",
+            ));
+        }
+        if request.tty && request.stderr {
+            return Err(Status::invalid_argument(
+                "stderr cannot be streamed separately with a TTY",
+            ));
+        }
+
+        // Commands can only be executed in running containers
+        let container = self
+            .storage()
+            .clone()
+            .get::<_, Container>(Container::key(&request.container_id))
+            .map_err(|e| Status::internal(format!("get container: {}", e)))?
+            .ok_or_else(|| {
+                Status::not_found(format!("container {} not found", request.container_id))
+            })?;
+        if container.state() != ContainerState::Running {
+            return Err(Status::failed_precondition(format!(
+                "container {} is in state {:?}",
+                container,
+                container.state()
+            )));
+        }
+
+        let recording = self.session_recording(&container, "exec", &request.cmd)?;
+        let resp = ExecResponse {
+            url: self.streaming().insert(Session::Exec(request, recording)),
+        };
         Ok(Response::new(resp))
     }
+
+    /// Decide whether the session of the `kind` running the `command` in the `container` gets
+    /// recorded, which depends on the namespace of its pod.
+    pub fn session_recording(
+        &self,
+        container: &Container,
+        kind: &str,
+        command: &[String],
+    ) -> Result<Option<Recording>, Status> {
+        if self.config().session_recording_path().is_none() {
+            return Ok(None);
+        }
+        let sandbox = self
+            .storage()
+            .clone()
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(
+                container.pod_sandbox_id(),
+            ))
+            .map_err(|e| Status::internal(format!("get pod sandbox: {}", e)))?
+            .ok_or_else(|| {
+                Status::not_found(format!(
+                    "pod sandbox {} not found",
+                    container.pod_sandbox_id()
+                ))
+            })?;
+        let data = sandbox.data();
+        let info = SessionInfo::new(
+            kind,
+            container.id(),
+            data.id(),
+            data.namespace(),
+            data.name(),
+            command,
+        );
+        let now = self
+            .clock()
+            .unix_nanos()
+            .map_err(|e| Status::internal(format!("get time: {:#}", e)))?;
+        Ok(Recording::new(self.config(), info, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::{new_cri_service, new_cri_service_with_runtime},
+        criapi::{runtime_service_server::RuntimeService, StartContainerRequest},
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use tonic::Code;
+
+    fn new_exec_request(id: &str) -> ExecRequest {
+        ExecRequest {
+            container_id: id.into(),
+            cmd: vec!["sh".into()],
+            tty: true,
+            stdin: true,
+            stdout: true,
+            stderr: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn exec_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "running")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+
+        // Created containers cannot execute commands yet
+        let response = sut.exec(Request::new(new_exec_request(&id))).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+
+        let request = StartContainerRequest {
+            container_id: id.clone(),
+        };
+        sut.start_container(Request::new(request)).await?;
+        let url = sut
+            .exec(Request::new(new_exec_request(&id)))
+            .await?
+            .into_inner()
+            .url;
+        assert!(url.contains("/exec/"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exec_fail_invalid() -> Result<()> {
+        let sut = new_cri_service()?;
+        let requests = vec![
+            ExecRequest {
+                cmd: vec![],
+                ..new_exec_request("id")
+            },
+            ExecRequest {
+                stdin: false,
+                stdout: false,
+                ..new_exec_request("id")
+            },
+            ExecRequest {
+                stderr: true,
+                ..new_exec_request("id")
+            },
+        ];
+        for request in requests {
+            let response = sut.exec(Request::new(request)).await;
+            assert_eq!(
+                response.err().map(|x| x.code()),
+                Some(Code::InvalidArgument)
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exec_fail_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let response = sut.exec(Request::new(new_exec_request("unknown"))).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
 }
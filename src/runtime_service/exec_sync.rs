@@ -1,14 +1,16 @@
 use crate::{
     cri_service::CRIService,
     criapi::{ExecSyncRequest, ExecSyncResponse},
+    storage::KeyValueStorage,
 };
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_exec_sync(
         &self,
-        _request: Request<ExecSyncRequest>,
+        request: Request<ExecSyncRequest>,
     ) -> Result<Response<ExecSyncResponse>, Status> {
+        self.resolve_container(&request.get_ref().container_id)?;
         let resp = ExecSyncResponse {
             exit_code: -1,
             stderr: Vec::new(),
@@ -1,15 +1,71 @@
 use crate::{
+    container::ContainerState,
     cri_service::CRIService,
     criapi::{ListContainerStatsRequest, ListContainerStatsResponse},
+    resources::DefaultResourceManager,
+    storage::KeyValueStorage,
 };
+use log::debug;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_list_container_stats(
         &self,
-        _request: Request<ListContainerStatsRequest>,
+        request: Request<ListContainerStatsRequest>,
     ) -> Result<Response<ListContainerStatsResponse>, Status> {
-        let resp = ListContainerStatsResponse { stats: vec![] };
+        let filter = request.into_inner().filter.unwrap_or_default();
+        let containers =
+            self.select_containers(&filter.id, &filter.pod_sandbox_id, &filter.label_selector)?;
+
+        // Only running containers are accounted, whereas containers which exit in the meantime
+        // are skipped
+        let manager = DefaultResourceManager::default();
+        let mut stats = vec![];
+        for container in containers
+            .iter()
+            .filter(|x| x.state() == ContainerState::Running)
+        {
+            match self.stats().get(&manager, container) {
+                Ok(sample) => stats.push(sample),
+                Err(e) => debug!("Skipping stats of container {}: {:#}", container, e),
+            }
+        }
+
+        let resp = ListContainerStatsResponse { stats };
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service_with_runtime,
+        criapi::runtime_service_server::RuntimeService,
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn list_container_stats_success_not_running() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        sut.create_container(Request::new(new_create_container_request(
+            &sandbox_id,
+            "name",
+        )))
+        .await?;
+
+        let response = sut
+            .list_container_stats(Request::new(ListContainerStatsRequest::default()))
+            .await?;
+        assert!(response.get_ref().stats.is_empty());
+        Ok(())
+    }
+}
@@ -1,15 +1,208 @@
 use crate::{
+    container::{self, Container, POD_SANDBOX_FIELD},
     cri_service::CRIService,
-    criapi::{ListContainersRequest, ListContainersResponse},
+    criapi::{self, ImageSpec, ListContainersRequest, ListContainersResponse},
+    storage::KeyValueStorage,
 };
+use std::collections::HashMap;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_list_containers(
         &self,
-        _request: Request<ListContainersRequest>,
+        request: Request<ListContainersRequest>,
     ) -> Result<Response<ListContainersResponse>, Status> {
-        let resp = ListContainersResponse { containers: vec![] };
+        let filter = request.into_inner().filter.unwrap_or_default();
+        let containers =
+            self.select_containers(&filter.id, &filter.pod_sandbox_id, &filter.label_selector)?;
+
+        let containers = containers
+            .iter()
+            .map(|x| criapi::Container {
+                id: x.id().clone(),
+                pod_sandbox_id: x.pod_sandbox_id().clone(),
+                metadata: Some(x.metadata()),
+                image: Some(ImageSpec {
+                    image: x.image().clone(),
+                    annotations: HashMap::new(),
+                }),
+                image_ref: x.image().clone(),
+                state: criapi::ContainerState::from(x.state()) as i32,
+                created_at: x.created_at(),
+                labels: x.labels().clone(),
+                annotations: x.annotations().clone(),
+            })
+            .filter(|x| filter.state.as_ref().map_or(true, |s| s.state == x.state))
+            .collect();
+        let resp = ListContainersResponse { containers };
         Ok(Response::new(resp))
     }
+
+    /// Retrieve the containers whose ID starts with `id` and which have all of the `labels`,
+    /// whereas only the ones of the pod sandbox `pod_sandbox_id` are selected unless it is empty.
+    /// The containers are looked up via the secondary index if possible.
+    pub fn select_containers(
+        &self,
+        id: &str,
+        pod_sandbox_id: &str,
+        labels: &HashMap<String, String>,
+    ) -> Result<Vec<Container>, Status> {
+        let mut storage = self.storage().clone();
+        let index_error =
+            |e: anyhow::Error| Status::internal(format!("select containers: {:#}", e));
+        let mut ids = container::INDEX
+            .select(&mut storage, labels)
+            .map_err(index_error)?;
+        if !pod_sandbox_id.is_empty() {
+            let pod = container::INDEX
+                .lookup(&mut storage, POD_SANDBOX_FIELD, pod_sandbox_id)
+                .map_err(index_error)?;
+            ids = Some(match ids {
+                Some(ids) => ids.intersection(&pod).cloned().collect(),
+                None => pod,
+            });
+        }
+
+        let containers = match ids {
+            Some(ids) => {
+                let mut containers = vec![];
+                for container_id in ids.iter().filter(|x| x.starts_with(id)) {
+                    // Stale entries of interrupted removals are skipped
+                    if let Some(container) = storage
+                        .get::<_, Container>(Container::key(container_id))
+                        .map_err(|e| Status::internal(format!("get container: {}", e)))?
+                    {
+                        containers.push(container);
+                    }
+                }
+                containers
+            }
+            None => storage
+                .scan_prefix::<_, Container>(Container::key_prefix())
+                .map_err(|e| Status::internal(format!("list containers: {}", e)))?,
+        };
+        Ok(containers
+            .into_iter()
+            .filter(|x| {
+                x.id().starts_with(id)
+                    && (pod_sandbox_id.is_empty() || x.pod_sandbox_id() == pod_sandbox_id)
+                    && labels.iter().all(|(k, v)| x.labels().get(k) == Some(v))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service_with_runtime,
+        criapi::{
+            runtime_service_server::RuntimeService, ContainerFilter, ContainerStateValue,
+            RemoveContainerRequest,
+        },
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::{Context, Result};
+    use tempfile::tempdir;
+
+    async fn create(sut: &CRIService, sandbox_id: &str, name: &str) -> Result<String> {
+        let mut request = new_create_container_request(sandbox_id, name);
+        let config = request.config.as_mut().context("no config")?;
+        config.labels.insert("app".into(), name.into());
+        config.labels.insert("tier".into(), "backend".into());
+        Ok(sut
+            .create_container(Request::new(request))
+            .await?
+            .into_inner()
+            .container_id)
+    }
+
+    async fn list(sut: &CRIService, filter: ContainerFilter) -> Result<Vec<String>> {
+        let request = ListContainersRequest {
+            filter: Some(filter),
+        };
+        let response = sut.list_containers(Request::new(request)).await?;
+        let mut ids: Vec<String> = response
+            .into_inner()
+            .containers
+            .into_iter()
+            .map(|x| x.id)
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    #[tokio::test]
+    async fn list_containers_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let first = create(&sut, &sandbox_id, "first").await?;
+        let second = create(&sut, &sandbox_id, "second").await?;
+        let mut all = vec![first.clone(), second.clone()];
+        all.sort();
+        assert_eq!(list(&sut, ContainerFilter::default()).await?, all);
+
+        let by_labels = |labels: &[(&str, &str)]| ContainerFilter {
+            label_selector: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(list(&sut, by_labels(&[("tier", "backend")])).await?, all);
+        assert_eq!(
+            list(&sut, by_labels(&[("tier", "backend"), ("app", "first")])).await?,
+            vec![first.clone()]
+        );
+        assert!(list(&sut, by_labels(&[("app", "third")])).await?.is_empty());
+
+        let by_sandbox = |id: &str| ContainerFilter {
+            pod_sandbox_id: id.into(),
+            ..by_labels(&[("app", "second")])
+        };
+        assert_eq!(
+            list(&sut, by_sandbox(&sandbox_id)).await?,
+            vec![second.clone()]
+        );
+        assert!(list(&sut, by_sandbox("other")).await?.is_empty());
+
+        let by_state = |state: criapi::ContainerState| ContainerFilter {
+            state: Some(ContainerStateValue {
+                state: state as i32,
+            }),
+            id: first[..8].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            list(&sut, by_state(criapi::ContainerState::ContainerCreated)).await?,
+            vec![first.clone()]
+        );
+        assert!(
+            list(&sut, by_state(criapi::ContainerState::ContainerRunning))
+                .await?
+                .is_empty()
+        );
+
+        // Removed containers are dropped from the index
+        let request = RemoveContainerRequest {
+            container_id: first,
+        };
+        sut.remove_container(Request::new(request)).await?;
+        assert!(list(&sut, by_labels(&[("app", "first")])).await?.is_empty());
+
+        // Records stored before the index existed get indexed once
+        assert_eq!(sut.rebuild_indexes()?, 2);
+        assert_eq!(sut.rebuild_indexes()?, 0);
+        assert_eq!(
+            list(&sut, by_labels(&[("app", "second")])).await?,
+            vec![second]
+        );
+        Ok(())
+    }
 }
@@ -1,15 +1,163 @@
 use crate::{
     cri_service::CRIService,
-    criapi::{ListPodSandboxRequest, ListPodSandboxResponse},
+    criapi::{ListPodSandboxRequest, ListPodSandboxResponse, PodSandbox, PodSandboxState},
+    sandbox::{self, infra::InfraSandbox, Sandbox},
+    storage::KeyValueStorage,
 };
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_list_pod_sandbox(
         &self,
-        _request: Request<ListPodSandboxRequest>,
+        request: Request<ListPodSandboxRequest>,
     ) -> Result<Response<ListPodSandboxResponse>, Status> {
-        let reply = ListPodSandboxResponse { items: vec![] };
+        let filter = request.into_inner().filter.unwrap_or_default();
+
+        // Selecting by labels only reads the matching sandboxes
+        let mut storage = self.storage().clone();
+        let ids = sandbox::INDEX
+            .select(&mut storage, &filter.label_selector)
+            .map_err(|e| Status::internal(format!("select pod sandboxes: {:#}", e)))?;
+        let mut sandboxes = match ids {
+            Some(ids) => {
+                let mut sandboxes = vec![];
+                for id in ids.iter().filter(|x| x.starts_with(&filter.id)) {
+                    if let Some(sandbox) = storage
+                        .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(id))
+                        .map_err(|e| Status::internal(format!("get pod sandbox: {}", e)))?
+                    {
+                        sandboxes.push(sandbox);
+                    }
+                }
+                sandboxes
+            }
+            None => storage
+                .scan_prefix::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key_prefix())
+                .map_err(|e| Status::internal(format!("list pod sandboxes: {}", e)))?,
+        };
+
+        let mut items = vec![];
+        for sandbox in sandboxes.iter_mut() {
+            let data = sandbox.data();
+            if !data.id().starts_with(&filter.id)
+                || !filter
+                    .label_selector
+                    .iter()
+                    .all(|(k, v)| data.labels().get(k) == Some(v))
+            {
+                continue;
+            }
+
+            let state = if sandbox
+                .ready()
+                .map_err(|e| Status::internal(format!("check pod sandbox readiness: {:#}", e)))?
+            {
+                PodSandboxState::SandboxReady
+            } else {
+                PodSandboxState::SandboxNotready
+            } as i32;
+            if filter.state.as_ref().map_or(false, |x| x.state != state) {
+                continue;
+            }
+
+            let data = sandbox.data();
+            items.push(PodSandbox {
+                id: data.id().clone(),
+                metadata: Some(data.metadata()),
+                state,
+                created_at: *data.created_at(),
+                labels: data.labels().clone(),
+                annotations: data.annotations().clone(),
+                runtime_handler: data.runtime_handler().clone(),
+            });
+        }
+
+        let reply = ListPodSandboxResponse { items };
         Ok(Response::new(reply))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service,
+        criapi::{
+            runtime_service_server::RuntimeService, PodSandboxFilter, PodSandboxStateValue,
+            StopPodSandboxRequest,
+        },
+        runtime_service::run_pod_sandbox::tests::new_run_pod_sandbox_request,
+    };
+    use anyhow::{Context, Result};
+
+    async fn run(sut: &CRIService, uid: &str, labels: &[(&str, &str)]) -> Result<String> {
+        let mut request = new_run_pod_sandbox_request(uid, 0);
+        let config = request.config.as_mut().context("no config")?;
+        for (k, v) in labels {
+            config.labels.insert(k.to_string(), v.to_string());
+        }
+        Ok(sut
+            .run_pod_sandbox(Request::new(request))
+            .await?
+            .into_inner()
+            .pod_sandbox_id)
+    }
+
+    async fn list(sut: &CRIService, filter: Option<PodSandboxFilter>) -> Result<Vec<String>> {
+        let request = ListPodSandboxRequest { filter };
+        let response = sut.list_pod_sandbox(Request::new(request)).await?;
+        let mut ids: Vec<String> = response
+            .into_inner()
+            .items
+            .into_iter()
+            .map(|x| x.id)
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    #[tokio::test]
+    async fn list_pod_sandbox_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        assert!(list(&sut, None).await?.is_empty());
+
+        let first = run(&sut, "1", &[("app", "first")]).await?;
+        let second = run(&sut, "2", &[("app", "second")]).await?;
+        let mut all = vec![first.clone(), second.clone()];
+        all.sort();
+        assert_eq!(list(&sut, None).await?, all);
+
+        let request = StopPodSandboxRequest {
+            pod_sandbox_id: second.clone(),
+        };
+        sut.stop_pod_sandbox(Request::new(request)).await?;
+
+        let by_state = |state: PodSandboxState| PodSandboxFilter {
+            state: Some(PodSandboxStateValue {
+                state: state as i32,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            list(&sut, Some(by_state(PodSandboxState::SandboxReady))).await?,
+            vec![first.clone()]
+        );
+        assert_eq!(
+            list(&sut, Some(by_state(PodSandboxState::SandboxNotready))).await?,
+            vec![second.clone()]
+        );
+
+        let by_id = PodSandboxFilter {
+            id: first[..6].into(),
+            ..Default::default()
+        };
+        assert_eq!(list(&sut, Some(by_id)).await?, vec![first.clone()]);
+
+        let mut by_label = PodSandboxFilter::default();
+        by_label
+            .label_selector
+            .insert("app".into(), "second".into());
+        assert_eq!(list(&sut, Some(by_label)).await?, vec![second]);
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{
+    cri_service::CRIService,
+    criapi::{ListPodSandboxStatsRequest, ListPodSandboxStatsResponse},
+    sandbox::{infra::InfraSandbox, Sandbox},
+    storage::KeyValueStorage,
+};
+use tonic::{Request, Response, Status};
+
+impl<S: KeyValueStorage> CRIService<S> {
+    pub async fn handle_list_pod_sandbox_stats(
+        &self,
+        request: Request<ListPodSandboxStatsRequest>,
+    ) -> Result<Response<ListPodSandboxStatsResponse>, Status> {
+        let filter = request.into_inner().filter.unwrap_or_default();
+        let sandboxes = self
+            .storage()
+            .clone()
+            .scan_prefix::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key_prefix())
+            .map_err(|e| Status::internal(format!("list pod sandboxes: {}", e)))?;
+
+        let mut stats = vec![];
+        for sandbox in sandboxes.iter().filter(|x| {
+            x.id().starts_with(&filter.id)
+                && filter
+                    .label_selector
+                    .iter()
+                    .all(|(k, v)| x.data().labels().get(k) == Some(v))
+        }) {
+            stats.push(self.pod_sandbox_stats(sandbox)?);
+        }
+
+        let resp = ListPodSandboxStatsResponse { stats };
+        Ok(Response::new(resp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service,
+        criapi::{runtime_service_server::RuntimeService, PodSandboxStatsFilter},
+        runtime_service::run_pod_sandbox::tests::new_pod_sandbox,
+    };
+    use anyhow::Result;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn list_pod_sandbox_stats_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let id = new_pod_sandbox(&sut).await?;
+
+        let list = |filter: PodSandboxStatsFilter| {
+            sut.list_pod_sandbox_stats(Request::new(ListPodSandboxStatsRequest {
+                filter: Some(filter),
+            }))
+        };
+        let stats = list(PodSandboxStatsFilter::default())
+            .await?
+            .into_inner()
+            .stats;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(
+            stats[0].attributes.as_ref().map(|x| x.id.as_str()),
+            Some(id.as_str())
+        );
+
+        let mut label_selector = HashMap::new();
+        label_selector.insert("unknown".to_string(), "label".to_string());
+        let stats = list(PodSandboxStatsFilter {
+            id: "".into(),
+            label_selector,
+        })
+        .await?
+        .into_inner()
+        .stats;
+        assert!(stats.is_empty());
+        Ok(())
+    }
+}
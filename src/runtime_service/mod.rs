@@ -1,10 +1,34 @@
 use crate::{
+    container::{
+        self,
+        id_index::{IdIndex, ResolveError},
+        Container,
+    },
     cri_service::CRIService,
     criapi::{self, runtime_service_server::RuntimeService},
+    error_details::ErrorDetails,
+    latency::Timeline,
+    logging::{self, Fields},
+    network::{
+        self,
+        cni::CniNetwork,
+        leaks::{Leak, LeakDetector},
+        netns, NetworkStatus,
+    },
+    quota::{QuotaExceeded, QuotaKind, QuotaReservation, Quotas, Reservation},
+    sandbox::{self, infra::InfraSandbox, Sandbox, SandboxData, SandboxDataBuilder},
+    storage::KeyValueStorage,
 };
-use tonic::{Request, Response, Status};
+use anyhow::{format_err, Context, Result};
+use log::{error, info, warn};
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+};
+use tonic::{Code, Request, Response, Status};
 
 mod attach;
+mod checkpoint;
 mod container_stats;
 mod container_status;
 mod create_container;
@@ -13,6 +37,8 @@ mod exec_sync;
 mod list_container_stats;
 mod list_containers;
 mod list_pod_sandbox;
+mod list_pod_sandbox_stats;
+mod pod_sandbox_stats;
 mod pod_sandbox_status;
 mod port_forward;
 mod remove_container;
@@ -28,157 +54,562 @@ mod update_runtime_config;
 mod version;
 
 #[tonic::async_trait]
-impl RuntimeService for CRIService {
+impl<S: KeyValueStorage> RuntimeService for CRIService<S> {
     async fn version(
         &self,
         request: Request<criapi::VersionRequest>,
     ) -> Result<Response<criapi::VersionResponse>, Status> {
-        self.handle_version(request).await
+        self.bounded("Version", request, |r| self.handle_version(r))
+            .await
     }
 
     async fn create_container(
         &self,
         request: Request<criapi::CreateContainerRequest>,
     ) -> Result<Response<criapi::CreateContainerResponse>, Status> {
-        self.handle_create_container(request).await
+        let pod = request.get_ref().pod_sandbox_id.clone();
+        self.bounded("CreateContainer", request, |r| {
+            self.queued(&pod, None, self.handle_create_container(r))
+        })
+        .await
     }
 
     async fn start_container(
         &self,
         request: Request<criapi::StartContainerRequest>,
     ) -> Result<Response<criapi::StartContainerResponse>, Status> {
-        self.handle_start_container(request).await
+        let id = request.get_ref().container_id.clone();
+        let pod = self.container_pod(&id);
+        self.bounded("StartContainer", request, |r| {
+            self.queued(&pod, Some(&id), self.handle_start_container(r))
+        })
+        .await
     }
 
     async fn stop_container(
         &self,
         request: Request<criapi::StopContainerRequest>,
     ) -> Result<Response<criapi::StopContainerResponse>, Status> {
-        self.handle_stop_container(request).await
+        let id = request.get_ref().container_id.clone();
+        let pod = self.container_pod(&id);
+        self.bounded("StopContainer", request, |r| {
+            self.queued(&pod, Some(&id), self.handle_stop_container(r))
+        })
+        .await
     }
 
     async fn remove_container(
         &self,
         request: Request<criapi::RemoveContainerRequest>,
     ) -> Result<Response<criapi::RemoveContainerResponse>, Status> {
-        self.handle_remove_container(request).await
+        let id = request.get_ref().container_id.clone();
+        let pod = self.container_pod(&id);
+        self.bounded("RemoveContainer", request, |r| {
+            self.queued(&pod, Some(&id), self.handle_remove_container(r))
+        })
+        .await
     }
 
     async fn list_containers(
         &self,
         request: Request<criapi::ListContainersRequest>,
     ) -> Result<Response<criapi::ListContainersResponse>, Status> {
-        self.handle_list_containers(request).await
+        self.bounded("ListContainers", request, |r| {
+            self.handle_list_containers(r)
+        })
+        .await
     }
 
     async fn container_status(
         &self,
         request: Request<criapi::ContainerStatusRequest>,
     ) -> Result<Response<criapi::ContainerStatusResponse>, Status> {
-        self.handle_container_status(request).await
+        self.bounded("ContainerStatus", request, |r| {
+            self.handle_container_status(r)
+        })
+        .await
     }
 
     async fn container_stats(
         &self,
         request: Request<criapi::ContainerStatsRequest>,
     ) -> Result<Response<criapi::ContainerStatsResponse>, Status> {
-        self.handle_container_stats(request).await
+        self.bounded("ContainerStats", request, |r| {
+            self.handle_container_stats(r)
+        })
+        .await
     }
 
     async fn list_container_stats(
         &self,
         request: Request<criapi::ListContainerStatsRequest>,
     ) -> Result<Response<criapi::ListContainerStatsResponse>, Status> {
-        self.handle_list_container_stats(request).await
+        self.bounded("ListContainerStats", request, |r| {
+            self.handle_list_container_stats(r)
+        })
+        .await
+    }
+
+    async fn pod_sandbox_stats(
+        &self,
+        request: Request<criapi::PodSandboxStatsRequest>,
+    ) -> Result<Response<criapi::PodSandboxStatsResponse>, Status> {
+        self.bounded("PodSandboxStats", request, |r| {
+            self.handle_pod_sandbox_stats(r)
+        })
+        .await
+    }
+
+    async fn list_pod_sandbox_stats(
+        &self,
+        request: Request<criapi::ListPodSandboxStatsRequest>,
+    ) -> Result<Response<criapi::ListPodSandboxStatsResponse>, Status> {
+        self.bounded("ListPodSandboxStats", request, |r| {
+            self.handle_list_pod_sandbox_stats(r)
+        })
+        .await
     }
 
     async fn update_container_resources(
         &self,
         request: Request<criapi::UpdateContainerResourcesRequest>,
     ) -> Result<Response<criapi::UpdateContainerResourcesResponse>, Status> {
-        self.handle_update_container_resources(request).await
+        let id = request.get_ref().container_id.clone();
+        let pod = self.container_pod(&id);
+        self.bounded("UpdateContainerResources", request, |r| {
+            self.queued(&pod, Some(&id), self.handle_update_container_resources(r))
+        })
+        .await
     }
 
     async fn reopen_container_log(
         &self,
         request: Request<criapi::ReopenContainerLogRequest>,
     ) -> Result<Response<criapi::ReopenContainerLogResponse>, Status> {
-        self.handle_reopen_container_log(request).await
+        self.bounded("ReopenContainerLog", request, |r| {
+            self.handle_reopen_container_log(r)
+        })
+        .await
     }
 
     async fn exec_sync(
         &self,
         request: Request<criapi::ExecSyncRequest>,
     ) -> Result<Response<criapi::ExecSyncResponse>, Status> {
-        self.handle_exec_sync(request).await
+        self.bounded("ExecSync", request, |r| self.handle_exec_sync(r))
+            .await
     }
 
     async fn exec(
         &self,
         request: Request<criapi::ExecRequest>,
     ) -> Result<Response<criapi::ExecResponse>, Status> {
-        self.handle_exec(request).await
+        self.bounded("Exec", request, |r| self.handle_exec(r)).await
     }
 
     async fn attach(
         &self,
         request: Request<criapi::AttachRequest>,
     ) -> Result<Response<criapi::AttachResponse>, Status> {
-        self.handle_attach(request).await
+        self.bounded("Attach", request, |r| self.handle_attach(r))
+            .await
     }
     async fn port_forward(
         &self,
         request: Request<criapi::PortForwardRequest>,
     ) -> Result<Response<criapi::PortForwardResponse>, Status> {
-        self.handle_port_forward(request).await
+        self.bounded("PortForward", request, |r| self.handle_port_forward(r))
+            .await
     }
 
     async fn run_pod_sandbox(
         &self,
         request: Request<criapi::RunPodSandboxRequest>,
     ) -> Result<Response<criapi::RunPodSandboxResponse>, Status> {
-        self.handle_run_pod_sandbox(request).await
+        let (pod, uid) = request
+            .get_ref()
+            .config
+            .as_ref()
+            .and_then(|x| x.metadata.as_ref())
+            .map(|x| (SandboxData::new_id(&x.uid, x.attempt), Some(x.uid.clone())))
+            .unwrap_or_default();
+        // The sandbox does not exist yet, which is why its UID cannot be looked up
+        let fields = Fields::pod(&pod, uid);
+        self.bounded("RunPodSandbox", request, |r| {
+            logging::scope(
+                fields,
+                self.operations().run(&pod, self.handle_run_pod_sandbox(r)),
+            )
+        })
+        .await
     }
 
     async fn stop_pod_sandbox(
         &self,
         request: Request<criapi::StopPodSandboxRequest>,
     ) -> Result<Response<criapi::StopPodSandboxResponse>, Status> {
-        self.handle_stop_pod_sandbox(request).await
+        let pod = request.get_ref().pod_sandbox_id.clone();
+        self.bounded("StopPodSandbox", request, |r| {
+            self.queued(&pod, None, self.handle_stop_pod_sandbox(r))
+        })
+        .await
     }
 
     async fn remove_pod_sandbox(
         &self,
         request: Request<criapi::RemovePodSandboxRequest>,
     ) -> Result<Response<criapi::RemovePodSandboxResponse>, Status> {
-        self.handle_remove_pod_sandbox(request).await
+        let pod = request.get_ref().pod_sandbox_id.clone();
+        self.bounded("RemovePodSandbox", request, |r| {
+            self.queued(&pod, None, self.handle_remove_pod_sandbox(r))
+        })
+        .await
     }
 
     async fn list_pod_sandbox(
         &self,
         request: Request<criapi::ListPodSandboxRequest>,
     ) -> Result<Response<criapi::ListPodSandboxResponse>, Status> {
-        self.handle_list_pod_sandbox(request).await
+        self.bounded("ListPodSandbox", request, |r| {
+            self.handle_list_pod_sandbox(r)
+        })
+        .await
     }
 
     async fn pod_sandbox_status(
         &self,
         request: Request<criapi::PodSandboxStatusRequest>,
     ) -> Result<Response<criapi::PodSandboxStatusResponse>, Status> {
-        self.handle_pod_sandbox_status(request).await
+        self.bounded("PodSandboxStatus", request, |r| {
+            self.handle_pod_sandbox_status(r)
+        })
+        .await
     }
 
     async fn status(
         &self,
         request: Request<criapi::StatusRequest>,
     ) -> Result<Response<criapi::StatusResponse>, Status> {
-        self.handle_status(request).await
+        self.bounded("Status", request, |r| self.handle_status(r))
+            .await
     }
 
     async fn update_runtime_config(
         &self,
         request: Request<criapi::UpdateRuntimeConfigRequest>,
     ) -> Result<Response<criapi::UpdateRuntimeConfigResponse>, Status> {
-        self.handle_update_runtime_config(request).await
+        self.bounded("UpdateRuntimeConfig", request, |r| {
+            self.handle_update_runtime_config(r)
+        })
+        .await
+    }
+}
+
+impl<S: KeyValueStorage> CRIService<S> {
+    /// Retrieve the pod sandbox ID of the container `id` for queueing its operations. Unknown
+    /// containers are queued by their own ID.
+    fn container_pod(&self, id: &str) -> String {
+        self.resolve_container(id)
+            .map(|x| x.pod_sandbox_id().clone())
+            .unwrap_or_else(|_| id.into())
+    }
+
+    /// Retrieve the container whose ID is `id` or starts with the unique prefix `id`, like docker
+    /// and crictl resolve them.
+    pub fn resolve_container(&self, id: &str) -> Result<Container, Status> {
+        let mut storage = self.storage().clone();
+        if let Some(container) = storage
+            .get::<_, Container>(Container::key(id))
+            .map_err(|e| Status::internal(format!("get container: {}", e)))?
+        {
+            return Ok(container);
+        }
+
+        let resolved = self
+            .container_ids()
+            .read()
+            .map_err(|_| Status::internal("lock container IDs"))?
+            .resolve(id)
+            .map(str::to_string)
+            .map_err(|e| match e {
+                ResolveError::NotFound(_) => {
+                    Status::not_found(format!("container {} not found", id))
+                }
+                e => Status::invalid_argument(e.to_string()),
+            })?;
+        storage
+            .get::<_, Container>(Container::key(&resolved))
+            .map_err(|e| Status::internal(format!("get container: {}", e)))?
+            .ok_or_else(|| Status::not_found(format!("container {} not found", id)))
+    }
+
+    /// Retrieve the path of the log file of the container `id`, which may be a unique prefix.
+    pub fn container_log_path(&self, id: &str) -> Result<PathBuf, Status> {
+        let container = self.resolve_container(id)?;
+        let sandbox = self
+            .storage()
+            .clone()
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(
+                container.pod_sandbox_id(),
+            ))
+            .map_err(|e| Status::internal(format!("get pod sandbox: {}", e)))?
+            .ok_or_else(|| {
+                Status::not_found(format!("pod sandbox of container {} not found", id))
+            })?;
+        if sandbox.data().log_directory().is_empty() || container.log_path().is_empty() {
+            return Err(Status::not_found(format!(
+                "container {} has no log",
+                container.id()
+            )));
+        }
+        Ok(Path::new(sandbox.data().log_directory()).join(container.log_path()))
+    }
+
+    /// Add the container `id` to the index resolving container ID prefixes.
+    fn index_container_id(&self, id: &str) -> Result<(), Status> {
+        self.container_ids()
+            .write()
+            .map_err(|_| Status::internal("lock container IDs"))?
+            .add(id)
+            .map_err(|e| Status::internal(format!("index container ID: {:#}", e)))?;
+        Ok(())
+    }
+
+    /// Remove the container `id` from the index resolving container ID prefixes.
+    fn forget_container_id(&self, id: &str) {
+        if let Ok(mut ids) = self.container_ids().write() {
+            ids.remove(id);
+        }
+    }
+
+    /// Run the operation `f` in the queue of the pod sandbox `pod`, whereas its logs carry the
+    /// pod and the optional `container` as structured fields.
+    async fn queued<F, T>(&self, pod: &str, container: Option<&str>, f: F) -> T
+    where
+        F: Future<Output = T>,
+    {
+        let mut fields = Fields::pod(pod, self.pod_uid(pod));
+        if let Some(id) = container {
+            fields = fields.container(id);
+        }
+        logging::scope(fields, self.operations().run(pod, f)).await
+    }
+
+    /// Retrieve the UID of the pod owning the sandbox `id`, if the sandbox exists.
+    fn pod_uid(&self, id: &str) -> Option<String> {
+        self.storage()
+            .clone()
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(id))
+            .ok()
+            .flatten()
+            .map(|x| x.data().uid().clone())
+    }
+
+    /// Reserve the creation of the object `id` of the `kind` inside of the `namespace` with the
+    /// runtime `handler`, which gets rejected if it would exceed one of the configured quotas. The
+    /// returned reservation has to be kept until the object is stored and is `None` if there are
+    /// no quotas.
+    fn enforce_quota(
+        &self,
+        id: &str,
+        kind: QuotaKind,
+        namespace: &str,
+        handler: &str,
+    ) -> Result<Option<QuotaReservation>, Status> {
+        let quotas = Quotas::from_config(self.config());
+        if quotas.is_empty() {
+            return Ok(None);
+        }
+        let mut storage = self.storage().clone();
+        let reservation = Reservation::new(id, kind, namespace, handler);
+        let res = self.quota_reservations().reserve(reservation, |pending| {
+            let sandboxes = storage
+                .scan_prefix::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key_prefix())
+                .context("list pod sandboxes")?;
+            let containers = match kind {
+                QuotaKind::PodSandbox => vec![],
+                QuotaKind::Container => storage
+                    .scan_prefix::<_, Container>(Container::key_prefix())
+                    .context("list containers")?,
+            };
+            let sandboxes: Vec<_> = sandboxes.iter().map(Sandbox::data).collect();
+            quotas
+                .check(kind, namespace, handler, &sandboxes, &containers, pending)
+                .map_err(Into::into)
+        });
+        match res {
+            Ok(reservation) => Ok(Some(reservation)),
+            Err(e) => match e.downcast::<QuotaExceeded>() {
+                Ok(e) => {
+                    self.metrics().observe_quota_rejection(
+                        e.scope(),
+                        e.name(),
+                        &e.kind().to_string(),
+                    );
+                    Err(ErrorDetails::new("enforce quota")
+                        .hint("remove unused pod sandboxes and containers or raise the quota")
+                        .status(Code::ResourceExhausted, e))
+                }
+                Err(e) => Err(Status::internal(format!("check quotas: {:#}", e))),
+            },
+        }
+    }
+
+    /// Detach the `sandbox` from its network and drop its network status. Sandboxes which are
+    /// not attached to a network are left untouched.
+    async fn detach_network(&self, sandbox: &SandboxData) -> Result<(), Status> {
+        let mut storage = self.storage().clone();
+        let key = NetworkStatus::key(sandbox.id());
+        if let Some(status) = storage
+            .get::<_, NetworkStatus>(&key)
+            .map_err(|e| Status::internal(format!("get network status: {}", e)))?
+        {
+            let config = self.live_config().current();
+            network::detach(&status, sandbox, config.cni_plugin_dirs())
+                .await
+                .map_err(|e| Status::internal(format!("detach pod sandbox network: {:#}", e)))?;
+            storage
+                .remove(&key)
+                .map_err(|e| Status::internal(format!("remove network status: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// Index all stored pod sandboxes and containers, unless their secondary indexes are up to
+    /// date already. The container ID prefixes are always indexed from scratch, because they are
+    /// only kept in memory. Returns the number of indexed records.
+    pub fn rebuild_indexes(&self) -> Result<usize> {
+        let mut storage = self.storage().clone();
+        let sandboxes = storage
+            .scan_prefix::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key_prefix())
+            .context("list pod sandboxes")?
+            .iter()
+            .map(|x| (x.id().to_string(), x.data().index_entries()))
+            .collect::<Vec<_>>();
+        let containers = storage
+            .scan_prefix::<_, Container>(Container::key_prefix())
+            .context("list containers")?
+            .iter()
+            .map(|x| (x.id().clone(), x.index_entries()))
+            .collect::<Vec<_>>();
+        let mut ids = IdIndex::default();
+        for (id, _) in &containers {
+            ids.add(id.as_str())?;
+        }
+        *self
+            .container_ids()
+            .write()
+            .map_err(|_| format_err!("lock container IDs"))? = ids;
+        Ok(sandbox::INDEX.rebuild(&mut storage, &sandboxes)?
+            + container::INDEX.rebuild(&mut storage, &containers)?)
+    }
+
+    /// Attach all stored pod sandboxes again to their networks, whose network namespace got lost,
+    /// for example because the node rebooted. Sandboxes failing to recover keep their previous
+    /// network status, so that they can still be removed. Returns the number of recovered
+    /// sandboxes.
+    pub async fn recover_networks(&self) -> Result<usize> {
+        let mut storage = self.storage().clone();
+        let sandboxes = storage
+            .scan_prefix::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key_prefix())
+            .context("list pod sandboxes")?;
+
+        let mut recovered = 0;
+        for sandbox in sandboxes.iter().map(Sandbox::data) {
+            let key = NetworkStatus::key(sandbox.id());
+            let status = match storage
+                .get::<_, NetworkStatus>(&key)
+                .context("get network status")?
+            {
+                Some(status) => status,
+                None => continue,
+            };
+            match self.recover_network(sandbox, &status).await {
+                Ok(true) => recovered += 1,
+                Ok(false) => {}
+                Err(e) => error!(
+                    "Unable to recover network of pod sandbox {}: {:#}",
+                    sandbox.id(),
+                    e
+                ),
+            }
+        }
+        Ok(recovered)
+    }
+
+    /// Attach the `sandbox` again to the network of its `status`, unless its network namespace is
+    /// still pinned. Returns whether the network has been recovered.
+    async fn recover_network(&self, sandbox: &SandboxData, status: &NetworkStatus) -> Result<bool> {
+        if netns::is_pinned(status.netns())? {
+            return Ok(false);
+        }
+
+        let config = self.live_config().current();
+        let mut timeline = Timeline::start(self.clock().clone());
+        let status = network::reattach(status, sandbox, config.cni_plugin_dirs(), &mut timeline)
+            .await
+            .context("reattach network")?;
+        self.storage()
+            .clone()
+            .insert(NetworkStatus::key(sandbox.id()), &status)
+            .context("update network status")?;
+        info!("Recovered network of pod sandbox {}", sandbox.id());
+        Ok(true)
+    }
+
+    /// Release the network namespaces and the IP allocations of the current network without a
+    /// stored pod sandbox, which have been found by two consecutive scans of the `detector`.
+    /// Allocations get released by deleting the network of their sandbox ID. Returns the number of
+    /// released resources.
+    pub async fn release_network_leaks(&self, detector: &mut LeakDetector) -> Result<usize> {
+        let owners = self
+            .storage()
+            .clone()
+            .scan_prefix::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key_prefix())
+            .context("list pod sandboxes")?
+            .iter()
+            .map(|x| x.data().id().clone())
+            .collect();
+        let config = self.live_config().current();
+        let network = CniNetwork::load(config.cni_config_dir(), config.cni_plugin_dirs())
+            .context("load network configuration")?;
+        let ipam_dir = network
+            .as_ref()
+            .map(|x| self.config().cni_ipam_state_path().join(x.name()));
+        let netns_dir = self.config().netns_path();
+        let leaks = detector.scan(netns_dir, ipam_dir.as_deref(), &owners)?;
+
+        let mut released = 0;
+        for leak in leaks {
+            let res = match (&leak, &network) {
+                (Leak::Netns(path), _) => netns::unpin(path),
+                (Leak::Ip(_, id), Some(network)) => {
+                    let sandbox = SandboxDataBuilder::default()
+                        .id(id.as_str())
+                        .name("")
+                        .namespace("")
+                        .attempt(0u32)
+                        .build()
+                        .map_err(|e| format_err!("build sandbox data: {}", e))?;
+                    network.del(&sandbox, &netns_dir.join(id), None).await
+                }
+                (Leak::Ip(..), None) => continue,
+            };
+            match res {
+                Ok(()) => {
+                    warn!("Released leaked {}", leak);
+                    released += 1;
+                }
+                Err(e) => error!("Unable to release leaked {}: {:#}", leak, e),
+            }
+        }
+        Ok(released)
+    }
+
+    /// Retrieve the current time of the clock in nanoseconds since the Unix epoch.
+    fn unix_nanos(&self) -> Result<i64, Status> {
+        self.clock()
+            .unix_nanos()
+            .map_err(|e| Status::internal(format!("{:#}", e)))
     }
 }
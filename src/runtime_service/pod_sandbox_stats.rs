@@ -0,0 +1,132 @@
+use crate::{
+    container::ContainerState,
+    cri_service::CRIService,
+    criapi::{
+        LinuxPodSandboxStats, PodSandboxAttributes, PodSandboxStats, PodSandboxStatsRequest,
+        PodSandboxStatsResponse,
+    },
+    network::{stats, NetworkStatus},
+    resources::DefaultResourceManager,
+    sandbox::{infra::InfraSandbox, Sandbox},
+    storage::KeyValueStorage,
+};
+use log::debug;
+use std::collections::HashMap;
+use tonic::{Request, Response, Status};
+
+impl<S: KeyValueStorage> CRIService<S> {
+    pub async fn handle_pod_sandbox_stats(
+        &self,
+        request: Request<PodSandboxStatsRequest>,
+    ) -> Result<Response<PodSandboxStatsResponse>, Status> {
+        let id = request.into_inner().pod_sandbox_id;
+        let sandbox = self
+            .storage()
+            .clone()
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(&id))
+            .map_err(|e| Status::internal(format!("get pod sandbox: {}", e)))?
+            .ok_or_else(|| Status::not_found(format!("pod sandbox {} not found", id)))?;
+
+        let resp = PodSandboxStatsResponse {
+            stats: Some(self.pod_sandbox_stats(&sandbox)?),
+        };
+        Ok(Response::new(resp))
+    }
+
+    /// Collect the statistics of the `sandbox`, which consist of the counters of its network
+    /// interfaces and the statistics of its running containers.
+    pub fn pod_sandbox_stats(
+        &self,
+        sandbox: &Sandbox<InfraSandbox>,
+    ) -> Result<PodSandboxStats, Status> {
+        let mut storage = self.storage().clone();
+        let timestamp = self
+            .clock()
+            .unix_nanos()
+            .map_err(|e| Status::internal(format!("get current time: {:#}", e)))?;
+
+        // Sandboxes on the host network have no namespace of their own to account
+        let network = storage
+            .get::<_, NetworkStatus>(NetworkStatus::key(sandbox.id()))
+            .map_err(|e| Status::internal(format!("get network status: {}", e)))?
+            .and_then(|status| match stats::read(status.netns()) {
+                Ok(interfaces) => Some(stats::usage(&interfaces, timestamp)),
+                Err(e) => {
+                    debug!("Skipping network stats of {}: {:#}", sandbox.id(), e);
+                    None
+                }
+            });
+
+        let manager = DefaultResourceManager::default();
+        let containers = self
+            .select_containers("", sandbox.id(), &HashMap::new())?
+            .iter()
+            .filter(|x| x.state() == ContainerState::Running)
+            .filter_map(|container| match self.stats().get(&manager, container) {
+                Ok(sample) => Some(sample),
+                Err(e) => {
+                    debug!("Skipping stats of container {}: {:#}", container, e);
+                    None
+                }
+            })
+            .collect();
+
+        let data = sandbox.data();
+        Ok(PodSandboxStats {
+            attributes: Some(PodSandboxAttributes {
+                id: data.id().clone(),
+                metadata: Some(data.metadata()),
+                labels: data.labels().clone(),
+                annotations: data.annotations().clone(),
+            }),
+            linux: Some(LinuxPodSandboxStats {
+                network,
+                containers,
+                ..Default::default()
+            }),
+            windows: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service, criapi::runtime_service_server::RuntimeService,
+        runtime_service::run_pod_sandbox::tests::new_pod_sandbox,
+    };
+    use anyhow::{Context, Result};
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn pod_sandbox_stats_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let id = new_pod_sandbox(&sut).await?;
+
+        let stats = sut
+            .pod_sandbox_stats(Request::new(PodSandboxStatsRequest {
+                pod_sandbox_id: id.clone(),
+            }))
+            .await?
+            .into_inner()
+            .stats
+            .context("no stats")?;
+        assert_eq!(stats.attributes.map(|x| x.id), Some(id));
+        let linux = stats.linux.context("no linux stats")?;
+        assert!(linux.containers.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pod_sandbox_stats_fail_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let response = sut
+            .pod_sandbox_stats(Request::new(PodSandboxStatsRequest {
+                pod_sandbox_id: "unknown".into(),
+            }))
+            .await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+}
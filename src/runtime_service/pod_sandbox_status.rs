@@ -1,19 +1,164 @@
 use crate::{
     cri_service::CRIService,
-    criapi::{PodSandboxStatusRequest, PodSandboxStatusResponse},
+    criapi::{
+        LinuxPodSandboxStatus, Namespace, NamespaceMode, NamespaceOption, PodIp,
+        PodSandboxNetworkStatus, PodSandboxState, PodSandboxStatus, PodSandboxStatusRequest,
+        PodSandboxStatusResponse,
+    },
+    network::NetworkStatus,
+    sandbox::{infra::InfraSandbox, Sandbox},
+    storage::KeyValueStorage,
 };
 use std::collections::HashMap;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_pod_sandbox_status(
         &self,
-        _request: Request<PodSandboxStatusRequest>,
+        request: Request<PodSandboxStatusRequest>,
     ) -> Result<Response<PodSandboxStatusResponse>, Status> {
+        let request = request.into_inner();
+        let key = Sandbox::<InfraSandbox>::key(&request.pod_sandbox_id);
+
+        let mut storage = self.storage().clone();
+        let mut sandbox = storage
+            .get::<_, Sandbox<InfraSandbox>>(&key)
+            .map_err(|e| Status::internal(format!("get pod sandbox: {}", e)))?
+            .ok_or_else(|| {
+                Status::not_found(format!("pod sandbox {} not found", request.pod_sandbox_id))
+            })?;
+
+        // Persist the sandbox if its infra process exited in the meantime
+        let pid = sandbox.implementation().pid();
+        let ready = sandbox
+            .ready()
+            .map_err(|e| Status::internal(format!("check pod sandbox readiness: {:#}", e)))?;
+        if pid != sandbox.implementation().pid() {
+            storage
+                .insert(&key, &sandbox)
+                .map_err(|e| Status::internal(format!("update pod sandbox: {}", e)))?;
+        }
+
+        // The primary IP comes first, followed by the additional ones of dual stack networks
+        let ips: Vec<String> = storage
+            .get::<_, NetworkStatus>(NetworkStatus::key(sandbox.id()))
+            .map_err(|e| Status::internal(format!("get network status: {}", e)))?
+            .map(|x| x.ips().iter().map(ToString::to_string).collect())
+            .unwrap_or_default();
+
+        // Extra information is only allowed on verbose requests
+        let mut info = HashMap::new();
+        if request.verbose {
+            let implementation = serde_json::to_string(sandbox.implementation())
+                .map_err(|e| Status::internal(format!("serialize pod sandbox: {}", e)))?;
+            info.insert("info".into(), implementation);
+        }
+
+        let data = sandbox.data();
+        let mode = |host: bool| {
+            if host {
+                NamespaceMode::Node as i32
+            } else {
+                NamespaceMode::Pod as i32
+            }
+        };
+        let state = if ready {
+            PodSandboxState::SandboxReady
+        } else {
+            PodSandboxState::SandboxNotready
+        };
+        let status = PodSandboxStatus {
+            id: data.id().clone(),
+            metadata: Some(data.metadata()),
+            state: state as i32,
+            created_at: *data.created_at(),
+            network: Some(PodSandboxNetworkStatus {
+                ip: ips.first().cloned().unwrap_or_default(),
+                additional_ips: ips.into_iter().skip(1).map(|ip| PodIp { ip }).collect(),
+            }),
+            linux: Some(LinuxPodSandboxStatus {
+                namespaces: Some(Namespace {
+                    options: Some(NamespaceOption {
+                        network: mode(*data.host_network()),
+                        pid: *data.pid_mode(),
+                        ipc: mode(*data.host_ipc()),
+                        target_id: "".into(),
+                    }),
+                }),
+            }),
+            labels: data.labels().clone(),
+            annotations: data.annotations().clone(),
+            runtime_handler: data.runtime_handler().clone(),
+        };
+
         let reply = PodSandboxStatusResponse {
-            info: HashMap::new(),
-            status: None,
+            info,
+            status: Some(status),
         };
         Ok(Response::new(reply))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service,
+        criapi::{runtime_service_server::RuntimeService, StopPodSandboxRequest},
+        runtime_service::run_pod_sandbox::tests::new_run_pod_sandbox_request,
+    };
+    use anyhow::{Context, Result};
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn pod_sandbox_status_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let id = sut
+            .run_pod_sandbox(Request::new(new_run_pod_sandbox_request("123", 0)))
+            .await?
+            .into_inner()
+            .pod_sandbox_id;
+
+        let request = PodSandboxStatusRequest {
+            pod_sandbox_id: id.clone(),
+            verbose: false,
+        };
+        let response = sut.pod_sandbox_status(Request::new(request)).await?;
+        assert!(response.get_ref().info.is_empty());
+        let status = response.get_ref().status.as_ref().context("no status")?;
+        assert_eq!(status.id, id);
+        assert_eq!(status.state, PodSandboxState::SandboxReady as i32);
+        assert!(status.created_at > 0);
+        assert_eq!(
+            status.metadata.as_ref().map(|x| x.uid.as_str()),
+            Some("123")
+        );
+
+        let request = StopPodSandboxRequest {
+            pod_sandbox_id: id.clone(),
+        };
+        sut.stop_pod_sandbox(Request::new(request)).await?;
+
+        let request = PodSandboxStatusRequest {
+            pod_sandbox_id: id,
+            verbose: true,
+        };
+        let response = sut.pod_sandbox_status(Request::new(request)).await?;
+        assert!(response.get_ref().info.contains_key("info"));
+        let status = response.get_ref().status.as_ref().context("no status")?;
+        assert_eq!(status.state, PodSandboxState::SandboxNotready as i32);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pod_sandbox_status_fail_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = PodSandboxStatusRequest {
+            pod_sandbox_id: "unknown".into(),
+            verbose: false,
+        };
+        let response = sut.pod_sandbox_status(Request::new(request)).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+}
@@ -1,15 +1,145 @@
 use crate::{
     cri_service::CRIService,
     criapi::{PortForwardRequest, PortForwardResponse},
+    sandbox::{infra::InfraSandbox, Sandbox},
+    storage::KeyValueStorage,
+    streaming::session::Session,
 };
+use std::convert::TryFrom;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_port_forward(
         &self,
-        _request: Request<PortForwardRequest>,
+        request: Request<PortForwardRequest>,
     ) -> Result<Response<PortForwardResponse>, Status> {
-        let resp = PortForwardResponse { url: "url".into() };
+        let request = request.into_inner();
+        let ports = request
+            .port
+            .iter()
+            .map(|port| {
+                u16::try_from(*port)
+                    .map_err(|_| Status::invalid_argument(format!("invalid port {}", port)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The ports are forwarded inside of the network namespace of the sandbox
+        let sandbox = self
+            .storage()
+            .clone()
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(&request.pod_sandbox_id))
+            .map_err(|e| Status::internal(format!("get pod sandbox: {}", e)))?
+            .ok_or_else(|| {
+                Status::not_found(format!("pod sandbox {} not found", request.pod_sandbox_id))
+            })?;
+
+        let data = sandbox.data();
+        let netns = match data.netns() {
+            Some(netns) => Some(netns.clone()),
+            None if *data.host_network() => None,
+            None => {
+                return Err(Status::failed_precondition(format!(
+                    "pod sandbox {} has no shared network namespace",
+                    sandbox
+                )))
+            }
+        };
+        let session = Session::PortForward { netns, ports };
+        let resp = PortForwardResponse {
+            url: self.streaming().insert(session),
+        };
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service,
+        criapi::{
+            runtime_service_server::RuntimeService, LinuxPodSandboxConfig,
+            LinuxSandboxSecurityContext, NamespaceMode, NamespaceOption,
+        },
+        runtime_service::run_pod_sandbox::tests::{new_pod_sandbox, new_run_pod_sandbox_request},
+    };
+    use anyhow::Result;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn port_forward_success_host_network() -> Result<()> {
+        let sut = new_cri_service()?;
+        let mut request = new_run_pod_sandbox_request("123", 0);
+        if let Some(config) = request.config.as_mut() {
+            config.linux = Some(LinuxPodSandboxConfig {
+                security_context: Some(LinuxSandboxSecurityContext {
+                    namespace_options: Some(NamespaceOption {
+                        network: NamespaceMode::Node as i32,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+        }
+        let id = sut
+            .run_pod_sandbox(Request::new(request))
+            .await?
+            .into_inner()
+            .pod_sandbox_id;
+
+        let request = PortForwardRequest {
+            pod_sandbox_id: id,
+            port: vec![8080],
+        };
+        let url = sut
+            .port_forward(Request::new(request))
+            .await?
+            .into_inner()
+            .url;
+        assert!(url.contains("/portforward/"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn port_forward_fail_no_network_namespace() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = PortForwardRequest {
+            pod_sandbox_id: new_pod_sandbox(&sut).await?,
+            port: vec![8080],
+        };
+        let response = sut.port_forward(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn port_forward_fail_invalid_port() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = PortForwardRequest {
+            pod_sandbox_id: "unknown".into(),
+            port: vec![-1],
+        };
+        let response = sut.port_forward(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn port_forward_fail_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = PortForwardRequest {
+            pod_sandbox_id: "unknown".into(),
+            port: vec![8080],
+        };
+        let response = sut.port_forward(Request::new(request)).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+}
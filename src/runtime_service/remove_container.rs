@@ -1,15 +1,269 @@
 use crate::{
+    container::{self, gc::RetentionPolicy, Container},
+    container_log,
     cri_service::CRIService,
     criapi::{RemoveContainerRequest, RemoveContainerResponse},
+    event::Event,
+    idempotency::IdempotencyRecord,
+    oci::runtime::{error_status, OciRuntime},
+    sandbox::{checkpoint::Checkpoint, infra::InfraSandbox, Sandbox},
+    storage::KeyValueStorage,
 };
+use anyhow::{format_err, Context, Result};
+use log::{info, warn};
+use std::{collections::HashSet, fs, path::Path, time::Duration};
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_remove_container(
         &self,
-        _request: Request<RemoveContainerRequest>,
+        request: Request<RemoveContainerRequest>,
     ) -> Result<Response<RemoveContainerResponse>, Status> {
+        let id = request.into_inner().container_id;
+        let key = Container::key(&id);
+
+        // Removing an unknown container is not an error, because it may have been removed already
+        let mut storage = self.storage().clone();
+        let container = match storage
+            .get::<_, Container>(&key)
+            .map_err(|e| Status::internal(format!("get container: {}", e)))?
+        {
+            Some(container) => container,
+            None => {
+                info!("Container {} not found, nothing to remove", id);
+                return Ok(Response::new(RemoveContainerResponse {}));
+            }
+        };
+
+        // A running container gets forcibly killed before being removed
+        OciRuntime::new(self.config().oci_runtime())
+            .delete(&id, true)
+            .await
+            .map_err(|e| error_status("remove container", e))?;
+        if container.bundle().exists() {
+            fs::remove_dir_all(container.bundle()).map_err(|e| {
+                Status::internal(format!(
+                    "remove bundle {}: {}",
+                    container.bundle().display(),
+                    e
+                ))
+            })?;
+        }
+
+        // Drop all records of the container, so that nothing refers to it anymore
+        let idempotency_key = IdempotencyRecord::container_key(
+            container.pod_sandbox_id(),
+            container.name(),
+            container.attempt(),
+        );
+        if storage
+            .get::<_, IdempotencyRecord>(&idempotency_key)
+            .map_err(|e| Status::internal(format!("get idempotency record: {}", e)))?
+            .map_or(false, |x| x.id() == container.id())
+        {
+            storage
+                .remove(&idempotency_key)
+                .map_err(|e| Status::internal(format!("remove idempotency record: {}", e)))?;
+        }
+        storage
+            .remove(&key)
+            .map_err(|e| Status::internal(format!("remove container record: {}", e)))?;
+        self.forget_container_id(container.id());
+        container::INDEX
+            .remove(&mut storage, container.id(), &container.index_entries())
+            .map_err(|e| Status::internal(format!("remove container index: {:#}", e)))?;
+        self.stats().forget(container.id());
+        info!("Removed container {}", container);
+
         let resp = RemoveContainerResponse {};
         Ok(Response::new(resp))
     }
+
+    /// Remove the exited containers exceeding the configured retention together with their log
+    /// files, whereas every removal runs in the queue of its pod sandbox. Returns the number of
+    /// removed containers.
+    pub async fn collect_containers(&self) -> Result<usize> {
+        let policy = RetentionPolicy::new(
+            self.config().exited_containers_per_pod(),
+            Duration::from_secs(self.config().exited_container_max_age()),
+        );
+        let mut storage = self.storage().clone();
+        let containers = storage
+            .scan_prefix::<_, Container>(Container::key_prefix())
+            .context("list containers")?;
+        let now = self.clock().unix_nanos()?;
+
+        // Checkpointed containers are only exited until they get restored
+        let checkpointed = storage
+            .scan_prefix::<_, Checkpoint>(Checkpoint::key_prefix())
+            .context("list checkpoints")?
+            .iter()
+            .flat_map(|x| x.containers().clone())
+            .collect::<HashSet<_>>();
+        let containers = containers
+            .into_iter()
+            .filter(|x| !checkpointed.contains(x.id()))
+            .collect::<Vec<_>>();
+
+        let mut removed = 0;
+        for (container, reason) in policy.select(&containers, now) {
+            let (id, pod) = (container.id(), container.pod_sandbox_id());
+            let res = self
+                .queued(pod, Some(id), async {
+                    let request = RemoveContainerRequest {
+                        container_id: id.clone(),
+                    };
+                    self.handle_remove_container(Request::new(request))
+                        .await
+                        .map_err(|e| format_err!("{}", e.message()))?;
+                    let sandbox = storage
+                        .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(pod))
+                        .context("get pod sandbox")?;
+                    match sandbox {
+                        Some(sandbox)
+                            if !sandbox.data().log_directory().is_empty()
+                                && !container.log_path().is_empty() =>
+                        {
+                            let path = Path::new(sandbox.data().log_directory())
+                                .join(container.log_path());
+                            container_log::remove(&path)
+                        }
+                        _ => Ok(()),
+                    }
+                })
+                .await;
+            match res {
+                Ok(()) => {
+                    info!(
+                        "Collected exited container {} ({})",
+                        container,
+                        reason.as_ref()
+                    );
+                    self.events().publish(Event::ContainerCollected {
+                        id: id.clone(),
+                        pod_sandbox_id: pod.clone(),
+                        reason: reason.as_ref().into(),
+                    });
+                    removed += 1;
+                }
+                Err(e) => warn!("Unable to collect exited container {}: {:#}", container, e),
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::{
+            new_cri_service_with_config, new_cri_service_with_runtime, test_config,
+        },
+        criapi::runtime_service_server::RuntimeService,
+        oci::runtime::tests::{fake_runtime, fake_runtime_log},
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn remove_container_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "stopped")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+        let bundle = sut.config().container_path().join(&id);
+        assert!(bundle.exists());
+
+        let request = RemoveContainerRequest {
+            container_id: id.clone(),
+        };
+        sut.remove_container(Request::new(request)).await?;
+
+        let mut storage = sut.storage().clone();
+        assert!(storage.get::<_, Container>(Container::key(&id))?.is_none());
+        assert!(storage
+            .get::<_, IdempotencyRecord>(IdempotencyRecord::container_key(&sandbox_id, "name", 0))?
+            .is_none());
+        assert!(!bundle.exists());
+        assert!(fake_runtime_log(dir.path())?.contains(&format!("delete --force {}", id)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_container_success_not_found() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "stopped")?;
+        let request = RemoveContainerRequest {
+            container_id: "unknown".into(),
+        };
+        sut.remove_container(Request::new(request)).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn collect_containers_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .oci_runtime(fake_runtime(dir.path(), "stopped")?)
+                .exited_containers_per_pod(1usize)
+                .build()?,
+        )?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+
+        let mut storage = sut.storage().clone();
+        let mut ids = vec![];
+        for attempt in 0..2 {
+            let mut request = new_create_container_request(&sandbox_id, "name");
+            if let Some(metadata) = request.config.as_mut().and_then(|x| x.metadata.as_mut()) {
+                metadata.attempt = attempt;
+            }
+            let id = sut
+                .create_container(Request::new(request))
+                .await?
+                .into_inner()
+                .container_id;
+            let mut container = storage
+                .get::<_, Container>(Container::key(&id))?
+                .context("no container")?;
+            container.set_exited(i64::from(attempt) + 1);
+            storage.insert(Container::key(&id), &container)?;
+            ids.push(id);
+        }
+
+        let mut events = sut.events().subscribe();
+        assert_eq!(sut.collect_containers().await?, 1);
+        assert!(storage
+            .get::<_, Container>(Container::key(&ids[0]))?
+            .is_none());
+        assert!(storage
+            .get::<_, Container>(Container::key(&ids[1]))?
+            .is_some());
+        assert_eq!(
+            events.recv().await?,
+            Event::ContainerCollected {
+                id: ids[0].clone(),
+                pod_sandbox_id: sandbox_id,
+                reason: "max_per_pod".into(),
+            }
+        );
+
+        // The latest attempt is always retained
+        assert_eq!(sut.collect_containers().await?, 0);
+        Ok(())
+    }
 }
@@ -1,15 +1,142 @@
 use crate::{
     cri_service::CRIService,
     criapi::{RemovePodSandboxRequest, RemovePodSandboxResponse},
+    idempotency::IdempotencyRecord,
+    resources::{sandbox_cgroup_path, DefaultResourceManager, ResourceManager},
+    sandbox::{self, checkpoint::Checkpoint, infra::InfraSandbox, tombstone::Tombstone, Sandbox},
+    storage::KeyValueStorage,
 };
+use log::{info, warn};
+use std::fs;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_remove_pod_sandbox(
         &self,
-        _request: Request<RemovePodSandboxRequest>,
+        request: Request<RemovePodSandboxRequest>,
     ) -> Result<Response<RemovePodSandboxResponse>, Status> {
+        let id = request.into_inner().pod_sandbox_id;
+        let key = Sandbox::<InfraSandbox>::key(&id);
+
+        // Removing an unknown sandbox is not an error, because it may have been removed already
+        let mut storage = self.storage().clone();
+        let mut sandbox = match storage
+            .get::<_, Sandbox<InfraSandbox>>(&key)
+            .map_err(|e| Status::internal(format!("get pod sandbox: {}", e)))?
+        {
+            Some(sandbox) => sandbox,
+            None => {
+                info!("Pod sandbox {} not found, nothing to remove", id);
+                return Ok(Response::new(RemovePodSandboxResponse {}));
+            }
+        };
+
+        // A running sandbox gets forcibly stopped before being removed
+        self.detach_network(sandbox.data()).await?;
+        sandbox
+            .remove()
+            .map_err(|e| Status::internal(format!("remove pod sandbox: {:#}", e)))?;
+
+        // The parent cgroup of the containers is left behind by the OCI runtime
+        let cgroup_path = sandbox_cgroup_path(sandbox.id());
+        if let Err(e) = DefaultResourceManager::default().remove(&cgroup_path) {
+            warn!("Unable to remove cgroup {}: {:#}", cgroup_path.display(), e);
+        }
+
+        // Drop all records of the sandbox, so that nothing refers to it anymore
+        let idempotency_key =
+            IdempotencyRecord::pod_sandbox_key(sandbox.data().uid(), *sandbox.data().attempt());
+        if storage
+            .get::<_, IdempotencyRecord>(&idempotency_key)
+            .map_err(|e| Status::internal(format!("get idempotency record: {}", e)))?
+            .map_or(false, |x| x.id() == sandbox.id())
+        {
+            storage
+                .remove(&idempotency_key)
+                .map_err(|e| Status::internal(format!("remove idempotency record: {}", e)))?;
+        }
+        let tombstone_key = Tombstone::key(sandbox.id());
+        if storage
+            .get::<_, Tombstone>(&tombstone_key)
+            .map_err(|e| Status::internal(format!("get sandbox tombstone: {}", e)))?
+            .is_some()
+        {
+            storage
+                .remove(&tombstone_key)
+                .map_err(|e| Status::internal(format!("remove sandbox tombstone: {}", e)))?;
+        }
+        let checkpoint_key = Checkpoint::key(sandbox.id());
+        if storage
+            .get::<_, Checkpoint>(&checkpoint_key)
+            .map_err(|e| Status::internal(format!("get checkpoint: {}", e)))?
+            .is_some()
+        {
+            if let Some(path) = self.config().checkpoint_path() {
+                let dir = Checkpoint::dir(path, sandbox.id());
+                if let Err(e) = fs::remove_dir_all(&dir) {
+                    warn!("Unable to remove checkpoint {}: {}", dir.display(), e);
+                }
+            }
+            storage
+                .remove(&checkpoint_key)
+                .map_err(|e| Status::internal(format!("remove checkpoint: {}", e)))?;
+        }
+        storage
+            .remove(&key)
+            .map_err(|e| Status::internal(format!("remove pod sandbox record: {}", e)))?;
+        sandbox::INDEX
+            .remove(&mut storage, sandbox.id(), &sandbox.data().index_entries())
+            .map_err(|e| Status::internal(format!("remove pod sandbox index: {:#}", e)))?;
+        info!("Removed pod sandbox {}", sandbox);
+
         let reply = RemovePodSandboxResponse {};
         Ok(Response::new(reply))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service, criapi::runtime_service_server::RuntimeService,
+        runtime_service::run_pod_sandbox::tests::new_run_pod_sandbox_request,
+    };
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn remove_pod_sandbox_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let id = sut
+            .run_pod_sandbox(Request::new(new_run_pod_sandbox_request("123", 0)))
+            .await?
+            .into_inner()
+            .pod_sandbox_id;
+        let path = sut.config().sandbox_path().join(&id);
+        assert!(path.exists());
+
+        let request = RemovePodSandboxRequest {
+            pod_sandbox_id: id.clone(),
+        };
+        sut.remove_pod_sandbox(Request::new(request)).await?;
+
+        let mut storage = sut.storage().clone();
+        assert!(storage
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(&id))?
+            .is_none());
+        assert!(storage
+            .get::<_, IdempotencyRecord>(IdempotencyRecord::pod_sandbox_key("123", 0))?
+            .is_none());
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_pod_sandbox_success_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = RemovePodSandboxRequest {
+            pod_sandbox_id: "unknown".into(),
+        };
+        sut.remove_pod_sandbox(Request::new(request)).await?;
+        Ok(())
+    }
+}
@@ -1,15 +1,99 @@
 use crate::{
+    container::{Container, ContainerState},
     cri_service::CRIService,
     criapi::{ReopenContainerLogRequest, ReopenContainerLogResponse},
+    storage::KeyValueStorage,
 };
+use log::info;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_reopen_container_log(
         &self,
-        _request: Request<ReopenContainerLogRequest>,
+        request: Request<ReopenContainerLogRequest>,
     ) -> Result<Response<ReopenContainerLogResponse>, Status> {
+        let id = request.into_inner().container_id;
+        let container = self
+            .storage()
+            .clone()
+            .get::<_, Container>(Container::key(&id))
+            .map_err(|e| Status::internal(format!("get container: {}", e)))?
+            .ok_or_else(|| Status::not_found(format!("container {} not found", id)))?;
+
+        // Only running containers write their output, so the kubelet retries on anything else
+        if container.state() != ContainerState::Running {
+            return Err(Status::failed_precondition(format!(
+                "container {} is not running",
+                container
+            )));
+        }
+        let reopened = self
+            .logs()
+            .reopen(&id)
+            .await
+            .map_err(|e| Status::internal(format!("reopen container log: {:#}", e)))?;
+        if !reopened {
+            return Err(Status::failed_precondition(format!(
+                "log of container {} is not being written",
+                container
+            )));
+        }
+        info!("Reopened log of container {}", container);
+
         let resp = ReopenContainerLogResponse {};
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::{new_cri_service, new_cri_service_with_runtime},
+        criapi::runtime_service_server::RuntimeService,
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn reopen_container_log_fail_not_running() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let container_id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+
+        let response = sut
+            .reopen_container_log(Request::new(ReopenContainerLogRequest { container_id }))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reopen_container_log_fail_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let response = sut
+            .reopen_container_log(Request::new(ReopenContainerLogRequest {
+                container_id: "unknown".into(),
+            }))
+            .await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+}
@@ -1,19 +1,50 @@
 use crate::{
     cri_service::CRIService,
-    criapi::{RunPodSandboxRequest, RunPodSandboxResponse},
-    sandbox::{pinned::PinnedSandbox, SandboxBuilder, SandboxDataBuilder},
+    criapi::{NamespaceMode, RunPodSandboxRequest, RunPodSandboxResponse},
+    error_details::ErrorDetails,
+    idempotency::IdempotencyRecord,
+    latency::Timeline,
+    network::{self, cni::CniNetwork, netns, NetworkStatus},
+    quota::QuotaKind,
+    sandbox::{
+        self,
+        dns::{resolv_conf, RESOLV_CONF_FILE},
+        infra::InfraSandbox,
+        ipc::host_ipc,
+        namespaces::Namespace,
+        prefetch::prefetch_images,
+        tombstone::Tombstone,
+        uts::uts_names,
+        PortMapping, Sandbox, SandboxBuilder, SandboxData, SandboxDataBuilder,
+    },
+    storage::KeyValueStorage,
 };
-use log::{debug, info};
-use tonic::{Request, Response, Status};
+use anyhow::Context;
+use log::{debug, error, info, warn};
+use nix::unistd;
+use std::fs;
+use tonic::{Code, Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_run_pod_sandbox(
         &self,
         request: Request<RunPodSandboxRequest>,
     ) -> Result<Response<RunPodSandboxResponse>, Status> {
+        // Run all admissions which may reject or mutate the request
+        let mut timeline = Timeline::start(self.clock().clone());
+        let mut request = request.into_inner();
+        self.admission()
+            .admit_pod_sandbox(&mut request)
+            .await
+            .map_err(|e| {
+                ErrorDetails::new("admit pod sandbox")
+                    .hint("check the namespace policies of the runtime")
+                    .status(Code::PermissionDenied, format!("{:#}", e))
+            })?;
+        timeline.step("admission");
+
         // Take the pod sandbox config
         let config = request
-            .into_inner()
             .config
             .take()
             .ok_or_else(|| Status::invalid_argument("no pod sandbox config provided"))?;
@@ -23,29 +54,212 @@ impl CRIService {
             .metadata
             .ok_or_else(|| Status::invalid_argument("no pod sandbox metadata provided"))?;
 
+        // Return the original result if the request is a retry of an already successful one
+        let mut storage = self.storage().clone();
+        let idempotency_key = IdempotencyRecord::pod_sandbox_key(&metadata.uid, metadata.attempt);
+        if let Some(record) = storage
+            .get::<_, IdempotencyRecord>(&idempotency_key)
+            .map_err(|e| Status::internal(format!("get idempotency record: {}", e)))?
+        {
+            info!("Pod sandbox {} already running for request", record.id());
+            let reply = RunPodSandboxResponse {
+                pod_sandbox_id: record.id().into(),
+            };
+            return Ok(Response::new(reply));
+        }
+        let attempt = metadata.attempt;
+        let id = SandboxData::new_id(&metadata.uid, attempt);
+        let _reservation = self.enforce_quota(
+            &id,
+            QuotaKind::PodSandbox,
+            &metadata.namespace,
+            &request.runtime_handler,
+        )?;
+
+        // Pods using the host network share the UTS namespace with the host, too
+        let namespace_options = config
+            .linux
+            .as_ref()
+            .and_then(|x| x.security_context.as_ref())
+            .and_then(|x| x.namespace_options.as_ref());
+        let host_network = namespace_options
+            .map(|x| x.network == NamespaceMode::Node as i32)
+            .unwrap_or(false);
+        let (hostname, domainname) = uts_names(&config.hostname, host_network)
+            .map_err(|e| Status::invalid_argument(format!("invalid hostname: {}", e)))?;
+        let host_ipc = host_ipc(namespace_options.map(|x| x.ipc).unwrap_or_default())
+            .map_err(|e| Status::invalid_argument(format!("invalid IPC namespace: {}", e)))?;
+
+        // The resolv.conf gets generated from the DNS config of the kubelet, if provided
+        let resolv_conf = match &config.dns_config {
+            Some(dns_config) => Some(
+                resolv_conf(
+                    Some(dns_config),
+                    &config.annotations,
+                    self.config().dns_options(),
+                )
+                .map_err(|e| Status::invalid_argument(format!("invalid DNS config: {:#}", e)))?,
+            ),
+            None => None,
+        };
+
+        // Pods using their own network namespace get attached to the CNI network, if configured
+        let network = if host_network {
+            None
+        } else {
+            let config = self.live_config().current();
+            CniNetwork::load(config.cni_config_dir(), config.cni_plugin_dirs())
+                .map_err(|e| Status::internal(format!("load CNI network: {:#}", e)))?
+        };
+        timeline.step("cni-config");
+
         // Build a new sandbox from it
-        let mut sandbox = SandboxBuilder::<PinnedSandbox>::default()
+        let netns = network
+            .as_ref()
+            .map(|_| self.config().netns_path().join(&id));
+        let created_at = self.unix_nanos()?;
+        let sandbox_path = self.config().sandbox_path().join(&id);
+        let resolv_conf_path = resolv_conf
+            .as_ref()
+            .map(|_| sandbox_path.join(RESOLV_CONF_FILE));
+
+        // The containers join the namespaces of the infra process, which can only be created and
+        // pinned by privileged servers. Otherwise every container gets its own namespaces.
+        let pid_mode = namespace_options.map(|x| x.pid).unwrap_or_default();
+        let privileged = unistd::getuid().is_root();
+        let pinned = |namespace: Namespace, shared: bool| {
+            if privileged && shared {
+                Some(namespace.path(&sandbox_path))
+            } else {
+                None
+            }
+        };
+        let ipc_ns = pinned(Namespace::Ipc, !host_ipc);
+        let uts_ns = pinned(Namespace::Uts, !host_network);
+        let pid_ns = pinned(Namespace::Pid, pid_mode == NamespaceMode::Pod as i32);
+        let implementation = InfraSandbox::new(
+            self.config()
+                .infra_command()
+                .split_whitespace()
+                .map(Into::into)
+                .collect(),
+            sandbox_path,
+        );
+        let mut sandbox = SandboxBuilder::<InfraSandbox>::default()
             .data(
                 SandboxDataBuilder::default()
-                    .id(metadata.uid)
+                    .id(id)
+                    .uid(metadata.uid)
                     .name(metadata.name)
                     .namespace(metadata.namespace)
                     .attempt(metadata.attempt)
+                    .hostname(hostname)
+                    .domainname(domainname)
+                    .host_ipc(host_ipc)
+                    .host_network(host_network)
+                    .netns(netns)
+                    .ipc_ns(ipc_ns)
+                    .uts_ns(uts_ns)
+                    .pid_ns(pid_ns)
+                    .resolv_conf(resolv_conf_path.clone())
+                    .pid_mode(pid_mode)
+                    .created_at(created_at)
+                    .labels(config.labels)
+                    .annotations(config.annotations)
+                    .log_directory(config.log_directory)
+                    .runtime_handler(request.runtime_handler)
+                    // Mappings without host port only document the port, like in containerd
+                    .port_mappings(
+                        config
+                            .port_mappings
+                            .iter()
+                            .filter(|x| x.host_port > 0)
+                            .map(PortMapping::from)
+                            .collect::<Vec<_>>(),
+                    )
                     .build()
                     .map_err(|e| {
                         Status::internal(format!("build sandbox data from metadata: {}", e))
                     })?,
             )
+            .implementation(implementation)
             .build()
             .map_err(|e| Status::internal(format!("build sandbox from config: {}", e)))?;
 
         debug!("Created pod sandbox {:?}", sandbox);
 
-        // Run the sandbox
-        sandbox
-            .run()
-            .map_err(|e| Status::internal(format!("run pod sandbox: {}", e)))?;
+        // Cleanup any leftovers of a previously failed attempt
+        let tombstone_key = Tombstone::key(sandbox.id());
+        if let Some(tombstone) = storage
+            .get::<_, Tombstone>(&tombstone_key)
+            .map_err(|e| Status::internal(format!("get sandbox tombstone: {}", e)))?
+        {
+            info!(
+                "Cleaning up failed attempt {} of pod sandbox {}: {}",
+                tombstone.attempt(),
+                sandbox,
+                tombstone.reason()
+            );
+            sandbox
+                .rollback()
+                .map_err(|e| Status::internal(format!("cleanup failed pod sandbox: {}", e)))?;
+            storage
+                .remove(&tombstone_key)
+                .map_err(|e| Status::internal(format!("remove sandbox tombstone: {}", e)))?;
+        }
+        timeline.step("cleanup");
+
+        // Run the sandbox, write its resolv.conf, attach it to the network and roll it back on
+        // failure
+        let res = sandbox.run();
+        timeline.step("runtime");
+        let mut guard = RunGuard::new(storage.clone(), &sandbox, network.as_ref());
+        let res = match (res, &resolv_conf_path, &resolv_conf) {
+            (Ok(()), Some(path), Some(content)) => fs::write(path, content)
+                .with_context(|| format!("write resolv.conf {}", path.display())),
+            (res, _, _) => res,
+        };
+        let res = match res {
+            Ok(()) => {
+                self.attach_network(network.as_ref(), &sandbox, &mut timeline)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            guard.disarm();
+            if let Err(rollback_err) = sandbox.rollback() {
+                error!(
+                    "Unable to roll back pod sandbox {}: {}",
+                    sandbox, rollback_err
+                );
+            }
+            storage
+                .insert(&tombstone_key, Tombstone::new(attempt, e.to_string()))
+                .map_err(|e| Status::internal(format!("insert sandbox tombstone: {}", e)))?;
+            timeline.step("rollback");
+            self.observe_latency("RunPodSandbox", &sandbox.to_string(), &timeline);
+            return Err(ErrorDetails::new("run pod sandbox")
+                .hint("the failed attempt gets cleaned up on the next retry")
+                .status(Code::Internal, format!("{:#}", e)));
+        }
         info!("Started pod sandbox {}", sandbox);
+        self.prefetch_images(&mut storage, &prefetch_images(sandbox.data().annotations()));
+        sandbox::INDEX
+            .insert(&mut storage, sandbox.id(), &sandbox.data().index_entries())
+            .map_err(|e| Status::internal(format!("index pod sandbox: {:#}", e)))?;
+        storage
+            .insert(Sandbox::<InfraSandbox>::key(sandbox.id()), &sandbox)
+            .map_err(|e| Status::internal(format!("insert pod sandbox: {}", e)))?;
+        guard.disarm();
+        storage
+            .insert(
+                &idempotency_key,
+                IdempotencyRecord::new(sandbox.id().into()),
+            )
+            .map_err(|e| Status::internal(format!("insert idempotency record: {}", e)))?;
+        timeline.step("storage");
+        self.observe_latency("RunPodSandbox", &sandbox.to_string(), &timeline);
 
         // Build and return the response
         let reply = RunPodSandboxResponse {
@@ -53,17 +267,171 @@ impl CRIService {
         };
         Ok(Response::new(reply))
     }
+
+    /// Attach the running `sandbox` to the `network` and record its network status and the
+    /// durations of the steps in the `timeline`. Nothing happens if the sandbox does not have its
+    /// own network namespace.
+    async fn attach_network(
+        &self,
+        network: Option<&CniNetwork>,
+        sandbox: &Sandbox<InfraSandbox>,
+        timeline: &mut Timeline,
+    ) -> anyhow::Result<()> {
+        let (network, netns) = match (network, sandbox.data().netns()) {
+            (Some(network), Some(netns)) => (network, netns),
+            _ => return Ok(()),
+        };
+        let status = network::attach(network, sandbox.data(), netns, &[], timeline)
+            .await
+            .context("attach network")?;
+        if let Err(e) = self
+            .storage()
+            .clone()
+            .insert(NetworkStatus::key(sandbox.id()), &status)
+        {
+            let config = self.live_config().current();
+            if let Err(detach_err) =
+                network::detach(&status, sandbox.data(), config.cni_plugin_dirs()).await
+            {
+                error!(
+                    "Unable to detach network of pod sandbox {}: {:#}",
+                    sandbox, detach_err
+                );
+            }
+            return Err(e).context("insert network status");
+        }
+        Ok(())
+    }
+}
+
+/// RunGuard rolls back a started pod sandbox if its request gets cancelled before the sandbox has
+/// been stored, for example because the deadline of the request exceeded while attaching the
+/// network. Otherwise the infra process and the network namespace would leak.
+struct RunGuard<S: KeyValueStorage> {
+    /// The storage which records the tombstone of the rolled back sandbox.
+    storage: S,
+
+    /// The started sandbox, which is `None` if the guard got disarmed.
+    sandbox: Option<Sandbox<InfraSandbox>>,
+
+    /// The network the sandbox gets attached to.
+    network: Option<CniNetwork>,
+}
+
+impl<S: KeyValueStorage> RunGuard<S> {
+    /// Guard the started `sandbox`, which gets attached to the `network`.
+    fn new(storage: S, sandbox: &Sandbox<InfraSandbox>, network: Option<&CniNetwork>) -> Self {
+        Self {
+            storage,
+            sandbox: Some(sandbox.clone()),
+            network: network.cloned(),
+        }
+    }
+
+    /// Disarm the guard, because the sandbox got either stored or rolled back already.
+    fn disarm(&mut self) {
+        self.sandbox = None;
+    }
+}
+
+impl<S: KeyValueStorage> Drop for RunGuard<S> {
+    fn drop(&mut self) {
+        let mut sandbox = match self.sandbox.take() {
+            Some(sandbox) => sandbox,
+            None => return,
+        };
+        warn!("Rolling back pod sandbox {} of cancelled request", sandbox);
+        if let Err(e) = sandbox.rollback() {
+            error!("Unable to roll back pod sandbox {}: {}", sandbox, e);
+        }
+        if let Err(e) = self.storage.insert(
+            Tombstone::key(sandbox.id()),
+            Tombstone::new(*sandbox.data().attempt(), "request cancelled".into()),
+        ) {
+            error!(
+                "Unable to insert tombstone of pod sandbox {}: {}",
+                sandbox, e
+            );
+        }
+
+        // The plugins can only be run asynchronously. The network status gets stored right before
+        // the sandbox, so there is never a previous result to pass to them.
+        let (network, netns) = match (self.network.take(), sandbox.data().netns().clone()) {
+            (Some(network), Some(netns)) => (network, netns),
+            _ => return,
+        };
+        let data = sandbox.data().clone();
+        tokio::spawn(async move {
+            if let Err(e) = network.del(&data, &netns, None).await {
+                error!(
+                    "Unable to delete network of cancelled pod sandbox {}: {:#}",
+                    data.id(),
+                    e
+                );
+            }
+            if let Err(e) = netns::unpin(&netns) {
+                error!(
+                    "Unable to remove network namespace {}: {:#}",
+                    netns.display(),
+                    e
+                );
+            }
+        });
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
     use crate::{
-        cri_service::tests::new_cri_service,
-        criapi::{runtime_service_server::RuntimeService, PodSandboxConfig, PodSandboxMetadata},
+        admission::tests::RejectAll,
+        cri_service::tests::{
+            new_cri_service, new_cri_service_with_admission, new_cri_service_with_config,
+            test_config,
+        },
+        criapi::{
+            runtime_service_server::RuntimeService, DnsConfig, LinuxPodSandboxConfig,
+            LinuxSandboxSecurityContext, NamespaceOption, PodSandboxConfig, PodSandboxMetadata,
+        },
+        network::cni::tests::{fake_plugin, fake_plugin_log},
+        sandbox::dns::NAMESERVERS_ANNOTATION,
     };
-    use anyhow::Result;
-    use std::collections::HashMap;
+    use anyhow::{Context, Result};
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+    use tempfile::tempdir;
+    use tokio::time;
+    use tonic::Code;
+
+    /// Run a new pod sandbox and return its ID.
+    pub async fn new_pod_sandbox(sut: &CRIService) -> Result<String> {
+        Ok(sut
+            .run_pod_sandbox(Request::new(new_run_pod_sandbox_request("123", 0)))
+            .await?
+            .into_inner()
+            .pod_sandbox_id)
+    }
+
+    /// Create a new request for running the sandbox of the pod `uid` with the `attempt`.
+    pub fn new_run_pod_sandbox_request(uid: &str, attempt: u32) -> RunPodSandboxRequest {
+        RunPodSandboxRequest {
+            config: Some(PodSandboxConfig {
+                metadata: Some(PodSandboxMetadata {
+                    name: "name".into(),
+                    uid: uid.into(),
+                    namespace: "namespace".into(),
+                    attempt,
+                }),
+                hostname: "".into(),
+                log_directory: "".into(),
+                dns_config: None,
+                port_mappings: vec![],
+                labels: HashMap::new(),
+                annotations: HashMap::new(),
+                linux: None,
+            }),
+            runtime_handler: "".into(),
+        }
+    }
 
     #[tokio::test]
     async fn run_pod_sandbox_success() -> Result<()> {
@@ -88,7 +456,142 @@ mod tests {
             runtime_handler: "".into(),
         };
         let response = sut.run_pod_sandbox(Request::new(request)).await?;
-        assert_eq!(response.get_ref().pod_sandbox_id, test_id);
+        let id = SandboxData::new_id(test_id, 0);
+        assert_eq!(response.get_ref().pod_sandbox_id, id);
+        let record = sut
+            .storage()
+            .clone()
+            .get::<_, IdempotencyRecord>(IdempotencyRecord::pod_sandbox_key(test_id, 0))?;
+        assert_eq!(record, Some(IdempotencyRecord::new(id.clone())));
+
+        let mut sandbox = sut
+            .storage()
+            .clone()
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(&id))?
+            .context("sandbox not stored")?;
+        assert_eq!(sandbox.data().uid(), test_id);
+        assert!(*sandbox.data().created_at() > 0);
+        assert!(sandbox.ready()?);
+        sandbox.remove()
+    }
+
+    #[tokio::test]
+    async fn run_pod_sandbox_success_resolv_conf() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .dns_options(vec!["ndots:2".parse()?])
+                .build()?,
+        )?;
+        let mut request = new_run_pod_sandbox_request("123", 0);
+        if let Some(config) = request.config.as_mut() {
+            config.dns_config = Some(DnsConfig {
+                servers: vec!["10.0.0.10".into()],
+                searches: vec!["cluster.local".into()],
+                options: vec![],
+            });
+            config
+                .annotations
+                .insert(NAMESERVERS_ANNOTATION.into(), "10.0.0.11".into());
+        }
+        let id = sut
+            .run_pod_sandbox(Request::new(request))
+            .await?
+            .into_inner()
+            .pod_sandbox_id;
+
+        let sandbox = sut
+            .storage()
+            .clone()
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(&id))?
+            .context("sandbox not stored")?;
+        let path = sut.config().sandbox_path().join(&id).join(RESOLV_CONF_FILE);
+        assert_eq!(sandbox.data().resolv_conf().as_ref(), Some(&path));
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "nameserver 10.0.0.10\n\
+             nameserver 10.0.0.11\n\
+             search cluster.local\n\
+             options ndots:2\n"
+        );
+
+        // Invalid DNS annotations reject the sandbox
+        let mut request = new_run_pod_sandbox_request("456", 0);
+        if let Some(config) = request.config.as_mut() {
+            config.dns_config = Some(DnsConfig::default());
+            config
+                .annotations
+                .insert(NAMESERVERS_ANNOTATION.into(), "invalid".into());
+        }
+        let response = sut.run_pod_sandbox(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_pod_sandbox_success_cleanup_tombstone() -> Result<()> {
+        let sut = new_cri_service()?;
+        let test_id = "123";
+        let id = SandboxData::new_id(test_id, 1);
+        let key = Tombstone::key(&id);
+        sut.storage()
+            .clone()
+            .insert(&key, Tombstone::new(1, "failure".into()))?;
+
+        let request = RunPodSandboxRequest {
+            config: Some(PodSandboxConfig {
+                metadata: Some(PodSandboxMetadata {
+                    name: "".into(),
+                    uid: test_id.into(),
+                    namespace: "".into(),
+                    attempt: 1,
+                }),
+                hostname: "".into(),
+                log_directory: "".into(),
+                dns_config: None,
+                port_mappings: vec![],
+                labels: HashMap::new(),
+                annotations: HashMap::new(),
+                linux: None,
+            }),
+            runtime_handler: "".into(),
+        };
+        let response = sut.run_pod_sandbox(Request::new(request)).await?;
+        assert_eq!(response.get_ref().pod_sandbox_id, id);
+        assert!(sut.storage().clone().get::<_, Tombstone>(&key)?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_pod_sandbox_success_idempotent() -> Result<()> {
+        let sut = new_cri_service()?;
+        let key = IdempotencyRecord::pod_sandbox_key("123", 0);
+        sut.storage()
+            .clone()
+            .insert(&key, IdempotencyRecord::new("existing".into()))?;
+
+        let request = RunPodSandboxRequest {
+            config: Some(PodSandboxConfig {
+                metadata: Some(PodSandboxMetadata {
+                    name: "".into(),
+                    uid: "123".into(),
+                    namespace: "".into(),
+                    attempt: 0,
+                }),
+                hostname: "".into(),
+                log_directory: "".into(),
+                dns_config: None,
+                port_mappings: vec![],
+                labels: HashMap::new(),
+                annotations: HashMap::new(),
+                linux: None,
+            }),
+            runtime_handler: "".into(),
+        };
+        let response = sut.run_pod_sandbox(Request::new(request)).await?;
+        assert_eq!(response.get_ref().pod_sandbox_id, "existing");
         Ok(())
     }
 
@@ -104,6 +607,85 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn run_pod_sandbox_fail_rejected() -> Result<()> {
+        let sut = new_cri_service_with_admission(Arc::new(RejectAll))?;
+        let request = RunPodSandboxRequest {
+            config: None,
+            runtime_handler: "".into(),
+        };
+        let response = sut.run_pod_sandbox(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::PermissionDenied)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_pod_sandbox_fail_invalid_hostname() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = RunPodSandboxRequest {
+            config: Some(PodSandboxConfig {
+                metadata: Some(PodSandboxMetadata {
+                    name: "".into(),
+                    uid: "123".into(),
+                    namespace: "".into(),
+                    attempt: 0,
+                }),
+                hostname: "-invalid".into(),
+                log_directory: "".into(),
+                dns_config: None,
+                port_mappings: vec![],
+                labels: HashMap::new(),
+                annotations: HashMap::new(),
+                linux: None,
+            }),
+            runtime_handler: "".into(),
+        };
+        let response = sut.run_pod_sandbox(Request::new(request)).await;
+        assert!(response.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_pod_sandbox_fail_invalid_ipc_namespace() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = RunPodSandboxRequest {
+            config: Some(PodSandboxConfig {
+                metadata: Some(PodSandboxMetadata {
+                    name: "".into(),
+                    uid: "123".into(),
+                    namespace: "".into(),
+                    attempt: 0,
+                }),
+                hostname: "".into(),
+                log_directory: "".into(),
+                dns_config: None,
+                port_mappings: vec![],
+                labels: HashMap::new(),
+                annotations: HashMap::new(),
+                linux: Some(LinuxPodSandboxConfig {
+                    security_context: Some(LinuxSandboxSecurityContext {
+                        namespace_options: Some(NamespaceOption {
+                            ipc: NamespaceMode::Container as i32,
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+            }),
+            runtime_handler: "".into(),
+        };
+        let response = sut.run_pod_sandbox(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn run_pod_sandbox_fail_no_config_metadata() -> Result<()> {
         let sut = new_cri_service()?;
@@ -124,4 +706,123 @@ mod tests {
         assert!(response.is_err());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn run_pod_sandbox_fail_quota_exceeded() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .namespace_quotas(vec!["namespace=1:0".parse()?])
+                .build()?,
+        )?;
+        sut.run_pod_sandbox(Request::new(new_run_pod_sandbox_request("123", 0)))
+            .await?;
+
+        // Retries of the existing sandbox are not rejected
+        sut.run_pod_sandbox(Request::new(new_run_pod_sandbox_request("123", 0)))
+            .await?;
+        let response = sut
+            .run_pod_sandbox(Request::new(new_run_pod_sandbox_request("456", 0)))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::ResourceExhausted)
+        );
+        let metrics = sut.metrics().render(1, 0);
+        assert!(
+            metrics.contains("{scope=\"namespace\",name=\"namespace\",kind=\"pod_sandbox\"} 1"),
+            "{}",
+            metrics
+        );
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn run_pod_sandbox_fail_quota_exceeded_concurrently() -> Result<()> {
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .namespace_quotas(vec!["namespace=2:0".parse()?])
+                .build()?,
+        )?;
+        let handles: Vec<_> = (0..8)
+            .map(|uid| {
+                let sut = sut.clone();
+                tokio::spawn(async move {
+                    sut.run_pod_sandbox(Request::new(new_run_pod_sandbox_request(
+                        &uid.to_string(),
+                        0,
+                    )))
+                    .await
+                })
+            })
+            .collect();
+
+        // Concurrent creations can not exceed the quota together
+        let mut codes = vec![];
+        for handle in handles {
+            codes.push(handle.await?.err().map(|x| x.code()));
+        }
+        assert_eq!(codes.iter().filter(|x| x.is_none()).count(), 2);
+        assert!(codes
+            .iter()
+            .flatten()
+            .all(|x| *x == Code::ResourceExhausted));
+
+        // Rejected creations do not keep their reservation
+        let mut storage = sut.storage().clone();
+        let sandboxes = storage
+            .scan_prefix::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key_prefix())?;
+        let sandbox = sandboxes.first().context("no sandbox stored")?;
+        storage.remove(Sandbox::<InfraSandbox>::key(sandbox.id()))?;
+        sut.run_pod_sandbox(Request::new(new_run_pod_sandbox_request("new", 0)))
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_pod_sandbox_fail_cancelled_rollback() -> Result<()> {
+        let dir = tempdir()?;
+        fake_plugin(dir.path())?;
+        let sut = new_cri_service_with_config(
+            test_config()?
+                .cni_plugin_dirs(vec![dir.path().into()])
+                .netns_path(dir.path().join("netns"))
+                .method_timeouts(vec!["RunPodSandbox=1".parse()?])
+                .build()?,
+        )?;
+        fs::write(
+            sut.config().cni_config_dir().join("10-test.conf"),
+            r#"{"cniVersion": "0.4.0", "name": "test", "type": "fake", "mode": "hang"}"#,
+        )?;
+
+        // The deadline exceeds while the plugin attaches the sandbox
+        let response = sut
+            .run_pod_sandbox(Request::new(new_run_pod_sandbox_request("123", 0)))
+            .await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::DeadlineExceeded)
+        );
+
+        let id = SandboxData::new_id("123", 0);
+        let mut storage = sut.storage().clone();
+        assert!(storage
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(&id))?
+            .is_none());
+        assert!(storage.get::<_, Tombstone>(Tombstone::key(&id))?.is_some());
+        assert!(!sut.config().sandbox_path().join(&id).exists());
+
+        // The network gets deleted in the background
+        let netns = dir.path().join("netns").join(&id);
+        for _ in 0..50 {
+            if !netns.exists() {
+                break;
+            }
+            time::delay_for(Duration::from_millis(100)).await;
+        }
+        assert!(!netns.exists());
+        assert!(fake_plugin_log(dir.path())?
+            .iter()
+            .any(|x| x.starts_with(&format!("DEL {}", id))));
+        Ok(())
+    }
 }
@@ -1,15 +1,158 @@
 use crate::{
+    container::{Container, ContainerState},
     cri_service::CRIService,
     criapi::{StartContainerRequest, StartContainerResponse},
+    oci::runtime::{error_status, OciRuntime},
+    storage::KeyValueStorage,
 };
+use log::info;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_start_container(
         &self,
-        _request: Request<StartContainerRequest>,
+        request: Request<StartContainerRequest>,
     ) -> Result<Response<StartContainerResponse>, Status> {
+        let id = request.into_inner().container_id;
+        let key = Container::key(&id);
+
+        let mut storage = self.storage().clone();
+        let mut container = storage
+            .get::<_, Container>(&key)
+            .map_err(|e| Status::internal(format!("get container: {}", e)))?
+            .ok_or_else(|| Status::not_found(format!("container {} not found", id)))?;
+
+        // Only created containers can be started, because the OCI runtime runs them only once
+        if container.state() != ContainerState::Created {
+            return Err(Status::failed_precondition(format!(
+                "container {} is in state {:?}",
+                container,
+                container.state()
+            )));
+        }
+
+        OciRuntime::new(self.config().oci_runtime())
+            .start(&id)
+            .await
+            .map_err(|e| error_status("start container", e))?;
+        container.set_running(self.unix_nanos()?);
+        storage
+            .insert(&key, &container)
+            .map_err(|e| Status::internal(format!("update container: {}", e)))?;
+        info!("Started container {}", container);
+
         let resp = StartContainerResponse {};
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::tests::FakeClock,
+        cri_service::tests::{
+            new_cri_service_with_clock, new_cri_service_with_runtime, test_config,
+        },
+        criapi::runtime_service_server::RuntimeService,
+        oci::runtime::tests::{fake_runtime, fake_runtime_log},
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::{Context, Result};
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn start_container_success_timestamps() -> Result<()> {
+        let dir = tempdir()?;
+        let clock = FakeClock::default();
+        let sut = new_cri_service_with_clock(
+            test_config()?
+                .oci_runtime(fake_runtime(dir.path(), "running")?)
+                .build()?,
+            &clock,
+        )?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+
+        clock.advance(Duration::from_secs(3));
+        let request = StartContainerRequest {
+            container_id: id.clone(),
+        };
+        sut.start_container(Request::new(request)).await?;
+
+        let container = sut
+            .storage()
+            .clone()
+            .get::<_, Container>(Container::key(&id))?
+            .context("container not stored")?;
+        assert_eq!(
+            container.started_at() - container.created_at(),
+            3_000_000_000
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn start_container_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "running")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+
+        let request = StartContainerRequest {
+            container_id: id.clone(),
+        };
+        sut.start_container(Request::new(request)).await?;
+
+        let container = sut
+            .storage()
+            .clone()
+            .get::<_, Container>(Container::key(&id))?
+            .context("container not stored")?;
+        assert_eq!(container.state(), ContainerState::Running);
+        assert!(container.started_at() > 0);
+        assert!(fake_runtime_log(dir.path())?.contains(&format!("start {}", id)));
+
+        // Containers can not be started twice
+        let request = StartContainerRequest { container_id: id };
+        let response = sut.start_container(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::FailedPrecondition)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn start_container_fail_not_found() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "running")?;
+        let request = StartContainerRequest {
+            container_id: "unknown".into(),
+        };
+        let response = sut.start_container(Request::new(request)).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+}
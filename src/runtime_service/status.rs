@@ -1,19 +1,377 @@
 use crate::{
     cri_service::CRIService,
-    criapi::{StatusRequest, StatusResponse},
+    criapi::{RuntimeCondition, RuntimeStatus, StatusRequest, StatusResponse},
+    feature::Feature,
+    network::cni::{CniNetwork, PluginInfo},
+    oci::runtime::OciRuntime,
+    storage::KeyValueStorage,
+};
+use anyhow::{bail, Context, Result};
+use clap::crate_version;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
 };
-use std::collections::HashMap;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+/// The condition type reporting whether containers can be run.
+const RUNTIME_READY: &str = "RuntimeReady";
+
+/// The condition type reporting whether pods can be attached to the network.
+const NETWORK_READY: &str = "NetworkReady";
+
+/// The condition reason if the storage or the OCI runtime is not usable.
+const REASON_RUNTIME_NOT_READY: &str = "RuntimeNotReady";
+
+/// The condition reason if no usable CNI network is configured.
+const REASON_NETWORK_NOT_READY: &str = "NetworkPluginNotReady";
+
+/// The storage key read to verify that the storage is usable.
+const STORAGE_PROBE_KEY: &str = "status";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+/// The versions reported on verbose requests.
+struct VersionInfo<'a> {
+    /// The version of the server.
+    version: &'a str,
+
+    /// The version reported by the OCI runtime, if it is usable.
+    oci_runtime: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+/// The CNI setup reported on verbose requests.
+struct CniInfo<'a> {
+    /// The directory the network configuration is loaded from.
+    config_dir: &'a Path,
+
+    /// The directories searched for the plugin binaries.
+    plugin_dirs: &'a [PathBuf],
+
+    /// The loaded network, if any.
+    network: Option<NetworkInfo<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+/// A loaded CNI network reported on verbose requests.
+struct NetworkInfo<'a> {
+    /// The name of the network.
+    name: &'a str,
+
+    /// The CNI specification version of the configuration.
+    cni_version: &'a str,
+
+    /// The plugins of the network in execution order.
+    plugins: Vec<PluginInfo>,
+}
+
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_status(
         &self,
-        _request: Request<StatusRequest>,
+        request: Request<StatusRequest>,
     ) -> Result<Response<StatusResponse>, Status> {
+        let config = self.live_config().current();
+        let runtime_version = self.runtime_version().await;
+        let network = CniNetwork::load(config.cni_config_dir(), config.cni_plugin_dirs());
+
+        // Custom health checks are reported in addition to the built-in conditions
+        let mut conditions = vec![
+            condition(RUNTIME_READY, REASON_RUNTIME_NOT_READY, &runtime_version),
+            condition(
+                NETWORK_READY,
+                REASON_NETWORK_NOT_READY,
+                &network_ready(&network),
+            ),
+        ];
+        conditions.extend(self.health().conditions().await);
+
+        let mut info = HashMap::new();
+
+        // Extra information is only allowed on verbose requests
+        if request.get_ref().verbose {
+            info.insert(
+                "features".into(),
+                json("features", &Feature::states(self.config().features()))?,
+            );
+            info.insert(
+                "tasks".into(),
+                json("task health", &self.supervisor().health())?,
+            );
+            info.insert(
+                "version".into(),
+                json(
+                    "version",
+                    &VersionInfo {
+                        version: crate_version!(),
+                        oci_runtime: runtime_version.as_deref().ok(),
+                    },
+                )?,
+            );
+            info.insert(
+                "storagePath".into(),
+                json("storage path", self.config().storage_path())?,
+            );
+            let network = network.as_ref().ok().and_then(Option::as_ref);
+            info.insert(
+                "cni".into(),
+                json(
+                    "CNI info",
+                    &CniInfo {
+                        config_dir: config.cni_config_dir(),
+                        plugin_dirs: config.cni_plugin_dirs(),
+                        network: network.map(|x| NetworkInfo {
+                            name: x.name(),
+                            cni_version: x.cni_version(),
+                            plugins: x.plugins(),
+                        }),
+                    },
+                )?,
+            );
+        }
+
         let resp = StatusResponse {
-            status: None,
-            info: HashMap::new(),
+            status: Some(RuntimeStatus { conditions }),
+            info,
         };
         Ok(Response::new(resp))
     }
+
+    /// Verify that the storage is readable and retrieve the version of the OCI runtime, which
+    /// fails if either of them is not usable.
+    async fn runtime_version(&self) -> Result<String> {
+        self.storage()
+            .clone()
+            .get::<_, String>(STORAGE_PROBE_KEY)
+            .context("read storage")?;
+        OciRuntime::new(self.config().oci_runtime())
+            .version()
+            .await
+            .context("retrieve OCI runtime version")
+    }
+}
+
+/// Verify that the CNI network has been loaded and all of its plugin binaries exist.
+fn network_ready(network: &Result<Option<CniNetwork>>) -> Result<String> {
+    let network = match network {
+        Ok(Some(network)) => network,
+        Ok(None) => bail!("no CNI network configured"),
+        Err(e) => bail!("load CNI network: {:#}", e),
+    };
+    let missing: Vec<_> = network
+        .plugins()
+        .into_iter()
+        .filter(|x| x.binary.is_none())
+        .map(|x| x.r#type)
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "CNI plugins {} of network {} not found",
+            missing.join(", "),
+            network.name()
+        )
+    }
+    Ok(format!("CNI network {} is ready", network.name()))
+}
+
+/// Build the condition `typ` from the `result` of its check, whereas failures are reported with
+/// the `reason`.
+fn condition(typ: &str, reason: &str, result: &Result<String>) -> RuntimeCondition {
+    let (status, reason, message) = match result {
+        Ok(message) => (true, "", message.clone()),
+        Err(e) => (false, reason, format!("{:#}", e)),
+    };
+    RuntimeCondition {
+        r#type: typ.into(),
+        status,
+        reason: reason.into(),
+        message,
+    }
+}
+
+/// Serialize the verbose info `value` described by `name` to JSON.
+fn json<T: Serialize + ?Sized>(name: &str, value: &T) -> Result<String, Status> {
+    serde_json::to_string(value).map_err(|e| Status::internal(format!("serialize {}: {}", name, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ConfigBuilder,
+        cri_service::tests::{new_cri_service, new_cri_service_with_config, test_config},
+        criapi::runtime_service_server::RuntimeService,
+        network::cni::tests::fake_plugin,
+        oci::runtime::tests::fake_runtime,
+    };
+    use std::{fs, time::Duration};
+    use tempfile::tempdir;
+    use tokio::{net::TcpListener, time};
+
+    #[tokio::test]
+    async fn status_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = StatusRequest { verbose: false };
+        let response = sut.status(Request::new(request)).await?;
+        assert!(response.get_ref().info.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn status_success_conditions() -> Result<()> {
+        let dir = tempdir()?;
+        fake_plugin(dir.path())?;
+        let config = test_config()?
+            .oci_runtime(fake_runtime(dir.path(), "running")?)
+            .cni_plugin_dirs(vec![dir.path().into()])
+            .build()?;
+        let sut = new_cri_service_with_config(config)?;
+        let request = StatusRequest { verbose: false };
+
+        let response = sut.status(Request::new(request.clone())).await?;
+        let status = response
+            .get_ref()
+            .status
+            .as_ref()
+            .context("status is none")?;
+        assert_eq!(status.conditions[0].r#type, RUNTIME_READY);
+        assert!(status.conditions[0].status);
+        assert_eq!(status.conditions[0].message, "fake version 1.0.0");
+        assert_eq!(status.conditions[1].r#type, NETWORK_READY);
+        assert!(!status.conditions[1].status);
+        assert_eq!(status.conditions[1].reason, REASON_NETWORK_NOT_READY);
+
+        fs::write(
+            sut.config().cni_config_dir().join("10-test.conf"),
+            r#"{"cniVersion": "0.4.0", "name": "test", "type": "fake"}"#,
+        )?;
+        let response = sut.status(Request::new(request)).await?;
+        let status = response
+            .get_ref()
+            .status
+            .as_ref()
+            .context("status is none")?;
+        assert!(status.conditions[1].status);
+        assert_eq!(status.conditions[1].message, "CNI network test is ready");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn status_fail_conditions() -> Result<()> {
+        let config = test_config()?
+            .oci_runtime(PathBuf::from("/bin/false"))
+            .build()?;
+        let sut = new_cri_service_with_config(config)?;
+        fs::write(
+            sut.config().cni_config_dir().join("10-test.conf"),
+            r#"{"cniVersion": "0.4.0", "name": "test", "type": "missing"}"#,
+        )?;
+        let request = StatusRequest { verbose: false };
+        let response = sut.status(Request::new(request)).await?;
+
+        let status = response
+            .get_ref()
+            .status
+            .as_ref()
+            .context("status is none")?;
+        assert!(!status.conditions[0].status);
+        assert_eq!(status.conditions[0].reason, REASON_RUNTIME_NOT_READY);
+        assert!(!status.conditions[1].status);
+        assert_eq!(
+            status.conditions[1].message,
+            "CNI plugins missing of network test not found"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn status_success_verbose_info() -> Result<()> {
+        let dir = tempdir()?;
+        let config = test_config()?
+            .oci_runtime(fake_runtime(dir.path(), "running")?)
+            .storage_path(dir.path().join("storage"))
+            .build()?;
+        let sut = new_cri_service_with_config(config)?;
+        fs::write(
+            sut.config().cni_config_dir().join("10-test.conf"),
+            r#"{"cniVersion": "0.4.0", "name": "test", "type": "bridge"}"#,
+        )?;
+        let request = StatusRequest { verbose: true };
+        let response = sut.status(Request::new(request)).await?;
+        let info = &response.get_ref().info;
+
+        let version = info.get("version").context("version info is none")?;
+        assert!(version.contains("\"ociRuntime\":\"fake version 1.0.0\""));
+        let storage_path = info.get("storagePath").context("storage path is none")?;
+        assert!(storage_path.ends_with("/storage\""));
+        let cni = info.get("cni").context("CNI info is none")?;
+        assert!(cni.contains("\"name\":\"test\""));
+        assert!(cni.contains("\"plugins\":[{\"type\":\"bridge\",\"binary\":null}]"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn status_success_health_checks() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let config = ConfigBuilder::default()
+            .health_checks(vec![format!(
+                "RegistryMirrorReachable=tcp:{}",
+                listener.local_addr()?
+            )
+            .parse()?])
+            .build()?;
+        let sut = new_cri_service_with_config(config)?;
+        let request = StatusRequest { verbose: false };
+        let response = sut.status(Request::new(request)).await?;
+
+        let status = response
+            .get_ref()
+            .status
+            .as_ref()
+            .context("status is none")?;
+        assert_eq!(status.conditions.len(), 3);
+        assert_eq!(status.conditions[2].r#type, "RegistryMirrorReachable");
+        assert!(status.conditions[2].status);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn status_success_verbose_features() -> Result<()> {
+        let config = ConfigBuilder::default()
+            .features(vec![Feature::Checkpointing])
+            .build()?;
+        let sut = new_cri_service_with_config(config)?;
+        let request = StatusRequest { verbose: true };
+        let response = sut.status(Request::new(request)).await?;
+
+        let features = response
+            .get_ref()
+            .info
+            .get("features")
+            .context("features info is none")?;
+        assert!(features.contains("\"checkpoint\":true"));
+        assert!(features.contains("\"nri\":false"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn status_success_verbose_tasks() -> Result<()> {
+        let sut = new_cri_service()?;
+        sut.supervisor().spawn("task", || async { Ok(()) });
+        time::delay_for(Duration::from_millis(10)).await;
+
+        let request = StatusRequest { verbose: true };
+        let response = sut.status(Request::new(request)).await?;
+
+        let tasks = response
+            .get_ref()
+            .info
+            .get("tasks")
+            .context("tasks info is none")?;
+        assert!(tasks.contains("\"task\":{\"state\":\"finished\""));
+        Ok(())
+    }
 }
@@ -1,15 +1,212 @@
 use crate::{
+    container::{
+        stop::{stop_signal, stop_timeout},
+        Container, ContainerState,
+    },
     cri_service::CRIService,
     criapi::{StopContainerRequest, StopContainerResponse},
+    oci::runtime::{error_status, OciRuntime},
+    storage::KeyValueStorage,
 };
+use log::{info, warn};
+use nix::sys::signal::Signal;
+use std::time::Duration;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+/// The time to wait for a container to exit after it got killed.
+const KILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_stop_container(
         &self,
-        _request: Request<StopContainerRequest>,
+        request: Request<StopContainerRequest>,
     ) -> Result<Response<StopContainerResponse>, Status> {
+        let request = request.into_inner();
+        let mut container = self.resolve_container(&request.container_id)?;
+        let key = Container::key(container.id());
+        let mut storage = self.storage().clone();
+
+        // Stopping an already stopped container is not an error
+        if container.state() == ContainerState::Exited {
+            return Ok(Response::new(StopContainerResponse {}));
+        }
+
+        // Ask the container to stop gracefully, and kill it after the grace period
+        let signal = stop_signal(
+            container.annotations(),
+            container.image_stop_signal().as_deref(),
+        )
+        .map_err(|e| Status::invalid_argument(format!("get stop signal: {}", e)))?;
+        let timeout = stop_timeout(
+            request.timeout,
+            Duration::from_secs(self.config().stop_timeout()),
+        );
+        let runtime = OciRuntime::new(self.config().oci_runtime());
+        runtime
+            .kill(container.id(), signal)
+            .await
+            .map_err(|e| error_status("stop container", e))?;
+        if !runtime
+            .wait_stopped(container.id(), timeout)
+            .await
+            .map_err(|e| error_status("wait for container", e))?
+        {
+            warn!(
+                "Container {} did not stop within {:?}, killing it",
+                container, timeout
+            );
+            runtime
+                .kill(container.id(), Signal::SIGKILL)
+                .await
+                .map_err(|e| error_status("kill container", e))?;
+            if !runtime
+                .wait_stopped(container.id(), KILL_TIMEOUT)
+                .await
+                .map_err(|e| error_status("wait for container", e))?
+            {
+                return Err(Status::internal(format!(
+                    "container {} did not stop after being killed",
+                    container
+                )));
+            }
+        }
+
+        container.set_exited(self.unix_nanos()?);
+        storage
+            .insert(&key, &container)
+            .map_err(|e| Status::internal(format!("update container: {}", e)))?;
+        info!("Stopped container {}", container);
+
         let resp = StopContainerResponse {};
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service_with_runtime,
+        criapi::runtime_service_server::RuntimeService,
+        oci::runtime::tests::fake_runtime_log,
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_image, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::{Context, Result};
+    use tempfile::tempdir;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn stop_container_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "stopped")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+
+        for _ in 0..2 {
+            let request = StopContainerRequest {
+                container_id: id.clone(),
+                timeout: 1,
+            };
+            sut.stop_container(Request::new(request)).await?;
+        }
+
+        let container = sut
+            .storage()
+            .clone()
+            .get::<_, Container>(Container::key(&id))?
+            .context("container not stored")?;
+        assert_eq!(container.state(), ContainerState::Exited);
+        assert!(container.finished_at() > 0);
+
+        // The second stop request must not signal the container again
+        let kills: Vec<String> = fake_runtime_log(dir.path())?
+            .into_iter()
+            .filter(|x| x.starts_with("kill"))
+            .collect();
+        assert_eq!(kills, vec![format!("kill {} 15", id)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stop_container_success_image_stop_signal() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "stopped")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_image(&sut, serde_json::json!({"StopSignal": "SIGQUIT"})).await?;
+        let id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+
+        let request = StopContainerRequest {
+            container_id: id.clone(),
+            timeout: 1,
+        };
+        sut.stop_container(Request::new(request)).await?;
+
+        let kills: Vec<String> = fake_runtime_log(dir.path())?
+            .into_iter()
+            .filter(|x| x.starts_with("kill"))
+            .collect();
+        assert_eq!(kills, vec![format!("kill {} 3", id)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stop_container_success_id_prefix() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "stopped")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let id = sut
+            .create_container(Request::new(new_create_container_request(
+                &sandbox_id,
+                "name",
+            )))
+            .await?
+            .into_inner()
+            .container_id;
+
+        let request = StopContainerRequest {
+            container_id: id[..8].into(),
+            timeout: 1,
+        };
+        sut.stop_container(Request::new(request)).await?;
+        let container = sut
+            .storage()
+            .clone()
+            .get::<_, Container>(Container::key(&id))?
+            .context("container not stored")?;
+        assert_eq!(container.state(), ContainerState::Exited);
+        assert!(fake_runtime_log(dir.path())?.contains(&format!("kill {} 15", id)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stop_container_fail_not_found() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "stopped")?;
+        let request = StopContainerRequest {
+            container_id: "unknown".into(),
+            timeout: 0,
+        };
+        let response = sut.stop_container(Request::new(request)).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+}
@@ -1,15 +1,106 @@
 use crate::{
     cri_service::CRIService,
     criapi::{StopPodSandboxRequest, StopPodSandboxResponse},
+    sandbox::{infra::InfraSandbox, Sandbox},
+    storage::KeyValueStorage,
 };
+use log::info;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_stop_pod_sandbox(
         &self,
-        _request: Request<StopPodSandboxRequest>,
+        request: Request<StopPodSandboxRequest>,
     ) -> Result<Response<StopPodSandboxResponse>, Status> {
+        let id = request.into_inner().pod_sandbox_id;
+        let key = Sandbox::<InfraSandbox>::key(&id);
+
+        // Stopping an unknown sandbox is not an error, because it may have been removed already
+        let mut storage = self.storage().clone();
+        let mut sandbox = match storage
+            .get::<_, Sandbox<InfraSandbox>>(&key)
+            .map_err(|e| Status::internal(format!("get pod sandbox: {}", e)))?
+        {
+            Some(sandbox) => sandbox,
+            None => {
+                info!("Pod sandbox {} not found, nothing to stop", id);
+                return Ok(Response::new(StopPodSandboxResponse {}));
+            }
+        };
+
+        // The network gets detached first, because its plugins may need the running sandbox
+        self.detach_network(sandbox.data()).await?;
+        sandbox
+            .stop()
+            .map_err(|e| Status::internal(format!("stop pod sandbox: {:#}", e)))?;
+        storage
+            .insert(&key, &sandbox)
+            .map_err(|e| Status::internal(format!("update pod sandbox: {}", e)))?;
+        info!("Stopped pod sandbox {}", sandbox);
+
         let reply = StopPodSandboxResponse {};
         Ok(Response::new(reply))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::new_cri_service, criapi::runtime_service_server::RuntimeService,
+        runtime_service::run_pod_sandbox::tests::new_run_pod_sandbox_request,
+    };
+    use anyhow::{Context, Result};
+
+    #[tokio::test]
+    async fn stop_pod_sandbox_success() -> Result<()> {
+        let sut = new_cri_service()?;
+        let id = sut
+            .run_pod_sandbox(Request::new(new_run_pod_sandbox_request("123", 0)))
+            .await?
+            .into_inner()
+            .pod_sandbox_id;
+
+        let request = StopPodSandboxRequest {
+            pod_sandbox_id: id.clone(),
+        };
+        sut.stop_pod_sandbox(Request::new(request)).await?;
+
+        let mut sandbox = sut
+            .storage()
+            .clone()
+            .get::<_, Sandbox<InfraSandbox>>(Sandbox::<InfraSandbox>::key(&id))?
+            .context("sandbox not stored")?;
+        assert!(!sandbox.ready()?);
+        assert!(sandbox.implementation().pid().is_none());
+        sandbox.remove()
+    }
+
+    #[tokio::test]
+    async fn stop_pod_sandbox_success_idempotent() -> Result<()> {
+        let sut = new_cri_service()?;
+        let id = sut
+            .run_pod_sandbox(Request::new(new_run_pod_sandbox_request("123", 0)))
+            .await?
+            .into_inner()
+            .pod_sandbox_id;
+
+        for _ in 0..2 {
+            let request = StopPodSandboxRequest {
+                pod_sandbox_id: id.clone(),
+            };
+            sut.stop_pod_sandbox(Request::new(request)).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stop_pod_sandbox_success_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = StopPodSandboxRequest {
+            pod_sandbox_id: "unknown".into(),
+        };
+        sut.stop_pod_sandbox(Request::new(request)).await?;
+        Ok(())
+    }
+}
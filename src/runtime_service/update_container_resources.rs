@@ -1,15 +1,150 @@
 use crate::{
+    container::Container,
     cri_service::CRIService,
     criapi::{UpdateContainerResourcesRequest, UpdateContainerResourcesResponse},
+    resources::{burst::cpu_burst, container_cgroup_path, DefaultResourceManager, ResourceManager},
+    storage::KeyValueStorage,
 };
+use log::{info, warn};
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_update_container_resources(
         &self,
-        _request: Request<UpdateContainerResourcesRequest>,
+        request: Request<UpdateContainerResourcesRequest>,
     ) -> Result<Response<UpdateContainerResourcesResponse>, Status> {
+        let request = request.into_inner();
+        let resources = request
+            .linux
+            .ok_or_else(|| Status::invalid_argument("no linux resources provided"))?;
+        let key = Container::key(&request.container_id);
+
+        let mut storage = self.storage().clone();
+        let mut container = storage
+            .get::<_, Container>(&key)
+            .map_err(|e| Status::internal(format!("get container: {}", e)))?
+            .ok_or_else(|| {
+                Status::not_found(format!("container {} not found", request.container_id))
+            })?;
+
+        // The burst follows the new CPU quota
+        let burst = cpu_burst(
+            container.annotations(),
+            self.config().cpu_burst(),
+            &resources,
+        )
+        .map_err(|e| Status::invalid_argument(format!("get CPU burst: {:#}", e)))?;
+
+        // The kernel may accept the new resources only partially, which has to be reported in
+        // the container status to let the kubelet know that the resize is not complete
+        let cgroup_path = container_cgroup_path(container.pod_sandbox_id(), container.id());
+        let manager = DefaultResourceManager::default();
+        manager
+            .update(&cgroup_path, &resources)
+            .map_err(|e| Status::internal(format!("update resources: {:#}", e)))?;
+        let mut degradations = manager
+            .verify(&cgroup_path, &resources)
+            .map_err(|e| Status::internal(format!("verify resources: {:#}", e)))?;
+        if let Some(burst) = burst {
+            if let Err(e) = manager.set_cpu_burst(&cgroup_path, burst) {
+                degradations.push(format!("cpu.max.burst is not available: {:#}", e));
+            }
+        }
+        if degradations.is_empty() {
+            info!("Updated resources of container {}", container);
+        } else {
+            warn!(
+                "Updated resources of container {} with degradations: {}",
+                container,
+                degradations.join(", ")
+            );
+        }
+
+        container.set_resource_degradations(degradations);
+        storage
+            .insert(&key, &container)
+            .map_err(|e| Status::internal(format!("update container: {}", e)))?;
+
         let resp = UpdateContainerResourcesResponse {};
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::{new_cri_service, new_cri_service_with_runtime},
+        criapi::{runtime_service_server::RuntimeService, LinuxContainerResources},
+        resources::burst::CPU_BURST_ANNOTATION,
+        runtime_service::{
+            create_container::tests::{new_create_container_request, new_test_image},
+            run_pod_sandbox::tests::new_pod_sandbox,
+        },
+    };
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn update_container_resources_fail_no_resources() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = UpdateContainerResourcesRequest {
+            container_id: "unknown".into(),
+            linux: None,
+        };
+        let response = sut.update_container_resources(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_container_resources_fail_cpu_burst() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_cri_service_with_runtime(dir.path(), "created")?;
+        let sandbox_id = new_pod_sandbox(&sut).await?;
+        new_test_image(&sut).await?;
+        let mut request = new_create_container_request(&sandbox_id, "name");
+        if let Some(config) = request.config.as_mut() {
+            config
+                .annotations
+                .insert(CPU_BURST_ANNOTATION.into(), "50000".into());
+        }
+        let id = sut
+            .create_container(Request::new(request))
+            .await?
+            .into_inner()
+            .container_id;
+
+        // The requested burst exceeds the new quota
+        let request = UpdateContainerResourcesRequest {
+            container_id: id,
+            linux: Some(LinuxContainerResources {
+                cpu_quota: 10_000,
+                cpu_period: 100_000,
+                ..Default::default()
+            }),
+        };
+        let response = sut.update_container_resources(Request::new(request)).await;
+        assert_eq!(
+            response.err().map(|x| x.code()),
+            Some(Code::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_container_resources_fail_not_found() -> Result<()> {
+        let sut = new_cri_service()?;
+        let request = UpdateContainerResourcesRequest {
+            container_id: "unknown".into(),
+            linux: Some(LinuxContainerResources::default()),
+        };
+        let response = sut.update_container_resources(Request::new(request)).await;
+        assert_eq!(response.err().map(|x| x.code()), Some(Code::NotFound));
+        Ok(())
+    }
+}
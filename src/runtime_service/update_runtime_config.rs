@@ -1,15 +1,138 @@
 use crate::{
     cri_service::CRIService,
     criapi::{UpdateRuntimeConfigRequest, UpdateRuntimeConfigResponse},
+    network::template,
+    storage::KeyValueStorage,
 };
+use log::{debug, info};
+use std::fs;
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_update_runtime_config(
         &self,
-        _request: Request<UpdateRuntimeConfigRequest>,
+        request: Request<UpdateRuntimeConfigRequest>,
     ) -> Result<Response<UpdateRuntimeConfigResponse>, Status> {
         let resp = UpdateRuntimeConfigResponse {};
+
+        // An empty pod CIDR has to be ignored
+        let pod_cidr = request
+            .get_ref()
+            .runtime_config
+            .as_ref()
+            .and_then(|x| x.network_config.as_ref())
+            .map(|x| x.pod_cidr.as_str())
+            .unwrap_or_default();
+        if pod_cidr.is_empty() {
+            return Ok(Response::new(resp));
+        }
+        let path = match self.config().cni_config_template() {
+            Some(path) => path,
+            None => {
+                debug!(
+                    "Ignoring pod CIDR {}, no CNI config template configured",
+                    pod_cidr
+                );
+                return Ok(Response::new(resp));
+            }
+        };
+
+        let cidrs = template::parse_pod_cidrs(pod_cidr)
+            .map_err(|e| Status::invalid_argument(format!("invalid pod CIDR: {:#}", e)))?;
+        let template = fs::read_to_string(path).map_err(|e| {
+            Status::internal(format!(
+                "read CNI config template {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        let config = template::render(&template, &cidrs)
+            .map_err(|e| Status::internal(format!("render CNI config: {:#}", e)))?;
+        let config_dir = self.live_config().current().cni_config_dir().clone();
+        if template::write(&config_dir, &config)
+            .map_err(|e| Status::internal(format!("update CNI config: {:#}", e)))?
+        {
+            info!("Updated CNI config for pod CIDR {}", pod_cidr);
+        }
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cri_service::tests::{new_cri_service, new_cri_service_with_config, test_config},
+        criapi::{runtime_service_server::RuntimeService, NetworkConfig, RuntimeConfig},
+        network::cni::CniNetwork,
+    };
+    use anyhow::{Context, Result};
+    use tempfile::tempdir;
+    use tonic::Code;
+
+    const TEMPLATE: &str = r#"{
+      "cniVersion": "0.4.0",
+      "name": "cri",
+      "type": "bridge",
+      "ipam": {"type": "host-local", "subnet": "{{.PodCIDR}}"}
+    }"#;
+
+    fn request(pod_cidr: &str) -> Request<UpdateRuntimeConfigRequest> {
+        Request::new(UpdateRuntimeConfigRequest {
+            runtime_config: Some(RuntimeConfig {
+                network_config: Some(NetworkConfig {
+                    pod_cidr: pod_cidr.into(),
+                }),
+            }),
+        })
+    }
+
+    #[tokio::test]
+    async fn update_runtime_config_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("template.conflist");
+        fs::write(&path, TEMPLATE)?;
+        let sut =
+            new_cri_service_with_config(test_config()?.cni_config_template(Some(path)).build()?)?;
+        let config_dir = sut.config().cni_config_dir();
+
+        sut.update_runtime_config(request("10.1.0.0/24")).await?;
+        let network = CniNetwork::load(config_dir, &[])?.context("no network")?;
+        assert!(network.config().contains(r#""subnet": "10.1.0.0/24""#));
+
+        sut.update_runtime_config(request("10.2.0.0/24")).await?;
+        let network = CniNetwork::load(config_dir, &[])?.context("no network")?;
+        assert!(network.config().contains(r#""subnet": "10.2.0.0/24""#));
+
+        // An empty pod CIDR keeps the current config
+        sut.update_runtime_config(request("")).await?;
+        let network = CniNetwork::load(config_dir, &[])?.context("no network")?;
+        assert!(network.config().contains("10.2.0.0/24"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_runtime_config_success_without_template() -> Result<()> {
+        let sut = new_cri_service()?;
+        sut.update_runtime_config(request("10.1.0.0/24")).await?;
+        assert!(CniNetwork::load(sut.config().cni_config_dir(), &[])?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_runtime_config_fail_invalid_cidr() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("template.conflist");
+        fs::write(&path, TEMPLATE)?;
+        let sut =
+            new_cri_service_with_config(test_config()?.cni_config_template(Some(path)).build()?)?;
+
+        let status = sut
+            .update_runtime_config(request("10.1.0.0"))
+            .await
+            .err()
+            .context("no error")?;
+        assert_eq!(status.code(), Code::InvalidArgument);
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
 use crate::{
     cri_service::CRIService,
     criapi::{VersionRequest, VersionResponse},
+    storage::KeyValueStorage,
 };
 use tonic::{Request, Response, Status};
 
-impl CRIService {
+impl<S: KeyValueStorage> CRIService<S> {
     pub async fn handle_version(
         &self,
         _request: Request<VersionRequest>,
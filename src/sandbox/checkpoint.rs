@@ -0,0 +1,79 @@
+//! Checkpoints of pod sandboxes, which keep their containers across node maintenance.
+
+use crate::network::NetworkStatus;
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The storage key prefix of all checkpoints.
+const KEY_PREFIX: &str = "sandbox-checkpoint/";
+
+#[derive(Clone, CopyGetters, Debug, Deserialize, Getters, PartialEq, Serialize)]
+/// A Checkpoint records the containers of a pod sandbox which got checkpointed by the OCI
+/// runtime, together with the network state of the sandbox at that time.
+pub struct Checkpoint {
+    #[get = "pub"]
+    /// The identifiers of the checkpointed containers.
+    containers: Vec<String>,
+
+    #[get = "pub"]
+    /// The network status of the sandbox, if it is attached to a network.
+    network: Option<NetworkStatus>,
+
+    #[get_copy = "pub"]
+    /// The time of the checkpoint in nanoseconds since the Unix epoch.
+    created_at: i64,
+}
+
+impl Checkpoint {
+    /// Create a new checkpoint without containers for the `network` of the sandbox.
+    pub fn new(network: Option<NetworkStatus>, created_at: i64) -> Self {
+        Self {
+            containers: vec![],
+            network,
+            created_at,
+        }
+    }
+
+    /// Retrieve the storage key for the checkpoint of the sandbox `id`.
+    pub fn key(id: &str) -> String {
+        format!("{}{}", KEY_PREFIX, id)
+    }
+
+    /// Retrieve the storage key prefix of all checkpoints.
+    pub fn key_prefix() -> &'static str {
+        KEY_PREFIX
+    }
+
+    /// Retrieve the directory below the checkpoint `path` containing the checkpoint images of
+    /// the containers of the sandbox `id`.
+    pub fn dir(path: &Path, id: &str) -> PathBuf {
+        path.join(id)
+    }
+
+    /// Record that the container `id` has been checkpointed.
+    pub fn add_container(&mut self, id: &str) {
+        self.containers.push(id.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key() {
+        let key = Checkpoint::key("id");
+        assert!(key.starts_with(KEY_PREFIX));
+        assert!(key.ends_with("id"));
+    }
+
+    #[test]
+    fn add_container() {
+        let mut checkpoint = Checkpoint::new(None, 1);
+        checkpoint.add_container("a");
+        checkpoint.add_container("b");
+        assert_eq!(checkpoint.containers(), &["a", "b"]);
+        assert_eq!(checkpoint.created_at(), 1);
+    }
+}
@@ -0,0 +1,286 @@
+//! Generation of the resolv.conf of pod sandboxes.
+
+use crate::criapi::DnsConfig;
+use anyhow::{bail, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, net::IpAddr, str::FromStr};
+
+/// The annotation which can be used to append nameservers to the DNS config of the kubelet. The
+/// value is a comma separated list of IP addresses.
+pub const NAMESERVERS_ANNOTATION: &str = "io.kubernetes.cri.dns-nameservers";
+
+/// The annotation which can be used to append search domains to the DNS config of the kubelet.
+/// The value is a comma separated list of domains.
+pub const SEARCHES_ANNOTATION: &str = "io.kubernetes.cri.dns-searches";
+
+/// The file name of the generated resolv.conf inside the sandbox directory.
+pub const RESOLV_CONF_FILE: &str = "resolv.conf";
+
+/// The path of the resolv.conf inside of containers.
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// The maximum number of nameservers supported by the libc resolver.
+const MAX_NAMESERVERS: usize = 3;
+
+/// The maximum number of search domains supported by older libc resolvers.
+const MAX_SEARCHES: usize = 6;
+
+/// The maximum `ndots` value respected by the libc resolver.
+const MAX_NDOTS: u8 = 15;
+
+/// The maximum `timeout` value in seconds respected by the libc resolver.
+const MAX_TIMEOUT: u8 = 30;
+
+/// The maximum `attempts` value respected by the libc resolver.
+const MAX_ATTEMPTS: u8 = 5;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+/// DnsOption is a resolver option applied to pods whose DNS config does not set it.
+pub enum DnsOption {
+    /// The number of dots a name needs to be looked up as absolute name first.
+    Ndots(u8),
+
+    /// The time in seconds to wait for a response of a nameserver.
+    Timeout(u8),
+
+    /// The number of queries sent to all nameservers before giving up.
+    Attempts(u8),
+
+    /// Use TCP instead of UDP for all queries.
+    UseVc,
+}
+
+impl DnsOption {
+    /// Retrieve the name of the option, which identifies it independent of its value.
+    fn name(&self) -> &'static str {
+        match self {
+            DnsOption::Ndots(_) => "ndots",
+            DnsOption::Timeout(_) => "timeout",
+            DnsOption::Attempts(_) => "attempts",
+            DnsOption::UseVc => "use-vc",
+        }
+    }
+}
+
+impl FromStr for DnsOption {
+    type Err = Error;
+
+    /// Parse an option in the resolv.conf format, like `ndots:2` or `use-vc`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, ':');
+        let (name, value) = (parts.next().unwrap_or_default(), parts.next());
+        let parse = |max: u8| -> Result<u8> {
+            let value = value
+                .context("missing value")?
+                .parse()
+                .context("parse value")?;
+            if value > max {
+                bail!("value {} exceeds maximum of {}", value, max)
+            }
+            Ok(value)
+        };
+        let option = match (name, value) {
+            ("ndots", _) => DnsOption::Ndots(parse(MAX_NDOTS)?),
+            ("timeout", _) => DnsOption::Timeout(parse(MAX_TIMEOUT)?),
+            ("attempts", _) => DnsOption::Attempts(parse(MAX_ATTEMPTS)?),
+            ("use-vc", None) => DnsOption::UseVc,
+            _ => bail!(
+                "unsupported DNS option {}, expected ndots:N, timeout:N, attempts:N or use-vc",
+                s
+            ),
+        };
+        Ok(option)
+    }
+}
+
+impl fmt::Display for DnsOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsOption::Ndots(x) | DnsOption::Timeout(x) | DnsOption::Attempts(x) => {
+                write!(f, "{}:{}", self.name(), x)
+            }
+            DnsOption::UseVc => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+/// Generate the content of the resolv.conf from the `dns_config` of the kubelet, whereas
+/// additional nameservers and search domains can be appended via `annotations`. Duplicate
+/// entries are removed and the result is truncated to the limits of the resolver. The `defaults`
+/// are added to the options unless the kubelet sets an option of the same name.
+pub fn resolv_conf(
+    dns_config: Option<&DnsConfig>,
+    annotations: &HashMap<String, String>,
+    defaults: &[DnsOption],
+) -> Result<String> {
+    let default = DnsConfig::default();
+    let dns_config = dns_config.unwrap_or(&default);
+
+    let mut nameservers = dns_config.servers.clone();
+    for server in list_annotation(annotations, NAMESERVERS_ANNOTATION) {
+        server
+            .parse::<IpAddr>()
+            .with_context(|| format!("invalid nameserver {}", server))?;
+        nameservers.push(server);
+    }
+
+    let mut searches = dns_config.searches.clone();
+    for search in list_annotation(annotations, SEARCHES_ANNOTATION) {
+        if search.chars().any(char::is_whitespace) {
+            bail!("invalid search domain {}", search)
+        }
+        searches.push(search);
+    }
+
+    let mut res = String::new();
+    for server in dedup(nameservers).iter().take(MAX_NAMESERVERS) {
+        res += &format!("nameserver {}\n", server);
+    }
+    let searches = dedup(searches);
+    if !searches.is_empty() {
+        let searches: Vec<&str> = searches
+            .iter()
+            .take(MAX_SEARCHES)
+            .map(AsRef::as_ref)
+            .collect();
+        res += &format!("search {}\n", searches.join(" "));
+    }
+    let mut options = dns_config.options.clone();
+    for default in defaults {
+        let set = options
+            .iter()
+            .any(|x| x.splitn(2, ':').next() == Some(default.name()));
+        if !set {
+            options.push(default.to_string());
+        }
+    }
+    if !options.is_empty() {
+        res += &format!("options {}\n", options.join(" "));
+    }
+    Ok(res)
+}
+
+/// Retrieve the comma separated entries of the annotation `key`.
+fn list_annotation(annotations: &HashMap<String, String>, key: &str) -> Vec<String> {
+    annotations
+        .get(key)
+        .map(|x| {
+            x.split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Remove duplicate entries while preserving their order.
+fn dedup(values: Vec<String>) -> Vec<String> {
+    let mut res: Vec<String> = vec![];
+    for value in values {
+        if !res.contains(&value) {
+            res.push(value)
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dns_config() -> DnsConfig {
+        DnsConfig {
+            servers: vec!["10.0.0.10".into()],
+            searches: vec!["default.svc.cluster.local".into(), "cluster.local".into()],
+            options: vec!["ndots:5".into()],
+        }
+    }
+
+    #[test]
+    fn resolv_conf_success() -> Result<()> {
+        let res = resolv_conf(Some(&dns_config()), &HashMap::new(), &[])?;
+        assert_eq!(
+            res,
+            "nameserver 10.0.0.10\n\
+             search default.svc.cluster.local cluster.local\n\
+             options ndots:5\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolv_conf_success_empty() -> Result<()> {
+        assert!(resolv_conf(None, &HashMap::new(), &[])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn resolv_conf_success_annotations() -> Result<()> {
+        let mut annotations = HashMap::new();
+        annotations.insert(
+            NAMESERVERS_ANNOTATION.into(),
+            "10.0.0.10, 192.168.0.1,fd00::1,8.8.8.8".into(),
+        );
+        annotations.insert(SEARCHES_ANNOTATION.into(), "corp.example.com".into());
+
+        let res = resolv_conf(Some(&dns_config()), &annotations, &[])?;
+        assert_eq!(
+            res,
+            "nameserver 10.0.0.10\n\
+             nameserver 192.168.0.1\n\
+             nameserver fd00::1\n\
+             search default.svc.cluster.local cluster.local corp.example.com\n\
+             options ndots:5\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolv_conf_success_default_options() -> Result<()> {
+        let defaults = vec![DnsOption::Ndots(2), DnsOption::Timeout(1), DnsOption::UseVc];
+        let res = resolv_conf(Some(&dns_config()), &HashMap::new(), &defaults)?;
+        assert!(
+            res.ends_with("options ndots:5 timeout:1 use-vc\n"),
+            "{}",
+            res
+        );
+
+        let res = resolv_conf(None, &HashMap::new(), &defaults)?;
+        assert_eq!(res, "options ndots:2 timeout:1 use-vc\n");
+        Ok(())
+    }
+
+    #[test]
+    fn dns_option_from_str_success() -> Result<()> {
+        for (input, expected) in vec![
+            ("ndots:2", DnsOption::Ndots(2)),
+            ("timeout:30", DnsOption::Timeout(30)),
+            ("attempts:1", DnsOption::Attempts(1)),
+            ("use-vc", DnsOption::UseVc),
+        ] {
+            let option: DnsOption = input.parse()?;
+            assert_eq!(option, expected);
+            assert_eq!(option.to_string(), input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dns_option_from_str_fail() {
+        for input in &["ndots", "ndots:16", "timeout:x", "use-vc:1", "rotate", ""] {
+            assert!(input.parse::<DnsOption>().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn resolv_conf_fail_invalid_annotations() {
+        let mut annotations = HashMap::new();
+        annotations.insert(NAMESERVERS_ANNOTATION.into(), "not-an-ip".into());
+        assert!(resolv_conf(None, &annotations, &[]).is_err());
+
+        let mut annotations = HashMap::new();
+        annotations.insert(SEARCHES_ANNOTATION.into(), "a b".into());
+        assert!(resolv_conf(None, &annotations, &[]).is_err());
+    }
+}
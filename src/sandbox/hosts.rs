@@ -0,0 +1,241 @@
+//! Generation of the hosts file of pod sandboxes.
+
+use anyhow::{bail, Context, Error, Result};
+use getset::{CopyGetters, Getters};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, net::IpAddr, str::FromStr};
+
+/// The annotation which can be used to add entries to the hosts file of a pod sandbox. The value
+/// is a comma separated list of `NAME=IP` entries.
+pub const EXTRA_HOSTS_ANNOTATION: &str = "io.kubernetes.cri.extra-hosts";
+
+/// The file name of the generated hosts file inside the container bundle.
+pub const HOSTS_FILE: &str = "hosts";
+
+/// The path of the hosts file inside of containers.
+pub const HOSTS_PATH: &str = "/etc/hosts";
+
+/// The hosts file used if the kubelet does not provide one.
+const DEFAULT_HOSTS: &str = "127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n";
+
+#[derive(Clone, CopyGetters, Debug, Deserialize, Eq, Getters, PartialEq, Serialize)]
+/// HostEntry is a single additional entry of the hosts file.
+pub struct HostEntry {
+    #[get = "pub"]
+    /// The host name to be resolved.
+    name: String,
+
+    #[get_copy = "pub"]
+    /// The IP address the name resolves to.
+    ip: IpAddr,
+}
+
+impl FromStr for HostEntry {
+    type Err = Error;
+
+    /// Parse a host entry in the format `NAME=IP`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+            (Some(name), Some(ip)) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+                Ok(Self {
+                    name: name.into(),
+                    ip: ip
+                        .parse()
+                        .with_context(|| format!("parse IP of host {}", name))?,
+                })
+            }
+            _ => bail!("invalid host entry {}, expected NAME=IP", s),
+        }
+    }
+}
+
+impl fmt::Display for HostEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\t{}", self.ip, self.name)
+    }
+}
+
+/// Merge the `extra_hosts` of the runtime config and the entries of the `annotations` into the
+/// hosts file provided by the kubelet, or into a default hosts file if the kubelet does not
+/// provide one. Returns `None` if no entries had to be added.
+///
+/// Entries of the kubelet, like the pod IP and host aliases, always take precedence. A
+/// conflicting entry of the runtime config gets skipped with a warning, because it applies to
+/// every pod, whereas a conflicting entry of the annotations results in an error.
+pub fn hosts_file(
+    kubelet_hosts: Option<&str>,
+    extra_hosts: &[HostEntry],
+    annotations: &HashMap<String, String>,
+) -> Result<Option<String>> {
+    let base = kubelet_hosts.unwrap_or(DEFAULT_HOSTS);
+    let mut resolved = parse(base);
+
+    let mut added = vec![];
+    for entry in extra_hosts {
+        match resolved.get(entry.name()) {
+            Some(ip) if *ip == entry.ip() => {}
+            Some(ip) => warn!(
+                "Skipping extra host {}: {} already resolves to {}",
+                entry,
+                entry.name(),
+                ip
+            ),
+            None => {
+                resolved.insert(entry.name().clone(), entry.ip());
+                added.push(entry.clone());
+            }
+        }
+    }
+    for entry in annotation_entries(annotations)? {
+        match resolved.get(entry.name()) {
+            Some(ip) if *ip == entry.ip() => {}
+            Some(ip) => bail!(
+                "extra host {} conflicts with {} resolving to {}",
+                entry,
+                entry.name(),
+                ip
+            ),
+            None => {
+                resolved.insert(entry.name().clone(), entry.ip());
+                added.push(entry);
+            }
+        }
+    }
+
+    if added.is_empty() {
+        return Ok(None);
+    }
+    let mut res = base.to_string();
+    if !res.is_empty() && !res.ends_with('\n') {
+        res.push('\n');
+    }
+    res += "# Extra hosts added by the container runtime\n";
+    for entry in added {
+        res += &format!("{}\n", entry);
+    }
+    Ok(Some(res))
+}
+
+/// Retrieve the host entries of the `annotations`.
+fn annotation_entries(annotations: &HashMap<String, String>) -> Result<Vec<HostEntry>> {
+    annotations
+        .get(EXTRA_HOSTS_ANNOTATION)
+        .map(|x| {
+            x.split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(str::parse)
+                .collect()
+        })
+        .unwrap_or_else(|| Ok(vec![]))
+}
+
+/// Parse the names of the `hosts` file content and the IP addresses they resolve to. The first
+/// entry of a name wins, as it does for the resolver.
+fn parse(hosts: &str) -> HashMap<String, IpAddr> {
+    let mut res = HashMap::new();
+    for line in hosts.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let ip = match fields.next().and_then(|x| x.parse::<IpAddr>().ok()) {
+            Some(ip) => ip,
+            None => continue,
+        };
+        for name in fields {
+            res.entry(name.into()).or_insert(ip);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KUBELET_HOSTS: &str = "# Kubernetes-managed hosts file.\n\
+                                 127.0.0.1\tlocalhost\n\
+                                 10.1.0.5\tmy-pod\n\
+                                 # Entries added by HostAliases.\n\
+                                 10.2.0.1\tregistry.local";
+
+    #[test]
+    fn host_entry_from_str_success() -> Result<()> {
+        let entry: HostEntry = "registry.local=10.0.0.1".parse()?;
+        assert_eq!(entry.name(), "registry.local");
+        assert_eq!(entry.ip(), "10.0.0.1".parse::<IpAddr>()?);
+        assert_eq!(entry.to_string(), "10.0.0.1\tregistry.local");
+
+        let entry: HostEntry = "metadata = fd00::1".parse()?;
+        assert_eq!(entry.ip(), "fd00::1".parse::<IpAddr>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn host_entry_from_str_fail() {
+        assert!("registry.local".parse::<HostEntry>().is_err());
+        assert!("=10.0.0.1".parse::<HostEntry>().is_err());
+        assert!("a b=10.0.0.1".parse::<HostEntry>().is_err());
+        assert!("registry.local=invalid".parse::<HostEntry>().is_err());
+    }
+
+    #[test]
+    fn hosts_file_success_none() -> Result<()> {
+        assert!(hosts_file(Some(KUBELET_HOSTS), &[], &HashMap::new())?.is_none());
+
+        // Entries which already exist do not have to be added again
+        let extra = vec!["registry.local=10.2.0.1".parse()?];
+        assert!(hosts_file(Some(KUBELET_HOSTS), &extra, &HashMap::new())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn hosts_file_success() -> Result<()> {
+        let extra = vec![
+            "mirror.local=10.0.0.1".parse()?,
+            "registry.local=10.0.0.2".parse()?,
+        ];
+        let mut annotations = HashMap::new();
+        annotations.insert(
+            EXTRA_HOSTS_ANNOTATION.into(),
+            "metadata=169.254.169.254, mirror.local=10.0.0.1".into(),
+        );
+
+        let res = hosts_file(Some(KUBELET_HOSTS), &extra, &annotations)?;
+        assert_eq!(
+            res.as_deref(),
+            Some(
+                "# Kubernetes-managed hosts file.\n\
+                 127.0.0.1\tlocalhost\n\
+                 10.1.0.5\tmy-pod\n\
+                 # Entries added by HostAliases.\n\
+                 10.2.0.1\tregistry.local\n\
+                 # Extra hosts added by the container runtime\n\
+                 10.0.0.1\tmirror.local\n\
+                 169.254.169.254\tmetadata\n"
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hosts_file_success_default() -> Result<()> {
+        let extra = vec!["mirror.local=10.0.0.1".parse()?];
+        let res = hosts_file(None, &extra, &HashMap::new())?.unwrap_or_default();
+        assert!(res.starts_with(DEFAULT_HOSTS));
+        assert!(res.ends_with("10.0.0.1\tmirror.local\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn hosts_file_fail_annotation_conflict() {
+        let mut annotations = HashMap::new();
+        annotations.insert(EXTRA_HOSTS_ANNOTATION.into(), "my-pod=10.0.0.1".into());
+        assert!(hosts_file(Some(KUBELET_HOSTS), &[], &annotations).is_err());
+
+        let mut annotations = HashMap::new();
+        annotations.insert(EXTRA_HOSTS_ANNOTATION.into(), "invalid".into());
+        assert!(hosts_file(Some(KUBELET_HOSTS), &[], &annotations).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! A pod sandbox implementation which is backed by a long running infra process.
+
+use crate::sandbox::{namespaces, Pod, SandboxData};
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use log::debug;
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{kill, Signal},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// The file name of the infra process PID file inside the sandbox directory.
+pub const PID_FILE: &str = "infra.pid";
+
+#[derive(Clone, CopyGetters, Debug, Default, Deserialize, Getters, Serialize)]
+/// InfraSandbox keeps a pod sandbox alive by running an infra process, whose PID gets written
+/// into the sandbox directory. The sandbox is ready as long as the infra process is running.
+pub struct InfraSandbox {
+    #[get = "pub"]
+    /// The command line of the infra process.
+    command: Vec<String>,
+
+    #[get = "pub"]
+    /// The directory of the sandbox, which contains the PID file.
+    path: PathBuf,
+
+    #[get_copy = "pub"]
+    /// The PID of the infra process, which is `None` if the sandbox is not running.
+    pid: Option<u32>,
+}
+
+impl InfraSandbox {
+    /// Create a new infra sandbox running `command` inside the sandbox directory `path`.
+    pub fn new(command: Vec<String>, path: PathBuf) -> Self {
+        Self {
+            command,
+            path,
+            pid: None,
+        }
+    }
+
+    /// Retrieve the path to the PID file of the infra process.
+    pub fn pid_file(&self) -> PathBuf {
+        self.path.join(PID_FILE)
+    }
+
+    /// Wait for the exited infra process `pid` to avoid leaving a zombie behind. The process is
+    /// not our child if the server got restarted in the meantime, which is fine.
+    fn reap(pid: Pid) -> Result<()> {
+        match waitpid(pid, None) {
+            Ok(_) | Err(nix::Error::Sys(Errno::ECHILD)) => Ok(()),
+            Err(e) => Err(e).context("wait for infra process"),
+        }
+    }
+
+    /// Remove the file at `path` if it exists.
+    fn remove_file(path: &Path) -> Result<()> {
+        if path.exists() {
+            fs::remove_file(path).with_context(|| format!("remove {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Pod for InfraSandbox {
+    fn run(&mut self, sandbox: &SandboxData) -> Result<()> {
+        let (program, args) = match self.command.split_first() {
+            Some(x) => x,
+            None => bail!("no infra command provided"),
+        };
+        fs::create_dir_all(&self.path)
+            .with_context(|| format!("create sandbox directory {}", self.path.display()))?;
+
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .current_dir(&self.path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let child = namespaces::spawn(command, sandbox)
+            .with_context(|| format!("spawn infra process {}", program))?;
+        self.pid = Some(child.id());
+
+        fs::write(self.pid_file(), child.id().to_string())
+            .with_context(|| format!("write PID file {}", self.pid_file().display()))?;
+        debug!(
+            "Infra process {} of sandbox {} running",
+            child.id(),
+            sandbox.id()
+        );
+        Ok(())
+    }
+
+    fn stop(&mut self, sandbox: &SandboxData) -> Result<()> {
+        if let Some(pid) = self.pid.take() {
+            let pid = Pid::from_raw(pid as i32);
+            match kill(pid, Signal::SIGKILL) {
+                Ok(()) | Err(nix::Error::Sys(Errno::ESRCH)) => {}
+                Err(e) => {
+                    self.pid = Some(pid.as_raw() as u32);
+                    return Err(e).context("kill infra process");
+                }
+            }
+            Self::reap(pid)?;
+            debug!("Infra process {} of sandbox {} stopped", pid, sandbox.id());
+        }
+        namespaces::unpin(sandbox)?;
+        Self::remove_file(&self.pid_file())
+    }
+
+    fn remove(&mut self, sandbox: &SandboxData) -> Result<()> {
+        self.stop(sandbox)?;
+        if self.path.exists() {
+            fs::remove_dir_all(&self.path)
+                .with_context(|| format!("remove sandbox directory {}", self.path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn ready(&mut self, _: &SandboxData) -> Result<bool> {
+        let pid = match self.pid {
+            Some(pid) => Pid::from_raw(pid as i32),
+            None => return Ok(false),
+        };
+        match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) => Ok(true),
+            Ok(_) => {
+                self.pid = None;
+                Ok(false)
+            }
+            // The infra process is not our child if the server got restarted
+            Err(nix::Error::Sys(Errno::ECHILD)) => match kill(pid, None) {
+                Ok(()) => Ok(true),
+                Err(nix::Error::Sys(Errno::ESRCH)) => Ok(false),
+                Err(e) => Err(e).context("check infra process"),
+            },
+            Err(e) => Err(e).context("wait for infra process"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::SandboxDataBuilder;
+    use anyhow::format_err;
+    use std::{thread, time::Duration};
+    use tempfile::TempDir;
+
+    fn new_sandbox_data() -> Result<SandboxData> {
+        SandboxDataBuilder::default()
+            .id("id")
+            .name("name")
+            .namespace("namespace")
+            .attempt(0u32)
+            .build()
+            .map_err(|e| format_err!("build sandbox data: {}", e))
+    }
+
+    #[test]
+    fn run_stop_remove() -> Result<()> {
+        let dir = TempDir::new()?;
+        let data = new_sandbox_data()?;
+        let path = dir.path().join("id");
+        let mut sut = InfraSandbox::new(vec!["sleep".into(), "30".into()], path.clone());
+        assert!(!sut.ready(&data)?);
+
+        sut.run(&data)?;
+        assert!(sut.ready(&data)?);
+        let pid = sut.pid().context("no pid")?;
+        assert_eq!(fs::read_to_string(sut.pid_file())?, pid.to_string());
+
+        sut.stop(&data)?;
+        assert!(!sut.ready(&data)?);
+        assert!(sut.pid().is_none());
+        assert!(!sut.pid_file().exists());
+        assert!(path.exists());
+
+        sut.remove(&data)?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn ready_exited() -> Result<()> {
+        let dir = TempDir::new()?;
+        let data = new_sandbox_data()?;
+        let mut sut = InfraSandbox::new(vec!["true".into()], dir.path().join("id"));
+
+        sut.run(&data)?;
+        for _ in 0..100 {
+            if !sut.ready(&data)? {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!sut.ready(&data)?);
+        assert!(sut.pid().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn run_fail_no_command() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut sut = InfraSandbox::new(vec![], dir.path().join("id"));
+        assert!(sut.run(&new_sandbox_data()?).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Hints about the sequential start of init containers.
+//!
+//! The kubelet starts the init containers of a pod one after another and the app containers
+//! afterwards, but only tells the runtime about a container once its predecessor has finished.
+//! Pods can announce the whole sequence via an annotation, which allows fetching the image of the
+//! next container while the current one is still running.
+
+use anyhow::{bail, Result};
+use getset::Getters;
+use std::collections::HashMap;
+
+/// The annotation announcing the containers of a pod in the order they get started. The value is
+/// a comma separated list of `NAME=IMAGE` pairs, like
+/// `migrate=quay.io/app/migrate:1,app=quay.io/app/server:1`.
+pub const INIT_SEQUENCE_ANNOTATION: &str = "io.kubernetes.cri.init-sequence";
+
+#[derive(Clone, Debug, Getters, PartialEq)]
+/// A single container of the init sequence.
+pub struct InitStep {
+    #[get = "pub"]
+    /// The name of the container.
+    name: String,
+
+    #[get = "pub"]
+    /// The image of the container.
+    image: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// InitSequence is the announced start order of the containers of a pod.
+pub struct InitSequence {
+    /// The containers in their start order.
+    steps: Vec<InitStep>,
+}
+
+impl InitSequence {
+    /// Parse the sequence from the pod `annotations`. Returns `None` if it is not announced.
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Option<Self>> {
+        let value = match annotations.get(INIT_SEQUENCE_ANNOTATION) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let mut steps: Vec<InitStep> = vec![];
+        for entry in value.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let mut parts = entry.splitn(2, '=').map(str::trim);
+            let step = match (parts.next(), parts.next()) {
+                (Some(name), Some(image)) if !name.is_empty() && !image.is_empty() => InitStep {
+                    name: name.into(),
+                    image: image.into(),
+                },
+                _ => bail!("invalid init sequence entry {}", entry),
+            };
+            if steps.iter().any(|x| x.name == step.name) {
+                bail!("duplicate init sequence entry {}", step.name)
+            }
+            steps.push(step);
+        }
+        Ok(Some(Self { steps }))
+    }
+
+    /// Retrieve the step following the container `name`, which is `None` for the last or an
+    /// unknown container.
+    pub fn next(&self, name: &str) -> Option<&InitStep> {
+        let index = self.steps.iter().position(|x| x.name == name)?;
+        self.steps.get(index + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotations(value: &str) -> HashMap<String, String> {
+        vec![(INIT_SEQUENCE_ANNOTATION.to_string(), value.to_string())]
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn from_annotations_success() -> Result<()> {
+        assert_eq!(InitSequence::from_annotations(&HashMap::new())?, None);
+
+        let sut = InitSequence::from_annotations(&annotations(
+            "migrate=quay.io/app/migrate:1, warmup = quay.io/app/warmup:1,app=quay.io/app/server:1",
+        ))?
+        .unwrap_or_default();
+        assert_eq!(
+            sut.next("migrate").map(|x| x.image().as_str()),
+            Some("quay.io/app/warmup:1")
+        );
+        assert_eq!(sut.next("warmup").map(|x| x.name().as_str()), Some("app"));
+        assert_eq!(sut.next("app"), None);
+        assert_eq!(sut.next("unknown"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn from_annotations_fail() {
+        for value in &["migrate", "=quay.io/app/migrate:1", "a=b,a=c"] {
+            assert!(InitSequence::from_annotations(&annotations(value)).is_err());
+        }
+    }
+}
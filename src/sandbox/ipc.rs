@@ -0,0 +1,87 @@
+//! IPC namespace handling of pod sandboxes.
+
+use crate::{
+    criapi::NamespaceMode,
+    oci_spec::runtime::{Mount, MountBuilder},
+};
+use anyhow::{bail, format_err, Result};
+
+/// The path to the POSIX message queue filesystem.
+const MQUEUE_PATH: &str = "/dev/mqueue";
+
+/// Retrieve if the sandbox should use the IPC namespace of the host for the provided CRI
+/// namespace `mode`. Pods can only use their own or the host IPC namespace.
+pub fn host_ipc(mode: i32) -> Result<bool> {
+    if mode == NamespaceMode::Pod as i32 {
+        Ok(false)
+    } else if mode == NamespaceMode::Node as i32 {
+        Ok(true)
+    } else {
+        bail!("unsupported IPC namespace mode {}", mode)
+    }
+}
+
+/// Retrieve the `/dev/mqueue` mount for containers of a sandbox. Sandboxes sharing the host IPC
+/// namespace bind mount the message queues of the host, whereas all others get a new mqueue
+/// filesystem, which is shared by all containers joining the IPC namespace of the sandbox.
+pub fn mqueue_mount(host_ipc: bool) -> Result<Mount> {
+    let builder = MountBuilder::default().destination(MQUEUE_PATH);
+    let builder = if host_ipc {
+        builder.typ("bind").source(MQUEUE_PATH).options(vec![
+            "rbind".into(),
+            "nosuid".into(),
+            "noexec".into(),
+            "nodev".into(),
+        ])
+    } else {
+        builder.typ("mqueue").source("mqueue").options(vec![
+            "nosuid".into(),
+            "noexec".into(),
+            "nodev".into(),
+        ])
+    };
+    builder
+        .build()
+        .map_err(|e| format_err!("build mqueue mount: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn host_ipc_success() -> Result<()> {
+        assert!(!host_ipc(NamespaceMode::Pod as i32)?);
+        assert!(host_ipc(NamespaceMode::Node as i32)?);
+        Ok(())
+    }
+
+    #[test]
+    fn host_ipc_failure() {
+        assert!(host_ipc(NamespaceMode::Container as i32).is_err());
+        assert!(host_ipc(42).is_err());
+    }
+
+    #[test]
+    fn mqueue_mount_pod() -> Result<()> {
+        let mount = mqueue_mount(false)?;
+        assert_eq!(mount.destination(), &PathBuf::from(MQUEUE_PATH));
+        assert_eq!(mount.typ().as_deref(), Some("mqueue"));
+        assert_eq!(mount.source(), &Some(PathBuf::from("mqueue")));
+        Ok(())
+    }
+
+    #[test]
+    fn mqueue_mount_host() -> Result<()> {
+        let mount = mqueue_mount(true)?;
+        assert_eq!(mount.typ().as_deref(), Some("bind"));
+        assert_eq!(mount.source(), &Some(PathBuf::from(MQUEUE_PATH)));
+        assert!(mount
+            .options()
+            .as_ref()
+            .map(|x| x.contains(&"rbind".to_string()))
+            .unwrap_or_default());
+        Ok(())
+    }
+}
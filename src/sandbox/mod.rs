@@ -1,13 +1,41 @@
 //! Basic Pod Sandbox types
 
+pub mod checkpoint;
+pub mod dns;
+pub mod hosts;
+pub mod infra;
+pub mod init_sequence;
+pub mod ipc;
+pub mod namespaces;
 pub mod pinned;
-
-use anyhow::Result;
+pub mod prefetch;
+pub mod tombstone;
+pub mod uts;
+
+use crate::{
+    criapi::{PodSandboxMetadata, PortMapping as CriPortMapping, Protocol},
+    id::stable_id,
+    storage::index::{self, Entry, Index},
+};
+use anyhow::{format_err, Result};
 use derive_builder::Builder;
 use getset::Getters;
-use std::fmt;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, path::PathBuf};
+
+/// The storage key prefix of all sandboxes.
+const KEY_PREFIX: &str = "sandbox/";
+
+/// The secondary index of all sandboxes.
+pub const INDEX: Index = Index::new("sandbox");
+
+/// The index field of the Kubernetes UID of the pod.
+pub const UID_FIELD: &str = "uid";
 
-#[derive(Builder)]
+/// The index field of the namespace of the pod.
+pub const NAMESPACE_FIELD: &str = "namespace";
+
+#[derive(Builder, Clone, Deserialize, Serialize)]
 #[builder(pattern = "owned", setter(into))]
 /// This is the main data structure for a Pod Sandbox. The implementation `T` can vary and is being
 /// defined in the `Pod` trait. Responsibility of the `Sandbox` is to hold arbitrary necessary data
@@ -23,7 +51,7 @@ where
     implementation: T,
 }
 
-#[derive(Builder, Getters)]
+#[derive(Builder, Clone, Deserialize, Getters, Serialize)]
 #[builder(pattern = "owned", setter(into))]
 /// SandboxData holds all the data which will be passed around to the `Pod` trait, too.
 pub struct SandboxData {
@@ -31,6 +59,11 @@ pub struct SandboxData {
     /// The unique identifier.
     id: String,
 
+    #[get = "pub"]
+    #[builder(default)]
+    /// The Kubernetes UID of the pod.
+    uid: String,
+
     #[get = "pub"]
     /// Full name of the sandbox.
     name: String,
@@ -43,6 +76,156 @@ pub struct SandboxData {
     /// Sandbox creation attempt. It only changes if the Kubernetes sandbox data changed or dies
     /// because of any error, not if the sandbox creation itself fails.
     attempt: u32,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Hostname of the sandbox, which is `None` if the hostname of the UTS namespace should not be
+    /// modified.
+    hostname: Option<String>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Domain name of the sandbox, which is `None` if the domain name of the UTS namespace should
+    /// not be modified.
+    domainname: Option<String>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Whether the sandbox uses the IPC namespace of the host instead of its own one.
+    host_ipc: bool,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Whether the sandbox uses the network namespace of the host instead of its own one.
+    host_network: bool,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// The path of the pinned network namespace shared by all containers of the sandbox, which is
+    /// `None` if each container gets its own one.
+    netns: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// The path of the pinned IPC namespace shared by all containers of the sandbox, which is
+    /// `None` if they use the IPC namespace of the host or each container gets its own one.
+    ipc_ns: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// The path of the pinned UTS namespace shared by all containers of the sandbox, which is
+    /// `None` if they use the UTS namespace of the host or each container gets its own one.
+    uts_ns: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// The path of the pinned PID namespace shared by all containers of the sandbox, which is
+    /// `None` unless the pod shares its process namespace.
+    pid_ns: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// The generated resolv.conf shared by all containers of the sandbox, which is `None` if the
+    /// kubelet did not provide a DNS config.
+    resolv_conf: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// The raw CRI `NamespaceMode` of the PID namespace.
+    pid_mode: i32,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Creation time of the sandbox in nanoseconds since the Unix epoch.
+    created_at: i64,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Labels of the sandbox, which can be used to select it.
+    labels: HashMap<String, String>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Unstructured key value data of the sandbox.
+    annotations: HashMap<String, String>,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Directory on the host where the container log files of the sandbox are stored.
+    log_directory: String,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Name of the runtime handler requested for the sandbox.
+    runtime_handler: String,
+
+    #[get = "pub"]
+    #[builder(default)]
+    /// Ports of the sandbox which are exposed on the host by the CNI plugins.
+    port_mappings: Vec<PortMapping>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+/// PortMapping exposes a port of the sandbox on the host.
+pub struct PortMapping {
+    /// The lowercase protocol of the port, like `tcp`.
+    pub protocol: String,
+
+    /// The port inside of the sandbox.
+    pub container_port: i32,
+
+    /// The port on the host.
+    pub host_port: i32,
+
+    /// The IP address on the host, which is empty for all addresses.
+    pub host_ip: String,
+}
+
+impl From<&CriPortMapping> for PortMapping {
+    fn from(mapping: &CriPortMapping) -> Self {
+        let protocol = if mapping.protocol == Protocol::Udp as i32 {
+            "udp"
+        } else if mapping.protocol == Protocol::Sctp as i32 {
+            "sctp"
+        } else {
+            "tcp"
+        };
+        Self {
+            protocol: protocol.into(),
+            container_port: mapping.container_port,
+            host_port: mapping.host_port,
+            host_ip: mapping.host_ip.clone(),
+        }
+    }
+}
+
+impl SandboxData {
+    /// Generate the sandbox identifier for the pod `uid` and its `attempt`. The identifier is
+    /// stable, so that retries of the same attempt end up with the same sandbox, whereas a new
+    /// attempt never collides with the sandbox of a previous one.
+    pub fn new_id(uid: &str, attempt: u32) -> String {
+        stable_id(&[uid, &attempt.to_string()])
+    }
+
+    /// Retrieve the entries of the sandbox in the secondary `INDEX`.
+    pub fn index_entries(&self) -> Vec<Entry> {
+        let mut entries = vec![
+            (UID_FIELD, self.uid.clone()),
+            (NAMESPACE_FIELD, self.namespace.clone()),
+        ];
+        entries.extend(index::label_entries(&self.labels));
+        entries
+    }
+
+    /// Retrieve the CRI metadata of the sandbox.
+    pub fn metadata(&self) -> PodSandboxMetadata {
+        PodSandboxMetadata {
+            name: self.name.clone(),
+            uid: self.uid.clone(),
+            namespace: self.namespace.clone(),
+            attempt: self.attempt,
+        }
+    }
 }
 
 pub trait Pod {
@@ -78,24 +261,58 @@ where
         &self.data.id
     }
 
+    /// Retrieve the data of the sandbox
+    pub fn data(&self) -> &SandboxData {
+        &self.data
+    }
+
+    /// Retrieve the implementation of the sandbox
+    pub fn implementation(&self) -> &T {
+        &self.implementation
+    }
+
+    /// Retrieve the storage key for the sandbox `id`.
+    pub fn key(id: &str) -> String {
+        format!("{}{}", KEY_PREFIX, id)
+    }
+
+    /// Retrieve the storage key prefix of all sandboxes.
+    pub fn key_prefix() -> &'static str {
+        KEY_PREFIX
+    }
+
     /// Wrapper for the implementations `run` method
     pub fn run(&mut self) -> Result<()> {
         self.implementation.run(&self.data)
     }
 
-    #[allow(dead_code)]
     /// Wrapper for the implementations `stop` method
     pub fn stop(&mut self) -> Result<()> {
         self.implementation.stop(&self.data)
     }
 
-    #[allow(dead_code)]
     /// Wrapper for the implementations `remove` method
     pub fn remove(&mut self) -> Result<()> {
         self.implementation.remove(&self.data)
     }
 
-    #[allow(dead_code)]
+    /// Roll back a sandbox whose `run` failed by stopping and removing it. Both steps are always
+    /// executed, so that as many partially created resources as possible get cleaned up.
+    pub fn rollback(&mut self) -> Result<()> {
+        let stopped = self.stop();
+        let removed = self.remove();
+        match (stopped, removed) {
+            (Ok(()), Ok(())) => Ok(()),
+            (Err(e), Ok(())) => Err(e.context("stop sandbox")),
+            (Ok(()), Err(e)) => Err(e.context("remove sandbox")),
+            (Err(stop), Err(remove)) => Err(format_err!(
+                "stop sandbox: {}, remove sandbox: {}",
+                stop,
+                remove
+            )),
+        }
+    }
+
     /// Wrapper for the implementations `ready` method
     pub fn ready(&mut self) -> Result<bool> {
         self.implementation.ready(&self.data)
@@ -109,9 +326,15 @@ where
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Sandbox")
             .field("id", self.data.id())
+            .field("uid", self.data.uid())
             .field("name", self.data.name())
             .field("namespace", self.data.namespace())
             .field("attempt", self.data.attempt())
+            .field("hostname", self.data.hostname())
+            .field("domainname", self.data.domainname())
+            .field("host_ipc", self.data.host_ipc())
+            .field("host_network", self.data.host_network())
+            .field("runtime_handler", self.data.runtime_handler())
             .finish()
     }
 }
@@ -128,7 +351,7 @@ where
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use anyhow::format_err;
+    use anyhow::Context;
 
     #[derive(Default)]
     struct Mock {
@@ -166,6 +389,7 @@ pub mod tests {
                     .name("name")
                     .namespace("namespace")
                     .attempt(1u32)
+                    .hostname(Some("my-hostname".to_string()))
                     .build()
                     .map_err(|e| format_err!("build sandbox data: {}", e))?,
             )
@@ -183,9 +407,27 @@ pub mod tests {
         assert!(sandbox_debug.contains(sandbox.data.namespace()));
         assert!(sandbox_debug.contains(sandbox.data.id()));
         assert!(sandbox_debug.contains(&sandbox.data.attempt().to_string()));
+        assert!(sandbox_debug.contains("my-hostname"));
+        assert_eq!(sandbox.data.domainname(), &None);
         Ok(())
     }
 
+    #[test]
+    fn new_id() {
+        let id = SandboxData::new_id("uid", 0);
+        assert_eq!(id.len(), 16);
+        assert_eq!(id, SandboxData::new_id("uid", 0));
+        assert_ne!(id, SandboxData::new_id("uid", 1));
+        assert_ne!(id, SandboxData::new_id("uid0", 0));
+    }
+
+    #[test]
+    fn key() {
+        let key = Sandbox::<Mock>::key("id");
+        assert!(key.starts_with(Sandbox::<Mock>::key_prefix()));
+        assert!(key.ends_with("id"));
+    }
+
     #[test]
     fn create_custom_impl() -> Result<()> {
         let implementation = Mock::default();
@@ -223,4 +465,61 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[derive(Default)]
+    struct FailingMock {
+        remove_called: bool,
+    }
+    impl Pod for FailingMock {
+        fn stop(&mut self, _: &SandboxData) -> Result<()> {
+            Err(format_err!("stop failed"))
+        }
+        fn remove(&mut self, _: &SandboxData) -> Result<()> {
+            self.remove_called = true;
+            Err(format_err!("remove failed"))
+        }
+    }
+
+    #[test]
+    fn rollback_success() -> Result<()> {
+        let mut sandbox = SandboxBuilder::<Mock>::default()
+            .data(
+                SandboxDataBuilder::default()
+                    .id("id")
+                    .name("name")
+                    .namespace("namespace")
+                    .attempt(0u32)
+                    .build()
+                    .map_err(|e| format_err!("build sandbox data: {}", e))?,
+            )
+            .build()
+            .map_err(|e| format_err!("build sandbox: {}", e))?;
+
+        sandbox.rollback()?;
+        assert!(sandbox.implementation.stop_called);
+        assert!(sandbox.implementation.remove_called);
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_fail() -> Result<()> {
+        let mut sandbox = SandboxBuilder::<FailingMock>::default()
+            .data(
+                SandboxDataBuilder::default()
+                    .id("id")
+                    .name("name")
+                    .namespace("namespace")
+                    .attempt(0u32)
+                    .build()
+                    .map_err(|e| format_err!("build sandbox data: {}", e))?,
+            )
+            .build()
+            .map_err(|e| format_err!("build sandbox: {}", e))?;
+
+        let err = sandbox.rollback().err().context("rollback succeeded")?;
+        assert!(sandbox.implementation.remove_called);
+        assert!(err.to_string().contains("stop failed"));
+        assert!(err.to_string().contains("remove failed"));
+        Ok(())
+    }
 }
@@ -0,0 +1,230 @@
+//! Namespaces which are shared by all containers of a pod sandbox.
+//!
+//! The infra process of a sandbox gets spawned into new IPC and UTS namespaces and, if the pod
+//! shares its process namespace, into a new PID namespace, whose init process it becomes. The
+//! namespaces of the infra process are pinned by bind mounting them into the sandbox directory,
+//! which is where the containers of the sandbox join them. Orphaned processes of a shared PID
+//! namespace get reparented to the infra process, which should reap them like `pause` does.
+
+use crate::sandbox::SandboxData;
+use anyhow::{Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command},
+};
+
+#[cfg(target_os = "linux")]
+use crate::network::netns::is_mount_point;
+#[cfg(target_os = "linux")]
+use anyhow::format_err;
+#[cfg(target_os = "linux")]
+use nix::{
+    errno::Errno,
+    mount::{mount, umount2, MntFlags, MsFlags},
+    sched::{unshare, CloneFlags},
+};
+#[cfg(target_os = "linux")]
+use std::{fs, thread};
+
+#[cfg(not(target_os = "linux"))]
+use anyhow::bail;
+
+/// The directory inside of the sandbox directory containing the pinned namespaces.
+const NAMESPACES_DIR: &str = "ns";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Namespace is a namespace which the containers of a sandbox can share.
+pub enum Namespace {
+    /// The IPC namespace, which contains the message queues and shared memory segments.
+    Ipc,
+
+    /// The UTS namespace, which contains the hostname and the domain name.
+    Uts,
+
+    /// The PID namespace, which allows the containers to see the processes of each other.
+    Pid,
+}
+
+impl Namespace {
+    /// Retrieve the name of the namespace below `/proc/PID/ns`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Namespace::Ipc => "ipc",
+            Namespace::Uts => "uts",
+            Namespace::Pid => "pid",
+        }
+    }
+
+    /// Retrieve the path of the pinned namespace inside the sandbox directory `dir`.
+    pub fn path(self, dir: &Path) -> PathBuf {
+        dir.join(NAMESPACES_DIR).join(self.name())
+    }
+
+    #[cfg(target_os = "linux")]
+    /// Retrieve the flag which creates the namespace.
+    fn flag(self) -> CloneFlags {
+        match self {
+            Namespace::Ipc => CloneFlags::CLONE_NEWIPC,
+            Namespace::Uts => CloneFlags::CLONE_NEWUTS,
+            Namespace::Pid => CloneFlags::CLONE_NEWPID,
+        }
+    }
+}
+
+/// Retrieve the namespaces shared by the containers of the `sandbox` together with the paths
+/// they get pinned to.
+pub fn shared(sandbox: &SandboxData) -> Vec<(Namespace, &Path)> {
+    vec![
+        (Namespace::Ipc, sandbox.ipc_ns()),
+        (Namespace::Uts, sandbox.uts_ns()),
+        (Namespace::Pid, sandbox.pid_ns()),
+    ]
+    .into_iter()
+    .filter_map(|(namespace, path)| path.as_deref().map(|x| (namespace, x)))
+    .collect()
+}
+
+#[cfg(target_os = "linux")]
+/// Spawn the infra `command` of the `sandbox` into new namespaces for all shared ones, which get
+/// pinned afterwards. The infra process gets killed if pinning its namespaces fails.
+pub fn spawn(mut command: Command, sandbox: &SandboxData) -> Result<Child> {
+    let namespaces = shared(sandbox);
+    if namespaces.is_empty() {
+        return command.spawn().context("spawn process");
+    }
+    let flags = namespaces
+        .iter()
+        .fold(CloneFlags::empty(), |flags, (x, _)| flags | x.flag());
+
+    // Only the namespaces of the spawned thread change, which the process inherits
+    let mut child = thread::spawn(move || -> Result<Child> {
+        unshare(flags).context("unshare namespaces")?;
+        command.spawn().context("spawn process")
+    })
+    .join()
+    .map_err(|_| format_err!("thread spawning the process in new namespaces panicked"))
+    .and_then(|x| x)?;
+
+    let pinned = namespaces
+        .iter()
+        .try_for_each(|(namespace, path)| pin(child.id(), *namespace, path));
+    if let Err(e) = pinned {
+        child.kill().ok();
+        child.wait().ok();
+        unpin(sandbox).ok();
+        return Err(e);
+    }
+    Ok(child)
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Spawn the infra `command` of the `sandbox`, whereas sharing namespaces is only supported on
+/// Linux.
+pub fn spawn(mut command: Command, sandbox: &SandboxData) -> Result<Child> {
+    if let Some((namespace, _)) = shared(sandbox).first() {
+        bail!(
+            "unable to share the {} namespace: not supported on this platform",
+            namespace.name()
+        )
+    }
+    command.spawn().context("spawn process")
+}
+
+#[cfg(target_os = "linux")]
+/// Pin the `namespace` of the process `pid` by bind mounting it to the file at `path`.
+fn pin(pid: u32, namespace: Namespace, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("create namespace directory {}", parent.display()))?;
+    }
+    fs::File::create(path).with_context(|| format!("create namespace file {}", path.display()))?;
+    let source = format!("/proc/{}/ns/{}", pid, namespace.name());
+    mount(
+        Some(source.as_str()),
+        path,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .with_context(|| {
+        format!(
+            "bind mount {} namespace to {}",
+            namespace.name(),
+            path.display()
+        )
+    })
+}
+
+#[cfg(target_os = "linux")]
+/// Unmount and remove the pinned namespaces of the `sandbox`, if they exist.
+pub fn unpin(sandbox: &SandboxData) -> Result<()> {
+    for (namespace, path) in shared(sandbox) {
+        if !path.exists() {
+            continue;
+        }
+
+        // The file is not a mount point if pinning it failed half way
+        if is_mount_point(path)? {
+            match umount2(path, MntFlags::MNT_DETACH) {
+                Ok(()) | Err(nix::Error::Sys(Errno::EINVAL)) => {}
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("unmount {} namespace {}", namespace.name(), path.display())
+                    })
+                }
+            }
+        }
+        fs::remove_file(path)
+            .with_context(|| format!("remove namespace file {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Unpinning namespaces is a no-op, because they are only shared on Linux.
+pub fn unpin(_: &SandboxData) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::SandboxDataBuilder;
+    use anyhow::format_err;
+
+    #[test]
+    fn shared_success() -> Result<()> {
+        let dir = Path::new("/run/sandboxes/id");
+        let sandbox = SandboxDataBuilder::default()
+            .id("id")
+            .name("name")
+            .namespace("namespace")
+            .attempt(0u32)
+            .ipc_ns(Some(Namespace::Ipc.path(dir)))
+            .pid_ns(Some(Namespace::Pid.path(dir)))
+            .build()
+            .map_err(|e| format_err!("build sandbox data: {}", e))?;
+        assert_eq!(
+            shared(&sandbox),
+            vec![
+                (Namespace::Ipc, Path::new("/run/sandboxes/id/ns/ipc")),
+                (Namespace::Pid, Path::new("/run/sandboxes/id/ns/pid")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn spawn_success_not_shared() -> Result<()> {
+        let sandbox = SandboxDataBuilder::default()
+            .id("id")
+            .name("name")
+            .namespace("namespace")
+            .attempt(0u32)
+            .build()
+            .map_err(|e| format_err!("build sandbox data: {}", e))?;
+        let mut child = spawn(Command::new("true"), &sandbox)?;
+        assert!(child.wait()?.success());
+        unpin(&sandbox)
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::sandbox::Pod;
 
+#[allow(dead_code)]
 #[derive(Default)]
 pub struct PinnedSandbox {}
 
@@ -0,0 +1,49 @@
+//! Hints about images which the containers of a pod need later on.
+//!
+//! Pods can list the images of containers which get created only some time after the sandbox,
+//! like sidecars injected later or the containers of the next stage of a job. The images are
+//! pulled in the background once the sandbox runs, so that creating the containers does not have
+//! to wait for them.
+
+use std::collections::HashMap;
+
+/// The annotation listing the images to prefetch for a pod. The value is a comma separated list
+/// of image references, like `quay.io/app/sidecar:1,quay.io/app/stage:1`.
+pub const PREFETCH_IMAGES_ANNOTATION: &str = "io.kubernetes.cri.prefetch-images";
+
+/// Retrieve the images to prefetch from the pod `annotations` in their listed order, whereas
+/// duplicates are skipped.
+pub fn prefetch_images(annotations: &HashMap<String, String>) -> Vec<String> {
+    let mut images: Vec<String> = vec![];
+    let value = annotations
+        .get(PREFETCH_IMAGES_ANNOTATION)
+        .map(String::as_str)
+        .unwrap_or_default();
+    for image in value.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+        if !images.iter().any(|x| x == image) {
+            images.push(image.into());
+        }
+    }
+    images
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefetch_images_success() {
+        assert!(prefetch_images(&HashMap::new()).is_empty());
+
+        let annotations = vec![(
+            PREFETCH_IMAGES_ANNOTATION.to_string(),
+            "quay.io/app/sidecar:1, ,quay.io/app/stage:1,quay.io/app/sidecar:1".to_string(),
+        )]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            prefetch_images(&annotations),
+            vec!["quay.io/app/sidecar:1", "quay.io/app/stage:1"]
+        );
+    }
+}
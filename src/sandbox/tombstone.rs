@@ -0,0 +1,44 @@
+//! Tombstones of pod sandboxes whose creation failed.
+
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+
+/// The storage key prefix of all tombstones.
+const KEY_PREFIX: &str = "sandbox-tombstone/";
+
+#[derive(CopyGetters, Debug, Deserialize, Getters, PartialEq, Serialize)]
+/// A Tombstone records that running a sandbox failed and that it might have left partially
+/// created resources behind.
+pub struct Tombstone {
+    #[get_copy = "pub"]
+    /// Sandbox creation attempt which failed.
+    attempt: u32,
+
+    #[get = "pub"]
+    /// The reason why the sandbox creation failed.
+    reason: String,
+}
+
+impl Tombstone {
+    /// Create a new tombstone for the failed `attempt`.
+    pub fn new(attempt: u32, reason: String) -> Self {
+        Self { attempt, reason }
+    }
+
+    /// Retrieve the storage key for the tombstone of the sandbox `id`.
+    pub fn key(id: &str) -> String {
+        format!("{}{}", KEY_PREFIX, id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key() {
+        let key = Tombstone::key("id");
+        assert!(key.starts_with(KEY_PREFIX));
+        assert!(key.ends_with("id"));
+    }
+}
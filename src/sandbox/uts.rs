@@ -0,0 +1,88 @@
+//! UTS namespace handling for pod sandboxes.
+
+use anyhow::{bail, Result};
+
+/// The maximum length of a hostname or domain name supported by the kernel.
+const MAX_NAME_LEN: usize = 64;
+
+/// Retrieve the effective hostname and domain name for a sandbox. Sandboxes sharing the UTS
+/// namespace of the host must not modify the names, which is why both are `None` in that case.
+/// A fully qualified `hostname` is split into the hostname and the domain name, similar to other
+/// container runtimes.
+pub fn uts_names(hostname: &str, host_uts: bool) -> Result<(Option<String>, Option<String>)> {
+    if host_uts || hostname.is_empty() {
+        return Ok((None, None));
+    }
+
+    let mut parts = hostname.splitn(2, '.');
+    let name = parts.next().unwrap_or_default();
+    let domain = parts.next();
+
+    validate(name)?;
+    if let Some(domain) = domain {
+        validate(domain)?;
+    }
+
+    Ok((Some(name.into()), domain.map(Into::into)))
+}
+
+/// Validate that the provided `name` is a RFC 1123 compliant DNS name and fits into the kernel
+/// limits.
+fn validate(name: &str) -> Result<()> {
+    if name.len() > MAX_NAME_LEN {
+        bail!("name {} exceeds {} characters", name, MAX_NAME_LEN)
+    }
+
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|x| x.is_ascii_alphanumeric() || x == '-')
+    };
+    if !name.split('.').all(valid_label) {
+        bail!("name {} is not a valid RFC 1123 DNS name", name)
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uts_names_success() -> Result<()> {
+        assert_eq!(uts_names("my-pod", false)?, (Some("my-pod".into()), None));
+        Ok(())
+    }
+
+    #[test]
+    fn uts_names_success_fqdn() -> Result<()> {
+        assert_eq!(
+            uts_names("my-pod.sub.namespace", false)?,
+            (Some("my-pod".into()), Some("sub.namespace".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn uts_names_success_host_uts() -> Result<()> {
+        assert_eq!(uts_names("my-pod", true)?, (None, None));
+        assert_eq!(uts_names("-invalid", true)?, (None, None));
+        Ok(())
+    }
+
+    #[test]
+    fn uts_names_success_empty() -> Result<()> {
+        assert_eq!(uts_names("", false)?, (None, None));
+        Ok(())
+    }
+
+    #[test]
+    fn uts_names_fail_invalid() {
+        assert!(uts_names("-invalid", false).is_err());
+        assert!(uts_names("in_valid", false).is_err());
+        assert!(uts_names("pod..domain", false).is_err());
+        assert!(uts_names(&"a".repeat(MAX_NAME_LEN + 1), false).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+//! Serialization of mutating operations per pod
+//!
+//! The kubelet may issue conflicting requests for the same pod in rapid succession, like stopping
+//! a pod sandbox while its containers are still being created. Mutating operations are therefore
+//! queued per pod and run one after another in their order of arrival, whereas operations on
+//! different pods run in parallel.
+
+use log::debug;
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone, Default)]
+/// OperationQueue serializes operations per pod.
+pub struct OperationQueue {
+    /// The queues of all pods with pending or running operations by the pod sandbox ID.
+    pods: Arc<Mutex<HashMap<String, PodQueue>>>,
+}
+
+#[derive(Default)]
+/// The queue of a single pod.
+struct PodQueue {
+    /// The lock held by the running operation, which hands over to waiting operations in their
+    /// order of arrival.
+    lock: Arc<tokio::sync::Mutex<()>>,
+
+    /// The number of pending and running operations.
+    depth: usize,
+}
+
+/// DepthGuard removes an operation from the queue depth when being dropped, which also covers
+/// operations cancelled while waiting.
+struct DepthGuard<'a> {
+    /// The queue the operation belongs to.
+    queue: &'a OperationQueue,
+
+    /// The pod sandbox ID of the operation.
+    pod: &'a str,
+}
+
+impl Drop for DepthGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut pods) = self.queue.pods.lock() {
+            if let Some(queue) = pods.get_mut(self.pod) {
+                queue.depth -= 1;
+                if queue.depth == 0 {
+                    pods.remove(self.pod);
+                }
+            }
+        }
+    }
+}
+
+impl OperationQueue {
+    /// Run the operation `f` on the pod sandbox `pod` after all previously queued operations of
+    /// the pod have finished.
+    pub async fn run<F, T>(&self, pod: &str, f: F) -> T
+    where
+        F: Future<Output = T>,
+    {
+        let lock = self.pods.lock().ok().map(|mut pods| {
+            let queue = pods.entry(pod.into()).or_default();
+            queue.depth += 1;
+            if queue.depth > 1 {
+                debug!(
+                    "Queueing operation on pod sandbox {} behind {} others",
+                    pod,
+                    queue.depth - 1
+                );
+            }
+            queue.lock.clone()
+        });
+        let lock = match lock {
+            Some(lock) => lock,
+            // Operations are not serialized if the queue is unusable
+            None => return f.await,
+        };
+
+        let _depth = DepthGuard { queue: self, pod };
+        let _running = lock.lock().await;
+        f.await
+    }
+
+    /// Retrieve the number of pending and running operations of all pods with a non empty queue,
+    /// ordered by their pod sandbox ID.
+    pub fn depths(&self) -> BTreeMap<String, usize> {
+        self.pods
+            .lock()
+            .map(|x| x.iter().map(|(k, v)| (k.clone(), v.depth)).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::time::Duration;
+    use tokio::{
+        sync::{mpsc, oneshot},
+        task, time,
+    };
+
+    #[tokio::test]
+    async fn run_serializes_per_pod() -> Result<()> {
+        let sut = OperationQueue::default();
+        let (order, mut finished) = mpsc::unbounded_channel();
+        let (release, released) = oneshot::channel::<()>();
+
+        // The first operation blocks the pod until it gets released
+        let first = task::spawn({
+            let (sut, order) = (sut.clone(), order.clone());
+            async move {
+                sut.run("pod", async {
+                    released.await.ok();
+                    order.send("first").ok();
+                })
+                .await
+            }
+        });
+        time::delay_for(Duration::from_millis(10)).await;
+        let second = task::spawn({
+            let (sut, order) = (sut.clone(), order.clone());
+            async move { sut.run("pod", async { order.send("second").ok() }).await }
+        });
+        time::delay_for(Duration::from_millis(10)).await;
+
+        // Other pods are not blocked
+        sut.run("other", async { order.send("other").ok() }).await;
+        assert_eq!(sut.depths().get("pod"), Some(&2));
+        assert_eq!(sut.depths().get("other"), None);
+
+        release.send(()).ok();
+        first.await?;
+        second.await?;
+        drop(order);
+        let mut order = vec![];
+        while let Some(x) = finished.recv().await {
+            order.push(x);
+        }
+        assert_eq!(order, vec!["other", "first", "second"]);
+        assert!(sut.depths().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_cancelled_while_waiting() -> Result<()> {
+        let sut = OperationQueue::default();
+        let (release, released) = oneshot::channel::<()>();
+        let first = task::spawn({
+            let sut = sut.clone();
+            async move {
+                sut.run("pod", async {
+                    released.await.ok();
+                })
+                .await
+            }
+        });
+        time::delay_for(Duration::from_millis(10)).await;
+
+        let waiting = time::timeout(Duration::from_millis(10), sut.run("pod", async {})).await;
+        assert!(waiting.is_err());
+        assert_eq!(sut.depths().get("pod"), Some(&1));
+
+        release.send(()).ok();
+        first.await?;
+        assert!(sut.depths().is_empty());
+        Ok(())
+    }
+}
@@ -1,24 +1,69 @@
+#[cfg(target_os = "linux")]
+use crate::listener::vsock::VsockSocketListener;
 use crate::{
-    config::{Config, LogScope},
+    admin::AdminService,
+    adminapi::admin_server::AdminServer,
+    admission::{pod_security::PodSecurity, policy::Policies, AdmissionChain},
+    clock::SystemClock,
+    config::{Config, LogScope, PodSecurityLevel, StorageBackend, StorageRecovery},
     cri_service::CRIService,
     criapi::{
         image_service_server::ImageServiceServer, runtime_service_server::RuntimeServiceServer,
     },
-    storage::{default_key_value_storage::DefaultKeyValueStorage, KeyValueStorage},
-    unix_stream,
+    diagnostics::Dump,
+    feature::Feature,
+    image::{
+        fs_usage::ImageFsUsage,
+        gc::GcPolicy,
+        store::{ImageStore, Recovery},
+    },
+    listener::{
+        peers::Peers, tcp::TcpSocketListener, unix::UnixSocketListener, ListenAddress, Listener,
+    },
+    logging::Sink,
+    metrics,
+    network::leaks::LeakDetector,
+    reload::LiveConfig,
+    request_log::{LoggerHandle, ScopedLogger},
+    resources::{daemon, DefaultResourceManager},
+    storage::{
+        default_key_value_storage::DefaultKeyValueStorage, lock::StorageLock,
+        memory_key_value_storage::MemoryKeyValueStorage, KeyValueStorage,
+    },
+    streaming::StreamingServer,
+    supervisor::Supervisor,
+    systemd,
+    telemetry::Tracer,
 };
 use anyhow::{bail, Context, Result};
 use clap::crate_name;
-use futures_util::stream::TryStreamExt;
-use log::{debug, info};
-use std::env;
-#[cfg(unix)]
-use tokio::net::UnixListener;
-use tokio::{
-    fs,
-    signal::unix::{signal, SignalKind},
+use futures_util::future::{self, FutureExt};
+use log::{debug, error, info, warn};
+use std::{
+    env,
+    fs::Permissions,
+    future::Future,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
-use tonic::{transport, Request, Status};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::{sync::oneshot, task, time};
+use tonic::transport::{self, Certificate, Identity, ServerTlsConfig};
+
+/// The permissions of the admin socket, which allow its owner and group to connect.
+const ADMIN_SOCK_MODE: u32 = 0o660;
+
+/// The interval of checking the image filesystem for disk pressure.
+const IMAGE_GC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The interval of the garbage collection of exited containers.
+const CONTAINER_GC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The interval of the garbage collection of unreferenced artifacts.
+const ARTIFACT_GC_INTERVAL: Duration = Duration::from_secs(300);
 
 /// Server is the main instance to run the Container Runtime Interface
 pub struct Server {
@@ -33,94 +78,691 @@ impl Server {
 
     /// Start a new server with its default values
     pub async fn start(self) -> Result<()> {
-        self.set_logging_verbosity()
+        let logs = self
+            .set_logging_verbosity()
             .context("set logging verbosity")?;
 
+        match self.config.storage_backend() {
+            StorageBackend::Sled => {
+                self.start_with_storage::<DefaultKeyValueStorage>(logs)
+                    .await
+            }
+            StorageBackend::Memory => {
+                warn!("Using in-memory storage, all state gets lost on restart");
+                self.start_with_storage::<MemoryKeyValueStorage>(logs).await
+            }
+        }
+    }
+
+    /// Start the server using the storage implementation `S`, whereas `logs` controls the
+    /// initialized logger.
+    async fn start_with_storage<S: KeyValueStorage>(self, logs: LoggerHandle) -> Result<()> {
+        // Fail early if the host does not allow us to write where we have to
+        self.verify_writable_paths()?;
+
+        // Lock the storage to prevent other server instances from using it, which has to happen
+        // before a second instance changes the limits of the cgroup of the running one
+        let _storage_lock = StorageLock::acquire(self.config.storage_path())?;
+
+        // Prevent the server from starving workloads or being killed first on OOM
+        daemon::confine(&self.config, &DefaultResourceManager::default())
+            .context("confine server process")?;
+        self.recover_images()?;
+
         // Setup the storage and pass it to the service
-        let storage = DefaultKeyValueStorage::open(&self.config.storage_path())?;
-        let cri_service = CRIService::new(storage.clone());
+        let storage = self.open_storage::<S>()?;
+        let cri_service = CRIService::new(
+            Arc::new(self.config.clone()),
+            storage.clone(),
+            self.admission()?,
+        );
+        match cri_service.rebuild_indexes() {
+            Ok(0) => {}
+            Ok(n) => info!("Indexed {} pod sandboxes and containers", n),
+            Err(e) => error!("Unable to index pod sandboxes and containers: {:#}", e),
+        }
+        match cri_service.recover_networks().await {
+            Ok(0) => {}
+            Ok(n) => info!("Recovered the networks of {} pod sandboxes", n),
+            Err(e) => error!("Unable to recover pod sandbox networks: {:#}", e),
+        }
+        cri_service.pre_pull_images();
+        self.spawn_storage_snapshots(cri_service.supervisor(), storage.clone());
+        self.spawn_diagnostics(cri_service.clone());
+        self.spawn_image_gc(&cri_service)?;
+        Self::spawn_container_gc(cri_service.clone());
+        Self::spawn_artifact_gc(cri_service.clone());
+        Self::spawn_network_leak_scans(cri_service.clone());
+        self.spawn_image_fs_usage(cri_service.supervisor(), cri_service.image_fs());
+        Self::spawn_config_reload(cri_service.supervisor(), cri_service.live_config(), logs);
+        Self::spawn_trace_export(cri_service.supervisor(), cri_service.tracer());
+        Self::spawn_watchdog(cri_service.supervisor());
 
         // Build a new socket from the config
-        let mut uds = self.unix_domain_listener().await?;
+        let admin_listener = match self.config.admin_sock_path() {
+            Some(path) => Some(Self::bind_admin(path, cri_service.peers()).await?),
+            None => None,
+        };
+        let tls = self.tls_config()?;
+        let activated = systemd::take_listener().context("take socket passed by systemd")?;
+        let passed = activated.is_some();
+        match (self.config.listen_address(), activated) {
+            (ListenAddress::Unix(path), activated) => {
+                let listener = match activated {
+                    Some(listener) => {
+                        let listener = UnixSocketListener::from_std(listener)?;
+                        if listener.address() != path.display().to_string() {
+                            warn!(
+                                "Using socket {} passed by systemd instead of {}",
+                                listener.address(),
+                                path.display()
+                            );
+                        }
+                        listener
+                    }
+                    None => UnixSocketListener::bind(&path).await?,
+                };
+                let listener = listener.with_peers(cri_service.peers().clone());
+                Self::serve(listener, admin_listener, tls, cri_service).await?;
+            }
+            (_, Some(_)) => bail!("socket passed by systemd requires a unix listen address"),
+            (ListenAddress::Tcp(address), None) => {
+                let listener = TcpSocketListener::bind(address).await?;
+                if self.config.tls_client_ca().is_none() {
+                    warn!("Serving unauthenticated TCP connections on {}", address);
+                }
+                Self::serve(listener, admin_listener, tls, cri_service).await?;
+            }
+            #[cfg(target_os = "linux")]
+            (ListenAddress::Vsock { cid, port }, None) => {
+                let listener = VsockSocketListener::bind(cid, port)?;
+                Self::serve(listener, admin_listener, tls, cri_service).await?;
+            }
+            #[cfg(not(target_os = "linux"))]
+            (ListenAddress::Vsock { .. }, None) => bail!("vsock is only supported on Linux"),
+        }
 
-        // Handle shutdown based on signals
-        let mut shutdown_terminate = signal(SignalKind::terminate())?;
-        let mut shutdown_interrupt = signal(SignalKind::interrupt())?;
+        self.cleanup(storage, passed)
+    }
+
+    /// Serve the runtime and image service on the provided `listener`, the read-only admin service
+    /// on the optional `admin_listener`, the streaming sessions and the metrics until the server
+    /// receives a shutdown signal. The runtime and image service use `tls` if provided.
+    ///
+    /// On shutdown, the gRPC servers stop accepting connections and the in-flight requests as
+    /// well as the running image prefetches get drained for up to the shutdown timeout.
+    async fn serve<L: Listener, A: Listener, S: KeyValueStorage>(
+        listener: L,
+        admin_listener: Option<A>,
+        tls: Option<ServerTlsConfig>,
+        cri_service: CRIService<S>,
+    ) -> Result<()> {
+        info!("Runtime server listening on {}", listener.address());
+        Self::notify_systemd(systemd::READY);
+        let streaming = StreamingServer::new(
+            cri_service.config().clone(),
+            cri_service.streaming().clone(),
+            cri_service.logs().clone(),
+            cri_service.events().clone(),
+        )
+        .with_clock(cri_service.clock().clone());
+        let mut builder = transport::Server::builder();
+        if let Some(tls) = tls {
+            builder = builder.tls_config(tls);
+        }
 
-        info!(
-            "Runtime server listening on {}",
-            self.config.sock_path().display()
+        let (stop, stopped) = oneshot::channel::<()>();
+        let stopped = stopped.map(|_| ()).shared();
+        let grpc = future::try_join(
+            builder
+                .add_service(RuntimeServiceServer::new(cri_service.clone()))
+                .add_service(ImageServiceServer::new(cri_service.clone()))
+                .add_service(AdminServer::new(AdminService::writable(
+                    cri_service.clone(),
+                )))
+                .serve_with_incoming_shutdown(listener.incoming(), stopped.clone())
+                .map(|x| x.context("run GRPC server")),
+            Self::serve_admin(
+                admin_listener,
+                AdminService::new(cri_service.clone()),
+                stopped,
+            )
+            .map(|x| x.context("run admin GRPC server")),
         );
+        tokio::pin!(grpc);
 
         tokio::select! {
-            res = transport::Server::builder()
-                .add_service(RuntimeServiceServer::with_interceptor(cri_service.clone(), Self::intercept))
-                .add_service(ImageServiceServer::with_interceptor(cri_service, Self::intercept))
-                .serve_with_incoming(uds.incoming().map_ok(unix_stream::UnixStream)) => {
-                res.context("run GRPC server")?
+            res = &mut grpc => {
+                return res.map(|_| ())
+            }
+            res = Self::serve_metrics(cri_service.clone()) => {
+                return res.context("run metrics server")
+            }
+            res = streaming.serve() => {
+                return res.context("run streaming server")
+            }
+            res = Self::shutdown_signal() => {
+                res.context("wait for shutdown signal")?
+            }
+        }
+        Self::notify_systemd(systemd::STOPPING);
+
+        let timeout = Duration::from_secs(cri_service.config().shutdown_timeout());
+        info!("Draining in-flight requests for up to {:?}", timeout);
+        stop.send(()).ok();
+        let drained = future::join(grpc, cri_service.prefetches().wait_all());
+        match time::timeout(timeout, drained).await {
+            Ok((res, ())) => res.map(|_| ()),
+            Err(_) => {
+                warn!(
+                    "Aborting {} in-flight requests after shutdown timeout of {:?}",
+                    cri_service.rpcs().list().len(),
+                    timeout
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Build the TLS configuration of the runtime server, which is `None` if no certificate is
+    /// configured. Client certificates are required if a client CA is configured.
+    fn tls_config(&self) -> Result<Option<ServerTlsConfig>> {
+        let read = |path: &Path| {
+            std::fs::read(path).with_context(|| format!("read TLS file {}", path.display()))
+        };
+        let tls = match (self.config.tls_cert(), self.config.tls_key()) {
+            (Some(cert), Some(key)) => {
+                ServerTlsConfig::new().identity(Identity::from_pem(read(cert)?, read(key)?))
+            }
+            (None, None) if self.config.tls_client_ca().is_some() => {
+                bail!("TLS client CA requires a server certificate and key")
+            }
+            (None, None) => return Ok(None),
+            _ => bail!("TLS requires both a certificate and a key"),
+        };
+        match self.config.tls_client_ca() {
+            Some(ca) => {
+                info!(
+                    "Requiring TLS client certificates signed by {}",
+                    ca.display()
+                );
+                Ok(Some(tls.client_ca_root(Certificate::from_pem(read(ca)?))))
             }
-            _ = shutdown_interrupt.recv() => {
+            None => Ok(Some(tls)),
+        }
+    }
+
+    /// Bind the admin socket at `path` and restrict its permissions to its owner and group, so
+    /// that only monitoring agents of that group are able to connect. Accepted connections are
+    /// registered at the `peers`.
+    async fn bind_admin(path: &Path, peers: &Peers) -> Result<UnixSocketListener> {
+        let listener = UnixSocketListener::bind(path)
+            .await?
+            .with_peers(peers.clone());
+        std::fs::set_permissions(path, Permissions::from_mode(ADMIN_SOCK_MODE))
+            .with_context(|| format!("set permissions of admin socket {}", path.display()))?;
+        Ok(listener)
+    }
+
+    /// Serve the read-only admin service on the `listener` until the `shutdown` future completes.
+    async fn serve_admin<L: Listener, S: KeyValueStorage, F: Future<Output = ()>>(
+        listener: Option<L>,
+        admin: AdminService<S>,
+        shutdown: F,
+    ) -> Result<()> {
+        match listener {
+            Some(listener) => {
+                info!("Admin server listening on {}", listener.address());
+                transport::Server::builder()
+                    .add_service(RuntimeServiceServer::new(admin.clone()))
+                    .add_service(ImageServiceServer::new(admin.clone()))
+                    .add_service(AdminServer::new(admin))
+                    .serve_with_incoming_shutdown(listener.incoming(), shutdown)
+                    .await
+                    .map_err(Into::into)
+            }
+            None => {
+                shutdown.await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Serve the metrics of the `cri_service` on the configured address, or wait forever if
+    /// metrics are disabled.
+    async fn serve_metrics<S: KeyValueStorage>(cri_service: CRIService<S>) -> Result<()> {
+        match cri_service.config().metrics_address() {
+            Some(address) => metrics::serve(address, cri_service).await,
+            None => future::pending().await,
+        }
+    }
+
+    /// Notify systemd about the `state` of the server, if it runs as notify unit.
+    fn notify_systemd(state: &str) {
+        if let Err(e) = systemd::notify(state) {
+            warn!("Unable to notify systemd: {:#}", e);
+        }
+    }
+
+    /// Reset the watchdog of the systemd unit in a supervised background task, if the unit has a
+    /// watchdog configured.
+    fn spawn_watchdog(supervisor: &Supervisor) {
+        if let Some(interval) = systemd::watchdog_interval() {
+            info!("Resetting systemd watchdog every {:?}", interval);
+            supervisor.spawn("watchdog", move || systemd::watchdog(interval));
+        }
+    }
+
+    #[cfg(unix)]
+    /// Wait until the server receives either an interrupt or a termination signal.
+    async fn shutdown_signal() -> Result<()> {
+        let mut terminate = signal(SignalKind::terminate()).context("register SIGTERM")?;
+        let mut interrupt = signal(SignalKind::interrupt()).context("register SIGINT")?;
+
+        tokio::select! {
+            _ = interrupt.recv() => {
                 info!("Got interrupt signal, shutting down server");
             }
-            _ = shutdown_terminate.recv() => {
+            _ = terminate.recv() => {
                 info!("Got termination signal, shutting down server");
             }
         }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    /// Wait until the server receives a Ctrl-C.
+    async fn shutdown_signal() -> Result<()> {
+        tokio::signal::ctrl_c().await.context("wait for Ctrl-C")?;
+        info!("Got interrupt signal, shutting down server");
+        Ok(())
+    }
+
+    /// Verify that all paths the server writes to can be created and written. The resulting error
+    /// lists every unwritable path, which eases the setup on hosts with a read-only root
+    /// filesystem.
+    fn verify_writable_paths(&self) -> Result<()> {
+        let errors: Vec<String> = self
+            .config
+            .writable_paths()
+            .into_iter()
+            .filter_map(|(name, path)| {
+                Self::verify_writable(&path)
+                    .err()
+                    .map(|e| format!("{} {}: {:#}", name, path.display(), e))
+            })
+            .collect();
+
+        if !errors.is_empty() {
+            bail!("unwritable paths found: {}", errors.join("; "))
+        }
+        Ok(())
+    }
+
+    /// Verify that the directory at `path` exists or can be created and that files can be
+    /// written into it.
+    fn verify_writable(path: &Path) -> Result<()> {
+        std::fs::create_dir_all(path).context("create directory")?;
+        let test_file = path.join(format!(".{}-write-test", crate_name!()));
+        std::fs::write(&test_file, b"").context("write test file")?;
+        std::fs::remove_file(&test_file).context("remove test file")
+    }
+
+    /// Open the storage and recover it from its latest snapshot if it is corrupted and the
+    /// configuration allows it.
+    fn open_storage<S: KeyValueStorage>(&self) -> Result<S> {
+        let path = self.config.storage_path();
+        match S::open(path) {
+            Ok(storage) => Ok(storage),
+            Err(e) if self.config.storage_recovery() == StorageRecovery::Restore => {
+                error!("Unable to open storage, trying to recover: {:#}", e);
+                let (storage, report) =
+                    S::recover(path, &SystemClock).context("recover storage")?;
+                warn!("Recovered storage: {}", report);
+                Ok(storage)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Periodically write snapshots of the storage in a supervised background task.
+    fn spawn_storage_snapshots<S: KeyValueStorage>(&self, supervisor: &Supervisor, storage: S) {
+        let interval = self.config.storage_snapshot_interval();
+        if interval == 0 {
+            return;
+        }
+        let path = self.config.storage_path().clone();
+        let interval = Duration::from_secs(interval);
+
+        supervisor.spawn("storage-snapshot", move || {
+            Self::snapshot_storage(storage.clone(), path.clone(), interval)
+        });
+    }
+
+    /// Write a storage snapshot every `interval` until writing fails.
+    async fn snapshot_storage<S: KeyValueStorage>(
+        storage: S,
+        path: PathBuf,
+        interval: Duration,
+    ) -> Result<()> {
+        let mut interval = time::interval(interval);
+        loop {
+            interval.tick().await;
+            let records = storage.snapshot(&path).context("write storage snapshot")?;
+            debug!("Wrote storage snapshot with {} records", records);
+        }
+    }
+
+    /// Write diagnostic dumps on SIGUSR1 in a supervised background task.
+    fn spawn_diagnostics<S: KeyValueStorage>(&self, cri_service: CRIService<S>) {
+        let path = self.config.diagnostics_path().clone();
+        cri_service
+            .supervisor()
+            .clone()
+            .spawn("diagnostics", move || {
+                Self::dump_diagnostics(cri_service.clone(), path.clone())
+            });
+    }
 
-        self.cleanup(storage)
+    /// Recover the image store from the pulls interrupted by a previous crash, before any new
+    /// pull can start.
+    fn recover_images(&self) -> Result<()> {
+        let store = ImageStore::open(
+            self.config.image_path(),
+            self.config.layer_cache_path().as_deref(),
+        )
+        .context("open image store")?;
+        let recovery = store.recover().context("recover image store")?;
+        if recovery != Recovery::default() {
+            warn!("Recovered image store: {}", recovery);
+        }
+        Ok(())
     }
 
-    /// Create a new UnixListener from the configs socket path.
-    async fn unix_domain_listener(&self) -> Result<UnixListener> {
-        let sock_path = self.config.sock_path();
-        if !sock_path.is_absolute() {
-            bail!(
-                "specified socket path {} is not absolute",
-                sock_path.display()
+    /// Collect unused images on disk pressure in a supervised background task, if enabled.
+    fn spawn_image_gc<S: KeyValueStorage>(&self, cri_service: &CRIService<S>) -> Result<()> {
+        let high_threshold = self.config.image_gc_high_threshold();
+        if high_threshold == 0 {
+            return Ok(());
+        }
+        let policy = GcPolicy::new(high_threshold, self.config.image_gc_low_threshold());
+        let path = self.config.image_path().clone();
+        let pinned = self.config.pinned_images().clone();
+        let store = cri_service.image_store()?;
+        let storage = cri_service.storage().clone();
+
+        cri_service.supervisor().spawn("image-gc", move || {
+            Self::collect_images(
+                policy,
+                store.clone(),
+                storage.clone(),
+                path.clone(),
+                pinned.clone(),
             )
+        });
+        Ok(())
+    }
+
+    /// Run the image garbage collection of the `policy` every `IMAGE_GC_INTERVAL`, which keeps
+    /// the `pinned` images. Failing collections do not stop the task.
+    async fn collect_images<S: KeyValueStorage>(
+        policy: GcPolicy,
+        store: ImageStore,
+        storage: S,
+        path: PathBuf,
+        pinned: Vec<String>,
+    ) -> Result<()> {
+        let mut interval = time::interval(IMAGE_GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            // Stating the filesystem and removing images is IO bound and must not block the
+            // other tasks
+            let (store, mut storage, path, pinned) =
+                (store.clone(), storage.clone(), path.clone(), pinned.clone());
+            let collected =
+                task::spawn_blocking(move || policy.collect(&store, &mut storage, &path, &pinned))
+                    .await;
+            match collected {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!("Unable to collect images: {:#}", e),
+                Err(e) => error!("Unable to collect images: {}", e),
+            }
         }
-        if sock_path.exists() {
-            fs::remove_file(sock_path)
-                .await
-                .with_context(|| format!("unable to remove socket file {}", sock_path.display()))?;
-        } else {
-            let sock_dir = sock_path.parent().context("get socket path directory")?;
-            fs::create_dir_all(sock_dir)
-                .await
-                .with_context(|| format!("create socket dir {}", sock_dir.display()))?;
+    }
+
+    /// Collect exited containers exceeding their retention in a supervised background task, if
+    /// enabled.
+    fn spawn_container_gc<S: KeyValueStorage>(cri_service: CRIService<S>) {
+        let config = cri_service.config();
+        if config.exited_containers_per_pod() == 0 && config.exited_container_max_age() == 0 {
+            return;
+        }
+        cri_service
+            .supervisor()
+            .clone()
+            .spawn("container-gc", move || {
+                Self::collect_containers(cri_service.clone())
+            });
+    }
+
+    /// Run the garbage collection of exited containers every `CONTAINER_GC_INTERVAL`. Failing
+    /// collections do not stop the task.
+    async fn collect_containers<S: KeyValueStorage>(cri_service: CRIService<S>) -> Result<()> {
+        let mut interval = time::interval(CONTAINER_GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = cri_service.collect_containers().await {
+                error!("Unable to collect exited containers: {:#}", e);
+            }
+        }
+    }
+
+    /// Collect artifacts which are not referenced anymore in a supervised background task.
+    fn spawn_artifact_gc<S: KeyValueStorage>(cri_service: CRIService<S>) {
+        cri_service
+            .supervisor()
+            .clone()
+            .spawn("artifact-gc", move || {
+                Self::collect_artifacts(cri_service.clone())
+            });
+    }
+
+    /// Run the garbage collection of artifacts every `ARTIFACT_GC_INTERVAL`. Failing collections
+    /// do not stop the task.
+    async fn collect_artifacts<S: KeyValueStorage>(cri_service: CRIService<S>) -> Result<()> {
+        let mut interval = time::interval(ARTIFACT_GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            // Removing artifacts is IO bound and must not block the other tasks
+            let service = cri_service.clone();
+            match task::spawn_blocking(move || service.collect_artifacts()).await {
+                Ok(Ok(0)) => {}
+                Ok(Ok(removed)) => info!("Removed {} unreferenced artifacts", removed),
+                Ok(Err(e)) => error!("Unable to collect artifacts: {:#}", e),
+                Err(e) => error!("Unable to collect artifacts: {}", e),
+            }
+        }
+    }
+
+    /// Release leaked network namespaces and IP allocations in a supervised background task, if
+    /// enabled.
+    fn spawn_network_leak_scans<S: KeyValueStorage>(cri_service: CRIService<S>) {
+        let interval = cri_service.config().network_leak_scan_interval();
+        if interval == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(interval);
+        cri_service
+            .supervisor()
+            .clone()
+            .spawn("network-leaks", move || {
+                Self::scan_network_leaks(cri_service.clone(), interval)
+            });
+    }
+
+    /// Scan for leaked network resources every `interval`. Failing scans do not stop the task.
+    async fn scan_network_leaks<S: KeyValueStorage>(
+        cri_service: CRIService<S>,
+        interval: Duration,
+    ) -> Result<()> {
+        let mut detector = LeakDetector::default();
+        let mut interval = time::interval(interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = cri_service.release_network_leaks(&mut detector).await {
+                error!("Unable to release leaked network resources: {:#}", e);
+            }
+        }
+    }
+
+    /// Sample the usage of the image filesystem in a supervised background task, if enabled.
+    fn spawn_image_fs_usage(&self, supervisor: &Supervisor, usage: &ImageFsUsage) {
+        let interval = self.config.image_fs_interval();
+        if interval == 0 {
+            return;
+        }
+        let (usage, interval) = (usage.clone(), Duration::from_secs(interval));
+        supervisor.spawn("image-fs-usage", move || usage.clone().run(interval));
+    }
+
+    /// Export the finished spans of the `tracer` in a supervised background task, if an OTLP
+    /// collector is configured.
+    fn spawn_trace_export(supervisor: &Supervisor, tracer: &Tracer) {
+        if tracer.endpoint().is_none() {
+            return;
+        }
+        let tracer = tracer.clone();
+        supervisor.spawn("trace-export", move || tracer.clone().export());
+    }
+
+    /// Reload the configuration on SIGHUP in a supervised background task.
+    fn spawn_config_reload(supervisor: &Supervisor, live: &LiveConfig, logs: LoggerHandle) {
+        let live = live.clone();
+        supervisor.spawn("config-reload", move || {
+            Self::reload_config(live.clone(), logs.clone())
+        });
+    }
+
+    #[cfg(unix)]
+    /// Re-read the configuration whenever the server receives SIGHUP and apply its reloadable
+    /// settings to `live`, whereas `logs` gets a new logger if the log level changed. Neither the
+    /// listeners nor in-flight requests are affected. Failing reloads keep the current
+    /// configuration and do not stop the task.
+    async fn reload_config(live: LiveConfig, logs: LoggerHandle) -> Result<()> {
+        let mut hangup = signal(SignalKind::hangup()).context("register SIGHUP")?;
+        while hangup.recv().await.is_some() {
+            info!("Got hangup signal, reloading configuration");
+            let config = match Config::load() {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Unable to reload configuration: {:#}", e);
+                    continue;
+                }
+            };
+            let changed = live.reload(&config);
+            if changed.contains(&"log-level") {
+                logs.replace(Self::logger(&live.current()));
+            }
+            if changed.is_empty() {
+                info!("Reloaded configuration without changes");
+            } else {
+                info!("Reloaded configuration, changed {}", changed.join(", "));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    /// Configuration reloads are triggered via signals, which are not supported on this platform.
+    async fn reload_config(_: LiveConfig, _: LoggerHandle) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    /// Write a diagnostic dump of the `cri_service` into `path` whenever the server receives
+    /// SIGUSR1. Failing dumps do not stop the task.
+    async fn dump_diagnostics<S: KeyValueStorage>(
+        cri_service: CRIService<S>,
+        path: Option<PathBuf>,
+    ) -> Result<()> {
+        let mut dump = signal(SignalKind::user_defined1()).context("register SIGUSR1")?;
+        while dump.recv().await.is_some() {
+            info!("Got user defined signal 1, writing diagnostic dump");
+            if let Err(e) = Dump::collect(&cri_service).and_then(|x| x.write(path.as_deref())) {
+                error!("Unable to write diagnostic dump: {:#}", e);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    /// Diagnostic dumps are triggered via signals, which are not supported on this platform.
+    async fn dump_diagnostics<S: KeyValueStorage>(
+        _: CRIService<S>,
+        _: Option<PathBuf>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Build the admission chain from the configuration.
+    fn admission(&self) -> Result<AdmissionChain> {
+        let mut admission = AdmissionChain::default();
+        if let Some(path) = self.config.policy_path() {
+            let policies = Policies::from(path).context("load namespace policies")?;
+            info!("Loaded namespace policies from {}", path.display());
+            admission.push(Arc::new(policies));
+        }
+        // Enforce the pod security standard last, because it has to see all mutations
+        let level = self.config.pod_security();
+        if level != PodSecurityLevel::Privileged {
+            info!("Enforcing pod security standard {:?}", level);
+            admission.push(Arc::new(PodSecurity::new(level)));
         }
+        Ok(admission)
+    }
 
-        Ok(UnixListener::bind(sock_path).context("bind socket from path")?)
+    /// Initialize the logger writing in the configured format via the configured driver and set
+    /// the verbosity to the provided level.
+    fn set_logging_verbosity(&self) -> Result<LoggerHandle> {
+        let sink = Sink::new(
+            self.config.log_format(),
+            self.config.log_driver(),
+            self.config.log_file().as_deref(),
+            Arc::new(SystemClock),
+        )
+        .context("create log sink")?;
+        let scoped = self.config.features().contains(&Feature::RequestTracing);
+        ScopedLogger::init(Self::logger(&self.config), sink, scoped).context("init logger")
     }
 
-    /// Initialize the logger and set the verbosity to the provided level.
-    fn set_logging_verbosity(&self) -> Result<()> {
+    /// Build a logger with the verbosity of the `config`.
+    fn logger(config: &Config) -> env_logger::Logger {
         // Set the logging verbosity via the env
-        let level = if self.config.log_scope() == LogScope::Global {
-            self.config.log_level().to_string()
+        let level = if config.log_scope() == LogScope::Global {
+            config.log_level().to_string()
         } else {
-            format!("{}={}", crate_name!(), self.config.log_level())
+            format!("{}={}", crate_name!(), config.log_level())
         };
         env::set_var("RUST_LOG", level);
-
-        // Initialize the logger
-        env_logger::try_init().context("init env logger")
+        env_logger::Builder::from_default_env().build()
     }
 
-    /// This function will get called on each inbound request, if a `Status`
-    /// is returned, it will cancel the request and return that status to the
-    /// client.
-    fn intercept(req: Request<()>) -> std::result::Result<Request<()>, Status> {
-        debug!("{:?}", req);
-        Ok(req)
-    }
-
-    /// Cleanup the server and persist any data if necessary.
-    fn cleanup(self, mut storage: DefaultKeyValueStorage) -> Result<()> {
+    /// Cleanup the server and persist any data if necessary. The socket is kept if it has been
+    /// `passed` by systemd, which owns it.
+    fn cleanup<S: KeyValueStorage>(self, mut storage: S, passed: bool) -> Result<()> {
         debug!("Cleaning up server");
         storage.persist().context("persist storage")?;
-        std::fs::remove_file(self.config.sock_path())
-            .with_context(|| format!("remove socket path {}", self.config.sock_path().display()))?;
+        storage
+            .snapshot(self.config.storage_path())
+            .context("write storage snapshot")?;
+        if let (ListenAddress::Unix(path), false) = (self.config.listen_address(), passed) {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("remove socket path {}", path.display()))?;
+        }
+        if let Some(path) = self.config.admin_sock_path() {
+            std::fs::remove_file(path)
+                .with_context(|| format!("remove admin socket path {}", path.display()))?;
+        }
         Ok(())
     }
 }
@@ -131,42 +773,112 @@ mod tests {
     use crate::config::ConfigBuilder;
     use tempfile::{tempdir, NamedTempFile};
 
-    #[tokio::test]
-    async fn unix_domain_listener_success() -> Result<()> {
-        let sock_path = &tempdir()?.path().join("test.sock");
-        let config = ConfigBuilder::default().sock_path(sock_path).build()?;
+    #[test]
+    fn verify_writable_paths_success() -> Result<()> {
+        let dir = tempdir()?;
+        let config = ConfigBuilder::default()
+            .sock_path(dir.path().join("run").join("test.sock"))
+            .storage_path(dir.path().join("storage"))
+            .netns_path(dir.path().join("netns"))
+            .log_path(dir.path().join("logs"))
+            .sandbox_path(dir.path().join("sandboxes"))
+            .container_path(dir.path().join("containers"))
+            .image_path(dir.path().join("images"))
+            .build()?;
         let sut = Server::new(config);
 
-        assert!(!sock_path.exists());
-        sut.unix_domain_listener().await?;
-        assert!(sock_path.exists());
+        sut.verify_writable_paths()?;
+        assert!(dir.path().join("netns").exists());
+        assert!(!dir.path().join("netns").join(".cri-write-test").exists());
 
         Ok(())
     }
 
-    #[tokio::test]
-    async fn unix_domain_listener_success_exists() -> Result<()> {
-        let sock_path = NamedTempFile::new()?;
+    #[test]
+    fn open_storage_success() -> Result<()> {
+        let dir = tempdir()?;
         let config = ConfigBuilder::default()
-            .sock_path(sock_path.path())
+            .storage_path(dir.path().join("storage"))
             .build()?;
         let sut = Server::new(config);
 
-        assert!(sock_path.path().exists());
-        sut.unix_domain_listener().await?;
-        assert!(sock_path.path().exists());
+        let mut storage = sut.open_storage::<DefaultKeyValueStorage>()?;
+        storage.insert("key", "value")?;
+        storage.persist()?;
+        let mut storage = sut.open_storage::<MemoryKeyValueStorage>()?;
+        assert!(storage.get::<_, String>("key")?.is_none());
+        Ok(())
+    }
 
+    #[test]
+    fn tls_config_success() -> Result<()> {
+        let dir = tempdir()?;
+        for file in &["server.crt", "server.key", "ca.crt"] {
+            std::fs::write(dir.path().join(file), "PEM")?;
+        }
+        let builder = || {
+            ConfigBuilder::default()
+                .tls_cert(Some(dir.path().join("server.crt")))
+                .tls_key(Some(dir.path().join("server.key")))
+        };
+
+        assert!(Server::new(Config::default()).tls_config()?.is_none());
+        assert!(Server::new(builder().build()?).tls_config()?.is_some());
+        let config = builder()
+            .tls_client_ca(Some(dir.path().join("ca.crt")))
+            .build()?;
+        assert!(Server::new(config).tls_config()?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn tls_config_fail() -> Result<()> {
+        let dir = tempdir()?;
+        let cert = dir.path().join("server.crt");
+        std::fs::write(&cert, "PEM")?;
+
+        let config = ConfigBuilder::default()
+            .tls_cert(Some(cert.clone()))
+            .build()?;
+        assert!(Server::new(config).tls_config().is_err());
+
+        let config = ConfigBuilder::default()
+            .tls_client_ca(Some(cert.clone()))
+            .build()?;
+        assert!(Server::new(config).tls_config().is_err());
+
+        let config = ConfigBuilder::default()
+            .tls_cert(Some(cert))
+            .tls_key(Some(dir.path().join("missing.key")))
+            .build()?;
+        let err = Server::new(config).tls_config().err().context("no error")?;
+        assert!(format!("{:#}", err).contains("missing.key"));
         Ok(())
     }
 
-    #[tokio::test]
-    async fn unix_domain_listener_fail_not_absolute() -> Result<()> {
+    #[test]
+    fn verify_writable_paths_fail() -> Result<()> {
+        let dir = tempdir()?;
+        let file = NamedTempFile::new()?;
         let config = ConfigBuilder::default()
-            .sock_path("not/absolute/path")
+            .sock_path(dir.path().join("test.sock"))
+            .storage_path(file.path().join("storage"))
+            .netns_path(dir.path().join("netns"))
+            .log_path(file.path().join("logs"))
+            .sandbox_path(dir.path().join("sandboxes"))
+            .container_path(dir.path().join("containers"))
+            .image_path(dir.path().join("images"))
             .build()?;
         let sut = Server::new(config);
 
-        assert!(sut.unix_domain_listener().await.is_err());
+        let err = sut
+            .verify_writable_paths()
+            .err()
+            .context("no error")?
+            .to_string();
+        assert!(err.contains("storage path"));
+        assert!(err.contains("log path"));
+        assert!(!err.contains("network namespace path"));
 
         Ok(())
     }
@@ -0,0 +1,389 @@
+//! Container statistics based on the cgroup accounting and the usage of the writable layer.
+//!
+//! The kubelet polls the statistics of all containers periodically, which is why samples are
+//! cached for the configured interval. Walking the writable layer is the most expensive part and
+//! only happens once per interval and container.
+//!
+//! Every fresh sample is also appended to a short per container history, which outlives the
+//! container itself until it gets removed. This gives postmortems of OOM kills some context
+//! without requiring an external metrics stack.
+
+use crate::{
+    clock::{Clock, SystemClock},
+    container::Container,
+    criapi::{
+        ContainerAttributes, ContainerStats, CpuUsage, FilesystemIdentifier, FilesystemUsage,
+        MemoryUsage, UInt64Value,
+    },
+    oci::spec::ROOTFS_DIR,
+    resources::{container_cgroup_path, ResourceManager},
+};
+use anyhow::{Context, Result};
+use getset::CopyGetters;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::ErrorKind,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The size of the blocks reported by `stat`.
+const BLOCK_SIZE: u64 = 512;
+
+#[derive(Clone)]
+/// StatsCache samples the statistics of containers and reuses them within the interval.
+pub struct StatsCache {
+    /// The time a sample stays valid.
+    interval: Duration,
+
+    /// The latest samples and their sampling time by container ID.
+    samples: Arc<Mutex<HashMap<String, (Instant, ContainerStats)>>>,
+
+    /// The maximum number of samples in the history of a container.
+    capacity: usize,
+
+    /// The recent samples of every container by its ID, oldest first.
+    history: Arc<Mutex<HashMap<String, VecDeque<UsageSample>>>>,
+
+    /// The clock the samples and their validity are based on.
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Clone, Copy, CopyGetters, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+/// UsageSample is a single entry of the resource usage history of a container.
+pub struct UsageSample {
+    #[get_copy = "pub"]
+    /// The time of the sample in nanoseconds since the epoch.
+    timestamp: i64,
+
+    #[get_copy = "pub"]
+    /// The cumulative CPU usage in nanoseconds.
+    cpu_nanos: u64,
+
+    #[get_copy = "pub"]
+    /// The memory working set in bytes.
+    memory_working_set: u64,
+}
+
+impl UsageSample {
+    /// Extract the sample from the `stats`, whereas missing values are reported as `0`.
+    fn from_stats(stats: &ContainerStats) -> Self {
+        Self {
+            timestamp: stats.cpu.as_ref().map(|x| x.timestamp).unwrap_or_default(),
+            cpu_nanos: stats
+                .cpu
+                .as_ref()
+                .and_then(|x| x.usage_core_nano_seconds.as_ref())
+                .map(|x| x.value)
+                .unwrap_or_default(),
+            memory_working_set: stats
+                .memory
+                .as_ref()
+                .and_then(|x| x.working_set_bytes.as_ref())
+                .map(|x| x.value)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl StatsCache {
+    /// Create a new cache whose samples are valid for `interval`, keeping the last `capacity`
+    /// samples of every container as history.
+    pub fn new(interval: Duration, capacity: usize) -> Self {
+        Self {
+            interval,
+            samples: Arc::new(Mutex::new(HashMap::new())),
+            capacity,
+            history: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Base the samples and their validity on the `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Retrieve the statistics of the `container`, which get sampled via the resource `manager`
+    /// if the previous sample is outdated.
+    pub fn get<M: ResourceManager>(
+        &self,
+        manager: &M,
+        container: &Container,
+    ) -> Result<ContainerStats> {
+        let now = self.clock.instant();
+        let cached = self.samples.lock().ok().and_then(|x| {
+            x.get(container.id())
+                .filter(|(sampled, _)| now.duration_since(*sampled) < self.interval)
+                .map(|(_, stats)| stats.clone())
+        });
+        if let Some(stats) = cached {
+            return Ok(stats);
+        }
+
+        let stats = sample(manager, container, self.clock.unix_nanos()?)?;
+
+        // Outdated samples are removed lazily, which covers the samples of removed containers
+        if let Ok(mut samples) = self.samples.lock() {
+            let interval = self.interval;
+            samples.retain(|_, (sampled, _)| now.duration_since(*sampled) < interval);
+            samples.insert(container.id().clone(), (now, stats.clone()));
+        }
+        self.record(container.id(), UsageSample::from_stats(&stats));
+        Ok(stats)
+    }
+
+    /// Retrieve the usage history of the container `id`, oldest first.
+    pub fn history(&self, id: &str) -> Vec<UsageSample> {
+        self.history
+            .lock()
+            .ok()
+            .and_then(|x| x.get(id).map(|x| x.iter().copied().collect()))
+            .unwrap_or_default()
+    }
+
+    /// Drop the history of the removed container `id`.
+    pub fn forget(&self, id: &str) {
+        if let Ok(mut history) = self.history.lock() {
+            history.remove(id);
+        }
+    }
+
+    /// Append the `sample` to the history of the container `id`, evicting the oldest sample if
+    /// the history is full.
+    fn record(&self, id: &str, sample: UsageSample) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Ok(mut history) = self.history.lock() {
+            let samples = history.entry(id.into()).or_default();
+            if samples.len() == self.capacity {
+                samples.pop_front();
+            }
+            samples.push_back(sample);
+        }
+    }
+}
+
+/// Sample the statistics of the `container` via the resource `manager` at the `timestamp` in
+/// nanoseconds since the epoch.
+fn sample<M: ResourceManager>(
+    manager: &M,
+    container: &Container,
+    timestamp: i64,
+) -> Result<ContainerStats> {
+    let cgroup_path = container_cgroup_path(container.pod_sandbox_id(), container.id());
+    let usage = manager
+        .usage(&cgroup_path)
+        .with_context(|| format!("get usage of container {}", container))?;
+    let rootfs = container.bundle().join(ROOTFS_DIR);
+    let (used_bytes, inodes_used) = disk_usage(&rootfs)?;
+
+    Ok(ContainerStats {
+        attributes: Some(ContainerAttributes {
+            id: container.id().clone(),
+            metadata: Some(container.metadata()),
+            labels: container.labels().clone(),
+            annotations: container.annotations().clone(),
+        }),
+        cpu: Some(CpuUsage {
+            timestamp,
+            usage_core_nano_seconds: Some(UInt64Value {
+                value: usage.cpu_nanos(),
+            }),
+        }),
+        memory: Some(MemoryUsage {
+            timestamp,
+            working_set_bytes: Some(UInt64Value {
+                value: usage.memory_working_set(),
+            }),
+        }),
+        writable_layer: Some(FilesystemUsage {
+            timestamp,
+            fs_id: Some(FilesystemIdentifier {
+                mountpoint: rootfs.display().to_string(),
+            }),
+            used_bytes: Some(UInt64Value { value: used_bytes }),
+            inodes_used: Some(UInt64Value { value: inodes_used }),
+        }),
+    })
+}
+
+/// Retrieve the allocated bytes and the number of inodes below `path`, like `du` does. Other
+/// filesystems mounted below `path` are not taken into account.
+pub fn disk_usage(path: &Path) -> Result<(u64, u64)> {
+    let root = fs::symlink_metadata(path).with_context(|| format!("stat {}", path.display()))?;
+    let (mut bytes, mut inodes) = (root.blocks() * BLOCK_SIZE, 1);
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // The container may remove files while walking the layer
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("read {}", dir.display())),
+        };
+        for entry in entries {
+            let entry = entry.with_context(|| format!("read {}", dir.display()))?;
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("stat {}", entry.path().display()))
+                }
+            };
+            if metadata.dev() != root.dev() {
+                continue;
+            }
+            bytes += metadata.blocks() * BLOCK_SIZE;
+            inodes += 1;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            }
+        }
+    }
+    Ok((bytes, inodes))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::tests::FakeClock, container::ContainerBuilder, resources::cgroups::CgroupManager,
+    };
+    use anyhow::format_err;
+    use tempfile::tempdir;
+
+    /// Create a container whose bundle is in `dir`, together with a cgroup hierarchy providing
+    /// its usage.
+    fn new_container(dir: &Path) -> Result<(Container, CgroupManager)> {
+        let container = ContainerBuilder::default()
+            .id("id")
+            .pod_sandbox_id("sandbox")
+            .name("name")
+            .attempt(0u32)
+            .bundle(dir.join("bundle"))
+            .build()
+            .map_err(|e| format_err!("build container: {}", e))?;
+        let rootfs = container.bundle().join(ROOTFS_DIR);
+        fs::create_dir_all(rootfs.join("data"))?;
+        fs::write(rootfs.join("data").join("file"), vec![0; 8192])?;
+
+        let root = dir.join("cgroup");
+        let cgroup = root.join("cri").join("sandbox").join("id");
+        fs::create_dir_all(&cgroup)?;
+        fs::write(root.join("cgroup.controllers"), "cpu memory")?;
+        fs::write(cgroup.join("cpu.stat"), "usage_usec 10\n")?;
+        fs::write(cgroup.join("memory.current"), "4096\n")?;
+        Ok((container, CgroupManager::new(root)))
+    }
+
+    #[test]
+    fn get_success() -> Result<()> {
+        let dir = tempdir()?;
+        let (container, manager) = new_container(dir.path())?;
+        let sut = StatsCache::new(Duration::from_secs(60), 10);
+
+        let stats = sut.get(&manager, &container)?;
+        assert_eq!(stats.attributes.map(|x| x.id), Some("id".into()));
+        assert_eq!(
+            stats.cpu.and_then(|x| x.usage_core_nano_seconds),
+            Some(UInt64Value { value: 10_000 })
+        );
+        assert_eq!(
+            stats.memory.and_then(|x| x.working_set_bytes),
+            Some(UInt64Value { value: 4096 })
+        );
+        let layer = stats.writable_layer.context("no writable layer")?;
+        assert!(layer.used_bytes.map(|x| x.value).unwrap_or_default() >= 8192);
+        assert_eq!(layer.inodes_used, Some(UInt64Value { value: 3 }));
+        Ok(())
+    }
+
+    #[test]
+    fn get_success_cached() -> Result<()> {
+        let dir = tempdir()?;
+        let (container, manager) = new_container(dir.path())?;
+        let sut = StatsCache::new(Duration::from_secs(60), 10);
+        let first = sut.get(&manager, &container)?;
+
+        // Samples within the interval do not access the cgroup again
+        let other = CgroupManager::new(dir.path().join("missing"));
+        assert_eq!(sut.get(&other, &container)?, first);
+
+        let sut = StatsCache::new(Duration::from_secs(0), 10);
+        sut.get(&manager, &container)?;
+        assert!(sut.get(&other, &container).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn get_success_outdated() -> Result<()> {
+        let dir = tempdir()?;
+        let (container, manager) = new_container(dir.path())?;
+        let clock = FakeClock::default();
+        let sut = StatsCache::new(Duration::from_secs(60), 10).with_clock(Arc::new(clock.clone()));
+        let first = sut.get(&manager, &container)?;
+        let other = CgroupManager::new(dir.path().join("missing"));
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(sut.get(&other, &container)?, first);
+
+        // Samples outside of the interval are taken again, together with a new timestamp
+        clock.advance(Duration::from_secs(1));
+        assert!(sut.get(&other, &container).is_err());
+        let second = sut.get(&manager, &container)?;
+        assert_eq!(
+            second.cpu.map(|x| x.timestamp),
+            first.cpu.map(|x| x.timestamp + 60_000_000_000)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn history_success() -> Result<()> {
+        let dir = tempdir()?;
+        let (container, manager) = new_container(dir.path())?;
+        let sut = StatsCache::new(Duration::from_secs(0), 2);
+        assert!(sut.history(container.id()).is_empty());
+
+        for usage in &["10", "20", "30"] {
+            let cgroup = dir
+                .path()
+                .join("cgroup")
+                .join("cri")
+                .join("sandbox")
+                .join("id");
+            fs::write(cgroup.join("cpu.stat"), format!("usage_usec {}\n", usage))?;
+            sut.get(&manager, &container)?;
+        }
+
+        // Only the latest samples are kept
+        let history = sut.history(container.id());
+        assert_eq!(
+            history.iter().map(|x| x.cpu_nanos()).collect::<Vec<_>>(),
+            vec![20_000, 30_000]
+        );
+        assert_eq!(history[1].memory_working_set(), 4096);
+        assert!(history[0].timestamp() <= history[1].timestamp());
+
+        sut.forget(container.id());
+        assert!(sut.history(container.id()).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn history_disabled() -> Result<()> {
+        let dir = tempdir()?;
+        let (container, manager) = new_container(dir.path())?;
+        let sut = StatsCache::new(Duration::from_secs(0), 0);
+        sut.get(&manager, &container)?;
+        assert!(sut.history(container.id()).is_empty());
+        Ok(())
+    }
+}
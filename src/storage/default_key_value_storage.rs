@@ -1,10 +1,18 @@
 //! The default key value storage implementation for storing arbitrary data.
 
-use crate::storage::KeyValueStorage;
+use crate::{
+    clock::Clock,
+    storage::{
+        lock::LOCK_FILE,
+        snapshot::{Snapshot, SNAPSHOT_FILE},
+        KeyValueStorage, RecoveryReport,
+    },
+};
 use anyhow::{Context, Result};
+use log::warn;
 use serde::{de::DeserializeOwned, Serialize};
 use sled::Db;
-use std::{convert::AsRef, path::Path};
+use std::{convert::AsRef, fs, path::Path, time::UNIX_EPOCH};
 
 #[derive(Clone)]
 /// A default key value storage implementation
@@ -49,6 +57,21 @@ impl KeyValueStorage for DefaultKeyValueStorage {
         Ok(())
     }
 
+    fn scan_prefix<K, V>(&mut self, prefix: K) -> Result<Vec<V>>
+    where
+        K: AsRef<[u8]>,
+        V: DeserializeOwned,
+    {
+        let mut values = vec![];
+        for item in self.db.scan_prefix(prefix) {
+            let (_, value) = item.context("retrieve value for prefix")?;
+            if let Ok(value) = bincode::deserialize(&value) {
+                values.push(value);
+            }
+        }
+        Ok(values)
+    }
+
     fn remove<K>(&mut self, key: K) -> Result<()>
     where
         K: AsRef<[u8]>,
@@ -61,11 +84,81 @@ impl KeyValueStorage for DefaultKeyValueStorage {
         self.db.flush().context("persist db")?;
         Ok(())
     }
+
+    fn snapshot(&self, path: &Path) -> Result<usize> {
+        let records = self
+            .db
+            .iter()
+            .map(|x| x.map(|(k, v)| (k.to_vec(), v.to_vec())))
+            .collect::<sled::Result<Vec<_>>>()
+            .context("read records")?;
+        let len = records.len();
+        Snapshot::new(records).write(&path.join(SNAPSHOT_FILE))?;
+        Ok(len)
+    }
+
+    /// The files of the corrupted database are moved into a backup directory and a new database
+    /// is populated from the latest snapshot. Changes done after the snapshot got written are
+    /// lost.
+    fn recover(path: &Path, clock: &dyn Clock) -> Result<(Self, RecoveryReport)> {
+        let timestamp = clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .context("get current time")?
+            .as_secs();
+        let backup_path = path.join(format!("corrupted-{}", timestamp));
+        fs::create_dir_all(&backup_path)
+            .with_context(|| format!("create backup path {}", backup_path.display()))?;
+
+        for entry in fs::read_dir(path).with_context(|| format!("read dir {}", path.display()))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name == LOCK_FILE || name == SNAPSHOT_FILE || name.starts_with("corrupted-") {
+                continue;
+            }
+            fs::rename(entry.path(), backup_path.join(entry.file_name()))
+                .with_context(|| format!("move {} into backup", entry.path().display()))?;
+        }
+
+        let storage = Self::open(path)?;
+        let snapshot_path = path.join(SNAPSHOT_FILE);
+        let snapshot = if snapshot_path.exists() {
+            Snapshot::read(&snapshot_path).unwrap_or_else(|e| {
+                warn!("Unable to read storage snapshot: {:#}", e);
+                Snapshot::default()
+            })
+        } else {
+            Snapshot::default()
+        };
+
+        let (mut restored, mut dropped) = (0, 0);
+        for (key, value) in snapshot.records() {
+            match storage.db.insert(key.as_slice(), value.as_slice()) {
+                Ok(_) => restored += 1,
+                Err(e) => {
+                    warn!("Dropping unrestorable record: {}", e);
+                    dropped += 1
+                }
+            }
+        }
+        storage.db.flush().context("persist recovered db")?;
+
+        Ok((
+            storage,
+            RecoveryReport {
+                backup_path,
+                restored,
+                dropped,
+            },
+        ))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::tests::FakeClock;
     use serde::Deserialize;
     use tempfile::TempDir;
 
@@ -103,6 +196,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn scan_prefix_values() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut db = DefaultKeyValueStorage::open(dir.path())?;
+
+        db.insert("prefix/b", "value 2")?;
+        db.insert("prefix/a", "value 1")?;
+        db.insert("other/c", "value 3")?;
+        db.insert("prefix/c", 42u8)?;
+
+        let values: Vec<String> = db.scan_prefix("prefix/")?;
+        assert_eq!(values, vec!["value 1", "value 2"]);
+        assert!(db.scan_prefix::<_, String>("none/")?.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn persist() -> Result<()> {
         let dir = TempDir::new()?;
@@ -140,6 +249,46 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn snapshot_and_recover() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut db = DefaultKeyValueStorage::open(dir.path())?;
+        db.insert("key", "value")?;
+        assert_eq!(db.snapshot(dir.path())?, 1);
+        db.insert("lost", "value")?;
+        db.persist()?;
+        drop(db);
+
+        let clock = FakeClock::default();
+        let (mut db, report) = DefaultKeyValueStorage::recover(dir.path(), &clock)?;
+        let timestamp = clock.now().duration_since(UNIX_EPOCH)?.as_secs();
+        assert_eq!(
+            report.backup_path(),
+            &dir.path().join(format!("corrupted-{}", timestamp))
+        );
+        assert_eq!(report.restored(), 1);
+        assert_eq!(report.dropped(), 0);
+        assert!(report.backup_path().exists());
+        assert!(dir.path().join(SNAPSHOT_FILE).exists());
+        assert_eq!(db.get::<_, String>("key")?.as_deref(), Some("value"));
+        assert!(db.get::<_, String>("lost")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn recover_without_snapshot() -> Result<()> {
+        let dir = TempDir::new()?;
+        fs::write(dir.path().join("db"), b"corrupted")?;
+        fs::write(dir.path().join(LOCK_FILE), b"")?;
+
+        let (mut db, report) = DefaultKeyValueStorage::recover(dir.path(), &FakeClock::default())?;
+        assert_eq!(report.restored(), 0);
+        assert!(report.backup_path().join("db").exists());
+        assert!(dir.path().join(LOCK_FILE).exists());
+        assert!(db.get::<_, String>("key")?.is_none());
+        Ok(())
+    }
+
     #[test]
     fn open_twice() -> Result<()> {
         let dir = TempDir::new()?;
@@ -0,0 +1,245 @@
+//! Secondary indexes of stored records, like the pod sandboxes by their labels.
+//!
+//! Every indexed field value of a record is stored as an entry of its own, whose key is made of
+//! the index name, the field, the value and the ID of the record separated by NUL bytes, which
+//! cannot be part of the indexed values. Looking up a value therefore only reads the entries of
+//! the matching records instead of deserializing all of them. Entries are inserted before their
+//! record and removed after it, so that an interrupted operation only leaves stale entries
+//! behind. Callers load the records of the returned IDs anyway, which skips stale entries.
+
+use crate::storage::KeyValueStorage;
+use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashMap};
+
+/// The storage key prefix of all index entries.
+const KEY_PREFIX: &str = "index/";
+
+/// The separator of the parts of an entry key.
+const SEPARATOR: char = '\0';
+
+/// The version of the entries, which gets stored per index to rebuild outdated ones.
+const VERSION: u32 = 1;
+
+/// The field of the label keys of a record.
+pub const LABEL_KEY: &str = "label-key";
+
+/// The field of the label key value pairs of a record.
+pub const LABEL: &str = "label";
+
+/// An indexed field of a record together with its value.
+pub type Entry = (&'static str, String);
+
+/// Retrieve the index entries of the `labels` of a record, which are both the keys and the pairs.
+pub fn label_entries(labels: &HashMap<String, String>) -> Vec<Entry> {
+    labels
+        .iter()
+        .flat_map(|(k, v)| {
+            vec![
+                (LABEL_KEY, k.clone()),
+                (LABEL, format!("{}{}{}", k, SEPARATOR, v)),
+            ]
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Index maps the field values of a single kind of records to their IDs.
+pub struct Index {
+    /// The name of the index, like `sandbox`.
+    name: &'static str,
+}
+
+impl Index {
+    /// Create the index with the `name`.
+    pub const fn new(name: &'static str) -> Self {
+        Self { name }
+    }
+
+    /// Retrieve the key prefix of all entries of the `field` with the `value`.
+    fn prefix(&self, field: &str, value: &str) -> String {
+        format!(
+            "{}{}{s}{}{s}{}{s}",
+            KEY_PREFIX,
+            self.name,
+            field,
+            value,
+            s = SEPARATOR
+        )
+    }
+
+    /// Retrieve the key of the version of the index.
+    fn version_key(&self) -> String {
+        format!("{}{}{}version", KEY_PREFIX, self.name, SEPARATOR)
+    }
+
+    /// Add the `entries` of the record `id` to the index.
+    pub fn insert<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        id: &str,
+        entries: &[Entry],
+    ) -> Result<()> {
+        for (field, value) in entries {
+            storage
+                .insert(format!("{}{}", self.prefix(field, value), id), id)
+                .with_context(|| format!("insert {} index entry {}", self.name, field))?;
+        }
+        Ok(())
+    }
+
+    /// Remove the `entries` of the record `id` from the index. Missing entries are skipped.
+    pub fn remove<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        id: &str,
+        entries: &[Entry],
+    ) -> Result<()> {
+        for (field, value) in entries {
+            let key = format!("{}{}", self.prefix(field, value), id);
+            if storage
+                .get::<_, String>(&key)
+                .with_context(|| format!("get {} index entry {}", self.name, field))?
+                .is_some()
+            {
+                storage
+                    .remove(&key)
+                    .with_context(|| format!("remove {} index entry {}", self.name, field))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Retrieve the IDs of all records whose `field` has the `value`.
+    pub fn lookup<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        field: &str,
+        value: &str,
+    ) -> Result<BTreeSet<String>> {
+        Ok(storage
+            .scan_prefix::<_, String>(self.prefix(field, value))
+            .with_context(|| format!("scan {} index {}", self.name, field))?
+            .into_iter()
+            .collect())
+    }
+
+    /// Retrieve the IDs of all records which have all of the `labels`. Returns `None` if there
+    /// are no labels to select by, which means that all records match.
+    pub fn select<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        labels: &HashMap<String, String>,
+    ) -> Result<Option<BTreeSet<String>>> {
+        let mut selected: Option<BTreeSet<String>> = None;
+        for (k, v) in labels {
+            let ids = self.lookup(storage, LABEL, &format!("{}{}{}", k, SEPARATOR, v))?;
+            let ids = match selected {
+                Some(selected) => selected.intersection(&ids).cloned().collect(),
+                None => ids,
+            };
+            if ids.is_empty() {
+                return Ok(Some(ids));
+            }
+            selected = Some(ids);
+        }
+        Ok(selected)
+    }
+
+    /// Add the entries of all `records` to the index, unless the index is up to date already,
+    /// for example because the records have been stored before the index existed. Returns the
+    /// number of indexed records.
+    pub fn rebuild<S: KeyValueStorage>(
+        &self,
+        storage: &mut S,
+        records: &[(String, Vec<Entry>)],
+    ) -> Result<usize> {
+        let key = self.version_key();
+        if storage
+            .get::<_, u32>(&key)
+            .with_context(|| format!("get {} index version", self.name))?
+            == Some(VERSION)
+        {
+            return Ok(0);
+        }
+        for (id, entries) in records {
+            self.insert(storage, id, entries)?;
+        }
+        storage
+            .insert(&key, VERSION)
+            .with_context(|| format!("insert {} index version", self.name))?;
+        Ok(records.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory_key_value_storage::MemoryKeyValueStorage;
+
+    const SUT: Index = Index::new("test");
+
+    fn labels(labels: &[(&str, &str)]) -> HashMap<String, String> {
+        labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn ids(ids: &[&str]) -> BTreeSet<String> {
+        ids.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn select_success() -> Result<()> {
+        let mut storage = MemoryKeyValueStorage::default();
+        let first = label_entries(&labels(&[("app", "web"), ("tier", "frontend")]));
+        let second = label_entries(&labels(&[("app", "web"), ("tier", "backend")]));
+        SUT.insert(&mut storage, "first", &first)?;
+        SUT.insert(&mut storage, "second", &second)?;
+        Index::new("other").insert(&mut storage, "other", &first)?;
+
+        let select = |storage: &mut MemoryKeyValueStorage, selector: &[(&str, &str)]| {
+            SUT.select(storage, &labels(selector))
+        };
+        assert_eq!(select(&mut storage, &[])?, None);
+        assert_eq!(
+            select(&mut storage, &[("app", "web")])?,
+            Some(ids(&["first", "second"]))
+        );
+        assert_eq!(
+            select(&mut storage, &[("app", "web"), ("tier", "backend")])?,
+            Some(ids(&["second"]))
+        );
+        assert_eq!(select(&mut storage, &[("app", "db")])?, Some(ids(&[])));
+
+        // Values cannot match the prefix of other values
+        assert_eq!(select(&mut storage, &[("app", "we")])?, Some(ids(&[])));
+        assert_eq!(
+            SUT.lookup(&mut storage, LABEL_KEY, "tier")?,
+            ids(&["first", "second"])
+        );
+
+        SUT.remove(&mut storage, "second", &second)?;
+        SUT.remove(&mut storage, "second", &second)?;
+        assert_eq!(
+            select(&mut storage, &[("app", "web")])?,
+            Some(ids(&["first"]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rebuild_success() -> Result<()> {
+        let mut storage = MemoryKeyValueStorage::default();
+        let records = vec![("id".to_string(), vec![("namespace", "default".to_string())])];
+        assert_eq!(SUT.rebuild(&mut storage, &records)?, 1);
+        assert_eq!(
+            SUT.lookup(&mut storage, "namespace", "default")?,
+            ids(&["id"])
+        );
+
+        // Up to date indexes are maintained incrementally
+        assert_eq!(SUT.rebuild(&mut storage, &records)?, 0);
+        Ok(())
+    }
+}
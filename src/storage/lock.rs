@@ -0,0 +1,108 @@
+//! Exclusive locking of the storage path.
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use nix::{
+    errno::Errno,
+    fcntl::{flock, FlockArg},
+};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    os::unix::io::AsRawFd,
+    path::Path,
+    process,
+};
+
+/// The name of the lock file inside the storage path.
+pub const LOCK_FILE: &str = "daemon.lock";
+
+/// StorageLock holds an exclusive advisory lock on a storage path as long as it is in scope. This
+/// prevents multiple server instances from using the same storage at the same time.
+pub struct StorageLock {
+    /// The opened lock file, which releases the lock when being dropped.
+    _file: File,
+}
+
+impl StorageLock {
+    /// Acquire the lock for the storage at `path`. This fails immediately if the lock is already
+    /// held by another process.
+    pub fn acquire(path: &Path) -> Result<Self> {
+        fs::create_dir_all(path)
+            .with_context(|| format!("create storage path {}", path.display()))?;
+
+        let lock_path = path.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&lock_path)
+            .with_context(|| format!("open lock file {}", lock_path.display()))?;
+
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => {}
+            Err(nix::Error::Sys(Errno::EWOULDBLOCK)) => {
+                let pid = fs::read_to_string(&lock_path).unwrap_or_default();
+                bail!(
+                    "storage path {} is already in use by another server instance (pid {})",
+                    path.display(),
+                    if pid.trim().is_empty() {
+                        "unknown"
+                    } else {
+                        pid.trim()
+                    }
+                )
+            }
+            Err(e) => return Err(e).with_context(|| format!("lock {}", lock_path.display())),
+        }
+
+        // Record the owner of the lock for better error messages
+        file.set_len(0).context("truncate lock file")?;
+        write!(file, "{}", process::id()).context("write pid to lock file")?;
+
+        debug!("Acquired storage lock {}", lock_path.display());
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn acquire_success() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("storage");
+
+        StorageLock::acquire(&path)?;
+        assert_eq!(
+            fs::read_to_string(path.join(LOCK_FILE))?,
+            process::id().to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn acquire_fail_already_locked() -> Result<()> {
+        let dir = TempDir::new()?;
+
+        let _lock = StorageLock::acquire(dir.path())?;
+        let err = StorageLock::acquire(dir.path())
+            .err()
+            .context("second lock succeeded")?;
+        assert!(err.to_string().contains("already in use"));
+        assert!(err.to_string().contains(&process::id().to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn acquire_success_after_release() -> Result<()> {
+        let dir = TempDir::new()?;
+
+        let lock = StorageLock::acquire(dir.path())?;
+        drop(lock);
+        StorageLock::acquire(dir.path())?;
+        Ok(())
+    }
+}
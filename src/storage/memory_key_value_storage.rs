@@ -0,0 +1,151 @@
+//! An in-memory key value storage, which does not outlive the process.
+//!
+//! The storage is meant for tests and CI environments, where the state of the runtime is
+//! disposable and disk I/O only slows things down.
+
+use crate::{
+    clock::Clock,
+    storage::{KeyValueStorage, RecoveryReport},
+};
+use anyhow::{bail, format_err, Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::BTreeMap,
+    convert::AsRef,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+#[derive(Clone, Default)]
+/// A key value storage implementation keeping all records in memory.
+pub struct MemoryKeyValueStorage {
+    /// The serialized values by their keys, ordered to support prefix scans.
+    records: Arc<Mutex<BTreeMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl MemoryKeyValueStorage {
+    /// Lock the records for accessing them.
+    fn records(&self) -> Result<MutexGuard<BTreeMap<Vec<u8>, Vec<u8>>>> {
+        self.records
+            .lock()
+            .map_err(|e| format_err!("lock records: {}", e))
+    }
+}
+
+impl KeyValueStorage for MemoryKeyValueStorage {
+    /// Open an empty storage, whereas the `Path` is not used at all.
+    fn open(_: &Path) -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    fn get<K, V>(&mut self, key: K) -> Result<Option<V>>
+    where
+        K: AsRef<[u8]>,
+        V: DeserializeOwned,
+    {
+        Ok(self
+            .records()?
+            .get(key.as_ref())
+            .and_then(|x| bincode::deserialize(x).ok()))
+    }
+
+    fn insert<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        V: Serialize,
+    {
+        let value = bincode::serialize(&value).context("serialize value")?;
+        self.records()?.insert(key.as_ref().to_vec(), value);
+        Ok(())
+    }
+
+    fn scan_prefix<K, V>(&mut self, prefix: K) -> Result<Vec<V>>
+    where
+        K: AsRef<[u8]>,
+        V: DeserializeOwned,
+    {
+        let prefix = prefix.as_ref();
+        Ok(self
+            .records()?
+            .range(prefix.to_vec()..)
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .filter_map(|(_, v)| bincode::deserialize(v).ok())
+            .collect())
+    }
+
+    fn remove<K>(&mut self, key: K) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.records()?
+            .remove(key.as_ref())
+            .context("remove value")?;
+        Ok(())
+    }
+
+    /// Nothing to persist, the records are lost when the process exits.
+    fn persist(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Snapshots are not written, because they would never be read again.
+    fn snapshot(&self, _: &Path) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn recover(_: &Path, _: &dyn Clock) -> Result<(Self, RecoveryReport)> {
+        bail!("in-memory storage cannot be recovered")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn new_storage() -> Result<MemoryKeyValueStorage> {
+        MemoryKeyValueStorage::open(&PathBuf::new())
+    }
+
+    #[test]
+    fn get_insert_remove() -> Result<()> {
+        let mut db = new_storage()?;
+        assert!(db.get::<_, String>("key")?.is_none());
+
+        db.insert("key", "value")?;
+        assert_eq!(db.get::<_, String>("key")?.as_deref(), Some("value"));
+
+        db.remove("key")?;
+        assert!(db.get::<_, String>("key")?.is_none());
+        assert!(db.remove("key").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn scan_prefix_values() -> Result<()> {
+        let mut db = new_storage()?;
+        db.insert("prefix/b", "value 2")?;
+        db.insert("prefix/a", "value 1")?;
+        db.insert("other/c", "value 3")?;
+        db.insert("prefix/c", 42u8)?;
+        db.insert("prefiy/d", "value 4")?;
+
+        let values: Vec<String> = db.scan_prefix("prefix/")?;
+        assert_eq!(values, vec!["value 1", "value 2"]);
+        assert!(db.scan_prefix::<_, String>("none/")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn open_twice() -> Result<()> {
+        let mut db1 = new_storage()?;
+        let mut db2 = db1.clone();
+
+        db1.insert("key", "value")?;
+        assert_eq!(db2.get::<_, String>("key")?.as_deref(), Some("value"));
+
+        // Opening again does not share the records
+        assert!(new_storage()?.get::<_, String>("key")?.is_none());
+        Ok(())
+    }
+}
@@ -1,13 +1,24 @@
 //! Basic storage types
 
 pub mod default_key_value_storage;
+pub mod index;
+pub mod lock;
+pub mod memory_key_value_storage;
+pub mod snapshot;
 
+use crate::clock::Clock;
 use anyhow::Result;
+use getset::{CopyGetters, Getters};
 use serde::{de::DeserializeOwned, Serialize};
-use std::{convert::AsRef, path::Path};
+use std::{
+    convert::AsRef,
+    fmt,
+    path::{Path, PathBuf},
+};
 
 /// The data storage trait which defines the methods a storage implementation should fulfill.
-pub trait KeyValueStorage {
+/// Storages are cheap handles, whereas all clones refer to the same data.
+pub trait KeyValueStorage: Clone + Send + Sync + 'static {
     /// Load the storage from the provided path.
     fn open(path: &Path) -> Result<Self>
     where
@@ -25,6 +36,13 @@ pub trait KeyValueStorage {
         K: AsRef<[u8]>,
         V: Serialize;
 
+    /// Get all items whose keys start with the provided `prefix` from the storage, ordered by
+    /// their keys.
+    fn scan_prefix<K, V>(&mut self, prefix: K) -> Result<Vec<V>>
+    where
+        K: AsRef<[u8]>,
+        V: DeserializeOwned;
+
     /// Remove an item from the storage.
     fn remove<K>(&mut self, key: K) -> Result<()>
     where
@@ -32,4 +50,43 @@ pub trait KeyValueStorage {
 
     /// Save the storage to disk so that it is safe to stop the application.
     fn persist(&mut self) -> Result<()>;
+
+    /// Write a snapshot of all records into the storage at `path`, which can be used by
+    /// `recover` later on. Returns the number of written records.
+    fn snapshot(&self, path: &Path) -> Result<usize>;
+
+    /// Recover the corrupted storage at `path` from its latest snapshot, if available. The
+    /// `clock` provides the time the corrupted files get backed up at.
+    fn recover(path: &Path, clock: &dyn Clock) -> Result<(Self, RecoveryReport)>
+    where
+        Self: Sized;
+}
+
+#[derive(CopyGetters, Debug, Getters)]
+/// RecoveryReport describes the outcome of recovering a corrupted storage.
+pub struct RecoveryReport {
+    #[get = "pub"]
+    /// The directory containing the files of the corrupted database.
+    backup_path: PathBuf,
+
+    #[get_copy = "pub"]
+    /// The number of records restored from the snapshot.
+    restored: usize,
+
+    #[get_copy = "pub"]
+    /// The number of records of the snapshot which could not be restored.
+    dropped: usize,
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "restored {} records from snapshot, dropped {} unreadable records, \
+             corrupted database moved to {}",
+            self.restored,
+            self.dropped,
+            self.backup_path.display()
+        )
+    }
 }
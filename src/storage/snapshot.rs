@@ -0,0 +1,71 @@
+//! Snapshots of the storage, which allow recovering from a corrupted database.
+
+use anyhow::{Context, Result};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// The name of the snapshot file inside the storage path.
+pub const SNAPSHOT_FILE: &str = "snapshot.bin";
+
+#[derive(Debug, Default, Deserialize, Getters, PartialEq, Serialize)]
+/// Snapshot is a compacted copy of all raw records of the storage.
+pub struct Snapshot {
+    #[get = "pub"]
+    /// All raw key value pairs.
+    records: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Snapshot {
+    /// Create a new snapshot from the provided raw `records`.
+    pub fn new(records: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        Self { records }
+    }
+
+    /// Read the snapshot from the file at `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let content =
+            fs::read(path).with_context(|| format!("read snapshot {}", path.display()))?;
+        bincode::deserialize(&content).context("deserialize snapshot")
+    }
+
+    /// Write the snapshot to the file at `path`. The file is replaced atomically, which ensures
+    /// that an interrupted write does not destroy the previous snapshot.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        fs::write(
+            &tmp_path,
+            bincode::serialize(self).context("serialize snapshot")?,
+        )
+        .with_context(|| format!("write snapshot {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("rename snapshot to {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn write_read_success() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join(SNAPSHOT_FILE);
+        let snapshot = Snapshot::new(vec![(b"key".to_vec(), b"value".to_vec())]);
+
+        snapshot.write(&path)?;
+        assert!(!path.with_extension("tmp").exists());
+        assert_eq!(Snapshot::read(&path)?, snapshot);
+        Ok(())
+    }
+
+    #[test]
+    fn read_fail_corrupted() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join(SNAPSHOT_FILE);
+        fs::write(&path, b"\xff")?;
+        assert!(Snapshot::read(&path).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! Attach sessions, which stream the standard streams of a running container.
+//!
+//! Every session receives the output the log writer reads from the container after the session
+//! connected, so that multiple sessions can attach to the same container at once.
+
+use crate::{
+    config::AttachStdin,
+    container_log::{
+        attach::{Attachment, Output, Stdin},
+        format::Stream,
+    },
+    criapi::AttachRequest,
+    streaming::{
+        protocol::{self, CLOSE, STDERR, STDIN, STDOUT},
+        recording::Recorder,
+    },
+};
+use anyhow::format_err;
+use futures_util::{
+    stream::{SplitStream, StreamExt},
+    SinkExt,
+};
+use log::{debug, info};
+use tokio::sync::{
+    broadcast::{self, RecvError},
+    mpsc,
+};
+use warp::ws::{Message, WebSocket};
+
+/// The number of messages which can be queued for sending to the client.
+const QUEUE_SIZE: usize = 16;
+
+/// Stream the `attachment` of the container of the `request` over the `socket` until either the
+/// container exits or the client disconnects. The `mode` defines whether the input is shared,
+/// whereas all messages get recorded by the `recorder` if set.
+pub async fn attach(
+    socket: WebSocket,
+    attachment: Attachment,
+    request: AttachRequest,
+    mode: AttachStdin,
+    recorder: Option<Recorder>,
+) {
+    let (mut sink, stream) = socket.split();
+    let (mut tx, mut rx) = mpsc::channel::<Vec<u8>>(QUEUE_SIZE);
+    let output_recorder = recorder.clone();
+    let writer = tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            if let Some(recorder) = &output_recorder {
+                recorder.record(&frame);
+            }
+            if let Err(e) = sink.send(Message::binary(frame)).await {
+                debug!("Unable to send attach output: {}", e);
+                break;
+            }
+        }
+        sink.close().await.ok();
+    });
+
+    let id = &request.container_id;
+    let Attachment { output, stdin } = attachment;
+    let stdin = stdin.filter(|_| request.stdin);
+
+    // The hold gets released once the session ends
+    let (mut stdin, _hold) = match (stdin, mode) {
+        (Some(stdin), AttachStdin::Exclusive) => match stdin.hold() {
+            Some(hold) => (Some(stdin), Some(hold)),
+            None => {
+                let e = format_err!("input of container {} held by another session", id);
+                info!("Rejecting attach session: {:#}", e);
+                tx.send(protocol::error_status(&e)).await.ok();
+                drop(tx);
+                writer.await.ok();
+                return;
+            }
+        },
+        (stdin, _) => (stdin, None),
+    };
+
+    info!("Attaching to container {}", id);
+    tokio::select! {
+        _ = copy_output(output, tx.clone(), request.stdout, request.stderr) => {
+            info!("Container {} of attach session exited", id);
+        }
+        _ = copy_input(stream, &mut stdin, recorder.as_ref()) => {
+            info!("Detached from container {}", id);
+        }
+    }
+
+    // Containers with a single input session see EOF once it detaches
+    if let Some(stdin) = stdin.filter(Stdin::once) {
+        stdin.close().await;
+    }
+    drop(tx);
+    writer.await.ok();
+}
+
+/// Send the requested `stdout` and `stderr` chunks received from the `output` as messages to
+/// `tx`, until the container exits.
+async fn copy_output(
+    mut output: broadcast::Receiver<Output>,
+    mut tx: mpsc::Sender<Vec<u8>>,
+    stdout: bool,
+    stderr: bool,
+) {
+    loop {
+        let (stream, data) = match output.recv().await {
+            Ok(chunk) => chunk,
+            // Slow clients miss output instead of stalling the container
+            Err(RecvError::Lagged(n)) => {
+                debug!("Attach session missed {} output chunks", n);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let channel = match stream {
+            Stream::Stdout if stdout => STDOUT,
+            Stream::Stderr if stderr => STDERR,
+            _ => continue,
+        };
+        if tx.send(protocol::frame(channel, &data)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Write the messages of the standard input channel from the `stream` into the `stdin`, until
+/// the client disconnects. The `stdin` is taken once the client closes it, whereas every message
+/// gets recorded by the `recorder`.
+async fn copy_input(
+    mut stream: SplitStream<WebSocket>,
+    stdin: &mut Option<Stdin>,
+    recorder: Option<&Recorder>,
+) {
+    while let Some(Ok(message)) = stream.next().await {
+        if message.is_close() {
+            break;
+        }
+        if let Some(recorder) = recorder {
+            recorder.record(message.as_bytes());
+        }
+        let (channel, data) = match protocol::parse(message.as_bytes()) {
+            Some(x) => x,
+            None => continue,
+        };
+        match channel {
+            STDIN => {
+                let open = match stdin.as_ref() {
+                    Some(input) => input.write(data).await.unwrap_or_else(|e| {
+                        debug!("Unable to write attach input: {:#}", e);
+                        false
+                    }),
+                    None => true,
+                };
+                if !open {
+                    debug!("Closing attach input of closed container input");
+                    stdin.take();
+                }
+            }
+            CLOSE if data.first() == Some(&STDIN) => {
+                if let Some(input) = stdin.take().filter(Stdin::once) {
+                    input.close().await;
+                }
+            }
+            channel => debug!("Ignoring message on attach channel {}", channel),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Pseudo terminals of exec sessions, which the OCI runtime sends via a console socket.
+
+use crate::streaming::protocol::TerminalSize;
+use anyhow::{bail, Context, Result};
+use nix::{
+    cmsg_space,
+    errno::Errno,
+    pty::Winsize,
+    sys::{
+        socket::{recvmsg, ControlMessageOwned, MsgFlags},
+        uio::IoVec,
+    },
+};
+use std::{
+    fs::File,
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    time::Duration,
+};
+use tokio::{net::UnixStream, time};
+
+/// The interval for retrying to receive the terminal from a connected console socket.
+const RECEIVE_INTERVAL: Duration = Duration::from_millis(10);
+
+nix::ioctl_write_ptr_bad!(
+    /// Set the window size of a terminal.
+    set_window_size,
+    nix::libc::TIOCSWINSZ,
+    Winsize
+);
+
+/// Receive the master of the pseudo terminal, which the OCI runtime sends over the connected
+/// console socket `stream`.
+pub async fn receive(stream: &UnixStream) -> Result<File> {
+    loop {
+        match receive_fd(stream.as_raw_fd()) {
+            Ok(fd) => return Ok(unsafe { File::from_raw_fd(fd) }),
+            Err(nix::Error::Sys(Errno::EAGAIN)) => time::delay_for(RECEIVE_INTERVAL).await,
+            Err(e) => return Err(e).context("receive terminal from console socket"),
+        }
+    }
+}
+
+/// Receive a single file descriptor from the unix socket `fd`.
+fn receive_fd(fd: RawFd) -> nix::Result<RawFd> {
+    // The runtime sends the name of the terminal alongside its file descriptor
+    let mut buf = [0u8; 4096];
+    let iov = [IoVec::from_mut_slice(&mut buf)];
+    let mut cmsg = cmsg_space!([RawFd; 1]);
+    let msg = recvmsg(fd, &iov, Some(&mut cmsg), MsgFlags::empty())?;
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::ScmRights(fds) = cmsg {
+            if let Some(fd) = fds.first() {
+                return Ok(*fd);
+            }
+        }
+    }
+    Err(nix::Error::Sys(Errno::EBADMSG))
+}
+
+/// Resize the pseudo `terminal` to the provided `size`.
+pub fn resize(terminal: &File, size: &TerminalSize) -> Result<()> {
+    if size.width == 0 || size.height == 0 {
+        bail!("invalid terminal size {}x{}", size.width, size.height)
+    }
+    let winsize = Winsize {
+        ws_row: size.height,
+        ws_col: size.width,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let res = unsafe { set_window_size(terminal.as_raw_fd(), &winsize) };
+    res.map(|_| ()).context("set terminal size")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::{
+        pty::openpty,
+        sys::socket::{sendmsg, ControlMessage},
+        unistd::close,
+    };
+    use std::os::unix::net;
+
+    #[tokio::test]
+    async fn receive_and_resize_success() -> Result<()> {
+        let pty = openpty(None, None)?;
+        close(pty.slave)?;
+        let (local, remote) = net::UnixStream::pair()?;
+
+        let iov = [IoVec::from_slice(b"/dev/pts/0")];
+        let fds = [pty.master];
+        sendmsg(
+            remote.as_raw_fd(),
+            &iov,
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )?;
+        close(pty.master)?;
+
+        let terminal = receive(&UnixStream::from_std(local)?).await?;
+        resize(
+            &terminal,
+            &TerminalSize {
+                width: 80,
+                height: 24,
+            },
+        )?;
+        assert!(resize(
+            &terminal,
+            &TerminalSize {
+                width: 0,
+                height: 24
+            }
+        )
+        .is_err());
+        Ok(())
+    }
+}
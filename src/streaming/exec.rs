@@ -0,0 +1,291 @@
+//! Exec sessions, which run a command inside of a running container.
+
+use crate::{
+    criapi::ExecRequest,
+    oci::runtime::OciRuntime,
+    streaming::{
+        console,
+        protocol::{self, CLOSE, RESIZE, STDERR, STDIN, STDOUT},
+        recording::Recorder,
+    },
+};
+use anyhow::{bail, format_err, Context, Result};
+use futures_util::{
+    stream::{SplitStream, StreamExt},
+    SinkExt,
+};
+use log::{debug, info, warn};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::UnixListener,
+    process::Child,
+    sync::mpsc,
+    time,
+};
+use warp::ws::{Message, WebSocket};
+
+/// The number of messages which can be queued for sending to the client.
+const QUEUE_SIZE: usize = 16;
+
+/// The size of the buffer for reading the output of the process.
+const BUFFER_SIZE: usize = 32 * 1024;
+
+/// The length of the random suffix of console socket names.
+const SOCKET_SUFFIX_LEN: usize = 8;
+
+/// The time the OCI runtime has for sending the terminal of the process.
+const CONSOLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The input stream of a process.
+type Input = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// The output stream of a process.
+type Output = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Run the command of the exec `request` via the `runtime` and stream it over the `socket`. The
+/// console socket of terminal sessions gets created inside of the container `bundle`. All
+/// messages get recorded by the `recorder` if set.
+pub async fn exec(
+    socket: WebSocket,
+    runtime: OciRuntime,
+    bundle: PathBuf,
+    request: ExecRequest,
+    recorder: Option<Recorder>,
+) {
+    let (mut sink, stream) = socket.split();
+    let (mut tx, mut rx) = mpsc::channel::<Vec<u8>>(QUEUE_SIZE);
+    let output_recorder = recorder.clone();
+    let writer = tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            if let Some(recorder) = &output_recorder {
+                recorder.record(&frame);
+            }
+            if let Err(e) = sink.send(Message::binary(frame)).await {
+                debug!("Unable to send exec output: {}", e);
+                break;
+            }
+        }
+        sink.close().await.ok();
+    });
+
+    info!(
+        "Executing {:?} in container {}",
+        request.cmd, request.container_id
+    );
+    let status = match run(stream, tx.clone(), &runtime, &bundle, &request, recorder).await {
+        Ok(exit_code) => {
+            info!(
+                "Exec in container {} exited with {}",
+                request.container_id, exit_code
+            );
+            protocol::exit_status(exit_code)
+        }
+        Err(e) => {
+            warn!("Exec in container {} failed: {:#}", request.container_id, e);
+            protocol::error_status(&e)
+        }
+    };
+    tx.send(status).await.ok();
+    drop(tx);
+    writer.await.ok();
+}
+
+/// Run the process of the `request` until it exits and return its exit code. The output gets
+/// sent to `tx`, whereas the input is read from the `stream` and recorded by the `recorder`.
+async fn run(
+    stream: SplitStream<WebSocket>,
+    tx: mpsc::Sender<Vec<u8>>,
+    runtime: &OciRuntime,
+    bundle: &Path,
+    request: &ExecRequest,
+    recorder: Option<Recorder>,
+) -> Result<i32> {
+    if request.tty {
+        return run_terminal(stream, tx, runtime, bundle, request, recorder).await;
+    }
+
+    let pipe = |enabled: bool| {
+        if enabled {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        }
+    };
+    let mut child = runtime
+        .exec(&request.container_id, &request.cmd, None)
+        .stdin(pipe(request.stdin))
+        .stdout(pipe(request.stdout))
+        .stderr(pipe(request.stderr))
+        .spawn()
+        .context("run exec command")?;
+
+    let input = child.stdin.take().map(|x| Box::new(x) as Input);
+    let mut outputs = vec![];
+    if let Some(stdout) = child.stdout.take() {
+        outputs.push((STDOUT, Box::new(stdout) as Output));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        outputs.push((STDERR, Box::new(stderr) as Output));
+    }
+    stream_process(child, stream, tx, input, outputs, None, recorder).await
+}
+
+/// Run the process of the `request` inside of a pseudo terminal, which the runtime sends to a
+/// console socket inside of the `bundle`.
+async fn run_terminal(
+    stream: SplitStream<WebSocket>,
+    tx: mpsc::Sender<Vec<u8>>,
+    runtime: &OciRuntime,
+    bundle: &Path,
+    request: &ExecRequest,
+    recorder: Option<Recorder>,
+) -> Result<i32> {
+    let suffix: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SOCKET_SUFFIX_LEN)
+        .collect();
+    let path = bundle.join(format!("exec-{}.sock", suffix));
+    let mut listener = UnixListener::bind(&path)
+        .with_context(|| format!("bind console socket {}", path.display()))?;
+    let res: Result<(Child, File)> = async {
+        let mut child = runtime
+            .exec(&request.container_id, &request.cmd, Some(&path))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("run exec command")?;
+
+        let connection = tokio::select! {
+            res = time::timeout(CONSOLE_TIMEOUT, listener.accept()) => {
+                res.map_err(|_| format_err!("timeout waiting for the terminal"))?
+                    .context("accept console socket connection")?
+                    .0
+            }
+            res = &mut child => {
+                bail!("exec command exited with {} before sending the terminal", res?)
+            }
+        };
+        let terminal = console::receive(&connection).await?;
+        Ok((child, terminal))
+    }
+    .await;
+    if let Err(e) = fs::remove_file(&path) {
+        debug!("Unable to remove console socket {}: {}", path.display(), e);
+    }
+    let (child, terminal) = res?;
+
+    // Reading and writing the terminal needs separate handles, whereas resizing needs another one
+    // which stays open if the client closes the input
+    let input = if request.stdin {
+        Some(Box::new(tokio::fs::File::from_std(terminal.try_clone()?)) as Input)
+    } else {
+        None
+    };
+    let resize = terminal.try_clone()?;
+    let outputs = if request.stdout {
+        vec![(
+            STDOUT,
+            Box::new(tokio::fs::File::from_std(terminal)) as Output,
+        )]
+    } else {
+        vec![]
+    };
+    stream_process(child, stream, tx, input, outputs, Some(resize), recorder).await
+}
+
+/// Stream the `input` and `outputs` of the `child` until it exits and return its exit code.
+async fn stream_process(
+    child: Child,
+    stream: SplitStream<WebSocket>,
+    tx: mpsc::Sender<Vec<u8>>,
+    input: Option<Input>,
+    outputs: Vec<(u8, Output)>,
+    terminal: Option<File>,
+    recorder: Option<Recorder>,
+) -> Result<i32> {
+    let outputs: Vec<_> = outputs
+        .into_iter()
+        .map(|(channel, output)| tokio::spawn(copy_output(channel, output, tx.clone())))
+        .collect();
+
+    // The input ends with the connection, which outlives the process
+    tokio::spawn(copy_input(stream, input, terminal, recorder));
+
+    let status = child.await.context("wait for exec command")?;
+    for output in outputs {
+        output.await.ok();
+    }
+    status
+        .code()
+        .ok_or_else(|| format_err!("exec command terminated by {}", status))
+}
+
+/// Send everything read from the `output` as messages of the `channel` to `tx`.
+async fn copy_output(channel: u8, mut output: Output, mut tx: mpsc::Sender<Vec<u8>>) {
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        // Terminals fail with EIO instead of EOF once the process exited
+        match output.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if tx.send(protocol::frame(channel, &buf[..n])).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Write the messages of the standard input channel from the `stream` into the `input` and
+/// apply the resize messages to the `terminal`. Every message gets recorded by the `recorder`.
+async fn copy_input(
+    mut stream: SplitStream<WebSocket>,
+    mut input: Option<Input>,
+    terminal: Option<File>,
+    recorder: Option<Recorder>,
+) {
+    while let Some(Ok(message)) = stream.next().await {
+        if message.is_close() {
+            break;
+        }
+        if let Some(recorder) = &recorder {
+            recorder.record(message.as_bytes());
+        }
+        let (channel, data) = match protocol::parse(message.as_bytes()) {
+            Some(x) => x,
+            None => continue,
+        };
+        match channel {
+            STDIN => {
+                let failed = match input.as_mut() {
+                    Some(input) => input.write_all(data).await.is_err(),
+                    None => false,
+                };
+                if failed {
+                    debug!("Closing exec input after failed write");
+                    input = None;
+                }
+            }
+            RESIZE => {
+                if let Some(terminal) = &terminal {
+                    if let Err(e) =
+                        protocol::parse_resize(data).and_then(|x| console::resize(terminal, &x))
+                    {
+                        warn!("Unable to resize exec terminal: {:#}", e);
+                    }
+                }
+            }
+            // Dropping the input closes it, which signals EOF to the process
+            CLOSE if data.first() == Some(&STDIN) => input = None,
+            channel => debug!("Ignoring message on exec channel {}", channel),
+        }
+    }
+}
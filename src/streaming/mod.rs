@@ -0,0 +1,555 @@
+//! The streaming server for exec, attach and port forward sessions.
+//!
+//! The Exec, Attach and PortForward RPCs only register a session and return its URL, which the
+//! kubelet connects to afterwards. The sessions are streamed over WebSockets using the channel protocols
+//! of the Kubernetes streaming API.
+
+pub mod attach;
+pub mod console;
+pub mod exec;
+pub mod port_forward;
+pub mod protocol;
+pub mod recording;
+pub mod session;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    config::Config,
+    container_log::manager::LogManager,
+    event::EventBus,
+    oci::runtime::OciRuntime,
+    streaming::{
+        recording::{Recorder, Recording},
+        session::{Session, SessionCache},
+    },
+};
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use std::{net::SocketAddr, sync::Arc};
+use warp::{
+    http::StatusCode,
+    reply::{with_header, with_status},
+    ws::Ws,
+    Filter, Reply,
+};
+
+/// The WebSocket header for negotiating the protocol.
+const PROTOCOL_HEADER: &str = "sec-websocket-protocol";
+
+#[derive(Clone)]
+/// StreamingServer serves the sessions of the session cache.
+pub struct StreamingServer {
+    /// The server configuration.
+    config: Arc<Config>,
+
+    /// The sessions which can be connected to.
+    sessions: SessionCache,
+
+    /// The log writers, which hold the standard streams of the containers attached to.
+    logs: LogManager,
+
+    /// The event bus announcing finished session recordings.
+    events: EventBus,
+
+    /// The clock the timestamps of session recordings are based on.
+    clock: Arc<dyn Clock>,
+}
+
+impl StreamingServer {
+    /// Create a new streaming server for the `sessions`, which attaches to containers via the
+    /// `logs` and announces recorded sessions via the `events`.
+    pub fn new(
+        config: Arc<Config>,
+        sessions: SessionCache,
+        logs: LogManager,
+        events: EventBus,
+    ) -> Self {
+        Self {
+            config,
+            sessions,
+            logs,
+            events,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use the `clock` for the timestamps of session recordings.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Serve the sessions on the configured address until the server fails.
+    pub async fn serve(self) -> Result<()> {
+        let address = SocketAddr::new(
+            self.config.streaming_address(),
+            self.config.streaming_port(),
+        );
+        let cert = self.config.streaming_tls_cert().clone();
+        let key = self.config.streaming_tls_key().clone();
+        let routes = self.routes();
+
+        match (cert, key) {
+            (Some(cert), Some(key)) => {
+                // The TLS server panics on invalid files, so they are checked beforehand
+                for path in &[&cert, &key] {
+                    std::fs::metadata(path)
+                        .with_context(|| format!("access streaming TLS file {}", path.display()))?;
+                }
+                info!("Streaming server listening on https://{}", address);
+                warp::serve(routes)
+                    .tls()
+                    .cert_path(cert)
+                    .key_path(key)
+                    .run(address)
+                    .await;
+            }
+            (None, None) => {
+                let (address, server) = warp::serve(routes)
+                    .try_bind_ephemeral(address)
+                    .context("bind streaming server")?;
+                info!("Streaming server listening on http://{}", address);
+                server.await;
+            }
+            _ => bail!("streaming TLS requires both a certificate and a key"),
+        }
+        Ok(())
+    }
+
+    /// Build the routes serving the sessions, whose URLs are `/<kind>/<token>`.
+    fn routes(self) -> impl Filter<Extract = (Box<dyn Reply>,), Error = warp::Rejection> + Clone {
+        warp::path!(String / String)
+            .and(warp::ws())
+            .and(warp::header::optional::<String>(PROTOCOL_HEADER))
+            .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+            .map(move |kind, token, ws, protocols, query| {
+                self.upgrade(&kind, &token, ws, protocols, &query)
+            })
+    }
+
+    /// Upgrade the connection to the session `token` of the `kind` to a WebSocket, which uses
+    /// the preferred of the requested `protocols`.
+    fn upgrade(
+        &self,
+        kind: &str,
+        token: &str,
+        ws: Ws,
+        protocols: Option<String>,
+        query: &str,
+    ) -> Box<dyn Reply> {
+        let session = match self.sessions.take(token) {
+            Some(session) if session.kind() == kind => session,
+            _ => {
+                debug!("Streaming session {} not found", token);
+                return Box::new(StatusCode::NOT_FOUND);
+            }
+        };
+
+        let supported = match session {
+            Session::Exec(..) | Session::Attach(..) => protocol::EXEC_PROTOCOLS,
+            Session::PortForward { .. } => protocol::PORT_FORWARD_PROTOCOLS,
+        };
+        let protocol = match protocol::negotiate(protocols.as_deref(), supported) {
+            Some(protocol) => protocol,
+            None => {
+                return Box::new(with_status(
+                    format!("supported protocols: {}", supported.join(", ")),
+                    StatusCode::BAD_REQUEST,
+                ))
+            }
+        };
+
+        match session {
+            Session::Exec(request, recording) => {
+                let runtime = OciRuntime::new(self.config.oci_runtime());
+                let bundle = self.config.container_path().join(&request.container_id);
+                let recorder = match self.recorder(recording.as_ref()) {
+                    Ok(recorder) => recorder,
+                    Err(reply) => return reply,
+                };
+                let events = self.events.clone();
+                let reply = ws.on_upgrade(move |socket| async move {
+                    exec::exec(socket, runtime, bundle, request, recorder.clone()).await;
+                    if let Some(recorder) = recorder {
+                        recorder.finish(&events);
+                    }
+                });
+                Box::new(with_header(reply, PROTOCOL_HEADER, protocol))
+            }
+            Session::Attach(request, recording) => {
+                // The container may have exited since the session got requested
+                let attachment = match self.logs.attach(&request.container_id) {
+                    Some(attachment) => attachment,
+                    None => {
+                        debug!("Container {} is not running", request.container_id);
+                        return Box::new(StatusCode::NOT_FOUND);
+                    }
+                };
+                let mode = self.config.attach_stdin();
+                let recorder = match self.recorder(recording.as_ref()) {
+                    Ok(recorder) => recorder,
+                    Err(reply) => return reply,
+                };
+                let events = self.events.clone();
+                let reply = ws.on_upgrade(move |socket| async move {
+                    attach::attach(socket, attachment, request, mode, recorder.clone()).await;
+                    if let Some(recorder) = recorder {
+                        recorder.finish(&events);
+                    }
+                });
+                Box::new(with_header(reply, PROTOCOL_HEADER, protocol))
+            }
+            Session::PortForward { netns, ports } => {
+                // Clients like kubectl request the ports when connecting
+                let ports = match port_forward::parse_ports(query) {
+                    Ok(requested) if requested.is_empty() => ports,
+                    Ok(requested) => requested,
+                    Err(e) => {
+                        return Box::new(with_status(format!("{:#}", e), StatusCode::BAD_REQUEST))
+                    }
+                };
+                if ports.is_empty() || ports.len() > port_forward::MAX_PORTS {
+                    return Box::new(with_status(
+                        format!("between 1 and {} ports required", port_forward::MAX_PORTS),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+                let reply =
+                    ws.on_upgrade(move |socket| port_forward::port_forward(socket, netns, ports));
+                Box::new(with_header(reply, PROTOCOL_HEADER, protocol))
+            }
+        }
+    }
+
+    /// Create the recorder of a session with the `recording`. Sessions which should be recorded
+    /// are refused if the recording cannot be created, because they must not bypass the audit.
+    fn recorder(&self, recording: Option<&Recording>) -> Result<Option<Recorder>, Box<dyn Reply>> {
+        let recording = match recording {
+            Some(recording) => recording,
+            None => return Ok(None),
+        };
+        Recorder::create(recording, self.clock.clone())
+            .map(Some)
+            .map_err(|e| {
+                warn!("Unable to record streaming session: {:#}", e);
+                Box::new(StatusCode::INTERNAL_SERVER_ERROR) as Box<dyn Reply>
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ConfigBuilder,
+        container_log::{
+            attach::Stdin,
+            pipe::{pipe, AsyncPipe},
+        },
+        criapi::{AttachRequest, ExecRequest},
+        event::Event,
+        oci::runtime::tests::fake_runtime,
+        streaming::recording::SessionInfo,
+    };
+    use anyhow::format_err;
+    use serde_json::Value;
+    use std::io::Write;
+    use tempfile::tempdir;
+    use tokio::io::AsyncReadExt;
+    use warp::ws::Message;
+
+    /// Create a new server whose OCI runtime is a fake one in `dir`, which records sessions into
+    /// the `recordings` directory of it.
+    fn new_server(dir: &std::path::Path) -> Result<StreamingServer> {
+        let config = ConfigBuilder::default()
+            .oci_runtime(fake_runtime(dir, "running")?)
+            .container_path(dir)
+            .session_recording_path(Some(dir.join("recordings")))
+            .build()?;
+        let sessions = SessionCache::new(&config);
+        Ok(StreamingServer::new(
+            Arc::new(config),
+            sessions,
+            LogManager::default(),
+            EventBus::default(),
+        ))
+    }
+
+    /// Retrieve the path of the session `url`.
+    fn path(url: &str) -> Result<String> {
+        url.splitn(4, '/')
+            .nth(3)
+            .map(|x| format!("/{}", x))
+            .ok_or_else(|| format_err!("no path in {}", url))
+    }
+
+    #[tokio::test]
+    async fn exec_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_server(dir.path())?;
+        let url = sut.sessions.insert(Session::Exec(
+            ExecRequest {
+                container_id: "id".into(),
+                cmd: vec![
+                    "sh".into(),
+                    "-c".into(),
+                    "echo out; echo err >&2; exit 3".into(),
+                ],
+                tty: false,
+                stdin: false,
+                stdout: true,
+                stderr: true,
+            },
+            None,
+        ));
+
+        let mut client = warp::test::ws()
+            .path(&path(&url)?)
+            .header(PROTOCOL_HEADER, "v4.channel.k8s.io")
+            .handshake(sut.routes())
+            .await?;
+
+        let (mut stdout, mut stderr) = (vec![], vec![]);
+        let status = loop {
+            let message: Message = client.recv().await?;
+            match protocol::parse(message.as_bytes()) {
+                Some((protocol::STDOUT, data)) => stdout.extend_from_slice(data),
+                Some((protocol::STDERR, data)) => stderr.extend_from_slice(data),
+                Some((protocol::ERROR, data)) => break serde_json::from_slice::<Value>(data)?,
+                _ => {}
+            }
+        };
+        assert_eq!(stdout, b"out\n");
+        assert_eq!(stderr, b"err\n");
+        assert_eq!(status["reason"], "NonZeroExitCode");
+        assert_eq!(status["details"]["causes"][0]["message"], "3");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exec_success_stdin() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_server(dir.path())?;
+        let mut events = sut.events.subscribe();
+        let request = ExecRequest {
+            container_id: "id".into(),
+            cmd: vec!["head".into(), "-n1".into()],
+            tty: false,
+            stdin: true,
+            stdout: true,
+            stderr: false,
+        };
+        let info = SessionInfo::new("exec", "id", "pod", "default", "name", &request.cmd);
+        let recording = Recording::new(&sut.config, info, 10);
+        let url = sut
+            .sessions
+            .insert(Session::Exec(request, recording.clone()));
+
+        let mut client = warp::test::ws()
+            .path(&path(&url)?)
+            .header(PROTOCOL_HEADER, "v5.channel.k8s.io")
+            .handshake(sut.routes())
+            .await?;
+        client
+            .send(Message::binary(protocol::frame(
+                protocol::STDIN,
+                b"input\n",
+            )))
+            .await;
+
+        let mut stdout = vec![];
+        let status = loop {
+            let message: Message = client.recv().await?;
+            match protocol::parse(message.as_bytes()) {
+                Some((protocol::STDOUT, data)) => stdout.extend_from_slice(data),
+                Some((protocol::ERROR, data)) => break serde_json::from_slice::<Value>(data)?,
+                _ => {}
+            }
+        };
+        assert_eq!(stdout, b"input\n");
+        assert_eq!(status["status"], "Success");
+
+        // The recording is announced once the session ended
+        let event = events.recv().await?;
+        assert!(
+            matches!(&event, Event::SessionRecorded { container_id, .. } if container_id == "id"),
+            "{:?}",
+            event
+        );
+        let path = recording.context("not recorded")?.path().clone();
+        let content = std::fs::read_to_string(path)?;
+        let streams: Vec<String> = content
+            .lines()
+            .skip(1)
+            .map(|x| Ok(serde_json::from_str::<Value>(x)?["stream"].to_string()))
+            .collect::<Result<_>>()?;
+        assert_eq!(streams.first().map(String::as_str), Some("\"stdin\""));
+        assert!(streams.contains(&"\"stdout\"".to_string()));
+        assert_eq!(streams.last().map(String::as_str), Some("\"error\""));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn attach_success() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_server(dir.path())?;
+        let ((stdout, mut stdout_write), (stderr, _stderr_write)) = (pipe()?, pipe()?);
+        let (stdin, stdin_write) = pipe()?;
+        sut.logs
+            .start("id", &dir.path().join("0.log"), stdout, stderr, None)?;
+        sut.logs.set_stdin("id", Stdin::new(stdin_write, true)?);
+        let request = AttachRequest {
+            container_id: "id".into(),
+            stdin: true,
+            tty: false,
+            stdout: true,
+            stderr: false,
+        };
+
+        let mut clients = vec![];
+        for _ in 0..2 {
+            let url = sut.sessions.insert(Session::Attach(request.clone(), None));
+            let client = warp::test::ws()
+                .path(&path(&url)?)
+                .header(PROTOCOL_HEADER, "v4.channel.k8s.io")
+                .handshake(sut.clone().routes())
+                .await?;
+            clients.push(client);
+        }
+
+        // Every attached session receives the output
+        stdout_write.write_all(b"output\n")?;
+        for client in &mut clients {
+            let message: Message = client.recv().await?;
+            assert_eq!(
+                protocol::parse(message.as_bytes()),
+                Some((protocol::STDOUT, &b"output\n"[..]))
+            );
+        }
+
+        // Closing the input of a single input session closes the one of the container
+        clients[0]
+            .send(Message::binary(protocol::frame(protocol::STDIN, b"input")))
+            .await;
+        clients[0]
+            .send(Message::binary(protocol::frame(
+                protocol::CLOSE,
+                &[protocol::STDIN],
+            )))
+            .await;
+        let mut input = vec![];
+        AsyncPipe::new(stdin)?.read_to_end(&mut input).await?;
+        assert_eq!(input, b"input");
+        sut.logs.stop("id");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn attach_fail_not_running() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_server(dir.path())?;
+        let url = sut.sessions.insert(Session::Attach(
+            AttachRequest {
+                container_id: "id".into(),
+                stdin: false,
+                tty: false,
+                stdout: true,
+                stderr: true,
+            },
+            None,
+        ));
+
+        let res = warp::test::ws()
+            .path(&path(&url)?)
+            .header(PROTOCOL_HEADER, "v4.channel.k8s.io")
+            .handshake(sut.routes())
+            .await;
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upgrade_fail_not_found() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_server(dir.path())?;
+        let url = sut.sessions.insert(Session::PortForward {
+            netns: None,
+            ports: vec![8080],
+        });
+
+        // The session kind has to match and every session can be connected to only once
+        let exec_path = path(&url)?.replace("portforward", "exec");
+        for path in &["/exec/unknown".to_string(), exec_path, path(&url)?] {
+            let res = warp::test::ws()
+                .path(path)
+                .header(PROTOCOL_HEADER, "v4.channel.k8s.io")
+                .handshake(sut.clone().routes())
+                .await;
+            assert!(res.is_err(), "{}", path);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upgrade_fail_unsupported_protocol() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = new_server(dir.path())?;
+        let url = sut.sessions.insert(Session::PortForward {
+            netns: None,
+            ports: vec![8080],
+        });
+
+        let res = warp::test::ws()
+            .path(&path(&url)?)
+            .header(PROTOCOL_HEADER, "channel.k8s.io")
+            .handshake(sut.routes())
+            .await;
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn port_forward_success() -> Result<()> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server = std::thread::spawn(move || -> Result<()> {
+            use std::io::{Read, Write};
+            let (mut connection, _) = listener.accept()?;
+            let mut buf = [0; 4];
+            connection.read_exact(&mut buf)?;
+            connection.write_all(b"pong")?;
+            Ok(())
+        });
+
+        let dir = tempdir()?;
+        let sut = new_server(dir.path())?;
+        let url = sut.sessions.insert(Session::PortForward {
+            netns: None,
+            ports: vec![],
+        });
+        let mut client = warp::test::ws()
+            .path(&format!("{}?port={}", path(&url)?, port))
+            .header(PROTOCOL_HEADER, "v4.channel.k8s.io")
+            .handshake(sut.routes())
+            .await?;
+
+        // Both channels announce the port first
+        for channel in 0..2 {
+            let message: Message = client.recv().await?;
+            assert_eq!(
+                protocol::parse(message.as_bytes()),
+                Some((channel, &port.to_le_bytes()[..]))
+            );
+        }
+
+        client
+            .send(Message::binary(protocol::frame(0, b"ping")))
+            .await;
+        let message: Message = client.recv().await?;
+        assert_eq!(protocol::parse(message.as_bytes()), Some((0, &b"pong"[..])));
+        server
+            .join()
+            .map_err(|_| format_err!("server thread panicked"))??;
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+//! Port forward sessions, which forward TCP connections into the network of a pod sandbox.
+//!
+//! Every forwarded port uses a pair of channels: the data channel `2 * i` and the error channel
+//! `2 * i + 1` for the `i`-th port. The first message on both channels is the port number as
+//! little endian `u16`, which allows the client to match the channels to its ports.
+
+use crate::{network::netns, streaming::protocol};
+use anyhow::{Context, Result};
+use futures_util::{stream::StreamExt, SinkExt};
+use log::{debug, info, warn};
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, TcpStream as StdTcpStream},
+    path::PathBuf,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    task,
+};
+use warp::ws::{Message, WebSocket};
+
+/// The maximum number of ports of a single session, which is limited by the channel numbers.
+pub const MAX_PORTS: usize = 128;
+
+/// The number of messages which can be queued for sending to the client.
+const QUEUE_SIZE: usize = 16;
+
+/// The size of the buffer for reading from the forwarded connections.
+const BUFFER_SIZE: usize = 32 * 1024;
+
+/// Forward the `ports` inside of the network namespace `netns` over the `socket`. The host
+/// network is used if `netns` is `None`.
+pub async fn port_forward(socket: WebSocket, netns: Option<PathBuf>, ports: Vec<u16>) {
+    let (mut sink, mut stream) = socket.split();
+    let (mut tx, mut rx) = mpsc::channel::<Vec<u8>>(QUEUE_SIZE);
+    let writer = tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            if let Err(e) = sink.send(Message::binary(frame)).await {
+                debug!("Unable to send port forward data: {}", e);
+                break;
+            }
+        }
+        sink.close().await.ok();
+    });
+
+    let mut connections: HashMap<u8, mpsc::Sender<Vec<u8>>> = HashMap::new();
+    for (i, port) in ports.iter().enumerate() {
+        let (data, error) = ((2 * i) as u8, (2 * i + 1) as u8);
+        let prefix = port.to_le_bytes();
+        tx.send(protocol::frame(data, &prefix)).await.ok();
+        tx.send(protocol::frame(error, &prefix)).await.ok();
+
+        match connect(netns.clone(), *port).await {
+            Ok(connection) => {
+                info!("Forwarding port {}", port);
+                let (input, rx) = mpsc::channel(QUEUE_SIZE);
+                tokio::spawn(forward(data, connection, rx, tx.clone()));
+                connections.insert(data, input);
+            }
+            Err(e) => {
+                warn!("Unable to forward port {}: {:#}", port, e);
+                let message = format!("{:#}", e);
+                tx.send(protocol::frame(error, message.as_bytes()))
+                    .await
+                    .ok();
+            }
+        }
+    }
+    drop(tx);
+
+    while let Some(Ok(message)) = stream.next().await {
+        if message.is_close() {
+            break;
+        }
+        if let Some((channel, data)) = protocol::parse(message.as_bytes()) {
+            let failed = match connections.get_mut(&channel) {
+                Some(connection) => connection.send(data.to_vec()).await.is_err(),
+                None => {
+                    debug!("Ignoring message on port forward channel {}", channel);
+                    false
+                }
+            };
+            if failed {
+                connections.remove(&channel);
+            }
+        }
+    }
+
+    // Closing the inputs ends the forwarding of all connections
+    drop(connections);
+    writer.await.ok();
+}
+
+/// Connect to the local `port` inside of the network namespace `netns`.
+async fn connect(netns: Option<PathBuf>, port: u16) -> Result<TcpStream> {
+    let address = SocketAddr::from(([127, 0, 0, 1], port));
+    let stream = task::spawn_blocking(move || match netns {
+        Some(netns) => netns::connect(&netns, address),
+        None => StdTcpStream::connect(address).with_context(|| format!("connect to {}", address)),
+    })
+    .await
+    .context("join connecting task")??;
+    stream
+        .set_nonblocking(true)
+        .context("set connection non-blocking")?;
+    TcpStream::from_std(stream).context("register connection")
+}
+
+/// Write the `input` into the `connection` and send everything read from it as messages of the
+/// `channel` to `tx`, until either side closes.
+async fn forward(
+    channel: u8,
+    mut connection: TcpStream,
+    mut input: mpsc::Receiver<Vec<u8>>,
+    mut tx: mpsc::Sender<Vec<u8>>,
+) {
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        tokio::select! {
+            res = connection.read(&mut buf) => match res {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if tx.send(protocol::frame(channel, &buf[..n])).await.is_err() {
+                        break;
+                    }
+                }
+            },
+            data = input.recv() => match data {
+                Some(data) => {
+                    if connection.write_all(&data).await.is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            },
+        }
+    }
+}
+
+/// Parse the ports of the raw `query` of the session URL, which can be either repeated or comma
+/// separated, like `port=80&port=8080` or `port=80,8080`.
+pub fn parse_ports(query: &str) -> Result<Vec<u16>> {
+    let mut ports = vec![];
+    for (key, value) in query.split('&').filter_map(|x| {
+        let mut kv = x.splitn(2, '=');
+        Some((kv.next()?, kv.next()?))
+    }) {
+        if key != "port" {
+            continue;
+        }
+        for port in value.split(',').filter(|x| !x.is_empty()) {
+            ports.push(
+                port.parse()
+                    .with_context(|| format!("parse port {:?}", port))?,
+            );
+        }
+    }
+    Ok(ports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ports_success() -> Result<()> {
+        assert_eq!(parse_ports("")?, Vec::<u16>::new());
+        assert_eq!(parse_ports("port=80&port=8080")?, vec![80, 8080]);
+        assert_eq!(parse_ports("port=80,8080&other=1")?, vec![80, 8080]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_ports_fail_invalid() {
+        assert!(parse_ports("port=http").is_err());
+        assert!(parse_ports("port=70000").is_err());
+    }
+}
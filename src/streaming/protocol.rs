@@ -0,0 +1,172 @@
+//! The channel based WebSocket protocols of the Kubernetes streaming API.
+//!
+//! Every binary message starts with the channel it belongs to, followed by the payload. Exec
+//! sessions use the standard channels below, whereas port forward sessions use a data and an
+//! error channel per forwarded port.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+/// The channel of the standard input.
+pub const STDIN: u8 = 0;
+
+/// The channel of the standard output.
+pub const STDOUT: u8 = 1;
+
+/// The channel of the standard error.
+pub const STDERR: u8 = 2;
+
+/// The channel of the final status of an exec session.
+pub const ERROR: u8 = 3;
+
+/// The channel of terminal resize events.
+pub const RESIZE: u8 = 4;
+
+/// The channel for closing another channel, which is only available in protocol version 5.
+pub const CLOSE: u8 = 255;
+
+/// The supported protocols of exec sessions, ordered by preference.
+pub const EXEC_PROTOCOLS: &[&str] = &["v5.channel.k8s.io", "v4.channel.k8s.io"];
+
+/// The supported protocols of port forward sessions, ordered by preference.
+pub const PORT_FORWARD_PROTOCOLS: &[&str] = &["v4.channel.k8s.io"];
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+/// The new size of a terminal as sent on the resize channel.
+pub struct TerminalSize {
+    /// The number of columns.
+    pub width: u16,
+
+    /// The number of rows.
+    pub height: u16,
+}
+
+/// Select the most preferred of the `supported` protocols out of the comma separated `requested`
+/// ones of the `Sec-WebSocket-Protocol` header.
+pub fn negotiate(requested: Option<&str>, supported: &[&'static str]) -> Option<&'static str> {
+    let requested: Vec<&str> = requested
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .collect();
+    supported.iter().find(|x| requested.contains(x)).copied()
+}
+
+/// Build a message containing the `data` of the `channel`.
+pub fn frame(channel: u8, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(data.len() + 1);
+    frame.push(channel);
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// Split the `message` into its channel and the data. Returns `None` for empty messages.
+pub fn parse(message: &[u8]) -> Option<(u8, &[u8])> {
+    message
+        .split_first()
+        .map(|(channel, data)| (*channel, data))
+}
+
+/// Parse the JSON encoded terminal size of a resize message.
+pub fn parse_resize(data: &[u8]) -> Result<TerminalSize> {
+    serde_json::from_slice(data).context("parse terminal size")
+}
+
+/// Build the status message of an exec session, whose process exited with `exit_code`.
+pub fn exit_status(exit_code: i32) -> Vec<u8> {
+    let status = if exit_code == 0 {
+        json!({"metadata": {}, "status": "Success"})
+    } else {
+        json!({
+            "metadata": {},
+            "status": "Failure",
+            "message": format!("command terminated with non-zero exit code: {}", exit_code),
+            "reason": "NonZeroExitCode",
+            "details": {"causes": [{"reason": "ExitCode", "message": exit_code.to_string()}]},
+        })
+    };
+    frame(ERROR, status.to_string().as_bytes())
+}
+
+/// Build the status message of an exec session, which failed with the `error`.
+pub fn error_status(error: &anyhow::Error) -> Vec<u8> {
+    let status = json!({
+        "metadata": {},
+        "status": "Failure",
+        "message": format!("{:#}", error),
+    });
+    frame(ERROR, status.to_string().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::format_err;
+    use serde_json::Value;
+
+    #[test]
+    fn negotiate_success() {
+        assert_eq!(
+            negotiate(Some("v4.channel.k8s.io, v5.channel.k8s.io"), EXEC_PROTOCOLS),
+            Some("v5.channel.k8s.io")
+        );
+        assert_eq!(
+            negotiate(Some("v4.channel.k8s.io"), EXEC_PROTOCOLS),
+            Some("v4.channel.k8s.io")
+        );
+    }
+
+    #[test]
+    fn negotiate_fail_unsupported() {
+        assert_eq!(negotiate(None, EXEC_PROTOCOLS), None);
+        assert_eq!(
+            negotiate(Some("base64.channel.k8s.io"), PORT_FORWARD_PROTOCOLS),
+            None
+        );
+    }
+
+    #[test]
+    fn frame_and_parse_success() {
+        let message = frame(STDOUT, b"output");
+        assert_eq!(parse(&message), Some((STDOUT, &b"output"[..])));
+        assert_eq!(parse(&[STDIN]), Some((STDIN, &b""[..])));
+        assert_eq!(parse(&[]), None);
+    }
+
+    #[test]
+    fn parse_resize_success() -> Result<()> {
+        assert_eq!(
+            parse_resize(br#"{"Width":80,"Height":24}"#)?,
+            TerminalSize {
+                width: 80,
+                height: 24
+            }
+        );
+        assert!(parse_resize(b"80x24").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn exit_status_success() -> Result<()> {
+        let message = exit_status(0);
+        assert_eq!(message[0], ERROR);
+        let status: Value = serde_json::from_slice(&message[1..])?;
+        assert_eq!(status["status"], "Success");
+
+        let status: Value = serde_json::from_slice(&exit_status(2)[1..])?;
+        assert_eq!(status["reason"], "NonZeroExitCode");
+        assert_eq!(status["details"]["causes"][0]["message"], "2");
+        Ok(())
+    }
+
+    #[test]
+    fn error_status_success() -> Result<()> {
+        let message = error_status(&format_err!("failure"));
+        let status: Value = serde_json::from_slice(&message[1..])?;
+        assert_eq!(status["status"], "Failure");
+        assert_eq!(status["message"], "failure");
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+//! Recording of interactive exec and attach sessions for audits.
+//!
+//! Every recorded session gets its own file below the recording directory, grouped by the
+//! namespace of its pod. The first line of a recording describes the session, whereas every
+//! following line is a JSON record of a single message with the time it passed the streaming
+//! server, like `{"time":1600000000000000000,"stream":"stdin","data":"ls\n"}`. The data gets
+//! converted lossily to UTF-8, because interactive sessions are text almost always. Once a
+//! session ends, an event announces its recording, so that external sinks subscribed to the events
+//! can ship it.
+
+use crate::{
+    clock::Clock,
+    config::Config,
+    event::{Event, EventBus},
+    streaming::protocol::{self, ERROR, RESIZE, STDERR, STDIN, STDOUT},
+};
+use anyhow::{Context, Result};
+use getset::Getters;
+use log::warn;
+use serde::Serialize;
+use std::{
+    fs::{DirBuilder, File, OpenOptions},
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+#[derive(Clone, Debug, Getters, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+/// SessionInfo describes a recorded session in the first line of its recording.
+pub struct SessionInfo {
+    #[get = "pub"]
+    /// The kind of the session, like `exec`.
+    kind: String,
+
+    #[get = "pub"]
+    /// The ID of the container the session is connected to.
+    container_id: String,
+
+    #[get = "pub"]
+    /// The ID of the pod sandbox of the container.
+    pod_sandbox_id: String,
+
+    #[get = "pub"]
+    /// The namespace of the pod.
+    namespace: String,
+
+    #[get = "pub"]
+    /// The name of the pod.
+    pod: String,
+
+    #[get = "pub"]
+    /// The executed command, which is empty for attach sessions.
+    command: Vec<String>,
+}
+
+impl SessionInfo {
+    /// Describe a session of the `kind` running the `command` in the container `container_id`
+    /// of the pod sandbox `pod_sandbox_id`, which belongs to the `pod` in the `namespace`.
+    pub fn new(
+        kind: &str,
+        container_id: &str,
+        pod_sandbox_id: &str,
+        namespace: &str,
+        pod: &str,
+        command: &[String],
+    ) -> Self {
+        Self {
+            kind: kind.into(),
+            container_id: container_id.into(),
+            pod_sandbox_id: pod_sandbox_id.into(),
+            namespace: namespace.into(),
+            pod: pod.into(),
+            command: command.to_vec(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Getters, PartialEq)]
+/// Recording defines where a requested session gets recorded once the client connects.
+pub struct Recording {
+    #[get = "pub"]
+    /// The path of the recording file.
+    path: PathBuf,
+
+    #[get = "pub"]
+    /// The description of the session.
+    info: SessionInfo,
+}
+
+impl Recording {
+    /// Decide whether the session `info` gets recorded according to the `config`, whereas the
+    /// file name contains the time of the request `now` in nanoseconds since the Unix epoch.
+    /// Returns `None` if recording is disabled for the namespace of the session.
+    pub fn new(config: &Config, info: SessionInfo, now: i64) -> Option<Self> {
+        let dir = config.session_recording_path().as_ref()?;
+        let namespaces = config.session_recording_namespaces();
+        if !namespaces.is_empty() && !namespaces.contains(&info.namespace) {
+            return None;
+        }
+        let path = dir
+            .join(&info.namespace)
+            .join(format!("{}-{}-{}.jsonl", info.container_id, info.kind, now));
+        Some(Self { path, info })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+/// The first line of a recording.
+struct Header<'a> {
+    /// The description of the session.
+    #[serde(flatten)]
+    info: &'a SessionInfo,
+
+    /// The time the client connected in nanoseconds since the Unix epoch.
+    started_at: i64,
+}
+
+#[derive(Serialize)]
+/// A single message of a recorded session.
+struct Record<'a> {
+    /// The time of the message in nanoseconds since the Unix epoch.
+    time: i64,
+
+    /// The stream of the message, like `stdin`.
+    stream: &'a str,
+
+    /// The content of the message.
+    data: &'a str,
+}
+
+#[derive(Clone, Debug)]
+/// Recorder writes the messages of a connected session into its recording.
+pub struct Recorder {
+    /// The path of the recording file.
+    path: PathBuf,
+
+    /// The open recording file.
+    file: Arc<Mutex<File>>,
+
+    /// Whether recording a message failed already, which gets logged only once.
+    failed: Arc<AtomicBool>,
+
+    /// The description of the session.
+    info: SessionInfo,
+
+    /// The clock the time of the messages is based on.
+    clock: Arc<dyn Clock>,
+}
+
+impl Recorder {
+    /// Create the file of the `recording` and write its header. The recording is only readable
+    /// by the owner, because sessions may contain secrets typed by users.
+    pub fn create(recording: &Recording, clock: Arc<dyn Clock>) -> Result<Self> {
+        let path = recording.path();
+        if let Some(parent) = path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)
+                .with_context(|| format!("create recording directory {}", parent.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("create recording {}", path.display()))?;
+
+        let header = Header {
+            info: recording.info(),
+            started_at: clock.unix_nanos()?,
+        };
+        let mut line = serde_json::to_vec(&header).context("serialize recording header")?;
+        line.push(b'\n');
+        file.write_all(&line)
+            .with_context(|| format!("write recording {}", path.display()))?;
+        Ok(Self {
+            path: path.clone(),
+            file: Arc::new(Mutex::new(file)),
+            failed: Arc::default(),
+            info: recording.info().clone(),
+            clock,
+        })
+    }
+
+    /// Record the protocol `frame`, which is either received from or sent to the client.
+    /// Failures are logged, because they must not interrupt the session.
+    pub fn record(&self, frame: &[u8]) {
+        let (stream, data) = match protocol::parse(frame) {
+            Some((STDIN, data)) => ("stdin", data),
+            Some((STDOUT, data)) => ("stdout", data),
+            Some((STDERR, data)) => ("stderr", data),
+            Some((ERROR, data)) => ("error", data),
+            Some((RESIZE, data)) => ("resize", data),
+            _ => return,
+        };
+        if let Err(e) = self.write(stream, data) {
+            if !self.failed.swap(true, Ordering::Relaxed) {
+                warn!("Unable to record session {}: {:#}", self.path.display(), e);
+            }
+        }
+    }
+
+    /// Append the `data` of the `stream` as record to the recording.
+    fn write(&self, stream: &str, data: &[u8]) -> Result<()> {
+        let record = Record {
+            time: self.clock.unix_nanos()?,
+            stream,
+            data: &String::from_utf8_lossy(data),
+        };
+        let mut line = serde_json::to_vec(&record).context("serialize record")?;
+        line.push(b'\n');
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| anyhow::format_err!("recording lock poisoned"))?;
+        file.write_all(&line).context("write record")
+    }
+
+    /// Finish the recording and announce it via the `events`.
+    pub fn finish(self, events: &EventBus) {
+        if let Ok(file) = self.file.lock() {
+            file.sync_all().ok();
+        }
+        events.publish(Event::SessionRecorded {
+            kind: self.info.kind,
+            container_id: self.info.container_id,
+            path: self.path.display().to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::tests::FakeClock, config::ConfigBuilder};
+    use serde_json::Value;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn new_info(namespace: &str) -> SessionInfo {
+        SessionInfo::new("exec", "ctr", "pod", namespace, "name", &["sh".into()])
+    }
+
+    #[test]
+    fn recording_success_namespaces() -> Result<()> {
+        let config = ConfigBuilder::default()
+            .session_recording_path(Some(PathBuf::from("/recordings")))
+            .session_recording_namespaces(vec!["audited".into()])
+            .build()?;
+        let recording = Recording::new(&config, new_info("audited"), 10);
+        assert_eq!(
+            recording.map(|x| x.path),
+            Some(PathBuf::from("/recordings/audited/ctr-exec-10.jsonl"))
+        );
+        assert!(Recording::new(&config, new_info("other"), 10).is_none());
+
+        let config = ConfigBuilder::default().build()?;
+        assert!(Recording::new(&config, new_info("audited"), 10).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recorder_success() -> Result<()> {
+        let dir = tempdir()?;
+        let config = ConfigBuilder::default()
+            .session_recording_path(Some(dir.path().into()))
+            .build()?;
+        let recording = Recording::new(&config, new_info("default"), 10).context("not recorded")?;
+        let sut = Recorder::create(&recording, Arc::new(FakeClock::default()))?;
+        sut.record(&protocol::frame(STDIN, b"ls\n"));
+        sut.record(&protocol::frame(STDOUT, b"file\n"));
+        sut.record(&protocol::frame(7, b"unknown"));
+
+        let events = EventBus::default();
+        let mut subscriber = events.subscribe();
+        sut.clone().finish(&events);
+        assert!(matches!(
+            subscriber.recv().await?,
+            Event::SessionRecorded { kind, .. } if kind == "exec"
+        ));
+
+        let content = fs::read_to_string(recording.path())?;
+        let lines: Vec<Value> = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["containerId"], "ctr");
+        assert_eq!(lines[0]["command"][0], "sh");
+        assert_eq!(lines[1]["stream"], "stdin");
+        assert_eq!(lines[1]["data"], "ls\n");
+        assert_eq!(lines[2]["stream"], "stdout");
+
+        // Every session gets a new recording
+        assert!(Recorder::create(&recording, Arc::new(FakeClock::default())).is_err());
+        Ok(())
+    }
+}
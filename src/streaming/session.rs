@@ -0,0 +1,182 @@
+//! Streaming sessions which have been requested via the CRI, but not been connected yet.
+
+use crate::{
+    clock::{Clock, SystemClock},
+    config::Config,
+    criapi::{AttachRequest, ExecRequest},
+    streaming::recording::Recording,
+};
+use log::debug;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The time a requested session can be connected to before it expires.
+const SESSION_TTL: Duration = Duration::from_secs(60);
+
+/// The length of the random tokens identifying sessions in their URL.
+const TOKEN_LEN: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+/// A Session describes what gets streamed once the client connects to its URL.
+pub enum Session {
+    /// Execute a command inside of a running container, which gets recorded if the recording is
+    /// set.
+    Exec(ExecRequest, Option<Recording>),
+
+    /// Attach to the standard streams of a running container, which gets recorded if the
+    /// recording is set.
+    Attach(AttachRequest, Option<Recording>),
+
+    /// Forward TCP ports of a pod sandbox.
+    PortForward {
+        /// The path of the network namespace of the sandbox, which is `None` if the sandbox uses
+        /// the host network.
+        netns: Option<PathBuf>,
+
+        /// The ports to forward, if not requested by the client when connecting.
+        ports: Vec<u16>,
+    },
+}
+
+impl Session {
+    /// Retrieve the first path segment of the session URL.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Session::Exec(..) => "exec",
+            Session::Attach(..) => "attach",
+            Session::PortForward { .. } => "portforward",
+        }
+    }
+}
+
+#[derive(Clone)]
+/// SessionCache holds the sessions until either the client connects or they expire.
+pub struct SessionCache {
+    /// The URL of the streaming server, without a trailing slash.
+    base_url: String,
+
+    /// The pending sessions and their creation time by their token.
+    sessions: Arc<Mutex<HashMap<String, (Instant, Session)>>>,
+
+    /// The clock the expiry of sessions is based on.
+    clock: Arc<dyn Clock>,
+}
+
+impl SessionCache {
+    /// Create a new cache for the streaming server of the `config`.
+    pub fn new(config: &Config) -> Self {
+        let scheme = if config.streaming_tls_cert().is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        let address = SocketAddr::new(config.streaming_address(), config.streaming_port());
+        Self {
+            base_url: format!("{}://{}", scheme, address),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Base the expiry of sessions on the `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Add the `session` to the cache and return the URL the client has to connect to.
+    pub fn insert(&self, session: Session) -> String {
+        let token: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LEN)
+            .collect();
+        let url = format!("{}/{}/{}", self.base_url, session.kind(), token);
+
+        // Expired sessions are removed lazily, because the cache only grows with each request
+        let now = self.clock.instant();
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.retain(|_, (created, _)| now.duration_since(*created) < SESSION_TTL);
+            sessions.insert(token, (now, session));
+        }
+        url
+    }
+
+    /// Remove the session with the `token` from the cache. Returns `None` if the session does not
+    /// exist or is expired, because every session can only be connected to once.
+    pub fn take(&self, token: &str) -> Option<Session> {
+        let (created, session) = self.sessions.lock().ok()?.remove(token)?;
+        if self.clock.instant().duration_since(created) >= SESSION_TTL {
+            debug!("Streaming session {} expired", token);
+            return None;
+        }
+        Some(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::tests::FakeClock, config::ConfigBuilder};
+    use anyhow::{Context, Result};
+
+    fn port_forward() -> Session {
+        Session::PortForward {
+            netns: None,
+            ports: vec![8080],
+        }
+    }
+
+    #[test]
+    fn insert_and_take_success() -> Result<()> {
+        let sut = SessionCache::new(&ConfigBuilder::default().build()?);
+        let url = sut.insert(port_forward());
+        assert!(url.starts_with("http://127.0.0.1:10010/portforward/"));
+
+        let token = url.rsplit('/').next().context("no token")?;
+        assert_eq!(token.len(), TOKEN_LEN);
+        assert_eq!(sut.take(token), Some(port_forward()));
+        assert_eq!(sut.take(token), None);
+        Ok(())
+    }
+
+    #[test]
+    fn insert_success_tls() -> Result<()> {
+        let sut = SessionCache::new(
+            &ConfigBuilder::default()
+                .streaming_tls_cert(Some(PathBuf::from("/streaming.crt")))
+                .build()?,
+        );
+        assert!(sut.insert(port_forward()).starts_with("https://"));
+        Ok(())
+    }
+
+    #[test]
+    fn take_fail_expired() -> Result<()> {
+        let clock = FakeClock::default();
+        let sut = SessionCache::new(&ConfigBuilder::default().build()?)
+            .with_clock(Arc::new(clock.clone()));
+        let first = sut.insert(port_forward());
+        let second = sut.insert(port_forward());
+        let token = |url: &str| url.rsplit('/').next().map(String::from).context("no token");
+
+        clock.advance(SESSION_TTL - Duration::from_secs(1));
+        assert_eq!(sut.take(&token(&first)?), Some(port_forward()));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(sut.take(&token(&second)?), None);
+        Ok(())
+    }
+
+    #[test]
+    fn take_fail_unknown_token() -> Result<()> {
+        let sut = SessionCache::new(&ConfigBuilder::default().build()?);
+        assert_eq!(sut.take("unknown"), None);
+        Ok(())
+    }
+}
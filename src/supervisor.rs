@@ -0,0 +1,260 @@
+//! Supervision of long-lived background tasks
+//!
+//! Every supervised task runs in its own tokio task. If the task fails or panics, then it gets
+//! restarted after an exponentially increasing backoff, whereas tasks which finish successfully
+//! are not restarted at all.
+
+use crate::clock::{Clock, SystemClock};
+use anyhow::Result;
+use log::{debug, error};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time;
+
+/// The backoff before the first restart of a failed task.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The maximum backoff between two restarts of a failed task.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+/// Supervisor keeps track of all background tasks and their health.
+pub struct Supervisor {
+    /// The health of all supervised tasks by their name.
+    tasks: Arc<Mutex<BTreeMap<String, TaskHealth>>>,
+
+    /// The backoff before the first restart.
+    min_backoff: Duration,
+
+    /// The maximum backoff between two restarts.
+    max_backoff: Duration,
+
+    /// The clock the health of the tasks is measured with.
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+/// The health of a single supervised task.
+pub struct TaskHealth {
+    /// The current state of the task.
+    pub state: TaskState,
+
+    /// The number of restarts since the supervision started.
+    pub restarts: u32,
+
+    /// The error of the latest failure, if any.
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// The state of a supervised task.
+pub enum TaskState {
+    /// The task is running.
+    Running,
+
+    /// The task failed and waits to be restarted.
+    Backoff,
+
+    /// The task finished successfully and will not be restarted.
+    Finished,
+}
+
+impl Default for TaskState {
+    fn default() -> Self {
+        TaskState::Running
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new(MIN_BACKOFF, MAX_BACKOFF)
+    }
+}
+
+impl Supervisor {
+    /// Create a new supervisor with the provided restart backoff boundaries.
+    pub fn new(min_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            tasks: Arc::new(Mutex::new(BTreeMap::new())),
+            min_backoff,
+            max_backoff,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Measure the time tasks stay healthy with the `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Spawn the task named `name` in the background. The `task` closure gets called for every
+    /// (re)start of the task.
+    pub fn spawn<F, Fut>(&self, name: &str, task: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.to_string();
+        tokio::spawn(async move { supervisor.supervise(&name, task).await });
+    }
+
+    /// Run the task until it finishes successfully.
+    async fn supervise<F, Fut>(&self, name: &str, task: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let mut backoff = self.min_backoff;
+        loop {
+            self.update(name, |x| x.state = TaskState::Running);
+            debug!("Starting background task {}", name);
+
+            let started = self.clock.instant();
+            let err = match tokio::spawn(task()).await {
+                Ok(Ok(())) => {
+                    debug!("Background task {} finished", name);
+                    self.update(name, |x| x.state = TaskState::Finished);
+                    return;
+                }
+                Ok(Err(e)) => format!("{:#}", e),
+                Err(e) => format!("task aborted: {}", e),
+            };
+
+            // Tasks which have been healthy for long enough start over with the minimum backoff
+            if self.clock.instant() - started >= self.max_backoff {
+                backoff = self.min_backoff;
+            }
+            error!(
+                "Background task {} failed, restarting in {:?}: {}",
+                name, backoff, err
+            );
+            self.update(name, |x| {
+                x.state = TaskState::Backoff;
+                x.restarts += 1;
+                x.last_error = Some(err);
+            });
+
+            time::delay_for(backoff).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+    }
+
+    /// Update the health of the task `name` via the provided closure `f`.
+    fn update<F: FnOnce(&mut TaskHealth)>(&self, name: &str, f: F) {
+        if let Ok(mut tasks) = self.tasks.lock() {
+            f(tasks.entry(name.into()).or_default())
+        }
+    }
+
+    /// Retrieve the health of all supervised tasks by their name.
+    pub fn health(&self) -> BTreeMap<String, TaskHealth> {
+        self.tasks.lock().map(|x| x.clone()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::tests::FakeClock;
+    use anyhow::{bail, Context};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn new_supervisor() -> Supervisor {
+        Supervisor::new(Duration::from_millis(1), Duration::from_millis(10))
+    }
+
+    async fn wait_for<F: Fn(&TaskHealth) -> bool>(
+        sut: &Supervisor,
+        name: &str,
+        f: F,
+    ) -> Result<TaskHealth> {
+        for _ in 0..1000 {
+            if let Some(health) = sut.health().get(name).filter(|x| f(x)) {
+                return Ok(health.clone());
+            }
+            time::delay_for(Duration::from_millis(1)).await;
+        }
+        bail!("task {} did not reach the expected health", name)
+    }
+
+    #[tokio::test]
+    async fn spawn_success_finished() -> Result<()> {
+        let sut = new_supervisor();
+        sut.spawn("task", || async { Ok(()) });
+
+        let health = wait_for(&sut, "task", |x| x.state == TaskState::Finished).await?;
+        assert_eq!(health.restarts, 0);
+        assert!(health.last_error.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn spawn_success_restart_on_error() -> Result<()> {
+        let sut = new_supervisor();
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let task_runs = runs.clone();
+        sut.spawn("task", move || {
+            let runs = task_runs.clone();
+            async move {
+                if runs.fetch_add(1, Ordering::SeqCst) < 2 {
+                    bail!("failure")
+                }
+                Ok(())
+            }
+        });
+
+        let health = wait_for(&sut, "task", |x| x.state == TaskState::Finished).await?;
+        assert_eq!(health.restarts, 2);
+        assert_eq!(health.last_error.as_deref(), Some("failure"));
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn spawn_success_backoff_reset() -> Result<()> {
+        let clock = FakeClock::default();
+        let sut = Supervisor::new(Duration::from_millis(1), Duration::from_secs(10))
+            .with_clock(Arc::new(clock.clone()));
+        let runs = Arc::new(AtomicU32::new(0));
+
+        // Every run stays healthy for the maximum backoff, so that the backoff never grows
+        // beyond the minimum, whereas growing backoffs would take seconds
+        let task_runs = runs.clone();
+        sut.spawn("task", move || {
+            let (runs, clock) = (task_runs.clone(), clock.clone());
+            async move {
+                clock.advance(Duration::from_secs(10));
+                if runs.fetch_add(1, Ordering::SeqCst) < 12 {
+                    bail!("failure")
+                }
+                Ok(())
+            }
+        });
+
+        let health = wait_for(&sut, "task", |x| x.state == TaskState::Finished).await?;
+        assert_eq!(health.restarts, 12);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn spawn_success_restart_on_panic() -> Result<()> {
+        let sut = new_supervisor();
+        sut.spawn("task", || async { panic!("boom") });
+
+        let health = wait_for(&sut, "task", |x| x.restarts >= 2).await?;
+        assert!(health
+            .last_error
+            .context("no last error")?
+            .contains("panic"));
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! Integration with the systemd service manager.
+//!
+//! A socket unit can bind the runtime socket and pass it to the server on startup, which allows
+//! clients like the kubelet to connect before the server is ready. The server notifies systemd
+//! about its readiness and shutdown via `sd_notify` and sends keep-alive pings if the unit has a
+//! watchdog configured, which makes `Type=notify` units reflect the actual state of the server.
+
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag},
+    sys::socket::{self, AddressFamily, MsgFlags, SockAddr, SockFlag, SockType, UnixAddr},
+};
+use std::{
+    env,
+    os::unix::{
+        io::{AsRawFd, FromRawFd, RawFd},
+        net::{UnixDatagram, UnixListener},
+    },
+    process,
+    time::Duration,
+};
+use tokio::time;
+
+/// The first file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The environment variables describing the passed file descriptors.
+const LISTEN_ENV: &[&str] = &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"];
+
+/// The environment variable containing the address of the notification socket.
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+
+/// The state notifying systemd that the server is ready to handle requests.
+pub const READY: &str = "READY=1";
+
+/// The state notifying systemd that the server is shutting down.
+pub const STOPPING: &str = "STOPPING=1";
+
+/// The state resetting the watchdog timer of the unit.
+const WATCHDOG: &str = "WATCHDOG=1";
+
+/// Take the listening socket passed by systemd, which is `None` if the server has not been
+/// socket activated. The environment gets cleared, so that child processes do not inherit the
+/// socket. Only the first socket is used if systemd passes multiple ones.
+pub fn take_listener() -> Result<Option<UnixListener>> {
+    let fds = listen_fds();
+    for key in LISTEN_ENV {
+        env::remove_var(key);
+    }
+    let fds = match fds {
+        Some(fds) => fds,
+        None => return Ok(None),
+    };
+    if fds > 1 {
+        warn!("Ignoring {} additional sockets passed by systemd", fds - 1);
+    }
+
+    // Passed file descriptors do not get closed on exec
+    fcntl(LISTEN_FDS_START, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+        .context("set close on exec flag of passed socket")?;
+    let listener = unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    listener
+        .local_addr()
+        .context("passed socket is no unix domain socket")?;
+    Ok(Some(listener))
+}
+
+/// Retrieve the number of file descriptors passed to this process, if any.
+fn listen_fds() -> Option<u32> {
+    let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    if pid != process::id() {
+        return None;
+    }
+    env::var("LISTEN_FDS").ok()?.parse().ok().filter(|x| *x > 0)
+}
+
+/// Send the `state` to systemd. Returns `false` if the server does not run as notify unit.
+pub fn notify(state: &str) -> Result<bool> {
+    let path = match env::var(NOTIFY_SOCKET_ENV) {
+        Ok(path) if !path.is_empty() => path,
+        _ => return Ok(false),
+    };
+    let address = match path.strip_prefix('@') {
+        Some(name) => UnixAddr::new_abstract(name.as_bytes()),
+        None if path.starts_with('/') => UnixAddr::new(path.as_str()),
+        None => bail!("unsupported notification socket {}", path),
+    }
+    .with_context(|| format!("parse notification socket {}", path))?;
+
+    let fd = socket::socket(
+        AddressFamily::Unix,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .context("create notification socket")?;
+    // Closes the socket when being dropped
+    let socket = unsafe { UnixDatagram::from_raw_fd(fd) };
+    socket::sendto(
+        socket.as_raw_fd(),
+        state.as_bytes(),
+        &SockAddr::Unix(address),
+        MsgFlags::empty(),
+    )
+    .with_context(|| format!("send {} to {}", state, path))?;
+    debug!("Notified systemd about {}", state);
+    Ok(true)
+}
+
+/// Retrieve the interval of resetting the watchdog of the unit, which is half of its timeout.
+/// Returns `None` if the watchdog is disabled.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec / 2)).filter(|x| *x > Duration::from_secs(0))
+}
+
+/// Reset the watchdog of the unit every `interval` until the server shuts down.
+pub async fn watchdog(interval: Duration) -> Result<()> {
+    let mut interval = time::interval(interval);
+    loop {
+        interval.tick().await;
+        notify(WATCHDOG)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn notify_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("notify.sock");
+        let receiver = UnixDatagram::bind(&path)?;
+
+        env::set_var(NOTIFY_SOCKET_ENV, &path);
+        let notified = notify(READY);
+        env::remove_var(NOTIFY_SOCKET_ENV);
+        assert!(notified?);
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf)?;
+        assert_eq!(&buf[..len], READY.as_bytes());
+
+        assert!(!notify(STOPPING)?);
+        Ok(())
+    }
+
+    #[test]
+    fn take_listener_success_not_activated() -> Result<()> {
+        env::set_var("LISTEN_PID", "1");
+        env::set_var("LISTEN_FDS", "1");
+        assert!(take_listener()?.is_none());
+        assert!(env::var("LISTEN_FDS").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,385 @@
+//! Distributed tracing of RPCs.
+//!
+//! Every RPC gets a span, which continues the trace of the client if it propagates a W3C
+//! `traceparent` via the gRPC metadata. Finished spans are logged on debug level and exported to
+//! an OTLP/HTTP collector if one is configured. Spans are dropped instead of blocking RPCs if the
+//! collector cannot keep up.
+
+use crate::clock::{Clock, SystemClock};
+use anyhow::{Context, Result};
+use getset::Getters;
+use log::{debug, info, warn};
+use rand::{thread_rng, RngCore};
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::{
+    fmt::Write,
+    mem,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time;
+use tonic::{metadata::MetadataMap, Code};
+
+/// The metadata key propagating the W3C trace context.
+pub const TRACEPARENT_METADATA_KEY: &str = "traceparent";
+
+/// The interval of exporting finished spans.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The maximum number of finished spans waiting for their export.
+const MAX_PENDING_SPANS: usize = 2048;
+
+/// The flag of sampled traces in the trace context.
+const SAMPLED_FLAG: u8 = 0x01;
+
+/// The OTLP kind of spans handling incoming requests.
+const SPAN_KIND_SERVER: u8 = 2;
+
+/// The OTLP status code of failed spans.
+const STATUS_CODE_ERROR: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+/// TraceContext identifies the parent of a span.
+pub struct TraceContext {
+    /// The hex encoded 16 byte trace ID.
+    trace_id: String,
+
+    /// The hex encoded 8 byte span ID of the parent.
+    span_id: String,
+
+    /// Whether the trace has been sampled by the client.
+    sampled: bool,
+}
+
+impl TraceContext {
+    /// Parse the trace context from the `traceparent` of the `metadata`. Returns `None` if it is
+    /// missing or invalid, which starts a new trace.
+    pub fn from_metadata(metadata: &MetadataMap) -> Option<Self> {
+        let value = metadata.get(TRACEPARENT_METADATA_KEY)?.to_str().ok()?;
+        let parts: Vec<&str> = value.trim().split('-').collect();
+        match parts.as_slice() {
+            [version, trace_id, span_id, flags]
+                if version.len() == 2
+                    && *version != "ff"
+                    && is_hex_id(trace_id, 32)
+                    && is_hex_id(span_id, 16) =>
+            {
+                let flags = u8::from_str_radix(flags, 16).ok()?;
+                Some(Self {
+                    trace_id: (*trace_id).into(),
+                    span_id: (*span_id).into(),
+                    sampled: flags & SAMPLED_FLAG != 0,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Check if `id` consists of `len` lowercase hex digits which are not all zero.
+fn is_hex_id(id: &str, len: usize) -> bool {
+    id.len() == len
+        && id
+            .chars()
+            .all(|x| x.is_ascii_digit() || ('a'..='f').contains(&x))
+        && id.chars().any(|x| x != '0')
+}
+
+/// Generate a random hex encoded ID of `bytes`.
+fn new_id(bytes: usize) -> String {
+    let mut id = vec![0; bytes];
+    thread_rng().fill_bytes(&mut id);
+    id.iter().fold(String::new(), |mut res, x| {
+        write!(res, "{:02x}", x).ok();
+        res
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Span is the trace of a single RPC.
+pub struct Span {
+    /// The hex encoded trace ID.
+    trace_id: String,
+
+    /// The hex encoded span ID.
+    span_id: String,
+
+    /// The span ID of the parent in the client, if any.
+    parent_span_id: Option<String>,
+
+    /// Whether the span gets exported.
+    sampled: bool,
+
+    /// The gRPC method of the RPC.
+    method: String,
+
+    /// The start time in nanoseconds since the Unix epoch.
+    start: u128,
+
+    /// The end time in nanoseconds since the Unix epoch, which is zero until it finished.
+    end: u128,
+
+    /// The status code the RPC finished with.
+    code: Code,
+}
+
+impl Span {
+    /// Encode the span in the OTLP JSON format.
+    fn otlp(&self) -> Value {
+        let mut span = json!({
+            "traceId": self.trace_id,
+            "spanId": self.span_id,
+            "name": self.method,
+            "kind": SPAN_KIND_SERVER,
+            "startTimeUnixNano": self.start.to_string(),
+            "endTimeUnixNano": self.end.to_string(),
+            "attributes": [
+                attribute("rpc.system", json!({"stringValue": "grpc"})),
+                attribute("rpc.method", json!({"stringValue": self.method})),
+                attribute(
+                    "rpc.grpc.status_code",
+                    json!({"intValue": (self.code as i32).to_string()}),
+                ),
+            ],
+        });
+        if let Some(parent) = &self.parent_span_id {
+            span["parentSpanId"] = json!(parent);
+        }
+        if self.code != Code::Ok {
+            span["status"] = json!({"code": STATUS_CODE_ERROR});
+        }
+        span
+    }
+}
+
+/// Build the OTLP attribute `key` with its `value`.
+fn attribute(key: &str, value: Value) -> Value {
+    json!({"key": key, "value": value})
+}
+
+#[derive(Clone, Getters)]
+/// Tracer creates the spans of RPCs and keeps them until they get exported.
+pub struct Tracer {
+    #[get = "pub"]
+    /// The URL of the OTLP/HTTP collector, spans are only logged if there is none.
+    endpoint: Option<String>,
+
+    /// The finished spans waiting for their export.
+    pending: Arc<Mutex<Vec<Span>>>,
+
+    /// The clock the start and end times of the spans are based on.
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            pending: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl Tracer {
+    /// Create a new tracer exporting to the OTLP/HTTP collector at `endpoint`, if provided.
+    pub fn new(endpoint: Option<String>) -> Self {
+        Self {
+            endpoint,
+            ..Default::default()
+        }
+    }
+
+    /// Base the start and end times of the spans on the `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Retrieve the current time in nanoseconds since the Unix epoch.
+    fn unix_nanos(&self) -> u128 {
+        self.clock.unix_nanos().unwrap_or_default() as u128
+    }
+
+    /// Start the span of an RPC to `method`, which continues the trace propagated via the
+    /// `metadata`.
+    pub fn start(&self, method: &str, metadata: &MetadataMap) -> Span {
+        let parent = TraceContext::from_metadata(metadata);
+        Span {
+            trace_id: parent
+                .as_ref()
+                .map(|x| x.trace_id.clone())
+                .unwrap_or_else(|| new_id(16)),
+            span_id: new_id(8),
+            parent_span_id: parent.as_ref().map(|x| x.span_id.clone()),
+            // New traces are always sampled, because there is no other sampler
+            sampled: parent.map_or(true, |x| x.sampled),
+            method: method.into(),
+            start: self.unix_nanos(),
+            end: 0,
+            code: Code::Ok,
+        }
+    }
+
+    /// Finish the `span` with the status `code` and queue it for export if sampled.
+    pub fn finish(&self, mut span: Span, code: Code) {
+        span.end = self.unix_nanos();
+        span.code = code;
+        debug!(
+            "Finished {} with {:?} after {:?} (trace {}, span {})",
+            span.method,
+            code,
+            Duration::from_nanos(span.end.saturating_sub(span.start) as u64),
+            span.trace_id,
+            span.span_id
+        );
+        if self.endpoint.is_none() || !span.sampled {
+            return;
+        }
+        if let Ok(mut pending) = self.pending.lock() {
+            if pending.len() < MAX_PENDING_SPANS {
+                pending.push(span);
+            }
+        }
+    }
+
+    /// Take all finished spans and encode them as OTLP export request. Returns `None` if there
+    /// is nothing to export.
+    fn take_request(&self) -> Option<Value> {
+        let spans = self
+            .pending
+            .lock()
+            .map(|mut x| mem::take(&mut *x))
+            .unwrap_or_default();
+        if spans.is_empty() {
+            return None;
+        }
+        Some(json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [attribute("service.name", json!({"stringValue": "cri"}))],
+                },
+                "scopeSpans": [{
+                    "scope": {"name": "cri"},
+                    "spans": spans.iter().map(Span::otlp).collect::<Vec<_>>(),
+                }],
+            }],
+        }))
+    }
+
+    /// Export the finished spans every `EXPORT_INTERVAL` until the server shuts down. Failing
+    /// exports drop their spans and do not stop the task.
+    pub async fn export(self) -> Result<()> {
+        let url = match &self.endpoint {
+            Some(endpoint) => format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+            None => return Ok(()),
+        };
+        info!("Exporting spans to {}", url);
+        let client = Client::new();
+        let mut interval = time::interval(EXPORT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let request = match self.take_request() {
+                Some(request) => request,
+                None => continue,
+            };
+            let res = client
+                .post(&url)
+                .json(&request)
+                .send()
+                .await
+                .and_then(|x| x.error_for_status())
+                .with_context(|| format!("send spans to {}", url));
+            if let Err(e) = res {
+                warn!("Unable to export spans: {:#}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::tests::FakeClock;
+
+    fn metadata(traceparent: &str) -> Result<MetadataMap> {
+        let mut metadata = MetadataMap::new();
+        metadata.insert(TRACEPARENT_METADATA_KEY, traceparent.parse()?);
+        Ok(metadata)
+    }
+
+    #[test]
+    fn from_metadata_success() -> Result<()> {
+        assert_eq!(TraceContext::from_metadata(&MetadataMap::new()), None);
+
+        let context = TraceContext::from_metadata(&metadata(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        )?);
+        assert_eq!(
+            context,
+            Some(TraceContext {
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".into(),
+                span_id: "00f067aa0ba902b7".into(),
+                sampled: true,
+            })
+        );
+
+        for invalid in &[
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceContext::from_metadata(&metadata(invalid)?), None);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn start_finish_success() -> Result<()> {
+        let clock = FakeClock::default();
+        let sut =
+            Tracer::new(Some("http://127.0.0.1:4318".into())).with_clock(Arc::new(clock.clone()));
+        let span = sut.start(
+            "RunPodSandbox",
+            &metadata("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")?,
+        );
+        assert_eq!(span.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert!(is_hex_id(&span.span_id, 16));
+        clock.advance(Duration::from_millis(5));
+        sut.finish(span, Code::NotFound);
+
+        // Spans of unsampled traces are not exported
+        let span = sut.start(
+            "Version",
+            &metadata("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00")?,
+        );
+        sut.finish(span, Code::Ok);
+
+        let request = sut.take_request().context("no export request")?;
+        let spans = &request["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans.as_array().map(Vec::len), Some(1));
+        assert_eq!(spans[0]["name"], "RunPodSandbox");
+        assert_eq!(spans[0]["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(spans[0]["status"]["code"], STATUS_CODE_ERROR);
+        assert_eq!(spans[0]["endTimeUnixNano"], clock.unix_nanos()?.to_string());
+        assert_eq!(
+            spans[0]["startTimeUnixNano"],
+            (clock.unix_nanos()? - 5_000_000).to_string()
+        );
+        assert!(sut.take_request().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn finish_without_endpoint() {
+        let sut = Tracer::default();
+        let span = sut.start("Version", &MetadataMap::new());
+        assert!(is_hex_id(&span.trace_id, 32));
+        assert_eq!(span.parent_span_id, None);
+        sut.finish(span, Code::Ok);
+        assert!(sut.take_request().is_none());
+    }
+}
@@ -0,0 +1,114 @@
+//! Server side deadlines of requests.
+
+use anyhow::{bail, format_err, Context, Error, Result};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, time::Duration};
+use tonic::metadata::MetadataMap;
+
+/// The metadata key of the deadline requested by gRPC clients.
+const GRPC_TIMEOUT_KEY: &str = "grpc-timeout";
+
+#[derive(Clone, CopyGetters, Debug, Deserialize, Eq, Getters, PartialEq, Serialize)]
+/// MethodTimeout overrides the request timeout for a single gRPC method.
+pub struct MethodTimeout {
+    #[get = "pub"]
+    /// The name of the gRPC method, like `PullImage`.
+    method: String,
+
+    #[get_copy = "pub"]
+    /// The timeout in seconds, whereas `0` means unbounded.
+    timeout: u64,
+}
+
+impl FromStr for MethodTimeout {
+    type Err = Error;
+
+    /// Parse a method timeout in the format `METHOD=SECONDS`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(method), Some(timeout)) if !method.is_empty() => Ok(Self {
+                method: method.into(),
+                timeout: timeout
+                    .parse()
+                    .with_context(|| format!("parse timeout of method {}", method))?,
+            }),
+            _ => bail!("invalid method timeout {}, expected METHOD=SECONDS", s),
+        }
+    }
+}
+
+/// Retrieve the deadline requested by the client via the `grpc-timeout` metadata, if available.
+pub fn grpc_timeout(metadata: &MetadataMap) -> Result<Option<Duration>> {
+    let value = match metadata.get(GRPC_TIMEOUT_KEY) {
+        Some(value) => value.to_str().context("convert grpc-timeout to string")?,
+        None => return Ok(None),
+    };
+    if value.len() < 2 {
+        bail!("invalid grpc-timeout {}", value)
+    }
+
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount = amount
+        .parse::<u64>()
+        .with_context(|| format!("parse grpc-timeout {}", value))?;
+    Ok(Some(match unit {
+        "H" => Duration::from_secs(amount * 60 * 60),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return Err(format_err!("invalid grpc-timeout unit {}", unit)),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_timeout_from_str_success() -> Result<()> {
+        let t = MethodTimeout::from_str("PullImage=600")?;
+        assert_eq!(t.method(), "PullImage");
+        assert_eq!(t.timeout(), 600);
+        Ok(())
+    }
+
+    #[test]
+    fn method_timeout_from_str_failure() {
+        assert!(MethodTimeout::from_str("PullImage").is_err());
+        assert!(MethodTimeout::from_str("=10").is_err());
+        assert!(MethodTimeout::from_str("PullImage=a").is_err());
+    }
+
+    #[test]
+    fn grpc_timeout_success() -> Result<()> {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(grpc_timeout(&metadata)?, None);
+
+        for (value, expected) in &[
+            ("2H", Duration::from_secs(7200)),
+            ("1M", Duration::from_secs(60)),
+            ("30S", Duration::from_secs(30)),
+            ("1500m", Duration::from_millis(1500)),
+            ("10u", Duration::from_micros(10)),
+            ("99n", Duration::from_nanos(99)),
+        ] {
+            metadata.insert(GRPC_TIMEOUT_KEY, value.parse()?);
+            assert_eq!(grpc_timeout(&metadata)?, Some(*expected));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn grpc_timeout_failure() -> Result<()> {
+        for value in &["S", "10", "10x", "aS"] {
+            let mut metadata = MetadataMap::new();
+            metadata.insert(GRPC_TIMEOUT_KEY, value.parse()?);
+            assert!(grpc_timeout(&metadata).is_err());
+        }
+        Ok(())
+    }
+}
@@ -9,6 +9,7 @@ use std::{
     env,
     fs::{self, File},
     io::{BufRead, BufReader},
+    net::TcpListener,
     path::{Path, PathBuf},
     process::{exit, Command, Stdio},
     sync::Once,
@@ -70,6 +71,9 @@ impl Sut {
         info!("Starting server");
         let run_path = tmp_dir.path().to_owned();
         let sock_path = run_path.join("test.sock");
+
+        // Parallel tests need distinct streaming ports
+        let streaming_port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
         let child = Command::new(BINARY_PATH)
             .arg("--log-level=debug")
             .arg(format!("--sock-path={}", sock_path.display()))
@@ -77,6 +81,26 @@ impl Sut {
                 "--storage-path={}",
                 run_path.join("storage").display()
             ))
+            .arg(format!("--netns-path={}", run_path.join("netns").display()))
+            .arg(format!("--log-path={}", run_path.join("logs").display()))
+            .arg(format!(
+                "--sandbox-path={}",
+                run_path.join("sandboxes").display()
+            ))
+            .arg("--infra-command=sleep 30")
+            .arg(format!(
+                "--container-path={}",
+                run_path.join("containers").display()
+            ))
+            .arg(format!(
+                "--image-path={}",
+                run_path.join("images").display()
+            ))
+            .arg(format!(
+                "--cni-config-dir={}",
+                run_path.join("cni").display()
+            ))
+            .arg(format!("--streaming-port={}", streaming_port))
             .stderr(Stdio::from(err_file))
             .stdout(Stdio::from(out_file))
             .spawn()
@@ -1,8 +1,11 @@
 use crate::common::{
-    criapi::{PodSandboxConfig, PodSandboxMetadata, RunPodSandboxRequest, RunPodSandboxResponse},
+    criapi::{
+        PodSandboxConfig, PodSandboxMetadata, PodSandboxState, PodSandboxStatusRequest,
+        RunPodSandboxRequest,
+    },
     Sut,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::collections::HashMap;
 use tonic::Request;
 
@@ -38,12 +41,18 @@ async fn run_pod_sandbox_success() -> Result<()> {
         .into_inner();
 
     // Then
-    assert_eq!(
-        response,
-        RunPodSandboxResponse {
-            pod_sandbox_id: test_id.into()
-        }
-    );
+    assert!(!response.pod_sandbox_id.is_empty());
+    let status = sut
+        .runtime_client_mut()
+        .pod_sandbox_status(Request::new(PodSandboxStatusRequest {
+            pod_sandbox_id: response.pod_sandbox_id,
+            verbose: false,
+        }))
+        .await?
+        .into_inner()
+        .status
+        .context("no pod sandbox status")?;
+    assert_eq!(status.state, PodSandboxState::SandboxReady as i32);
 
     sut.cleanup()
 }